@@ -14,16 +14,19 @@ var src: texture_2d<f32>;
 @binding(1)
 var dst: texture_storage_2d_array<rgba32float, write>;
 
+// Side of the square workgroups, set by `EquirectToCubemapConverter::with_workgroup_size`
+override workgroup_size: u32 = 16;
+
 @compute
-@workgroup_size(16, 16, 1)
+@workgroup_size(workgroup_size, workgroup_size, 1)
 fn compute_equirect_to_cubemap(
     @builtin(global_invocation_id)
     gid: vec3<u32>,
 ) {
-    // If texture size is not divisible by 32, we
-    // need to make sure we don't try to write to
-    // pixels that don't exist.
-    if gid.x >= u32(textureDimensions(dst).x) {
+    // If texture size is not divisible by the workgroup
+    // size, we need to make sure we don't try to write
+    // to pixels that don't exist.
+    if any(gid.xy >= textureDimensions(dst)) {
         return;
     }
 
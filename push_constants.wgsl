@@ -0,0 +1,17 @@
+// Appended to shader.wgsl on devices with push constants, see
+// `render_pipeline::create_push_constant_pipeline`
+
+// Matches `render_pipeline::DrawTransform`
+struct DrawTransform {
+  model: mat4x4<f32>,
+  normal: mat3x3<f32>,
+};
+
+var<push_constant> draw_transform: DrawTransform;
+
+// `vs_main` for a single model, its matrices pushed with each draw instead of read from
+// an instance buffer
+@vertex
+fn vs_push_constant(model: VertexInput) -> VertexOutput {
+  return transform_vertex(model, draw_transform.model, draw_transform.normal);
+}
@@ -1,7 +1,4 @@
-struct Light {
-  position: vec3<f32>,
-  color: vec3<f32>,
-};
+// `CameraUniform` and the other shared types are declared in `src/prelude.wgsl`
 
 struct VertexInput {
   @location(0) position: vec3<f32>,
@@ -9,6 +6,9 @@ struct VertexInput {
   @location(2) normal: vec3<f32>,
   @location(3) tangent: vec3<f32>,
   @location(4) bitangent: vec3<f32>,
+  @location(12) joints: vec4<u32>,
+  @location(13) weights: vec4<f32>,
+  @location(14) color: vec4<f32>,
 };
 
 struct InstanceInput {
@@ -22,19 +22,14 @@ struct InstanceInput {
 };
 
 struct VertexOutput {
-  @builtin(position) pos: vec4<f32>,
+  // Invariant so the depth prepass and the color pass produce identical depths
+  @builtin(position) @invariant pos: vec4<f32>,
   @location(0) tex_coords: vec2<f32>,
-  @location(1) tangent_position: vec3<f32>,
-  @location(2) tangent_light_position: vec3<f32>,
-  @location(3) tangent_view_position: vec3<f32>,
-};
-
-struct CameraUniform {
-  view_pos: vec4<f32>,
-  view: mat4x4<f32>,
-  view_proj: mat4x4<f32>,
-  inv_proj: mat4x4<f32>,
-  inv_view: mat4x4<f32>,
+  @location(1) world_position: vec3<f32>,
+  @location(2) world_normal: vec3<f32>,
+  @location(3) world_tangent: vec3<f32>,
+  @location(4) world_bitangent: vec3<f32>,
+  @location(5) color: vec4<f32>,
 };
 
 @group(0) @binding(0)
@@ -53,7 +48,44 @@ var s_normal: sampler;
 var<uniform> camera: CameraUniform;
 
 @group(2) @binding(0)
-var<uniform> light: Light;
+var<storage, read> lights: LightArray;
+@group(2) @binding(1)
+var<storage, read> spot_lights: SpotLightArray;
+
+@group(3) @binding(0)
+var<uniform> sun: DirectionalLight;
+
+// `Shadow` and the functions sampling the cascades are in `src/shadow_sampling.wgsl`
+@group(4) @binding(0)
+var t_shadow: texture_depth_2d_array;
+
+@group(4) @binding(1)
+var s_shadow: sampler_comparison;
+
+@group(4) @binding(2)
+var<uniform> shadow: Shadow;
+
+@group(5) @binding(0)
+var<uniform> fog: Fog;
+
+@group(6) @binding(0)
+var<storage, read> joint_matrices: array<mat4x4<f32>>;
+
+// Linear blend skinning. Vertices without weights aren't skinned.
+fn skin_matrix(joints: vec4<u32>, weights: vec4<f32>) -> mat4x4<f32> {
+  if dot(weights, vec4<f32>(1.0)) == 0.0 {
+    return mat4x4<f32>(
+      vec4<f32>(1.0, 0.0, 0.0, 0.0),
+      vec4<f32>(0.0, 1.0, 0.0, 0.0),
+      vec4<f32>(0.0, 0.0, 1.0, 0.0),
+      vec4<f32>(0.0, 0.0, 0.0, 1.0),
+    );
+  }
+  return joint_matrices[joints.x] * weights.x
+    + joint_matrices[joints.y] * weights.y
+    + joint_matrices[joints.z] * weights.z
+    + joint_matrices[joints.w] * weights.w;
+}
 
 @vertex
 fn vs_main(model: VertexInput, instance: InstanceInput) -> VertexOutput {
@@ -68,44 +100,93 @@ fn vs_main(model: VertexInput, instance: InstanceInput) -> VertexOutput {
     instance.normal_matrix_1,
     instance.normal_matrix_2,
   );
+  return transform_vertex(model, model_matrix, normal_matrix);
+}
 
-  let world_normal = normalize(normal_matrix * model.normal);
-  let world_tangent = normalize(normal_matrix * model.tangent);
-  let world_bitangent = normalize(normal_matrix * model.bitangent);
-  let tangent_matrix = transpose(mat3x3<f32>(
-    world_tangent,
-    world_bitangent,
-    world_normal,
-  ));
-  let world_position = model_matrix * vec4<f32>(model.position, 1.0);
+// Skins `model` and places it in the world, shared by every vertex entry point
+fn transform_vertex(
+  model: VertexInput,
+  model_matrix: mat4x4<f32>,
+  normal_matrix: mat3x3<f32>,
+) -> VertexOutput {
+  let skin = skin_matrix(model.joints, model.weights);
+  // Ignores non-uniform scale in the joints, like most skinned content expects
+  let skin_normal = mat3x3<f32>(skin[0].xyz, skin[1].xyz, skin[2].xyz);
+
+  let world_position = model_matrix * skin * vec4<f32>(model.position, 1.0);
   var out: VertexOutput;
   out.pos = camera.view_proj * world_position;
   out.tex_coords = model.tex_coords;
-  out.tangent_position = tangent_matrix * world_position.xyz;
-  out.tangent_view_position = tangent_matrix * camera.view_pos.xyz;
-  out.tangent_light_position = tangent_matrix * light.position;  
+  out.world_position = world_position.xyz;
+  out.world_normal = normalize(normal_matrix * skin_normal * model.normal);
+  out.world_tangent = normalize(normal_matrix * skin_normal * model.tangent);
+  out.world_bitangent = normalize(normal_matrix * skin_normal * model.bitangent);
+  out.color = model.color;
   return out;
 }
 
+// Tangent space normal from a normal map texel. Two channel maps such as BC5 leave blue
+// at 0, as z follows from x and y for unit normals.
+fn unpack_normal(texel: vec3<f32>) -> vec3<f32> {
+  let xy = texel.xy * 2.0 - 1.0;
+  let z = sqrt(max(1.0 - dot(xy, xy), 0.0));
+  return vec3<f32>(xy, select(texel.z * 2.0 - 1.0, z, texel.z == 0.0));
+}
+
 @fragment
 fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
-  let object_color = textureSample(t_diffuse, s_diffuse, in.tex_coords);
+  let object_color = textureSample(t_diffuse, s_diffuse, in.tex_coords) * in.color;
   let object_normal = textureSample(t_normal, s_normal, in.tex_coords);
 
-  let tangent_normal = object_normal.xyz * 2.0 - 1.0;
-  let light_dir = normalize(in.tangent_light_position - in.tangent_position);
-  let view_dir = normalize(in.tangent_view_position - in.tangent_position);
-  let half_dir = normalize(view_dir + light_dir);
-
-  let diffuse_strength = max(dot(tangent_normal, light_dir), 0.0);
-  let diffuse_color = light.color * diffuse_strength * object_color.xyz;
-
-  let specular_strength = pow(max(dot(tangent_normal, half_dir), 0.0), 32.0);
-  let specular_color = specular_strength * light.color;
-
-  let ambient_strength = 0.1;
-  let ambient_color = light.color * ambient_strength * object_color.xyz;
-  
-  return vec4<f32>(ambient_color + diffuse_color + specular_color, object_color.a);
+  // Lights live in world space, so bring the normal map sample there instead of
+  // moving every light into tangent space
+  let tangent_matrix = mat3x3<f32>(
+    normalize(in.world_tangent),
+    normalize(in.world_bitangent),
+    normalize(in.world_normal),
+  );
+  let tangent_normal = unpack_normal(object_normal.xyz);
+  let normal = normalize(tangent_matrix * tangent_normal);
+  let view_dir = normalize(camera.view_pos.xyz - in.world_position);
+
+  // Ambient light once for the whole scene, not once per light
+  var color = AMBIENT_STRENGTH * object_color.xyz;
+  let count = min(lights.count, arrayLength(&lights.lights));
+  for (var i = 0u; i < count; i += 1u) {
+    let light = lights.lights[i];
+    let light_dir = normalize(light.position - in.world_position);
+    let half_dir = normalize(view_dir + light_dir);
+
+    let diffuse_strength = max(dot(normal, light_dir), 0.0);
+    let diffuse_color = light.color * diffuse_strength * object_color.xyz;
+
+    let specular_strength = pow(max(dot(normal, half_dir), 0.0), 32.0);
+    let specular_color = specular_strength * light.color;
+
+    let attenuation = point_attenuation(light, in.world_position);
+    color += (diffuse_color + specular_color) * attenuation;
+  }
+
+  let spot_count = min(spot_lights.count, arrayLength(&spot_lights.lights));
+  for (var i = 0u; i < spot_count; i += 1u) {
+    let light = spot_lights.lights[i];
+    let light_dir = normalize(light.position - in.world_position);
+    let half_dir = normalize(view_dir + light_dir);
+    let diffuse_color = light.color * max(dot(normal, light_dir), 0.0) * object_color.xyz;
+    let specular_color = light.color * pow(max(dot(normal, half_dir), 0.0), 32.0);
+    color += (diffuse_color + specular_color) * spot_attenuation(light, in.world_position);
+  }
+
+  let sun_dir = -sun.direction;
+  let sun_half_dir = normalize(view_dir + sun_dir);
+  let sun_diffuse = sun.color * max(dot(normal, sun_dir), 0.0) * object_color.xyz;
+  let sun_specular = sun.color * pow(max(dot(normal, sun_half_dir), 0.0), 32.0);
+  color += (sun_diffuse + sun_specular) * sun_visibility(in.world_position, normal, sun_dir);
+
+  // The view space camera looks down -z
+  color *= cascade_tint(in.world_position);
+  let view_depth = -(camera.view * vec4<f32>(in.world_position, 1.0)).z;
+  color = mix(color, fog.color.rgb, fog_factor(fog, view_depth));
+
+  return vec4<f32>(color, object_color.a);
 }
-
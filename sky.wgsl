@@ -1,12 +1,7 @@
-struct Camera {
-    view_pos: vec4<f32>,
-    view: mat4x4<f32>,
-    view_proj: mat4x4<f32>,
-    inv_proj: mat4x4<f32>,
-    inv_view: mat4x4<f32>,
-}
+// `CameraUniform` and the other shared types are declared in `src/prelude.wgsl`
+
 @group(0) @binding(0)
-var<uniform> camera: Camera;
+var<uniform> camera: CameraUniform;
 
 @group(1)
 @binding(0)
@@ -15,6 +10,9 @@ var env_map: texture_cube<f32>;
 @binding(1)
 var env_sampler: sampler;
 
+// Depth of the far plane, 0 with reverse-Z
+override far_depth: f32 = 1.0;
+
 struct VertexOutput {
     @builtin(position) frag_position: vec4<f32>,
     @location(0) clip_position: vec4<f32>,
@@ -30,8 +28,8 @@ fn vs_main(
     ));
     var out: VertexOutput;
     // out.clip_position = vec4(uv * vec2(4.0, -4.0) + vec2(-1.0, 1.0), 0.0, 1.0);
-    out.clip_position = vec4(uv * 4.0 - 1.0, 1.0, 1.0);
-    out.frag_position = vec4(uv * 4.0 - 1.0, 1.0, 1.0);
+    out.clip_position = vec4(uv * 4.0 - 1.0, far_depth, 1.0);
+    out.frag_position = vec4(uv * 4.0 - 1.0, far_depth, 1.0);
     return out;
 }
 
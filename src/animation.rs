@@ -0,0 +1,475 @@
+use std::time::Duration;
+
+use cgmath::{InnerSpace, Matrix4, Quaternion, SquareMatrix, Vector3, VectorSpace};
+use wgpu::util::DeviceExt as _;
+
+use crate::{
+    instance::Instance,
+    model::{self, DrawModel},
+};
+
+/// Transform of a joint relative to its parent.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct JointPose {
+    pub translation: Vector3<f32>,
+    pub rotation: Quaternion<f32>,
+    pub scale: Vector3<f32>,
+}
+
+impl JointPose {
+    pub fn matrix(&self) -> Matrix4<f32> {
+        Matrix4::from_translation(self.translation)
+            * Matrix4::from(self.rotation)
+            * Matrix4::from_nonuniform_scale(self.scale.x, self.scale.y, self.scale.z)
+    }
+}
+
+impl Default for JointPose {
+    fn default() -> Self {
+        Self {
+            translation: Vector3::new(0.0, 0.0, 0.0),
+            rotation: Quaternion::new(1.0, 0.0, 0.0, 0.0),
+            scale: Vector3::new(1.0, 1.0, 1.0),
+        }
+    }
+}
+
+pub struct Joint {
+    pub name: String,
+    /// Index of the parent joint, `None` for the roots of the skeleton
+    pub parent: Option<usize>,
+    /// Model space transform of a root joint's parent node, identity for other joints
+    pub parent_transform: Matrix4<f32>,
+    /// Pose the joint has while no animation moves it
+    pub rest: JointPose,
+    /// Brings vertices from model space into the joint's space in the bind pose
+    pub inverse_bind: Matrix4<f32>,
+}
+
+/// The joints vertices are skinned to. Vertex joint indices index into `joints`.
+pub struct Skeleton {
+    joints: Vec<Joint>,
+    /// Joint indices with every parent before its children
+    order: Vec<usize>,
+}
+
+impl Skeleton {
+    /// Fails if the parents don't form a tree.
+    pub fn new(joints: Vec<Joint>) -> anyhow::Result<Self> {
+        let mut order = Vec::with_capacity(joints.len());
+        let mut placed = vec![false; joints.len()];
+        while order.len() < joints.len() {
+            let before = order.len();
+            for (i, joint) in joints.iter().enumerate() {
+                let ready = match joint.parent {
+                    None => true,
+                    Some(parent) => *placed
+                        .get(parent)
+                        .ok_or_else(|| anyhow::anyhow!("joint {i} has no parent {parent}"))?,
+                };
+                if ready && !placed[i] {
+                    placed[i] = true;
+                    order.push(i);
+                }
+            }
+            if order.len() == before {
+                anyhow::bail!("skeleton joints form a cycle");
+            }
+        }
+        Ok(Self { joints, order })
+    }
+
+    pub fn joints(&self) -> &[Joint] {
+        &self.joints
+    }
+
+    pub fn rest_pose(&self) -> Vec<JointPose> {
+        self.joints.iter().map(|joint| joint.rest).collect()
+    }
+
+    /// Skinning matrices for `poses`, one per joint: model space bind pose to the posed
+    /// joint in model space.
+    pub fn joint_matrices(&self, poses: &[JointPose], matrices: &mut Vec<Matrix4<f32>>) {
+        let mut globals = vec![Matrix4::identity(); self.joints.len()];
+        for &i in &self.order {
+            let joint = &self.joints[i];
+            let parent = match joint.parent {
+                Some(parent) => globals[parent],
+                None => joint.parent_transform,
+            };
+            globals[i] = parent * poses[i].matrix();
+        }
+        matrices.clear();
+        matrices.extend(
+            globals
+                .iter()
+                .zip(&self.joints)
+                .map(|(global, joint)| global * joint.inverse_bind),
+        );
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Interpolation {
+    /// Holds each keyframe until the next one
+    Step,
+    /// Linear for translation and scale, spherical for rotation
+    Linear,
+}
+
+#[derive(Debug, Clone)]
+pub enum Keyframes {
+    Translation(Vec<Vector3<f32>>),
+    Rotation(Vec<Quaternion<f32>>),
+    Scale(Vec<Vector3<f32>>),
+}
+
+impl Keyframes {
+    pub fn len(&self) -> usize {
+        match self {
+            Keyframes::Translation(values) | Keyframes::Scale(values) => values.len(),
+            Keyframes::Rotation(values) => values.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// Animates one property of one joint.
+#[derive(Debug, Clone)]
+pub struct Channel {
+    pub joint: usize,
+    /// Ascending keyframe times in seconds
+    pub times: Vec<f32>,
+    /// One value per time
+    pub keyframes: Keyframes,
+    pub interpolation: Interpolation,
+}
+
+impl Channel {
+    /// The keyframes around `time` and how far `time` is between them. Times outside the
+    /// channel hold the first or last keyframe.
+    fn keys_at(&self, time: f32) -> (usize, usize, f32) {
+        let next = self.times.partition_point(|&t| t <= time);
+        if next == 0 {
+            return (0, 0, 0.0);
+        }
+        if next == self.times.len() {
+            return (next - 1, next - 1, 0.0);
+        }
+        let (start, end) = (self.times[next - 1], self.times[next]);
+        let t = match self.interpolation {
+            Interpolation::Step => 0.0,
+            Interpolation::Linear => (time - start) / (end - start),
+        };
+        (next - 1, next, t)
+    }
+
+    fn apply(&self, time: f32, pose: &mut JointPose) {
+        if self.times.is_empty() || self.keyframes.len() < self.times.len() {
+            return;
+        }
+        let (a, b, t) = self.keys_at(time);
+        match &self.keyframes {
+            Keyframes::Translation(values) => {
+                pose.translation = values[a].lerp(values[b], t);
+            }
+            Keyframes::Rotation(values) => {
+                // Take the shorter way around
+                let (from, to) = (values[a], values[b]);
+                let to = if from.dot(to) < 0.0 { -to } else { to };
+                pose.rotation = from.slerp(to, t).normalize();
+            }
+            Keyframes::Scale(values) => {
+                pose.scale = values[a].lerp(values[b], t);
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct AnimationClip {
+    pub name: String,
+    /// Time of the last keyframe of any channel
+    pub duration: f32,
+    pub channels: Vec<Channel>,
+}
+
+impl AnimationClip {
+    pub fn new(name: impl Into<String>, channels: Vec<Channel>) -> Self {
+        let duration = channels
+            .iter()
+            .filter_map(|channel| channel.times.last().copied())
+            .fold(0.0, f32::max);
+        Self {
+            name: name.into(),
+            duration,
+            channels,
+        }
+    }
+
+    /// Overwrites the animated properties in `poses` with their values at `time`.
+    pub fn sample(&self, time: f32, poses: &mut [JointPose]) {
+        for channel in &self.channels {
+            if let Some(pose) = poses.get_mut(channel.joint) {
+                channel.apply(time, pose);
+            }
+        }
+    }
+}
+
+/// Plays the clips of a skeleton, one at a time, and keeps the resulting joint matrices.
+pub struct AnimationPlayer {
+    skeleton: Skeleton,
+    clips: Vec<AnimationClip>,
+    current: Option<usize>,
+    time: f32,
+    /// Playback rate, 1 is real time
+    pub speed: f32,
+    /// Restarts the clip when it ends instead of holding the last frame
+    pub looping: bool,
+    poses: Vec<JointPose>,
+    joint_matrices: Vec<Matrix4<f32>>,
+}
+
+impl AnimationPlayer {
+    /// Starts out in the rest pose with no clip playing.
+    pub fn new(skeleton: Skeleton, clips: Vec<AnimationClip>) -> Self {
+        let poses = skeleton.rest_pose();
+        let mut joint_matrices = Vec::new();
+        skeleton.joint_matrices(&poses, &mut joint_matrices);
+        Self {
+            skeleton,
+            clips,
+            current: None,
+            time: 0.0,
+            speed: 1.0,
+            looping: true,
+            poses,
+            joint_matrices,
+        }
+    }
+
+    pub fn skeleton(&self) -> &Skeleton {
+        &self.skeleton
+    }
+
+    pub fn clip_names(&self) -> impl Iterator<Item = &str> {
+        self.clips.iter().map(|clip| clip.name.as_str())
+    }
+
+    /// Plays the clip called `clip_name` from its start.
+    pub fn play(&mut self, clip_name: &str) -> anyhow::Result<()> {
+        let index = self
+            .clips
+            .iter()
+            .position(|clip| clip.name == clip_name)
+            .ok_or_else(|| anyhow::anyhow!("no animation clip named {clip_name:?}"))?;
+        self.current = Some(index);
+        self.time = 0.0;
+        self.pose();
+        Ok(())
+    }
+
+    /// Stops playback and returns to the rest pose.
+    pub fn stop(&mut self) {
+        self.current = None;
+        self.time = 0.0;
+        self.pose();
+    }
+
+    pub fn current_clip(&self) -> Option<&AnimationClip> {
+        self.current.map(|index| &self.clips[index])
+    }
+
+    /// Playback position in the current clip, in seconds.
+    pub fn time(&self) -> f32 {
+        self.time
+    }
+
+    /// Advances the current clip by `dt` and recomputes the joint matrices.
+    pub fn update(&mut self, dt: Duration) {
+        let Some(clip) = self.current_clip() else {
+            return;
+        };
+        let duration = clip.duration;
+        self.time += dt.as_secs_f32() * self.speed;
+        self.time = if self.looping && duration > 0.0 {
+            self.time.rem_euclid(duration)
+        } else {
+            self.time.clamp(0.0, duration)
+        };
+        self.pose();
+    }
+
+    fn pose(&mut self) {
+        self.poses.clear();
+        self.poses
+            .extend(self.skeleton.joints.iter().map(|joint| joint.rest));
+        if let Some(index) = self.current {
+            self.clips[index].sample(self.time, &mut self.poses);
+        }
+        self.skeleton
+            .joint_matrices(&self.poses, &mut self.joint_matrices);
+    }
+
+    /// One skinning matrix per joint for the current pose.
+    pub fn joint_matrices(&self) -> &[Matrix4<f32>] {
+        &self.joint_matrices
+    }
+}
+
+/// The joint matrices of one skinned model in a storage buffer, bound as group 6 of the
+/// forward pipeline and group 1 of the shadow pipeline. Vertices without joint weights
+/// ignore it. The previous frame's matrices are bound next to them for motion vectors.
+pub struct SkinUniform {
+    buffer: wgpu::Buffer,
+    previous_buffer: wgpu::Buffer,
+    /// What `buffer` holds, `None` until the first `upload`
+    uploaded: Option<Vec<[[f32; 4]; 4]>>,
+    /// Matrices the buffer has room for
+    capacity: usize,
+    pub bind_group: wgpu::BindGroup,
+}
+
+impl SkinUniform {
+    pub fn create_bind_group_layout(device: &wgpu::Device) -> wgpu::BindGroupLayout {
+        let matrices = |binding| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::VERTEX,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Storage { read_only: true },
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+        device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            // The current matrices, then the previous frame's
+            entries: &[matrices(0), matrices(1)],
+            label: Some("skin_bind_group_layout"),
+        })
+    }
+
+    /// Starts out with every joint at identity. An empty `joints` still allocates one.
+    pub fn new(device: &wgpu::Device, layout: &wgpu::BindGroupLayout, joints: usize) -> Self {
+        let capacity = joints.max(1);
+        let identity: [[f32; 4]; 4] = Matrix4::identity().into();
+        let create_buffer = |label| {
+            device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: Some(label),
+                contents: bytemuck::cast_slice(&vec![identity; capacity]),
+                usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+            })
+        };
+        let buffer = create_buffer("Skin Buffer");
+        let previous_buffer = create_buffer("Previous Skin Buffer");
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: previous_buffer.as_entire_binding(),
+                },
+            ],
+            label: Some("skin_bind_group"),
+        });
+        Self {
+            buffer,
+            previous_buffer,
+            uploaded: None,
+            capacity,
+            bind_group,
+        }
+    }
+
+    /// Writes `matrices`, dropping any beyond the joint count given to `new`, and keeps
+    /// the ones written before as the previous frame's.
+    pub fn upload(&mut self, queue: &wgpu::Queue, matrices: &[Matrix4<f32>]) {
+        let raw = matrices
+            .iter()
+            .take(self.capacity)
+            .map(|&m| -> [[f32; 4]; 4] { m.into() })
+            .collect::<Vec<_>>();
+        // The first pose has no previous frame, so it starts out without motion
+        let previous = self.uploaded.as_ref().unwrap_or(&raw);
+        queue.write_buffer(&self.previous_buffer, 0, bytemuck::cast_slice(previous));
+        queue.write_buffer(&self.buffer, 0, bytemuck::cast_slice(&raw));
+        self.uploaded = Some(raw);
+    }
+}
+
+/// A skinned model, the player animating it and where it stands in the world.
+pub struct AnimatedModel {
+    pub model: model::Model,
+    pub player: AnimationPlayer,
+    pub instance: Instance,
+    skin: SkinUniform,
+    /// The model matrix of `instance` as of the previous update, for motion vectors
+    previous: Matrix4<f32>,
+    instance_buffer: wgpu::Buffer,
+}
+
+impl AnimatedModel {
+    pub fn new(
+        device: &wgpu::Device,
+        skin_layout: &wgpu::BindGroupLayout,
+        model: model::Model,
+        player: AnimationPlayer,
+        instance: Instance,
+    ) -> Self {
+        let skin = SkinUniform::new(device, skin_layout, player.skeleton().joints().len());
+        let raw = instance.to_raw();
+        let instance_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Animated Model Instance Buffer"),
+            contents: bytemuck::cast_slice(&[raw]),
+            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+        });
+        Self {
+            model,
+            player,
+            instance,
+            skin,
+            previous: raw.model.into(),
+            instance_buffer,
+        }
+    }
+
+    /// Advances the animation by `dt` and uploads the pose and `instance`, keeping the
+    /// ones of the previous update for motion vectors.
+    pub fn update(&mut self, queue: &wgpu::Queue, dt: Duration) {
+        self.player.update(dt);
+        self.skin.upload(queue, self.player.joint_matrices());
+        let raw = self.instance.to_raw().moved_from(self.previous);
+        queue.write_buffer(&self.instance_buffer, 0, bytemuck::cast_slice(&[raw]));
+        self.previous = raw.model.into();
+    }
+
+    /// Draws the model with a pipeline of the forward layout, which must already be set
+    /// along with groups 3 to 5. Leaves group 6 set to this model's skin.
+    pub fn draw<'a>(
+        &'a self,
+        pass: &mut wgpu::RenderPass<'a>,
+        camera_bind_group: &'a wgpu::BindGroup,
+        light_bind_group: &'a wgpu::BindGroup,
+    ) {
+        pass.set_bind_group(6, &self.skin.bind_group, &[]);
+        pass.set_vertex_buffer(1, self.instance_buffer.slice(..));
+        pass.draw_model_instanced(&self.model, 0..1, camera_bind_group, light_bind_group);
+    }
+
+    /// Draws the model into a shadow pass. Leaves group 1 set to this model's skin.
+    pub fn draw_depth<'a>(&'a self, pass: &mut wgpu::RenderPass<'a>) {
+        use model::DrawDepth;
+
+        pass.set_bind_group(1, &self.skin.bind_group, &[]);
+        pass.set_vertex_buffer(1, self.instance_buffer.slice(..));
+        pass.draw_model_depth_instanced(&self.model, 0..1);
+    }
+}
@@ -0,0 +1,298 @@
+use cgmath::{InnerSpace, Vector3};
+use wgpu::util::DeviceExt as _;
+
+use crate::{hot_reload::wgsl, texture};
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct AtmosphereUniform {
+    sun_direction: [f32; 3],
+    turbidity: f32,
+    ground_albedo: [f32; 3],
+    intensity: f32,
+}
+
+/// Draws a procedural daylight sky as the scene background, an alternative to a `Skybox`
+/// for scenes without an environment map.
+///
+/// The sky follows the Preetham model for the sun's direction and the air's turbidity, with
+/// the sun's disc in it and the ground below the horizon. It is drawn like the `Skybox`, a
+/// fullscreen triangle at the far plane whose view rays come from the camera's
+/// `inv_proj`/`inv_view`, and can be baked into a `CubeTexture`, e.g. for `IblEnvironment`.
+/// Changes to the fields take effect on `upload`.
+pub struct AtmosphereSkybox {
+    /// Haziness from about 2, a clear sky, to 10, a hazy one
+    pub turbidity: f32,
+    /// Linear color of the ground below the horizon
+    pub ground_albedo: [f32; 3],
+    /// Scales the sky's luminance into HDR values
+    pub intensity: f32,
+    sun_direction: Vector3<f32>,
+    buffer: wgpu::Buffer,
+    bind_group_layout: wgpu::BindGroupLayout,
+    bind_group: wgpu::BindGroup,
+    render_pipeline: wgpu::RenderPipeline,
+}
+
+impl AtmosphereSkybox {
+    pub const MIN_TURBIDITY: f32 = 1.7;
+    pub const MAX_TURBIDITY: f32 = 10.0;
+
+    pub fn new(
+        device: &wgpu::Device,
+        camera_bind_group_layout: &wgpu::BindGroupLayout,
+        color_format: wgpu::TextureFormat,
+        depth_format: Option<wgpu::TextureFormat>,
+        depth_compare: wgpu::CompareFunction,
+        sample_count: u32,
+    ) -> Self {
+        let sun_direction = Vector3::new(0.5, 1.0, 0.3).normalize();
+        let (turbidity, ground_albedo, intensity) = (2.5, [0.3, 0.28, 0.25], 0.05);
+        let uniform = AtmosphereUniform {
+            sun_direction: sun_direction.into(),
+            turbidity,
+            ground_albedo,
+            intensity,
+        };
+        let buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Atmosphere Buffer"),
+            contents: bytemuck::cast_slice(&[uniform]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::VERTEX_FRAGMENT | wgpu::ShaderStages::COMPUTE,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            }],
+            label: Some("atmosphere_bind_group_layout"),
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &bind_group_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: buffer.as_entire_binding(),
+            }],
+            label: Some("atmosphere_bind_group"),
+        });
+        let render_pipeline = Self::create_render_pipeline(
+            device,
+            &bind_group_layout,
+            camera_bind_group_layout,
+            color_format,
+            depth_format,
+            depth_compare,
+            sample_count,
+        );
+        Self {
+            turbidity,
+            ground_albedo,
+            intensity,
+            sun_direction,
+            buffer,
+            bind_group_layout,
+            bind_group,
+            render_pipeline,
+        }
+    }
+
+    /// Rebuilds the pipeline, e.g. after the MSAA sample count changed.
+    pub fn recreate_pipeline(
+        &mut self,
+        device: &wgpu::Device,
+        camera_bind_group_layout: &wgpu::BindGroupLayout,
+        color_format: wgpu::TextureFormat,
+        depth_format: Option<wgpu::TextureFormat>,
+        depth_compare: wgpu::CompareFunction,
+        sample_count: u32,
+    ) {
+        self.render_pipeline = Self::create_render_pipeline(
+            device,
+            &self.bind_group_layout,
+            camera_bind_group_layout,
+            color_format,
+            depth_format,
+            depth_compare,
+            sample_count,
+        );
+    }
+
+    fn create_render_pipeline(
+        device: &wgpu::Device,
+        bind_group_layout: &wgpu::BindGroupLayout,
+        camera_bind_group_layout: &wgpu::BindGroupLayout,
+        color_format: wgpu::TextureFormat,
+        depth_format: Option<wgpu::TextureFormat>,
+        depth_compare: wgpu::CompareFunction,
+        sample_count: u32,
+    ) -> wgpu::RenderPipeline {
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Atmosphere Pipeline Layout"),
+            bind_group_layouts: &[camera_bind_group_layout, bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let shader = device.create_shader_module(wgsl!("src/atmosphere.wgsl", "src/prelude.wgsl"));
+        // Greater depths are nearer with reverse-Z, so the far plane is at 0
+        let far_depth = match depth_compare {
+            wgpu::CompareFunction::Greater | wgpu::CompareFunction::GreaterEqual => 0.0,
+            _ => 1.0,
+        };
+        let constants = [("far_depth", far_depth)];
+        device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Atmosphere Pipeline"),
+            layout: Some(&layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: Some("vs_main"),
+                buffers: &[],
+                compilation_options: wgpu::PipelineCompilationOptions {
+                    constants: &constants,
+                    ..Default::default()
+                },
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: Some("fs_main"),
+                targets: &[Some(wgpu::ColorTargetState {
+                    format: color_format,
+                    blend: Some(wgpu::BlendState::REPLACE),
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+                compilation_options: Default::default(),
+            }),
+            primitive: wgpu::PrimitiveState::default(),
+            // On the far plane like the `Skybox`, behind everything and never writing depth
+            depth_stencil: depth_format.map(|format| wgpu::DepthStencilState {
+                format,
+                depth_write_enabled: false,
+                depth_compare,
+                stencil: wgpu::StencilState::default(),
+                bias: wgpu::DepthBiasState::default(),
+            }),
+            multisample: wgpu::MultisampleState {
+                count: sample_count,
+                ..Default::default()
+            },
+            multiview: None,
+            cache: None,
+        })
+    }
+
+    /// Direction towards the sun.
+    pub fn sun_direction(&self) -> Vector3<f32> {
+        self.sun_direction
+    }
+
+    /// Points the sun towards `direction`, the opposite of the way its light travels.
+    pub fn set_sun_direction(&mut self, direction: Vector3<f32>) {
+        if direction.magnitude2() > 0.0 {
+            self.sun_direction = direction.normalize();
+        }
+    }
+
+    pub fn upload(&self, queue: &wgpu::Queue) {
+        let uniform = AtmosphereUniform {
+            sun_direction: self.sun_direction.into(),
+            turbidity: self
+                .turbidity
+                .clamp(Self::MIN_TURBIDITY, Self::MAX_TURBIDITY),
+            ground_albedo: self.ground_albedo,
+            intensity: self.intensity.max(0.0),
+        };
+        queue.write_buffer(&self.buffer, 0, bytemuck::cast_slice(&[uniform]));
+    }
+
+    /// Renders the sky as uploaded into a new cube of `size` texels per side. Like cubes
+    /// converted from equirectangular images it is Rgba32Float and samples with `Nearest`
+    /// filtering, so it can replace one in a `Skybox` or be baked into an `IblEnvironment`.
+    /// Needs compute shader support.
+    pub fn bake(
+        &self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        size: u32,
+    ) -> texture::CubeTexture {
+        const FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba32Float;
+        let cube = texture::CubeTexture::create_2d(
+            device,
+            size,
+            FORMAT,
+            1,
+            wgpu::TextureUsages::STORAGE_BINDING | wgpu::TextureUsages::TEXTURE_BINDING,
+            wgpu::FilterMode::Nearest,
+            Some("Atmosphere Cubemap"),
+        );
+
+        let cube_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::COMPUTE,
+                ty: wgpu::BindingType::StorageTexture {
+                    access: wgpu::StorageTextureAccess::WriteOnly,
+                    format: FORMAT,
+                    view_dimension: wgpu::TextureViewDimension::D2Array,
+                },
+                count: None,
+            }],
+            label: Some("atmosphere_bake_bind_group_layout"),
+        });
+        let layers = cube.texture().create_view(&wgpu::TextureViewDescriptor {
+            label: Some("Atmosphere Cubemap Layers"),
+            dimension: Some(wgpu::TextureViewDimension::D2Array),
+            ..Default::default()
+        });
+        let cube_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &cube_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: wgpu::BindingResource::TextureView(&layers),
+            }],
+            label: Some("atmosphere_bake_bind_group"),
+        });
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Atmosphere Bake Pipeline Layout"),
+            bind_group_layouts: &[&cube_layout, &self.bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let shader = device.create_shader_module(wgsl!("src/atmosphere.wgsl", "src/prelude.wgsl"));
+        let pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: Some("Atmosphere Bake Pipeline"),
+            layout: Some(&layout),
+            module: &shader,
+            entry_point: Some("bake"),
+            compilation_options: Default::default(),
+            cache: None,
+        });
+
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("Atmosphere Bake Encoder"),
+        });
+        {
+            let mut pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+                label: Some("Atmosphere Bake"),
+                timestamp_writes: None,
+            });
+            let groups = size.div_ceil(16);
+            pass.set_pipeline(&pipeline);
+            pass.set_bind_group(0, &cube_bind_group, &[]);
+            pass.set_bind_group(1, &self.bind_group, &[]);
+            pass.dispatch_workgroups(groups, groups, 6);
+        }
+        queue.submit([encoder.finish()]);
+        cube
+    }
+
+    /// Draws the sky. Call this first in a pass whose depth was cleared to the far plane,
+    /// before opaque geometry. Leaves bind groups 0 and 1 set to the camera and atmosphere.
+    pub fn draw(&self, pass: &mut wgpu::RenderPass<'_>, camera_bind_group: &wgpu::BindGroup) {
+        pass.set_pipeline(&self.render_pipeline);
+        pass.set_bind_group(0, camera_bind_group, &[]);
+        pass.set_bind_group(1, &self.bind_group, &[]);
+        pass.draw(0..3, 0..1);
+    }
+}
@@ -0,0 +1,153 @@
+// Procedural daylight sky after Preetham, Shirley and Smits, "A Practical Analytic Model
+// for Daylight": the sky's luminance and chromaticity follow Perez distributions fitted to
+// the turbidity and the sun's angle from the zenith
+
+const PI: f32 = 3.1415926535897932384626433832795;
+// Angular radius of the sun's disc
+const SUN_RADIUS: f32 = 0.0047;
+
+// `CameraUniform` and the other shared types are declared in `src/prelude.wgsl`
+
+struct Atmosphere {
+  // Towards the sun
+  sun_direction: vec3<f32>,
+  // Haziness, 2 is a clear sky and 10 a hazy one
+  turbidity: f32,
+  // Color of the ground below the horizon
+  ground_albedo: vec3<f32>,
+  // Scales the sky's luminance, in thousands of cd/m², into HDR values
+  intensity: f32,
+};
+
+@group(1) @binding(0)
+var<uniform> atmosphere: Atmosphere;
+
+// Depth of the far plane, 0 with reverse-Z
+override far_depth: f32 = 1.0;
+
+// Relative luminance of a direction `theta` from the zenith and `gamma` from the sun
+fn perez(theta: f32, gamma: f32, a: f32, b: f32, c: f32, d: f32, e: f32) -> f32 {
+  let cos_gamma = cos(gamma);
+  return (1.0 + a * exp(b / max(cos(theta), 0.01))) * (1.0 + c * exp(d * gamma) + e * cos_gamma * cos_gamma);
+}
+
+fn xyz_to_linear_srgb(xyz: vec3<f32>) -> vec3<f32> {
+  return vec3<f32>(
+    3.2406 * xyz.x - 1.5372 * xyz.y - 0.4986 * xyz.z,
+    -0.9689 * xyz.x + 1.8758 * xyz.y + 0.0415 * xyz.z,
+    0.0557 * xyz.x - 0.2040 * xyz.y + 1.0570 * xyz.z,
+  );
+}
+
+// Radiance of the sky above the horizon towards `direction`
+fn sky(direction: vec3<f32>) -> vec3<f32> {
+  let t = atmosphere.turbidity;
+  let sun = atmosphere.sun_direction;
+  // The model only holds for a sun above the horizon, so it stays there and the sky
+  // darkens as the real one sinks below
+  let theta_s = acos(clamp(sun.y, 0.0, 1.0));
+  let theta = acos(clamp(direction.y, 0.0, 1.0));
+  let gamma = acos(clamp(dot(direction, sun), -1.0, 1.0));
+
+  let chi = (4.0 / 9.0 - t / 120.0) * (PI - 2.0 * theta_s);
+  let zenith_y = (4.0453 * t - 4.9710) * tan(chi) - 0.2155 * t + 2.4192;
+  let ts = vec3<f32>(theta_s * theta_s * theta_s, theta_s * theta_s, theta_s);
+  let zenith_x = t * t * dot(vec3<f32>(0.00166, -0.00375, 0.00209), ts)
+    + t * (dot(vec3<f32>(-0.02903, 0.06377, -0.03202), ts) + 0.00394)
+    + dot(vec3<f32>(0.11693, -0.21196, 0.06052), ts) + 0.25886;
+  let zenith_yc = t * t * dot(vec3<f32>(0.00275, -0.00610, 0.00317), ts)
+    + t * (dot(vec3<f32>(-0.04214, 0.08970, -0.04153), ts) + 0.00516)
+    + dot(vec3<f32>(0.15346, -0.26756, 0.06670), ts) + 0.26688;
+
+  let luminance = zenith_y
+    * perez(theta, gamma, 0.1787 * t - 1.4630, -0.3554 * t + 0.4275, -0.0227 * t + 5.3251, 0.1206 * t - 2.5771, -0.0670 * t + 0.3703)
+    / perez(0.0, theta_s, 0.1787 * t - 1.4630, -0.3554 * t + 0.4275, -0.0227 * t + 5.3251, 0.1206 * t - 2.5771, -0.0670 * t + 0.3703);
+  let x = zenith_x
+    * perez(theta, gamma, -0.0193 * t - 0.2592, -0.0665 * t + 0.0008, -0.0004 * t + 0.2125, -0.0641 * t - 0.8989, -0.0033 * t + 0.0452)
+    / perez(0.0, theta_s, -0.0193 * t - 0.2592, -0.0665 * t + 0.0008, -0.0004 * t + 0.2125, -0.0641 * t - 0.8989, -0.0033 * t + 0.0452);
+  let y = zenith_yc
+    * perez(theta, gamma, -0.0167 * t - 0.2608, -0.0950 * t + 0.0092, -0.0079 * t + 0.2102, -0.0441 * t - 1.6537, -0.0109 * t + 0.0529)
+    / perez(0.0, theta_s, -0.0167 * t - 0.2608, -0.0950 * t + 0.0092, -0.0079 * t + 0.2102, -0.0441 * t - 1.6537, -0.0109 * t + 0.0529);
+
+  let xyz = vec3<f32>(x / y * luminance, luminance, (1.0 - x - y) / y * luminance);
+  let night = smoothstep(-0.1, 0.05, sun.y);
+  return max(xyz_to_linear_srgb(xyz), vec3<f32>(0.0)) * atmosphere.intensity * night;
+}
+
+// Radiance towards `direction`: the sky, the sun's disc in it, or the ground below the
+// horizon lit by both
+fn radiance(direction: vec3<f32>) -> vec3<f32> {
+  let sun = atmosphere.sun_direction;
+  let horizon = normalize(vec3<f32>(direction.x, 0.0, direction.z) + vec3<f32>(0.0, 0.001, 0.0));
+  if direction.y < 0.0 {
+    // Lambertian ground under the sky's zenith and the sun, fading into the horizon's haze
+    let sunlight = max(sun.y, 0.0) * vec3<f32>(1.0, 0.95, 0.85) * atmosphere.intensity * 8.0;
+    let ground = atmosphere.ground_albedo * (sky(vec3<f32>(0.0, 1.0, 0.0)) + sunlight) / PI;
+    return mix(sky(horizon), ground, smoothstep(0.0, 0.05, -direction.y));
+  }
+  var color = sky(direction);
+  let disc = smoothstep(cos(SUN_RADIUS * 1.5), cos(SUN_RADIUS), dot(direction, sun));
+  if disc > 0.0 {
+    // Reddened near the horizon, where the light crosses more air
+    let air = 1.0 / max(sun.y + 0.15, 0.05);
+    let transmittance = exp(-vec3<f32>(0.05, 0.12, 0.27) * atmosphere.turbidity * 0.1 * air);
+    color += disc * transmittance * atmosphere.intensity * 400.0 * smoothstep(-0.02, 0.0, sun.y);
+  }
+  return color;
+}
+
+struct VertexOutput {
+  @builtin(position) frag_position: vec4<f32>,
+  @location(0) clip_position: vec4<f32>,
+};
+
+@group(0) @binding(0)
+var<uniform> camera: CameraUniform;
+
+@vertex
+fn vs_main(@builtin(vertex_index) id: u32) -> VertexOutput {
+  let uv = vec2<f32>(vec2<u32>(id & 1u, (id >> 1u) & 1u));
+  var out: VertexOutput;
+  out.clip_position = vec4<f32>(uv * 4.0 - 1.0, far_depth, 1.0);
+  out.frag_position = out.clip_position;
+  return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+  let view_position = camera.inv_proj * in.clip_position;
+  let view_ray = view_position.xyz / view_position.w;
+  let direction = normalize((camera.inv_view * vec4<f32>(view_ray, 0.0)).xyz);
+  return vec4<f32>(radiance(direction), 1.0);
+}
+
+// Bakes the sky into a cube, e.g. for image-based lighting. The cube is bound as a storage
+// array here, where group 0 is the camera for the other entry points.
+@group(0) @binding(0)
+var cube: texture_storage_2d_array<rgba32float, write>;
+
+// Direction through the center of texel `gid.xy` on cube face `gid.z`, following the
+// hardware cube-map convention
+fn cube_direction(gid: vec3<u32>, size: vec2<u32>) -> vec3<f32> {
+  let uv = (vec2<f32>(gid.xy) + 0.5) / vec2<f32>(size) * 2.0 - 1.0;
+  var dir: vec3<f32>;
+  switch gid.z {
+    case 0u: { dir = vec3(1.0, -uv.y, -uv.x); }
+    case 1u: { dir = vec3(-1.0, -uv.y, uv.x); }
+    case 2u: { dir = vec3(uv.x, 1.0, uv.y); }
+    case 3u: { dir = vec3(uv.x, -1.0, -uv.y); }
+    case 4u: { dir = vec3(uv.x, -uv.y, 1.0); }
+    default: { dir = vec3(-uv.x, -uv.y, -1.0); }
+  }
+  return normalize(dir);
+}
+
+@compute @workgroup_size(16, 16, 1)
+fn bake(@builtin(global_invocation_id) gid: vec3<u32>) {
+  let size = textureDimensions(cube);
+  if gid.x >= size.x || gid.y >= size.y {
+    return;
+  }
+  let color = radiance(cube_direction(gid, size));
+  textureStore(cube, gid.xy, gid.z, vec4<f32>(color, 1.0));
+}
@@ -0,0 +1,348 @@
+use cgmath::{InnerSpace, Point3};
+use wgpu::util::DeviceExt as _;
+
+use crate::{frustum::Aabb, hot_reload::wgsl, model::Vertex, terrain::LodSelector, texture};
+
+/// A camera-facing quad showing one cell of a `Billboard`'s atlas.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct BillboardInstance {
+    /// World space center of the quad
+    pub position: [f32; 3],
+    /// Width and height in world units
+    pub size: [f32; 2],
+    /// Atlas cell, counted left to right and then top to bottom
+    pub atlas_index: u32,
+}
+
+impl Vertex for BillboardInstance {
+    fn desc() -> wgpu::VertexBufferLayout<'static> {
+        use std::mem;
+        wgpu::VertexBufferLayout {
+            array_stride: mem::size_of::<BillboardInstance>() as wgpu::BufferAddress,
+            step_mode: wgpu::VertexStepMode::Instance,
+            attributes: &[
+                wgpu::VertexAttribute {
+                    offset: 0,
+                    shader_location: 0,
+                    format: wgpu::VertexFormat::Float32x3,
+                },
+                wgpu::VertexAttribute {
+                    offset: mem::size_of::<[f32; 3]>() as wgpu::BufferAddress,
+                    shader_location: 1,
+                    format: wgpu::VertexFormat::Float32x2,
+                },
+                wgpu::VertexAttribute {
+                    offset: mem::size_of::<[f32; 5]>() as wgpu::BufferAddress,
+                    shader_location: 2,
+                    format: wgpu::VertexFormat::Uint32,
+                },
+            ],
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
+struct AtlasUniform {
+    grid: [u32; 2],
+}
+
+/// Draws instanced billboards that turn about the world up axis to face the camera, with
+/// their pictures taken from a texture atlas of equally sized cells. Texels with alpha
+/// below 0.5 are cut out, and billboards are unlit.
+pub struct Billboard {
+    atlas: texture::Texture,
+    grid: (u32, u32),
+    bind_group_layout: wgpu::BindGroupLayout,
+    bind_group: wgpu::BindGroup,
+    pipeline: wgpu::RenderPipeline,
+    instance_buffer: wgpu::Buffer,
+    /// Instances the buffer has room for
+    capacity: usize,
+    /// Instances written by the last `upload`
+    count: u32,
+}
+
+impl Billboard {
+    /// `atlas` is divided into `columns` x `rows` cells. Its mip chain should stop before
+    /// neighboring cells bleed into each other. `scene_layouts` are the camera's and the
+    /// sun's, lighting the billboards like the meshes they stand in for.
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        device: &wgpu::Device,
+        scene_layouts: [&wgpu::BindGroupLayout; 2],
+        atlas: texture::Texture,
+        columns: u32,
+        rows: u32,
+        color_format: wgpu::TextureFormat,
+        depth_format: Option<wgpu::TextureFormat>,
+        depth_compare: wgpu::CompareFunction,
+        sample_count: u32,
+    ) -> Self {
+        let grid = (columns.max(1), rows.max(1));
+        let uniform_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Billboard Atlas Buffer"),
+            contents: bytemuck::cast_slice(&[AtlasUniform {
+                grid: [grid.0, grid.1],
+            }]),
+            usage: wgpu::BufferUsages::UNIFORM,
+        });
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::VERTEX,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ],
+            label: Some("billboard_bind_group_layout"),
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&atlas.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(&atlas.sampler),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: uniform_buffer.as_entire_binding(),
+                },
+            ],
+            label: Some("billboard_bind_group"),
+        });
+        let pipeline = Self::create_pipeline(
+            device,
+            scene_layouts,
+            &bind_group_layout,
+            color_format,
+            depth_format,
+            depth_compare,
+            sample_count,
+        );
+
+        Self {
+            atlas,
+            grid,
+            bind_group_layout,
+            bind_group,
+            pipeline,
+            instance_buffer: Self::create_instance_buffer(device, 1),
+            capacity: 1,
+            count: 0,
+        }
+    }
+
+    pub fn atlas(&self) -> &texture::Texture {
+        &self.atlas
+    }
+
+    /// Columns and rows of atlas cells.
+    pub fn grid(&self) -> (u32, u32) {
+        self.grid
+    }
+
+    /// Rebuilds the pipeline, e.g. after the MSAA sample count changed.
+    pub fn recreate_pipeline(
+        &mut self,
+        device: &wgpu::Device,
+        scene_layouts: [&wgpu::BindGroupLayout; 2],
+        color_format: wgpu::TextureFormat,
+        depth_format: Option<wgpu::TextureFormat>,
+        depth_compare: wgpu::CompareFunction,
+        sample_count: u32,
+    ) {
+        self.pipeline = Self::create_pipeline(
+            device,
+            scene_layouts,
+            &self.bind_group_layout,
+            color_format,
+            depth_format,
+            depth_compare,
+            sample_count,
+        );
+    }
+
+    fn create_instance_buffer(device: &wgpu::Device, capacity: usize) -> wgpu::Buffer {
+        device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Billboard Instance Buffer"),
+            size: (capacity * std::mem::size_of::<BillboardInstance>()) as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        })
+    }
+
+    fn create_pipeline(
+        device: &wgpu::Device,
+        [camera_bind_group_layout, sun_bind_group_layout]: [&wgpu::BindGroupLayout; 2],
+        bind_group_layout: &wgpu::BindGroupLayout,
+        color_format: wgpu::TextureFormat,
+        depth_format: Option<wgpu::TextureFormat>,
+        depth_compare: wgpu::CompareFunction,
+        sample_count: u32,
+    ) -> wgpu::RenderPipeline {
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Billboard Pipeline Layout"),
+            bind_group_layouts: &[
+                camera_bind_group_layout,
+                bind_group_layout,
+                sun_bind_group_layout,
+            ],
+            push_constant_ranges: &[],
+        });
+        let shader = device.create_shader_module(wgsl!("src/billboard.wgsl", "src/prelude.wgsl"));
+        device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Billboard Pipeline"),
+            layout: Some(&layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: Some("vs_main"),
+                buffers: &[BillboardInstance::desc()],
+                compilation_options: Default::default(),
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: Some("fs_main"),
+                targets: &[Some(wgpu::ColorTargetState {
+                    format: color_format,
+                    blend: Some(wgpu::BlendState::REPLACE),
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+                compilation_options: Default::default(),
+            }),
+            primitive: wgpu::PrimitiveState {
+                topology: wgpu::PrimitiveTopology::TriangleStrip,
+                ..Default::default()
+            },
+            depth_stencil: depth_format.map(|format| wgpu::DepthStencilState {
+                format,
+                depth_write_enabled: true,
+                depth_compare,
+                stencil: wgpu::StencilState::default(),
+                bias: wgpu::DepthBiasState::default(),
+            }),
+            multisample: wgpu::MultisampleState {
+                count: sample_count,
+                ..Default::default()
+            },
+            multiview: None,
+            cache: None,
+        })
+    }
+
+    /// Replaces the billboards drawn, growing the instance buffer if needed.
+    pub fn upload(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        instances: &[BillboardInstance],
+    ) {
+        if instances.len() > self.capacity {
+            self.capacity = instances.len().next_power_of_two();
+            self.instance_buffer = Self::create_instance_buffer(device, self.capacity);
+        }
+        queue.write_buffer(&self.instance_buffer, 0, bytemuck::cast_slice(instances));
+        self.count = instances.len() as u32;
+    }
+
+    /// Number of billboards written by the last `upload`.
+    pub fn count(&self) -> u32 {
+        self.count
+    }
+
+    /// Draws the billboards of the last `upload`, if any. Changes bind groups 0 to 2 to
+    /// the camera, atlas and sun.
+    pub fn draw(
+        &self,
+        pass: &mut wgpu::RenderPass<'_>,
+        camera_bind_group: &wgpu::BindGroup,
+        sun_bind_group: &wgpu::BindGroup,
+    ) {
+        if self.count == 0 {
+            return;
+        }
+        pass.set_pipeline(&self.pipeline);
+        pass.set_bind_group(0, camera_bind_group, &[]);
+        pass.set_bind_group(1, &self.bind_group, &[]);
+        pass.set_bind_group(2, sun_bind_group, &[]);
+        pass.set_vertex_buffer(0, self.instance_buffer.slice(..));
+        pass.draw(0..4, 0..self.count);
+    }
+}
+
+/// Far LOD for instanced meshes: each instance the selector puts past LOD 0 is drawn as a
+/// billboard sized to its bounds instead of its mesh.
+pub struct ImpostorLod {
+    pub billboard: Billboard,
+    pub selector: LodSelector,
+    /// Atlas cell showing the mesh
+    pub atlas_index: u32,
+    /// LOD of each instance by index, `None` until it is first selected
+    lods: Vec<Option<usize>>,
+}
+
+impl ImpostorLod {
+    pub fn new(billboard: Billboard, selector: LodSelector, atlas_index: u32) -> Self {
+        Self {
+            billboard,
+            selector,
+            atlas_index,
+            lods: Vec::new(),
+        }
+    }
+
+    /// Selects the LOD of instance `index` with world space `bounds` as seen from `eye`
+    /// and returns whether it should be drawn as a billboard.
+    pub fn use_billboard(&mut self, index: usize, eye: Point3<f32>, bounds: &Aabb) -> bool {
+        if index >= self.lods.len() {
+            self.lods.resize(index + 1, None);
+        }
+        let radius = (bounds.max - bounds.min).magnitude() / 2.0;
+        let lod = self
+            .selector
+            .select(eye, bounds.center(), radius, self.lods[index]);
+        self.lods[index] = Some(lod);
+        lod > 0
+    }
+
+    /// A billboard covering `bounds`, as wide as its widest horizontal extent.
+    pub fn billboard_instance(&self, bounds: &Aabb) -> BillboardInstance {
+        let extent = bounds.max - bounds.min;
+        BillboardInstance {
+            position: bounds.center().into(),
+            size: [extent.x.max(extent.z), extent.y],
+            atlas_index: self.atlas_index,
+        }
+    }
+
+    /// Forgets the selected LODs, e.g. after the instances were replaced.
+    pub fn reset(&mut self) {
+        self.lods.clear();
+    }
+}
@@ -0,0 +1,67 @@
+// `CameraUniform` and the other shared types are declared in `src/prelude.wgsl`
+
+@group(0) @binding(0)
+var<uniform> camera: CameraUniform;
+
+// Number of cells across and down the atlas
+struct Atlas {
+    grid: vec2<u32>,
+}
+
+@group(1) @binding(0)
+var t_atlas: texture_2d<f32>;
+@group(1) @binding(1)
+var s_atlas: sampler;
+@group(1) @binding(2)
+var<uniform> atlas: Atlas;
+
+@group(2) @binding(0)
+var<uniform> sun: DirectionalLight;
+
+struct InstanceInput {
+    @location(0) position: vec3<f32>,
+    @location(1) size: vec2<f32>,
+    @location(2) atlas_index: u32,
+}
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) uv: vec2<f32>,
+    @location(1) world_normal: vec3<f32>,
+}
+
+@vertex
+fn vs_main(@builtin(vertex_index) id: u32, instance: InstanceInput) -> VertexOutput {
+    // Triangle strip over the corners (0, 0), (1, 0), (0, 1), (1, 1)
+    let corner = vec2<f32>(f32(id & 1u), f32(id >> 1u));
+    // The first row of the view matrix is the camera's right axis in world space. Turning
+    // only about world up keeps trees upright when looking down at them.
+    let right = normalize(vec3<f32>(camera.view[0].x, camera.view[1].x, camera.view[2].x));
+    let up = vec3<f32>(0.0, 1.0, 0.0);
+    let offset = (corner - 0.5) * instance.size;
+    let world_position = instance.position + right * offset.x + up * offset.y;
+
+    let cell = vec2<f32>(
+        f32(instance.atlas_index % atlas.grid.x),
+        f32(instance.atlas_index / atlas.grid.x),
+    );
+    var out: VertexOutput;
+    out.clip_position = camera.view_proj * vec4<f32>(world_position, 1.0);
+    // Texture rows go down, the quad goes up
+    out.uv = (cell + vec2<f32>(corner.x, 1.0 - corner.y)) / vec2<f32>(atlas.grid);
+    // Facing the camera, so the sun lights the billboard like the side of the mesh it shows
+    out.world_normal = cross(right, up);
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let color = textureSample(t_atlas, s_atlas, in.uv);
+    // Cut out the background around the object, depth then sorts billboards for free
+    if color.a < 0.5 {
+        discard;
+    }
+    // Ambient and sun diffuse, as `shader.wgsl` shades the mesh
+    let sun_diffuse = sun.color * max(dot(normalize(in.world_normal), -sun.direction), 0.0);
+    return vec4<f32>((AMBIENT_STRENGTH + sun_diffuse) * color.rgb, 1.0);
+}
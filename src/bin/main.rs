@@ -1,31 +1,120 @@
+#[cfg(not(target_arch = "wasm32"))]
 use std::time::Instant;
+// `std::time::Instant` panics in the browser
+#[cfg(target_arch = "wasm32")]
+use web_time::Instant;
 
 use winit::{
     application::ApplicationHandler,
-    event::{self, DeviceEvent, WindowEvent},
-    event_loop::EventLoop,
+    event::{self, DeviceEvent, ElementState, KeyEvent, WindowEvent},
+    event_loop::{ControlFlow, EventLoop, EventLoopProxy},
+    keyboard::{KeyCode, PhysicalKey},
     window::Window,
 };
 
 use rust_terrain_codex::state::WindowState;
 
+/// Id of the `<canvas>` element drawn into in the browser, see `index.html`.
+#[cfg(target_arch = "wasm32")]
+const CANVAS_ID: &str = "canvas";
+
 struct App {
     window_state: Option<WindowState>,
     last_render_time: Instant,
+    /// Hands the window state back to the event loop once the browser finished
+    /// initializing it asynchronously
+    #[cfg_attr(not(target_arch = "wasm32"), allow(unused))]
+    proxy: EventLoopProxy<WindowState>,
+    #[cfg(feature = "gamepad")]
+    gilrs: Option<gilrs::Gilrs>,
+}
+
+/// Feeds the analog state of the last gamepad that sent an event to the camera controller.
+#[cfg(feature = "gamepad")]
+fn poll_gamepad(gilrs: &mut gilrs::Gilrs, window_state: &mut WindowState) {
+    use gilrs::{Axis, Button};
+
+    let mut active = None;
+    while let Some(gilrs::Event { id, .. }) = gilrs.next_event() {
+        active = Some(id);
+    }
+    let Some(id) = active else {
+        return;
+    };
+
+    let gamepad = gilrs.gamepad(id);
+    let axis = |axis| gamepad.axis_data(axis).map_or(0.0, |data| data.value());
+    let button = |button| gamepad.button_data(button).map_or(0.0, |data| data.value());
+    let input = rust_terrain_codex::camera::GamepadInput {
+        left_stick: cgmath::Vector2::new(axis(Axis::LeftStickX), axis(Axis::LeftStickY)),
+        right_stick: cgmath::Vector2::new(axis(Axis::RightStickX), axis(Axis::RightStickY)),
+        left_trigger: button(Button::LeftTrigger2),
+        right_trigger: button(Button::RightTrigger2),
+    };
+    window_state.camera_controller.process_gamepad(&input);
 }
 
-impl ApplicationHandler for App {
+impl ApplicationHandler<WindowState> for App {
     fn resumed(&mut self, event_loop: &winit::event_loop::ActiveEventLoop) {
         if self.window_state.is_some() {
             return;
         }
 
-        let window = std::sync::Arc::new(
-            event_loop
-                .create_window(Window::default_attributes().with_title("Hello, Winit!"))
-                .unwrap(),
-        );
-        self.window_state = Some(pollster::block_on(WindowState::new(window.clone())));
+        #[allow(unused_mut)]
+        let mut attributes = Window::default_attributes().with_title("Hello, Winit!");
+        #[cfg(target_arch = "wasm32")]
+        {
+            use wasm_bindgen::JsCast;
+            use winit::platform::web::WindowAttributesExtWebSys;
+
+            // Without a canvas on the page winit creates one and appends it to the body
+            let canvas = web_sys::window()
+                .and_then(|window| window.document())
+                .and_then(|document| document.get_element_by_id(CANVAS_ID))
+                .and_then(|element| element.dyn_into::<web_sys::HtmlCanvasElement>().ok());
+            attributes = attributes.with_canvas(canvas).with_append(true);
+        }
+        let window = std::sync::Arc::new(event_loop.create_window(attributes).unwrap());
+
+        #[cfg(not(target_arch = "wasm32"))]
+        {
+            self.window_state = Some(pollster::block_on(WindowState::new(window.clone())));
+        }
+        // The browser can't block on the GPU setup, finish it in the background and pick
+        // the result up in `user_event`
+        #[cfg(target_arch = "wasm32")]
+        {
+            let proxy = self.proxy.clone();
+            wasm_bindgen_futures::spawn_local(async move {
+                let window_state = WindowState::new(window).await;
+                if proxy.send_event(window_state).is_err() {
+                    log::error!("Event loop closed before the window state was ready");
+                }
+            });
+        }
+    }
+
+    fn user_event(&mut self, _: &winit::event_loop::ActiveEventLoop, window_state: WindowState) {
+        window_state.request_redraw();
+        self.last_render_time = Instant::now();
+        self.window_state = Some(window_state);
+    }
+
+    fn about_to_wait(&mut self, event_loop: &winit::event_loop::ActiveEventLoop) {
+        let Some(window_state) = self.window_state.as_ref() else {
+            return;
+        };
+        // Sleeps until the next frame is due rather than rendering as fast as possible
+        match window_state.frame_interval() {
+            Some(interval) => {
+                let next_frame = self.last_render_time + interval;
+                if Instant::now() >= next_frame {
+                    window_state.request_redraw();
+                }
+                event_loop.set_control_flow(ControlFlow::WaitUntil(next_frame));
+            }
+            None => event_loop.set_control_flow(ControlFlow::Poll),
+        }
     }
 
     fn device_event(
@@ -36,7 +125,8 @@ impl ApplicationHandler for App {
     ) {
         if let Some(window_state) = self.window_state.as_mut()
             && let DeviceEvent::MouseMotion { delta } = event
-            && window_state.mouse_pressed
+            && window_state.mouse_look()
+            && window_state.camera_path_mut().is_none()
         {
             window_state
                 .camera_controller
@@ -59,6 +149,10 @@ impl ApplicationHandler for App {
                     let now = Instant::now();
                     let dt = now - self.last_render_time;
                     self.last_render_time = now;
+                    #[cfg(feature = "gamepad")]
+                    if let Some(gilrs) = self.gilrs.as_mut() {
+                        poll_gamepad(gilrs, window_state);
+                    }
                     window_state.update(dt);
                     match window_state.render() {
                         Ok(_) => {}
@@ -67,6 +161,19 @@ impl ApplicationHandler for App {
                         }
                     }
                 }
+                WindowEvent::KeyboardInput {
+                    event:
+                        KeyEvent {
+                            physical_key: PhysicalKey::Code(KeyCode::F12),
+                            state: ElementState::Pressed,
+                            repeat: false,
+                            ..
+                        },
+                    ..
+                } => match window_state.save_screenshot("screenshot.png") {
+                    Ok(()) => log::info!("Saved screenshot.png"),
+                    Err(e) => log::error!("Unable to save screenshot {}", e),
+                },
                 WindowEvent::CloseRequested => {
                     event_loop.exit();
                 }
@@ -79,13 +186,33 @@ impl ApplicationHandler for App {
 }
 
 fn main() {
+    #[cfg(not(target_arch = "wasm32"))]
     env_logger::init();
-    let event_loop = EventLoop::new().unwrap();
-    event_loop.set_control_flow(winit::event_loop::ControlFlow::Poll);
+    #[cfg(target_arch = "wasm32")]
+    {
+        console_error_panic_hook::set_once();
+        console_log::init_with_level(log::Level::Info).expect("failed to initialize logging");
+    }
+
+    let event_loop = EventLoop::<WindowState>::with_user_event().build().unwrap();
+    event_loop.set_control_flow(ControlFlow::Poll);
 
+    #[cfg_attr(target_arch = "wasm32", allow(unused_mut))]
     let mut app = App {
         window_state: None,
         last_render_time: Instant::now(),
+        proxy: event_loop.create_proxy(),
+        #[cfg(feature = "gamepad")]
+        gilrs: gilrs::Gilrs::new()
+            .inspect_err(|e| log::error!("Unable to initialize gamepad support {}", e))
+            .ok(),
     };
+    #[cfg(not(target_arch = "wasm32"))]
     event_loop.run_app(&mut app).unwrap();
+    // `run_app` never returns in the browser, hand the app over to it instead
+    #[cfg(target_arch = "wasm32")]
+    {
+        use winit::platform::web::EventLoopExtWebSys;
+        event_loop.spawn_app(app);
+    }
 }
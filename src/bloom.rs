@@ -0,0 +1,350 @@
+use wgpu::util::DeviceExt as _;
+
+use crate::{
+    hot_reload::wgsl,
+    texture::{MipmapBlit, Texture},
+    tonemap::Tonemapper,
+};
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
+struct BloomUniform {
+    threshold: f32,
+    knee: f32,
+    intensity: f32,
+    levels: f32,
+}
+
+/// Makes bright parts of the HDR scene, e.g. emissive surfaces or the sun, glow into their
+/// surroundings before tone mapping.
+///
+/// Colors above `threshold` are downsampled through a mip chain starting at half the
+/// target's resolution and upsampled back, adding each level onto the next larger one, the
+/// dual filter. The result is added onto the HDR target scaled by `intensity`. Like
+/// `Texture::generate_mipmaps` every level is drawn by a fullscreen triangle sampling the
+/// level before it, through the same `Texture::mip_level_view` and `MipmapBlit::draw`, but
+/// with filters of its own and the pipelines and views kept for every frame.
+pub struct Bloom {
+    uniform: BloomUniform,
+    buffer: wgpu::Buffer,
+    bind_group_layout: wgpu::BindGroupLayout,
+    sampler: wgpu::Sampler,
+    prefilter_pipeline: wgpu::RenderPipeline,
+    downsample_pipeline: wgpu::RenderPipeline,
+    upsample_pipeline: wgpu::RenderPipeline,
+    composite_pipeline: wgpu::RenderPipeline,
+    /// One view per level of the chain, largest first
+    levels: Vec<wgpu::TextureView>,
+    /// Binds the HDR target, the source of the first level
+    source_bind_group: wgpu::BindGroup,
+    /// Binds each level as the source of the next pass
+    level_bind_groups: Vec<wgpu::BindGroup>,
+}
+
+impl Bloom {
+    /// Most levels of the chain, the last one being 1/64 of the target's size
+    pub const MAX_LEVELS: u32 = 6;
+    /// Levels stop before getting smaller than this many texels on a side
+    const MIN_LEVEL_SIZE: u32 = 8;
+
+    /// Bloom for an HDR target of `width` x `height` bound from `source`.
+    pub fn new(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        source: &wgpu::TextureView,
+        width: u32,
+        height: u32,
+        intensity: f32,
+        threshold: f32,
+    ) -> Self {
+        let uniform = BloomUniform {
+            threshold: threshold.max(0.0),
+            knee: 0.5 * threshold.max(0.0),
+            intensity: intensity.max(0.0),
+            levels: 1.0,
+        };
+        let buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Bloom Buffer"),
+            contents: bytemuck::cast_slice(&[uniform]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        multisampled: false,
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ],
+            label: Some("bloom_bind_group_layout"),
+        });
+        let (prefilter_pipeline, downsample_pipeline, upsample_pipeline, composite_pipeline) =
+            Self::create_pipelines(device, &bind_group_layout);
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("Bloom Sampler"),
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
+
+        let source_bind_group =
+            Self::create_bind_group(device, &bind_group_layout, &sampler, &buffer, source);
+        let mut bloom = Self {
+            uniform,
+            buffer,
+            bind_group_layout,
+            sampler,
+            prefilter_pipeline,
+            downsample_pipeline,
+            upsample_pipeline,
+            composite_pipeline,
+            levels: Vec::new(),
+            source_bind_group,
+            level_bind_groups: Vec::new(),
+        };
+        bloom.resize(device, queue, source, width, height);
+        bloom
+    }
+
+    /// The prefilter, downsample, upsample and composite pipelines.
+    fn create_pipelines(
+        device: &wgpu::Device,
+        bind_group_layout: &wgpu::BindGroupLayout,
+    ) -> (
+        wgpu::RenderPipeline,
+        wgpu::RenderPipeline,
+        wgpu::RenderPipeline,
+        wgpu::RenderPipeline,
+    ) {
+        let shader = device.create_shader_module(wgsl!("src/bloom.wgsl", "src/fullscreen.wgsl"));
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Bloom Pipeline Layout"),
+            bind_group_layouts: &[bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let additive = wgpu::BlendState {
+            color: wgpu::BlendComponent {
+                src_factor: wgpu::BlendFactor::One,
+                dst_factor: wgpu::BlendFactor::One,
+                operation: wgpu::BlendOperation::Add,
+            },
+            alpha: wgpu::BlendComponent::OVER,
+        };
+        let create_pipeline = |label, entry_point, blend| {
+            device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                label: Some(label),
+                layout: Some(&pipeline_layout),
+                vertex: wgpu::VertexState {
+                    module: &shader,
+                    entry_point: Some("vs_main"),
+                    buffers: &[],
+                    compilation_options: Default::default(),
+                },
+                fragment: Some(wgpu::FragmentState {
+                    module: &shader,
+                    entry_point: Some(entry_point),
+                    targets: &[Some(wgpu::ColorTargetState {
+                        format: Tonemapper::HDR_FORMAT,
+                        blend,
+                        write_mask: wgpu::ColorWrites::ALL,
+                    })],
+                    compilation_options: Default::default(),
+                }),
+                primitive: wgpu::PrimitiveState::default(),
+                depth_stencil: None,
+                multisample: wgpu::MultisampleState::default(),
+                multiview: None,
+                cache: None,
+            })
+        };
+        (
+            create_pipeline("Bloom Prefilter Pipeline", "fs_prefilter", None),
+            create_pipeline("Bloom Downsample Pipeline", "fs_downsample", None),
+            create_pipeline("Bloom Upsample Pipeline", "fs_upsample", Some(additive)),
+            create_pipeline("Bloom Composite Pipeline", "fs_composite", Some(additive)),
+        )
+    }
+
+    /// Rebuilds the pipelines from the current shader, see `hot_reload`.
+    pub fn recreate_pipelines(&mut self, device: &wgpu::Device) {
+        (
+            self.prefilter_pipeline,
+            self.downsample_pipeline,
+            self.upsample_pipeline,
+            self.composite_pipeline,
+        ) = Self::create_pipelines(device, &self.bind_group_layout);
+    }
+
+    fn create_bind_group(
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        sampler: &wgpu::Sampler,
+        buffer: &wgpu::Buffer,
+        source: &wgpu::TextureView,
+    ) -> wgpu::BindGroup {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(source),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(sampler),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: buffer.as_entire_binding(),
+                },
+            ],
+            label: Some("bloom_bind_group"),
+        })
+    }
+
+    /// Recreates the mip chain for a new HDR target. Call it whenever the target is
+    /// recreated.
+    pub fn resize(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        source: &wgpu::TextureView,
+        width: u32,
+        height: u32,
+    ) {
+        let size = wgpu::Extent3d {
+            width: (width / 2).max(1),
+            height: (height / 2).max(1),
+            depth_or_array_layers: 1,
+        };
+        let mut level_count = 1;
+        while level_count < Self::MAX_LEVELS
+            && (size.width >> level_count).min(size.height >> level_count) >= Self::MIN_LEVEL_SIZE
+        {
+            level_count += 1;
+        }
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("bloom_texture"),
+            size,
+            mip_level_count: level_count,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: Tonemapper::HDR_FORMAT,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+            view_formats: &[],
+        });
+        self.levels = (0..level_count)
+            .map(|level| Texture::mip_level_view(&texture, level, 0))
+            .collect();
+        self.source_bind_group = Self::create_bind_group(
+            device,
+            &self.bind_group_layout,
+            &self.sampler,
+            &self.buffer,
+            source,
+        );
+        self.level_bind_groups = self
+            .levels
+            .iter()
+            .map(|view| {
+                Self::create_bind_group(
+                    device,
+                    &self.bind_group_layout,
+                    &self.sampler,
+                    &self.buffer,
+                    view,
+                )
+            })
+            .collect();
+        self.uniform.levels = level_count as f32;
+        self.upload(queue);
+    }
+
+    pub fn intensity(&self) -> f32 {
+        self.uniform.intensity
+    }
+
+    pub fn threshold(&self) -> f32 {
+        self.uniform.threshold
+    }
+
+    /// Scales the glow added onto the scene by `intensity` and lets colors brighter than
+    /// `threshold` bloom, fading in from half the threshold.
+    pub fn set(&mut self, queue: &wgpu::Queue, intensity: f32, threshold: f32) {
+        self.uniform.intensity = intensity.max(0.0);
+        self.uniform.threshold = threshold.max(0.0);
+        self.uniform.knee = 0.5 * self.uniform.threshold;
+        self.upload(queue);
+    }
+
+    fn upload(&self, queue: &wgpu::Queue) {
+        queue.write_buffer(&self.buffer, 0, bytemuck::cast_slice(&[self.uniform]));
+    }
+
+    /// Records the chain and adds the glow onto `target`, the HDR target bound at creation
+    /// or the last `resize`. Returns the number of draw calls.
+    pub fn apply(&self, encoder: &mut wgpu::CommandEncoder, target: &wgpu::TextureView) -> u32 {
+        let mut draws = 0;
+        let mut pass = |label, pipeline, bind_group, view, load| {
+            MipmapBlit::draw(encoder, label, pipeline, bind_group, view, load);
+            draws += 1;
+        };
+        let clear = wgpu::LoadOp::Clear(wgpu::Color::BLACK);
+
+        pass(
+            "Bloom Prefilter Pass",
+            &self.prefilter_pipeline,
+            &self.source_bind_group,
+            &self.levels[0],
+            clear,
+        );
+        for level in 1..self.levels.len() {
+            pass(
+                "Bloom Downsample Pass",
+                &self.downsample_pipeline,
+                &self.level_bind_groups[level - 1],
+                &self.levels[level],
+                clear,
+            );
+        }
+        for level in (0..self.levels.len() - 1).rev() {
+            pass(
+                "Bloom Upsample Pass",
+                &self.upsample_pipeline,
+                &self.level_bind_groups[level + 1],
+                &self.levels[level],
+                wgpu::LoadOp::Load,
+            );
+        }
+        pass(
+            "Bloom Composite Pass",
+            &self.composite_pipeline,
+            &self.level_bind_groups[0],
+            target,
+            wgpu::LoadOp::Load,
+        );
+        draws
+    }
+}
@@ -0,0 +1,81 @@
+// Bloom with the dual filter (Bjørge, "Bandwidth-Efficient Rendering", 2015): the bright
+// parts of the HDR scene are downsampled through a mip chain and upsampled back, each step
+// blurring a little, then added onto the scene
+
+struct Bloom {
+  // Brightness above which colors bloom
+  threshold: f32,
+  // Width of the soft transition below `threshold`
+  knee: f32,
+  intensity: f32,
+  // Levels of the chain, whose sum the composite divides by
+  levels: f32,
+};
+
+// `vs_main` and `FullscreenOutput` are appended from `src/fullscreen.wgsl`
+
+@group(0) @binding(0)
+var t_src: texture_2d<f32>;
+@group(0) @binding(1)
+var s_src: sampler;
+@group(0) @binding(2)
+var<uniform> bloom: Bloom;
+
+fn tap(uv: vec2<f32>) -> vec3<f32> {
+  return textureSample(t_src, s_src, uv).rgb;
+}
+
+// Halves the resolution: the center and four bilinear taps a texel away diagonally
+fn downsample(uv: vec2<f32>) -> vec3<f32> {
+  let o = 1.0 / vec2<f32>(textureDimensions(t_src));
+  var sum = tap(uv) * 4.0;
+  sum += tap(uv - o);
+  sum += tap(uv + o);
+  sum += tap(uv + vec2<f32>(o.x, -o.y));
+  sum += tap(uv - vec2<f32>(o.x, -o.y));
+  return sum / 8.0;
+}
+
+// Doubles the resolution with a tent of eight taps around the destination texel
+fn upsample(uv: vec2<f32>) -> vec3<f32> {
+  let o = 0.5 / vec2<f32>(textureDimensions(t_src));
+  var sum = tap(uv + vec2<f32>(-2.0 * o.x, 0.0));
+  sum += tap(uv + vec2<f32>(2.0 * o.x, 0.0));
+  sum += tap(uv + vec2<f32>(0.0, -2.0 * o.y));
+  sum += tap(uv + vec2<f32>(0.0, 2.0 * o.y));
+  sum += tap(uv + vec2<f32>(-o.x, o.y)) * 2.0;
+  sum += tap(uv + vec2<f32>(o.x, o.y)) * 2.0;
+  sum += tap(uv + vec2<f32>(o.x, -o.y)) * 2.0;
+  sum += tap(uv + vec2<f32>(-o.x, -o.y)) * 2.0;
+  return sum / 12.0;
+}
+
+// Downsamples the scene keeping only what is brighter than the threshold, fading in over
+// the knee so bloom doesn't pop on and off
+@fragment
+fn fs_prefilter(in: FullscreenOutput) -> @location(0) vec4<f32> {
+  // Single very bright pixels would flicker as they move between texels
+  let color = min(downsample(in.uv), vec3<f32>(64.0));
+  let brightness = max(color.r, max(color.g, color.b));
+  var soft = clamp(brightness - bloom.threshold + bloom.knee, 0.0, 2.0 * bloom.knee);
+  soft = soft * soft / (4.0 * bloom.knee + 0.0001);
+  let contribution = max(soft, brightness - bloom.threshold) / max(brightness, 0.0001);
+  return vec4<f32>(color * contribution, 1.0);
+}
+
+@fragment
+fn fs_downsample(in: FullscreenOutput) -> @location(0) vec4<f32> {
+  return vec4<f32>(downsample(in.uv), 1.0);
+}
+
+// Added onto the next larger level
+@fragment
+fn fs_upsample(in: FullscreenOutput) -> @location(0) vec4<f32> {
+  return vec4<f32>(upsample(in.uv), 1.0);
+}
+
+// Added onto the scene
+@fragment
+fn fs_composite(in: FullscreenOutput) -> @location(0) vec4<f32> {
+  return vec4<f32>(upsample(in.uv) * bloom.intensity / bloom.levels, 0.0);
+}
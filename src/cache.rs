@@ -0,0 +1,141 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
+use crate::{model, texture};
+
+/// Identifies a texture loaded from a file. The same file loaded as a normal map, or with
+/// different filtering, is a different GPU texture.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct TextureKey {
+    pub path: String,
+    pub is_normal_map: bool,
+    pub anisotropy: u16,
+}
+
+/// Lookups answered by a `ResourceCache` since it was created or cleared.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CacheStats {
+    pub texture_hits: u64,
+    pub texture_misses: u64,
+    pub material_hits: u64,
+    pub material_misses: u64,
+}
+
+impl CacheStats {
+    /// Share of all lookups that found their resource, 0 before the first lookup.
+    pub fn hit_rate(&self) -> f32 {
+        let hits = self.texture_hits + self.material_hits;
+        let total = hits + self.texture_misses + self.material_misses;
+        if total == 0 {
+            0.0
+        } else {
+            hits as f32 / total as f32
+        }
+    }
+}
+
+/// Textures and materials already uploaded to the GPU, so loading the same ones again
+/// shares them instead of uploading duplicates. Safe to share between threads, e.g. with the
+/// workers of a `ResourceLoader`. In the browser the GPU resources aren't `Send`, but there
+/// is only one thread to share them on, so the `Arc`s are still fine there.
+#[derive(Default)]
+pub struct ResourceCache {
+    textures: Mutex<HashMap<TextureKey, Arc<texture::Texture>>>,
+    /// Keyed by the files of the textures the material binds
+    materials: Mutex<HashMap<String, Arc<model::Material>>>,
+    stats: Mutex<CacheStats>,
+}
+
+impl ResourceCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The texture loaded for `key` before, if any.
+    pub fn texture(&self, key: &TextureKey) -> Option<Arc<texture::Texture>> {
+        let texture = self.textures.lock().unwrap().get(key).cloned();
+        let mut stats = self.stats.lock().unwrap();
+        if texture.is_some() {
+            stats.texture_hits += 1;
+        } else {
+            stats.texture_misses += 1;
+        }
+        texture
+    }
+
+    /// Keeps `texture` for later lookups of `key`. If another thread inserted one for `key`
+    /// in the meantime, that one is kept and returned instead.
+    pub fn insert_texture(
+        &self,
+        key: TextureKey,
+        texture: texture::Texture,
+    ) -> Arc<texture::Texture> {
+        self.textures
+            .lock()
+            .unwrap()
+            .entry(key)
+            .or_insert_with(|| Arc::new(texture))
+            .clone()
+    }
+
+    /// The texture for `key`, loaded by `load` only if it isn't cached yet.
+    pub fn texture_or_load(
+        &self,
+        key: TextureKey,
+        load: impl FnOnce(&TextureKey) -> anyhow::Result<texture::Texture>,
+    ) -> anyhow::Result<Arc<texture::Texture>> {
+        if let Some(texture) = self.texture(&key) {
+            return Ok(texture);
+        }
+        let texture = load(&key)?;
+        Ok(self.insert_texture(key, texture))
+    }
+
+    /// The material created before for `key`, if any.
+    pub fn material(&self, key: &str) -> Option<Arc<model::Material>> {
+        let material = self.materials.lock().unwrap().get(key).cloned();
+        let mut stats = self.stats.lock().unwrap();
+        if material.is_some() {
+            stats.material_hits += 1;
+        } else {
+            stats.material_misses += 1;
+        }
+        material
+    }
+
+    /// Keeps `material` for later lookups of `key`, like `insert_texture`.
+    pub fn insert_material(
+        &self,
+        key: impl Into<String>,
+        material: model::Material,
+    ) -> Arc<model::Material> {
+        self.materials
+            .lock()
+            .unwrap()
+            .entry(key.into())
+            .or_insert_with(|| Arc::new(material))
+            .clone()
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        *self.stats.lock().unwrap()
+    }
+
+    /// Number of cached textures and materials.
+    pub fn counts(&self) -> (usize, usize) {
+        (
+            self.textures.lock().unwrap().len(),
+            self.materials.lock().unwrap().len(),
+        )
+    }
+
+    /// Drops every cached resource and resets the stats. Resources still in use stay alive
+    /// until their last user drops them.
+    pub fn clear(&self) {
+        self.textures.lock().unwrap().clear();
+        self.materials.lock().unwrap().clear();
+        *self.stats.lock().unwrap() = CacheStats::default();
+    }
+}
@@ -1,26 +1,63 @@
-use std::{f32::consts::FRAC_PI_2, time::Duration};
+use std::{collections::HashMap, f32::consts::FRAC_PI_2, time::Duration};
 
-use cgmath::{InnerSpace, Matrix, Matrix4, Point3, Rad, SquareMatrix, Vector3};
+use cgmath::{Deg, InnerSpace, Matrix4, Point3, Rad, SquareMatrix, Vector2, Vector3, Zero};
+use serde::{Deserialize, Serialize};
 use winit::{
     dpi::PhysicalPosition,
     event::{ElementState, MouseScrollDelta},
     keyboard::KeyCode,
 };
 
+/// cgmath produces OpenGL style clip space with depth in -1..1, wgpu expects 0..1.
+#[rustfmt::skip]
+pub const OPENGL_TO_WGPU_MATRIX: Matrix4<f32> = Matrix4::new(
+    1.0, 0.0, 0.0, 0.0,
+    0.0, 1.0, 0.0, 0.0,
+    0.0, 0.0, 0.5, 0.0,
+    0.0, 0.0, 0.5, 1.0,
+);
+
+/// Flips wgpu's 0..1 depth so the near plane maps to 1 and the far plane to 0. Floats are
+/// densest near 0, which then balances the perspective divide crowding distant depths.
+#[rustfmt::skip]
+pub const REVERSE_Z_MATRIX: Matrix4<f32> = Matrix4::new(
+    1.0, 0.0, 0.0, 0.0,
+    0.0, 1.0, 0.0, 0.0,
+    0.0, 0.0, -1.0, 0.0,
+    0.0, 0.0, 1.0, 1.0,
+);
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ProjectionKind {
+    Perspective {
+        fovy: Rad<f32>,
+    },
+    /// `height` is the vertical extent of the view volume in world units.
+    Orthographic {
+        height: f32,
+    },
+}
+
+#[derive(Debug, Clone, Copy)]
 pub struct Projection {
     aspect: f32,
-    fovy: Rad<f32>,
+    kind: ProjectionKind,
     znear: f32,
     zfar: f32,
+    reverse_z: bool,
+    /// Offset of the image in normalized device coordinates, see `set_jitter`
+    jitter: Vector2<f32>,
 }
 
 impl Projection {
     pub fn new<F: Into<Rad<f32>>>(width: u32, height: u32, fovy: F, znear: f32, zfar: f32) -> Self {
         Self {
             aspect: width as f32 / height as f32,
-            fovy: fovy.into(),
+            kind: ProjectionKind::Perspective { fovy: fovy.into() },
             znear,
             zfar,
+            reverse_z: false,
+            jitter: Vector2::zero(),
         }
     }
 
@@ -28,11 +65,125 @@ impl Projection {
         self.aspect = width as f32 / height as f32;
     }
 
+    pub fn kind(&self) -> ProjectionKind {
+        self.kind
+    }
+
+    pub fn set_perspective<F: Into<Rad<f32>>>(&mut self, fovy: F) {
+        self.kind = ProjectionKind::Perspective { fovy: fovy.into() };
+    }
+
+    /// Switches to an orthographic projection `height` world units tall. Fails, leaving
+    /// the projection as it was, unless `height` is positive.
+    pub fn set_orthographic(&mut self, height: f32) -> anyhow::Result<()> {
+        if !(height > 0.0 && height.is_finite()) {
+            anyhow::bail!("invalid orthographic height {height}, expected a positive height");
+        }
+        self.kind = ProjectionKind::Orthographic { height };
+        Ok(())
+    }
+
+    pub fn znear(&self) -> f32 {
+        self.znear
+    }
+
+    pub fn zfar(&self) -> f32 {
+        self.zfar
+    }
+
+    /// Moves the near and far planes, e.g. to fit the scale of a scene. Depth precision
+    /// depends mostly on `znear`, so it is best kept as large as the scene allows. Fails,
+    /// leaving the planes as they were, unless `0 < znear < zfar`.
+    pub fn set_clip_planes(&mut self, znear: f32, zfar: f32) -> anyhow::Result<()> {
+        if !(znear > 0.0 && zfar > znear && zfar.is_finite()) {
+            anyhow::bail!("invalid clip planes {znear}..{zfar}, expected 0 < znear < zfar");
+        }
+        self.znear = znear;
+        self.zfar = zfar;
+        Ok(())
+    }
+
+    /// `set_clip_planes` keeping the far plane.
+    pub fn set_znear(&mut self, znear: f32) -> anyhow::Result<()> {
+        self.set_clip_planes(znear, self.zfar)
+    }
+
+    /// `set_clip_planes` keeping the near plane.
+    pub fn set_zfar(&mut self, zfar: f32) -> anyhow::Result<()> {
+        self.set_clip_planes(self.znear, zfar)
+    }
+
+    /// This projection cut down to the view depths from `znear` to `zfar`, e.g. to fit a
+    /// shadow cascade around part of the view.
+    pub fn with_depth_range(&self, znear: f32, zfar: f32) -> Self {
+        Self {
+            znear,
+            zfar,
+            ..*self
+        }
+    }
+
+    /// The projection into wgpu's clip space, with depth in 0..1 for both kinds, flipped
+    /// with reverse-Z.
     pub fn calc_matrix(&self) -> Matrix4<f32> {
-        cgmath::perspective(self.fovy, self.aspect, self.znear, self.zfar)
+        let proj = match self.kind {
+            ProjectionKind::Perspective { fovy } => {
+                cgmath::perspective(fovy, self.aspect, self.znear, self.zfar)
+            }
+            ProjectionKind::Orthographic { height } => {
+                let half_height = height / 2.0;
+                let half_width = half_height * self.aspect;
+                cgmath::ortho(
+                    -half_width,
+                    half_width,
+                    -half_height,
+                    half_height,
+                    self.znear,
+                    self.zfar,
+                )
+            }
+        };
+        let proj = OPENGL_TO_WGPU_MATRIX * proj;
+        let proj = if self.reverse_z {
+            REVERSE_Z_MATRIX * proj
+        } else {
+            proj
+        };
+        Matrix4::from_translation(self.jitter.extend(0.0)) * proj
+    }
+
+    pub fn jitter(&self) -> Vector2<f32> {
+        self.jitter
+    }
+
+    /// Shifts the projected image by `jitter` in normalized device coordinates, where a
+    /// pixel is `2 / width` wide and `2 / height` high. TAA moves it by a fraction of a pixel
+    /// each frame to sample different points of every pixel, see `Renderer::set_taa`.
+    pub fn set_jitter(&mut self, jitter: Vector2<f32>) {
+        self.jitter = jitter;
+    }
+
+    /// This projection without the jitter, for what shouldn't shake with it, e.g. the fit
+    /// of the shadow cascades.
+    pub fn without_jitter(&self) -> Self {
+        Self {
+            jitter: Vector2::zero(),
+            ..*self
+        }
+    }
+
+    pub fn reverse_z(&self) -> bool {
+        self.reverse_z
+    }
+
+    /// Maps near to depth 1 and far to 0, see `REVERSE_Z_MATRIX`. The depth buffer has to
+    /// be cleared to 0 and tested with `GreaterEqual` to match, see `RendererOptions`.
+    pub fn set_reverse_z(&mut self, reverse_z: bool) {
+        self.reverse_z = reverse_z;
     }
 }
 
+#[derive(Debug, Clone, Copy)]
 pub struct Camera {
     // pub eye: cgmath::Point3<f32>,
     // pub target: cgmath::Point3<f32>,
@@ -46,7 +197,58 @@ pub struct Camera {
     pitch: Rad<f32>,
 }
 
-const SAFE_FRAC_PI_2: f32 = FRAC_PI_2 - 0.0001;
+pub(crate) const SAFE_FRAC_PI_2: f32 = FRAC_PI_2 - 0.0001;
+
+/// A saved viewpoint: the camera's placement and the projection's lens, with angles in
+/// degrees so the JSON stays readable.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct CameraState {
+    /// `CameraState::VERSION` when written. Files without one predate versioning.
+    #[serde(default)]
+    pub version: u32,
+    pub position: [f32; 3],
+    pub yaw: f32,
+    pub pitch: f32,
+    /// Vertical field of view, ignored when `orthographic_height` is set
+    pub fovy: f32,
+    pub znear: f32,
+    pub zfar: f32,
+    /// Set for orthographic projections
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub orthographic_height: Option<f32>,
+}
+
+impl CameraState {
+    /// Bumped whenever a field is added, newer fields need a `#[serde(default)]` so older
+    /// files keep loading.
+    pub const VERSION: u32 = 1;
+
+    /// Parses a state written by `to_json`. Files from newer versions load with a warning
+    /// as long as the fields this version knows about are there, unknown fields are skipped.
+    pub fn from_json(json: &str) -> anyhow::Result<Self> {
+        let state: Self =
+            serde_json::from_str(json).map_err(|e| anyhow::anyhow!("invalid camera state: {e}"))?;
+        if state.version > Self::VERSION {
+            log::warn!(
+                "Camera state version {} is newer than {}, newer settings are ignored",
+                state.version,
+                Self::VERSION
+            );
+        }
+        let finite = state.position.iter().all(|v| v.is_finite())
+            && [state.yaw, state.pitch, state.fovy, state.znear, state.zfar]
+                .iter()
+                .all(|v| v.is_finite());
+        if !finite || state.znear <= 0.0 || state.zfar <= state.znear {
+            anyhow::bail!("invalid camera state: non-finite values or bad clip planes");
+        }
+        Ok(state)
+    }
+
+    pub fn to_json(&self) -> anyhow::Result<String> {
+        Ok(serde_json::to_string_pretty(self)?)
+    }
+}
 
 impl Camera {
     pub fn new<V: Into<Point3<f32>>, Y: Into<Rad<f32>>, P: Into<Rad<f32>>>(
@@ -61,6 +263,102 @@ impl Camera {
         }
     }
 
+    /// Places the camera at `position` looking towards `target`.
+    pub fn look_at<V: Into<Point3<f32>>, T: Into<Point3<f32>>>(position: V, target: T) -> Self {
+        let position = position.into();
+        let direction = (target.into() - position).normalize();
+        let pitch = direction.y.clamp(-1.0, 1.0).asin();
+        let yaw = direction.z.atan2(direction.x);
+        Self::new(
+            position,
+            Rad(yaw),
+            Rad(pitch.clamp(-SAFE_FRAC_PI_2, SAFE_FRAC_PI_2)),
+        )
+    }
+
+    pub fn yaw(&self) -> Rad<f32> {
+        self.yaw
+    }
+
+    pub fn pitch(&self) -> Rad<f32> {
+        self.pitch
+    }
+
+    /// Captures the camera together with the lens of `projection`.
+    pub fn save_state(&self, projection: &Projection) -> CameraState {
+        let (fovy, orthographic_height) = match projection.kind {
+            ProjectionKind::Perspective { fovy } => (Deg::from(fovy).0, None),
+            // Keep a sensible lens around for switching back to perspective
+            ProjectionKind::Orthographic { height } => (45.0, Some(height)),
+        };
+        CameraState {
+            version: CameraState::VERSION,
+            position: self.position.into(),
+            yaw: Deg::from(self.yaw).0,
+            pitch: Deg::from(self.pitch).0,
+            fovy,
+            znear: projection.znear,
+            zfar: projection.zfar,
+            orthographic_height,
+        }
+    }
+
+    /// Moves the camera to `state` and gives `projection` its lens. The projection keeps
+    /// its aspect ratio, which follows the window instead.
+    pub fn restore_state(&mut self, state: &CameraState, projection: &mut Projection) {
+        self.position = state.position.into();
+        self.yaw = Deg(state.yaw).into();
+        self.pitch = Rad(Rad::from(Deg(state.pitch))
+            .0
+            .clamp(-SAFE_FRAC_PI_2, SAFE_FRAC_PI_2));
+        projection.kind = match state.orthographic_height {
+            Some(height) => ProjectionKind::Orthographic { height },
+            None => ProjectionKind::Perspective {
+                fovy: Deg(state.fovy).into(),
+            },
+        };
+        projection.znear = state.znear;
+        projection.zfar = state.zfar;
+    }
+
+    /// Turns the camera towards `center` and moves it along its view direction until the
+    /// sphere of `radius` around it just fits the view of `projection`, horizontally as
+    /// well as vertically. An orthographic projection is fitted by changing its height
+    /// instead, with the camera backed off far enough to keep the sphere past the near plane.
+    /// A camera sitting at `center` keeps looking where it did.
+    pub fn frame_sphere(&mut self, projection: &mut Projection, center: Point3<f32>, radius: f32) {
+        // Points still get a sphere the near plane can't cut into
+        let radius = radius.max(projection.znear);
+        let direction = if (center - self.position).magnitude2() > 0.0 {
+            (center - self.position).normalize()
+        } else {
+            let (sin_p, cos_p) = self.pitch.0.sin_cos();
+            let (sin_y, cos_y) = self.yaw.0.sin_cos();
+            Vector3::new(cos_p * cos_y, sin_p, cos_p * sin_y)
+        };
+        self.yaw = Rad(direction.z.atan2(direction.x));
+        self.pitch = Rad(direction
+            .y
+            .clamp(-1.0, 1.0)
+            .asin()
+            .clamp(-SAFE_FRAC_PI_2, SAFE_FRAC_PI_2));
+
+        let distance = match projection.kind {
+            ProjectionKind::Perspective { fovy } => {
+                let half_fovy = fovy.0 / 2.0;
+                let half_fovx = (half_fovy.tan() * projection.aspect).atan();
+                radius / half_fovy.min(half_fovx).sin()
+            }
+            ProjectionKind::Orthographic { .. } => {
+                projection.kind = ProjectionKind::Orthographic {
+                    height: 2.0 * radius * (1.0 / projection.aspect).max(1.0),
+                };
+                radius + projection.znear
+            }
+        };
+        self.position = center - direction * distance;
+    }
+
     pub fn calc_matrix(&self) -> Matrix4<f32> {
         let (sin_p, cos_p) = self.pitch.0.sin_cos();
         let (sin_y, cos_y) = self.yaw.0.sin_cos();
@@ -82,22 +380,105 @@ pub struct CameraUniform {
     view_proj: [[f32; 4]; 4],
     inv_proj: [[f32; 4]; 4], // NEW!
     inv_view: [[f32; 4]; 4], // NEW!
+    /// `view_proj` of the update before the last, for motion vectors
+    prev_view_proj: [[f32; 4]; 4],
+    /// 1 once `prev_view_proj` holds a previous update's `view_proj`, 0 for a new uniform
+    has_history: u32,
+    _padding: [u32; 3],
 }
 
+// `CameraUniform` in src/prelude.wgsl: a vec4 and five mat4x4s, then the history flag
+// padded to 16 bytes
+crate::layout_check::assert_layout!(
+    CameraUniform,
+    size 16 + 5 * 64 + 16,
+    view_position @ 0,
+    view @ 16,
+    view_proj @ 80,
+    inv_proj @ 144,
+    inv_view @ 208,
+    prev_view_proj @ 272,
+    has_history @ 336,
+);
+
 impl CameraUniform {
     pub fn new() -> Self {
         Self::default()
     }
 
     pub fn update_view_proj(&mut self, camera: &Camera, projection: &Projection) {
-        self.view_position = camera.position.to_homogeneous().into();
-        let proj = projection.calc_matrix();
-        let view = camera.calc_matrix();
+        self.update_matrices(
+            camera.position,
+            camera.calc_matrix(),
+            projection.calc_matrix(),
+        );
+    }
+
+    /// Like `update_view_proj`, for views a `Camera` can't express, e.g. looking straight
+    /// up or down with a roll, as the faces of a cube do.
+    pub fn update_matrices(
+        &mut self,
+        position: Point3<f32>,
+        view: Matrix4<f32>,
+        proj: Matrix4<f32>,
+    ) {
+        self.view_position = position.to_homogeneous().into();
         let view_proj = proj * view;
+        // A new uniform has no previous frame, so it starts out without motion
+        self.prev_view_proj = if self.has_history != 0 {
+            self.view_proj
+        } else {
+            view_proj.into()
+        };
+        self.has_history = 1;
         self.view = view.into();
         self.view_proj = view_proj.into();
         self.inv_proj = proj.invert().unwrap().into();
-        self.inv_view = view.transpose().into();
+        self.inv_view = view.invert().unwrap().into();
+    }
+
+    pub fn view_proj(&self) -> Matrix4<f32> {
+        self.view_proj.into()
+    }
+
+    /// `view_proj` as of the previous update.
+    pub fn prev_view_proj(&self) -> Matrix4<f32> {
+        self.prev_view_proj.into()
+    }
+
+    /// Forgets the previous view, e.g. after the camera cut to somewhere else, so the
+    /// next frame's motion vectors don't smear the jump.
+    pub fn reset_history(&mut self) {
+        self.prev_view_proj = self.view_proj;
+    }
+
+    /// How far the world space `position` moved on screen since the previous update due
+    /// to the camera, in UV units (x right, y down): where it is now minus where it was.
+    /// What `MotionVectors` computes per pixel.
+    pub fn motion(&self, position: Point3<f32>) -> Vector2<f32> {
+        let ndc = |view_proj: Matrix4<f32>| {
+            let clip = view_proj * position.to_homogeneous();
+            Vector2::new(clip.x, clip.y) / clip.w
+        };
+        let delta = ndc(self.view_proj()) - ndc(self.prev_view_proj());
+        Vector2::new(delta.x * 0.5, delta.y * -0.5)
+    }
+
+    /// World space position of the camera.
+    pub fn position(&self) -> Point3<f32> {
+        Point3::new(
+            self.view_position[0],
+            self.view_position[1],
+            self.view_position[2],
+        )
+    }
+
+    /// Maps a point in normalized device coordinates (x, y in -1..1, depth in 0..1) back to
+    /// world space.
+    pub fn unproject(&self, ndc: Point3<f32>) -> Point3<f32> {
+        let view = Matrix4::from(self.inv_proj) * ndc.to_homogeneous();
+        let view = view / view.w;
+        Point3::from_homogeneous(Matrix4::from(self.inv_view) * view)
     }
 }
 
@@ -109,11 +490,227 @@ impl Default for CameraUniform {
             inv_proj: cgmath::Matrix4::identity().into(), // NEW!
             inv_view: cgmath::Matrix4::identity().into(), // NEW!
             view: cgmath::Matrix4::identity().into(),
+            prev_view_proj: cgmath::Matrix4::identity().into(),
+            has_history: 0,
+            _padding: [0; 3],
+        }
+    }
+}
+
+/// Input-driven camera movement, implemented by every controller `WindowState` can drive,
+/// see `WindowState::set_camera_controller`.
+pub trait CameraController {
+    fn process_keyboard(&mut self, keycode: KeyCode, state: ElementState) -> bool;
+    fn handle_mouse(&mut self, mouse_dx: f64, mouse_dy: f64);
+    fn handle_mouse_scroll(&mut self, delta: &MouseScrollDelta);
+    fn update_camera(&mut self, camera: &mut Camera, dt: Duration);
+
+    /// Called after `update_camera` with the projection it views the scene through, for
+    /// controllers that change the lens, e.g. zooming with the mouse wheel.
+    fn update_projection(&mut self, _projection: &mut Projection) {}
+
+    /// Applies the current state of a gamepad. Controllers that don't support gamepads
+    /// ignore it and return `false`.
+    fn process_gamepad(&mut self, _input: &GamepadInput) -> bool {
+        false
+    }
+
+    /// Called after the camera was moved from outside, e.g. by `Camera::restore_state`,
+    /// so controllers that keep their own copy of the pose can pick up the new one.
+    fn sync_to_camera(&mut self, _camera: &Camera) {}
+
+    /// Like `sync_to_camera`, for a camera that was pointed at `focus`, e.g. by
+    /// `Camera::frame_sphere`. Controllers circling a point take it as their new center.
+    fn sync_to_focus(&mut self, camera: &Camera, _focus: Point3<f32>) {
+        self.sync_to_camera(camera);
+    }
+}
+
+/// A snapshot of the analog controls of a gamepad, independent of the input library.
+/// Stick axes are -1..1 with +y pointing up, triggers are 0..1.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct GamepadInput {
+    pub left_stick: Vector2<f32>,
+    pub right_stick: Vector2<f32>,
+    pub left_trigger: f32,
+    pub right_trigger: f32,
+}
+
+impl Default for GamepadInput {
+    fn default() -> Self {
+        Self {
+            left_stick: Vector2::zero(),
+            right_stick: Vector2::zero(),
+            left_trigger: 0.0,
+            right_trigger: 0.0,
+        }
+    }
+}
+
+impl GamepadInput {
+    /// Zeroes sticks and triggers inside `dead_zone` and rescales the rest so the output
+    /// still starts at 0 and reaches 1 at full deflection.
+    pub fn with_dead_zone(self, dead_zone: f32) -> Self {
+        let rescale = |magnitude: f32| {
+            if magnitude <= dead_zone {
+                0.0
+            } else {
+                ((magnitude - dead_zone) / (1.0 - dead_zone)).min(1.0)
+            }
+        };
+        // Radial, so diagonals aren't favored over the axes
+        let stick = |v: Vector2<f32>| {
+            let magnitude = v.magnitude();
+            if magnitude <= dead_zone {
+                Vector2::zero()
+            } else {
+                v / magnitude * rescale(magnitude)
+            }
+        };
+        Self {
+            left_stick: stick(self.left_stick),
+            right_stick: stick(self.right_stick),
+            left_trigger: rescale(self.left_trigger),
+            right_trigger: rescale(self.right_trigger),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum CameraAction {
+    Forward,
+    Back,
+    Left,
+    Right,
+    Up,
+    Down,
+    /// Held to multiply the movement speed by `FlyCameraController::sprint_multiplier`
+    Sprint,
+}
+
+/// Maps physical keys to camera movement. Several keys may trigger the same action.
+#[derive(Debug, Clone)]
+pub struct KeyBindings {
+    bindings: HashMap<KeyCode, CameraAction>,
+}
+
+impl KeyBindings {
+    /// Bindings with no keys assigned.
+    pub fn empty() -> Self {
+        Self {
+            bindings: HashMap::new(),
+        }
+    }
+
+    /// Binds `key` to `action`, replacing whatever the key was bound to before.
+    pub fn bind(&mut self, key: KeyCode, action: CameraAction) -> &mut Self {
+        self.bindings.insert(key, action);
+        self
+    }
+
+    pub fn unbind(&mut self, key: KeyCode) -> &mut Self {
+        self.bindings.remove(&key);
+        self
+    }
+
+    /// Removes every key bound to `action`.
+    pub fn clear_action(&mut self, action: CameraAction) -> &mut Self {
+        self.bindings.retain(|_, a| *a != action);
+        self
+    }
+
+    pub fn action(&self, key: KeyCode) -> Option<CameraAction> {
+        self.bindings.get(&key).copied()
+    }
+
+    pub fn keys_for(&self, action: CameraAction) -> impl Iterator<Item = KeyCode> + '_ {
+        self.bindings
+            .iter()
+            .filter(move |(_, a)| **a == action)
+            .map(|(key, _)| *key)
+    }
+}
+
+impl Default for KeyBindings {
+    fn default() -> Self {
+        let mut bindings = Self::empty();
+        bindings
+            .bind(KeyCode::KeyW, CameraAction::Forward)
+            .bind(KeyCode::ArrowUp, CameraAction::Forward)
+            .bind(KeyCode::KeyS, CameraAction::Back)
+            .bind(KeyCode::ArrowDown, CameraAction::Back)
+            .bind(KeyCode::KeyA, CameraAction::Left)
+            .bind(KeyCode::ArrowLeft, CameraAction::Left)
+            .bind(KeyCode::KeyD, CameraAction::Right)
+            .bind(KeyCode::ArrowRight, CameraAction::Right)
+            .bind(KeyCode::Space, CameraAction::Up)
+            .bind(KeyCode::ShiftLeft, CameraAction::Down)
+            .bind(KeyCode::ControlLeft, CameraAction::Sprint);
+        bindings
+    }
+}
+
+/// Gives the free-fly camera momentum. Velocities approach the input exponentially,
+/// using `acceleration` while input is held and `damping` once it is released.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MovementSmoothing {
+    /// Rate per second at which velocity approaches the input
+    pub acceleration: f32,
+    /// Rate per second at which velocity decays without input
+    pub damping: f32,
+    /// Also smooth mouse look and scroll dolly, not just keyboard movement
+    pub smooth_look: bool,
+}
+
+impl Default for MovementSmoothing {
+    fn default() -> Self {
+        Self {
+            acceleration: 8.0,
+            damping: 6.0,
+            smooth_look: true,
         }
     }
 }
 
-pub struct CameraController {
+impl MovementSmoothing {
+    /// Frame rate independent exponential approach from `current` towards `target`.
+    fn approach<V>(&self, current: V, target: V, dt: f32, active: bool) -> V
+    where
+        V: Copy
+            + std::ops::Sub<Output = V>
+            + std::ops::Add<Output = V>
+            + std::ops::Mul<f32, Output = V>,
+    {
+        let rate = if active {
+            self.acceleration
+        } else {
+            self.damping
+        };
+        target + (current - target) * (-rate * dt).exp()
+    }
+}
+
+/// What the mouse wheel does for the free-fly camera.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ScrollMode {
+    /// Moves the camera along its view direction
+    #[default]
+    Dolly,
+    /// Scales the base movement speed, by `FlyCameraController::SPEED_STEP` per line
+    AdjustSpeed,
+    /// Narrows or widens a perspective projection's field of view like a zoom lens, by
+    /// `FlyCameraController::ZOOM_STEP` per line within `min_fovy..=max_fovy`
+    Zoom,
+}
+
+/// Free-flying controller: keys move the camera along its own axes and the mouse turns it.
+/// The default of `WindowState`.
+pub struct FlyCameraController {
+    key_bindings: KeyBindings,
+    smoothing: Option<MovementSmoothing>,
+    velocity: Vector3<f32>,
+    dolly_velocity: Vector3<f32>,
+    angular_velocity: Vector2<f32>,
     amount_left: f32,
     amount_right: f32,
     amount_forward: f32,
@@ -122,16 +719,67 @@ pub struct CameraController {
     amount_down: f32,
     rotate_horizontal: f32,
     rotate_vertical: f32,
+    /// Left stick deflection along right and forward and the trigger difference along up,
+    /// held until the next gamepad update
+    stick_move: Vector3<f32>,
+    /// Right stick deflection, held until the next gamepad update
+    stick_look: Vector2<f32>,
     scroll: f32,
+    /// Scroll in `ScrollMode::Zoom` not yet applied by `update_projection`
+    zoom_scroll: f32,
     speed: f32,
-    sensitivity: f32,
+    sprinting: bool,
+    /// Factor applied to `speed` while the sprint key is held. Only affects translation.
+    pub sprint_multiplier: f32,
+    pub scroll_mode: ScrollMode,
+    /// Narrowest field of view `ScrollMode::Zoom` zooms in to
+    pub min_fovy: Rad<f32>,
+    /// Widest field of view `ScrollMode::Zoom` zooms out to
+    pub max_fovy: Rad<f32>,
+    /// Yaw rate per unit of horizontal mouse motion
+    pub sensitivity_x: f32,
+    /// Pitch rate per unit of vertical mouse motion
+    pub sensitivity_y: f32,
+    /// Distance factor per unit of scroll in `ScrollMode::Dolly`
+    pub scroll_sensitivity: f32,
+    /// Moving the mouse or right stick up pitches the camera down
+    pub invert_y: bool,
+    /// Yaw/pitch rate in radians per second at full right stick deflection
+    pub gamepad_look_speed: f32,
+    pub gamepad_dead_zone: f32,
 }
 
-impl CameraController {
+impl FlyCameraController {
+    /// Speed factor per scroll line in `ScrollMode::AdjustSpeed`
+    pub const SPEED_STEP: f32 = 1.2;
+    /// Field of view factor per scroll line in `ScrollMode::Zoom`
+    pub const ZOOM_STEP: f32 = 1.1;
+    const MIN_SPEED: f32 = 0.01;
+    const MAX_SPEED: f32 = 10_000.0;
+
+    /// Uses `sensitivity` for both mouse axes and scrolling, see `sensitivity_x`,
+    /// `sensitivity_y` and `scroll_sensitivity` to split them.
     pub fn new(speed: f32, sensitivity: f32) -> Self {
+        Self::with_key_bindings(speed, sensitivity, KeyBindings::default())
+    }
+
+    pub fn with_key_bindings(speed: f32, sensitivity: f32, key_bindings: KeyBindings) -> Self {
         Self {
+            key_bindings,
+            smoothing: None,
+            velocity: Vector3::zero(),
+            dolly_velocity: Vector3::zero(),
+            angular_velocity: Vector2::zero(),
             speed,
-            sensitivity,
+            sprinting: false,
+            sprint_multiplier: 4.0,
+            scroll_mode: ScrollMode::Dolly,
+            min_fovy: Deg(10.0).into(),
+            max_fovy: Deg(90.0).into(),
+            sensitivity_x: sensitivity,
+            sensitivity_y: sensitivity,
+            scroll_sensitivity: sensitivity,
+            invert_y: false,
             amount_left: 0.0,
             amount_right: 0.0,
             amount_forward: 0.0,
@@ -140,11 +788,281 @@ impl CameraController {
             amount_down: 0.0,
             rotate_horizontal: 0.0,
             rotate_vertical: 0.0,
+            stick_move: Vector3::zero(),
+            stick_look: Vector2::zero(),
+            scroll: 0.0,
+            zoom_scroll: 0.0,
+            gamepad_look_speed: 2.0,
+            gamepad_dead_zone: 0.15,
+        }
+    }
+
+    /// Enables momentum with the given smoothing parameters.
+    pub fn with_smoothing(mut self, smoothing: MovementSmoothing) -> Self {
+        self.smoothing = Some(smoothing);
+        self
+    }
+
+    /// `None` restores instant, momentum-free movement.
+    pub fn set_smoothing(&mut self, smoothing: Option<MovementSmoothing>) {
+        self.smoothing = smoothing;
+        self.velocity = Vector3::zero();
+        self.dolly_velocity = Vector3::zero();
+        self.angular_velocity = Vector2::zero();
+    }
+
+    pub fn smoothing(&self) -> Option<MovementSmoothing> {
+        self.smoothing
+    }
+
+    /// Base movement speed in units per second, before the sprint multiplier.
+    pub fn speed(&self) -> f32 {
+        self.speed
+    }
+
+    pub fn set_speed(&mut self, speed: f32) {
+        self.speed = speed.clamp(Self::MIN_SPEED, Self::MAX_SPEED);
+    }
+
+    pub fn is_sprinting(&self) -> bool {
+        self.sprinting
+    }
+
+    /// Movement speed with the sprint multiplier applied if the sprint key is held.
+    pub fn effective_speed(&self) -> f32 {
+        if self.sprinting {
+            self.speed * self.sprint_multiplier
+        } else {
+            self.speed
+        }
+    }
+
+    pub fn key_bindings(&self) -> &KeyBindings {
+        &self.key_bindings
+    }
+
+    pub fn set_key_bindings(&mut self, key_bindings: KeyBindings) {
+        self.key_bindings = key_bindings;
+    }
+}
+
+impl CameraController for FlyCameraController {
+    fn process_keyboard(&mut self, keycode: KeyCode, state: ElementState) -> bool {
+        let amount = if state == ElementState::Pressed {
+            1.0
+        } else {
+            0.0
+        };
+        let Some(action) = self.key_bindings.action(keycode) else {
+            return false;
+        };
+        match action {
+            CameraAction::Forward => self.amount_forward = amount,
+            CameraAction::Back => self.amount_backward = amount,
+            CameraAction::Left => self.amount_left = amount,
+            CameraAction::Right => self.amount_right = amount,
+            CameraAction::Up => self.amount_up = amount,
+            CameraAction::Down => self.amount_down = amount,
+            CameraAction::Sprint => self.sprinting = state == ElementState::Pressed,
+        }
+        true
+    }
+
+    fn handle_mouse(&mut self, mouse_dx: f64, mouse_dy: f64) {
+        self.rotate_horizontal = mouse_dx as f32;
+        self.rotate_vertical = mouse_dy as f32;
+    }
+
+    fn handle_mouse_scroll(&mut self, delta: &MouseScrollDelta) {
+        self.scroll = -match delta {
+            // I'm assuming a line is about 100 pixels
+            MouseScrollDelta::LineDelta(_, scroll) => scroll * 100.0,
+            MouseScrollDelta::PixelDelta(PhysicalPosition { y: scroll, .. }) => *scroll as f32,
+        };
+    }
+
+    fn update_camera(&mut self, camera: &mut Camera, dt: Duration) {
+        let dt = dt.as_secs_f32();
+        let mut dolly_scroll = 0.0;
+        match self.scroll_mode {
+            ScrollMode::Dolly => dolly_scroll = self.scroll,
+            ScrollMode::AdjustSpeed => {
+                // Scrolling up (negative `scroll`) speeds up, one step per 100 pixel line
+                let speed = self.speed * Self::SPEED_STEP.powf(-self.scroll / 100.0);
+                self.set_speed(speed);
+            }
+            ScrollMode::Zoom => self.zoom_scroll += self.scroll,
+        }
+        self.scroll = 0.0;
+        let speed = self.effective_speed();
+
+        let (yaw_s, yaw_c) = camera.yaw.0.sin_cos();
+        let forward = Vector3::new(yaw_c, 0.0, yaw_s).normalize();
+        let right = Vector3::new(-yaw_s, 0.0, yaw_c).normalize();
+        // Keys and sticks add up, capped at full speed along each axis
+        let axis = |positive: f32, negative: f32, stick: f32| {
+            (positive - negative + stick).clamp(-1.0, 1.0)
+        };
+        let target_velocity = (forward
+            * axis(self.amount_forward, self.amount_backward, self.stick_move.z)
+            + right * axis(self.amount_right, self.amount_left, self.stick_move.x)
+            + Vector3::unit_y() * axis(self.amount_up, self.amount_down, self.stick_move.y))
+            * speed;
+
+        let (pitch_sin, pitch_cos) = camera.pitch.0.sin_cos();
+        let scrollward = Vector3::new(pitch_cos * yaw_c, pitch_sin, pitch_cos * yaw_s).normalize();
+        let dolly = scrollward * dolly_scroll * speed * self.scroll_sensitivity;
+
+        let mut look = Vector2::new(
+            self.rotate_horizontal * self.sensitivity_x,
+            -self.rotate_vertical * self.sensitivity_y,
+        ) + self.stick_look * self.gamepad_look_speed;
+        if self.invert_y {
+            look.y = -look.y;
+        }
+        self.rotate_horizontal = 0.0;
+        self.rotate_vertical = 0.0;
+
+        match self.smoothing {
+            None => {
+                camera.position += (target_velocity + dolly) * dt;
+                camera.yaw += Rad(look.x) * dt;
+                camera.pitch += Rad(look.y) * dt;
+            }
+            Some(smoothing) => {
+                self.velocity = smoothing.approach(
+                    self.velocity,
+                    target_velocity,
+                    dt,
+                    !target_velocity.is_zero(),
+                );
+                camera.position += self.velocity * dt;
+
+                if smoothing.smooth_look {
+                    // Spread each scroll step over time while covering the same distance
+                    // an unsmoothed step would have
+                    self.dolly_velocity += dolly * smoothing.damping * dt;
+                    camera.position += self.dolly_velocity * dt;
+                    self.dolly_velocity =
+                        smoothing.approach(self.dolly_velocity, Vector3::zero(), dt, false);
+
+                    self.angular_velocity =
+                        smoothing.approach(self.angular_velocity, look, dt, !look.is_zero());
+                    camera.yaw += Rad(self.angular_velocity.x) * dt;
+                    camera.pitch += Rad(self.angular_velocity.y) * dt;
+                } else {
+                    camera.position += dolly * dt;
+                    camera.yaw += Rad(look.x) * dt;
+                    camera.pitch += Rad(look.y) * dt;
+                }
+            }
+        }
+
+        if camera.pitch < -Rad(SAFE_FRAC_PI_2) {
+            camera.pitch = -Rad(SAFE_FRAC_PI_2);
+        } else if camera.pitch > Rad(SAFE_FRAC_PI_2) {
+            camera.pitch = Rad(SAFE_FRAC_PI_2);
+        }
+    }
+
+    /// Applies `ScrollMode::Zoom` scrolling to a perspective `projection`. Orthographic
+    /// projections are left alone.
+    fn update_projection(&mut self, projection: &mut Projection) {
+        if self.zoom_scroll == 0.0 {
+            return;
+        }
+        // Scrolling up (negative `zoom_scroll`) zooms in, one step per 100 pixel line
+        let factor = Self::ZOOM_STEP.powf(self.zoom_scroll / 100.0);
+        self.zoom_scroll = 0.0;
+        if let ProjectionKind::Perspective { fovy } = projection.kind() {
+            let fovy = (fovy.0 * factor).clamp(self.min_fovy.0, self.max_fovy.0);
+            projection.set_perspective(Rad(fovy));
+        }
+    }
+
+    /// Left stick moves and strafes, the right stick looks around and the right/left
+    /// triggers fly up/down, all proportional to how far they are pushed.
+    fn process_gamepad(&mut self, input: &GamepadInput) -> bool {
+        let input = input.with_dead_zone(self.gamepad_dead_zone);
+        self.stick_move = Vector3::new(
+            input.left_stick.x,
+            input.right_trigger - input.left_trigger,
+            input.left_stick.y,
+        );
+        self.stick_look = input.right_stick;
+        true
+    }
+
+    /// Drops any smoothed velocity so the camera doesn't drift away from its new pose.
+    fn sync_to_camera(&mut self, _camera: &Camera) {
+        self.velocity = Vector3::zero();
+        self.dolly_velocity = Vector3::zero();
+        self.angular_velocity = Vector2::zero();
+        self.zoom_scroll = 0.0;
+    }
+}
+
+/// Orbits the camera around a focus point. Mouse drag changes azimuth and
+/// elevation, scrolling changes the distance to the focus point.
+pub struct OrbitCameraController {
+    focus: Point3<f32>,
+    radius: f32,
+    azimuth: Rad<f32>,
+    elevation: Rad<f32>,
+    amount_left: f32,
+    amount_right: f32,
+    amount_up: f32,
+    amount_down: f32,
+    rotate_horizontal: f32,
+    rotate_vertical: f32,
+    scroll: f32,
+    speed: f32,
+    sensitivity: f32,
+}
+
+impl OrbitCameraController {
+    const MIN_RADIUS: f32 = 0.1;
+
+    pub fn new<F: Into<Point3<f32>>, A: Into<Rad<f32>>, E: Into<Rad<f32>>>(
+        focus: F,
+        radius: f32,
+        azimuth: A,
+        elevation: E,
+        speed: f32,
+        sensitivity: f32,
+    ) -> Self {
+        Self {
+            focus: focus.into(),
+            radius: radius.max(Self::MIN_RADIUS),
+            azimuth: azimuth.into(),
+            elevation: elevation.into(),
+            amount_left: 0.0,
+            amount_right: 0.0,
+            amount_up: 0.0,
+            amount_down: 0.0,
+            rotate_horizontal: 0.0,
+            rotate_vertical: 0.0,
             scroll: 0.0,
+            speed,
+            sensitivity,
         }
     }
 
-    pub fn process_keyboard(&mut self, keycode: KeyCode, state: ElementState) -> bool {
+    pub fn focus(&self) -> Point3<f32> {
+        self.focus
+    }
+
+    pub fn set_focus<F: Into<Point3<f32>>>(&mut self, focus: F) {
+        self.focus = focus.into();
+    }
+
+    pub fn radius(&self) -> f32 {
+        self.radius
+    }
+}
+
+impl CameraController for OrbitCameraController {
+    fn process_keyboard(&mut self, keycode: KeyCode, state: ElementState) -> bool {
         let amount = if state == ElementState::Pressed {
             1.0
         } else {
@@ -152,11 +1070,11 @@ impl CameraController {
         };
         match keycode {
             KeyCode::KeyW | KeyCode::ArrowUp => {
-                self.amount_forward = amount;
+                self.amount_up = amount;
                 true
             }
             KeyCode::KeyS | KeyCode::ArrowDown => {
-                self.amount_backward = amount;
+                self.amount_down = amount;
                 true
             }
             KeyCode::KeyA | KeyCode::ArrowLeft => {
@@ -167,54 +1085,67 @@ impl CameraController {
                 self.amount_right = amount;
                 true
             }
-            KeyCode::Space => {
-                self.amount_up = amount;
-                true
-            }
-            KeyCode::ShiftLeft => {
-                self.amount_down = amount;
-                true
-            }
             _ => false,
         }
     }
 
-    pub fn handle_mouse(&mut self, mouse_dx: f64, mouse_dy: f64) {
+    fn handle_mouse(&mut self, mouse_dx: f64, mouse_dy: f64) {
         self.rotate_horizontal = mouse_dx as f32;
         self.rotate_vertical = mouse_dy as f32;
     }
 
-    pub fn handle_mouse_scroll(&mut self, delta: &MouseScrollDelta) {
+    fn handle_mouse_scroll(&mut self, delta: &MouseScrollDelta) {
         self.scroll = -match delta {
             // I'm assuming a line is about 100 pixels
             MouseScrollDelta::LineDelta(_, scroll) => scroll * 100.0,
             MouseScrollDelta::PixelDelta(PhysicalPosition { y: scroll, .. }) => *scroll as f32,
         };
     }
-    pub fn update_camera(&mut self, camera: &mut Camera, dt: Duration) {
-        let dt = dt.as_secs_f32();
-        let (yaw_s, yaw_c) = camera.yaw.0.sin_cos();
-        let forward = Vector3::new(yaw_c, 0.0, yaw_s).normalize();
-        let right = Vector3::new(-yaw_s, 0.0, yaw_c).normalize();
-        camera.position += forward * (self.amount_forward - self.amount_backward) * self.speed * dt;
-        camera.position += right * (self.amount_right - self.amount_left) * self.speed * dt;
-
-        let (pitch_sin, pitch_cos) = camera.pitch.0.sin_cos();
-        let scrollward = Vector3::new(pitch_cos * yaw_c, pitch_sin, pitch_cos * yaw_s).normalize();
-        camera.position += scrollward * self.scroll * self.speed * self.sensitivity * dt;
-        self.scroll = 0.0;
-        camera.position.y += (self.amount_up - self.amount_down) * self.speed * dt;
 
-        camera.yaw += Rad(self.rotate_horizontal) * self.sensitivity * dt;
-        camera.pitch += Rad(-self.rotate_vertical) * self.sensitivity * dt;
+    fn update_camera(&mut self, camera: &mut Camera, dt: Duration) {
+        let dt = dt.as_secs_f32();
 
+        self.azimuth += Rad(self.rotate_horizontal) * self.sensitivity * dt;
+        self.azimuth += Rad(self.amount_right - self.amount_left) * self.speed * dt;
+        self.elevation += Rad(self.rotate_vertical) * self.sensitivity * dt;
+        self.elevation += Rad(self.amount_up - self.amount_down) * self.speed * dt;
         self.rotate_horizontal = 0.0;
         self.rotate_vertical = 0.0;
 
-        if camera.pitch < -Rad(SAFE_FRAC_PI_2) {
-            camera.pitch = -Rad(SAFE_FRAC_PI_2);
-        } else if camera.pitch > Rad(SAFE_FRAC_PI_2) {
-            camera.pitch = Rad(SAFE_FRAC_PI_2);
+        if self.elevation < -Rad(SAFE_FRAC_PI_2) {
+            self.elevation = -Rad(SAFE_FRAC_PI_2);
+        } else if self.elevation > Rad(SAFE_FRAC_PI_2) {
+            self.elevation = Rad(SAFE_FRAC_PI_2);
         }
+
+        // Scale the zoom by the current distance so it feels the same close up and far away
+        self.radius += self.scroll * self.radius * self.sensitivity * dt;
+        self.radius = self.radius.max(Self::MIN_RADIUS);
+        self.scroll = 0.0;
+
+        let (sin_e, cos_e) = self.elevation.0.sin_cos();
+        let (sin_a, cos_a) = self.azimuth.0.sin_cos();
+        let offset = Vector3::new(cos_e * cos_a, sin_e, cos_e * sin_a) * self.radius;
+        camera.position = self.focus + offset;
+        // Look back along the offset towards the focus point
+        camera.yaw = self.azimuth + Rad(std::f32::consts::PI);
+        camera.pitch = -self.elevation;
+    }
+
+    /// Keeps the orbit radius and moves the focus in front of the camera, so the next
+    /// update reproduces the camera's pose instead of snapping back to the old orbit.
+    fn sync_to_camera(&mut self, camera: &Camera) {
+        let (sin_p, cos_p) = camera.pitch.0.sin_cos();
+        let (sin_y, cos_y) = camera.yaw.0.sin_cos();
+        let forward = Vector3::new(cos_p * cos_y, sin_p, cos_p * sin_y);
+        self.focus = camera.position + forward * self.radius;
+        self.azimuth = camera.yaw - Rad(std::f32::consts::PI);
+        self.elevation = -camera.pitch;
+    }
+
+    /// Orbits `focus` at the camera's distance from it.
+    fn sync_to_focus(&mut self, camera: &Camera, focus: Point3<f32>) {
+        self.radius = (focus - camera.position).magnitude().max(Self::MIN_RADIUS);
+        self.sync_to_camera(camera);
     }
 }
@@ -0,0 +1,178 @@
+use std::time::Duration;
+
+use cgmath::{EuclideanSpace, InnerSpace, Point3, Quaternion, Rad, Rotation, Rotation3, Vector3};
+
+use crate::camera::{Camera, SAFE_FRAC_PI_2};
+
+/// Where the camera is at `time` seconds into a `CameraPath` and what it looks at.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CameraKeyframe {
+    pub time: f32,
+    pub position: Point3<f32>,
+    pub target: Point3<f32>,
+}
+
+impl CameraKeyframe {
+    pub fn new<P: Into<Point3<f32>>, T: Into<Point3<f32>>>(
+        time: f32,
+        position: P,
+        target: T,
+    ) -> Self {
+        Self {
+            time,
+            position: position.into(),
+            target: target.into(),
+        }
+    }
+}
+
+/// A fly-through along keyframed viewpoints, e.g. for recording videos, played back by
+/// `WindowState::play_path`.
+///
+/// Positions follow a Catmull-Rom spline through the keyframes, which passes through every
+/// one of them without corners. The view turns from one keyframe's look direction to the
+/// next by slerp, at a constant rate between two keyframes.
+#[derive(Debug, Clone)]
+pub struct CameraPath {
+    keyframes: Vec<CameraKeyframe>,
+    time: f32,
+    /// Playback rate, 1 is real time
+    pub speed: f32,
+    /// Starts over after the last keyframe instead of stopping there. The spline doesn't
+    /// wrap around, so a seamless loop ends on the keyframe it started from.
+    pub looping: bool,
+}
+
+impl CameraPath {
+    /// Fails unless there is at least one keyframe, the times are finite and ascending and
+    /// no keyframe looks at its own position.
+    pub fn new(keyframes: Vec<CameraKeyframe>) -> anyhow::Result<Self> {
+        if keyframes.is_empty() {
+            anyhow::bail!("a camera path needs at least one keyframe");
+        }
+        if keyframes.iter().any(|key| !key.time.is_finite()) {
+            anyhow::bail!("camera path keyframe times must be finite");
+        }
+        if keyframes
+            .windows(2)
+            .any(|keys| keys[1].time <= keys[0].time)
+        {
+            anyhow::bail!("camera path keyframe times must be ascending");
+        }
+        if keyframes
+            .iter()
+            .any(|key| (key.target - key.position).magnitude2() == 0.0)
+        {
+            anyhow::bail!("camera path keyframes must look away from their position");
+        }
+        let time = keyframes[0].time;
+        Ok(Self {
+            keyframes,
+            time,
+            speed: 1.0,
+            looping: false,
+        })
+    }
+
+    pub fn keyframes(&self) -> &[CameraKeyframe] {
+        &self.keyframes
+    }
+
+    /// Time of the first keyframe
+    pub fn start(&self) -> f32 {
+        self.keyframes[0].time
+    }
+
+    /// Time of the last keyframe
+    pub fn end(&self) -> f32 {
+        self.keyframes[self.keyframes.len() - 1].time
+    }
+
+    /// Playback position in seconds, from `start` to `end`
+    pub fn time(&self) -> f32 {
+        self.time
+    }
+
+    /// Jumps to `time`, clamped to the path.
+    pub fn seek(&mut self, time: f32) {
+        self.time = time.clamp(self.start(), self.end());
+    }
+
+    /// Whether playback reached the end of a path that doesn't loop.
+    pub fn is_finished(&self) -> bool {
+        !self.looping && self.time >= self.end()
+    }
+
+    /// Advances playback by `dt` and returns the camera at the new position.
+    pub fn update(&mut self, dt: Duration) -> Camera {
+        let (start, end) = (self.start(), self.end());
+        let time = self.time + dt.as_secs_f32() * self.speed;
+        self.time = if self.looping && end > start {
+            start + (time - start).rem_euclid(end - start)
+        } else {
+            time.clamp(start, end)
+        };
+        let (position, yaw, pitch) = self.sample(self.time);
+        Camera::new(position, yaw, pitch)
+    }
+
+    /// The camera's position, yaw and pitch at `time`. Times outside the path hold the
+    /// first or last keyframe.
+    pub fn sample(&self, time: f32) -> (Point3<f32>, Rad<f32>, Rad<f32>) {
+        let keys = &self.keyframes;
+        let next = keys.partition_point(|key| key.time <= time);
+        let (a, b, t) = if next == 0 {
+            (0, 0, 0.0)
+        } else if next == keys.len() {
+            (next - 1, next - 1, 0.0)
+        } else {
+            let (from, to) = (keys[next - 1].time, keys[next].time);
+            (next - 1, next, (time - from) / (to - from))
+        };
+
+        // The end points repeat where the spline has no neighbor
+        let p0 = keys[a.saturating_sub(1)].position;
+        let p3 = keys[(b + 1).min(keys.len() - 1)].position;
+        let position = catmull_rom(p0, keys[a].position, keys[b].position, p3, t);
+
+        let (from, to) = (orientation(&keys[a]), orientation(&keys[b]));
+        // Take the shorter way around
+        let to = if from.dot(to) < 0.0 { -to } else { to };
+        let forward = from.slerp(to, t).rotate_vector(Vector3::unit_x());
+        let yaw = Rad(forward.z.atan2(forward.x));
+        let pitch = Rad(forward
+            .y
+            .clamp(-1.0, 1.0)
+            .asin()
+            .clamp(-SAFE_FRAC_PI_2, SAFE_FRAC_PI_2));
+        (position, yaw, pitch)
+    }
+}
+
+/// Turns +x into the look direction of `key`, the way `Camera` turns it by yaw and pitch.
+fn orientation(key: &CameraKeyframe) -> Quaternion<f32> {
+    let direction = (key.target - key.position).normalize();
+    let yaw = direction.z.atan2(direction.x);
+    let pitch = direction.y.clamp(-1.0, 1.0).asin();
+    Quaternion::from_angle_y(Rad(-yaw)) * Quaternion::from_angle_z(Rad(pitch))
+}
+
+/// The point `t` of the way from `p1` to `p2` on the uniform Catmull-Rom spline through
+/// `p0`..`p3`.
+fn catmull_rom(
+    p0: Point3<f32>,
+    p1: Point3<f32>,
+    p2: Point3<f32>,
+    p3: Point3<f32>,
+    t: f32,
+) -> Point3<f32> {
+    let (t2, t3) = (t * t, t * t * t);
+    let (p0, p1, p2, p3) = (p0.to_vec(), p1.to_vec(), p2.to_vec(), p3.to_vec());
+    Point3::from_vec(
+        (p1 * 2.0
+            + (p2 - p0) * t
+            + (p0 * 2.0 - p1 * 5.0 + p2 * 4.0 - p3) * t2
+            + (p1 * 3.0 - p0 - p2 * 3.0 + p3) * t3)
+            * 0.5,
+    )
+}
@@ -0,0 +1,156 @@
+use anyhow::Context as _;
+
+use crate::texture;
+
+// sRGB colors at evenly spaced points from 0 to 1, matching matplotlib's maps
+const VIRIDIS: [[u8; 3]; 11] = [
+    [0x44, 0x01, 0x54],
+    [0x48, 0x24, 0x75],
+    [0x41, 0x44, 0x87],
+    [0x35, 0x5f, 0x8d],
+    [0x2a, 0x78, 0x8e],
+    [0x21, 0x91, 0x8c],
+    [0x22, 0xa8, 0x84],
+    [0x44, 0xbf, 0x70],
+    [0x7a, 0xd1, 0x51],
+    [0xbd, 0xdf, 0x26],
+    [0xfd, 0xe7, 0x25],
+];
+const MAGMA: [[u8; 3]; 11] = [
+    [0x00, 0x00, 0x04],
+    [0x14, 0x0e, 0x36],
+    [0x3b, 0x0f, 0x70],
+    [0x64, 0x1a, 0x80],
+    [0x8c, 0x29, 0x81],
+    [0xb7, 0x37, 0x79],
+    [0xde, 0x49, 0x68],
+    [0xf7, 0x70, 0x5c],
+    [0xfe, 0x9f, 0x6d],
+    [0xfe, 0xcf, 0x92],
+    [0xfc, 0xfd, 0xbf],
+];
+const TURBO: [[u8; 3]; 11] = [
+    [0x30, 0x12, 0x3b],
+    [0x44, 0x54, 0xc4],
+    [0x44, 0x90, 0xfe],
+    [0x1f, 0xc8, 0xde],
+    [0x29, 0xef, 0xa2],
+    [0x7e, 0xff, 0x55],
+    [0xc1, 0xf3, 0x34],
+    [0xf1, 0xca, 0x3a],
+    [0xfe, 0x92, 0x2a],
+    [0xea, 0x4e, 0x0d],
+    [0x7a, 0x04, 0x03],
+];
+const GRAYSCALE: [[u8; 3]; 2] = [[0x00, 0x00, 0x00], [0xff, 0xff, 0xff]];
+
+/// A built-in gradient mapping values from 0 to 1 to colors, e.g. normalized elevation,
+/// uploaded as a `RESOLUTION` x 1 lookup texture. See `Renderer::set_terrain_colormap`.
+pub struct Colormap {
+    name: &'static str,
+    /// sRGB, so shaders sample linear colors. Clamps to the ends and filters linearly.
+    pub texture: texture::Texture,
+}
+
+impl Colormap {
+    /// Names accepted by `new`.
+    pub const NAMES: [&'static str; 4] = ["viridis", "magma", "turbo", "grayscale"];
+    /// Texels in the lookup texture.
+    pub const RESOLUTION: u32 = 256;
+
+    /// Uploads the built-in colormap `name`, one of `NAMES`.
+    pub fn new(device: &wgpu::Device, queue: &wgpu::Queue, name: &str) -> anyhow::Result<Self> {
+        let (name, stops) = Self::builtin(name).with_context(|| {
+            format!(
+                "unknown colormap {name:?}, expected one of {:?}",
+                Self::NAMES
+            )
+        })?;
+        let size = wgpu::Extent3d {
+            width: Self::RESOLUTION,
+            height: 1,
+            depth_or_array_layers: 1,
+        };
+        // 2D rather than 1D, which WebGL doesn't have
+        let mut texture = texture::Texture::create_texture(
+            device,
+            Some("colormap_texture"),
+            size,
+            wgpu::TextureFormat::Rgba8UnormSrgb,
+            wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+            wgpu::TextureDimension::D2,
+            wgpu::FilterMode::Linear,
+        );
+        // Terrain far away minifies the lookup too
+        texture.sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
+        let texels = (0..Self::RESOLUTION)
+            .flat_map(|i| Self::interpolate(stops, i as f32 / (Self::RESOLUTION - 1) as f32))
+            .collect::<Vec<_>>();
+        queue.write_texture(
+            texture.texture.as_image_copy(),
+            &texels,
+            wgpu::TexelCopyBufferLayout {
+                offset: 0,
+                bytes_per_row: Some(4 * Self::RESOLUTION),
+                rows_per_image: Some(1),
+            },
+            size,
+        );
+        Ok(Self { name, texture })
+    }
+
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    /// sRGB color of the colormap `name` at `t`, clamped to 0 to 1. `None` for unknown names.
+    pub fn sample(name: &str, t: f32) -> Option<[u8; 4]> {
+        Self::builtin(name).map(|(_, stops)| Self::interpolate(stops, t))
+    }
+
+    /// A `width` x `height` image of the colormap for a UI legend, the low end on the left.
+    pub fn legend(&self, width: u32, height: u32) -> image::RgbaImage {
+        let (_, stops) = Self::builtin(self.name).expect("colormap names are built-in");
+        let max_x = width.saturating_sub(1).max(1) as f32;
+        image::RgbaImage::from_fn(width, height, |x, _| {
+            image::Rgba(Self::interpolate(stops, x as f32 / max_x))
+        })
+    }
+
+    /// `legend` uploaded as a texture, to draw as a UI overlay.
+    pub fn legend_texture(
+        &self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        width: u32,
+        height: u32,
+    ) -> anyhow::Result<texture::Texture> {
+        let img = image::DynamicImage::ImageRgba8(self.legend(width, height));
+        texture::Texture::from_image(device, queue, &img, Some("colormap_legend"), false)
+    }
+
+    fn builtin(name: &str) -> Option<(&'static str, &'static [[u8; 3]])> {
+        Some(match name.to_ascii_lowercase().as_str() {
+            "viridis" => ("viridis", &VIRIDIS),
+            "magma" => ("magma", &MAGMA),
+            "turbo" => ("turbo", &TURBO),
+            "grayscale" => ("grayscale", &GRAYSCALE),
+            _ => return None,
+        })
+    }
+
+    // Linear between the neighboring stops in sRGB, like matplotlib
+    fn interpolate(stops: &[[u8; 3]], t: f32) -> [u8; 4] {
+        let x = t.clamp(0.0, 1.0) * (stops.len() - 1) as f32;
+        let i = (x.floor() as usize).min(stops.len() - 2);
+        let f = x - i as f32;
+        let [r, g, b] = std::array::from_fn(|c| {
+            (stops[i][c] as f32 * (1.0 - f) + stops[i + 1][c] as f32 * f).round() as u8
+        });
+        [r, g, b, 255]
+    }
+}
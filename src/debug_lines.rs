@@ -0,0 +1,231 @@
+use cgmath::Point3;
+
+use crate::{
+    frustum::{Aabb, Frustum},
+    hot_reload::wgsl,
+    model::Vertex,
+};
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct LineVertex {
+    pub position: [f32; 3],
+    pub color: [f32; 4],
+}
+
+impl Vertex for LineVertex {
+    fn desc() -> wgpu::VertexBufferLayout<'static> {
+        use std::mem;
+        wgpu::VertexBufferLayout {
+            array_stride: mem::size_of::<LineVertex>() as wgpu::BufferAddress,
+            step_mode: wgpu::VertexStepMode::Vertex,
+            attributes: &[
+                wgpu::VertexAttribute {
+                    offset: 0,
+                    shader_location: 0,
+                    format: wgpu::VertexFormat::Float32x3,
+                },
+                wgpu::VertexAttribute {
+                    offset: mem::size_of::<[f32; 3]>() as wgpu::BufferAddress,
+                    shader_location: 1,
+                    format: wgpu::VertexFormat::Float32x4,
+                },
+            ],
+        }
+    }
+}
+
+/// Pairs of corner indices forming the edges of a box, for corners ordered like
+/// `Aabb::corners`.
+const BOX_EDGES: [(usize, usize); 12] = [
+    (0, 1),
+    (2, 3),
+    (4, 5),
+    (6, 7),
+    (0, 2),
+    (1, 3),
+    (4, 6),
+    (5, 7),
+    (0, 4),
+    (1, 5),
+    (2, 6),
+    (3, 7),
+];
+
+/// Immediate mode line drawing for debugging, e.g. bounding boxes and frustums. Lines are
+/// collected on the CPU, uploaded into one vertex buffer by `upload` and drawn with a
+/// single `LineList` draw until the next upload.
+pub struct DebugLines {
+    vertices: Vec<LineVertex>,
+    buffer: wgpu::Buffer,
+    /// Vertices the buffer has room for
+    capacity: usize,
+    /// Vertices uploaded by the last `upload`
+    vertex_count: u32,
+    pipeline: wgpu::RenderPipeline,
+}
+
+impl DebugLines {
+    const INITIAL_CAPACITY: usize = 1024;
+
+    pub fn new(
+        device: &wgpu::Device,
+        camera_bind_group_layout: &wgpu::BindGroupLayout,
+        color_format: wgpu::TextureFormat,
+        depth_format: Option<wgpu::TextureFormat>,
+        depth_compare: wgpu::CompareFunction,
+        sample_count: u32,
+    ) -> Self {
+        Self {
+            vertices: Vec::new(),
+            buffer: Self::create_buffer(device, Self::INITIAL_CAPACITY),
+            capacity: Self::INITIAL_CAPACITY,
+            vertex_count: 0,
+            pipeline: Self::create_pipeline(
+                device,
+                camera_bind_group_layout,
+                color_format,
+                depth_format,
+                depth_compare,
+                sample_count,
+            ),
+        }
+    }
+
+    /// Rebuilds the pipeline, e.g. after the MSAA sample count changed.
+    pub fn recreate_pipeline(
+        &mut self,
+        device: &wgpu::Device,
+        camera_bind_group_layout: &wgpu::BindGroupLayout,
+        color_format: wgpu::TextureFormat,
+        depth_format: Option<wgpu::TextureFormat>,
+        depth_compare: wgpu::CompareFunction,
+        sample_count: u32,
+    ) {
+        self.pipeline = Self::create_pipeline(
+            device,
+            camera_bind_group_layout,
+            color_format,
+            depth_format,
+            depth_compare,
+            sample_count,
+        );
+    }
+
+    fn create_buffer(device: &wgpu::Device, capacity: usize) -> wgpu::Buffer {
+        device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Debug Line Buffer"),
+            size: (capacity * std::mem::size_of::<LineVertex>()) as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        })
+    }
+
+    fn create_pipeline(
+        device: &wgpu::Device,
+        camera_bind_group_layout: &wgpu::BindGroupLayout,
+        color_format: wgpu::TextureFormat,
+        depth_format: Option<wgpu::TextureFormat>,
+        depth_compare: wgpu::CompareFunction,
+        sample_count: u32,
+    ) -> wgpu::RenderPipeline {
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Debug Line Pipeline Layout"),
+            bind_group_layouts: &[camera_bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let shader = device.create_shader_module(wgsl!("src/debug_lines.wgsl", "src/prelude.wgsl"));
+        device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Debug Line Pipeline"),
+            layout: Some(&layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: Some("vs_main"),
+                buffers: &[LineVertex::desc()],
+                compilation_options: Default::default(),
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: Some("fs_main"),
+                targets: &[Some(wgpu::ColorTargetState {
+                    format: color_format,
+                    blend: Some(wgpu::BlendState::ALPHA_BLENDING),
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+                compilation_options: Default::default(),
+            }),
+            primitive: wgpu::PrimitiveState {
+                topology: wgpu::PrimitiveTopology::LineList,
+                ..Default::default()
+            },
+            // Hidden behind geometry like everything else, but never occluding it
+            depth_stencil: depth_format.map(|format| wgpu::DepthStencilState {
+                format,
+                depth_write_enabled: false,
+                depth_compare,
+                stencil: wgpu::StencilState::default(),
+                bias: wgpu::DepthBiasState::default(),
+            }),
+            multisample: wgpu::MultisampleState {
+                count: sample_count,
+                ..Default::default()
+            },
+            multiview: None,
+            cache: None,
+        })
+    }
+
+    pub fn draw_line(&mut self, from: Point3<f32>, to: Point3<f32>, color: [f32; 4]) {
+        self.vertices.extend([from, to].map(|p| LineVertex {
+            position: p.into(),
+            color,
+        }));
+    }
+
+    pub fn draw_aabb(&mut self, min: Point3<f32>, max: Point3<f32>, color: [f32; 4]) {
+        self.draw_box(&Aabb::new(min, max).corners(), color);
+    }
+
+    pub fn draw_frustum(&mut self, frustum: &Frustum, color: [f32; 4]) {
+        self.draw_box(&frustum.corners(), color);
+    }
+
+    fn draw_box(&mut self, corners: &[Point3<f32>; 8], color: [f32; 4]) {
+        for (a, b) in BOX_EDGES {
+            self.draw_line(corners[a], corners[b], color);
+        }
+    }
+
+    /// Lines queued since the last `upload`.
+    pub fn pending_lines(&self) -> usize {
+        self.vertices.len() / 2
+    }
+
+    /// Drops the queued lines without drawing them.
+    pub fn clear(&mut self) {
+        self.vertices.clear();
+    }
+
+    /// Writes the queued lines into the vertex buffer, growing it if needed, and starts a
+    /// new batch. Lines from earlier uploads are no longer drawn.
+    pub fn upload(&mut self, device: &wgpu::Device, queue: &wgpu::Queue) {
+        if self.vertices.len() > self.capacity {
+            self.capacity = self.vertices.len().next_power_of_two();
+            self.buffer = Self::create_buffer(device, self.capacity);
+        }
+        queue.write_buffer(&self.buffer, 0, bytemuck::cast_slice(&self.vertices));
+        self.vertex_count = self.vertices.len() as u32;
+        self.vertices.clear();
+    }
+
+    /// Draws the lines of the last `upload`, if any. Changes bind group 0 to the camera.
+    pub fn draw(&self, pass: &mut wgpu::RenderPass<'_>, camera_bind_group: &wgpu::BindGroup) {
+        if self.vertex_count == 0 {
+            return;
+        }
+        pass.set_pipeline(&self.pipeline);
+        pass.set_bind_group(0, camera_bind_group, &[]);
+        pass.set_vertex_buffer(0, self.buffer.slice(..));
+        pass.draw(0..self.vertex_count, 0..1);
+    }
+}
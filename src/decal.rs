@@ -0,0 +1,349 @@
+use cgmath::SquareMatrix;
+use wgpu::util::DeviceExt as _;
+
+use crate::{hot_reload::wgsl, texture, transform::Transform};
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct DecalUniform {
+    model: [[f32; 4]; 4],
+    inv_model: [[f32; 4]; 4],
+}
+
+impl From<&Transform> for DecalUniform {
+    fn from(transform: &Transform) -> Self {
+        let model = transform.to_matrix();
+        Self {
+            model: model.into(),
+            inv_model: model
+                .invert()
+                .unwrap_or_else(cgmath::Matrix4::identity)
+                .into(),
+        }
+    }
+}
+
+/// A texture stamped onto whatever lies inside a box, see `Decals`.
+pub struct Decal {
+    /// Places the unit box around the origin in the world. The texture is projected along
+    /// the box's y axis, its u along x and v along z, so the scale's x and z size the
+    /// stamp and its y how far above and below it reaches.
+    pub transform: Transform,
+    texture: texture::Texture,
+    buffer: wgpu::Buffer,
+    bind_group: wgpu::BindGroup,
+}
+
+impl Decal {
+    pub fn texture(&self) -> &texture::Texture {
+        &self.texture
+    }
+}
+
+/// Stamps textures like paths or craters onto the terrain, or anything else opaque,
+/// without touching its mesh.
+///
+/// Screen-space decals: after the opaque geometry is drawn, the back faces of each decal's
+/// box are drawn over it, and every pixel they cover reconstructs its world position from
+/// the depth buffer. Where that falls inside the box, the decal texture multiplies the lit
+/// color, so it darkens and tints the surface but keeps its lighting. White texels, and
+/// transparent ones, leave it as it was.
+pub struct Decals {
+    decals: Vec<Decal>,
+    device: wgpu::Device,
+    decal_bind_group_layout: wgpu::BindGroupLayout,
+    depth_bind_group: wgpu::BindGroup,
+    render_pipeline: wgpu::RenderPipeline,
+}
+
+impl Decals {
+    /// Decals drawn into targets of `color_format` over the depth texture `depth`,
+    /// matching its sample count.
+    pub fn new(
+        device: &wgpu::Device,
+        camera_bind_group_layout: &wgpu::BindGroupLayout,
+        color_format: wgpu::TextureFormat,
+        depth: &texture::Texture,
+        depth_compare: wgpu::CompareFunction,
+    ) -> Self {
+        let decal_bind_group_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                entries: &[
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 0,
+                        visibility: wgpu::ShaderStages::VERTEX_FRAGMENT,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Uniform,
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 1,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Texture {
+                            multisampled: false,
+                            view_dimension: wgpu::TextureViewDimension::D2,
+                            sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                        },
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 2,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                        count: None,
+                    },
+                ],
+                label: Some("decal_bind_group_layout"),
+            });
+        let sample_count = depth.texture.sample_count();
+        let render_pipeline = Self::create_render_pipeline(
+            device,
+            camera_bind_group_layout,
+            &decal_bind_group_layout,
+            color_format,
+            depth_compare,
+            sample_count,
+        );
+        Self {
+            decals: Vec::new(),
+            device: device.clone(),
+            decal_bind_group_layout,
+            depth_bind_group: Self::create_depth_bind_group(device, depth),
+            render_pipeline,
+        }
+    }
+
+    /// Binds the depth of a multisampled target as such, the layout only depends on that.
+    fn create_depth_bind_group_layout(
+        device: &wgpu::Device,
+        multisampled: bool,
+    ) -> wgpu::BindGroupLayout {
+        device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Texture {
+                    multisampled,
+                    view_dimension: wgpu::TextureViewDimension::D2,
+                    sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                },
+                count: None,
+            }],
+            label: Some("decal_depth_bind_group_layout"),
+        })
+    }
+
+    fn create_depth_bind_group(device: &wgpu::Device, depth: &texture::Texture) -> wgpu::BindGroup {
+        // Formats with stencil can only be sampled one aspect at a time
+        let view = depth.texture.create_view(&wgpu::TextureViewDescriptor {
+            label: Some("Decal Depth View"),
+            aspect: wgpu::TextureAspect::DepthOnly,
+            ..Default::default()
+        });
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &Self::create_depth_bind_group_layout(device, depth.texture.sample_count() > 1),
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: wgpu::BindingResource::TextureView(&view),
+            }],
+            label: Some("decal_depth_bind_group"),
+        })
+    }
+
+    fn create_render_pipeline(
+        device: &wgpu::Device,
+        camera_bind_group_layout: &wgpu::BindGroupLayout,
+        decal_bind_group_layout: &wgpu::BindGroupLayout,
+        color_format: wgpu::TextureFormat,
+        depth_compare: wgpu::CompareFunction,
+        sample_count: u32,
+    ) -> wgpu::RenderPipeline {
+        let multisampled = sample_count > 1;
+        let depth_bind_group_layout = Self::create_depth_bind_group_layout(device, multisampled);
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Decal Pipeline Layout"),
+            bind_group_layouts: &[
+                camera_bind_group_layout,
+                &depth_bind_group_layout,
+                decal_bind_group_layout,
+            ],
+            push_constant_ranges: &[],
+        });
+        let shader = device.create_shader_module(wgsl!("src/decal.wgsl", "src/prelude.wgsl"));
+        // Greater depths are nearer with reverse-Z, so the far plane is at 0
+        let far_depth = match depth_compare {
+            wgpu::CompareFunction::Greater | wgpu::CompareFunction::GreaterEqual => 0.0,
+            _ => 1.0,
+        };
+        let constants = [("far_depth", far_depth)];
+        device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Decal Pipeline"),
+            layout: Some(&layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: Some("vs_main"),
+                buffers: &[],
+                compilation_options: Default::default(),
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: Some(if multisampled {
+                    "fs_multisampled"
+                } else {
+                    "fs_main"
+                }),
+                targets: &[Some(wgpu::ColorTargetState {
+                    format: color_format,
+                    // dst * color * alpha + dst * (1 - alpha), the shader premultiplies
+                    blend: Some(wgpu::BlendState {
+                        color: wgpu::BlendComponent {
+                            src_factor: wgpu::BlendFactor::Dst,
+                            dst_factor: wgpu::BlendFactor::OneMinusSrcAlpha,
+                            operation: wgpu::BlendOperation::Add,
+                        },
+                        alpha: wgpu::BlendComponent {
+                            src_factor: wgpu::BlendFactor::Zero,
+                            dst_factor: wgpu::BlendFactor::One,
+                            operation: wgpu::BlendOperation::Add,
+                        },
+                    }),
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+                compilation_options: wgpu::PipelineCompilationOptions {
+                    constants: &constants,
+                    ..Default::default()
+                },
+            }),
+            primitive: wgpu::PrimitiveState {
+                // The back faces, so the box still covers its pixels with the camera inside
+                cull_mode: Some(wgpu::Face::Front),
+                ..Default::default()
+            },
+            // Reads the depth buffer instead of testing against it
+            depth_stencil: None,
+            multisample: wgpu::MultisampleState {
+                count: sample_count,
+                ..Default::default()
+            },
+            multiview: None,
+            cache: None,
+        })
+    }
+
+    /// Rebuilds the pipeline, e.g. after the MSAA sample count changed. Call `set_depth`
+    /// with the new depth texture too.
+    pub fn recreate_pipeline(
+        &mut self,
+        camera_bind_group_layout: &wgpu::BindGroupLayout,
+        color_format: wgpu::TextureFormat,
+        depth_compare: wgpu::CompareFunction,
+        sample_count: u32,
+    ) {
+        self.render_pipeline = Self::create_render_pipeline(
+            &self.device,
+            camera_bind_group_layout,
+            &self.decal_bind_group_layout,
+            color_format,
+            depth_compare,
+            sample_count,
+        );
+    }
+
+    /// Reads `depth` from now on, e.g. after the render targets were resized.
+    pub fn set_depth(&mut self, depth: &texture::Texture) {
+        self.depth_bind_group = Self::create_depth_bind_group(&self.device, depth);
+    }
+
+    /// Stamps `texture` onto what lies inside the box `transform` places, returning the
+    /// decal's index.
+    pub fn add(&mut self, transform: Transform, texture: texture::Texture) -> usize {
+        let buffer = self
+            .device
+            .create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: Some("Decal Buffer"),
+                contents: bytemuck::cast_slice(&[DecalUniform::from(&transform)]),
+                usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            });
+        let bind_group = self.device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &self.decal_bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(&texture.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::Sampler(&texture.sampler),
+                },
+            ],
+            label: Some("decal_bind_group"),
+        });
+        self.decals.push(Decal {
+            transform,
+            texture,
+            buffer,
+            bind_group,
+        });
+        self.decals.len() - 1
+    }
+
+    /// Removes the decal at `index`, shifting the later ones down by one.
+    pub fn remove(&mut self, index: usize) -> Option<Decal> {
+        (index < self.decals.len()).then(|| self.decals.remove(index))
+    }
+
+    pub fn clear(&mut self) {
+        self.decals.clear();
+    }
+
+    pub fn get(&self, index: usize) -> Option<&Decal> {
+        self.decals.get(index)
+    }
+
+    /// A decal's transform can be changed here, it is uploaded on `upload`.
+    pub fn get_mut(&mut self, index: usize) -> Option<&mut Decal> {
+        self.decals.get_mut(index)
+    }
+
+    pub fn len(&self) -> usize {
+        self.decals.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.decals.is_empty()
+    }
+
+    pub fn upload(&self, queue: &wgpu::Queue) {
+        for decal in &self.decals {
+            queue.write_buffer(
+                &decal.buffer,
+                0,
+                bytemuck::cast_slice(&[DecalUniform::from(&decal.transform)]),
+            );
+        }
+    }
+
+    /// Draws every decal and returns the number of draw calls. Call this in a pass over the
+    /// opaque scene without a depth attachment, since the depth texture is read instead.
+    pub fn draw(
+        &self,
+        pass: &mut wgpu::RenderPass<'_>,
+        camera_bind_group: &wgpu::BindGroup,
+    ) -> u32 {
+        pass.set_pipeline(&self.render_pipeline);
+        pass.set_bind_group(0, camera_bind_group, &[]);
+        pass.set_bind_group(1, &self.depth_bind_group, &[]);
+        for decal in &self.decals {
+            pass.set_bind_group(2, &decal.bind_group, &[]);
+            pass.draw(0..36, 0..1);
+        }
+        self.decals.len() as u32
+    }
+}
@@ -0,0 +1,81 @@
+// Screen-space decals: the back faces of each decal's box cover the pixels it may touch,
+// which reconstruct their world position from the depth buffer and are tinted by the
+// decal's texture where that position falls inside the box
+
+// `CameraUniform` and the other shared types are declared in `src/prelude.wgsl`
+
+@group(0) @binding(0)
+var<uniform> camera: CameraUniform;
+
+// The depth buffer, bound as an unfilterable float texture since GL can't load from depth
+// textures. Only one of them is bound, the one the entry point matching the sample count
+// reads.
+@group(1) @binding(0)
+var t_depth: texture_2d<f32>;
+@group(1) @binding(0)
+var t_depth_multisampled: texture_multisampled_2d<f32>;
+
+struct Decal {
+  // From the unit box around the origin into world space
+  model: mat4x4<f32>,
+  inv_model: mat4x4<f32>,
+}
+@group(2) @binding(0)
+var<uniform> decal: Decal;
+@group(2) @binding(1)
+var t_decal: texture_2d<f32>;
+@group(2) @binding(2)
+var s_decal: sampler;
+
+// Depth of the far plane, 0 with reverse-Z
+override far_depth: f32 = 1.0;
+
+// Triangles of the unit box, counter-clockwise seen from outside. Corner `i` has x, y and
+// z from its bits 0, 1 and 2.
+const BOX_INDICES = array<u32, 36>(
+  0u, 4u, 6u, 0u, 6u, 2u, // -X
+  1u, 3u, 7u, 1u, 7u, 5u, // +X
+  0u, 1u, 5u, 0u, 5u, 4u, // -Y
+  2u, 6u, 7u, 2u, 7u, 3u, // +Y
+  0u, 2u, 3u, 0u, 3u, 1u, // -Z
+  4u, 5u, 7u, 4u, 7u, 6u, // +Z
+);
+
+@vertex
+fn vs_main(@builtin(vertex_index) id: u32) -> @builtin(position) vec4<f32> {
+  let corner = BOX_INDICES[id];
+  let local = vec3<f32>(vec3<u32>(corner & 1u, (corner >> 1u) & 1u, (corner >> 2u) & 1u)) - 0.5;
+  return camera.view_proj * decal.model * vec4<f32>(local, 1.0);
+}
+
+fn shade(position: vec4<f32>, depth: f32, size: vec2<u32>) -> vec4<f32> {
+  let uv = position.xy / vec2<f32>(size);
+  let ndc = vec4<f32>(uv.x * 2.0 - 1.0, 1.0 - uv.y * 2.0, depth, 1.0);
+  let view = camera.inv_proj * ndc;
+  let world = camera.inv_view * vec4<f32>(view.xyz / view.w, 1.0);
+  let local = (decal.inv_model * world).xyz;
+
+  // Projected straight down the box's y axis, sampled before discarding so the
+  // derivatives picking the mip level stay defined
+  let color = textureSample(t_decal, s_decal, local.xz + 0.5);
+  if depth == far_depth || any(abs(local) > vec3<f32>(0.5)) {
+    discard;
+  }
+  // Fades out towards the top and bottom of the box rather than cutting off
+  let alpha = color.a * (1.0 - smoothstep(0.35, 0.5, abs(local.y)));
+  // Multiplied onto what is below, see the pipeline's blend state
+  return vec4<f32>(color.rgb * alpha, alpha);
+}
+
+@fragment
+fn fs_main(@builtin(position) position: vec4<f32>) -> @location(0) vec4<f32> {
+  let depth = textureLoad(t_depth, vec2<u32>(position.xy), 0).r;
+  return shade(position, depth, textureDimensions(t_depth));
+}
+
+// With MSAA, the depth of each pixel's first sample
+@fragment
+fn fs_multisampled(@builtin(position) position: vec4<f32>) -> @location(0) vec4<f32> {
+  let depth = textureLoad(t_depth_multisampled, vec2<u32>(position.xy), 0).r;
+  return shade(position, depth, textureDimensions(t_depth_multisampled));
+}
@@ -0,0 +1,400 @@
+use crate::{
+    hot_reload::wgsl,
+    instance::InstanceRaw,
+    model,
+    model::Vertex as _,
+    render_pipeline::{PipelineDesc, PrimitiveConfig, create_render_pipeline},
+    ssao::Ssao,
+    texture,
+    velocity::MotionVectors,
+};
+
+/// How the opaque scene is lit.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub enum RenderingMode {
+    /// Every opaque draw shades its own fragments, including those later drawn over
+    #[default]
+    Forward,
+    /// Opaque draws only write their surfaces into a G-buffer, then one fullscreen pass
+    /// shades every pixel once. Splatted terrain, water and transparent objects are still
+    /// drawn forward on top. Doesn't support MSAA.
+    Deferred,
+}
+
+/// The G-buffer of `RenderingMode::Deferred` and the pipelines filling and lighting it.
+pub struct Deferred {
+    /// Base color, with alpha
+    pub albedo: texture::Texture,
+    /// World space normal, after normal mapping
+    pub normal: texture::Texture,
+    /// World space position relative to the camera, with w 0 where nothing was drawn.
+    /// Relative, as half floats would lose too much precision far from the origin.
+    pub position: texture::Texture,
+    /// Ambient occlusion of the G-buffer, darkening the lighting pass's ambient light
+    pub ssao: Ssao,
+    bind_group_layout: wgpu::BindGroupLayout,
+    bind_group: wgpu::BindGroup,
+    geometry_pipeline: wgpu::RenderPipeline,
+    lighting_pipeline: wgpu::RenderPipeline,
+}
+
+impl Deferred {
+    pub const ALBEDO_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba16Float;
+    pub const NORMAL_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba16Float;
+    pub const POSITION_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba16Float;
+
+    /// `scene_layout` is the layout of the forward scene pipelines, whose bind groups the
+    /// geometry pass shares. The lighting pass binds the G-buffer, then the camera, lights,
+    /// sun, shadow and fog groups, and draws into `color_format` without MSAA. With
+    /// `velocity` the geometry pass also writes `MotionVectors::velocity`.
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        device: &wgpu::Device,
+        width: u32,
+        height: u32,
+        scene_layout: &wgpu::PipelineLayout,
+        lighting_layouts: [&wgpu::BindGroupLayout; 5],
+        color_format: wgpu::TextureFormat,
+        depth_format: wgpu::TextureFormat,
+        depth_compare: wgpu::CompareFunction,
+        primitive: PrimitiveConfig,
+        velocity: bool,
+    ) -> Self {
+        let geometry_pipeline = Self::create_geometry_pipeline(
+            device,
+            scene_layout,
+            depth_format,
+            depth_compare,
+            primitive,
+            velocity,
+        );
+
+        let texture = |binding| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Texture {
+                multisampled: false,
+                view_dimension: wgpu::TextureViewDimension::D2,
+                // Read with textureLoad, one texel per pixel
+                sample_type: wgpu::TextureSampleType::Float { filterable: false },
+            },
+            count: None,
+        };
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                texture(0),
+                texture(1),
+                texture(2),
+                texture(3),
+                wgpu::BindGroupLayoutEntry {
+                    binding: 4,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ],
+            label: Some("gbuffer_bind_group_layout"),
+        });
+
+        let lighting_pipeline = Self::create_lighting_pipeline(
+            device,
+            &bind_group_layout,
+            lighting_layouts,
+            color_format,
+            depth_format,
+        );
+
+        let (albedo, normal, position) = Self::create_targets(device, width, height);
+        let [camera, ..] = lighting_layouts;
+        let ssao = Ssao::new(device, camera, &normal.view, &position.view, width, height);
+        let bind_group = Self::create_bind_group(
+            device,
+            &bind_group_layout,
+            &albedo,
+            &normal,
+            &position,
+            &ssao,
+        );
+        Self {
+            albedo,
+            normal,
+            position,
+            ssao,
+            bind_group_layout,
+            bind_group,
+            geometry_pipeline,
+            lighting_pipeline,
+        }
+    }
+
+    fn create_lighting_pipeline(
+        device: &wgpu::Device,
+        bind_group_layout: &wgpu::BindGroupLayout,
+        lighting_layouts: [&wgpu::BindGroupLayout; 5],
+        color_format: wgpu::TextureFormat,
+        depth_format: wgpu::TextureFormat,
+    ) -> wgpu::RenderPipeline {
+        let [camera, lights, sun, shadow, fog] = lighting_layouts;
+        let lighting_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Deferred Lighting Pipeline Layout"),
+            bind_group_layouts: &[bind_group_layout, camera, lights, sun, shadow, fog],
+            push_constant_ranges: &[],
+        });
+        let shader = device.create_shader_module(wgsl!(
+            "src/deferred.wgsl",
+            "src/fullscreen.wgsl",
+            "src/shadow_sampling.wgsl",
+            "src/prelude.wgsl"
+        ));
+        device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Deferred Lighting Pipeline"),
+            layout: Some(&lighting_layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: Some("vs_main"),
+                buffers: &[],
+                compilation_options: Default::default(),
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: Some("fs_main"),
+                targets: &[Some(color_format.into())],
+                compilation_options: Default::default(),
+            }),
+            primitive: wgpu::PrimitiveState::default(),
+            // Shares the main pass's depth buffer, filled by the geometry pass
+            depth_stencil: Some(wgpu::DepthStencilState {
+                format: depth_format,
+                depth_write_enabled: false,
+                depth_compare: wgpu::CompareFunction::Always,
+                stencil: wgpu::StencilState::default(),
+                bias: wgpu::DepthBiasState::default(),
+            }),
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+            cache: None,
+        })
+    }
+
+    /// Rebuilds the lighting pass's pipeline from the current shader, see `hot_reload` and
+    /// `new`.
+    pub fn recreate_lighting_pipeline(
+        &mut self,
+        device: &wgpu::Device,
+        lighting_layouts: [&wgpu::BindGroupLayout; 5],
+        color_format: wgpu::TextureFormat,
+        depth_format: wgpu::TextureFormat,
+    ) {
+        self.lighting_pipeline = Self::create_lighting_pipeline(
+            device,
+            &self.bind_group_layout,
+            lighting_layouts,
+            color_format,
+            depth_format,
+        );
+    }
+
+    fn create_geometry_pipeline(
+        device: &wgpu::Device,
+        scene_layout: &wgpu::PipelineLayout,
+        depth_format: wgpu::TextureFormat,
+        depth_compare: wgpu::CompareFunction,
+        primitive: PrimitiveConfig,
+        velocity: bool,
+    ) -> wgpu::RenderPipeline {
+        let formats = [
+            Self::ALBEDO_FORMAT,
+            Self::NORMAL_FORMAT,
+            Self::POSITION_FORMAT,
+            MotionVectors::FORMAT,
+        ];
+        create_render_pipeline(
+            device,
+            scene_layout,
+            &formats[..if velocity { 4 } else { 3 }],
+            Some(depth_format),
+            // The previous model matrix takes the place of the normal matrix
+            &[model::ModelVertex::desc(), InstanceRaw::motion_desc()],
+            wgsl!("src/gbuffer.wgsl", "src/prelude.wgsl"),
+            PipelineDesc {
+                depth_compare,
+                primitive,
+                fragment_entry: if velocity { "fs_velocity" } else { "fs_main" },
+                ..Default::default()
+            },
+        )
+    }
+
+    /// Rebuilds the geometry pass's pipeline, e.g. once the culled faces changed or motion
+    /// vectors were turned on or off, see `new`.
+    pub fn recreate_geometry_pipeline(
+        &mut self,
+        device: &wgpu::Device,
+        scene_layout: &wgpu::PipelineLayout,
+        depth_format: wgpu::TextureFormat,
+        depth_compare: wgpu::CompareFunction,
+        primitive: PrimitiveConfig,
+        velocity: bool,
+    ) {
+        self.geometry_pipeline = Self::create_geometry_pipeline(
+            device,
+            scene_layout,
+            depth_format,
+            depth_compare,
+            primitive,
+            velocity,
+        );
+    }
+
+    fn create_targets(
+        device: &wgpu::Device,
+        width: u32,
+        height: u32,
+    ) -> (texture::Texture, texture::Texture, texture::Texture) {
+        let target = |label, format| {
+            texture::Texture::create_texture(
+                device,
+                Some(label),
+                wgpu::Extent3d {
+                    width: width.max(1),
+                    height: height.max(1),
+                    depth_or_array_layers: 1,
+                },
+                format,
+                wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+                wgpu::TextureDimension::D2,
+                wgpu::FilterMode::Nearest,
+            )
+        };
+        let albedo = target("gbuffer_albedo_texture", Self::ALBEDO_FORMAT);
+        let normal = target("gbuffer_normal_texture", Self::NORMAL_FORMAT);
+        let position = target("gbuffer_position_texture", Self::POSITION_FORMAT);
+        (albedo, normal, position)
+    }
+
+    fn create_bind_group(
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        albedo: &texture::Texture,
+        normal: &texture::Texture,
+        position: &texture::Texture,
+        ssao: &Ssao,
+    ) -> wgpu::BindGroup {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&albedo.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(&normal.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::TextureView(&position.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: wgpu::BindingResource::TextureView(&ssao.occlusion.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 4,
+                    resource: ssao.buffer().as_entire_binding(),
+                },
+            ],
+            label: Some("gbuffer_bind_group"),
+        })
+    }
+
+    /// Recreates the G-buffer at the render size, which must match the depth buffer's.
+    pub fn resize(&mut self, device: &wgpu::Device, width: u32, height: u32) {
+        (self.albedo, self.normal, self.position) = Self::create_targets(device, width, height);
+        self.ssao.resize(
+            device,
+            &self.normal.view,
+            &self.position.view,
+            width,
+            height,
+        );
+        self.bind_group = Self::create_bind_group(
+            device,
+            &self.bind_group_layout,
+            &self.albedo,
+            &self.normal,
+            &self.position,
+            &self.ssao,
+        );
+    }
+
+    /// Pipeline writing opaque geometry into the G-buffer, drawn like the forward scene
+    /// pipeline in `begin_geometry_pass`.
+    pub fn geometry_pipeline(&self) -> &wgpu::RenderPipeline {
+        &self.geometry_pipeline
+    }
+
+    /// Begins a pass clearing the G-buffer and `depth`, for drawing with
+    /// `geometry_pipeline`. `velocity` has to be given if and only if the pipeline writes
+    /// it, see `new`.
+    pub fn begin_geometry_pass<'a>(
+        &self,
+        encoder: &'a mut wgpu::CommandEncoder,
+        depth: &wgpu::TextureView,
+        depth_clear: f32,
+        velocity: Option<wgpu::RenderPassColorAttachment<'_>>,
+        timestamp_writes: Option<wgpu::RenderPassTimestampWrites<'_>>,
+    ) -> wgpu::RenderPass<'a> {
+        let targets = if velocity.is_some() { 4 } else { 3 };
+        let target = |view| {
+            Some(wgpu::RenderPassColorAttachment {
+                view,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(wgpu::Color::TRANSPARENT),
+                    store: wgpu::StoreOp::Store,
+                },
+                depth_slice: None,
+            })
+        };
+        encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("G-Buffer Pass"),
+            color_attachments: &[
+                target(&self.albedo.view),
+                target(&self.normal.view),
+                target(&self.position.view),
+                velocity,
+            ][..targets],
+            depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                view: depth,
+                depth_ops: Some(wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(depth_clear),
+                    store: wgpu::StoreOp::Store,
+                }),
+                stencil_ops: None,
+            }),
+            occlusion_query_set: None,
+            timestamp_writes,
+        })
+    }
+
+    /// Shades the G-buffer into `render_pass` with one fullscreen triangle, leaving the
+    /// pixels nothing was drawn to alone. `bind_groups` are the camera, lights, sun, shadow
+    /// and fog groups, in the order of the layouts given to `new`.
+    pub fn draw_lighting<'a>(
+        &'a self,
+        render_pass: &mut wgpu::RenderPass<'a>,
+        bind_groups: [&'a wgpu::BindGroup; 5],
+    ) {
+        render_pass.set_pipeline(&self.lighting_pipeline);
+        render_pass.set_bind_group(0, &self.bind_group, &[]);
+        for (index, bind_group) in bind_groups.into_iter().enumerate() {
+            render_pass.set_bind_group(index as u32 + 1, bind_group, &[]);
+        }
+        render_pass.draw(0..3, 0..1);
+    }
+}
@@ -0,0 +1,99 @@
+// Lighting pass of deferred rendering: shades every pixel of the G-buffer once with the
+// same lights as shader.wgsl, in a single fullscreen triangle
+
+// `CameraUniform` and the other shared types are declared in `src/prelude.wgsl`
+
+// Only `enabled` is read here, see ssao.wgsl
+struct Ssao {
+  kernel: array<vec4<f32>, 16>,
+  noise: array<vec4<f32>, 16>,
+  radius: f32,
+  bias: f32,
+  intensity: f32,
+  enabled: u32,
+};
+
+@group(0) @binding(0)
+var t_albedo: texture_2d<f32>;
+@group(0) @binding(1)
+var t_normal: texture_2d<f32>;
+@group(0) @binding(2)
+var t_position: texture_2d<f32>;
+@group(0) @binding(3)
+var t_occlusion: texture_2d<f32>;
+@group(0) @binding(4)
+var<uniform> ssao: Ssao;
+
+@group(1) @binding(0)
+var<uniform> camera: CameraUniform;
+
+@group(2) @binding(0)
+var<storage, read> lights: LightArray;
+@group(2) @binding(1)
+var<storage, read> spot_lights: SpotLightArray;
+
+@group(3) @binding(0)
+var<uniform> sun: DirectionalLight;
+
+// `Shadow` and the functions sampling the cascades are in `src/shadow_sampling.wgsl`
+@group(4) @binding(0)
+var t_shadow: texture_depth_2d_array;
+@group(4) @binding(1)
+var s_shadow: sampler_comparison;
+@group(4) @binding(2)
+var<uniform> shadow: Shadow;
+
+@group(5) @binding(0)
+var<uniform> fog: Fog;
+
+// `vs_main` is appended from `src/fullscreen.wgsl`
+
+@fragment
+fn fs_main(@builtin(position) frag_coord: vec4<f32>) -> @location(0) vec4<f32> {
+  let coords = vec2<i32>(frag_coord.xy);
+  let position_sample = textureLoad(t_position, coords, 0);
+  // Nothing was drawn here, leave it to the clear color and the skybox
+  if position_sample.w == 0.0 {
+    discard;
+  }
+  let world_position = position_sample.xyz + camera.view_pos.xyz;
+  let object_color = textureLoad(t_albedo, coords, 0);
+  let normal = normalize(textureLoad(t_normal, coords, 0).xyz);
+  let view_dir = normalize(camera.view_pos.xyz - world_position);
+  // Ambient light doesn't reach into creases
+  let occlusion = select(0.0, textureLoad(t_occlusion, coords, 0).r, ssao.enabled != 0u);
+
+  var color = AMBIENT_STRENGTH * object_color.xyz * (1.0 - occlusion);
+  let count = min(lights.count, arrayLength(&lights.lights));
+  for (var i = 0u; i < count; i += 1u) {
+    let light = lights.lights[i];
+    let light_dir = normalize(light.position - world_position);
+    let half_dir = normalize(view_dir + light_dir);
+    let diffuse_color = light.color * max(dot(normal, light_dir), 0.0) * object_color.xyz;
+    let specular_color = light.color * pow(max(dot(normal, half_dir), 0.0), 32.0);
+    let attenuation = point_attenuation(light, world_position);
+    color += (diffuse_color + specular_color) * attenuation;
+  }
+
+  let spot_count = min(spot_lights.count, arrayLength(&spot_lights.lights));
+  for (var i = 0u; i < spot_count; i += 1u) {
+    let light = spot_lights.lights[i];
+    let light_dir = normalize(light.position - world_position);
+    let half_dir = normalize(view_dir + light_dir);
+    let diffuse_color = light.color * max(dot(normal, light_dir), 0.0) * object_color.xyz;
+    let specular_color = light.color * pow(max(dot(normal, half_dir), 0.0), 32.0);
+    color += (diffuse_color + specular_color) * spot_attenuation(light, world_position);
+  }
+
+  let sun_dir = -sun.direction;
+  let sun_half_dir = normalize(view_dir + sun_dir);
+  let sun_diffuse = sun.color * max(dot(normal, sun_dir), 0.0) * object_color.xyz;
+  let sun_specular = sun.color * pow(max(dot(normal, sun_half_dir), 0.0), 32.0);
+  color += (sun_diffuse + sun_specular) * sun_visibility(world_position, normal, sun_dir);
+
+  color *= cascade_tint(world_position);
+  let view_depth = -(camera.view * vec4<f32>(world_position, 1.0)).z;
+  color = mix(color, fog.color.rgb, fog_factor(fog, view_depth));
+
+  return vec4<f32>(color, object_color.a);
+}
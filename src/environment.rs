@@ -0,0 +1,15 @@
+use crate::texture;
+
+/// Where the sky and the image-based lighting of a scene come from, see
+/// `Renderer::set_environment`. Either way the sky is drawn behind the scene and
+/// `Renderer::ibl` holds the lighting baked from it, so shaders using them don't depend on
+/// which one is active.
+pub enum Environment {
+    /// An environment map, e.g. converted from an equirectangular image by
+    /// `EquirectToCubemapConverter` or baked by `AtmosphereSkybox::bake`. It has to be a
+    /// non-filtered float cube like those.
+    Cubemap(texture::CubeTexture),
+    /// The procedural sky of `AtmosphereSkybox`, following the sun. Its lighting is baked
+    /// from the sky as it is when set.
+    Atmosphere,
+}
@@ -0,0 +1,315 @@
+use wgpu::util::DeviceExt as _;
+
+use crate::{hot_reload::wgsl, tonemap::Tonemapper};
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
+struct ExposureParams {
+    min_log_luminance: f32,
+    log_luminance_range: f32,
+    min_exposure: f32,
+    max_exposure: f32,
+    adaptation: f32,
+    key: f32,
+    _padding: [f32; 2],
+}
+
+/// How `AutoExposure` adapts.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AutoExposureSettings {
+    /// Lowest exposure it adapts to, limiting how far bright scenes are darkened
+    pub min_exposure: f32,
+    /// Highest exposure it adapts to, limiting how far dark scenes are brightened
+    pub max_exposure: f32,
+    /// Time constant of the adaptation in seconds: after `tau` the exposure covered about
+    /// 63% of the way to a new target. 0 adapts at once.
+    pub tau: f32,
+}
+
+impl Default for AutoExposureSettings {
+    fn default() -> Self {
+        Self {
+            min_exposure: 0.1,
+            max_exposure: 10.0,
+            tau: 1.0,
+        }
+    }
+}
+
+impl AutoExposureSettings {
+    /// Non-negative exposures with `max_exposure` at least `min_exposure`, and a
+    /// non-negative `tau`.
+    pub(crate) fn sanitized(self) -> Self {
+        let min_exposure = self.min_exposure.max(0.0);
+        Self {
+            min_exposure,
+            max_exposure: self.max_exposure.max(min_exposure),
+            tau: self.tau.max(0.0),
+        }
+    }
+}
+
+/// Eye adaptation: picks the exposure for tone mapping from how bright the HDR scene is,
+/// easing towards it over time like eyes getting used to the dark.
+///
+/// A compute pass sorts the pixels of the HDR target into a 256 bin histogram of their log
+/// luminance, a second one averages it and moves the exposure that maps the average to
+/// middle gray a step closer, all without reading anything back. The exposure stays in a
+/// buffer on the GPU and is copied into the `Tonemapper`'s uniform before it runs, so its
+/// manual exposure comes back as soon as this stops being applied. Needs compute shaders.
+pub struct AutoExposure {
+    settings: AutoExposureSettings,
+    params_buffer: wgpu::Buffer,
+    histogram_buffer: wgpu::Buffer,
+    /// The adapted exposure, a single `f32`
+    state_buffer: wgpu::Buffer,
+    bind_group_layout: wgpu::BindGroupLayout,
+    bind_group: wgpu::BindGroup,
+    histogram_pipeline: wgpu::ComputePipeline,
+    average_pipeline: wgpu::ComputePipeline,
+    width: u32,
+    height: u32,
+}
+
+impl AutoExposure {
+    /// Middle gray, what the average luminance is exposed to
+    pub const KEY: f32 = 0.18;
+    /// log2 luminance of the darkest bin, darker pixels only count as black
+    pub const MIN_LOG_LUMINANCE: f32 = -10.0;
+    /// log2 luminance range of the histogram, brighter pixels count as its brightest bin
+    pub const LOG_LUMINANCE_RANGE: f32 = 16.0;
+    const BINS: u64 = 256;
+    const WORKGROUP_SIZE: u32 = 16;
+
+    /// Adapts to the HDR target of `width` x `height` bound from `source`, starting out at
+    /// `exposure`.
+    pub fn new(
+        device: &wgpu::Device,
+        source: &wgpu::TextureView,
+        width: u32,
+        height: u32,
+        settings: AutoExposureSettings,
+        exposure: f32,
+    ) -> Self {
+        let settings = settings.sanitized();
+        let params_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Auto Exposure Params Buffer"),
+            contents: bytemuck::cast_slice(&[Self::params(settings, 0.0)]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        let histogram_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Auto Exposure Histogram Buffer"),
+            size: Self::BINS * std::mem::size_of::<u32>() as u64,
+            usage: wgpu::BufferUsages::STORAGE,
+            mapped_at_creation: false,
+        });
+        let state_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Auto Exposure State Buffer"),
+            contents: bytemuck::cast_slice(&[exposure]),
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC,
+        });
+
+        let storage_entry = |binding| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Storage { read_only: false },
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Texture {
+                        multisampled: false,
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                    },
+                    count: None,
+                },
+                storage_entry(1),
+                storage_entry(2),
+                wgpu::BindGroupLayoutEntry {
+                    binding: 3,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ],
+            label: Some("auto_exposure_bind_group_layout"),
+        });
+        let (histogram_pipeline, average_pipeline) =
+            Self::create_pipelines(device, &bind_group_layout);
+
+        let bind_group = Self::create_bind_group(
+            device,
+            &bind_group_layout,
+            source,
+            &histogram_buffer,
+            &state_buffer,
+            &params_buffer,
+        );
+        Self {
+            settings,
+            params_buffer,
+            histogram_buffer,
+            state_buffer,
+            bind_group_layout,
+            bind_group,
+            histogram_pipeline,
+            average_pipeline,
+            width,
+            height,
+        }
+    }
+
+    /// The histogram and averaging pipelines.
+    fn create_pipelines(
+        device: &wgpu::Device,
+        bind_group_layout: &wgpu::BindGroupLayout,
+    ) -> (wgpu::ComputePipeline, wgpu::ComputePipeline) {
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Auto Exposure Pipeline Layout"),
+            bind_group_layouts: &[bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let shader = device.create_shader_module(wgsl!("src/exposure.wgsl"));
+        let pipeline = |label, entry_point| {
+            device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+                label: Some(label),
+                layout: Some(&pipeline_layout),
+                module: &shader,
+                entry_point: Some(entry_point),
+                compilation_options: Default::default(),
+                cache: None,
+            })
+        };
+        (
+            pipeline("Luminance Histogram Pipeline", "build_histogram"),
+            pipeline("Auto Exposure Pipeline", "average"),
+        )
+    }
+
+    /// Rebuilds the pipelines from the current shader, see `hot_reload`.
+    pub fn recreate_pipelines(&mut self, device: &wgpu::Device) {
+        (self.histogram_pipeline, self.average_pipeline) =
+            Self::create_pipelines(device, &self.bind_group_layout);
+    }
+
+    fn create_bind_group(
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        source: &wgpu::TextureView,
+        histogram_buffer: &wgpu::Buffer,
+        state_buffer: &wgpu::Buffer,
+        params_buffer: &wgpu::Buffer,
+    ) -> wgpu::BindGroup {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(source),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: histogram_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: state_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: params_buffer.as_entire_binding(),
+                },
+            ],
+            label: Some("auto_exposure_bind_group"),
+        })
+    }
+
+    fn params(settings: AutoExposureSettings, adaptation: f32) -> ExposureParams {
+        ExposureParams {
+            min_log_luminance: Self::MIN_LOG_LUMINANCE,
+            log_luminance_range: Self::LOG_LUMINANCE_RANGE,
+            min_exposure: settings.min_exposure,
+            max_exposure: settings.max_exposure,
+            adaptation,
+            key: Self::KEY,
+            _padding: [0.0; 2],
+        }
+    }
+
+    /// Rebinds a new HDR target of `width` x `height`. The exposure adapted so far is kept.
+    pub fn resize(
+        &mut self,
+        device: &wgpu::Device,
+        source: &wgpu::TextureView,
+        width: u32,
+        height: u32,
+    ) {
+        self.bind_group = Self::create_bind_group(
+            device,
+            &self.bind_group_layout,
+            source,
+            &self.histogram_buffer,
+            &self.state_buffer,
+            &self.params_buffer,
+        );
+        self.width = width;
+        self.height = height;
+    }
+
+    pub fn settings(&self) -> AutoExposureSettings {
+        self.settings
+    }
+
+    /// Takes effect on the next `update`. Negative values are raised to 0 and
+    /// `max_exposure` to `min_exposure`.
+    pub fn set_settings(&mut self, settings: AutoExposureSettings) {
+        self.settings = settings.sanitized();
+    }
+
+    /// Uploads how far the next `apply` adapts, for a frame of `dt`.
+    pub fn update(&self, queue: &wgpu::Queue, dt: std::time::Duration) {
+        let adaptation = if self.settings.tau > 0.0 {
+            1.0 - (-dt.as_secs_f32() / self.settings.tau).exp()
+        } else {
+            1.0
+        };
+        queue.write_buffer(
+            &self.params_buffer,
+            0,
+            bytemuck::cast_slice(&[Self::params(self.settings, adaptation)]),
+        );
+    }
+
+    /// Measures the HDR target, adapts the exposure and hands it to `tonemapper` for the
+    /// tone mapping pass recorded after it.
+    pub fn apply(&self, encoder: &mut wgpu::CommandEncoder, tonemapper: &Tonemapper) {
+        {
+            let mut pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+                label: Some("Auto Exposure Pass"),
+                timestamp_writes: None,
+            });
+            pass.set_bind_group(0, &self.bind_group, &[]);
+            pass.set_pipeline(&self.histogram_pipeline);
+            pass.dispatch_workgroups(
+                self.width.div_ceil(Self::WORKGROUP_SIZE),
+                self.height.div_ceil(Self::WORKGROUP_SIZE),
+                1,
+            );
+            pass.set_pipeline(&self.average_pipeline);
+            pass.dispatch_workgroups(1, 1, 1);
+        }
+        tonemapper.copy_exposure(encoder, &self.state_buffer);
+    }
+}
@@ -0,0 +1,94 @@
+// Eye adaptation: `build_histogram` sorts the pixels of the HDR target into bins by their
+// log luminance, `average` turns the histogram into the exposure that maps the average log
+// luminance to middle gray and eases the current exposure towards it.
+
+struct Params {
+  // log2 luminance of the lower edge of bin 1, bin 0 takes everything darker
+  min_log_luminance: f32,
+  // log2 luminance range covered by bins 1 to 255
+  log_luminance_range: f32,
+  min_exposure: f32,
+  max_exposure: f32,
+  // Fraction of the way to the target exposure covered this frame, from the frame time
+  // and the adaptation time constant
+  adaptation: f32,
+  // Exposure that maps the average luminance to middle gray, over that luminance
+  key: f32,
+  _padding: vec2<f32>,
+};
+
+struct State {
+  exposure: f32,
+};
+
+const BINS: u32 = 256u;
+
+@group(0) @binding(0)
+var t_hdr: texture_2d<f32>;
+@group(0) @binding(1)
+var<storage, read_write> histogram: array<atomic<u32>, 256>;
+@group(0) @binding(2)
+var<storage, read_write> state: State;
+@group(0) @binding(3)
+var<uniform> params: Params;
+
+var<workgroup> local_bins: array<atomic<u32>, 256>;
+var<workgroup> weighted: array<f32, 256>;
+
+fn bin_of(color: vec3<f32>) -> u32 {
+  let luminance = dot(color, vec3<f32>(0.2126, 0.7152, 0.0722));
+  // Black pixels, e.g. unlit or masked ones, would drag the average to minus infinity
+  if luminance < 0.0001 {
+    return 0u;
+  }
+  let t = clamp((log2(luminance) - params.min_log_luminance) / params.log_luminance_range, 0.0, 1.0);
+  return u32(t * 254.0 + 1.0);
+}
+
+@compute @workgroup_size(16, 16, 1)
+fn build_histogram(
+  @builtin(global_invocation_id) gid: vec3<u32>,
+  @builtin(local_invocation_index) index: u32,
+) {
+  atomicStore(&local_bins[index], 0u);
+  workgroupBarrier();
+  if all(gid.xy < textureDimensions(t_hdr)) {
+    let color = textureLoad(t_hdr, gid.xy, 0).rgb;
+    atomicAdd(&local_bins[bin_of(color)], 1u);
+  }
+  workgroupBarrier();
+  atomicAdd(&histogram[index], atomicLoad(&local_bins[index]));
+}
+
+@compute @workgroup_size(256, 1, 1)
+fn average(@builtin(local_invocation_index) index: u32) {
+  let count = atomicLoad(&histogram[index]);
+  // Cleared for the next frame's histogram
+  atomicStore(&histogram[index], 0u);
+  weighted[index] = f32(count) * f32(index);
+  if index == 0u {
+    // Remembers the black pixels, which the reduction below doesn't need
+    atomicStore(&local_bins[0], count);
+  }
+  workgroupBarrier();
+
+  for (var stride = BINS / 2u; stride > 0u; stride >>= 1u) {
+    if index < stride {
+      weighted[index] += weighted[index + stride];
+    }
+    workgroupBarrier();
+  }
+
+  if index == 0u {
+    let size = textureDimensions(t_hdr);
+    let lit = f32(size.x * size.y - atomicLoad(&local_bins[0]));
+    // Nothing lit to adapt to, keep the exposure
+    if lit < 1.0 {
+      return;
+    }
+    let average_bin = weighted[0] / lit;
+    let log_luminance = (average_bin - 1.0) / 254.0 * params.log_luminance_range + params.min_log_luminance;
+    let target_exposure = clamp(params.key / exp2(log_luminance), params.min_exposure, params.max_exposure);
+    state.exposure = mix(state.exposure, target_exposure, params.adaptation);
+  }
+}
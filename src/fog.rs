@@ -0,0 +1,145 @@
+use wgpu::util::DeviceExt as _;
+
+/// How fog thickens with distance from the camera.
+#[repr(u32)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FogMode {
+    None = 0,
+    /// Ramps from no fog at `start` to full fog at `end`
+    Linear = 1,
+    /// `1 - exp(-density * d)`
+    Exponential = 2,
+    /// `1 - exp(-(density * d)^2)`, which keeps the near range clearer
+    ExponentialSquared = 3,
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct FogUniform {
+    pub color: [f32; 4],
+    pub start: f32,
+    pub end: f32,
+    pub density: f32,
+    pub mode: u32,
+}
+
+impl FogUniform {
+    pub fn linear(color: [f32; 4], start: f32, end: f32) -> Self {
+        Self {
+            color,
+            start,
+            end,
+            density: 0.0,
+            mode: FogMode::Linear as u32,
+        }
+    }
+
+    pub fn exponential(color: [f32; 4], density: f32) -> Self {
+        Self {
+            color,
+            start: 0.0,
+            end: 0.0,
+            density,
+            mode: FogMode::Exponential as u32,
+        }
+    }
+
+    pub fn exponential_squared(color: [f32; 4], density: f32) -> Self {
+        Self {
+            mode: FogMode::ExponentialSquared as u32,
+            ..Self::exponential(color, density)
+        }
+    }
+}
+
+impl Default for FogUniform {
+    fn default() -> Self {
+        Self {
+            color: [0.0; 4],
+            start: 0.0,
+            end: 0.0,
+            density: 0.0,
+            mode: FogMode::None as u32,
+        }
+    }
+}
+
+/// Distance fog blended over lit surfaces by view-space depth.
+pub struct Fog {
+    pub uniform: FogUniform,
+    pub buffer: wgpu::Buffer,
+    pub bind_group: wgpu::BindGroup,
+    pub bind_group_layout: wgpu::BindGroupLayout,
+}
+
+impl Fog {
+    pub fn new(device: &wgpu::Device, uniform: FogUniform) -> Self {
+        let buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Fog Buffer"),
+            contents: bytemuck::cast_slice(&[uniform]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            }],
+            label: Some("fog_bind_group_layout"),
+        });
+
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &bind_group_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: buffer.as_entire_binding(),
+            }],
+            label: Some("fog_bind_group"),
+        });
+
+        Self {
+            uniform,
+            buffer,
+            bind_group,
+            bind_group_layout,
+        }
+    }
+
+    pub fn mode(&self) -> FogMode {
+        match self.uniform.mode {
+            1 => FogMode::Linear,
+            2 => FogMode::Exponential,
+            3 => FogMode::ExponentialSquared,
+            _ => FogMode::None,
+        }
+    }
+
+    pub fn set_mode(&mut self, mode: FogMode) {
+        self.uniform.mode = mode as u32;
+    }
+
+    pub fn set_color(&mut self, color: [f32; 4]) {
+        self.uniform.color = color;
+    }
+
+    /// Sets the range used by `FogMode::Linear`.
+    pub fn set_range(&mut self, start: f32, end: f32) {
+        self.uniform.start = start;
+        self.uniform.end = end;
+    }
+
+    /// Sets the density used by the exponential modes.
+    pub fn set_density(&mut self, density: f32) {
+        self.uniform.density = density;
+    }
+
+    pub fn upload(&self, queue: &wgpu::Queue) {
+        queue.write_buffer(&self.buffer, 0, bytemuck::cast_slice(&[self.uniform]));
+    }
+}
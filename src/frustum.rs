@@ -0,0 +1,185 @@
+use cgmath::{EuclideanSpace, InnerSpace, Matrix, Matrix4, Point3, Vector3, Vector4};
+
+/// An axis-aligned bounding box.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Aabb {
+    pub min: Point3<f32>,
+    pub max: Point3<f32>,
+}
+
+impl Aabb {
+    /// The box containing nothing, the identity for `union`.
+    pub const EMPTY: Aabb = Aabb {
+        min: Point3::new(f32::INFINITY, f32::INFINITY, f32::INFINITY),
+        max: Point3::new(f32::NEG_INFINITY, f32::NEG_INFINITY, f32::NEG_INFINITY),
+    };
+
+    pub fn new(min: Point3<f32>, max: Point3<f32>) -> Self {
+        Self { min, max }
+    }
+
+    pub fn from_points(points: impl IntoIterator<Item = Point3<f32>>) -> Self {
+        points
+            .into_iter()
+            .fold(Self::EMPTY, |aabb, p| aabb.include(p))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.min.x > self.max.x || self.min.y > self.max.y || self.min.z > self.max.z
+    }
+
+    /// Grows the box to contain `p`.
+    pub fn include(self, p: Point3<f32>) -> Self {
+        Self {
+            min: Point3::new(
+                self.min.x.min(p.x),
+                self.min.y.min(p.y),
+                self.min.z.min(p.z),
+            ),
+            max: Point3::new(
+                self.max.x.max(p.x),
+                self.max.y.max(p.y),
+                self.max.z.max(p.z),
+            ),
+        }
+    }
+
+    pub fn union(self, other: Aabb) -> Self {
+        if other.is_empty() {
+            return self;
+        }
+        self.include(other.min).include(other.max)
+    }
+
+    pub fn center(&self) -> Point3<f32> {
+        self.min.midpoint(self.max)
+    }
+
+    pub fn corners(&self) -> [Point3<f32>; 8] {
+        let (a, b) = (self.min, self.max);
+        [
+            Point3::new(a.x, a.y, a.z),
+            Point3::new(b.x, a.y, a.z),
+            Point3::new(a.x, b.y, a.z),
+            Point3::new(b.x, b.y, a.z),
+            Point3::new(a.x, a.y, b.z),
+            Point3::new(b.x, a.y, b.z),
+            Point3::new(a.x, b.y, b.z),
+            Point3::new(b.x, b.y, b.z),
+        ]
+    }
+
+    /// The box containing this one after transforming it by `matrix`, e.g. a model matrix.
+    pub fn transform(&self, matrix: &Matrix4<f32>) -> Self {
+        if self.is_empty() {
+            return *self;
+        }
+        Self::from_points(
+            self.corners()
+                .map(|c| Point3::from_homogeneous(matrix * c.to_homogeneous())),
+        )
+    }
+}
+
+/// A plane `normal . p + distance = 0` whose normal points into the frustum.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Plane {
+    pub normal: Vector3<f32>,
+    pub distance: f32,
+}
+
+impl Plane {
+    fn from_coefficients(v: Vector4<f32>) -> Self {
+        let length = v.truncate().magnitude();
+        Self {
+            normal: v.truncate() / length,
+            distance: v.w / length,
+        }
+    }
+
+    /// Signed distance to `p`, positive on the inside.
+    pub fn distance_to(&self, p: Point3<f32>) -> f32 {
+        self.normal.dot(p.to_vec()) + self.distance
+    }
+
+    /// The point shared by three planes, which mustn't be parallel.
+    fn intersection(a: Plane, b: Plane, c: Plane) -> Point3<f32> {
+        let bc = b.normal.cross(c.normal);
+        let p = (bc * -a.distance
+            - c.normal.cross(a.normal) * b.distance
+            - a.normal.cross(b.normal) * c.distance)
+            / a.normal.dot(bc);
+        Point3::from_vec(p)
+    }
+}
+
+/// The six planes bounding what a camera can see.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Frustum {
+    /// Left, right, bottom, top, near, far
+    pub planes: [Plane; 6],
+}
+
+impl Frustum {
+    /// Extracts the planes from a combined view-projection matrix with wgpu's 0..1 depth
+    /// range, such as `Projection::calc_matrix() * Camera::calc_matrix()`. With reverse-Z
+    /// the near and far planes trade places, which culls the same volume.
+    pub fn from_matrix(view_proj: Matrix4<f32>) -> Self {
+        let r = |i| view_proj.row(i);
+        Self {
+            planes: [
+                Plane::from_coefficients(r(3) + r(0)),
+                Plane::from_coefficients(r(3) - r(0)),
+                Plane::from_coefficients(r(3) + r(1)),
+                Plane::from_coefficients(r(3) - r(1)),
+                Plane::from_coefficients(r(2)),
+                Plane::from_coefficients(r(3) - r(2)),
+            ],
+        }
+    }
+
+    /// Whether any part of `aabb` may be visible. Boxes that straddle a corner of the frustum
+    /// can be reported as visible even though they aren't, which only costs a wasted draw.
+    pub fn intersects_aabb(&self, aabb: &Aabb) -> bool {
+        if aabb.is_empty() {
+            return false;
+        }
+        self.planes.iter().all(|plane| {
+            // The corner furthest along the plane normal
+            let p = Point3::new(
+                if plane.normal.x >= 0.0 {
+                    aabb.max.x
+                } else {
+                    aabb.min.x
+                },
+                if plane.normal.y >= 0.0 {
+                    aabb.max.y
+                } else {
+                    aabb.min.y
+                },
+                if plane.normal.z >= 0.0 {
+                    aabb.max.z
+                } else {
+                    aabb.min.z
+                },
+            );
+            plane.distance_to(p) >= 0.0
+        })
+    }
+
+    pub fn contains_point(&self, p: Point3<f32>) -> bool {
+        self.planes.iter().all(|plane| plane.distance_to(p) >= 0.0)
+    }
+
+    /// The eight corners where three planes meet, ordered like `Aabb::corners`: bit 0 of
+    /// the index picks left/right, bit 1 bottom/top and bit 2 near/far.
+    pub fn corners(&self) -> [Point3<f32>; 8] {
+        let [left, right, bottom, top, near, far] = self.planes;
+        std::array::from_fn(|i| {
+            let x = if i & 1 == 0 { left } else { right };
+            let y = if i & 2 == 0 { bottom } else { top };
+            let z = if i & 4 == 0 { near } else { far };
+            Plane::intersection(x, y, z)
+        })
+    }
+}
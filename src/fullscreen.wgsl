@@ -0,0 +1,17 @@
+// Vertex stage of the fullscreen passes, appended to their source by `wgsl!`: a single
+// triangle covering the whole target, drawn with `draw(0..3, 0..1)` and no vertex buffers.
+
+struct FullscreenOutput {
+  @builtin(position) pos: vec4<f32>,
+  // Position across the target, 0 to 1 from its top left corner
+  @location(0) uv: vec2<f32>,
+}
+
+@vertex
+fn vs_main(@builtin(vertex_index) id: u32) -> FullscreenOutput {
+  let uv = vec2<f32>(f32((id << 1u) & 2u), f32(id & 2u));
+  var out: FullscreenOutput;
+  out.pos = vec4<f32>(uv * vec2<f32>(2.0, -2.0) + vec2<f32>(-1.0, 1.0), 0.0, 1.0);
+  out.uv = uv;
+  return out;
+}
@@ -0,0 +1,166 @@
+// Fast approximate anti-aliasing of the tone mapped image with a single fullscreen
+// triangle: finds luma edges, walks along them to their ends and blends across them
+
+struct Fxaa {
+  // Set when the input is an sRGB format, which samples as linear, so luma gets
+  // estimated from gamma encoded colors either way
+  linear_input: u32,
+};
+
+// `vs_main` and `FullscreenOutput` are appended from `src/fullscreen.wgsl`
+
+@group(0) @binding(0)
+var t_color: texture_2d<f32>;
+@group(0) @binding(1)
+var s_color: sampler;
+@group(0) @binding(2)
+var<uniform> fxaa: Fxaa;
+
+// Edges with less contrast than this are left alone, relative to the brightest neighbor
+const EDGE_THRESHOLD: f32 = 0.125;
+// ...and in absolute terms, so dark areas aren't blurred
+const EDGE_THRESHOLD_MIN: f32 = 0.0312;
+// How much single-pixel features get smoothed, 0 off and 1 softest
+const SUBPIXEL_QUALITY: f32 = 0.75;
+const SEARCH_STEPS: i32 = 12;
+
+fn luma(color: vec3<f32>) -> f32 {
+  let encoded = select(color, sqrt(color), fxaa.linear_input != 0u);
+  return dot(encoded, vec3<f32>(0.299, 0.587, 0.114));
+}
+
+fn luma_at(uv: vec2<f32>) -> f32 {
+  return luma(textureSampleLevel(t_color, s_color, uv, 0.0).rgb);
+}
+
+// Texels to advance in search step `i`, taking larger strides further out
+fn step_size(i: i32) -> f32 {
+  if i < 5 {
+    return 1.0;
+  }
+  if i == 5 {
+    return 1.5;
+  }
+  if i < 10 {
+    return 2.0;
+  }
+  if i == 10 {
+    return 4.0;
+  }
+  return 8.0;
+}
+
+@fragment
+fn fs_main(in: FullscreenOutput) -> @location(0) vec4<f32> {
+  let texel = 1.0 / vec2<f32>(textureDimensions(t_color));
+  let center = textureSampleLevel(t_color, s_color, in.uv, 0.0);
+  let luma_center = luma(center.rgb);
+  let luma_up = luma_at(in.uv + vec2<f32>(0.0, -texel.y));
+  let luma_down = luma_at(in.uv + vec2<f32>(0.0, texel.y));
+  let luma_left = luma_at(in.uv + vec2<f32>(-texel.x, 0.0));
+  let luma_right = luma_at(in.uv + vec2<f32>(texel.x, 0.0));
+
+  let luma_min = min(luma_center, min(min(luma_up, luma_down), min(luma_left, luma_right)));
+  let luma_max = max(luma_center, max(max(luma_up, luma_down), max(luma_left, luma_right)));
+  let luma_range = luma_max - luma_min;
+  if luma_range < max(EDGE_THRESHOLD_MIN, luma_max * EDGE_THRESHOLD) {
+    return center;
+  }
+
+  let luma_up_left = luma_at(in.uv - texel);
+  let luma_down_right = luma_at(in.uv + texel);
+  let luma_up_right = luma_at(in.uv + vec2<f32>(texel.x, -texel.y));
+  let luma_down_left = luma_at(in.uv + vec2<f32>(-texel.x, texel.y));
+
+  let luma_up_down = luma_up + luma_down;
+  let luma_left_right = luma_left + luma_right;
+  let luma_left_corners = luma_up_left + luma_down_left;
+  let luma_right_corners = luma_up_right + luma_down_right;
+  let luma_up_corners = luma_up_left + luma_up_right;
+  let luma_down_corners = luma_down_left + luma_down_right;
+
+  // A horizontal edge changes most from row to row
+  let edge_horizontal = abs(-2.0 * luma_left + luma_left_corners)
+    + abs(-2.0 * luma_center + luma_up_down) * 2.0
+    + abs(-2.0 * luma_right + luma_right_corners);
+  let edge_vertical = abs(-2.0 * luma_up + luma_up_corners)
+    + abs(-2.0 * luma_center + luma_left_right) * 2.0
+    + abs(-2.0 * luma_down + luma_down_corners);
+  let is_horizontal = edge_horizontal >= edge_vertical;
+
+  // The neighbors across the edge, on the negative and positive side
+  let luma_negative = select(luma_left, luma_up, is_horizontal);
+  let luma_positive = select(luma_right, luma_down, is_horizontal);
+  let gradient_negative = luma_negative - luma_center;
+  let gradient_positive = luma_positive - luma_center;
+  let negative_steepest = abs(gradient_negative) >= abs(gradient_positive);
+  let gradient_scaled = 0.25 * max(abs(gradient_negative), abs(gradient_positive));
+
+  var step_length = select(texel.x, texel.y, is_horizontal);
+  var luma_local_average = 0.5 * (luma_positive + luma_center);
+  if negative_steepest {
+    step_length = -step_length;
+    luma_local_average = 0.5 * (luma_negative + luma_center);
+  }
+
+  // Walk both ways along the edge, half a texel across it, until the luma changes
+  var edge_uv = in.uv;
+  if is_horizontal {
+    edge_uv.y += step_length * 0.5;
+  } else {
+    edge_uv.x += step_length * 0.5;
+  }
+  let along = select(vec2<f32>(0.0, texel.y), vec2<f32>(texel.x, 0.0), is_horizontal);
+  var uv1 = edge_uv - along;
+  var uv2 = edge_uv + along;
+  var luma_end1 = 0.0;
+  var luma_end2 = 0.0;
+  var reached1 = false;
+  var reached2 = false;
+  for (var i = 0; i < SEARCH_STEPS; i++) {
+    if !reached1 {
+      luma_end1 = luma_at(uv1) - luma_local_average;
+      reached1 = abs(luma_end1) >= gradient_scaled;
+    }
+    if !reached2 {
+      luma_end2 = luma_at(uv2) - luma_local_average;
+      reached2 = abs(luma_end2) >= gradient_scaled;
+    }
+    if reached1 && reached2 {
+      break;
+    }
+    if !reached1 {
+      uv1 -= along * step_size(i + 1);
+    }
+    if !reached2 {
+      uv2 += along * step_size(i + 1);
+    }
+  }
+
+  let distance1 = select(in.uv.y - uv1.y, in.uv.x - uv1.x, is_horizontal);
+  let distance2 = select(uv2.y - in.uv.y, uv2.x - in.uv.x, is_horizontal);
+  let closer_to_1 = distance1 < distance2;
+  let edge_length = distance1 + distance2;
+  let pixel_offset = 0.5 - min(distance1, distance2) / edge_length;
+
+  // Only blend if the nearer end varies the opposite way from the center, otherwise the
+  // pixel is past the middle of a step in the edge
+  let center_darker = luma_center < luma_local_average;
+  let end_darker = select(luma_end2, luma_end1, closer_to_1) < 0.0;
+  var offset = select(0.0, pixel_offset, end_darker != center_darker);
+
+  // Isolated pixels have no edge to walk, so blur them by their local contrast instead
+  let luma_average = (2.0 * (luma_up_down + luma_left_right) + luma_left_corners
+    + luma_right_corners) / 12.0;
+  let subpixel = clamp(abs(luma_average - luma_center) / luma_range, 0.0, 1.0);
+  let subpixel_smooth = (-2.0 * subpixel + 3.0) * subpixel * subpixel;
+  offset = max(offset, subpixel_smooth * subpixel_smooth * SUBPIXEL_QUALITY);
+
+  var uv = in.uv;
+  if is_horizontal {
+    uv.y += offset * step_length;
+  } else {
+    uv.x += offset * step_length;
+  }
+  return textureSampleLevel(t_color, s_color, uv, 0.0);
+}
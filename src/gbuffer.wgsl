@@ -0,0 +1,200 @@
+// Geometry pass of deferred rendering: the vertex stage of shader.wgsl, writing the
+// surface attributes into the G-buffer instead of shading them, with `fs_velocity` also
+// the motion vectors like velocity.wgsl. Groups 2 to 5 of the shared layout go unused.
+
+struct VertexInput {
+  @location(0) position: vec3<f32>,
+  @location(1) tex_coords: vec2<f32>,
+  @location(2) normal: vec3<f32>,
+  @location(3) tangent: vec3<f32>,
+  @location(4) bitangent: vec3<f32>,
+  @location(12) joints: vec4<u32>,
+  @location(13) weights: vec4<f32>,
+  @location(14) color: vec4<f32>,
+};
+
+// `InstanceRaw::motion_desc`, as the normal matrix wouldn't fit in along with the
+// previous model matrix
+struct InstanceInput {
+  @location(5) model_matrix_0: vec4<f32>,
+  @location(6) model_matrix_1: vec4<f32>,
+  @location(7) model_matrix_2: vec4<f32>,
+  @location(8) model_matrix_3: vec4<f32>,
+  // Rows of the previous model matrix
+  @location(9) previous_0: vec4<f32>,
+  @location(10) previous_1: vec4<f32>,
+  @location(11) previous_2: vec4<f32>,
+};
+
+struct VertexOutput {
+  // Invariant like shader.wgsl's, so splatted terrain drawn over it sees the same depths
+  @builtin(position) @invariant pos: vec4<f32>,
+  @location(0) tex_coords: vec2<f32>,
+  @location(1) world_position: vec3<f32>,
+  @location(2) world_normal: vec3<f32>,
+  @location(3) world_tangent: vec3<f32>,
+  @location(4) world_bitangent: vec3<f32>,
+  @location(5) color: vec4<f32>,
+  // Clip positions this frame and the last, for `fs_velocity`
+  @location(6) current: vec4<f32>,
+  @location(7) previous: vec4<f32>,
+};
+
+struct GBufferOutput {
+  @location(0) albedo: vec4<f32>,
+  @location(1) normal: vec4<f32>,
+  // Relative to the camera, w is 1 wherever geometry was drawn
+  @location(2) position: vec4<f32>,
+};
+
+// `CameraUniform` and the other shared types are declared in `src/prelude.wgsl`
+
+@group(0) @binding(0)
+var t_diffuse: texture_2d<f32>;
+@group(0) @binding(1)
+var s_diffuse: sampler;
+@group(0) @binding(2)
+var t_normal: texture_2d<f32>;
+@group(0) @binding(3)
+var s_normal: sampler;
+
+@group(1) @binding(0)
+var<uniform> camera: CameraUniform;
+
+@group(6) @binding(0)
+var<storage, read> joint_matrices: array<mat4x4<f32>>;
+@group(6) @binding(1)
+var<storage, read> previous_joint_matrices: array<mat4x4<f32>>;
+
+const IDENTITY = mat4x4<f32>(
+  vec4<f32>(1.0, 0.0, 0.0, 0.0),
+  vec4<f32>(0.0, 1.0, 0.0, 0.0),
+  vec4<f32>(0.0, 0.0, 1.0, 0.0),
+  vec4<f32>(0.0, 0.0, 0.0, 1.0),
+);
+
+// Linear blend skinning. Vertices without weights aren't skinned.
+fn skin_matrix(joints: vec4<u32>, weights: vec4<f32>) -> mat4x4<f32> {
+  if dot(weights, vec4<f32>(1.0)) == 0.0 {
+    return IDENTITY;
+  }
+  return joint_matrices[joints.x] * weights.x
+    + joint_matrices[joints.y] * weights.y
+    + joint_matrices[joints.z] * weights.z
+    + joint_matrices[joints.w] * weights.w;
+}
+
+// `skin_matrix` in the previous frame's pose
+fn previous_skin_matrix(joints: vec4<u32>, weights: vec4<f32>) -> mat4x4<f32> {
+  if dot(weights, vec4<f32>(1.0)) == 0.0 {
+    return IDENTITY;
+  }
+  return previous_joint_matrices[joints.x] * weights.x
+    + previous_joint_matrices[joints.y] * weights.y
+    + previous_joint_matrices[joints.z] * weights.z
+    + previous_joint_matrices[joints.w] * weights.w;
+}
+
+// The inverse transpose of the upper 3x3 of `m` up to its scale, which normalizing the
+// normals cancels: the cofactor matrix, flipped with mirroring matrices
+fn normal_matrix_of(m: mat4x4<f32>) -> mat3x3<f32> {
+  let x = m[0].xyz;
+  let y = m[1].xyz;
+  let z = m[2].xyz;
+  let cofactor = mat3x3<f32>(cross(y, z), cross(z, x), cross(x, y));
+  return cofactor * sign(dot(x, cross(y, z)));
+}
+
+// How far a point moved on screen in UV units, from its clip positions this frame and
+// the last, see `CameraUniform::motion`
+fn motion(current: vec4<f32>, previous: vec4<f32>) -> vec2<f32> {
+  return (current.xy / current.w - previous.xy / previous.w) * vec2<f32>(0.5, -0.5);
+}
+
+@vertex
+fn vs_main(model: VertexInput, instance: InstanceInput) -> VertexOutput {
+  let model_matrix = mat4x4<f32>(
+    instance.model_matrix_0,
+    instance.model_matrix_1,
+    instance.model_matrix_2,
+    instance.model_matrix_3,
+  );
+  let normal_matrix = normal_matrix_of(model_matrix);
+  // Affine, so the bottom row is 0, 0, 0, 1
+  let previous_model_matrix = transpose(mat4x4<f32>(
+    instance.previous_0,
+    instance.previous_1,
+    instance.previous_2,
+    vec4<f32>(0.0, 0.0, 0.0, 1.0),
+  ));
+
+  let skin = skin_matrix(model.joints, model.weights);
+  let skin_normal = mat3x3<f32>(skin[0].xyz, skin[1].xyz, skin[2].xyz);
+
+  let world_position = model_matrix * skin * vec4<f32>(model.position, 1.0);
+  let previous_world_position = previous_model_matrix
+    * previous_skin_matrix(model.joints, model.weights)
+    * vec4<f32>(model.position, 1.0);
+  var out: VertexOutput;
+  out.pos = camera.view_proj * world_position;
+  out.current = out.pos;
+  out.previous = camera.prev_view_proj * previous_world_position;
+  out.tex_coords = model.tex_coords;
+  out.world_position = world_position.xyz;
+  out.world_normal = normalize(normal_matrix * skin_normal * model.normal);
+  out.world_tangent = normalize(normal_matrix * skin_normal * model.tangent);
+  out.world_bitangent = normalize(normal_matrix * skin_normal * model.bitangent);
+  out.color = model.color;
+  return out;
+}
+
+// Tangent space normal from a normal map texel. Two channel maps such as BC5 leave blue
+// at 0, as z follows from x and y for unit normals.
+fn unpack_normal(texel: vec3<f32>) -> vec3<f32> {
+  let xy = texel.xy * 2.0 - 1.0;
+  let z = sqrt(max(1.0 - dot(xy, xy), 0.0));
+  return vec3<f32>(xy, select(texel.z * 2.0 - 1.0, z, texel.z == 0.0));
+}
+
+// The surface attributes of the fragment
+fn gbuffer(in: VertexOutput) -> GBufferOutput {
+  let object_color = textureSample(t_diffuse, s_diffuse, in.tex_coords) * in.color;
+  let object_normal = textureSample(t_normal, s_normal, in.tex_coords);
+
+  let tangent_matrix = mat3x3<f32>(
+    normalize(in.world_tangent),
+    normalize(in.world_bitangent),
+    normalize(in.world_normal),
+  );
+  let normal = normalize(tangent_matrix * unpack_normal(object_normal.xyz));
+
+  var out: GBufferOutput;
+  out.albedo = object_color;
+  out.normal = vec4<f32>(normal, 0.0);
+  out.position = vec4<f32>(in.world_position - camera.view_pos.xyz, 1.0);
+  return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> GBufferOutput {
+  return gbuffer(in);
+}
+
+struct VelocityGBufferOutput {
+  @location(0) albedo: vec4<f32>,
+  @location(1) normal: vec4<f32>,
+  @location(2) position: vec4<f32>,
+  // `MotionVectors::velocity`
+  @location(3) velocity: vec2<f32>,
+};
+
+@fragment
+fn fs_velocity(in: VertexOutput) -> VelocityGBufferOutput {
+  let surface = gbuffer(in);
+  return VelocityGBufferOutput(
+    surface.albedo,
+    surface.normal,
+    surface.position,
+    motion(in.current, in.previous),
+  );
+}
@@ -0,0 +1,285 @@
+use cgmath::{InnerSpace, Point3, Vector2, Vector3};
+
+use crate::{camera::CameraUniform, debug_lines::LineVertex, hot_reload::wgsl, model::Vertex};
+
+/// One of the arrows of a `Gizmo`, moving along a world axis.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GizmoAxis {
+    X,
+    Y,
+    Z,
+}
+
+impl GizmoAxis {
+    pub const ALL: [GizmoAxis; 3] = [GizmoAxis::X, GizmoAxis::Y, GizmoAxis::Z];
+
+    pub fn direction(self) -> Vector3<f32> {
+        match self {
+            GizmoAxis::X => Vector3::unit_x(),
+            GizmoAxis::Y => Vector3::unit_y(),
+            GizmoAxis::Z => Vector3::unit_z(),
+        }
+    }
+
+    fn color(self) -> [f32; 4] {
+        match self {
+            GizmoAxis::X => [1.0, 0.2, 0.2, 1.0],
+            GizmoAxis::Y => [0.2, 1.0, 0.2, 1.0],
+            GizmoAxis::Z => [0.2, 0.4, 1.0, 1.0],
+        }
+    }
+}
+
+/// A translation gizmo: three arrows along the world axes at `position`, drawn over the
+/// scene without depth testing. `begin_drag` picks the arrow under the cursor, then
+/// `handle_drag` moves `position` along it by the cursor's motion, e.g. to place a light.
+pub struct Gizmo {
+    pub position: Point3<f32>,
+    /// Arrow length as a fraction of the distance to the camera, which keeps the gizmo the
+    /// same size on screen
+    pub screen_size: f32,
+    dragging: Option<GizmoAxis>,
+    /// Camera and output size of the last `update`, to pick and drag with
+    camera: CameraUniform,
+    viewport: Vector2<f32>,
+    /// World space arrow length of the last `update`
+    length: f32,
+    buffer: wgpu::Buffer,
+    pipeline: wgpu::RenderPipeline,
+}
+
+impl Gizmo {
+    /// Shaft plus four lines for the arrow head, per axis
+    const VERTEX_COUNT: usize = 3 * 5 * 2;
+    /// How close to an arrow, relative to its length, the cursor picks it
+    const PICK_RADIUS: f32 = 0.08;
+
+    pub fn new(
+        device: &wgpu::Device,
+        camera_bind_group_layout: &wgpu::BindGroupLayout,
+        color_format: wgpu::TextureFormat,
+        depth_format: Option<wgpu::TextureFormat>,
+        sample_count: u32,
+    ) -> Self {
+        let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Gizmo Buffer"),
+            size: (Self::VERTEX_COUNT * std::mem::size_of::<LineVertex>()) as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        Self {
+            position: Point3::new(0.0, 0.0, 0.0),
+            screen_size: 0.15,
+            dragging: None,
+            camera: CameraUniform::new(),
+            viewport: Vector2::new(1.0, 1.0),
+            length: 1.0,
+            buffer,
+            pipeline: Self::create_pipeline(
+                device,
+                camera_bind_group_layout,
+                color_format,
+                depth_format,
+                sample_count,
+            ),
+        }
+    }
+
+    /// Rebuilds the pipeline, e.g. after the MSAA sample count changed.
+    pub fn recreate_pipeline(
+        &mut self,
+        device: &wgpu::Device,
+        camera_bind_group_layout: &wgpu::BindGroupLayout,
+        color_format: wgpu::TextureFormat,
+        depth_format: Option<wgpu::TextureFormat>,
+        sample_count: u32,
+    ) {
+        self.pipeline = Self::create_pipeline(
+            device,
+            camera_bind_group_layout,
+            color_format,
+            depth_format,
+            sample_count,
+        );
+    }
+
+    fn create_pipeline(
+        device: &wgpu::Device,
+        camera_bind_group_layout: &wgpu::BindGroupLayout,
+        color_format: wgpu::TextureFormat,
+        depth_format: Option<wgpu::TextureFormat>,
+        sample_count: u32,
+    ) -> wgpu::RenderPipeline {
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Gizmo Pipeline Layout"),
+            bind_group_layouts: &[camera_bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let shader = device.create_shader_module(wgsl!("src/debug_lines.wgsl", "src/prelude.wgsl"));
+        device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Gizmo Pipeline"),
+            layout: Some(&layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: Some("vs_main"),
+                buffers: &[LineVertex::desc()],
+                compilation_options: Default::default(),
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: Some("fs_main"),
+                targets: &[Some(color_format.into())],
+                compilation_options: Default::default(),
+            }),
+            primitive: wgpu::PrimitiveState {
+                topology: wgpu::PrimitiveTopology::LineList,
+                ..Default::default()
+            },
+            // Always on top, so it can be grabbed even inside or behind geometry
+            depth_stencil: depth_format.map(|format| wgpu::DepthStencilState {
+                format,
+                depth_write_enabled: false,
+                depth_compare: wgpu::CompareFunction::Always,
+                stencil: wgpu::StencilState::default(),
+                bias: wgpu::DepthBiasState::default(),
+            }),
+            multisample: wgpu::MultisampleState {
+                count: sample_count,
+                ..Default::default()
+            },
+            multiview: None,
+            cache: None,
+        })
+    }
+
+    /// Sizes the arrows for `camera` and uploads them. `width` and `height` are the output
+    /// size the cursor positions given to `begin_drag` and `handle_drag` are in.
+    pub fn update(&mut self, queue: &wgpu::Queue, camera: &CameraUniform, width: u32, height: u32) {
+        self.camera = *camera;
+        self.viewport = Vector2::new(width.max(1) as f32, height.max(1) as f32);
+        self.length = (camera.position() - self.position).magnitude().max(0.01) * self.screen_size;
+
+        let mut vertices = Vec::with_capacity(Self::VERTEX_COUNT);
+        for axis in GizmoAxis::ALL {
+            let color = if self.dragging == Some(axis) {
+                [1.0, 1.0, 0.2, 1.0]
+            } else {
+                axis.color()
+            };
+            let direction = axis.direction();
+            let tip = self.position + direction * self.length;
+            let mut line = |from: Point3<f32>, to: Point3<f32>| {
+                vertices.extend([from, to].map(|p| LineVertex {
+                    position: p.into(),
+                    color,
+                }));
+            };
+            line(self.position, tip);
+            // The head is four lines around the shaft, in the two other axes' directions
+            let base = tip - direction * self.length * 0.2;
+            let side = Vector3::new(direction.y, direction.z, direction.x);
+            let up = Vector3::new(direction.z, direction.x, direction.y);
+            for offset in [side, -side, up, -up] {
+                line(tip, base + offset * self.length * 0.07);
+            }
+        }
+        queue.write_buffer(&self.buffer, 0, bytemuck::cast_slice(&vertices));
+    }
+
+    /// Starts dragging the arrow under `cursor`, in pixels of the output from its top left
+    /// corner. Returns the arrow, `None` if the cursor isn't near any.
+    pub fn begin_drag(&mut self, cursor: Vector2<f32>) -> Option<GizmoAxis> {
+        self.dragging = self.pick(cursor);
+        self.dragging
+    }
+
+    /// The arrow `cursor` points at, the nearest one if several are close.
+    pub fn pick(&self, cursor: Vector2<f32>) -> Option<GizmoAxis> {
+        let ndc = Vector2::new(
+            cursor.x / self.viewport.x * 2.0 - 1.0,
+            1.0 - cursor.y / self.viewport.y * 2.0,
+        );
+        // Any two depths give points on the ray through the pixel
+        let near = self.camera.unproject(Point3::new(ndc.x, ndc.y, 0.25));
+        let far = self.camera.unproject(Point3::new(ndc.x, ndc.y, 0.75));
+        let ray = (far - near).normalize();
+
+        GizmoAxis::ALL
+            .into_iter()
+            .map(|axis| {
+                let distance =
+                    line_segment_distance(near, ray, self.position, axis.direction(), self.length);
+                (axis, distance)
+            })
+            .filter(|(_, distance)| *distance < self.length * Self::PICK_RADIUS)
+            .min_by(|(_, a), (_, b)| a.total_cmp(b))
+            .map(|(axis, _)| axis)
+    }
+
+    /// The arrow being dragged, if any.
+    pub fn dragging(&self) -> Option<GizmoAxis> {
+        self.dragging
+    }
+
+    /// Moves `position` along the dragged arrow by as much as the cursor moved along the
+    /// arrow on screen, by `screen_delta` pixels, and returns the new position.
+    pub fn handle_drag(&mut self, screen_delta: Vector2<f32>) -> Point3<f32> {
+        let Some(axis) = self.dragging else {
+            return self.position;
+        };
+        // Pixels the arrow's direction covers on screen per world unit
+        let on_screen =
+            self.to_screen(self.position + axis.direction()) - self.to_screen(self.position);
+        let pixels_per_unit = on_screen.magnitude2();
+        // Pointing straight at the camera the arrow can't be dragged along
+        if pixels_per_unit > 1e-6 {
+            self.position += axis.direction() * (screen_delta.dot(on_screen) / pixels_per_unit);
+        }
+        self.position
+    }
+
+    pub fn end_drag(&mut self) {
+        self.dragging = None;
+    }
+
+    /// `point` in pixels of the output, like the cursor.
+    fn to_screen(&self, point: Point3<f32>) -> Vector2<f32> {
+        let clip = self.camera.view_proj() * point.to_homogeneous();
+        let ndc = clip.truncate() / clip.w;
+        Vector2::new(
+            (ndc.x + 1.0) / 2.0 * self.viewport.x,
+            (1.0 - ndc.y) / 2.0 * self.viewport.y,
+        )
+    }
+
+    /// Draws the arrows of the last `update`. Changes bind group 0 to the camera.
+    pub fn draw(&self, pass: &mut wgpu::RenderPass<'_>, camera_bind_group: &wgpu::BindGroup) {
+        pass.set_pipeline(&self.pipeline);
+        pass.set_bind_group(0, camera_bind_group, &[]);
+        pass.set_vertex_buffer(0, self.buffer.slice(..));
+        pass.draw(0..Self::VERTEX_COUNT as u32, 0..1);
+    }
+}
+
+/// Distance between the line through `origin` along the unit vector `ray` and the segment
+/// from `start` along the unit vector `direction` for `length`.
+fn line_segment_distance(
+    origin: Point3<f32>,
+    ray: Vector3<f32>,
+    start: Point3<f32>,
+    direction: Vector3<f32>,
+    length: f32,
+) -> f32 {
+    let offset = start - origin;
+    let alignment = ray.dot(direction);
+    let denominator = 1.0 - alignment * alignment;
+    // Parallel to the ray, every point of the segment is equally far from it
+    let along_segment = if denominator < 1e-6 {
+        0.0
+    } else {
+        ((alignment * offset.dot(ray) - offset.dot(direction)) / denominator).clamp(0.0, length)
+    };
+    let point = start + direction * along_segment;
+    let to_point = point - origin;
+    (to_point - ray * to_point.dot(ray)).magnitude()
+}
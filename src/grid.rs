@@ -0,0 +1,216 @@
+use bytemuck::Zeroable as _;
+use wgpu::util::DeviceExt as _;
+
+use crate::hot_reload::wgsl;
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
+struct GridUniform {
+    color: [f32; 4],
+    x_axis_color: [f32; 4],
+    z_axis_color: [f32; 4],
+    spacing: f32,
+    major_every: u32,
+    line_width: f32,
+    height: f32,
+    fade_distance: f32,
+    highlight_axes: u32,
+    _padding: [u32; 2],
+}
+
+/// An infinite grid on a horizontal plane for orienting the camera, see
+/// `Renderer::set_grid_visible`. A fullscreen triangle intersects each pixel's view ray
+/// with the plane, so the lines reach the horizon without any geometry, antialiased with
+/// `fwidth` and fading out with distance. Drawn before opaque geometry, testing and writing
+/// depth where there are lines, so the scene covers the grid above the plane and the grid
+/// covers it below.
+pub struct GridPlane {
+    /// World units between neighboring lines
+    pub spacing: f32,
+    /// Every `major_every`th line, counting from the axes, is drawn twice as wide. 0 draws
+    /// no major lines.
+    pub major_every: u32,
+    /// Width of the lines in pixels
+    pub line_width: f32,
+    /// Linear HDR color of the lines, alpha is how much they cover what's behind
+    pub color: [f32; 4],
+    /// Draws the axes over the grid in `x_axis_color` and `z_axis_color`
+    pub highlight_axes: bool,
+    pub x_axis_color: [f32; 4],
+    pub z_axis_color: [f32; 4],
+    /// World height of the plane
+    pub height: f32,
+    /// Distance from the camera along the plane where the grid has faded out
+    pub fade_distance: f32,
+    buffer: wgpu::Buffer,
+    bind_group_layout: wgpu::BindGroupLayout,
+    bind_group: wgpu::BindGroup,
+    pipeline: wgpu::RenderPipeline,
+}
+
+impl GridPlane {
+    pub fn new(
+        device: &wgpu::Device,
+        camera_bind_group_layout: &wgpu::BindGroupLayout,
+        color_format: wgpu::TextureFormat,
+        depth_format: wgpu::TextureFormat,
+        depth_compare: wgpu::CompareFunction,
+        sample_count: u32,
+    ) -> Self {
+        let buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Grid Buffer"),
+            // Filled by the first `upload`, until then nothing is drawn
+            contents: bytemuck::cast_slice(&[GridUniform::zeroed()]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            }],
+            label: Some("grid_bind_group_layout"),
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &bind_group_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: buffer.as_entire_binding(),
+            }],
+            label: Some("grid_bind_group"),
+        });
+        let pipeline = Self::create_pipeline(
+            device,
+            camera_bind_group_layout,
+            &bind_group_layout,
+            color_format,
+            depth_format,
+            depth_compare,
+            sample_count,
+        );
+        Self {
+            spacing: 1.0,
+            major_every: 10,
+            line_width: 1.0,
+            color: [0.5, 0.5, 0.5, 0.6],
+            highlight_axes: true,
+            x_axis_color: [0.9, 0.15, 0.15, 1.0],
+            z_axis_color: [0.15, 0.3, 0.9, 1.0],
+            height: 0.0,
+            fade_distance: 100.0,
+            buffer,
+            bind_group_layout,
+            bind_group,
+            pipeline,
+        }
+    }
+
+    /// Rebuilds the pipeline, e.g. after the MSAA sample count changed.
+    pub fn recreate_pipeline(
+        &mut self,
+        device: &wgpu::Device,
+        camera_bind_group_layout: &wgpu::BindGroupLayout,
+        color_format: wgpu::TextureFormat,
+        depth_format: wgpu::TextureFormat,
+        depth_compare: wgpu::CompareFunction,
+        sample_count: u32,
+    ) {
+        self.pipeline = Self::create_pipeline(
+            device,
+            camera_bind_group_layout,
+            &self.bind_group_layout,
+            color_format,
+            depth_format,
+            depth_compare,
+            sample_count,
+        );
+    }
+
+    fn create_pipeline(
+        device: &wgpu::Device,
+        camera_bind_group_layout: &wgpu::BindGroupLayout,
+        bind_group_layout: &wgpu::BindGroupLayout,
+        color_format: wgpu::TextureFormat,
+        depth_format: wgpu::TextureFormat,
+        depth_compare: wgpu::CompareFunction,
+        sample_count: u32,
+    ) -> wgpu::RenderPipeline {
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Grid Pipeline Layout"),
+            bind_group_layouts: &[camera_bind_group_layout, bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let shader = device.create_shader_module(wgsl!("src/grid.wgsl", "src/prelude.wgsl"));
+        device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Grid Pipeline"),
+            layout: Some(&layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: Some("vs_main"),
+                buffers: &[],
+                compilation_options: Default::default(),
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: Some("fs_main"),
+                targets: &[Some(wgpu::ColorTargetState {
+                    format: color_format,
+                    blend: Some(wgpu::BlendState::PREMULTIPLIED_ALPHA_BLENDING),
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+                compilation_options: Default::default(),
+            }),
+            primitive: wgpu::PrimitiveState::default(),
+            depth_stencil: Some(wgpu::DepthStencilState {
+                format: depth_format,
+                depth_write_enabled: true,
+                depth_compare,
+                stencil: wgpu::StencilState::default(),
+                bias: wgpu::DepthBiasState::default(),
+            }),
+            multisample: wgpu::MultisampleState {
+                count: sample_count,
+                ..Default::default()
+            },
+            multiview: None,
+            cache: None,
+        })
+    }
+
+    /// Uploads the public fields.
+    pub fn upload(&self, queue: &wgpu::Queue) {
+        queue.write_buffer(&self.buffer, 0, bytemuck::cast_slice(&[self.uniform()]));
+    }
+
+    fn uniform(&self) -> GridUniform {
+        GridUniform {
+            color: self.color,
+            x_axis_color: self.x_axis_color,
+            z_axis_color: self.z_axis_color,
+            spacing: self.spacing.max(0.0001),
+            major_every: self.major_every,
+            line_width: self.line_width.max(0.0),
+            height: self.height,
+            fade_distance: self.fade_distance.max(0.0001),
+            highlight_axes: self.highlight_axes.into(),
+            _padding: [0; 2],
+        }
+    }
+
+    /// Draws the grid as seen with `camera_bind_group`. Changes bind groups 0 and 1.
+    pub fn draw<'a>(
+        &'a self,
+        pass: &mut wgpu::RenderPass<'a>,
+        camera_bind_group: &'a wgpu::BindGroup,
+    ) {
+        pass.set_pipeline(&self.pipeline);
+        pass.set_bind_group(0, camera_bind_group, &[]);
+        pass.set_bind_group(1, &self.bind_group, &[]);
+        pass.draw(0..3, 0..1);
+    }
+}
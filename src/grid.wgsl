@@ -0,0 +1,97 @@
+// Infinite reference grid on a horizontal plane: a fullscreen triangle whose pixels cast
+// view rays at the plane and draw the grid lines around where they hit it
+
+// `CameraUniform` and the other shared types are declared in `src/prelude.wgsl`
+
+struct Grid {
+  color: vec4<f32>,
+  // Line along the x axis, where z is 0
+  x_axis_color: vec4<f32>,
+  // Line along the z axis, where x is 0
+  z_axis_color: vec4<f32>,
+  spacing: f32,
+  // Every nth line is a major line, none when 0
+  major_every: u32,
+  // In pixels
+  line_width: f32,
+  height: f32,
+  // Distance along the plane where the grid has faded out
+  fade_distance: f32,
+  highlight_axes: u32,
+};
+
+@group(0) @binding(0)
+var<uniform> camera: CameraUniform;
+@group(1) @binding(0)
+var<uniform> grid: Grid;
+
+struct VertexOutput {
+  @builtin(position) pos: vec4<f32>,
+  @location(0) ndc: vec2<f32>,
+};
+
+struct FragmentOutput {
+  @location(0) color: vec4<f32>,
+  @builtin(frag_depth) depth: f32,
+};
+
+@vertex
+fn vs_main(@builtin(vertex_index) id: u32) -> VertexOutput {
+  let uv = vec2<f32>(f32(id & 1u), f32((id >> 1u) & 1u));
+  var out: VertexOutput;
+  out.ndc = uv * 4.0 - 1.0;
+  out.pos = vec4<f32>(out.ndc, 0.0, 1.0);
+  return out;
+}
+
+fn unproject(ndc: vec2<f32>, depth: f32) -> vec3<f32> {
+  let view = camera.inv_proj * vec4<f32>(ndc, depth, 1.0);
+  return (camera.inv_view * vec4<f32>(view.xyz / view.w, 1.0)).xyz;
+}
+
+// How much the lines `spacing` apart cover the pixel at `coord`, fading over one pixel at
+// their edges, with major lines every `major_every` lines drawn twice as wide
+fn line_coverage(coord: vec2<f32>, spacing: f32, width: f32) -> f32 {
+  let cells = coord / spacing;
+  let distance = abs(fract(cells - 0.5) - 0.5) / max(fwidth(cells), vec2<f32>(0.0001));
+  let nearest = vec2<i32>(round(cells));
+  let every = max(i32(grid.major_every), 1);
+  let major = ((nearest % every) + every) % every == vec2<i32>(0) & vec2<bool>(grid.major_every > 0u);
+  let half_width = select(vec2<f32>(width), vec2<f32>(2.0 * width), major) * 0.5;
+  let coverage = 1.0 - smoothstep(half_width - 0.5, half_width + 0.5, distance);
+  return max(coverage.x, coverage.y);
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> FragmentOutput {
+  // The view ray between the near and far plane, whichever depth each is at
+  let a = unproject(in.ndc, 0.0);
+  let b = unproject(in.ndc, 1.0);
+  let s = (grid.height - a.y) / (b.y - a.y);
+  let hit = mix(a, b, s);
+
+  // Premultiplied, the axes laid over the grid
+  let grid_coverage = grid.color.a * line_coverage(hit.xz, grid.spacing, grid.line_width);
+  var color = vec4<f32>(grid.color.rgb, 1.0) * grid_coverage;
+  // As wide as major lines
+  if grid.highlight_axes != 0u {
+    let axes = abs(hit.zx) / max(fwidth(hit.zx), vec2<f32>(0.0001));
+    let width = vec2<f32>(grid.line_width);
+    let coverage = 1.0 - smoothstep(width - 0.5, width + 0.5, axes);
+    let x_axis = grid.x_axis_color.a * coverage.x;
+    color = vec4<f32>(grid.x_axis_color.rgb, 1.0) * x_axis + color * (1.0 - x_axis);
+    let z_axis = grid.z_axis_color.a * coverage.y;
+    color = vec4<f32>(grid.z_axis_color.rgb, 1.0) * z_axis + color * (1.0 - z_axis);
+  }
+  color *= 1.0 - smoothstep(0.0, grid.fade_distance, distance(hit.xz, camera.view_pos.xz));
+
+  let clip = camera.view_proj * vec4<f32>(hit, 1.0);
+  // Rays that miss the plane, and empty pixels, which shouldn't hide what's below
+  if s < 0.0 || s > 1.0 || b.y == a.y || color.a < 0.004 {
+    discard;
+  }
+  var out: FragmentOutput;
+  out.color = color;
+  out.depth = clip.z / clip.w;
+  return out;
+}
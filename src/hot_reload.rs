@@ -0,0 +1,174 @@
+//! Loading WGSL, embedded in the binary unless the `hot-reload` feature reads it from disk
+//! so edits show up without recompiling, see `ShaderWatcher`.
+
+/// Source of the WGSL files at `$path`s, relative to the crate root, concatenated into a
+/// `Cow<'static, str>`, e.g. a shader followed by the snippets it shares with others.
+/// Embedded with `include_str!`, or with the `hot-reload` feature read from disk, as of the
+/// last edit `stage` took in, falling back to the embedded copy if a file can't be read.
+macro_rules! wgsl_source {
+    ($($path:literal),+ $(,)?) => {{
+        let embedded = concat!($(include_str!(concat!(env!("CARGO_MANIFEST_DIR"), "/", $path))),+);
+        #[cfg(feature = "hot-reload")]
+        let source = $crate::hot_reload::read_source(&[$($path),+], embedded);
+        #[cfg(not(feature = "hot-reload"))]
+        let source = std::borrow::Cow::Borrowed(embedded);
+        source
+    }};
+}
+
+/// Like `wgpu::include_wgsl!`, but with paths relative to the crate root, concatenated and
+/// loaded with `wgsl_source!`. The module is labeled with the first path.
+macro_rules! wgsl {
+    ($path:literal $(, $snippet:literal)* $(,)?) => {
+        wgpu::ShaderModuleDescriptor {
+            label: Some($path),
+            source: wgpu::ShaderSource::Wgsl($crate::hot_reload::wgsl_source!($path $(, $snippet)*)),
+        }
+    };
+}
+
+pub(crate) use {wgsl, wgsl_source};
+
+#[cfg(feature = "hot-reload")]
+pub use watcher::*;
+
+#[cfg(feature = "hot-reload")]
+mod watcher {
+    use std::{
+        borrow::Cow,
+        collections::HashMap,
+        path::{Path, PathBuf},
+        sync::{LazyLock, Mutex, mpsc},
+    };
+
+    use notify::Watcher as _;
+
+    /// The contents of every WGSL file read so far, by its path relative to the crate root.
+    /// Pipelines are built from these rather than from disk, so an edit only reaches them
+    /// once `stage` took it in, and `restore` can take back one that broke a pipeline.
+    static SOURCES: LazyLock<Mutex<HashMap<String, String>>> = LazyLock::new(Default::default);
+
+    fn full_path(path: &str) -> PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR")).join(path)
+    }
+
+    /// The WGSL files at `paths`, relative to the crate root, concatenated, or `embedded`
+    /// if one of them can't be read.
+    pub(crate) fn read_source(paths: &[&str], embedded: &'static str) -> Cow<'static, str> {
+        let mut sources = SOURCES.lock().unwrap();
+        let mut source = String::new();
+        for &path in paths {
+            if !sources.contains_key(path) {
+                match std::fs::read_to_string(full_path(path)) {
+                    Ok(file) => {
+                        sources.insert(path.to_string(), file);
+                    }
+                    Err(e) => {
+                        log::warn!("Unable to read shader {path}, using the embedded copy: {e}");
+                        return Cow::Borrowed(embedded);
+                    }
+                }
+            }
+            source.push_str(&sources[path]);
+        }
+        Cow::Owned(source)
+    }
+
+    /// Reads the `changed` files from disk for the next pipelines built, returning the
+    /// previous contents of those that really differ, for `restore`. Files that can't be
+    /// read, e.g. while an editor replaces them, are left as they were.
+    pub fn stage(changed: &[String]) -> Vec<(String, Option<String>)> {
+        let mut sources = SOURCES.lock().unwrap();
+        let mut previous = Vec::new();
+        for path in changed {
+            let Ok(source) = std::fs::read_to_string(full_path(path)) else {
+                continue;
+            };
+            if sources.get(path) != Some(&source) {
+                previous.push((path.clone(), sources.insert(path.clone(), source)));
+            }
+        }
+        previous
+    }
+
+    /// Takes back the edits `stage` returned `previous` for.
+    pub fn restore(previous: Vec<(String, Option<String>)>) {
+        let mut sources = SOURCES.lock().unwrap();
+        for (path, source) in previous {
+            match source {
+                Some(source) => sources.insert(path, source),
+                None => sources.remove(&path),
+            };
+        }
+    }
+
+    /// Watches the WGSL files in the crate root and `src` for changes, native only.
+    pub struct ShaderWatcher {
+        /// Delivers its events to `events` until dropped, `None` if watching failed
+        _watcher: Option<notify::RecommendedWatcher>,
+        /// In a `Mutex` only so the renderer stays `Sync`, `changed` takes `&mut self`
+        events: Mutex<mpsc::Receiver<notify::Result<notify::Event>>>,
+    }
+
+    impl ShaderWatcher {
+        pub fn new() -> Self {
+            let (sender, events) = mpsc::channel();
+            let root = Path::new(env!("CARGO_MANIFEST_DIR"));
+            let watcher = notify::recommended_watcher(sender).and_then(|mut watcher| {
+                for dir in [root, &root.join("src")] {
+                    watcher.watch(dir, notify::RecursiveMode::NonRecursive)?;
+                }
+                Ok(watcher)
+            });
+            let watcher = watcher
+                .inspect_err(|e| log::warn!("Unable to watch the shaders for changes: {e}"))
+                .ok();
+            Self {
+                _watcher: watcher,
+                events: Mutex::new(events),
+            }
+        }
+
+        /// WGSL files created or modified since the last call, relative to the crate root,
+        /// e.g. `src/prelude.wgsl`.
+        pub fn changed(&mut self) -> Vec<String> {
+            let root = Path::new(env!("CARGO_MANIFEST_DIR"));
+            let mut changed = Vec::new();
+            for event in self.events.get_mut().unwrap().try_iter() {
+                let event = match event {
+                    Ok(event) => event,
+                    Err(e) => {
+                        log::warn!("Error watching the shaders: {e}");
+                        continue;
+                    }
+                };
+                if !(event.kind.is_create() || event.kind.is_modify()) {
+                    continue;
+                }
+                for path in event.paths {
+                    if path.extension().is_none_or(|extension| extension != "wgsl") {
+                        continue;
+                    }
+                    let Ok(relative) = path.strip_prefix(root) else {
+                        continue;
+                    };
+                    let relative = relative
+                        .components()
+                        .map(|component| component.as_os_str().to_string_lossy())
+                        .collect::<Vec<_>>()
+                        .join("/");
+                    if !changed.contains(&relative) {
+                        changed.push(relative);
+                    }
+                }
+            }
+            changed
+        }
+    }
+
+    impl Default for ShaderWatcher {
+        fn default() -> Self {
+            Self::new()
+        }
+    }
+}
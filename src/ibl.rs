@@ -0,0 +1,500 @@
+use wgpu::util::DeviceExt as _;
+
+use crate::{hot_reload::wgsl, texture};
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct PrefilterParams {
+    roughness: f32,
+    _padding: [f32; 3],
+}
+
+/// Precomputes the image-based lighting inputs of a PBR shader from an environment cubemap:
+/// a diffuse irradiance cube, a specular cube prefiltered per roughness into its mip levels
+/// and the split-sum BRDF lookup table.
+///
+/// All passes run one 16x16 workgroup per 16x16 tile of the output, so a face of `size`
+/// texels dispatches `size.div_ceil(16)` groups along x and y, with one z group per face.
+pub struct IblBaker {
+    cube_layout: wgpu::BindGroupLayout,
+    lut_layout: wgpu::BindGroupLayout,
+    sampler: wgpu::Sampler,
+    irradiance_pipeline: wgpu::ComputePipeline,
+    prefiltered_pipeline: wgpu::ComputePipeline,
+    brdf_lut_pipeline: wgpu::ComputePipeline,
+}
+
+impl IblBaker {
+    pub const FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba16Float;
+    /// Irradiance varies slowly, so a small cube is enough
+    pub const IRRADIANCE_SIZE: u32 = 32;
+    pub const PREFILTERED_SIZE: u32 = 128;
+    /// Mip `i` of the prefiltered cube holds roughness `i / (PREFILTERED_MIP_LEVELS - 1)`
+    pub const PREFILTERED_MIP_LEVELS: u32 = 5;
+    pub const BRDF_LUT_SIZE: u32 = 512;
+
+    pub fn new(device: &wgpu::Device) -> Self {
+        let cube_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Texture {
+                        multisampled: false,
+                        view_dimension: wgpu::TextureViewDimension::Cube,
+                        // The converted environment is Rgba32Float, which isn't filterable
+                        sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::NonFiltering),
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::StorageTexture {
+                        access: wgpu::StorageTextureAccess::WriteOnly,
+                        format: Self::FORMAT,
+                        view_dimension: wgpu::TextureViewDimension::D2Array,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 3,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ],
+            label: Some("ibl_cube_bind_group_layout"),
+        });
+
+        let lut_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 4,
+                visibility: wgpu::ShaderStages::COMPUTE,
+                ty: wgpu::BindingType::StorageTexture {
+                    access: wgpu::StorageTextureAccess::WriteOnly,
+                    format: Self::FORMAT,
+                    view_dimension: wgpu::TextureViewDimension::D2,
+                },
+                count: None,
+            }],
+            label: Some("ibl_lut_bind_group_layout"),
+        });
+
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("IBL Sampler"),
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            ..Default::default()
+        });
+
+        let shader_module = device.create_shader_module(wgsl!("src/ibl.wgsl"));
+
+        let cube_pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("IBL Cube Pipeline Layout"),
+            bind_group_layouts: &[&cube_layout],
+            push_constant_ranges: &[],
+        });
+        let lut_pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("BRDF LUT Pipeline Layout"),
+            bind_group_layouts: &[&lut_layout],
+            push_constant_ranges: &[],
+        });
+
+        let create_pipeline = |label, layout, entry_point| {
+            device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+                label: Some(label),
+                layout: Some(layout),
+                module: &shader_module,
+                entry_point: Some(entry_point),
+                compilation_options: Default::default(),
+                cache: None,
+            })
+        };
+        let irradiance_pipeline = create_pipeline(
+            "Irradiance Pipeline",
+            &cube_pipeline_layout,
+            "compute_irradiance",
+        );
+        let prefiltered_pipeline = create_pipeline(
+            "Prefiltered Environment Pipeline",
+            &cube_pipeline_layout,
+            "compute_prefiltered",
+        );
+        let brdf_lut_pipeline = create_pipeline(
+            "BRDF LUT Pipeline",
+            &lut_pipeline_layout,
+            "compute_brdf_lut",
+        );
+
+        Self {
+            cube_layout,
+            lut_layout,
+            sampler,
+            irradiance_pipeline,
+            prefiltered_pipeline,
+            brdf_lut_pipeline,
+        }
+    }
+
+    /// Convolves `env` over the hemisphere into a cosine-weighted diffuse irradiance cube.
+    pub fn build_irradiance(
+        &self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        env: &texture::CubeTexture,
+    ) -> texture::CubeTexture {
+        let dst = Self::create_cube(device, Self::IRRADIANCE_SIZE, 1, "Irradiance Cubemap");
+
+        let mut encoder = device.create_command_encoder(&Default::default());
+        self.dispatch_cube(
+            device,
+            &mut encoder,
+            &self.irradiance_pipeline,
+            env,
+            &dst,
+            0,
+            0.0,
+        );
+        queue.submit([encoder.finish()]);
+        dst
+    }
+
+    /// Convolves `env` with the GGX distribution into a cube whose mip levels hold
+    /// increasing roughness, sampled in the shader with `textureSampleLevel(.., roughness * max_mip)`.
+    pub fn build_prefiltered(
+        &self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        env: &texture::CubeTexture,
+    ) -> texture::CubeTexture {
+        let dst = Self::create_cube(
+            device,
+            Self::PREFILTERED_SIZE,
+            Self::PREFILTERED_MIP_LEVELS,
+            "Prefiltered Cubemap",
+        );
+
+        let mut encoder = device.create_command_encoder(&Default::default());
+        for mip_level in 0..Self::PREFILTERED_MIP_LEVELS {
+            let roughness = mip_level as f32 / (Self::PREFILTERED_MIP_LEVELS - 1) as f32;
+            self.dispatch_cube(
+                device,
+                &mut encoder,
+                &self.prefiltered_pipeline,
+                env,
+                &dst,
+                mip_level,
+                roughness,
+            );
+        }
+        queue.submit([encoder.finish()]);
+        dst
+    }
+
+    /// Integrates the split-sum BRDF into a 2D table indexed by `(n.v, roughness)`. Red is the
+    /// scale and green the bias applied to F0.
+    pub fn build_brdf_lut(&self, device: &wgpu::Device, queue: &wgpu::Queue) -> texture::Texture {
+        let lut = texture::Texture::create_texture(
+            device,
+            Some("BRDF LUT"),
+            wgpu::Extent3d {
+                width: Self::BRDF_LUT_SIZE,
+                height: Self::BRDF_LUT_SIZE,
+                depth_or_array_layers: 1,
+            },
+            Self::FORMAT,
+            wgpu::TextureUsages::STORAGE_BINDING | wgpu::TextureUsages::TEXTURE_BINDING,
+            wgpu::TextureDimension::D2,
+            wgpu::FilterMode::Linear,
+        );
+
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("BRDF LUT"),
+            layout: &self.lut_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 4,
+                resource: wgpu::BindingResource::TextureView(&lut.view),
+            }],
+        });
+
+        let mut encoder = device.create_command_encoder(&Default::default());
+        let mut pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+            label: Some("BRDF LUT"),
+            timestamp_writes: None,
+        });
+        let num_workgroups = Self::BRDF_LUT_SIZE.div_ceil(16);
+        pass.set_pipeline(&self.brdf_lut_pipeline);
+        pass.set_bind_group(0, &bind_group, &[]);
+        pass.dispatch_workgroups(num_workgroups, num_workgroups, 1);
+        drop(pass);
+        queue.submit([encoder.finish()]);
+        lut
+    }
+
+    fn create_cube(
+        device: &wgpu::Device,
+        size: u32,
+        mip_level_count: u32,
+        label: &str,
+    ) -> texture::CubeTexture {
+        texture::CubeTexture::create_2d(
+            device,
+            size,
+            Self::FORMAT,
+            mip_level_count,
+            wgpu::TextureUsages::STORAGE_BINDING | wgpu::TextureUsages::TEXTURE_BINDING,
+            wgpu::FilterMode::Linear,
+            Some(label),
+        )
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn dispatch_cube(
+        &self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        pipeline: &wgpu::ComputePipeline,
+        env: &texture::CubeTexture,
+        dst: &texture::CubeTexture,
+        mip_level: u32,
+        roughness: f32,
+    ) {
+        let dst_view = dst.texture().create_view(&wgpu::TextureViewDescriptor {
+            label: Some("IBL Target"),
+            dimension: Some(wgpu::TextureViewDimension::D2Array),
+            base_mip_level: mip_level,
+            mip_level_count: Some(1),
+            ..Default::default()
+        });
+        let params = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("IBL Params"),
+            contents: bytemuck::cast_slice(&[PrefilterParams {
+                roughness,
+                _padding: [0.0; 3],
+            }]),
+            usage: wgpu::BufferUsages::UNIFORM,
+        });
+
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("IBL"),
+            layout: &self.cube_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(env.view()),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(&self.sampler),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::TextureView(&dst_view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: params.as_entire_binding(),
+                },
+            ],
+        });
+
+        let mut pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+            label: Some("IBL"),
+            timestamp_writes: None,
+        });
+        let size = (dst.texture().width() >> mip_level).max(1);
+        let num_workgroups = size.div_ceil(16);
+        pass.set_pipeline(pipeline);
+        pass.set_bind_group(0, &bind_group, &[]);
+        pass.dispatch_workgroups(num_workgroups, num_workgroups, 6);
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct IblParams {
+    intensity: f32,
+    max_mip: f32,
+    _padding: [f32; 2],
+}
+
+/// The baked IBL maps bound together for the PBR shader. An environment with an intensity
+/// of 0 makes the shader fall back to a small constant ambient term.
+pub struct IblEnvironment {
+    pub irradiance: texture::CubeTexture,
+    pub prefiltered: texture::CubeTexture,
+    pub brdf_lut: texture::Texture,
+    params: IblParams,
+    buffer: wgpu::Buffer,
+    pub bind_group: wgpu::BindGroup,
+}
+
+impl IblEnvironment {
+    pub fn create_bind_group_layout(device: &wgpu::Device) -> wgpu::BindGroupLayout {
+        let cube = |binding| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Texture {
+                multisampled: false,
+                view_dimension: wgpu::TextureViewDimension::Cube,
+                sample_type: wgpu::TextureSampleType::Float { filterable: true },
+            },
+            count: None,
+        };
+        let sampler = |binding| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+            count: None,
+        };
+        device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                cube(0),
+                cube(1),
+                sampler(2),
+                wgpu::BindGroupLayoutEntry {
+                    binding: 3,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        multisampled: false,
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                    },
+                    count: None,
+                },
+                sampler(4),
+                wgpu::BindGroupLayoutEntry {
+                    binding: 5,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ],
+            label: Some("ibl_bind_group_layout"),
+        })
+    }
+
+    /// Bakes all three maps from the environment cubemap `env`.
+    pub fn bake(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        baker: &IblBaker,
+        env: &texture::CubeTexture,
+        layout: &wgpu::BindGroupLayout,
+    ) -> Self {
+        Self::new(
+            device,
+            baker.build_irradiance(device, queue, env),
+            baker.build_prefiltered(device, queue, env),
+            baker.build_brdf_lut(device, queue),
+            1.0,
+            layout,
+        )
+    }
+
+    /// No image-based lighting, for scenes without an environment map.
+    pub fn empty(device: &wgpu::Device, layout: &wgpu::BindGroupLayout) -> Self {
+        // Zero-initialized 1x1 maps, never contributing since the intensity is 0
+        let cube = || IblBaker::create_cube(device, 1, 1, "Empty IBL Cubemap");
+        let brdf_lut = texture::Texture::create_texture(
+            device,
+            Some("Empty BRDF LUT"),
+            wgpu::Extent3d {
+                width: 1,
+                height: 1,
+                depth_or_array_layers: 1,
+            },
+            IblBaker::FORMAT,
+            wgpu::TextureUsages::TEXTURE_BINDING,
+            wgpu::TextureDimension::D2,
+            wgpu::FilterMode::Linear,
+        );
+        Self::new(device, cube(), cube(), brdf_lut, 0.0, layout)
+    }
+
+    pub fn new(
+        device: &wgpu::Device,
+        irradiance: texture::CubeTexture,
+        prefiltered: texture::CubeTexture,
+        brdf_lut: texture::Texture,
+        intensity: f32,
+        layout: &wgpu::BindGroupLayout,
+    ) -> Self {
+        let params = IblParams {
+            intensity,
+            max_mip: (prefiltered.texture().mip_level_count() - 1) as f32,
+            _padding: [0.0; 2],
+        };
+        let buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("IBL Buffer"),
+            contents: bytemuck::cast_slice(&[params]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(irradiance.view()),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(prefiltered.view()),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    // Filters linearly between the roughness mips
+                    resource: wgpu::BindingResource::Sampler(prefiltered.sampler()),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: wgpu::BindingResource::TextureView(&brdf_lut.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 4,
+                    resource: wgpu::BindingResource::Sampler(&brdf_lut.sampler),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 5,
+                    resource: buffer.as_entire_binding(),
+                },
+            ],
+            label: Some("ibl_bind_group"),
+        });
+
+        Self {
+            irradiance,
+            prefiltered,
+            brdf_lut,
+            params,
+            buffer,
+            bind_group,
+        }
+    }
+
+    pub fn intensity(&self) -> f32 {
+        self.params.intensity
+    }
+
+    /// Scales the ambient light from the environment, 0 turns it off.
+    pub fn set_intensity(&mut self, queue: &wgpu::Queue, intensity: f32) {
+        self.params.intensity = intensity.max(0.0);
+        queue.write_buffer(&self.buffer, 0, bytemuck::cast_slice(&[self.params]));
+    }
+}
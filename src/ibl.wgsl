@@ -0,0 +1,164 @@
+// Image-based lighting precomputation: diffuse irradiance, GGX-prefiltered specular
+// and the split-sum BRDF lookup table
+
+const PI: f32 = 3.1415926535897932384626433832795;
+
+struct Params {
+    roughness: f32,
+    _padding0: f32,
+    _padding1: f32,
+    _padding2: f32,
+}
+
+@group(0) @binding(0)
+var env_map: texture_cube<f32>;
+@group(0) @binding(1)
+var env_sampler: sampler;
+@group(0) @binding(2)
+var dst: texture_storage_2d_array<rgba16float, write>;
+@group(0) @binding(3)
+var<uniform> params: Params;
+
+// Direction through the center of texel `gid.xy` on cube face `gid.z`, following the
+// hardware cube-map convention so sampling the result by direction looks up this texel
+fn cube_direction(gid: vec3<u32>, size: vec2<u32>) -> vec3<f32> {
+    let uv = (vec2<f32>(gid.xy) + 0.5) / vec2<f32>(size) * 2.0 - 1.0;
+    var dir: vec3<f32>;
+    switch gid.z {
+        case 0u: { dir = vec3(1.0, -uv.y, -uv.x); }
+        case 1u: { dir = vec3(-1.0, -uv.y, uv.x); }
+        case 2u: { dir = vec3(uv.x, 1.0, uv.y); }
+        case 3u: { dir = vec3(uv.x, -1.0, -uv.y); }
+        case 4u: { dir = vec3(uv.x, -uv.y, 1.0); }
+        default: { dir = vec3(-uv.x, -uv.y, -1.0); }
+    }
+    return normalize(dir);
+}
+
+// Orthonormal basis with `n` as the z axis
+fn tangent_frame(n: vec3<f32>) -> mat3x3<f32> {
+    let up = select(vec3(0.0, 1.0, 0.0), vec3(1.0, 0.0, 0.0), abs(n.y) > 0.999);
+    let tangent = normalize(cross(up, n));
+    let bitangent = cross(n, tangent);
+    return mat3x3(tangent, bitangent, n);
+}
+
+fn hammersley(i: u32, count: u32) -> vec2<f32> {
+    return vec2(f32(i) / f32(count), f32(reverseBits(i)) * 2.3283064365386963e-10);
+}
+
+// GGX-distributed half vector around the z axis
+fn importance_sample_ggx(xi: vec2<f32>, roughness: f32) -> vec3<f32> {
+    let a = roughness * roughness;
+    let phi = 2.0 * PI * xi.x;
+    let cos_theta = sqrt((1.0 - xi.y) / (1.0 + (a * a - 1.0) * xi.y));
+    let sin_theta = sqrt(1.0 - cos_theta * cos_theta);
+    return vec3(cos(phi) * sin_theta, sin(phi) * sin_theta, cos_theta);
+}
+
+@compute
+@workgroup_size(16, 16, 1)
+fn compute_irradiance(@builtin(global_invocation_id) gid: vec3<u32>) {
+    let size = textureDimensions(dst);
+    if gid.x >= size.x || gid.y >= size.y {
+        return;
+    }
+
+    let frame = tangent_frame(cube_direction(gid, size));
+
+    // Riemann sum over the hemisphere, weighting each sample by cos(theta) * sin(theta)
+    let phi_steps = 128u;
+    let theta_steps = 32u;
+    var irradiance = vec3(0.0);
+    for (var i = 0u; i < phi_steps; i++) {
+        let phi = (f32(i) + 0.5) / f32(phi_steps) * 2.0 * PI;
+        for (var j = 0u; j < theta_steps; j++) {
+            let theta = (f32(j) + 0.5) / f32(theta_steps) * 0.5 * PI;
+            let local = vec3(sin(theta) * cos(phi), sin(theta) * sin(phi), cos(theta));
+            let color = textureSampleLevel(env_map, env_sampler, frame * local, 0.0).rgb;
+            irradiance += color * cos(theta) * sin(theta);
+        }
+    }
+    irradiance = PI * irradiance / f32(phi_steps * theta_steps);
+
+    textureStore(dst, gid.xy, gid.z, vec4(irradiance, 1.0));
+}
+
+@compute
+@workgroup_size(16, 16, 1)
+fn compute_prefiltered(@builtin(global_invocation_id) gid: vec3<u32>) {
+    let size = textureDimensions(dst);
+    if gid.x >= size.x || gid.y >= size.y {
+        return;
+    }
+
+    // Assume the view direction equals the reflection direction equals the normal
+    let n = cube_direction(gid, size);
+    let frame = tangent_frame(n);
+
+    let sample_count = 512u;
+    var color = vec3(0.0);
+    var total_weight = 0.0;
+    for (var i = 0u; i < sample_count; i++) {
+        let h = frame * importance_sample_ggx(hammersley(i, sample_count), params.roughness);
+        let l = normalize(2.0 * dot(n, h) * h - n);
+        let n_dot_l = dot(n, l);
+        if n_dot_l > 0.0 {
+            color += textureSampleLevel(env_map, env_sampler, l, 0.0).rgb * n_dot_l;
+            total_weight += n_dot_l;
+        }
+    }
+    color /= max(total_weight, 0.0001);
+
+    textureStore(dst, gid.xy, gid.z, vec4(color, 1.0));
+}
+
+// Kept off the bindings above so every resource in this file has its own slot
+@group(0) @binding(4)
+var lut: texture_storage_2d<rgba16float, write>;
+
+// Schlick-GGX with the k = a^2 / 2 remapping used for image-based lighting
+fn geometry_smith(n_dot_v: f32, n_dot_l: f32, roughness: f32) -> f32 {
+    let k = roughness * roughness / 2.0;
+    let ggx_v = n_dot_v / (n_dot_v * (1.0 - k) + k);
+    let ggx_l = n_dot_l / (n_dot_l * (1.0 - k) + k);
+    return ggx_v * ggx_l;
+}
+
+// x: n.v, y: roughness. Stores the scale (r) and bias (g) applied to F0
+@compute
+@workgroup_size(16, 16, 1)
+fn compute_brdf_lut(@builtin(global_invocation_id) gid: vec3<u32>) {
+    let size = textureDimensions(lut);
+    if gid.x >= size.x || gid.y >= size.y {
+        return;
+    }
+
+    let uv = (vec2<f32>(gid.xy) + 0.5) / vec2<f32>(size);
+    let n_dot_v = uv.x;
+    let roughness = uv.y;
+    let v = vec3(sqrt(1.0 - n_dot_v * n_dot_v), 0.0, n_dot_v);
+
+    let sample_count = 512u;
+    var scale = 0.0;
+    var bias = 0.0;
+    for (var i = 0u; i < sample_count; i++) {
+        let h = importance_sample_ggx(hammersley(i, sample_count), roughness);
+        let l = normalize(2.0 * dot(v, h) * h - v);
+        let n_dot_l = max(l.z, 0.0);
+        let n_dot_h = max(h.z, 0.0);
+        let v_dot_h = max(dot(v, h), 0.0);
+        if n_dot_l > 0.0 {
+            let g = geometry_smith(n_dot_v, n_dot_l, roughness);
+            let g_vis = g * v_dot_h / (n_dot_h * n_dot_v);
+            let fc = pow(1.0 - v_dot_h, 5.0);
+            scale += (1.0 - fc) * g_vis;
+            bias += fc * g_vis;
+        }
+    }
+
+    scale /= f32(sample_count);
+    bias /= f32(sample_count);
+
+    textureStore(lut, gid.xy, vec4(scale, bias, 0.0, 1.0));
+}
@@ -0,0 +1,160 @@
+use cgmath::{Matrix4, Quaternion, Vector3};
+use wgpu::util::DeviceExt as _;
+
+use crate::{model::Vertex, transform::Transform};
+
+/// Placement of one copy of a model, uploaded as per-instance vertex data.
+#[derive(Debug, Clone, Copy)]
+pub struct Instance {
+    pub position: Vector3<f32>,
+    pub rotation: Quaternion<f32>,
+    pub scale: Vector3<f32>,
+}
+
+impl Instance {
+    pub fn new(position: Vector3<f32>, rotation: Quaternion<f32>) -> Self {
+        Self {
+            position,
+            rotation,
+            scale: Vector3::new(1.0, 1.0, 1.0),
+        }
+    }
+
+    pub fn with_scale(mut self, scale: Vector3<f32>) -> Self {
+        self.scale = scale;
+        self
+    }
+
+    pub fn to_raw(&self) -> InstanceRaw {
+        Transform::from(*self).to_raw()
+    }
+}
+
+impl Default for Instance {
+    fn default() -> Self {
+        Self::new(
+            Vector3::new(0.0, 0.0, 0.0),
+            Quaternion::new(1.0, 0.0, 0.0, 0.0),
+        )
+    }
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct InstanceRaw {
+    pub model: [[f32; 4]; 4],
+    pub normal: [[f32; 3]; 3],
+    /// The top three rows of the previous frame's model matrix, for motion vectors. Model
+    /// matrices are affine, so the bottom row is always 0, 0, 0, 1.
+    pub previous: [[f32; 4]; 3],
+}
+
+impl InstanceRaw {
+    /// Sets where the instance was the previous frame, `previous` being its model matrix
+    /// then. `Instance::to_raw` leaves it where it is now.
+    pub fn moved_from(mut self, previous: Matrix4<f32>) -> Self {
+        self.previous = affine_rows(previous.into());
+        self
+    }
+
+    /// Layout of the model matrix (locations 5 to 8) and the previous one's rows (9 to 11)
+    /// in place of the normal matrix, for the pipelines writing `MotionVectors::velocity`.
+    /// Along with `ModelVertex` that stays within the 16 vertex attributes every device
+    /// supports.
+    pub fn motion_desc() -> wgpu::VertexBufferLayout<'static> {
+        use std::mem;
+        const ATTRIBUTES: [wgpu::VertexAttribute; 7] = {
+            let vec4 = mem::size_of::<[f32; 4]>() as wgpu::BufferAddress;
+            let previous = mem::offset_of!(InstanceRaw, previous) as wgpu::BufferAddress;
+            const fn attribute(
+                offset: wgpu::BufferAddress,
+                shader_location: u32,
+            ) -> wgpu::VertexAttribute {
+                wgpu::VertexAttribute {
+                    offset,
+                    shader_location,
+                    format: wgpu::VertexFormat::Float32x4,
+                }
+            }
+            [
+                attribute(0, 5),
+                attribute(vec4, 6),
+                attribute(2 * vec4, 7),
+                attribute(3 * vec4, 8),
+                attribute(previous, 9),
+                attribute(previous + vec4, 10),
+                attribute(previous + 2 * vec4, 11),
+            ]
+        };
+        wgpu::VertexBufferLayout {
+            array_stride: mem::size_of::<InstanceRaw>() as wgpu::BufferAddress,
+            step_mode: wgpu::VertexStepMode::Instance,
+            attributes: &ATTRIBUTES,
+        }
+    }
+}
+
+/// The first three rows of the column-major `matrix`.
+pub(crate) fn affine_rows(matrix: [[f32; 4]; 4]) -> [[f32; 4]; 3] {
+    std::array::from_fn(|row| matrix.map(|column| column[row]))
+}
+
+impl Vertex for InstanceRaw {
+    fn desc() -> wgpu::VertexBufferLayout<'static> {
+        use std::mem;
+        wgpu::VertexBufferLayout {
+            array_stride: mem::size_of::<InstanceRaw>() as wgpu::BufferAddress,
+            step_mode: wgpu::VertexStepMode::Instance,
+            attributes: &[
+                wgpu::VertexAttribute {
+                    offset: 0,
+                    // While our vertex shader only uses locations 0, and 1 now, in later tutorials, we'll
+                    // be using 2, 3, and 4, for Vertex. We'll start at slot 5, not conflict with them later
+                    shader_location: 5,
+                    format: wgpu::VertexFormat::Float32x4,
+                },
+                wgpu::VertexAttribute {
+                    offset: mem::size_of::<[f32; 4]>() as wgpu::BufferAddress,
+                    shader_location: 6,
+                    format: wgpu::VertexFormat::Float32x4,
+                },
+                wgpu::VertexAttribute {
+                    offset: mem::size_of::<[f32; 8]>() as wgpu::BufferAddress,
+                    shader_location: 7,
+                    format: wgpu::VertexFormat::Float32x4,
+                },
+                wgpu::VertexAttribute {
+                    offset: mem::size_of::<[f32; 12]>() as wgpu::BufferAddress,
+                    shader_location: 8,
+                    format: wgpu::VertexFormat::Float32x4,
+                },
+                wgpu::VertexAttribute {
+                    offset: mem::size_of::<[f32; 16]>() as wgpu::BufferAddress,
+                    shader_location: 9,
+                    format: wgpu::VertexFormat::Float32x3,
+                },
+                wgpu::VertexAttribute {
+                    offset: mem::size_of::<[f32; 19]>() as wgpu::BufferAddress,
+                    shader_location: 10,
+                    format: wgpu::VertexFormat::Float32x3,
+                },
+                wgpu::VertexAttribute {
+                    offset: mem::size_of::<[f32; 22]>() as wgpu::BufferAddress,
+                    shader_location: 11,
+                    format: wgpu::VertexFormat::Float32x3,
+                },
+            ],
+        }
+    }
+}
+
+/// Creates a vertex buffer holding `instances`, bound at slot 1 when drawing.
+/// It can be rewritten later with `queue.write_buffer` as long as the count doesn't grow.
+pub fn create_instance_buffer(device: &wgpu::Device, instances: &[Instance]) -> wgpu::Buffer {
+    let instance_data = instances.iter().map(Instance::to_raw).collect::<Vec<_>>();
+    device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+        label: Some("instance buffer"),
+        contents: bytemuck::cast_slice(&instance_data),
+        usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+    })
+}
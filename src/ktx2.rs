@@ -0,0 +1,696 @@
+//! Reading KTX2 texture containers holding GPU-compressed mip chains, and decoding the
+//! BC formats to RGBA8 on the CPU for adapters that can't sample them.
+
+use anyhow::Context as _;
+
+const IDENTIFIER: [u8; 12] = [
+    0xAB, 0x4B, 0x54, 0x58, 0x20, 0x32, 0x30, 0xBB, 0x0D, 0x0A, 0x1A, 0x0A,
+];
+/// Identifier, header and index, after which the level index starts
+const LEVEL_INDEX_OFFSET: usize = 80;
+
+/// A 2D texture read from a KTX2 file, borrowing its levels from the file's bytes.
+pub struct Ktx2Image<'a> {
+    pub format: wgpu::TextureFormat,
+    pub width: u32,
+    pub height: u32,
+    /// Data of each mip level, largest first, tightly packed rows of blocks
+    pub levels: Vec<&'a [u8]>,
+}
+
+impl<'a> Ktx2Image<'a> {
+    /// Reads the header and mip levels of a KTX2 file. Only single 2D images without
+    /// supercompression are supported. Basis Universal files (ETC1S or UASTC) need
+    /// transcoding into a GPU format first, which this doesn't do.
+    pub fn parse(bytes: &'a [u8]) -> anyhow::Result<Self> {
+        if bytes.get(..IDENTIFIER.len()) != Some(&IDENTIFIER[..]) {
+            anyhow::bail!("not a KTX2 file");
+        }
+        let u32_at = |offset: usize| -> anyhow::Result<u32> {
+            let field = bytes
+                .get(offset..offset + 4)
+                .context("KTX2 file is truncated")?;
+            Ok(u32::from_le_bytes(field.try_into()?))
+        };
+        let u64_at = |offset: usize| -> anyhow::Result<u64> {
+            let field = bytes
+                .get(offset..offset + 8)
+                .context("KTX2 file is truncated")?;
+            Ok(u64::from_le_bytes(field.try_into()?))
+        };
+
+        let vk_format = u32_at(12)?;
+        let width = u32_at(20)?;
+        let height = u32_at(24)?;
+        let depth = u32_at(28)?;
+        let layers = u32_at(32)?;
+        let faces = u32_at(36)?;
+        // Zero asks the loader to generate the mips, there's only the base level then
+        let level_count = u32_at(40)?.max(1);
+        let supercompression = u32_at(44)?;
+
+        if vk_format == 0 || supercompression == 1 {
+            anyhow::bail!("Basis Universal KTX2 files need transcoding, which isn't supported");
+        }
+        if supercompression != 0 {
+            anyhow::bail!("KTX2 supercompression scheme {supercompression} isn't supported");
+        }
+        if height == 0 || depth > 1 || layers > 1 || faces != 1 {
+            anyhow::bail!("only single 2D KTX2 images are supported");
+        }
+        let format = vk_format_to_wgpu(vk_format)
+            .with_context(|| format!("KTX2 format {vk_format} isn't supported"))?;
+
+        let levels = (0..level_count as usize)
+            .map(|level| {
+                let entry = LEVEL_INDEX_OFFSET + level * 24;
+                let offset = usize::try_from(u64_at(entry)?)?;
+                let length = usize::try_from(u64_at(entry + 8)?)?;
+                bytes
+                    .get(offset..offset + length)
+                    .with_context(|| format!("KTX2 mip level {level} is out of bounds"))
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+
+        Ok(Self {
+            format,
+            width,
+            height,
+            levels,
+        })
+    }
+}
+
+/// The wgpu equivalent of a Vulkan format KTX2 files store textures in.
+fn vk_format_to_wgpu(vk_format: u32) -> Option<wgpu::TextureFormat> {
+    use wgpu::{AstcBlock, AstcChannel, TextureFormat as F};
+
+    const ASTC_BLOCKS: [AstcBlock; 14] = [
+        AstcBlock::B4x4,
+        AstcBlock::B5x4,
+        AstcBlock::B5x5,
+        AstcBlock::B6x5,
+        AstcBlock::B6x6,
+        AstcBlock::B8x5,
+        AstcBlock::B8x6,
+        AstcBlock::B8x8,
+        AstcBlock::B10x5,
+        AstcBlock::B10x6,
+        AstcBlock::B10x8,
+        AstcBlock::B10x10,
+        AstcBlock::B12x10,
+        AstcBlock::B12x12,
+    ];
+    Some(match vk_format {
+        37 => F::Rgba8Unorm,
+        43 => F::Rgba8UnormSrgb,
+        131 | 133 => F::Bc1RgbaUnorm,
+        132 | 134 => F::Bc1RgbaUnormSrgb,
+        135 => F::Bc2RgbaUnorm,
+        136 => F::Bc2RgbaUnormSrgb,
+        137 => F::Bc3RgbaUnorm,
+        138 => F::Bc3RgbaUnormSrgb,
+        139 => F::Bc4RUnorm,
+        140 => F::Bc4RSnorm,
+        141 => F::Bc5RgUnorm,
+        142 => F::Bc5RgSnorm,
+        145 => F::Bc7RgbaUnorm,
+        146 => F::Bc7RgbaUnormSrgb,
+        // UNORM and SRGB alternate for each block size
+        157..=184 => F::Astc {
+            block: ASTC_BLOCKS[(vk_format - 157) as usize / 2],
+            channel: if vk_format % 2 == 1 {
+                AstcChannel::Unorm
+            } else {
+                AstcChannel::UnormSrgb
+            },
+        },
+        _ => return None,
+    })
+}
+
+/// Decodes one `width` x `height` level of a BC compressed `format` into RGBA8 rows, the
+/// way a GPU would sample it. Fails for formats without a CPU decoder, such as ASTC.
+pub fn decode_to_rgba8(
+    format: wgpu::TextureFormat,
+    width: u32,
+    height: u32,
+    data: &[u8],
+) -> anyhow::Result<Vec<u8>> {
+    use wgpu::TextureFormat as F;
+
+    let decode_block: fn(&[u8]) -> [[u8; 4]; 16] = match format {
+        F::Rgba8Unorm | F::Rgba8UnormSrgb => return Ok(data.to_vec()),
+        F::Bc1RgbaUnorm | F::Bc1RgbaUnormSrgb => |block| decode_bc1(block, false),
+        F::Bc2RgbaUnorm | F::Bc2RgbaUnormSrgb => decode_bc2,
+        F::Bc3RgbaUnorm | F::Bc3RgbaUnormSrgb => decode_bc3,
+        F::Bc4RUnorm => decode_bc4_block,
+        F::Bc5RgUnorm => decode_bc5,
+        F::Bc7RgbaUnorm | F::Bc7RgbaUnormSrgb => decode_bc7,
+        _ => anyhow::bail!("{format:?} can't be decoded on the CPU"),
+    };
+    let block_size = format.block_copy_size(None).unwrap_or(16) as usize;
+    let (blocks_x, blocks_y) = (width.div_ceil(4) as usize, height.div_ceil(4) as usize);
+    if data.len() < blocks_x * blocks_y * block_size {
+        anyhow::bail!("{format:?} level data is too short for {width}x{height}");
+    }
+
+    let (width, height) = (width as usize, height as usize);
+    let mut rgba = vec![0; width * height * 4];
+    for (index, block) in data
+        .chunks_exact(block_size)
+        .take(blocks_x * blocks_y)
+        .enumerate()
+    {
+        let (block_x, block_y) = (index % blocks_x * 4, index / blocks_x * 4);
+        for (texel, color) in decode_block(block).iter().enumerate() {
+            let (x, y) = (block_x + texel % 4, block_y + texel / 4);
+            // Blocks on the right and bottom edges may hang over the image
+            if x < width && y < height {
+                let offset = (y * width + x) * 4;
+                rgba[offset..offset + 4].copy_from_slice(color);
+            }
+        }
+    }
+    Ok(rgba)
+}
+
+fn expand_565(color: u16) -> [u8; 3] {
+    let (r, g, b) = (
+        (color >> 11) as u8 & 31,
+        (color >> 5) as u8 & 63,
+        color as u8 & 31,
+    );
+    [r << 3 | r >> 2, g << 2 | g >> 4, b << 3 | b >> 2]
+}
+
+/// BC1 color block, also the color half of BC2 and BC3 which are always in four color mode.
+fn decode_bc1(block: &[u8], four_colors: bool) -> [[u8; 4]; 16] {
+    let c0 = u16::from_le_bytes([block[0], block[1]]);
+    let c1 = u16::from_le_bytes([block[2], block[3]]);
+    let (e0, e1) = (expand_565(c0), expand_565(c1));
+    // Opaque blend of the endpoints, weighted w0 to w1
+    let mix = |w0: u32, w1: u32| {
+        let channel = |c: usize| ((e0[c] as u32 * w0 + e1[c] as u32 * w1) / (w0 + w1)) as u8;
+        [channel(0), channel(1), channel(2), 255]
+    };
+    let palette = if four_colors || c0 > c1 {
+        [mix(1, 0), mix(0, 1), mix(2, 1), mix(1, 2)]
+    } else {
+        // The fourth color is transparent black
+        [mix(1, 0), mix(0, 1), mix(1, 1), [0; 4]]
+    };
+    let indices = u32::from_le_bytes([block[4], block[5], block[6], block[7]]);
+    std::array::from_fn(|texel| palette[(indices >> (texel * 2)) as usize & 3])
+}
+
+fn decode_bc2(block: &[u8]) -> [[u8; 4]; 16] {
+    let alpha = u64::from_le_bytes(block[..8].try_into().unwrap());
+    let mut texels = decode_bc1(&block[8..], true);
+    for (texel, color) in texels.iter_mut().enumerate() {
+        color[3] = ((alpha >> (texel * 4)) as u8 & 15) * 17;
+    }
+    texels
+}
+
+fn decode_bc3(block: &[u8]) -> [[u8; 4]; 16] {
+    let alpha = decode_bc4(&block[..8]);
+    let mut texels = decode_bc1(&block[8..], true);
+    for (color, alpha) in texels.iter_mut().zip(alpha) {
+        color[3] = alpha;
+    }
+    texels
+}
+
+/// One interpolated 8-bit channel, shared by BC3 alpha, BC4 and both channels of BC5.
+fn decode_bc4(block: &[u8]) -> [u8; 16] {
+    let (r0, r1) = (block[0] as u32, block[1] as u32);
+    let palette: [u8; 8] = std::array::from_fn(|index| {
+        let index = index as u32;
+        (match (index, r0 > r1) {
+            (0, _) => r0,
+            (1, _) => r1,
+            (_, true) => ((8 - index) * r0 + (index - 1) * r1) / 7,
+            (6, false) => 0,
+            (7, false) => 255,
+            (_, false) => ((6 - index) * r0 + (index - 1) * r1) / 5,
+        }) as u8
+    });
+    let mut bits = [0; 8];
+    bits[..6].copy_from_slice(&block[2..8]);
+    let indices = u64::from_le_bytes(bits);
+    std::array::from_fn(|texel| palette[(indices >> (texel * 3)) as usize & 7])
+}
+
+fn decode_bc4_block(block: &[u8]) -> [[u8; 4]; 16] {
+    decode_bc4(block).map(|r| [r, 0, 0, 255])
+}
+
+fn decode_bc5(block: &[u8]) -> [[u8; 4]; 16] {
+    let (red, green) = (decode_bc4(&block[..8]), decode_bc4(&block[8..]));
+    std::array::from_fn(|texel| [red[texel], green[texel], 0, 255])
+}
+
+struct Bc7Mode {
+    subsets: usize,
+    partition_bits: u32,
+    rotation_bits: u32,
+    index_selection_bits: u32,
+    color_bits: u32,
+    alpha_bits: u32,
+    /// One p-bit per endpoint
+    endpoint_pbits: bool,
+    /// One p-bit per subset, shared by its two endpoints
+    shared_pbits: bool,
+    index_bits: u32,
+    /// Separate alpha indices, only for single subset modes
+    index_bits_2: u32,
+}
+
+#[allow(clippy::too_many_arguments)]
+const fn bc7_mode(
+    subsets: usize,
+    partition_bits: u32,
+    rotation_bits: u32,
+    index_selection_bits: u32,
+    color_bits: u32,
+    alpha_bits: u32,
+    pbits: (bool, bool),
+    index_bits: (u32, u32),
+) -> Bc7Mode {
+    Bc7Mode {
+        subsets,
+        partition_bits,
+        rotation_bits,
+        index_selection_bits,
+        color_bits,
+        alpha_bits,
+        endpoint_pbits: pbits.0,
+        shared_pbits: pbits.1,
+        index_bits: index_bits.0,
+        index_bits_2: index_bits.1,
+    }
+}
+
+const BC7_MODES: [Bc7Mode; 8] = [
+    bc7_mode(3, 4, 0, 0, 4, 0, (true, false), (3, 0)),
+    bc7_mode(2, 6, 0, 0, 6, 0, (false, true), (3, 0)),
+    bc7_mode(3, 6, 0, 0, 5, 0, (false, false), (2, 0)),
+    bc7_mode(2, 6, 0, 0, 7, 0, (true, false), (2, 0)),
+    bc7_mode(1, 0, 2, 1, 5, 6, (false, false), (2, 3)),
+    bc7_mode(1, 0, 2, 0, 7, 8, (false, false), (2, 2)),
+    bc7_mode(1, 0, 0, 0, 7, 7, (true, false), (4, 0)),
+    bc7_mode(2, 6, 0, 0, 5, 5, (true, false), (2, 0)),
+];
+
+/// Bit `i` set when texel `i` belongs to the second subset
+const BC7_PARTITIONS_2: [u16; 64] = [
+    0xcccc, 0x8888, 0xeeee, 0xecc8, 0xc880, 0xfeec, 0xfec8, 0xec80, //
+    0xc800, 0xffec, 0xfe80, 0xe800, 0xffe8, 0xff00, 0xfff0, 0xf000, //
+    0xf710, 0x008e, 0x7100, 0x08ce, 0x008c, 0x7310, 0x3100, 0x8cce, //
+    0x088c, 0x3110, 0x6666, 0x366c, 0x17e8, 0x0ff0, 0x718e, 0x399c, //
+    0xaaaa, 0xf0f0, 0x5a5a, 0x33cc, 0x3c3c, 0x55aa, 0x9696, 0xa55a, //
+    0x73ce, 0x13c8, 0x324c, 0x3bdc, 0x6996, 0xc33c, 0x9966, 0x0660, //
+    0x0272, 0x04e4, 0x4e40, 0x2720, 0xc936, 0x936c, 0x39c6, 0x639c, //
+    0x9336, 0x9cc6, 0x817e, 0xe718, 0xccf0, 0x0fcc, 0x7744, 0xee22, //
+];
+
+/// Bits `2i..2i+2` hold the subset of texel `i`
+const BC7_PARTITIONS_3: [u32; 64] = [
+    0xaa685050, 0x6a5a5040, 0x5a5a4200, 0x5450a0a8, 0xa5a50000, 0xa0a05050, //
+    0x5555a0a0, 0x5a5a5050, 0xaa550000, 0xaa555500, 0xaaaa5500, 0x90909090, //
+    0x94949494, 0xa4a4a4a4, 0xa9a59450, 0x2a0a4250, 0xa5945040, 0x0a425054, //
+    0xa5a5a500, 0x55a0a0a0, 0xa8a85454, 0x6a6a4040, 0xa4a45000, 0x1a1a0500, //
+    0x0050a4a4, 0xaaa59090, 0x14696914, 0x69691400, 0xa08585a0, 0xaa821414, //
+    0x50a4a450, 0x6a5a0200, 0xa9a58000, 0x5090a0a8, 0xa8a09050, 0x24242424, //
+    0x00aa5500, 0x24924924, 0x24499224, 0x50a50a50, 0x500aa550, 0xaaaa4444, //
+    0x66660000, 0xa5a0a5a0, 0x50a050a0, 0x69286928, 0x44aaaa44, 0x66666600, //
+    0xaa444444, 0x54a854a8, 0x95809580, 0x96969600, 0xa85454a8, 0x80959580, //
+    0xaa141414, 0x96960000, 0xaaaa1414, 0xa05050a0, 0xa0a5a5a0, 0x96000000, //
+    0x40804080, 0xa9a8a9a8, 0xaaaaaa44, 0x2a4a5254, //
+];
+
+/// Anchor texel of the second subset of each two subset partition
+const BC7_ANCHORS_2: [u8; 64] = [
+    15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, //
+    15, 2, 8, 2, 2, 8, 8, 15, 2, 8, 2, 2, 8, 8, 2, 2, //
+    15, 15, 6, 8, 2, 8, 15, 15, 2, 8, 2, 2, 2, 15, 15, 6, //
+    6, 2, 6, 8, 15, 15, 2, 2, 15, 15, 15, 15, 15, 2, 2, 15, //
+];
+
+/// Anchor texels of the second and third subsets of each three subset partition
+const BC7_ANCHORS_3: [[u8; 64]; 2] = [
+    [
+        3, 3, 15, 15, 8, 3, 15, 15, 8, 8, 6, 6, 6, 5, 3, 3, //
+        3, 3, 8, 15, 3, 3, 6, 10, 5, 8, 8, 6, 8, 5, 15, 15, //
+        8, 15, 3, 5, 6, 10, 8, 15, 15, 3, 15, 5, 15, 15, 15, 15, //
+        3, 15, 5, 5, 5, 8, 5, 10, 5, 10, 8, 13, 15, 12, 3, 3, //
+    ],
+    [
+        15, 8, 8, 3, 15, 15, 3, 8, 15, 15, 15, 15, 15, 15, 15, 8, //
+        15, 8, 15, 3, 15, 8, 15, 8, 3, 15, 6, 10, 15, 15, 10, 8, //
+        15, 3, 15, 10, 10, 8, 9, 10, 6, 15, 8, 15, 3, 6, 6, 8, //
+        15, 3, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 3, 15, 15, 8, //
+    ],
+];
+
+/// Interpolation weights out of 64 for 2, 3 and 4 bit indices
+const BC7_WEIGHTS_2: [u32; 4] = [0, 21, 43, 64];
+const BC7_WEIGHTS_3: [u32; 8] = [0, 9, 18, 27, 37, 46, 55, 64];
+const BC7_WEIGHTS_4: [u32; 16] = [0, 4, 9, 13, 17, 21, 26, 30, 34, 38, 43, 47, 51, 55, 60, 64];
+
+fn bc7_weight(index_bits: u32, index: u32) -> u32 {
+    match index_bits {
+        2 => BC7_WEIGHTS_2[index as usize],
+        3 => BC7_WEIGHTS_3[index as usize],
+        _ => BC7_WEIGHTS_4[index as usize],
+    }
+}
+
+fn decode_bc7(block: &[u8]) -> [[u8; 4]; 16] {
+    let bits = u128::from_le_bytes(block.try_into().unwrap());
+    let mode_index = block[0].trailing_zeros() as usize;
+    // No mode bit set is a reserved mode, which decodes to transparent black
+    let Some(mode) = BC7_MODES.get(mode_index) else {
+        return [[0; 4]; 16];
+    };
+    let mut position = mode_index as u32 + 1;
+    let mut read = |count: u32| {
+        let value = (bits >> position) as u32 & ((1u64 << count) - 1) as u32;
+        position += count;
+        value
+    };
+
+    let partition = read(mode.partition_bits) as usize;
+    let rotation = read(mode.rotation_bits);
+    let index_selection = read(mode.index_selection_bits);
+
+    let endpoint_count = mode.subsets * 2;
+    let mut endpoints = [[0u32; 4]; 6];
+    for channel in 0..3 {
+        for endpoint in &mut endpoints[..endpoint_count] {
+            endpoint[channel] = read(mode.color_bits);
+        }
+    }
+    for endpoint in &mut endpoints[..endpoint_count] {
+        endpoint[3] = read(mode.alpha_bits);
+    }
+
+    let (mut color_bits, mut alpha_bits) = (mode.color_bits, mode.alpha_bits);
+    if mode.endpoint_pbits || mode.shared_pbits {
+        let mut pbits = [0; 6];
+        if mode.endpoint_pbits {
+            for pbit in &mut pbits[..endpoint_count] {
+                *pbit = read(1);
+            }
+        } else {
+            for subset in 0..mode.subsets {
+                let pbit = read(1);
+                pbits[subset * 2] = pbit;
+                pbits[subset * 2 + 1] = pbit;
+            }
+        }
+        for (endpoint, pbit) in endpoints[..endpoint_count].iter_mut().zip(pbits) {
+            for channel in endpoint.iter_mut() {
+                *channel = *channel << 1 | pbit;
+            }
+        }
+        color_bits += 1;
+        if alpha_bits > 0 {
+            alpha_bits += 1;
+        }
+    }
+    let expand = |value: u32, bits: u32| {
+        let value = value << (8 - bits);
+        value | value >> bits
+    };
+    for endpoint in &mut endpoints[..endpoint_count] {
+        for channel in &mut endpoint[..3] {
+            *channel = expand(*channel, color_bits);
+        }
+        endpoint[3] = if alpha_bits > 0 {
+            expand(endpoint[3], alpha_bits)
+        } else {
+            255
+        };
+    }
+
+    let subset_of = |texel: usize| match mode.subsets {
+        2 => (BC7_PARTITIONS_2[partition] >> texel) as usize & 1,
+        3 => (BC7_PARTITIONS_3[partition] >> (texel * 2)) as usize & 3,
+        _ => 0,
+    };
+    // The first index of each subset has its top bit left out, as it is always 0
+    let is_anchor = |texel: usize| {
+        texel == 0
+            || match mode.subsets {
+                2 => texel == BC7_ANCHORS_2[partition] as usize,
+                3 => BC7_ANCHORS_3
+                    .iter()
+                    .any(|anchors| texel == anchors[partition] as usize),
+                _ => false,
+            }
+    };
+    let mut indices = [0; 16];
+    for (texel, index) in indices.iter_mut().enumerate() {
+        *index = read(mode.index_bits - is_anchor(texel) as u32);
+    }
+    let mut indices_2 = [0; 16];
+    if mode.index_bits_2 > 0 {
+        for (texel, index) in indices_2.iter_mut().enumerate() {
+            *index = read(mode.index_bits_2 - (texel == 0) as u32);
+        }
+    }
+
+    std::array::from_fn(|texel| {
+        let subset = subset_of(texel);
+        let (e0, e1) = (endpoints[subset * 2], endpoints[subset * 2 + 1]);
+        let (color_weight, alpha_weight) = if mode.index_bits_2 == 0 {
+            let weight = bc7_weight(mode.index_bits, indices[texel]);
+            (weight, weight)
+        } else {
+            let primary = bc7_weight(mode.index_bits, indices[texel]);
+            let secondary = bc7_weight(mode.index_bits_2, indices_2[texel]);
+            if index_selection == 1 {
+                (secondary, primary)
+            } else {
+                (primary, secondary)
+            }
+        };
+        let interpolate = |channel: usize, weight: u32| {
+            ((64 - weight) * e0[channel] + weight * e1[channel] + 32) >> 6
+        };
+        let mut color = [
+            interpolate(0, color_weight) as u8,
+            interpolate(1, color_weight) as u8,
+            interpolate(2, color_weight) as u8,
+            interpolate(3, alpha_weight) as u8,
+        ];
+        if rotation > 0 {
+            color.swap(3, rotation as usize - 1);
+        }
+        color
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A KTX2 file holding `level` as the only mip level of a `width` x `height` image
+    fn ktx2_file(vk_format: u32, width: u32, height: u32, level: &[u8]) -> Vec<u8> {
+        let data_offset = LEVEL_INDEX_OFFSET + 24;
+        let mut file = IDENTIFIER.to_vec();
+        // Format, type size, size, layers, faces, levels and supercompression
+        for field in [vk_format, 1, width, height, 0, 0, 1, 1, 0] {
+            file.extend(field.to_le_bytes());
+        }
+        file.resize(LEVEL_INDEX_OFFSET, 0);
+        for field in [data_offset, level.len(), level.len()] {
+            file.extend((field as u64).to_le_bytes());
+        }
+        file.extend(level);
+        file
+    }
+
+    /// The texels of a 4x4 block, decoded through `decode_to_rgba8`
+    fn decode_block(format: wgpu::TextureFormat, block: &[u8]) -> Vec<[u8; 4]> {
+        decode_to_rgba8(format, 4, 4, block)
+            .unwrap()
+            .chunks_exact(4)
+            .map(|texel| texel.try_into().unwrap())
+            .collect()
+    }
+
+    /// Index `i % 4` for texel `i`, 2 bits each
+    const RAMP_2: [u8; 4] = [0xe4; 4];
+    /// Index `i % 8` for texel `i`, 3 bits each
+    const RAMP_3: [u8; 6] = [0x88, 0xc6, 0xfa, 0x88, 0xc6, 0xfa];
+
+    #[test]
+    fn bc1_four_colors() {
+        // Red over blue, so the four color palette
+        let block = [[0x00, 0xf8, 0x1f, 0x00].as_slice(), &RAMP_2].concat();
+        let palette = [
+            [255, 0, 0, 255],
+            [0, 0, 255, 255],
+            [170, 0, 85, 255],
+            [85, 0, 170, 255],
+        ];
+        let expected = (0..16).map(|texel| palette[texel % 4]).collect::<Vec<_>>();
+        assert_eq!(
+            decode_block(wgpu::TextureFormat::Bc1RgbaUnorm, &block),
+            expected
+        );
+    }
+
+    #[test]
+    fn bc1_three_colors_and_transparent() {
+        // Blue over red, so the three color palette with transparent black
+        let block = [[0x1f, 0x00, 0x00, 0xf8].as_slice(), &RAMP_2].concat();
+        let palette = [
+            [0, 0, 255, 255],
+            [255, 0, 0, 255],
+            [127, 0, 127, 255],
+            [0, 0, 0, 0],
+        ];
+        let expected = (0..16).map(|texel| palette[texel % 4]).collect::<Vec<_>>();
+        assert_eq!(
+            decode_block(wgpu::TextureFormat::Bc1RgbaUnorm, &block),
+            expected
+        );
+    }
+
+    #[test]
+    fn bc2_explicit_alpha() {
+        // Alpha i / 15 for texel i over white
+        let alpha = [0x10, 0x32, 0x54, 0x76, 0x98, 0xba, 0xdc, 0xfe];
+        let block = [alpha.as_slice(), &[0xff, 0xff, 0, 0, 0, 0, 0, 0]].concat();
+        let expected = (0..16)
+            .map(|texel| [255, 255, 255, texel as u8 * 17])
+            .collect::<Vec<_>>();
+        assert_eq!(
+            decode_block(wgpu::TextureFormat::Bc2RgbaUnorm, &block),
+            expected
+        );
+    }
+
+    #[test]
+    fn bc3_interpolated_alpha() {
+        // 252 over 0, so eight alpha values in steps of 36, over red
+        let block = [
+            [252, 0].as_slice(),
+            &RAMP_3,
+            &[0x00, 0xf8, 0, 0, 0, 0, 0, 0],
+        ]
+        .concat();
+        let alphas = [252, 0, 216, 180, 144, 108, 72, 36];
+        let expected = (0..16)
+            .map(|texel| [255, 0, 0, alphas[texel % 8]])
+            .collect::<Vec<_>>();
+        assert_eq!(
+            decode_block(wgpu::TextureFormat::Bc3RgbaUnorm, &block),
+            expected
+        );
+    }
+
+    #[test]
+    fn bc4_six_values_and_extremes() {
+        // 0 under 255, so six values in steps of 51, then 0 and 255
+        let block = [[0, 255].as_slice(), &RAMP_3].concat();
+        let reds = [0, 255, 51, 102, 153, 204, 0, 255];
+        let expected = (0..16)
+            .map(|texel| [reds[texel % 8], 0, 0, 255])
+            .collect::<Vec<_>>();
+        assert_eq!(
+            decode_block(wgpu::TextureFormat::Bc4RUnorm, &block),
+            expected
+        );
+    }
+
+    #[test]
+    fn bc5_two_channels() {
+        // Red as in the BC4 test, green a flat 128
+        let block = [[0, 255].as_slice(), &RAMP_3, &[128, 128, 0, 0, 0, 0, 0, 0]].concat();
+        let reds = [0, 255, 51, 102, 153, 204, 0, 255];
+        let expected = (0..16)
+            .map(|texel| [reds[texel % 8], 128, 0, 255])
+            .collect::<Vec<_>>();
+        assert_eq!(
+            decode_block(wgpu::TextureFormat::Bc5RgUnorm, &block),
+            expected
+        );
+    }
+
+    #[test]
+    fn bc7_mode_6_ramp() {
+        // Mode 6 from transparent black to opaque white, p-bits 0 and 1, index i for
+        // texel i
+        let block = [
+            0x40, 0xc0, 0x1f, 0xf0, 0x07, 0xfc, 0x01, 0x7f, //
+            0x11, 0x32, 0x54, 0x76, 0x98, 0xba, 0xdc, 0xfe,
+        ];
+        // (64 - w) * 0 + w * 255 + 32 >> 6 for each 4 bit weight w
+        let ramp = [
+            0, 16, 36, 52, 68, 84, 104, 120, 135, 151, 171, 187, 203, 219, 239, 255,
+        ];
+        let expected = ramp.map(|value| [value; 4]).to_vec();
+        assert_eq!(
+            decode_block(wgpu::TextureFormat::Bc7RgbaUnorm, &block),
+            expected
+        );
+    }
+
+    #[test]
+    fn bc7_reserved_mode() {
+        assert_eq!(
+            decode_block(wgpu::TextureFormat::Bc7RgbaUnorm, &[0; 16]),
+            vec![[0; 4]; 16]
+        );
+    }
+
+    #[test]
+    fn decode_crops_partial_blocks() {
+        let block = [[0x00, 0xf8, 0x1f, 0x00].as_slice(), &RAMP_2].concat();
+        let rgba = decode_to_rgba8(wgpu::TextureFormat::Bc1RgbaUnorm, 2, 1, &block).unwrap();
+        assert_eq!(rgba, [255, 0, 0, 255, 0, 0, 255, 255]);
+        assert!(decode_to_rgba8(wgpu::TextureFormat::Bc1RgbaUnorm, 8, 4, &block).is_err());
+    }
+
+    #[test]
+    fn parse_single_level() {
+        let block = [0x00, 0xf8, 0x1f, 0x00, 0, 0, 0, 0];
+        let file = ktx2_file(131, 4, 4, &block);
+        let image = Ktx2Image::parse(&file).unwrap();
+        assert_eq!(image.format, wgpu::TextureFormat::Bc1RgbaUnorm);
+        assert_eq!((image.width, image.height), (4, 4));
+        assert_eq!(image.levels, [block.as_slice()]);
+    }
+
+    #[test]
+    fn parse_rejects_garbage() {
+        assert!(Ktx2Image::parse(&[]).is_err());
+        assert!(Ktx2Image::parse(b"\x89PNG\r\n\x1a\n not a KTX2 file at all").is_err());
+        // The identifier alone, without a header
+        assert!(Ktx2Image::parse(&IDENTIFIER).is_err());
+    }
+
+    #[test]
+    fn parse_rejects_truncated_files() {
+        let file = ktx2_file(131, 4, 4, &[0; 8]);
+        // Cut in the header, in the level index and in the level data
+        for length in [40, LEVEL_INDEX_OFFSET + 10, file.len() - 1] {
+            assert!(Ktx2Image::parse(&file[..length]).is_err(), "{length} bytes");
+        }
+    }
+
+    #[test]
+    fn parse_rejects_unsupported_headers() {
+        // Basis Universal, an unknown format and a 3D image
+        assert!(Ktx2Image::parse(&ktx2_file(0, 4, 4, &[0; 8])).is_err());
+        assert!(Ktx2Image::parse(&ktx2_file(1_000, 4, 4, &[0; 8])).is_err());
+        let mut file = ktx2_file(131, 4, 4, &[0; 8]);
+        file[28..32].copy_from_slice(&4u32.to_le_bytes());
+        assert!(Ktx2Image::parse(&file).is_err());
+    }
+}
@@ -0,0 +1,84 @@
+//! Compile-time checks that the `Pod` uniforms match the layout of their WGSL structs
+//! under the uniform address space rules: `vec3`s align to 16 bytes, a following scalar
+//! packs into their last 4 bytes, and struct sizes round up to their 16 byte alignment.
+//! A padding field added, dropped or moved fails the build instead of shading garbage.
+//! Uniforms with private fields are checked next to them with `assert_layout`.
+
+use crate::{
+    fog::FogUniform,
+    light::{DirectionalLightUniform, LightUniform, SpotLightUniform},
+    reflective::ReflectiveFactors,
+    shadow::{ShadowMap, ShadowUniform},
+};
+
+/// Asserts the size of `$ty` and the offsets of the listed fields, in bytes.
+macro_rules! assert_layout {
+    ($ty:ty, size $size:expr $(, $field:ident @ $offset:expr)* $(,)?) => {
+        const _: () = assert!(
+            std::mem::size_of::<$ty>() == $size,
+            concat!("size of ", stringify!($ty)),
+        );
+        $(
+            const _: () = assert!(
+                std::mem::offset_of!($ty, $field) == $offset,
+                concat!("offset of ", stringify!($ty), "::", stringify!($field)),
+            );
+        )*
+    };
+}
+
+pub(crate) use assert_layout;
+
+// `Light`, also the element of `LightArray`, whose stride is its size
+assert_layout!(
+    LightUniform,
+    size 48,
+    position @ 0,
+    range @ 12,
+    color @ 16,
+    constant @ 28,
+    linear @ 32,
+    quadratic @ 36,
+);
+
+// `DirectionalLight`, two vec3s each padded to 16 bytes
+assert_layout!(DirectionalLightUniform, size 32, direction @ 0, color @ 16);
+
+assert_layout!(
+    SpotLightUniform,
+    size 48,
+    position @ 0,
+    inner_cos @ 12,
+    direction @ 16,
+    outer_cos @ 28,
+    color @ 32,
+    range @ 44,
+);
+
+assert_layout!(
+    FogUniform,
+    size 32,
+    color @ 0,
+    start @ 16,
+    end @ 20,
+    density @ 24,
+    mode @ 28,
+);
+
+// `ReflectiveFactors`, whose reflectivity packs into the base color's vec3
+assert_layout!(ReflectiveFactors, size 16, base_color @ 0, reflectivity @ 12);
+
+// `Shadow`, whose `splits` is a vec4 so it only holds as many cascades as that has lanes
+const _: () = assert!(ShadowMap::MAX_CASCADES == 4);
+assert_layout!(
+    ShadowUniform,
+    size 304,
+    light_view_proj @ 0,
+    splits @ 256,
+    depth_bias @ 272,
+    normal_bias @ 276,
+    cascade_count @ 280,
+    debug_cascades @ 284,
+    pcf_samples @ 288,
+    texel_size @ 292,
+);
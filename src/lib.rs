@@ -1,7 +1,56 @@
+// wgpu's resources aren't `Send` in the browser, where models still share their materials
+// and textures through `Arc`s on the only thread there is
+#![cfg_attr(target_arch = "wasm32", allow(clippy::arc_with_non_send_sync))]
+
+pub mod animation;
+pub mod atmosphere;
+pub mod billboard;
+pub mod bloom;
+pub mod cache;
 pub mod camera;
+pub mod camera_path;
+pub mod colormap;
+pub mod debug_lines;
+pub mod decal;
+pub mod deferred;
+pub mod environment;
+pub mod exposure;
+pub mod fog;
+pub mod frustum;
+pub mod fxaa;
+pub mod gizmo;
+pub mod grid;
+pub mod hot_reload;
+pub mod ibl;
+pub mod instance;
+pub mod ktx2;
+mod layout_check;
 pub mod light;
+pub mod loader;
 pub mod model;
+pub mod outline;
+pub mod overlay;
+pub mod particles;
+pub mod pbr;
+pub mod picking;
+pub mod probe;
+pub mod reflective;
+pub mod render_graph;
 pub mod render_pipeline;
+pub mod renderer;
 pub mod resources;
+pub mod shadow;
+pub mod skybox;
+pub mod splat;
+pub mod ssao;
 pub mod state;
+pub mod taa;
+pub mod terrain;
+pub mod tessellation;
 pub mod texture;
+pub mod timing;
+pub mod tonemap;
+pub mod transform;
+pub mod velocity;
+pub mod viewport;
+pub mod water;
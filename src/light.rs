@@ -1,28 +1,98 @@
+use cgmath::InnerSpace as _;
 use wgpu::util::DeviceExt as _;
 
 use crate::{
+    hot_reload::wgsl_source,
     model::{self, Vertex},
-    render_pipeline::create_render_pipeline,
+    render_pipeline::{PipelineDesc, create_render_pipeline},
 };
 
+/// How a point light fades with distance `d`: its color is scaled by
+/// `1 / (constant + linear * d + quadratic * d²)`, smoothly reaching zero at `range`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Attenuation {
+    pub constant: f32,
+    pub linear: f32,
+    pub quadratic: f32,
+    pub range: f32,
+}
+
+impl Attenuation {
+    /// Full strength at any distance.
+    pub const NONE: Self = Self {
+        constant: 1.0,
+        linear: 0.0,
+        quadratic: 0.0,
+        range: f32::MAX,
+    };
+
+    /// Coefficients that leave little of the light by `range`, the common fit of
+    /// `linear = 4.5 / range` and `quadratic = 75 / range²`.
+    pub fn from_range(range: f32) -> Self {
+        let range = range.max(0.0001);
+        Self {
+            constant: 1.0,
+            linear: 4.5 / range,
+            quadratic: 75.0 / (range * range),
+            range,
+        }
+    }
+}
+
+impl Default for Attenuation {
+    fn default() -> Self {
+        Self::NONE
+    }
+}
+
 #[repr(C)]
 #[derive(Debug, Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
 pub struct LightUniform {
     pub position: [f32; 3],
-    _padding: u32,
+    pub range: f32,
     pub color: [f32; 3],
-    _padding2: u32,
+    pub constant: f32,
+    pub linear: f32,
+    pub quadratic: f32,
+    // Arrays of the struct step by 16 bytes in WGSL
+    _padding: [u32; 2],
 }
 
 impl LightUniform {
+    /// A light without falloff, see `with_attenuation`.
     pub fn new(position: [f32; 3], color: [f32; 3]) -> Self {
         Self {
             position,
-            _padding: 0,
+            range: 0.0,
             color,
-            _padding2: 0,
+            constant: 0.0,
+            linear: 0.0,
+            quadratic: 0.0,
+            _padding: [0; 2],
+        }
+        .with_attenuation(Attenuation::NONE)
+    }
+
+    pub fn with_attenuation(mut self, attenuation: Attenuation) -> Self {
+        self.set_attenuation(attenuation);
+        self
+    }
+
+    pub fn attenuation(&self) -> Attenuation {
+        Attenuation {
+            constant: self.constant,
+            linear: self.linear,
+            quadratic: self.quadratic,
+            range: self.range,
         }
     }
+
+    pub fn set_attenuation(&mut self, attenuation: Attenuation) {
+        self.constant = attenuation.constant;
+        self.linear = attenuation.linear;
+        self.quadratic = attenuation.quadratic;
+        self.range = attenuation.range;
+    }
 }
 
 pub struct Light {
@@ -39,7 +109,9 @@ impl Light {
         uniform: LightUniform,
         color_format: wgpu::TextureFormat,
         depth_format: Option<wgpu::TextureFormat>,
+        depth_compare: wgpu::CompareFunction,
         camera_bind_group_layout: &wgpu::BindGroupLayout,
+        sample_count: u32,
     ) -> Self {
         let buffer: wgpu::Buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
             label: Some("Light Buffer"),
@@ -70,32 +142,417 @@ impl Light {
             label: None,
         });
 
+        let render_pipeline = Self::create_render_pipeline(
+            device,
+            &bind_group_layout,
+            color_format,
+            depth_format,
+            depth_compare,
+            camera_bind_group_layout,
+            sample_count,
+        );
+
+        Self {
+            uniform,
+            buffer,
+            bind_group,
+            bind_group_layout,
+            render_pipeline,
+        }
+    }
+
+    pub fn upload(&self, queue: &wgpu::Queue) {
+        queue.write_buffer(&self.buffer, 0, bytemuck::cast_slice(&[self.uniform]));
+    }
+
+    /// Rebuilds the visualization pipeline, e.g. after the MSAA sample count changed.
+    pub fn recreate_pipeline(
+        &mut self,
+        device: &wgpu::Device,
+        color_format: wgpu::TextureFormat,
+        depth_format: Option<wgpu::TextureFormat>,
+        depth_compare: wgpu::CompareFunction,
+        camera_bind_group_layout: &wgpu::BindGroupLayout,
+        sample_count: u32,
+    ) {
+        self.render_pipeline = Self::create_render_pipeline(
+            device,
+            &self.bind_group_layout,
+            color_format,
+            depth_format,
+            depth_compare,
+            camera_bind_group_layout,
+            sample_count,
+        );
+    }
+
+    fn create_render_pipeline(
+        device: &wgpu::Device,
+        bind_group_layout: &wgpu::BindGroupLayout,
+        color_format: wgpu::TextureFormat,
+        depth_format: Option<wgpu::TextureFormat>,
+        depth_compare: wgpu::CompareFunction,
+        camera_bind_group_layout: &wgpu::BindGroupLayout,
+        sample_count: u32,
+    ) -> wgpu::RenderPipeline {
         let shader = wgpu::ShaderModuleDescriptor {
-            source: wgpu::ShaderSource::Wgsl(include_str!("./light.wgsl").into()),
+            source: wgpu::ShaderSource::Wgsl(wgsl_source!("src/light.wgsl", "src/prelude.wgsl")),
             label: Some("Light Shader"),
         };
         let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
             label: Some("Light Pipeline Layout"),
-            bind_group_layouts: &[camera_bind_group_layout, &bind_group_layout],
+            bind_group_layouts: &[camera_bind_group_layout, bind_group_layout],
             push_constant_ranges: &[],
         });
 
-        let render_pipeline = create_render_pipeline(
+        create_render_pipeline(
             device,
             &layout,
-            color_format,
+            &[color_format],
             depth_format,
             &[model::ModelVertex::desc()],
             shader,
-            false, // Light doesn't need wireframe typically
+            PipelineDesc {
+                depth_compare,
+                sample_count,
+                ..Default::default()
+            },
+        )
+    }
+}
+
+/// Orbits a light around the world Y axis through the origin, like the light of the
+/// tutorial.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LightAnimator {
+    pub enabled: bool,
+    /// Rotation per second, counterclockwise seen from above
+    pub speed: cgmath::Deg<f32>,
+}
+
+impl LightAnimator {
+    pub fn new(speed: cgmath::Deg<f32>) -> Self {
+        Self {
+            enabled: true,
+            speed,
+        }
+    }
+
+    /// Moves `light` along its orbit by `dt` and uploads it. Does nothing when disabled.
+    pub fn update(&self, light: &mut Light, queue: &wgpu::Queue, dt: std::time::Duration) {
+        use cgmath::Rotation3 as _;
+        if !self.enabled {
+            return;
+        }
+        let rotation = cgmath::Quaternion::from_axis_angle(
+            cgmath::Vector3::unit_y(),
+            self.speed * dt.as_secs_f32(),
         );
+        light.uniform.position = (rotation * cgmath::Vector3::from(light.uniform.position)).into();
+        light.upload(queue);
+    }
+}
+
+impl Default for LightAnimator {
+    fn default() -> Self {
+        Self::new(cgmath::Deg(60.0))
+    }
+}
+
+/// A light infinitely far away, such as the sun. `direction` is the world-space
+/// direction the light travels in and is expected to be normalized.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct DirectionalLightUniform {
+    pub direction: [f32; 3],
+    _padding: u32,
+    pub color: [f32; 3],
+    _padding2: u32,
+}
+
+impl DirectionalLightUniform {
+    pub fn new(direction: [f32; 3], color: [f32; 3]) -> Self {
+        let direction = cgmath::Vector3::from(direction).normalize();
+        Self {
+            direction: direction.into(),
+            _padding: 0,
+            color,
+            _padding2: 0,
+        }
+    }
+}
+
+pub struct DirectionalLight {
+    pub uniform: DirectionalLightUniform,
+    pub buffer: wgpu::Buffer,
+    pub bind_group: wgpu::BindGroup,
+    pub bind_group_layout: wgpu::BindGroupLayout,
+}
+
+impl DirectionalLight {
+    pub fn new(device: &wgpu::Device, uniform: DirectionalLightUniform) -> Self {
+        let buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Directional Light Buffer"),
+            contents: bytemuck::cast_slice(&[uniform]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::VERTEX_FRAGMENT,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            }],
+            label: Some("directional_light_bind_group_layout"),
+        });
+
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &bind_group_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: buffer.as_entire_binding(),
+            }],
+            label: Some("directional_light_bind_group"),
+        });
 
         Self {
             uniform,
             buffer,
             bind_group,
             bind_group_layout,
-            render_pipeline,
+        }
+    }
+
+    pub fn set_direction(&mut self, direction: [f32; 3]) {
+        self.uniform.direction = cgmath::Vector3::from(direction).normalize().into();
+    }
+
+    pub fn upload(&self, queue: &wgpu::Queue) {
+        queue.write_buffer(&self.buffer, 0, bytemuck::cast_slice(&[self.uniform]));
+    }
+}
+
+/// A cone of light, such as a flashlight or a streetlamp, as stored in the light array.
+/// `direction` is the normalized world-space axis of the cone. Light is full strength
+/// inside the angle whose cosine is `inner_cos`, fades out towards `outer_cos` and
+/// falls off with distance until it reaches zero at `range`.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct SpotLightUniform {
+    pub position: [f32; 3],
+    pub inner_cos: f32,
+    pub direction: [f32; 3],
+    pub outer_cos: f32,
+    pub color: [f32; 3],
+    pub range: f32,
+}
+
+/// A spotlight described by its half-angles, turned into a `SpotLightUniform` for the
+/// light array. `color` is the intensity right at the light, it falls off as
+/// `1 / (d² + 1)` with the distance `d`, so half of it is left one unit away.
+#[derive(Debug, Clone, Copy)]
+pub struct SpotLight {
+    pub position: cgmath::Point3<f32>,
+    pub direction: cgmath::Vector3<f32>,
+    pub color: [f32; 3],
+    /// Angle from the axis within which the light is full strength
+    pub inner_angle: cgmath::Deg<f32>,
+    /// Angle from the axis past which there is no light
+    pub outer_angle: cgmath::Deg<f32>,
+    pub range: f32,
+}
+
+impl SpotLight {
+    pub fn new(
+        position: cgmath::Point3<f32>,
+        direction: cgmath::Vector3<f32>,
+        color: [f32; 3],
+        inner_angle: cgmath::Deg<f32>,
+        outer_angle: cgmath::Deg<f32>,
+        range: f32,
+    ) -> Self {
+        Self {
+            position,
+            direction,
+            color,
+            inner_angle,
+            outer_angle,
+            range,
+        }
+    }
+
+    /// Points the light from its position towards `target`.
+    pub fn look_at(&mut self, target: cgmath::Point3<f32>) {
+        self.direction = target - self.position;
+    }
+
+    /// The uniform for the light array. An inner angle past the outer one is clamped to it,
+    /// and a zero `direction`, e.g. from `look_at` its own position, points straight down.
+    pub fn uniform(&self) -> SpotLightUniform {
+        use cgmath::Angle as _;
+        let outer = self.outer_angle.0.clamp(0.0, 90.0);
+        let inner = self.inner_angle.0.clamp(0.0, outer);
+        let direction = if self.direction.magnitude2() > 0.0 {
+            self.direction.normalize()
+        } else {
+            -cgmath::Vector3::unit_y()
+        };
+        SpotLightUniform {
+            position: self.position.into(),
+            inner_cos: cgmath::Deg(inner).cos(),
+            direction: direction.into(),
+            outer_cos: cgmath::Deg(outer).cos(),
+            color: self.color,
+            range: self.range.max(0.0),
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
+struct LightArrayHeader {
+    count: u32,
+    // The light array that follows has to start on a 16 byte boundary
+    _padding: [u32; 3],
+}
+
+/// Lists of point lights and spotlights stored in read-only storage buffers, each laid out
+/// as a `count` header followed by room for `capacity` lights.
+pub struct LightArray {
+    pub lights: Vec<LightUniform>,
+    pub spot_lights: Vec<SpotLightUniform>,
+    pub capacity: usize,
+    pub buffer: wgpu::Buffer,
+    pub spot_buffer: wgpu::Buffer,
+    pub bind_group: wgpu::BindGroup,
+    pub bind_group_layout: wgpu::BindGroupLayout,
+}
+
+impl LightArray {
+    pub const DEFAULT_CAPACITY: usize = 64;
+
+    pub fn new(device: &wgpu::Device, capacity: usize) -> Self {
+        let capacity = capacity.max(1);
+        let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Light Array Buffer"),
+            size: (std::mem::size_of::<LightArrayHeader>()
+                + std::mem::size_of::<LightUniform>() * capacity)
+                as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let spot_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Spot Light Array Buffer"),
+            size: (std::mem::size_of::<LightArrayHeader>()
+                + std::mem::size_of::<SpotLightUniform>() * capacity)
+                as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let storage_entry = |binding| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Storage { read_only: true },
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[storage_entry(0), storage_entry(1)],
+            label: Some("light_array_bind_group_layout"),
+        });
+
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: spot_buffer.as_entire_binding(),
+                },
+            ],
+            label: Some("light_array_bind_group"),
+        });
+
+        Self {
+            lights: Vec::with_capacity(capacity),
+            spot_lights: Vec::with_capacity(capacity),
+            capacity,
+            buffer,
+            spot_buffer,
+            bind_group,
+            bind_group_layout,
+        }
+    }
+
+    /// Appends a light and returns its index, or `None` if the buffer is full.
+    pub fn add_light(&mut self, light: LightUniform) -> Option<usize> {
+        if self.lights.len() >= self.capacity {
+            return None;
+        }
+        self.lights.push(light);
+        Some(self.lights.len() - 1)
+    }
+
+    /// Replaces the light at `index`, returning whether there was one.
+    pub fn update_light(&mut self, index: usize, light: LightUniform) -> bool {
+        match self.lights.get_mut(index) {
+            Some(slot) => {
+                *slot = light;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Appends a spotlight and returns its index, or `None` if the buffer is full.
+    pub fn add_spot_light(&mut self, light: SpotLightUniform) -> Option<usize> {
+        if self.spot_lights.len() >= self.capacity {
+            return None;
+        }
+        self.spot_lights.push(light);
+        Some(self.spot_lights.len() - 1)
+    }
+
+    /// Replaces the spotlight at `index`, returning whether there was one.
+    pub fn update_spot_light(&mut self, index: usize, light: SpotLightUniform) -> bool {
+        match self.spot_lights.get_mut(index) {
+            Some(slot) => {
+                *slot = light;
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn upload(&self, queue: &wgpu::Queue) {
+        Self::upload_list(queue, &self.buffer, &self.lights);
+        Self::upload_list(queue, &self.spot_buffer, &self.spot_lights);
+    }
+
+    fn upload_list<T: bytemuck::Pod>(queue: &wgpu::Queue, buffer: &wgpu::Buffer, lights: &[T]) {
+        let header = LightArrayHeader {
+            count: lights.len() as u32,
+            _padding: [0; 3],
+        };
+        queue.write_buffer(buffer, 0, bytemuck::cast_slice(&[header]));
+        if !lights.is_empty() {
+            queue.write_buffer(
+                buffer,
+                std::mem::size_of::<LightArrayHeader>() as wgpu::BufferAddress,
+                bytemuck::cast_slice(lights),
+            );
         }
     }
 }
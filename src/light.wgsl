@@ -1,18 +1,7 @@
-struct Camera {
-  view_pos: vec4<f32>,
-  view: mat4x4<f32>,
-  view_proj: mat4x4<f32>,
-  inv_proj: mat4x4<f32>,
-  inv_view: mat4x4<f32>,
-}
-
-struct Light {
-  position: vec3<f32>,
-  color: vec3<f32>,
-}
+// `CameraUniform` and the other shared types are declared in `src/prelude.wgsl`
 
 @group(0) @binding(0)
-var<uniform> camera: Camera;
+var<uniform> camera: CameraUniform;
 
 @group(1) @binding(0)
 var<uniform> light: Light;
@@ -0,0 +1,279 @@
+use std::{
+    future::Future,
+    sync::{Arc, Mutex, mpsc},
+};
+
+use crate::{cache::ResourceCache, model, resources, texture};
+
+/// `Send` where loads run on worker threads. In the browser they run as tasks on the page's
+/// single thread, where wgpu's types aren't `Send`.
+#[cfg(not(target_arch = "wasm32"))]
+pub trait MaybeSend: Send {}
+#[cfg(not(target_arch = "wasm32"))]
+impl<T: Send> MaybeSend for T {}
+#[cfg(target_arch = "wasm32")]
+pub trait MaybeSend {}
+#[cfg(target_arch = "wasm32")]
+impl<T> MaybeSend for T {}
+
+#[cfg(not(target_arch = "wasm32"))]
+type Job = std::pin::Pin<Box<dyn Future<Output = ()> + Send>>;
+
+/// The result of a load started on a `ResourceLoader`, filled in once it finishes.
+pub struct LoadHandle<T> {
+    result: Arc<Mutex<Option<anyhow::Result<T>>>>,
+}
+
+impl<T> LoadHandle<T> {
+    pub fn is_ready(&self) -> bool {
+        self.result.lock().unwrap().is_some()
+    }
+
+    /// The loaded resource or the error it failed with, `None` while it is still loading
+    /// and once taken.
+    pub fn take(&self) -> Option<anyhow::Result<T>> {
+        self.result.lock().unwrap().take()
+    }
+}
+
+/// How many of the loads started on a `ResourceLoader` have finished.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct LoadProgress {
+    pub finished: usize,
+    pub total: usize,
+    /// Label of the load that finished last
+    pub last: Option<String>,
+}
+
+impl LoadProgress {
+    /// Share of the loads finished, 1 when there are none.
+    pub fn fraction(&self) -> f32 {
+        if self.total == 0 {
+            1.0
+        } else {
+            self.finished as f32 / self.total as f32
+        }
+    }
+
+    pub fn is_done(&self) -> bool {
+        self.finished >= self.total
+    }
+}
+
+/// The worker threads running the loads of `ResourceLoader`s, see `ResourceLoader::on_pool`.
+/// Clones share the same threads, which end once every clone and every loader on them is
+/// dropped and the queued loads ran. In the browser loads run as tasks instead, and this
+/// holds nothing.
+#[derive(Clone)]
+pub struct LoaderPool {
+    #[cfg(not(target_arch = "wasm32"))]
+    jobs: mpsc::Sender<Job>,
+}
+
+impl LoaderPool {
+    /// Worker threads, loads past this many wait for one to free up
+    pub const WORKERS: usize = 4;
+
+    pub fn new() -> Self {
+        #[cfg(not(target_arch = "wasm32"))]
+        let jobs = {
+            let (jobs, worker_jobs) = mpsc::channel::<Job>();
+            let worker_jobs = Arc::new(Mutex::new(worker_jobs));
+            for index in 0..Self::WORKERS {
+                let worker_jobs = worker_jobs.clone();
+                std::thread::Builder::new()
+                    .name(format!("resource loader {index}"))
+                    .spawn(move || {
+                        // Ends once the pool is dropped and the queued jobs run out
+                        loop {
+                            let job = worker_jobs.lock().unwrap().recv();
+                            match job {
+                                Ok(job) => pollster::block_on(job),
+                                Err(_) => break,
+                            }
+                        }
+                    })
+                    .expect("failed to spawn resource loader thread");
+            }
+            jobs
+        };
+
+        Self {
+            #[cfg(not(target_arch = "wasm32"))]
+            jobs,
+        }
+    }
+}
+
+impl Default for LoaderPool {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Loads models and textures in the background, on a pool of worker threads or, in the
+/// browser, as async tasks. Each load returns a `LoadHandle` right away, and `poll` reports
+/// how many have finished so far, e.g. for a loading screen.
+pub struct ResourceLoader {
+    device: wgpu::Device,
+    queue: wgpu::Queue,
+    anisotropy: u16,
+    /// Shares textures and materials with earlier loads, see `with_cache`
+    cache: Option<Arc<ResourceCache>>,
+    #[cfg_attr(target_arch = "wasm32", allow(dead_code))]
+    pool: LoaderPool,
+    finished_sender: mpsc::Sender<String>,
+    finished: mpsc::Receiver<String>,
+    progress: LoadProgress,
+}
+
+impl ResourceLoader {
+    /// Textures are sampled with `anisotropy` levels of anisotropic filtering, 1 to turn it
+    /// off. Starts a `LoaderPool` of its own, see `on_pool` to share one.
+    pub fn new(device: &wgpu::Device, queue: &wgpu::Queue, anisotropy: u16) -> Self {
+        Self::on_pool(&LoaderPool::new(), device, queue, anisotropy)
+    }
+
+    /// `new` running its loads on the threads of `pool`, with a progress of its own.
+    pub fn on_pool(
+        pool: &LoaderPool,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        anisotropy: u16,
+    ) -> Self {
+        let (finished_sender, finished) = mpsc::channel();
+
+        Self {
+            device: device.clone(),
+            queue: queue.clone(),
+            anisotropy,
+            cache: None,
+            pool: pool.clone(),
+            finished_sender,
+            finished,
+            progress: LoadProgress::default(),
+        }
+    }
+
+    /// Loads textures and models through `cache`, reusing what it already holds.
+    pub fn with_cache(mut self, cache: Arc<ResourceCache>) -> Self {
+        self.cache = Some(cache);
+        self
+    }
+
+    /// Runs `load` in the background, counting it as `label` in the progress.
+    pub fn load<T, F>(&mut self, label: impl Into<String>, load: F) -> LoadHandle<T>
+    where
+        T: MaybeSend + 'static,
+        F: Future<Output = anyhow::Result<T>> + MaybeSend + 'static,
+    {
+        let label = label.into();
+        let result = Arc::new(Mutex::new(None));
+        let handle = LoadHandle {
+            result: result.clone(),
+        };
+        let finished = self.finished_sender.clone();
+        self.progress.total += 1;
+        self.spawn(async move {
+            let loaded = load.await;
+            if let Err(e) = &loaded {
+                log::error!("Unable to load {label} {e:#}");
+            }
+            *result.lock().unwrap() = Some(loaded);
+            // The loader may be gone already, then nobody is waiting on the progress
+            let _ = finished.send(label);
+        });
+        handle
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    fn spawn(&self, job: impl Future<Output = ()> + MaybeSend + 'static) {
+        if self.pool.jobs.send(Box::pin(job)).is_err() {
+            log::error!("Resource loader workers stopped");
+        }
+    }
+
+    #[cfg(target_arch = "wasm32")]
+    fn spawn(&self, job: impl Future<Output = ()> + MaybeSend + 'static) {
+        wasm_bindgen_futures::spawn_local(job);
+    }
+
+    /// Reads a file's bytes, see `resources::load_binary`.
+    pub fn load_binary(&mut self, path: &str) -> LoadHandle<Vec<u8>> {
+        let owned_path = path.to_string();
+        self.load(
+            path,
+            async move { resources::load_binary(&owned_path).await },
+        )
+    }
+
+    /// Loads a mipmapped texture from an encoded image file.
+    pub fn load_texture(
+        &mut self,
+        path: &str,
+        is_normal_map: bool,
+    ) -> LoadHandle<Arc<texture::Texture>> {
+        let (device, queue) = (self.device.clone(), self.queue.clone());
+        let anisotropy = self.anisotropy;
+        let cache = self.cache.clone();
+        let owned_path = path.to_string();
+        self.load(path, async move {
+            resources::load_texture_async(
+                &owned_path,
+                is_normal_map,
+                anisotropy,
+                &device,
+                &queue,
+                cache.as_deref(),
+            )
+            .await
+        })
+    }
+
+    /// Loads an OBJ model, see `resources::load_model`. `layout` is the materials' bind
+    /// group layout, e.g. `Renderer::texture_bind_group_layout`.
+    pub fn load_model(
+        &mut self,
+        path: &str,
+        layout: &wgpu::BindGroupLayout,
+    ) -> LoadHandle<model::Model> {
+        let (device, queue) = (self.device.clone(), self.queue.clone());
+        let anisotropy = self.anisotropy;
+        let layout = layout.clone();
+        let cache = self.cache.clone();
+        let owned_path = path.to_string();
+        self.load(path, async move {
+            match cache {
+                Some(cache) => {
+                    resources::load_model_cached(
+                        &owned_path,
+                        anisotropy,
+                        &device,
+                        &queue,
+                        &layout,
+                        &cache,
+                    )
+                    .await
+                }
+                None => {
+                    resources::load_model(&owned_path, anisotropy, &device, &queue, &layout).await
+                }
+            }
+        })
+    }
+
+    /// Counts the loads that finished since the last call and returns the progress so far.
+    pub fn poll(&mut self) -> &LoadProgress {
+        while let Ok(label) = self.finished.try_recv() {
+            log::info!("Loaded {label}");
+            self.progress.finished += 1;
+            self.progress.last = Some(label);
+        }
+        &self.progress
+    }
+
+    /// The progress as of the last `poll`.
+    pub fn progress(&self) -> &LoadProgress {
+        &self.progress
+    }
+}
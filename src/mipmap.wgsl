@@ -0,0 +1,14 @@
+// Downsamples one mip level into the next with a single fullscreen triangle
+
+// `vs_main` and `FullscreenOutput` are appended from `src/fullscreen.wgsl`
+
+@group(0) @binding(0)
+var t_src: texture_2d<f32>;
+@group(0) @binding(1)
+var s_src: sampler;
+
+@fragment
+fn fs_main(in: FullscreenOutput) -> @location(0) vec4<f32> {
+  // A bilinear tap halfway between four source texels is a 2x2 box filter
+  return textureSample(t_src, s_src, in.uv);
+}
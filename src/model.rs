@@ -1,6 +1,8 @@
-use std::ops::Range;
+use std::{ops::Range, sync::Arc};
 
-use crate::texture;
+use cgmath::{MetricSpace, Point3};
+
+use crate::{frustum::Aabb, texture, transform::ModelTransform};
 
 pub trait Vertex {
     fn desc() -> wgpu::VertexBufferLayout<'static>;
@@ -14,6 +16,12 @@ pub struct ModelVertex {
     pub normal: [f32; 3],
     pub tangent: [f32; 3],
     pub bitangent: [f32; 3],
+    /// Skeleton joints moving the vertex, see `animation::SkinUniform`
+    pub joints: [u32; 4],
+    /// How much each of `joints` moves the vertex. All zero for vertices that aren't skinned.
+    pub weights: [f32; 4],
+    /// Linear RGBA multiplied into the material's base color, white to leave it as is
+    pub color: [f32; 4],
 }
 
 impl Vertex for ModelVertex {
@@ -48,6 +56,22 @@ impl Vertex for ModelVertex {
                     shader_location: 4,
                     format: wgpu::VertexFormat::Float32x3,
                 },
+                // Locations 5 to 11 are taken by the instance data
+                wgpu::VertexAttribute {
+                    offset: mem::size_of::<[f32; 14]>() as wgpu::BufferAddress,
+                    shader_location: 12,
+                    format: wgpu::VertexFormat::Uint32x4,
+                },
+                wgpu::VertexAttribute {
+                    offset: mem::size_of::<[f32; 18]>() as wgpu::BufferAddress,
+                    shader_location: 13,
+                    format: wgpu::VertexFormat::Float32x4,
+                },
+                wgpu::VertexAttribute {
+                    offset: mem::size_of::<[f32; 22]>() as wgpu::BufferAddress,
+                    shader_location: 14,
+                    format: wgpu::VertexFormat::Float32x4,
+                },
             ],
         }
     }
@@ -55,24 +79,76 @@ impl Vertex for ModelVertex {
 
 pub struct Model {
     pub meshes: Vec<Mesh>,
-    pub materials: Vec<Material>,
+    /// Shared with other models using the same materials, e.g. through a `ResourceCache`
+    pub materials: Vec<Arc<Material>>,
+}
+
+impl Model {
+    /// Model-space bounds of all meshes.
+    pub fn bounding_box(&self) -> Aabb {
+        self.meshes
+            .iter()
+            .fold(Aabb::EMPTY, |aabb, mesh| aabb.union(mesh.bounds))
+    }
+
+    /// Model-space center and radius of a sphere around all meshes, e.g. to frame the model
+    /// with `Camera::frame_sphere`. Centered on `bounding_box`, and as large as needed for
+    /// the bounds of every mesh, which is tighter than the whole box when the meshes don't
+    /// fill its corners. A model without meshes, or only empty ones, gets a radius of 0 at
+    /// the origin.
+    pub fn bounding_sphere(&self) -> (Point3<f32>, f32) {
+        let bounds = self.bounding_box();
+        if bounds.is_empty() {
+            return (Point3::new(0.0, 0.0, 0.0), 0.0);
+        }
+        let center = bounds.center();
+        let radius = self
+            .meshes
+            .iter()
+            .filter(|mesh| !mesh.bounds.is_empty())
+            .flat_map(|mesh| mesh.bounds.corners())
+            .map(|corner| corner.distance(center))
+            .fold(0.0, f32::max);
+        (center, radius)
+    }
+
+    /// Draws `count` instances of every mesh using the per-instance data in `instance_buffer`.
+    /// The pipeline and any bind groups other than the material (group 0) must already be set.
+    pub fn draw_instanced<'a>(
+        &'a self,
+        pass: &mut wgpu::RenderPass<'a>,
+        count: u32,
+        instance_buffer: &'a wgpu::Buffer,
+    ) {
+        pass.set_vertex_buffer(1, instance_buffer.slice(..));
+        for mesh in &self.meshes {
+            pass.set_vertex_buffer(0, mesh.vertex_buffer.slice(..));
+            pass.set_index_buffer(mesh.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
+            pass.set_bind_group(0, &self.materials[mesh.material].bind_group, &[]);
+            pass.draw_indexed(0..mesh.num_elements, 0, 0..count);
+        }
+    }
 }
 
+/// Cheap to clone, the GPU resources are reference counted.
+#[derive(Clone)]
 pub struct Material {
     pub name: String,
-    pub diffuse_texture: texture::Texture,
-    pub normal_texture: texture::Texture,
+    pub diffuse_texture: Arc<texture::Texture>,
+    pub normal_texture: Arc<texture::Texture>,
     pub bind_group: wgpu::BindGroup,
 }
 
 impl Material {
+    /// The textures may be shared with other materials, e.g. through a `ResourceCache`.
     pub fn new(
         device: &wgpu::Device,
         name: &str,
-        diffuse_texture: texture::Texture,
-        normal_texture: texture::Texture,
+        diffuse_texture: impl Into<Arc<texture::Texture>>,
+        normal_texture: impl Into<Arc<texture::Texture>>,
         layout: &wgpu::BindGroupLayout,
     ) -> Self {
+        let (diffuse_texture, normal_texture) = (diffuse_texture.into(), normal_texture.into());
         let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
             layout,
             entries: &[
@@ -111,6 +187,8 @@ pub struct Mesh {
     pub index_buffer: wgpu::Buffer,
     pub num_elements: u32,
     pub material: usize,
+    /// Bounds of the vertex positions in model space
+    pub bounds: Aabb,
 }
 
 pub trait DrawModel<'a> {
@@ -130,9 +208,11 @@ pub trait DrawModel<'a> {
         light_bind_group: &'a wgpu::BindGroup,
     );
 
+    /// Draws a single `model` placed by `transform`, which takes vertex buffer slot 1.
     fn draw_model(
         &mut self,
         model: &'a Model,
+        transform: &'a ModelTransform,
         camera_bind_group: &'a wgpu::BindGroup,
         light_bind_group: &'a wgpu::BindGroup,
     );
@@ -196,9 +276,11 @@ where
     fn draw_model(
         &mut self,
         model: &'b Model,
+        transform: &'b ModelTransform,
         camera_bind_group: &'b wgpu::BindGroup,
         light_bind_group: &'b wgpu::BindGroup,
     ) {
+        self.set_vertex_buffer(1, transform.buffer().slice(..));
         for mesh in &model.meshes {
             self.draw_mesh(
                 mesh,
@@ -291,3 +373,27 @@ where
         }
     }
 }
+
+/// Draws geometry only, for passes that bind their own pipeline and bind groups
+/// such as the shadow pass.
+pub trait DrawDepth<'a> {
+    fn draw_mesh_depth_instanced(&mut self, mesh: &'a Mesh, instances: Range<u32>);
+    fn draw_model_depth_instanced(&mut self, model: &'a Model, instances: Range<u32>);
+}
+
+impl<'a, 'b> DrawDepth<'b> for wgpu::RenderPass<'a>
+where
+    'b: 'a,
+{
+    fn draw_mesh_depth_instanced(&mut self, mesh: &'b Mesh, instances: Range<u32>) {
+        self.set_vertex_buffer(0, mesh.vertex_buffer.slice(..));
+        self.set_index_buffer(mesh.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
+        self.draw_indexed(0..mesh.num_elements, 0, instances);
+    }
+
+    fn draw_model_depth_instanced(&mut self, model: &'b Model, instances: Range<u32>) {
+        for mesh in &model.meshes {
+            self.draw_mesh_depth_instanced(mesh, instances.clone());
+        }
+    }
+}
@@ -0,0 +1,612 @@
+use wgpu::util::DeviceExt as _;
+
+use crate::{
+    hot_reload::wgsl,
+    instance::{self, InstanceRaw},
+    model::{self, DrawDepth as _, Vertex},
+    render_pipeline::{PipelineDesc, create_stencil_pipeline},
+    texture,
+    transform::ModelTransform,
+    viewport::Rect,
+};
+
+/// Something in the scene that can be selected, see `Renderer::set_selected`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ObjectId {
+    /// An instance of the instanced model, by its index in `Renderer::instances`
+    Instance(usize),
+    /// A model added with `Renderer::add_model`, by the index it returned
+    Model(usize),
+}
+
+/// How `Outline` finds the outline of the selected objects.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OutlineMethod {
+    /// The objects are drawn alone into a mask of their normals and view depth, with a
+    /// depth buffer of their own, so hidden parts are outlined too. A Sobel filter over the
+    /// mask then finds their silhouettes and creases, and `color` is blended over the HDR
+    /// image there after the scene passes.
+    #[default]
+    Edges,
+    /// The objects mark the pixels they cover in the stencil buffer, then copies grown
+    /// along their normals are drawn only outside of them in the transparent pass, so just
+    /// the silhouette is outlined. The depth buffer needs a stencil aspect, e.g.
+    /// `Texture::DEPTH_STENCIL_FORMAT`.
+    Stencil,
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
+struct OutlineUniform {
+    color: [f32; 4],
+    width: f32,
+    threshold: f32,
+    target_height: f32,
+    _padding: u32,
+}
+
+/// Outlines the selected objects, e.g. for an editor, see `Renderer::set_selected` and
+/// `OutlineMethod`.
+pub struct Outline {
+    /// Linear HDR color, its alpha the outline's opacity
+    pub color: [f32; 4],
+    /// Thickness in pixels of the render target
+    pub width: f32,
+    /// Edge strength needed for a full outline with `OutlineMethod::Edges`, lower values
+    /// outline softer creases
+    pub threshold: f32,
+    method: OutlineMethod,
+    target_height: u32,
+    buffer: wgpu::Buffer,
+    bind_group: wgpu::BindGroup,
+    /// The uniform, group 1 of every pipeline
+    bind_group_layout: wgpu::BindGroupLayout,
+    mask_layout: wgpu::BindGroupLayout,
+    mask_bind_group: wgpu::BindGroup,
+    /// Normal in xyz and view depth in w of the selected objects, 0 elsewhere
+    mask: texture::Texture,
+    depth: texture::Texture,
+    /// The selected instances, refreshed by `set_instances`
+    instance_buffer: wgpu::Buffer,
+    instance_count: u32,
+    mask_pipeline: wgpu::RenderPipeline,
+    edge_pipeline: wgpu::RenderPipeline,
+    /// Marks the objects in the stencil buffer without touching color or depth, then draws
+    /// them grown, only built for `OutlineMethod::Stencil`
+    stencil_pipelines: Option<(wgpu::RenderPipeline, wgpu::RenderPipeline)>,
+}
+
+impl Outline {
+    pub const MASK_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba16Float;
+    const STENCIL_REFERENCE: u32 = 1;
+
+    /// An `OutlineMethod::Edges` outline for a `width` x `height` HDR target in
+    /// `color_format`, drawing the mask with the camera in the layout of `camera_layout`,
+    /// testing depth with `depth_compare`.
+    pub fn new(
+        device: &wgpu::Device,
+        camera_layout: &wgpu::BindGroupLayout,
+        color_format: wgpu::TextureFormat,
+        depth_compare: wgpu::CompareFunction,
+        width: u32,
+        height: u32,
+    ) -> Self {
+        let color = [1.0, 0.6, 0.1, 1.0];
+        let outline_width = 2.0;
+        let threshold = 1.0;
+        let buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Outline Buffer"),
+            contents: bytemuck::cast_slice(&[OutlineUniform {
+                color,
+                width: outline_width,
+                threshold,
+                target_height: height as f32,
+                _padding: 0,
+            }]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::VERTEX_FRAGMENT,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            }],
+            label: Some("outline_bind_group_layout"),
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &bind_group_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: buffer.as_entire_binding(),
+            }],
+            label: Some("outline_bind_group"),
+        });
+        let mask_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Texture {
+                    multisampled: false,
+                    view_dimension: wgpu::TextureViewDimension::D2,
+                    sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                },
+                count: None,
+            }],
+            label: Some("outline_mask_bind_group_layout"),
+        });
+
+        let (mask_pipeline, edge_pipeline) = Self::create_edge_pipelines(
+            device,
+            camera_layout,
+            [&mask_layout, &bind_group_layout],
+            color_format,
+            depth_compare,
+        );
+        let (mask, depth, mask_bind_group) =
+            Self::create_targets(device, &mask_layout, width, height);
+        Self {
+            color,
+            width: outline_width,
+            threshold,
+            method: OutlineMethod::Edges,
+            target_height: height,
+            buffer,
+            bind_group,
+            bind_group_layout,
+            mask_layout,
+            mask_bind_group,
+            mask,
+            depth,
+            instance_buffer: instance::create_instance_buffer(device, &[]),
+            instance_count: 0,
+            mask_pipeline,
+            edge_pipeline,
+            stencil_pipelines: None,
+        }
+    }
+
+    pub fn method(&self) -> OutlineMethod {
+        self.method
+    }
+
+    /// Switches to `method`, building the stencil pipelines for a depth buffer in
+    /// `depth_format` with `sample_count` samples the first time it is
+    /// `OutlineMethod::Stencil`. Fails if `depth_format` has no stencil aspect then.
+    pub fn set_method(
+        &mut self,
+        device: &wgpu::Device,
+        camera_layout: &wgpu::BindGroupLayout,
+        method: OutlineMethod,
+        color_format: wgpu::TextureFormat,
+        depth_format: wgpu::TextureFormat,
+        sample_count: u32,
+    ) -> anyhow::Result<()> {
+        if method == OutlineMethod::Stencil && self.stencil_pipelines.is_none() {
+            if !depth_format.has_stencil_aspect() {
+                anyhow::bail!(
+                    "stencil outlines need a stencil buffer, see `RendererOptions::stencil`"
+                );
+            }
+            self.stencil_pipelines = Some(Self::create_stencil_pipelines(
+                device,
+                camera_layout,
+                &self.bind_group_layout,
+                color_format,
+                depth_format,
+                sample_count,
+            ));
+        }
+        self.method = method;
+        Ok(())
+    }
+
+    /// The pipelines drawing the mask and blending the outline where it has edges.
+    fn create_edge_pipelines(
+        device: &wgpu::Device,
+        camera_layout: &wgpu::BindGroupLayout,
+        [mask_layout, bind_group_layout]: [&wgpu::BindGroupLayout; 2],
+        color_format: wgpu::TextureFormat,
+        depth_compare: wgpu::CompareFunction,
+    ) -> (wgpu::RenderPipeline, wgpu::RenderPipeline) {
+        let shader = device.create_shader_module(wgsl!(
+            "src/outline.wgsl",
+            "src/fullscreen.wgsl",
+            "src/prelude.wgsl"
+        ));
+        let mask_pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Outline Mask Pipeline Layout"),
+            bind_group_layouts: &[camera_layout],
+            push_constant_ranges: &[],
+        });
+        let mask_pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Outline Mask Pipeline"),
+            layout: Some(&mask_pipeline_layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: Some("vs_mask"),
+                buffers: &[model::ModelVertex::desc(), InstanceRaw::desc()],
+                compilation_options: Default::default(),
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: Some("fs_mask"),
+                targets: &[Some(Self::MASK_FORMAT.into())],
+                compilation_options: Default::default(),
+            }),
+            primitive: wgpu::PrimitiveState {
+                cull_mode: Some(wgpu::Face::Back),
+                ..Default::default()
+            },
+            depth_stencil: Some(wgpu::DepthStencilState {
+                format: texture::Texture::DEPTH_FORMAT,
+                depth_write_enabled: true,
+                depth_compare,
+                stencil: wgpu::StencilState::default(),
+                bias: wgpu::DepthBiasState::default(),
+            }),
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+            cache: None,
+        });
+        let edge_pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Outline Edge Pipeline Layout"),
+            bind_group_layouts: &[mask_layout, bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let edge_pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Outline Edge Pipeline"),
+            layout: Some(&edge_pipeline_layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: Some("vs_main"),
+                buffers: &[],
+                compilation_options: Default::default(),
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: Some("fs_edges"),
+                targets: &[Some(wgpu::ColorTargetState {
+                    format: color_format,
+                    blend: Some(wgpu::BlendState::ALPHA_BLENDING),
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+                compilation_options: Default::default(),
+            }),
+            primitive: wgpu::PrimitiveState::default(),
+            depth_stencil: None,
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+            cache: None,
+        });
+        (mask_pipeline, edge_pipeline)
+    }
+
+    /// The pipelines marking the objects in the stencil buffer and drawing them grown.
+    fn create_stencil_pipelines(
+        device: &wgpu::Device,
+        camera_layout: &wgpu::BindGroupLayout,
+        bind_group_layout: &wgpu::BindGroupLayout,
+        color_format: wgpu::TextureFormat,
+        depth_format: wgpu::TextureFormat,
+        sample_count: u32,
+    ) -> (wgpu::RenderPipeline, wgpu::RenderPipeline) {
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Outline Stencil Pipeline Layout"),
+            bind_group_layouts: &[camera_layout, bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let stencil = |compare, pass_op| {
+            let face = wgpu::StencilFaceState {
+                compare,
+                fail_op: wgpu::StencilOperation::Keep,
+                depth_fail_op: wgpu::StencilOperation::Keep,
+                pass_op,
+            };
+            wgpu::StencilState {
+                front: face,
+                back: face,
+                read_mask: 0xff,
+                write_mask: 0xff,
+            }
+        };
+        // Both ignore depth, so the outline shows through whatever is in front
+        let create_pipeline = |stencil, vertex_entry, color_writes| {
+            create_stencil_pipeline(
+                device,
+                &layout,
+                color_format,
+                wgpu::DepthStencilState {
+                    format: depth_format,
+                    depth_write_enabled: false,
+                    depth_compare: wgpu::CompareFunction::Always,
+                    stencil,
+                    bias: wgpu::DepthBiasState::default(),
+                },
+                &[model::ModelVertex::desc(), InstanceRaw::desc()],
+                wgsl!(
+                    "src/outline.wgsl",
+                    "src/fullscreen.wgsl",
+                    "src/prelude.wgsl"
+                ),
+                PipelineDesc {
+                    sample_count,
+                    color_writes,
+                    vertex_entry,
+                    fragment_entry: "fs_color",
+                    ..Default::default()
+                },
+            )
+        };
+        let mask_pipeline = create_pipeline(
+            stencil(
+                wgpu::CompareFunction::Always,
+                wgpu::StencilOperation::Replace,
+            ),
+            "vs_mask_stencil",
+            wgpu::ColorWrites::empty(),
+        );
+        let pipeline = create_pipeline(
+            stencil(
+                wgpu::CompareFunction::NotEqual,
+                wgpu::StencilOperation::Keep,
+            ),
+            "vs_grown",
+            wgpu::ColorWrites::ALL,
+        );
+        (mask_pipeline, pipeline)
+    }
+
+    /// Rebuilds the pipelines from the current shader, see `hot_reload`, or after the MSAA
+    /// sample count or depth buffer changed.
+    pub fn recreate_pipelines(
+        &mut self,
+        device: &wgpu::Device,
+        camera_layout: &wgpu::BindGroupLayout,
+        color_format: wgpu::TextureFormat,
+        depth_format: wgpu::TextureFormat,
+        depth_compare: wgpu::CompareFunction,
+        sample_count: u32,
+    ) {
+        (self.mask_pipeline, self.edge_pipeline) = Self::create_edge_pipelines(
+            device,
+            camera_layout,
+            [&self.mask_layout, &self.bind_group_layout],
+            color_format,
+            depth_compare,
+        );
+        if self.stencil_pipelines.is_none() {
+            return;
+        }
+        if depth_format.has_stencil_aspect() {
+            self.stencil_pipelines = Some(Self::create_stencil_pipelines(
+                device,
+                camera_layout,
+                &self.bind_group_layout,
+                color_format,
+                depth_format,
+                sample_count,
+            ));
+        } else {
+            log::warn!("The depth buffer lost its stencil aspect, outlining edges instead");
+            self.stencil_pipelines = None;
+            self.method = OutlineMethod::Edges;
+        }
+    }
+
+    fn create_targets(
+        device: &wgpu::Device,
+        mask_layout: &wgpu::BindGroupLayout,
+        width: u32,
+        height: u32,
+    ) -> (texture::Texture, texture::Texture, wgpu::BindGroup) {
+        let size = wgpu::Extent3d {
+            width: width.max(1),
+            height: height.max(1),
+            depth_or_array_layers: 1,
+        };
+        let target = |label, format| {
+            texture::Texture::create_texture(
+                device,
+                Some(label),
+                size,
+                format,
+                wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+                wgpu::TextureDimension::D2,
+                wgpu::FilterMode::Nearest,
+            )
+        };
+        let mask = target("outline_mask_texture", Self::MASK_FORMAT);
+        let depth = target("outline_depth_texture", texture::Texture::DEPTH_FORMAT);
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: mask_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: wgpu::BindingResource::TextureView(&mask.view),
+            }],
+            label: Some("outline_mask_bind_group"),
+        });
+        (mask, depth, bind_group)
+    }
+
+    /// Recreates the mask for a `width` x `height` HDR target.
+    pub fn resize(&mut self, device: &wgpu::Device, width: u32, height: u32) {
+        (self.mask, self.depth, self.mask_bind_group) =
+            Self::create_targets(device, &self.mask_layout, width, height);
+        self.target_height = height;
+    }
+
+    /// Uploads `color`, `width` and `threshold`.
+    pub fn upload(&self, queue: &wgpu::Queue) {
+        queue.write_buffer(
+            &self.buffer,
+            0,
+            bytemuck::cast_slice(&[OutlineUniform {
+                color: self.color,
+                width: self.width,
+                threshold: self.threshold,
+                target_height: self.target_height as f32,
+                _padding: 0,
+            }]),
+        );
+    }
+
+    /// Uploads the selected instances, growing the buffer if there are more than before.
+    pub fn set_instances(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        instances: &[InstanceRaw],
+    ) {
+        let size = std::mem::size_of_val(instances) as wgpu::BufferAddress;
+        if size > self.instance_buffer.size() {
+            self.instance_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: Some("Selected Instance Buffer"),
+                contents: bytemuck::cast_slice(instances),
+                usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+            });
+        } else {
+            queue.write_buffer(&self.instance_buffer, 0, bytemuck::cast_slice(instances));
+        }
+        self.instance_count = instances.len() as u32;
+    }
+
+    /// Number of selected instances uploaded by `set_instances`.
+    pub fn instance_count(&self) -> u32 {
+        self.instance_count
+    }
+
+    /// Draws the selected instances of `instanced` and the selected `models` with the
+    /// pipeline set, returning the number of draw calls.
+    fn draw_selected<'a>(
+        &'a self,
+        pass: &mut wgpu::RenderPass<'a>,
+        instanced: &'a model::Model,
+        models: &[(&'a model::Model, &'a ModelTransform)],
+    ) -> u32 {
+        let mut draws = 0;
+        if self.instance_count > 0 {
+            pass.set_vertex_buffer(1, self.instance_buffer.slice(..));
+            pass.draw_model_depth_instanced(instanced, 0..self.instance_count);
+            draws += instanced.meshes.len() as u32;
+        }
+        for (model, transform) in models {
+            pass.set_vertex_buffer(1, transform.buffer().slice(..));
+            pass.draw_model_depth_instanced(model, 0..1);
+            draws += model.meshes.len() as u32;
+        }
+        draws
+    }
+
+    /// With `OutlineMethod::Stencil`, draws the outline around the selected instances of
+    /// `instanced` and the selected `models` and returns the number of draw calls. The
+    /// pass's stencil buffer must not be marked yet where they are. Changes bind groups 0
+    /// and 1.
+    pub fn draw_stencil<'a>(
+        &'a self,
+        pass: &mut wgpu::RenderPass<'a>,
+        camera_bind_group: &'a wgpu::BindGroup,
+        instanced: &'a model::Model,
+        models: &[(&'a model::Model, &'a ModelTransform)],
+    ) -> u32 {
+        let Some((mask_pipeline, pipeline)) = self
+            .stencil_pipelines
+            .as_ref()
+            .filter(|_| self.method == OutlineMethod::Stencil)
+        else {
+            return 0;
+        };
+        pass.set_bind_group(0, camera_bind_group, &[]);
+        pass.set_bind_group(1, &self.bind_group, &[]);
+        pass.set_stencil_reference(Self::STENCIL_REFERENCE);
+        let mut draws = 0;
+        for pipeline in [mask_pipeline, pipeline] {
+            pass.set_pipeline(pipeline);
+            draws += self.draw_selected(pass, instanced, models);
+        }
+        draws
+    }
+
+    /// With `OutlineMethod::Edges`, records drawing the selected instances of `instanced`
+    /// and the selected `models` into the mask, once per camera bind group and the part of
+    /// the target it projects into, then blending the outline over `target`. `far_depth` is
+    /// what the depth buffer is cleared to. Returns the number of draw calls.
+    pub fn apply_edges(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        views: &[(&wgpu::BindGroup, Option<Rect>)],
+        instanced: &model::Model,
+        models: &[(&model::Model, &ModelTransform)],
+        far_depth: f32,
+        target: &wgpu::TextureView,
+    ) -> u32 {
+        if self.method != OutlineMethod::Edges {
+            return 0;
+        }
+        let mut draws = 0;
+        let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Outline Mask Pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: &self.mask.view,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(wgpu::Color::TRANSPARENT),
+                    store: wgpu::StoreOp::Store,
+                },
+                depth_slice: None,
+            })],
+            depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                view: &self.depth.view,
+                depth_ops: Some(wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(far_depth),
+                    store: wgpu::StoreOp::Discard,
+                }),
+                stencil_ops: None,
+            }),
+            timestamp_writes: None,
+            occlusion_query_set: None,
+        });
+        pass.set_pipeline(&self.mask_pipeline);
+        for (camera_bind_group, rect) in views {
+            if let Some(rect) = rect {
+                pass.set_viewport(
+                    rect.x as f32,
+                    rect.y as f32,
+                    rect.width as f32,
+                    rect.height as f32,
+                    0.0,
+                    1.0,
+                );
+                pass.set_scissor_rect(rect.x, rect.y, rect.width, rect.height);
+            }
+            pass.set_bind_group(0, *camera_bind_group, &[]);
+            draws += self.draw_selected(&mut pass, instanced, models);
+        }
+        drop(pass);
+
+        let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Outline Edge Pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: target,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Load,
+                    store: wgpu::StoreOp::Store,
+                },
+                depth_slice: None,
+            })],
+            depth_stencil_attachment: None,
+            timestamp_writes: None,
+            occlusion_query_set: None,
+        });
+        pass.set_pipeline(&self.edge_pipeline);
+        pass.set_bind_group(0, &self.mask_bind_group, &[]);
+        pass.set_bind_group(1, &self.bind_group, &[]);
+        pass.draw(0..3, 0..1);
+        draws + 1
+    }
+}
@@ -0,0 +1,141 @@
+// Outlines the selected objects, see `OutlineMethod`. With edges, `vs_mask` draws them
+// alone into a texture of their normals and view depth, then `fs_edges` blends the outline
+// color over the scene where a Sobel filter finds edges in it. With the stencil, they mark
+// their pixels in the stencil buffer with `vs_mask_stencil`, then `vs_grown` draws them
+// grown along their normals where nothing is marked.
+
+// `CameraUniform` and the other shared types are declared in `src/prelude.wgsl`
+
+struct Outline {
+  color: vec4<f32>,
+  // Pixels of the render target, the filter's sample spacing or how far objects grow
+  width: f32,
+  // Edge strength at which pixels are fully outlined
+  threshold: f32,
+  // Height of the render target in pixels
+  target_height: f32,
+};
+
+struct VertexInput {
+  @location(0) position: vec3<f32>,
+  @location(2) normal: vec3<f32>,
+};
+
+struct InstanceInput {
+  @location(5) model_matrix_0: vec4<f32>,
+  @location(6) model_matrix_1: vec4<f32>,
+  @location(7) model_matrix_2: vec4<f32>,
+  @location(8) model_matrix_3: vec4<f32>,
+  @location(9) normal_matrix_0: vec3<f32>,
+  @location(10) normal_matrix_1: vec3<f32>,
+  @location(11) normal_matrix_2: vec3<f32>,
+};
+
+fn model_matrix(instance: InstanceInput) -> mat4x4<f32> {
+  return mat4x4<f32>(
+    instance.model_matrix_0,
+    instance.model_matrix_1,
+    instance.model_matrix_2,
+    instance.model_matrix_3,
+  );
+}
+
+@group(0) @binding(0)
+var<uniform> camera: CameraUniform;
+@group(1) @binding(0)
+var<uniform> outline: Outline;
+
+struct MaskOutput {
+  @builtin(position) pos: vec4<f32>,
+  @location(0) world_position: vec3<f32>,
+  @location(1) world_normal: vec3<f32>,
+};
+
+@vertex
+fn vs_mask(model: VertexInput, instance: InstanceInput) -> MaskOutput {
+  let normal_matrix = mat3x3<f32>(
+    instance.normal_matrix_0,
+    instance.normal_matrix_1,
+    instance.normal_matrix_2,
+  );
+  let world_position = model_matrix(instance) * vec4<f32>(model.position, 1.0);
+  var out: MaskOutput;
+  out.pos = camera.view_proj * world_position;
+  out.world_position = world_position.xyz;
+  out.world_normal = normal_matrix * model.normal;
+  return out;
+}
+
+// Depth is positive in front of the camera, so 0 marks pixels without a selected object
+@fragment
+fn fs_mask(in: MaskOutput) -> @location(0) vec4<f32> {
+  let view_depth = -(camera.view * vec4<f32>(in.world_position, 1.0)).z;
+  return vec4<f32>(normalize(in.world_normal), view_depth);
+}
+
+// The edge pass runs the fullscreen `vs_main` appended from `src/fullscreen.wgsl`
+
+@group(0) @binding(0)
+var t_mask: texture_2d<f32>;
+
+@fragment
+fn fs_edges(@builtin(position) pos: vec4<f32>) -> @location(0) vec4<f32> {
+  let center = vec2<i32>(pos.xy);
+  let last = vec2<i32>(textureDimensions(t_mask)) - 1;
+  let step = max(i32(outline.width), 1);
+
+  var samples: array<vec4<f32>, 9>;
+  var max_depth = 0.0;
+  for (var i = 0; i < 9; i += 1) {
+    let offset = vec2<i32>(i % 3 - 1, i / 3 - 1) * step;
+    samples[i] = textureLoad(t_mask, clamp(center + offset, vec2<i32>(0), last), 0);
+    max_depth = max(max_depth, samples[i].w);
+  }
+  if max_depth == 0.0 {
+    discard;
+  }
+
+  // Sobel kernels, row by row from the top left
+  let kernel_x = array<f32, 9>(-1.0, 0.0, 1.0, -2.0, 0.0, 2.0, -1.0, 0.0, 1.0);
+  let kernel_y = array<f32, 9>(-1.0, -2.0, -1.0, 0.0, 0.0, 0.0, 1.0, 2.0, 1.0);
+  var gradient_x = vec4<f32>(0.0);
+  var gradient_y = vec4<f32>(0.0);
+  for (var i = 0; i < 9; i += 1) {
+    // Depth relative to the nearby surfaces, so the outline doesn't fade with distance
+    let sample = vec4<f32>(samples[i].xyz, samples[i].w / max_depth);
+    gradient_x += sample * kernel_x[i];
+    gradient_y += sample * kernel_y[i];
+  }
+  let normal_edge = sqrt(dot(gradient_x.xyz, gradient_x.xyz) + dot(gradient_y.xyz, gradient_y.xyz));
+  let depth_edge = length(vec2<f32>(gradient_x.w, gradient_y.w));
+  let edge = smoothstep(0.5 * outline.threshold, outline.threshold, max(normal_edge, depth_edge));
+  return vec4<f32>(outline.color.rgb, outline.color.a * edge);
+}
+
+@vertex
+fn vs_mask_stencil(model: VertexInput, instance: InstanceInput) -> @builtin(position) vec4<f32> {
+  return camera.view_proj * model_matrix(instance) * vec4<f32>(model.position, 1.0);
+}
+
+@vertex
+fn vs_grown(model: VertexInput, instance: InstanceInput) -> @builtin(position) vec4<f32> {
+  let model_matrix = model_matrix(instance);
+  var clip = camera.view_proj * model_matrix * vec4<f32>(model.position, 1.0);
+  let normal = camera.view_proj * model_matrix * vec4<f32>(model.normal, 0.0);
+
+  // Grows the same on screen at any distance, in the normal's direction on screen where
+  // x and y units are equally long
+  let aspect = camera.inv_proj[0][0] / camera.inv_proj[1][1];
+  let screen_normal = normal.xy * vec2<f32>(aspect, 1.0);
+  if length(screen_normal) > 1e-6 {
+    let width = 2.0 * outline.width / outline.target_height;
+    let offset = normalize(screen_normal) * vec2<f32>(1.0 / aspect, 1.0) * width;
+    clip = vec4<f32>(clip.xy + offset * clip.w, clip.zw);
+  }
+  return clip;
+}
+
+@fragment
+fn fs_color() -> @location(0) vec4<f32> {
+  return outline.color;
+}
@@ -0,0 +1,214 @@
+use std::{collections::VecDeque, time::Duration};
+
+use wgpu_text::{
+    BrushBuilder, TextBrush,
+    glyph_brush::{HorizontalAlign, Layout, Section, Text, VerticalAlign, ab_glyph::FontRef},
+};
+
+use crate::loader::LoadProgress;
+
+const FONT: &[u8] = include_bytes!("../res/DejaVuSansMono.ttf");
+
+/// Frame times over a sliding window, so the displayed numbers don't flicker.
+pub struct FrameStats {
+    frame_times: VecDeque<Duration>,
+    capacity: usize,
+}
+
+impl FrameStats {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            frame_times: VecDeque::with_capacity(capacity),
+            capacity: capacity.max(1),
+        }
+    }
+
+    pub fn record(&mut self, dt: Duration) {
+        if self.frame_times.len() == self.capacity {
+            self.frame_times.pop_front();
+        }
+        self.frame_times.push_back(dt);
+    }
+
+    /// Mean frame time over the window, zero before the first frame.
+    pub fn average_frame_time(&self) -> Duration {
+        if self.frame_times.is_empty() {
+            return Duration::ZERO;
+        }
+        self.frame_times.iter().sum::<Duration>() / self.frame_times.len() as u32
+    }
+
+    pub fn fps(&self) -> f32 {
+        let frame_time = self.average_frame_time().as_secs_f32();
+        if frame_time > 0.0 {
+            1.0 / frame_time
+        } else {
+            0.0
+        }
+    }
+}
+
+/// What the last frame drew, see `Renderer::render_stats`.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct RenderStats {
+    pub draw_calls: u32,
+    /// Triangles of the models, instances and terrain chunks drawn, once per pass drawing
+    /// them, e.g. again for every shadow cascade. Sky, grid, particles and other helpers
+    /// only count as draw calls.
+    pub triangles: u64,
+    /// Instances of those models, terrain chunks counting as one each
+    pub instances: u64,
+    /// GPU time of all timed passes of a recent frame, `None` while GPU timing is
+    /// unsupported or nothing was measured yet
+    pub gpu_time_ms: Option<f32>,
+}
+
+/// On-screen FPS, frame time, draw call, triangle and GPU time counter drawn in the top
+/// left corner.
+pub struct StatsOverlay {
+    brush: TextBrush<FontRef<'static>>,
+    pub stats: FrameStats,
+    visible: bool,
+}
+
+impl StatsOverlay {
+    /// Number of frames averaged, about a second at 60 Hz
+    pub const HISTORY: usize = 60;
+
+    /// `color_format` is the format of the single-sampled target the overlay is drawn onto.
+    pub fn new(
+        device: &wgpu::Device,
+        width: u32,
+        height: u32,
+        color_format: wgpu::TextureFormat,
+    ) -> Self {
+        let brush = BrushBuilder::using_font_bytes(FONT)
+            .expect("bundled font is valid")
+            .build(device, width, height, color_format);
+        Self {
+            brush,
+            stats: FrameStats::new(Self::HISTORY),
+            visible: false,
+        }
+    }
+
+    pub fn visible(&self) -> bool {
+        self.visible
+    }
+
+    pub fn set_visible(&mut self, visible: bool) {
+        self.visible = visible;
+    }
+
+    pub fn resize(&self, queue: &wgpu::Queue, width: u32, height: u32) {
+        self.brush.resize_view(width as f32, height as f32, queue);
+    }
+
+    /// Lays out the text for the next `draw`. Does nothing while hidden.
+    pub fn prepare(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        render_stats: &RenderStats,
+    ) {
+        if !self.visible {
+            return;
+        }
+        let mut text = format!(
+            "{:.0} FPS\n{:.2} ms\n{} draws\n{} triangles",
+            self.stats.fps(),
+            self.stats.average_frame_time().as_secs_f64() * 1000.0,
+            render_stats.draw_calls,
+            render_stats.triangles,
+        );
+        if let Some(gpu_time_ms) = render_stats.gpu_time_ms {
+            text += &format!("\n{gpu_time_ms:.2} ms GPU");
+        }
+        let section = Section::default()
+            .add_text(
+                Text::new(&text)
+                    .with_scale(18.0)
+                    .with_color([1.0, 1.0, 1.0, 1.0]),
+            )
+            .with_screen_position((10.0, 10.0));
+        if let Err(e) = self.brush.queue(device, queue, [&section]) {
+            log::error!("Unable to lay out stats overlay {}", e);
+        }
+    }
+
+    /// Draws the text laid out by the last `prepare` into a pass without depth or MSAA.
+    pub fn draw<'a>(&'a self, pass: &mut wgpu::RenderPass<'a>) {
+        if self.visible {
+            self.brush.draw(pass);
+        }
+    }
+}
+
+/// Progress of a `ResourceLoader` drawn as text in the middle of an otherwise empty frame,
+/// shown by `WindowState` until the loads finish.
+pub struct LoadingScreen {
+    brush: TextBrush<FontRef<'static>>,
+    width: u32,
+    height: u32,
+}
+
+impl LoadingScreen {
+    /// Characters across the progress bar
+    const BAR_WIDTH: usize = 30;
+
+    /// `color_format` is the format of the single-sampled target the screen is drawn onto.
+    pub fn new(
+        device: &wgpu::Device,
+        width: u32,
+        height: u32,
+        color_format: wgpu::TextureFormat,
+    ) -> Self {
+        let brush = BrushBuilder::using_font_bytes(FONT)
+            .expect("bundled font is valid")
+            .build(device, width, height, color_format);
+        Self {
+            brush,
+            width,
+            height,
+        }
+    }
+
+    pub fn resize(&mut self, queue: &wgpu::Queue, width: u32, height: u32) {
+        self.width = width;
+        self.height = height;
+        self.brush.resize_view(width as f32, height as f32, queue);
+    }
+
+    /// Lays out the text for `progress` for the next `draw`.
+    pub fn prepare(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, progress: &LoadProgress) {
+        let filled = (progress.fraction() * Self::BAR_WIDTH as f32).round() as usize;
+        let text = format!(
+            "Loading {}/{}\n[{}{}]\n{}",
+            progress.finished,
+            progress.total,
+            "#".repeat(filled),
+            "-".repeat(Self::BAR_WIDTH - filled),
+            progress.last.as_deref().unwrap_or(""),
+        );
+        let section = Section::default()
+            .add_text(
+                Text::new(&text)
+                    .with_scale(20.0)
+                    .with_color([1.0, 1.0, 1.0, 1.0]),
+            )
+            .with_screen_position((self.width as f32 * 0.5, self.height as f32 * 0.5))
+            .with_layout(
+                Layout::default()
+                    .h_align(HorizontalAlign::Center)
+                    .v_align(VerticalAlign::Center),
+            );
+        if let Err(e) = self.brush.queue(device, queue, [&section]) {
+            log::error!("Unable to lay out loading screen {}", e);
+        }
+    }
+
+    /// Draws the text laid out by the last `prepare` into a pass without depth or MSAA.
+    pub fn draw<'a>(&'a self, pass: &mut wgpu::RenderPass<'a>) {
+        self.brush.draw(pass);
+    }
+}
@@ -0,0 +1,503 @@
+use std::time::Duration;
+
+use cgmath::{Point3, Vector3};
+use wgpu::util::DeviceExt as _;
+
+use crate::{hot_reload::wgsl, model::Vertex};
+
+/// How a `ParticleSystem` moves and looks, see `rain` and `snow` for presets.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ParticleSettings {
+    /// Particles simulated and drawn at once
+    pub count: u32,
+    /// Acceleration in world units per second squared
+    pub gravity: Vector3<f32>,
+    /// How quickly particles settle into moving with the wind, per second. Together with
+    /// `gravity` it sets how fast they fall, `gravity / drag`.
+    pub drag: f32,
+    /// Velocity of the air in world units per second
+    pub wind: Vector3<f32>,
+    /// Half the width along x and z of the box around the camera particles spawn in
+    pub half_width: f32,
+    /// Half the height of the spawn box, centered on the camera
+    pub half_height: f32,
+    /// Width of a particle in world units
+    pub size: f32,
+    /// Draws particles as streaks this many world units long per unit of speed, like rain,
+    /// instead of round flakes
+    pub stretch: Option<f32>,
+    /// Sideways wobble in world units per second
+    pub sway: f32,
+    /// Linear HDR color, alpha is the coverage at the center of a particle
+    pub color: [f32; 4],
+}
+
+impl ParticleSettings {
+    /// Fast, thin streaks slanted by a light breeze.
+    pub fn rain() -> Self {
+        Self {
+            count: 20_000,
+            gravity: Vector3::new(0.0, -9.81, 0.0),
+            drag: 1.0,
+            wind: Vector3::new(1.5, 0.0, 0.5),
+            half_width: 20.0,
+            half_height: 12.0,
+            size: 0.02,
+            stretch: Some(0.03),
+            sway: 0.0,
+            color: [0.6, 0.65, 0.75, 0.35],
+        }
+    }
+
+    /// Slowly drifting, wobbling flakes.
+    pub fn snow() -> Self {
+        Self {
+            count: 15_000,
+            gravity: Vector3::new(0.0, -9.81, 0.0),
+            drag: 8.0,
+            wind: Vector3::new(0.6, 0.0, 0.2),
+            half_width: 20.0,
+            half_height: 12.0,
+            size: 0.08,
+            stretch: None,
+            sway: 0.4,
+            color: [0.95, 0.95, 1.0, 0.9],
+        }
+    }
+}
+
+impl Default for ParticleSettings {
+    fn default() -> Self {
+        Self::rain()
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
+struct ParticlesUniform {
+    center: [f32; 3],
+    dt: f32,
+    gravity: [f32; 3],
+    drag: f32,
+    wind: [f32; 3],
+    frame: u32,
+    extent: [f32; 2],
+    size: [f32; 2],
+    color: [f32; 4],
+    sway: f32,
+    stretch: u32,
+    count: u32,
+    has_ground: u32,
+    ground_origin: [f32; 2],
+    ground_spacing: f32,
+    ground_resolution: u32,
+}
+
+/// One particle as the compute pass stores it and the render pass reads it per instance.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
+struct Particle {
+    /// w is 0 until spawned, then the age in seconds
+    position: [f32; 4],
+    /// w is a random phase for swaying
+    velocity: [f32; 4],
+}
+
+impl Vertex for Particle {
+    fn desc() -> wgpu::VertexBufferLayout<'static> {
+        const ATTRIBUTES: [wgpu::VertexAttribute; 2] =
+            wgpu::vertex_attr_array![0 => Float32x4, 1 => Float32x4];
+        wgpu::VertexBufferLayout {
+            array_stride: std::mem::size_of::<Particle>() as wgpu::BufferAddress,
+            step_mode: wgpu::VertexStepMode::Instance,
+            attributes: &ATTRIBUTES,
+        }
+    }
+}
+
+/// Weather particles, e.g. rain or snow, in a box following the camera. A compute pass
+/// integrates their positions and velocities in a storage buffer, which the transparent
+/// pass then draws from as camera facing quads. Particles wrap around the sides of the
+/// box as it moves and respawn at its top once they fall below the ground, see
+/// `sample_ground`, or its bottom.
+pub struct ParticleSystem {
+    device: wgpu::Device,
+    queue: wgpu::Queue,
+    settings: ParticleSettings,
+    /// Center of the spawn box, the renderer keeps it on the camera
+    pub center: Point3<f32>,
+    /// Where `ground_buffer` was sampled, origin and spacing, `None` without ground
+    ground: Option<([f32; 2], f32)>,
+    frame: u32,
+    uniform_buffer: wgpu::Buffer,
+    particle_buffer: wgpu::Buffer,
+    ground_buffer: wgpu::Buffer,
+    compute_bind_group_layout: wgpu::BindGroupLayout,
+    compute_bind_group: wgpu::BindGroup,
+    compute_pipeline: wgpu::ComputePipeline,
+    render_bind_group_layout: wgpu::BindGroupLayout,
+    render_bind_group: wgpu::BindGroup,
+    render_pipeline: wgpu::RenderPipeline,
+}
+
+impl ParticleSystem {
+    /// Ground heights sampled per side of the spawn box
+    pub const GROUND_RESOLUTION: u32 = 17;
+    const WORKGROUP_SIZE: u32 = 64;
+
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        camera_bind_group_layout: &wgpu::BindGroupLayout,
+        color_format: wgpu::TextureFormat,
+        depth_format: wgpu::TextureFormat,
+        depth_compare: wgpu::CompareFunction,
+        sample_count: u32,
+        settings: ParticleSettings,
+    ) -> Self {
+        let uniform_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Particles Uniform Buffer"),
+            size: std::mem::size_of::<ParticlesUniform>() as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let particle_buffer = Self::create_particle_buffer(device, settings.count);
+        let ground_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Particles Ground Buffer"),
+            contents: bytemuck::cast_slice(
+                &[0.0f32; (Self::GROUND_RESOLUTION * Self::GROUND_RESOLUTION) as usize],
+            ),
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+        });
+
+        let buffer_entry = |binding, visibility, ty| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility,
+            ty: wgpu::BindingType::Buffer {
+                ty,
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+        let compute_bind_group_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                entries: &[
+                    buffer_entry(
+                        0,
+                        wgpu::ShaderStages::COMPUTE,
+                        wgpu::BufferBindingType::Uniform,
+                    ),
+                    buffer_entry(
+                        1,
+                        wgpu::ShaderStages::COMPUTE,
+                        wgpu::BufferBindingType::Storage { read_only: false },
+                    ),
+                    buffer_entry(
+                        2,
+                        wgpu::ShaderStages::COMPUTE,
+                        wgpu::BufferBindingType::Storage { read_only: true },
+                    ),
+                ],
+                label: Some("particles_compute_bind_group_layout"),
+            });
+        let render_bind_group_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                entries: &[buffer_entry(
+                    0,
+                    wgpu::ShaderStages::VERTEX_FRAGMENT,
+                    wgpu::BufferBindingType::Uniform,
+                )],
+                label: Some("particles_render_bind_group_layout"),
+            });
+        let render_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &render_bind_group_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: uniform_buffer.as_entire_binding(),
+            }],
+            label: Some("particles_render_bind_group"),
+        });
+        let compute_bind_group = Self::create_compute_bind_group(
+            device,
+            &compute_bind_group_layout,
+            &uniform_buffer,
+            &particle_buffer,
+            &ground_buffer,
+        );
+
+        let shader = device.create_shader_module(wgsl!("src/particles.wgsl", "src/prelude.wgsl"));
+        let compute_pipeline_layout =
+            device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: Some("Particles Compute Pipeline Layout"),
+                bind_group_layouts: &[&compute_bind_group_layout],
+                push_constant_ranges: &[],
+            });
+        let compute_pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: Some("Particles Compute Pipeline"),
+            layout: Some(&compute_pipeline_layout),
+            module: &shader,
+            entry_point: Some("simulate"),
+            compilation_options: Default::default(),
+            cache: None,
+        });
+        let render_pipeline = Self::create_render_pipeline(
+            device,
+            camera_bind_group_layout,
+            &render_bind_group_layout,
+            color_format,
+            depth_format,
+            depth_compare,
+            sample_count,
+        );
+
+        Self {
+            device: device.clone(),
+            queue: queue.clone(),
+            settings,
+            center: Point3::new(0.0, 0.0, 0.0),
+            ground: None,
+            frame: 0,
+            uniform_buffer,
+            particle_buffer,
+            ground_buffer,
+            compute_bind_group_layout,
+            compute_bind_group,
+            compute_pipeline,
+            render_bind_group_layout,
+            render_bind_group,
+            render_pipeline,
+        }
+    }
+
+    /// Rebuilds the render pipeline, e.g. after the MSAA sample count changed.
+    pub fn recreate_pipeline(
+        &mut self,
+        camera_bind_group_layout: &wgpu::BindGroupLayout,
+        color_format: wgpu::TextureFormat,
+        depth_format: wgpu::TextureFormat,
+        depth_compare: wgpu::CompareFunction,
+        sample_count: u32,
+    ) {
+        self.render_pipeline = Self::create_render_pipeline(
+            &self.device,
+            camera_bind_group_layout,
+            &self.render_bind_group_layout,
+            color_format,
+            depth_format,
+            depth_compare,
+            sample_count,
+        );
+    }
+
+    fn create_particle_buffer(device: &wgpu::Device, count: u32) -> wgpu::Buffer {
+        // Zeroed, so every particle spawns on the first update
+        device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Particle Buffer"),
+            size: (count.max(1) as usize * std::mem::size_of::<Particle>()) as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::VERTEX,
+            mapped_at_creation: false,
+        })
+    }
+
+    fn create_compute_bind_group(
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        uniform_buffer: &wgpu::Buffer,
+        particle_buffer: &wgpu::Buffer,
+        ground_buffer: &wgpu::Buffer,
+    ) -> wgpu::BindGroup {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: uniform_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: particle_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: ground_buffer.as_entire_binding(),
+                },
+            ],
+            label: Some("particles_compute_bind_group"),
+        })
+    }
+
+    fn create_render_pipeline(
+        device: &wgpu::Device,
+        camera_bind_group_layout: &wgpu::BindGroupLayout,
+        bind_group_layout: &wgpu::BindGroupLayout,
+        color_format: wgpu::TextureFormat,
+        depth_format: wgpu::TextureFormat,
+        depth_compare: wgpu::CompareFunction,
+        sample_count: u32,
+    ) -> wgpu::RenderPipeline {
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Particles Render Pipeline Layout"),
+            bind_group_layouts: &[camera_bind_group_layout, bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let shader = device.create_shader_module(wgsl!("src/particles.wgsl", "src/prelude.wgsl"));
+        device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Particles Render Pipeline"),
+            layout: Some(&layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: Some("vs_main"),
+                buffers: &[Particle::desc()],
+                compilation_options: Default::default(),
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: Some("fs_main"),
+                targets: &[Some(wgpu::ColorTargetState {
+                    format: color_format,
+                    blend: Some(wgpu::BlendState::PREMULTIPLIED_ALPHA_BLENDING),
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+                compilation_options: Default::default(),
+            }),
+            primitive: wgpu::PrimitiveState {
+                topology: wgpu::PrimitiveTopology::TriangleStrip,
+                ..Default::default()
+            },
+            // Tested against the scene but not written, the particles don't hide each other
+            depth_stencil: Some(wgpu::DepthStencilState {
+                format: depth_format,
+                depth_write_enabled: false,
+                depth_compare,
+                stencil: wgpu::StencilState::default(),
+                bias: wgpu::DepthBiasState::default(),
+            }),
+            multisample: wgpu::MultisampleState {
+                count: sample_count,
+                ..Default::default()
+            },
+            multiview: None,
+            cache: None,
+        })
+    }
+
+    pub fn settings(&self) -> ParticleSettings {
+        self.settings
+    }
+
+    /// Changing `count` starts over with new particles.
+    pub fn set_settings(&mut self, settings: ParticleSettings) {
+        if settings.count != self.settings.count {
+            self.particle_buffer = Self::create_particle_buffer(&self.device, settings.count);
+            self.compute_bind_group = Self::create_compute_bind_group(
+                &self.device,
+                &self.compute_bind_group_layout,
+                &self.uniform_buffer,
+                &self.particle_buffer,
+                &self.ground_buffer,
+            );
+        }
+        self.settings = settings;
+    }
+
+    /// Number of particles simulated and drawn.
+    pub fn count(&self) -> u32 {
+        self.settings.count
+    }
+
+    /// Samples the ground under the spawn box around `center` from `height`, which is
+    /// `None` where there is no ground, e.g. terrain that isn't loaded. Particles falling
+    /// below it respawn. Call again whenever `center` moves.
+    pub fn sample_ground(&mut self, height: impl Fn(f32, f32) -> Option<f32>) {
+        let resolution = Self::GROUND_RESOLUTION;
+        let spacing = 2.0 * self.settings.half_width / (resolution - 1) as f32;
+        let origin = [
+            self.center.x - self.settings.half_width,
+            self.center.z - self.settings.half_width,
+        ];
+        let floor = self.center.y - self.settings.half_height;
+        let heights = (0..resolution)
+            .flat_map(|z| (0..resolution).map(move |x| (x, z)))
+            .map(|(x, z)| {
+                height(
+                    origin[0] + x as f32 * spacing,
+                    origin[1] + z as f32 * spacing,
+                )
+                .unwrap_or(floor)
+            })
+            .collect::<Vec<_>>();
+        self.queue
+            .write_buffer(&self.ground_buffer, 0, bytemuck::cast_slice(&heights));
+        self.ground = Some((origin, spacing));
+    }
+
+    /// Forgets the ground of `sample_ground`, particles then fall to the bottom of the box.
+    pub fn clear_ground(&mut self) {
+        self.ground = None;
+    }
+
+    /// Advances the simulation by `dt` on the GPU.
+    pub fn update(&mut self, dt: Duration) {
+        let settings = &self.settings;
+        let (ground_origin, ground_spacing) = self.ground.unwrap_or_default();
+        let uniform = ParticlesUniform {
+            center: self.center.into(),
+            // Long hitches would throw particles far out of the box
+            dt: dt.as_secs_f32().min(0.1),
+            gravity: settings.gravity.into(),
+            drag: settings.drag.max(0.0),
+            wind: settings.wind.into(),
+            frame: self.frame,
+            extent: [
+                settings.half_width.max(0.01),
+                settings.half_height.max(0.01),
+            ],
+            size: [settings.size, settings.stretch.unwrap_or(0.0)],
+            color: settings.color,
+            sway: settings.sway,
+            stretch: settings.stretch.is_some().into(),
+            count: settings.count,
+            has_ground: self.ground.is_some().into(),
+            ground_origin,
+            ground_spacing,
+            ground_resolution: Self::GROUND_RESOLUTION,
+        };
+        self.frame = self.frame.wrapping_add(1);
+        self.queue
+            .write_buffer(&self.uniform_buffer, 0, bytemuck::cast_slice(&[uniform]));
+
+        let mut encoder = self
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some("Particles Encoder"),
+            });
+        {
+            let mut pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+                label: Some("Particle Simulation"),
+                timestamp_writes: None,
+            });
+            pass.set_pipeline(&self.compute_pipeline);
+            pass.set_bind_group(0, &self.compute_bind_group, &[]);
+            pass.dispatch_workgroups(settings.count.div_ceil(Self::WORKGROUP_SIZE), 1, 1);
+        }
+        self.queue.submit(std::iter::once(encoder.finish()));
+    }
+
+    /// Draws the particles as seen with `camera_bind_group`. Changes bind groups 0 and 1.
+    pub fn draw<'a>(
+        &'a self,
+        pass: &mut wgpu::RenderPass<'a>,
+        camera_bind_group: &'a wgpu::BindGroup,
+    ) {
+        if self.settings.count == 0 {
+            return;
+        }
+        pass.set_pipeline(&self.render_pipeline);
+        pass.set_bind_group(0, camera_bind_group, &[]);
+        pass.set_bind_group(1, &self.render_bind_group, &[]);
+        pass.set_vertex_buffer(0, self.particle_buffer.slice(..));
+        pass.draw(0..4, 0..self.settings.count);
+    }
+}
@@ -0,0 +1,195 @@
+// Weather particles: a compute pass moving them through a box following the camera, and
+// camera facing quads drawing them, stretched along their velocity for rain
+
+// `CameraUniform` and the other shared types are declared in `src/prelude.wgsl`
+
+struct Particles {
+  // Center of the box particles live in
+  center: vec3<f32>,
+  dt: f32,
+  gravity: vec3<f32>,
+  // How quickly velocities settle towards the wind, per second
+  drag: f32,
+  wind: vec3<f32>,
+  // Seeds the random numbers
+  frame: u32,
+  // Half the box's width along x and z, and half its height
+  extent: vec2<f32>,
+  // Width of the quads and, when stretched, their length per unit of speed
+  size: vec2<f32>,
+  // Linear HDR, alpha is the coverage at the center of a particle
+  color: vec4<f32>,
+  // Sideways wobble in world units per second, for snow
+  sway: f32,
+  // Stretched along the velocity like rain, or round like snow
+  stretch: u32,
+  count: u32,
+  has_ground: u32,
+  // World (x, z) of the first ground sample and the spacing between samples
+  ground_origin: vec2<f32>,
+  ground_spacing: f32,
+  ground_resolution: u32,
+};
+
+struct Particle {
+  // w is 0 until the particle is spawned, then its age in seconds
+  position: vec4<f32>,
+  // w is a random phase for swaying
+  velocity: vec4<f32>,
+};
+
+@group(0) @binding(0)
+var<uniform> params: Particles;
+@group(0) @binding(1)
+var<storage, read_write> particles: array<Particle>;
+// Terrain heights on a grid around the box, row-major
+@group(0) @binding(2)
+var<storage, read> ground: array<f32>;
+
+// PCG hash
+fn hash(seed: u32) -> u32 {
+  let state = seed * 747796405u + 2891336453u;
+  let word = ((state >> ((state >> 28u) + 4u)) ^ state) * 277803737u;
+  return (word >> 22u) ^ word;
+}
+
+// Uniform in 0..1, advancing `seed`
+fn random(seed: ptr<function, u32>) -> f32 {
+  *seed = hash(*seed);
+  return f32(*seed) / 4294967295.0;
+}
+
+fn ground_height(xz: vec2<f32>) -> f32 {
+  let floor_height = params.center.y - params.extent.y;
+  if params.has_ground == 0u {
+    return floor_height;
+  }
+  let last = f32(params.ground_resolution - 1u);
+  let grid = clamp((xz - params.ground_origin) / params.ground_spacing, vec2<f32>(0.0), vec2<f32>(last));
+  let base = min(floor(grid), vec2<f32>(last - 1.0));
+  let t = grid - base;
+  let i = u32(base.y) * params.ground_resolution + u32(base.x);
+  let row = params.ground_resolution;
+  let h0 = mix(ground[i], ground[i + 1u], t.x);
+  let h1 = mix(ground[i + row], ground[i + row + 1u], t.x);
+  return max(mix(h0, h1, t.y), floor_height);
+}
+
+// Velocity particles settle at, where drag balances gravity
+fn terminal_velocity() -> vec3<f32> {
+  if params.drag <= 0.0 {
+    return params.wind;
+  }
+  return params.wind + params.gravity / params.drag;
+}
+
+@compute @workgroup_size(64)
+fn simulate(@builtin(global_invocation_id) id: vec3<u32>) {
+  let index = id.x;
+  if index >= params.count {
+    return;
+  }
+  var particle = particles[index];
+  var seed = hash(index ^ hash(params.frame));
+  let extent = params.extent;
+
+  if particle.position.w == 0.0 {
+    // First spawn, anywhere in the box so the weather doesn't start as one sheet
+    let x = (random(&seed) * 2.0 - 1.0) * extent.x;
+    let y = (random(&seed) * 2.0 - 1.0) * extent.y;
+    let z = (random(&seed) * 2.0 - 1.0) * extent.x;
+    particle.position = vec4<f32>(params.center + vec3<f32>(x, y, z), 0.001);
+    particle.velocity = vec4<f32>(terminal_velocity(), random(&seed) * 6.2831853);
+  }
+
+  var velocity = particle.velocity.xyz;
+  velocity += params.gravity * params.dt;
+  velocity += (params.wind - velocity) * min(params.drag * params.dt, 1.0);
+  let age = particle.position.w + params.dt;
+  let phase = particle.velocity.w + age * 2.0;
+  let sway = vec3<f32>(sin(phase), 0.0, cos(phase * 0.7)) * params.sway;
+  var position = particle.position.xyz + (velocity + sway) * params.dt;
+
+  // Keep the box around the camera by wrapping particles to its other side
+  let offset = position.xz - params.center.xz;
+  let wrapped = offset - 2.0 * extent.x * round(offset / (2.0 * extent.x));
+  position = vec3<f32>(params.center.x + wrapped.x, position.y, params.center.z + wrapped.y);
+
+  // Recycle particles that hit the ground, or left the box vertically, at its top
+  let above = position.y > params.center.y + extent.y;
+  if position.y < ground_height(position.xz) || above {
+    let x = (random(&seed) * 2.0 - 1.0) * extent.x;
+    let z = (random(&seed) * 2.0 - 1.0) * extent.x;
+    // Rising particles, in an updraft, come back in at the bottom
+    let y = select(extent.y * (1.0 - 0.2 * random(&seed)), -extent.y, above);
+    particle.position = vec4<f32>(params.center + vec3<f32>(x, y, z), 0.001);
+    particle.velocity = vec4<f32>(terminal_velocity(), random(&seed) * 6.2831853);
+  } else {
+    particle.position = vec4<f32>(position, age);
+    particle.velocity = vec4<f32>(velocity, particle.velocity.w);
+  }
+  particles[index] = particle;
+}
+
+// The render pipeline's bindings, `render_params` is the same buffer as `params`
+@group(0) @binding(0)
+var<uniform> camera: CameraUniform;
+@group(1) @binding(0)
+var<uniform> render_params: Particles;
+
+struct InstanceInput {
+  @location(0) position: vec4<f32>,
+  @location(1) velocity: vec4<f32>,
+};
+
+struct VertexOutput {
+  @builtin(position) clip_position: vec4<f32>,
+  // -1..1 across and along the quad
+  @location(0) uv: vec2<f32>,
+  @location(1) fade: f32,
+};
+
+@vertex
+fn vs_main(@builtin(vertex_index) id: u32, instance: InstanceInput) -> VertexOutput {
+  // Triangle strip over the corners (0, 0), (1, 0), (0, 1), (1, 1)
+  let corner = vec2<f32>(f32(id & 1u), f32(id >> 1u)) * 2.0 - 1.0;
+  let position = instance.position.xyz;
+  let to_camera = camera.view_pos.xyz - position;
+  let distance = length(to_camera);
+  var right = camera.inv_view[0].xyz;
+  var up = camera.inv_view[1].xyz;
+  var half_size = vec2<f32>(render_params.size.x * 0.5);
+  let speed = length(instance.velocity.xyz);
+  if render_params.stretch != 0u && speed > 0.0001 {
+    // A streak along the velocity, turned to face the camera around it
+    up = instance.velocity.xyz / speed;
+    let side = cross(up, to_camera);
+    right = select(right, normalize(side), length(side) > 0.0001);
+    half_size.y = render_params.size.y * speed * 0.5;
+  }
+
+  var out: VertexOutput;
+  let world_position = position + right * corner.x * half_size.x + up * corner.y * half_size.y;
+  out.clip_position = camera.view_proj * vec4<f32>(world_position, 1.0);
+  out.uv = corner;
+  // Particles right in front of the camera would cover the view, and unspawned ones
+  // aren't anywhere yet
+  out.fade = smoothstep(0.3, 1.0, distance) * step(0.0001, instance.position.w);
+  return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+  var coverage: f32;
+  if render_params.stretch != 0u {
+    coverage = (1.0 - in.uv.x * in.uv.x) * (1.0 - in.uv.y * in.uv.y);
+  } else {
+    coverage = 1.0 - smoothstep(0.4, 1.0, length(in.uv));
+  }
+  let alpha = render_params.color.a * coverage * in.fade;
+  if alpha < 0.004 {
+    discard;
+  }
+  // Premultiplied
+  return vec4<f32>(render_params.color.rgb * alpha, alpha);
+}
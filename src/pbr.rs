@@ -0,0 +1,255 @@
+use std::ops::Range;
+
+use wgpu::util::DeviceExt as _;
+
+use crate::{
+    model::{Mesh, Model},
+    texture,
+};
+
+/// Scalars the material's texture samples are multiplied with, following glTF's
+/// metallic-roughness model.
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct PbrFactors {
+    /// Linear RGBA
+    pub base_color: [f32; 4],
+    pub metallic: f32,
+    pub roughness: f32,
+    /// How much the AO map darkens ambient light, 0 ignores it
+    pub occlusion_strength: f32,
+    /// Scales the XY of tangent space normals, 0 ignores the normal map
+    pub normal_scale: f32,
+}
+
+impl Default for PbrFactors {
+    fn default() -> Self {
+        Self {
+            base_color: [1.0; 4],
+            metallic: 0.0,
+            roughness: 0.5,
+            occlusion_strength: 1.0,
+            normal_scale: 1.0,
+        }
+    }
+}
+
+/// The maps of a `PbrMaterial`. Metallic is read from blue and roughness from green of
+/// `metallic_roughness`, occlusion from red of `occlusion`, all of them linear.
+pub struct PbrTextures {
+    /// sRGB base color and alpha
+    pub albedo: texture::Texture,
+    pub normal: texture::Texture,
+    pub metallic_roughness: texture::Texture,
+    pub occlusion: texture::Texture,
+}
+
+impl PbrTextures {
+    /// 1x1 maps that leave the factors unchanged and keep the surface normal.
+    pub fn fallback(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        label: &str,
+    ) -> anyhow::Result<Self> {
+        // `is_normal_map` selects a linear format, which the data maps need as well
+        Ok(Self {
+            albedo: texture::Texture::from_color(device, queue, [255; 4], label, false)?,
+            normal: texture::Texture::from_color(device, queue, [128, 128, 255, 255], label, true)?,
+            metallic_roughness: texture::Texture::from_color(device, queue, [255; 4], label, true)?,
+            occlusion: texture::Texture::from_color(device, queue, [255; 4], label, true)?,
+        })
+    }
+}
+
+pub struct PbrMaterial {
+    pub name: String,
+    pub textures: PbrTextures,
+    factors: PbrFactors,
+    factors_buffer: wgpu::Buffer,
+    pub bind_group: wgpu::BindGroup,
+}
+
+impl PbrMaterial {
+    /// Group 0 of `pbr.wgsl`: the albedo, normal, metallic-roughness and occlusion maps,
+    /// each followed by its sampler, then the factors uniform.
+    pub fn create_bind_group_layout(device: &wgpu::Device) -> wgpu::BindGroupLayout {
+        let texture = |binding| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Texture {
+                multisampled: false,
+                view_dimension: wgpu::TextureViewDimension::D2,
+                sample_type: wgpu::TextureSampleType::Float { filterable: true },
+            },
+            count: None,
+        };
+        let sampler = |binding| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+            count: None,
+        };
+        device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                texture(0),
+                sampler(1),
+                texture(2),
+                sampler(3),
+                texture(4),
+                sampler(5),
+                texture(6),
+                sampler(7),
+                wgpu::BindGroupLayoutEntry {
+                    binding: 8,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ],
+            label: Some("pbr_material_bind_group_layout"),
+        })
+    }
+
+    pub fn new(
+        device: &wgpu::Device,
+        name: &str,
+        textures: PbrTextures,
+        factors: PbrFactors,
+        layout: &wgpu::BindGroupLayout,
+    ) -> Self {
+        let factors_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some(name),
+            contents: bytemuck::cast_slice(&[factors]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        let maps = [
+            &textures.albedo,
+            &textures.normal,
+            &textures.metallic_roughness,
+            &textures.occlusion,
+        ];
+        let mut entries = maps
+            .iter()
+            .enumerate()
+            .flat_map(|(i, map)| {
+                [
+                    wgpu::BindGroupEntry {
+                        binding: 2 * i as u32,
+                        resource: wgpu::BindingResource::TextureView(&map.view),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 2 * i as u32 + 1,
+                        resource: wgpu::BindingResource::Sampler(&map.sampler),
+                    },
+                ]
+            })
+            .collect::<Vec<_>>();
+        entries.push(wgpu::BindGroupEntry {
+            binding: 8,
+            resource: factors_buffer.as_entire_binding(),
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout,
+            entries: &entries,
+            label: Some(name),
+        });
+
+        Self {
+            name: name.to_string(),
+            textures,
+            factors,
+            factors_buffer,
+            bind_group,
+        }
+    }
+
+    /// An untextured material shaded by `factors` alone.
+    pub fn from_factors(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        name: &str,
+        factors: PbrFactors,
+        layout: &wgpu::BindGroupLayout,
+    ) -> anyhow::Result<Self> {
+        let textures = PbrTextures::fallback(device, queue, name)?;
+        Ok(Self::new(device, name, textures, factors, layout))
+    }
+
+    pub fn factors(&self) -> PbrFactors {
+        self.factors
+    }
+
+    pub fn set_factors(&mut self, queue: &wgpu::Queue, factors: PbrFactors) {
+        self.factors = factors;
+        queue.write_buffer(&self.factors_buffer, 0, bytemuck::cast_slice(&[factors]));
+    }
+}
+
+/// Draws meshes with `PbrMaterial`s. Like `DrawModel`, this sets the material, camera and
+/// light array groups (0 to 2); the sun, shadow, fog and IBL groups (3 to 6) are left to
+/// the caller.
+pub trait DrawPbr<'a> {
+    fn draw_pbr_mesh_instanced(
+        &mut self,
+        mesh: &'a Mesh,
+        material: &'a PbrMaterial,
+        instances: Range<u32>,
+        camera_bind_group: &'a wgpu::BindGroup,
+        light_bind_group: &'a wgpu::BindGroup,
+    );
+
+    /// Draws every mesh of `model` with `materials[mesh.material]` in place of the model's
+    /// own materials.
+    fn draw_pbr_model_instanced(
+        &mut self,
+        model: &'a Model,
+        materials: &'a [PbrMaterial],
+        instances: Range<u32>,
+        camera_bind_group: &'a wgpu::BindGroup,
+        light_bind_group: &'a wgpu::BindGroup,
+    );
+}
+
+impl<'a, 'b> DrawPbr<'b> for wgpu::RenderPass<'a>
+where
+    'b: 'a,
+{
+    fn draw_pbr_mesh_instanced(
+        &mut self,
+        mesh: &'b Mesh,
+        material: &'b PbrMaterial,
+        instances: Range<u32>,
+        camera_bind_group: &'b wgpu::BindGroup,
+        light_bind_group: &'b wgpu::BindGroup,
+    ) {
+        self.set_vertex_buffer(0, mesh.vertex_buffer.slice(..));
+        self.set_index_buffer(mesh.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
+        self.set_bind_group(0, &material.bind_group, &[]);
+        self.set_bind_group(1, camera_bind_group, &[]);
+        self.set_bind_group(2, light_bind_group, &[]);
+        self.draw_indexed(0..mesh.num_elements, 0, instances);
+    }
+
+    fn draw_pbr_model_instanced(
+        &mut self,
+        model: &'b Model,
+        materials: &'b [PbrMaterial],
+        instances: Range<u32>,
+        camera_bind_group: &'b wgpu::BindGroup,
+        light_bind_group: &'b wgpu::BindGroup,
+    ) {
+        for mesh in &model.meshes {
+            self.draw_pbr_mesh_instanced(
+                mesh,
+                &materials[mesh.material],
+                instances.clone(),
+                camera_bind_group,
+                light_bind_group,
+            );
+        }
+    }
+}
@@ -0,0 +1,253 @@
+// Metallic-roughness PBR: Cook-Torrance GGX for the point lights and the sun, plus
+// image-based ambient light from the baked IBL maps
+
+const PI: f32 = 3.14159265359;
+
+// `CameraUniform` and the other shared types are declared in `src/prelude.wgsl`
+
+struct PbrFactors {
+  base_color: vec4<f32>,
+  metallic: f32,
+  roughness: f32,
+  occlusion_strength: f32,
+  normal_scale: f32,
+};
+
+// intensity 0 means there is no environment to light from
+struct Ibl {
+  intensity: f32,
+  max_mip: f32,
+};
+
+struct VertexInput {
+  @location(0) position: vec3<f32>,
+  @location(1) tex_coords: vec2<f32>,
+  @location(2) normal: vec3<f32>,
+  @location(3) tangent: vec3<f32>,
+  @location(4) bitangent: vec3<f32>,
+  @location(14) color: vec4<f32>,
+};
+
+struct InstanceInput {
+  @location(5) model_matrix_0: vec4<f32>,
+  @location(6) model_matrix_1: vec4<f32>,
+  @location(7) model_matrix_2: vec4<f32>,
+  @location(8) model_matrix_3: vec4<f32>,
+  @location(9) normal_matrix_0: vec3<f32>,
+  @location(10) normal_matrix_1: vec3<f32>,
+  @location(11) normal_matrix_2: vec3<f32>,
+};
+
+struct VertexOutput {
+  @builtin(position) pos: vec4<f32>,
+  @location(0) tex_coords: vec2<f32>,
+  @location(1) world_position: vec3<f32>,
+  @location(2) world_normal: vec3<f32>,
+  @location(3) world_tangent: vec3<f32>,
+  @location(4) world_bitangent: vec3<f32>,
+  @location(5) color: vec4<f32>,
+};
+
+@group(0) @binding(0)
+var t_albedo: texture_2d<f32>;
+@group(0) @binding(1)
+var s_albedo: sampler;
+@group(0) @binding(2)
+var t_normal: texture_2d<f32>;
+@group(0) @binding(3)
+var s_normal: sampler;
+@group(0) @binding(4)
+var t_metallic_roughness: texture_2d<f32>;
+@group(0) @binding(5)
+var s_metallic_roughness: sampler;
+@group(0) @binding(6)
+var t_occlusion: texture_2d<f32>;
+@group(0) @binding(7)
+var s_occlusion: sampler;
+@group(0) @binding(8)
+var<uniform> material: PbrFactors;
+
+@group(1) @binding(0)
+var<uniform> camera: CameraUniform;
+
+@group(2) @binding(0)
+var<storage, read> lights: LightArray;
+@group(2) @binding(1)
+var<storage, read> spot_lights: SpotLightArray;
+
+@group(3) @binding(0)
+var<uniform> sun: DirectionalLight;
+
+// `Shadow` and the functions sampling the cascades are in `src/shadow_sampling.wgsl`
+@group(4) @binding(0)
+var t_shadow: texture_depth_2d_array;
+@group(4) @binding(1)
+var s_shadow: sampler_comparison;
+@group(4) @binding(2)
+var<uniform> shadow: Shadow;
+
+@group(5) @binding(0)
+var<uniform> fog: Fog;
+
+@group(6) @binding(0)
+var t_irradiance: texture_cube<f32>;
+@group(6) @binding(1)
+var t_prefiltered: texture_cube<f32>;
+@group(6) @binding(2)
+var s_environment: sampler;
+@group(6) @binding(3)
+var t_brdf_lut: texture_2d<f32>;
+@group(6) @binding(4)
+var s_brdf_lut: sampler;
+@group(6) @binding(5)
+var<uniform> ibl: Ibl;
+
+// Trowbridge-Reitz GGX normal distribution
+fn distribution_ggx(n_dot_h: f32, roughness: f32) -> f32 {
+  let a = roughness * roughness;
+  let a2 = a * a;
+  let d = n_dot_h * n_dot_h * (a2 - 1.0) + 1.0;
+  return a2 / (PI * d * d);
+}
+
+// Smith's method with Schlick-GGX for both the view and the light direction
+fn geometry_smith(n_dot_v: f32, n_dot_l: f32, roughness: f32) -> f32 {
+  let r = roughness + 1.0;
+  let k = r * r / 8.0;
+  let g_v = n_dot_v / (n_dot_v * (1.0 - k) + k);
+  let g_l = n_dot_l / (n_dot_l * (1.0 - k) + k);
+  return g_v * g_l;
+}
+
+fn fresnel_schlick(cos_theta: f32, f0: vec3<f32>) -> vec3<f32> {
+  return f0 + (1.0 - f0) * pow(clamp(1.0 - cos_theta, 0.0, 1.0), 5.0);
+}
+
+// Rough surfaces reflect less of the environment at grazing angles
+fn fresnel_schlick_roughness(cos_theta: f32, f0: vec3<f32>, roughness: f32) -> vec3<f32> {
+  return f0 + (max(vec3<f32>(1.0 - roughness), f0) - f0) * pow(clamp(1.0 - cos_theta, 0.0, 1.0), 5.0);
+}
+
+// Outgoing radiance towards `v` from light arriving along `l` with `radiance`
+fn cook_torrance(
+  n: vec3<f32>,
+  v: vec3<f32>,
+  l: vec3<f32>,
+  radiance: vec3<f32>,
+  albedo: vec3<f32>,
+  metallic: f32,
+  roughness: f32,
+  f0: vec3<f32>,
+) -> vec3<f32> {
+  let h = normalize(v + l);
+  let n_dot_l = max(dot(n, l), 0.0);
+  let n_dot_v = max(dot(n, v), 0.0001);
+
+  let d = distribution_ggx(max(dot(n, h), 0.0), roughness);
+  let g = geometry_smith(n_dot_v, n_dot_l, roughness);
+  let f = fresnel_schlick(max(dot(h, v), 0.0), f0);
+  let specular = d * g * f / max(4.0 * n_dot_v * n_dot_l, 0.0001);
+
+  // Metals have no diffuse reflection
+  let k_d = (1.0 - f) * (1.0 - metallic);
+  return (k_d * albedo / PI + specular) * radiance * n_dot_l;
+}
+
+@vertex
+fn vs_main(model: VertexInput, instance: InstanceInput) -> VertexOutput {
+  let model_matrix = mat4x4<f32>(
+    instance.model_matrix_0,
+    instance.model_matrix_1,
+    instance.model_matrix_2,
+    instance.model_matrix_3,
+  );
+  let normal_matrix = mat3x3<f32>(
+    instance.normal_matrix_0,
+    instance.normal_matrix_1,
+    instance.normal_matrix_2,
+  );
+
+  let world_position = model_matrix * vec4<f32>(model.position, 1.0);
+  var out: VertexOutput;
+  out.pos = camera.view_proj * world_position;
+  out.tex_coords = model.tex_coords;
+  out.world_position = world_position.xyz;
+  out.world_normal = normalize(normal_matrix * model.normal);
+  out.world_tangent = normalize(normal_matrix * model.tangent);
+  out.world_bitangent = normalize(normal_matrix * model.bitangent);
+  out.color = model.color;
+  return out;
+}
+
+// Tangent space normal from a normal map texel. Two channel maps such as BC5 leave blue
+// at 0, as z follows from x and y for unit normals.
+fn unpack_normal(texel: vec3<f32>) -> vec3<f32> {
+  let xy = texel.xy * 2.0 - 1.0;
+  let z = sqrt(max(1.0 - dot(xy, xy), 0.0));
+  return vec3<f32>(xy, select(texel.z * 2.0 - 1.0, z, texel.z == 0.0));
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+  let base_color =
+    textureSample(t_albedo, s_albedo, in.tex_coords) * material.base_color * in.color;
+  let albedo = base_color.rgb;
+  let metallic_roughness = textureSample(t_metallic_roughness, s_metallic_roughness, in.tex_coords);
+  let metallic = clamp(metallic_roughness.b * material.metallic, 0.0, 1.0);
+  // Fully smooth surfaces make the GGX highlight infinitely small
+  let roughness = clamp(metallic_roughness.g * material.roughness, 0.04, 1.0);
+  let occlusion_sample = textureSample(t_occlusion, s_occlusion, in.tex_coords).r;
+  let occlusion = mix(1.0, occlusion_sample, material.occlusion_strength);
+
+  let tangent_matrix = mat3x3<f32>(
+    normalize(in.world_tangent),
+    normalize(in.world_bitangent),
+    normalize(in.world_normal),
+  );
+  var tangent_normal = unpack_normal(textureSample(t_normal, s_normal, in.tex_coords).xyz);
+  tangent_normal = vec3<f32>(tangent_normal.xy * material.normal_scale, tangent_normal.z);
+  let n = normalize(tangent_matrix * tangent_normal);
+  let v = normalize(camera.view_pos.xyz - in.world_position);
+  let n_dot_v = max(dot(n, v), 0.0001);
+
+  // Dielectrics reflect about 4% at normal incidence, metals tint it with their albedo
+  let f0 = mix(vec3<f32>(0.04), albedo, metallic);
+
+  var color = vec3<f32>(0.0);
+  let count = min(lights.count, arrayLength(&lights.lights));
+  for (var i = 0u; i < count; i += 1u) {
+    let light = lights.lights[i];
+    let l = normalize(light.position - in.world_position);
+    let radiance = light.color * point_attenuation(light, in.world_position);
+    color += cook_torrance(n, v, l, radiance, albedo, metallic, roughness, f0);
+  }
+
+  let spot_count = min(spot_lights.count, arrayLength(&spot_lights.lights));
+  for (var i = 0u; i < spot_count; i += 1u) {
+    let light = spot_lights.lights[i];
+    let l = normalize(light.position - in.world_position);
+    let radiance = light.color * spot_attenuation(light, in.world_position);
+    color += cook_torrance(n, v, l, radiance, albedo, metallic, roughness, f0);
+  }
+
+  let sun_dir = -sun.direction;
+  color += cook_torrance(n, v, sun_dir, sun.color, albedo, metallic, roughness, f0)
+    * sun_visibility(in.world_position, n, sun_dir);
+
+  // Split-sum image-based lighting
+  let f = fresnel_schlick_roughness(n_dot_v, f0, roughness);
+  let k_d = (1.0 - f) * (1.0 - metallic);
+  let irradiance = textureSample(t_irradiance, s_environment, n).rgb;
+  let prefiltered = textureSampleLevel(t_prefiltered, s_environment, reflect(-v, n), roughness * ibl.max_mip).rgb;
+  let brdf = textureSample(t_brdf_lut, s_brdf_lut, vec2<f32>(n_dot_v, roughness)).rg;
+  let environment = (k_d * irradiance * albedo + prefiltered * (f * brdf.x + brdf.y)) * ibl.intensity;
+  // Without an environment, a small constant ambient term keeps shadows from going black
+  let ambient = select(vec3<f32>(0.03) * albedo, environment, ibl.intensity > 0.0);
+  color += ambient * occlusion;
+
+  color *= cascade_tint(in.world_position);
+  let view_depth = -(camera.view * vec4<f32>(in.world_position, 1.0)).z;
+  color = mix(color, fog.color.rgb, fog_factor(fog, view_depth));
+
+  return vec4<f32>(color, base_color.a);
+}
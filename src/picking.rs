@@ -0,0 +1,209 @@
+use anyhow::Context as _;
+
+use crate::hot_reload::wgsl;
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct ProbeUniform {
+    pixel: [u32; 2],
+}
+
+/// Reads single depth values back from a depth texture for picking.
+pub struct DepthProbe {
+    layout: wgpu::BindGroupLayout,
+    layout_multisampled: wgpu::BindGroupLayout,
+    pipeline: wgpu::ComputePipeline,
+    pipeline_multisampled: wgpu::ComputePipeline,
+    probe_buffer: wgpu::Buffer,
+    result_buffer: wgpu::Buffer,
+    readback_buffer: wgpu::Buffer,
+}
+
+impl DepthProbe {
+    pub fn new(device: &wgpu::Device) -> Self {
+        let create_layout = |label, binding, multisampled| {
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                entries: &[
+                    wgpu::BindGroupLayoutEntry {
+                        binding,
+                        visibility: wgpu::ShaderStages::COMPUTE,
+                        ty: wgpu::BindingType::Texture {
+                            multisampled,
+                            view_dimension: wgpu::TextureViewDimension::D2,
+                            sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                        },
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 2,
+                        visibility: wgpu::ShaderStages::COMPUTE,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Uniform,
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 3,
+                        visibility: wgpu::ShaderStages::COMPUTE,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Storage { read_only: false },
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
+                ],
+                label: Some(label),
+            })
+        };
+        let layout = create_layout("depth_probe_bind_group_layout", 0, false);
+        let layout_multisampled =
+            create_layout("depth_probe_multisampled_bind_group_layout", 1, true);
+
+        let (pipeline, pipeline_multisampled) =
+            Self::create_pipelines(device, &layout, &layout_multisampled);
+
+        let probe_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Depth Probe Buffer"),
+            size: std::mem::size_of::<ProbeUniform>() as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let result_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Depth Probe Result Buffer"),
+            size: std::mem::size_of::<f32>() as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC,
+            mapped_at_creation: false,
+        });
+        let readback_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Depth Probe Readback Buffer"),
+            size: std::mem::size_of::<f32>() as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+            mapped_at_creation: false,
+        });
+
+        Self {
+            layout,
+            layout_multisampled,
+            pipeline,
+            pipeline_multisampled,
+            probe_buffer,
+            result_buffer,
+            readback_buffer,
+        }
+    }
+
+    /// The pipelines reading single sampled and multisampled depth.
+    fn create_pipelines(
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        layout_multisampled: &wgpu::BindGroupLayout,
+    ) -> (wgpu::ComputePipeline, wgpu::ComputePipeline) {
+        let shader_module = device.create_shader_module(wgsl!("src/picking.wgsl"));
+        let create_pipeline = |layout, entry_point| {
+            let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: Some("Depth Probe Pipeline Layout"),
+                bind_group_layouts: &[layout],
+                push_constant_ranges: &[],
+            });
+            device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+                label: Some("Depth Probe Pipeline"),
+                layout: Some(&pipeline_layout),
+                module: &shader_module,
+                entry_point: Some(entry_point),
+                compilation_options: Default::default(),
+                cache: None,
+            })
+        };
+        (
+            create_pipeline(layout, "read_depth"),
+            create_pipeline(layout_multisampled, "read_depth_multisampled"),
+        )
+    }
+
+    /// Rebuilds the pipelines from the current shader, see `hot_reload`.
+    pub fn recreate_pipelines(&mut self, device: &wgpu::Device) {
+        (self.pipeline, self.pipeline_multisampled) =
+            Self::create_pipelines(device, &self.layout, &self.layout_multisampled);
+    }
+
+    /// Returns the depth stored at pixel `(x, y)` of `depth`, blocking until the GPU is done.
+    /// The texture needs `TEXTURE_BINDING` usage and may be multisampled.
+    pub fn read_depth(
+        &self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        depth: &wgpu::Texture,
+        x: u32,
+        y: u32,
+    ) -> anyhow::Result<f32> {
+        anyhow::ensure!(
+            x < depth.width() && y < depth.height(),
+            "pixel ({x}, {y}) is outside the {}x{} depth texture",
+            depth.width(),
+            depth.height()
+        );
+
+        let (layout, pipeline, binding) = if depth.sample_count() > 1 {
+            (&self.layout_multisampled, &self.pipeline_multisampled, 1)
+        } else {
+            (&self.layout, &self.pipeline, 0)
+        };
+        let view = depth.create_view(&wgpu::TextureViewDescriptor {
+            aspect: wgpu::TextureAspect::DepthOnly,
+            ..Default::default()
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("depth_probe_bind_group"),
+            layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding,
+                    resource: wgpu::BindingResource::TextureView(&view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: self.probe_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: self.result_buffer.as_entire_binding(),
+                },
+            ],
+        });
+        queue.write_buffer(
+            &self.probe_buffer,
+            0,
+            bytemuck::cast_slice(&[ProbeUniform { pixel: [x, y] }]),
+        );
+
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("Depth Probe Encoder"),
+        });
+        {
+            let mut pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+                label: Some("Depth Probe"),
+                timestamp_writes: None,
+            });
+            pass.set_pipeline(pipeline);
+            pass.set_bind_group(0, &bind_group, &[]);
+            pass.dispatch_workgroups(1, 1, 1);
+        }
+        encoder.copy_buffer_to_buffer(&self.result_buffer, 0, &self.readback_buffer, 0, None);
+        queue.submit([encoder.finish()]);
+
+        let slice = self.readback_buffer.slice(..);
+        let (sender, receiver) = std::sync::mpsc::channel();
+        slice.map_async(wgpu::MapMode::Read, move |result| {
+            let _ = sender.send(result);
+        });
+        device.poll(wgpu::PollType::Wait)?;
+        receiver.recv()?.context("failed to map depth readback")?;
+
+        let depth = bytemuck::pod_read_unaligned::<f32>(&slice.get_mapped_range());
+        self.readback_buffer.unmap();
+        Ok(depth)
+    }
+}
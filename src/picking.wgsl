@@ -0,0 +1,30 @@
+// Reads a single depth value so it can be copied back to the CPU. Multisampled depth
+// can't be copied to a buffer directly, so both cases go through this shader. The depth
+// texture is bound as unfilterable float, since the GL backend can't textureLoad from
+// depth texture bindings.
+
+struct Probe {
+  pixel: vec2<u32>,
+}
+
+@group(0) @binding(0)
+var depth: texture_2d<f32>;
+@group(0) @binding(1)
+var depth_multisampled: texture_multisampled_2d<f32>;
+@group(0) @binding(2)
+var<uniform> probe: Probe;
+@group(0) @binding(3)
+var<storage, read_write> result: f32;
+
+@compute
+@workgroup_size(1)
+fn read_depth() {
+  result = textureLoad(depth, probe.pixel, 0).r;
+}
+
+// Sample 0 is as good as any other for picking
+@compute
+@workgroup_size(1)
+fn read_depth_multisampled() {
+  result = textureLoad(depth_multisampled, probe.pixel, 0).r;
+}
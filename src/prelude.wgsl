@@ -0,0 +1,106 @@
+// Declarations shared by the scene shaders and appended to their source by
+// `wgsl_source!`: the camera, the lights and the fog, laid out like the Rust uniforms.
+// Only types, constants and functions of nothing but their arguments, so any shader can
+// include it whatever it binds.
+
+// Share of a surface's color lit by the scene's ambient light, added once per fragment
+const AMBIENT_STRENGTH: f32 = 0.1;
+
+// `camera::CameraUniform`
+struct CameraUniform {
+  view_pos: vec4<f32>,
+  view: mat4x4<f32>,
+  view_proj: mat4x4<f32>,
+  inv_proj: mat4x4<f32>,
+  inv_view: mat4x4<f32>,
+  // `view_proj` as of the previous frame, for motion vectors
+  prev_view_proj: mat4x4<f32>,
+  // 1 once `prev_view_proj` holds a previous frame's `view_proj`
+  has_history: u32,
+};
+
+// Fades as 1 / (constant + linear * d + quadratic * d²), reaching zero at `range`
+struct Light {
+  position: vec3<f32>,
+  range: f32,
+  color: vec3<f32>,
+  constant: f32,
+  linear: f32,
+  quadratic: f32,
+};
+
+struct LightArray {
+  count: u32,
+  lights: array<Light>,
+};
+
+// How much of a point light reaches `position`
+fn point_attenuation(light: Light, position: vec3<f32>) -> f32 {
+  let distance = length(light.position - position);
+  let ratio = distance / max(light.range, 0.0001);
+  let window = clamp(1.0 - ratio * ratio * ratio * ratio, 0.0, 1.0);
+  let falloff = light.constant + light.linear * distance + light.quadratic * distance * distance;
+  return window * window / max(falloff, 0.0001);
+}
+
+// A cone along the normalized `direction`, full strength within the angle with cosine
+// `inner_cos` and dark past `outer_cos`
+struct SpotLight {
+  position: vec3<f32>,
+  inner_cos: f32,
+  direction: vec3<f32>,
+  outer_cos: f32,
+  color: vec3<f32>,
+  range: f32,
+};
+
+struct SpotLightArray {
+  count: u32,
+  lights: array<SpotLight>,
+};
+
+// How much of a spotlight reaches `position`: smooth between the inner and outer cone
+// and as `1 / (d² + 1)` with the distance `d`, windowed to reach zero at the light's range
+fn spot_attenuation(light: SpotLight, position: vec3<f32>) -> f32 {
+  let to_position = position - light.position;
+  let distance = length(to_position);
+  let cos_angle = dot(to_position / max(distance, 0.0001), light.direction);
+  let cone = smoothstep(light.outer_cos, light.inner_cos, cos_angle);
+  let ratio = distance / max(light.range, 0.0001);
+  let window = clamp(1.0 - ratio * ratio * ratio * ratio, 0.0, 1.0);
+  return cone * window * window / (distance * distance + 1.0);
+}
+
+// `direction` is the normalized world-space direction the light travels in
+struct DirectionalLight {
+  direction: vec3<f32>,
+  color: vec3<f32>,
+};
+
+// mode: 0 none, 1 linear (start/end), 2 exp, 3 exp2 (density)
+struct Fog {
+  color: vec4<f32>,
+  start: f32,
+  end: f32,
+  density: f32,
+  mode: u32,
+};
+
+// How much of the fog color covers a surface `depth` units in front of the camera
+fn fog_factor(fog: Fog, depth: f32) -> f32 {
+  switch fog.mode {
+    case 1u: {
+      return clamp((depth - fog.start) / max(fog.end - fog.start, 0.0001), 0.0, 1.0);
+    }
+    case 2u: {
+      return 1.0 - exp(-fog.density * depth);
+    }
+    case 3u: {
+      let d = fog.density * depth;
+      return 1.0 - exp(-d * d);
+    }
+    default: {
+      return 0.0;
+    }
+  }
+}
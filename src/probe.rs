@@ -0,0 +1,381 @@
+use std::time::Duration;
+
+use cgmath::{Deg, Matrix4, Point3, Vector3};
+use wgpu::util::DeviceExt as _;
+
+use crate::{
+    camera::{CameraUniform, Projection},
+    frustum::Frustum,
+    hot_reload::wgsl,
+    renderer::Renderer,
+    texture,
+    tonemap::Tonemapper,
+};
+
+/// Forward and up direction of each face, in layer order +X, -X, +Y, -Y, +Z, -Z. The
+/// faces are drawn upright with these and mirrored into the cube, whose texels follow the
+/// hardware cube-map convention.
+const FACES: [([f32; 3], [f32; 3]); 6] = [
+    ([1.0, 0.0, 0.0], [0.0, 1.0, 0.0]),
+    ([-1.0, 0.0, 0.0], [0.0, 1.0, 0.0]),
+    ([0.0, 1.0, 0.0], [0.0, 0.0, -1.0]),
+    ([0.0, -1.0, 0.0], [0.0, 0.0, 1.0]),
+    ([0.0, 0.0, 1.0], [0.0, 1.0, 0.0]),
+    ([0.0, 0.0, -1.0], [0.0, 1.0, 0.0]),
+];
+
+/// The camera looking through one face of the cube.
+struct ProbeFace {
+    uniform: CameraUniform,
+    buffer: wgpu::Buffer,
+    bind_group: wgpu::BindGroup,
+    frustum: Frustum,
+    /// The face's layer of the cube
+    view: wgpu::TextureView,
+}
+
+/// Renders the scene around `position` into a `CubeTexture`, an environment map for shiny
+/// things reflecting the terrain around them, e.g. baked with `IblEnvironment::bake` or
+/// drawn by a `Skybox`.
+///
+/// Each face is drawn like a scene view of the renderer with a 90 degree camera: the sky,
+/// every opaque instance, model and terrain chunk in its frustum, but nothing transparent.
+/// Drawing six views is expensive, so `update` only captures every `refresh_interval`.
+pub struct ReflectionProbe {
+    pub position: Point3<f32>,
+    /// Time between captures in `update`, zero captures every update
+    pub refresh_interval: Duration,
+    /// Time since the last capture, `None` before the first one
+    since_capture: Option<Duration>,
+    cube: texture::CubeTexture,
+    faces: Vec<ProbeFace>,
+    /// Each face is drawn into this, then mirrored into its layer of the cube
+    face_target: texture::Texture,
+    /// Multisampled target resolving into `face_target` while the renderer uses MSAA
+    msaa_view: Option<wgpu::TextureView>,
+    depth_view: wgpu::TextureView,
+    sample_count: u32,
+    depth_format: wgpu::TextureFormat,
+    mirror_layout: wgpu::PipelineLayout,
+    mirror_pipeline: wgpu::RenderPipeline,
+    mirror_bind_group: wgpu::BindGroup,
+}
+
+impl ReflectionProbe {
+    /// A probe at `position` capturing cube faces of `size` texels, drawn with the
+    /// pipelines of `renderer`. Nothing is captured until the first `capture` or `update`.
+    pub fn new(renderer: &Renderer, position: Point3<f32>, size: u32) -> Self {
+        let device = renderer.device();
+        let size = size.max(1);
+        let cube = texture::CubeTexture::create_2d(
+            device,
+            size,
+            Tonemapper::HDR_FORMAT,
+            1,
+            wgpu::TextureUsages::RENDER_ATTACHMENT
+                | wgpu::TextureUsages::TEXTURE_BINDING
+                | wgpu::TextureUsages::COPY_SRC,
+            wgpu::FilterMode::Linear,
+            Some("Reflection Probe Cubemap"),
+        );
+        let faces = (0..FACES.len() as u32)
+            .map(|layer| {
+                let uniform = CameraUniform::new();
+                let buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                    label: Some("Reflection Probe Camera Buffer"),
+                    contents: bytemuck::cast_slice(&[uniform]),
+                    usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+                });
+                let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+                    layout: renderer.camera_bind_group_layout(),
+                    entries: &[wgpu::BindGroupEntry {
+                        binding: 0,
+                        resource: buffer.as_entire_binding(),
+                    }],
+                    label: Some("reflection_probe_camera_bind_group"),
+                });
+                let view = cube.texture().create_view(&wgpu::TextureViewDescriptor {
+                    label: Some("Reflection Probe Face"),
+                    dimension: Some(wgpu::TextureViewDimension::D2),
+                    base_array_layer: layer,
+                    array_layer_count: Some(1),
+                    ..Default::default()
+                });
+                ProbeFace {
+                    uniform,
+                    buffer,
+                    bind_group,
+                    frustum: Frustum::from_matrix(cgmath::SquareMatrix::identity()),
+                    view,
+                }
+            })
+            .collect();
+
+        let extent = wgpu::Extent3d {
+            width: size,
+            height: size,
+            depth_or_array_layers: 1,
+        };
+        let face_target = texture::Texture::create_texture(
+            device,
+            Some("reflection_probe_face_texture"),
+            extent,
+            Tonemapper::HDR_FORMAT,
+            wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+            wgpu::TextureDimension::D2,
+            wgpu::FilterMode::Nearest,
+        );
+        let (msaa_view, depth_view) = Self::create_targets(
+            device,
+            extent,
+            renderer.sample_count(),
+            renderer.depth_format(),
+        );
+
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Texture {
+                    multisampled: false,
+                    view_dimension: wgpu::TextureViewDimension::D2,
+                    sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                },
+                count: None,
+            }],
+            label: Some("reflection_probe_bind_group_layout"),
+        });
+        let mirror_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &bind_group_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: wgpu::BindingResource::TextureView(&face_target.view),
+            }],
+            label: Some("reflection_probe_bind_group"),
+        });
+        let mirror_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Reflection Probe Pipeline Layout"),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let mirror_pipeline = Self::create_mirror_pipeline(device, &mirror_layout);
+
+        Self {
+            position,
+            refresh_interval: Duration::from_secs(1),
+            since_capture: None,
+            cube,
+            faces,
+            face_target,
+            msaa_view,
+            depth_view,
+            sample_count: renderer.sample_count(),
+            depth_format: renderer.depth_format(),
+            mirror_layout,
+            mirror_pipeline,
+            mirror_bind_group,
+        }
+    }
+
+    fn create_mirror_pipeline(
+        device: &wgpu::Device,
+        layout: &wgpu::PipelineLayout,
+    ) -> wgpu::RenderPipeline {
+        let shader = device.create_shader_module(wgsl!("src/probe.wgsl", "src/fullscreen.wgsl"));
+        device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Reflection Probe Pipeline"),
+            layout: Some(layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: Some("vs_main"),
+                buffers: &[],
+                compilation_options: Default::default(),
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: Some("fs_main"),
+                targets: &[Some(wgpu::ColorTargetState {
+                    format: Tonemapper::HDR_FORMAT,
+                    blend: None,
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+                compilation_options: Default::default(),
+            }),
+            primitive: wgpu::PrimitiveState::default(),
+            depth_stencil: None,
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+            cache: None,
+        })
+    }
+
+    /// Rebuilds the pipeline mirroring the faces into the cube from the current shader, see
+    /// `hot_reload`. `update` does so itself once the renderer reloaded it.
+    pub fn recreate_pipeline(&mut self, device: &wgpu::Device) {
+        self.mirror_pipeline = Self::create_mirror_pipeline(device, &self.mirror_layout);
+    }
+
+    /// The multisampled color target, if any, and the depth buffer the scene pipelines of a
+    /// renderer with `sample_count` and `depth_format` draw a face with.
+    fn create_targets(
+        device: &wgpu::Device,
+        size: wgpu::Extent3d,
+        sample_count: u32,
+        depth_format: wgpu::TextureFormat,
+    ) -> (Option<wgpu::TextureView>, wgpu::TextureView) {
+        let msaa_view = (sample_count > 1).then(|| {
+            device
+                .create_texture(&wgpu::TextureDescriptor {
+                    label: Some("reflection_probe_msaa_texture"),
+                    size,
+                    mip_level_count: 1,
+                    sample_count,
+                    dimension: wgpu::TextureDimension::D2,
+                    format: Tonemapper::HDR_FORMAT,
+                    usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+                    view_formats: &[],
+                })
+                .create_view(&wgpu::TextureViewDescriptor::default())
+        });
+        let depth_view = device
+            .create_texture(&wgpu::TextureDescriptor {
+                label: Some("reflection_probe_depth_texture"),
+                size,
+                mip_level_count: 1,
+                sample_count,
+                dimension: wgpu::TextureDimension::D2,
+                format: depth_format,
+                usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+                view_formats: &[],
+            })
+            .create_view(&wgpu::TextureViewDescriptor::default());
+        (msaa_view, depth_view)
+    }
+
+    /// The captured environment, black before the first capture.
+    pub fn cube(&self) -> &texture::CubeTexture {
+        &self.cube
+    }
+
+    /// Texels on a side of each face.
+    pub fn size(&self) -> u32 {
+        self.face_target.size.width
+    }
+
+    /// Draws the six faces of the scene as `renderer` last updated it, seen from
+    /// `position`, into the cube.
+    pub fn capture(&mut self, renderer: &Renderer) {
+        let device = renderer.device();
+        let queue = renderer.queue();
+        // Follow MSAA or depth format changes of the renderer, whose pipelines draw here
+        if renderer.sample_count() != self.sample_count
+            || renderer.depth_format() != self.depth_format
+        {
+            self.sample_count = renderer.sample_count();
+            self.depth_format = renderer.depth_format();
+            (self.msaa_view, self.depth_view) = Self::create_targets(
+                device,
+                self.face_target.size,
+                self.sample_count,
+                self.depth_format,
+            );
+        }
+
+        let mut projection = Projection::new(
+            self.size(),
+            self.size(),
+            Deg(90.0),
+            renderer.projection().znear(),
+            renderer.projection().zfar(),
+        );
+        projection.set_reverse_z(renderer.reverse_z());
+        let proj = projection.calc_matrix();
+        for (face, (forward, up)) in self.faces.iter_mut().zip(FACES) {
+            let view = Matrix4::look_to_rh(self.position, Vector3::from(forward), up.into());
+            face.uniform.update_matrices(self.position, view, proj);
+            queue.write_buffer(&face.buffer, 0, bytemuck::cast_slice(&[face.uniform]));
+            face.frustum = Frustum::from_matrix(proj * view);
+        }
+
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("Reflection Probe Encoder"),
+        });
+        for face in &self.faces {
+            let target = &self.face_target.view;
+            let mut scene_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("Reflection Probe Scene Pass"),
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view: self.msaa_view.as_ref().unwrap_or(target),
+                    resolve_target: self.msaa_view.as_ref().map(|_| target),
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(renderer.clear_color()),
+                        store: wgpu::StoreOp::Store,
+                    },
+                    depth_slice: None,
+                })],
+                depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                    view: &self.depth_view,
+                    depth_ops: Some(wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(renderer.depth_clear()),
+                        store: wgpu::StoreOp::Discard,
+                    }),
+                    stencil_ops: self.depth_format.has_stencil_aspect().then_some(
+                        wgpu::Operations {
+                            load: wgpu::LoadOp::Clear(0),
+                            store: wgpu::StoreOp::Discard,
+                        },
+                    ),
+                }),
+                occlusion_query_set: None,
+                timestamp_writes: None,
+            });
+            renderer.draw_environment(&mut scene_pass, &face.bind_group, &face.frustum);
+            drop(scene_pass);
+
+            let mut mirror_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("Reflection Probe Mirror Pass"),
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view: &face.view,
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                        store: wgpu::StoreOp::Store,
+                    },
+                    depth_slice: None,
+                })],
+                depth_stencil_attachment: None,
+                occlusion_query_set: None,
+                timestamp_writes: None,
+            });
+            mirror_pass.set_pipeline(&self.mirror_pipeline);
+            mirror_pass.set_bind_group(0, &self.mirror_bind_group, &[]);
+            mirror_pass.draw(0..3, 0..1);
+        }
+        queue.submit([encoder.finish()]);
+        self.since_capture = Some(Duration::ZERO);
+    }
+
+    /// Advances the time since the last capture by `dt` and captures once it reaches
+    /// `refresh_interval`, or if nothing was captured yet. Returns whether it captured.
+    pub fn update(&mut self, renderer: &Renderer, dt: Duration) -> bool {
+        #[cfg(feature = "hot-reload")]
+        if renderer
+            .reloaded_shaders()
+            .iter()
+            .any(|path| path == "src/probe.wgsl" || path == "src/fullscreen.wgsl")
+        {
+            self.recreate_pipeline(renderer.device());
+        }
+        let due = match self.since_capture.as_mut() {
+            Some(since_capture) => {
+                *since_capture += dt;
+                *since_capture >= self.refresh_interval
+            }
+            None => true,
+        };
+        if due {
+            self.capture(renderer);
+        }
+        due
+    }
+}
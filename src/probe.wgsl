@@ -0,0 +1,14 @@
+// Copies a face of a reflection probe, drawn upright by the scene pipelines, into its cube
+// layer. Cube faces are seen from inside the cube, so the copy mirrors it horizontally.
+
+// `vs_main` and `FullscreenOutput` are appended from `src/fullscreen.wgsl`
+
+@group(0) @binding(0)
+var t_face: texture_2d<f32>;
+
+@fragment
+fn fs_main(in: FullscreenOutput) -> @location(0) vec4<f32> {
+  let size = textureDimensions(t_face);
+  let texel = vec2<u32>(in.pos.xy);
+  return textureLoad(t_face, vec2<u32>(size.x - 1u - texel.x, texel.y), 0);
+}
@@ -0,0 +1,259 @@
+use std::ops::Range;
+
+use wgpu::util::DeviceExt as _;
+
+use crate::{
+    model::{Mesh, Model},
+    texture,
+};
+
+/// How a `ReflectiveMaterial` mixes its base color with the environment.
+#[repr(C)]
+#[derive(Debug, Copy, Clone, PartialEq, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct ReflectiveFactors {
+    /// Linear RGB, multiplied by the vertex colors
+    pub base_color: [f32; 3],
+    /// Share of the reflected environment, 0 shows only the base color and 1 is a perfect
+    /// mirror. Clamped to that range in the shader.
+    pub reflectivity: f32,
+}
+
+impl Default for ReflectiveFactors {
+    fn default() -> Self {
+        Self {
+            base_color: [1.0; 3],
+            reflectivity: 0.5,
+        }
+    }
+}
+
+/// A cheap mirror or metal look without PBR: the environment cube seen along the view ray
+/// reflected off the surface, mixed over a flat base color by `reflectivity`. Unlit, so it
+/// only matches the rest of the scene if the environment is the sky it is drawn under, e.g.
+/// the `Skybox`'s cube or one captured by a `ReflectionProbe`.
+pub struct ReflectiveMaterial {
+    pub name: String,
+    factors: ReflectiveFactors,
+    factors_buffer: wgpu::Buffer,
+    /// Linear filtering if the environment is `filterable`, else nearest, e.g. for the
+    /// Rgba32Float cubes converted by `EquirectToCubemapConverter`
+    sampler: wgpu::Sampler,
+    pub bind_group: wgpu::BindGroup,
+}
+
+impl ReflectiveMaterial {
+    /// Whether cubes in `format` can be sampled with linear filtering on `device`, which
+    /// the layout a material's environment is bound with has to match.
+    pub fn filterable(device: &wgpu::Device, format: wgpu::TextureFormat) -> bool {
+        matches!(
+            format.sample_type(None, Some(device.features())),
+            Some(wgpu::TextureSampleType::Float { filterable: true })
+        )
+    }
+
+    /// Group 0 of `reflective.wgsl`: the environment cube, its sampler and the factors
+    /// uniform. `filterable` environments get a filtering sampler, see `filterable`.
+    pub fn create_bind_group_layout(
+        device: &wgpu::Device,
+        filterable: bool,
+    ) -> wgpu::BindGroupLayout {
+        let sampler = if filterable {
+            wgpu::SamplerBindingType::Filtering
+        } else {
+            wgpu::SamplerBindingType::NonFiltering
+        };
+        device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        multisampled: false,
+                        view_dimension: wgpu::TextureViewDimension::Cube,
+                        sample_type: wgpu::TextureSampleType::Float { filterable },
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(sampler),
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ],
+            label: Some("reflective_material_bind_group_layout"),
+        })
+    }
+
+    /// A material reflecting `environment`, which may be any float cube. `layout` comes
+    /// from `create_bind_group_layout` with whether the environment is `filterable`.
+    pub fn new(
+        device: &wgpu::Device,
+        name: &str,
+        environment: &texture::CubeTexture,
+        factors: ReflectiveFactors,
+        layout: &wgpu::BindGroupLayout,
+    ) -> Self {
+        let factors_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some(name),
+            contents: bytemuck::cast_slice(&[factors]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        let sampler = Self::create_sampler(device, name, environment);
+        let bind_group =
+            Self::create_bind_group(device, name, layout, environment, &sampler, &factors_buffer);
+
+        Self {
+            name: name.to_string(),
+            factors,
+            factors_buffer,
+            sampler,
+            bind_group,
+        }
+    }
+
+    fn create_sampler(
+        device: &wgpu::Device,
+        name: &str,
+        environment: &texture::CubeTexture,
+    ) -> wgpu::Sampler {
+        let filter = if Self::filterable(device, environment.texture().format()) {
+            wgpu::FilterMode::Linear
+        } else {
+            wgpu::FilterMode::Nearest
+        };
+        device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some(name),
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: filter,
+            min_filter: filter,
+            mipmap_filter: filter,
+            ..Default::default()
+        })
+    }
+
+    fn create_bind_group(
+        device: &wgpu::Device,
+        name: &str,
+        layout: &wgpu::BindGroupLayout,
+        environment: &texture::CubeTexture,
+        sampler: &wgpu::Sampler,
+        factors_buffer: &wgpu::Buffer,
+    ) -> wgpu::BindGroup {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(environment.view()),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(sampler),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: factors_buffer.as_entire_binding(),
+                },
+            ],
+            label: Some(name),
+        })
+    }
+
+    /// Reflects `environment` instead, e.g. after `Renderer::set_environment` swapped the
+    /// sky. `layout` has to match its filterability like in `new`.
+    pub fn set_environment(
+        &mut self,
+        device: &wgpu::Device,
+        environment: &texture::CubeTexture,
+        layout: &wgpu::BindGroupLayout,
+    ) {
+        self.sampler = Self::create_sampler(device, &self.name, environment);
+        self.bind_group = Self::create_bind_group(
+            device,
+            &self.name,
+            layout,
+            environment,
+            &self.sampler,
+            &self.factors_buffer,
+        );
+    }
+
+    pub fn factors(&self) -> ReflectiveFactors {
+        self.factors
+    }
+
+    pub fn set_factors(&mut self, queue: &wgpu::Queue, factors: ReflectiveFactors) {
+        self.factors = factors;
+        queue.write_buffer(&self.factors_buffer, 0, bytemuck::cast_slice(&[factors]));
+    }
+}
+
+/// Draws meshes with a `ReflectiveMaterial`, setting the material and camera groups (0
+/// and 1). Instances come from vertex buffer slot 1, which is left to the caller.
+pub trait DrawReflective<'a> {
+    fn draw_reflective_mesh_instanced(
+        &mut self,
+        mesh: &'a Mesh,
+        material: &'a ReflectiveMaterial,
+        instances: Range<u32>,
+        camera_bind_group: &'a wgpu::BindGroup,
+    );
+
+    /// Draws every mesh of `model` with `material` in place of the model's own materials.
+    fn draw_reflective_model_instanced(
+        &mut self,
+        model: &'a Model,
+        material: &'a ReflectiveMaterial,
+        instances: Range<u32>,
+        camera_bind_group: &'a wgpu::BindGroup,
+    );
+}
+
+impl<'a, 'b> DrawReflective<'b> for wgpu::RenderPass<'a>
+where
+    'b: 'a,
+{
+    fn draw_reflective_mesh_instanced(
+        &mut self,
+        mesh: &'b Mesh,
+        material: &'b ReflectiveMaterial,
+        instances: Range<u32>,
+        camera_bind_group: &'b wgpu::BindGroup,
+    ) {
+        self.set_vertex_buffer(0, mesh.vertex_buffer.slice(..));
+        self.set_index_buffer(mesh.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
+        self.set_bind_group(0, &material.bind_group, &[]);
+        self.set_bind_group(1, camera_bind_group, &[]);
+        self.draw_indexed(0..mesh.num_elements, 0, instances);
+    }
+
+    fn draw_reflective_model_instanced(
+        &mut self,
+        model: &'b Model,
+        material: &'b ReflectiveMaterial,
+        instances: Range<u32>,
+        camera_bind_group: &'b wgpu::BindGroup,
+    ) {
+        for mesh in &model.meshes {
+            self.draw_reflective_mesh_instanced(
+                mesh,
+                material,
+                instances.clone(),
+                camera_bind_group,
+            );
+        }
+    }
+}
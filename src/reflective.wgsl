@@ -0,0 +1,76 @@
+// Environment-mapped mirror: the environment cube sampled along the view ray reflected
+// off the surface, mixed over a flat base color. No lights, just the look of chrome or
+// polished metal reflecting the sky.
+
+struct ReflectiveFactors {
+  base_color: vec3<f32>,
+  reflectivity: f32,
+};
+
+struct VertexInput {
+  @location(0) position: vec3<f32>,
+  @location(2) normal: vec3<f32>,
+  @location(14) color: vec4<f32>,
+};
+
+struct InstanceInput {
+  @location(5) model_matrix_0: vec4<f32>,
+  @location(6) model_matrix_1: vec4<f32>,
+  @location(7) model_matrix_2: vec4<f32>,
+  @location(8) model_matrix_3: vec4<f32>,
+  @location(9) normal_matrix_0: vec3<f32>,
+  @location(10) normal_matrix_1: vec3<f32>,
+  @location(11) normal_matrix_2: vec3<f32>,
+};
+
+struct VertexOutput {
+  @builtin(position) pos: vec4<f32>,
+  @location(0) world_position: vec3<f32>,
+  @location(1) world_normal: vec3<f32>,
+  @location(2) color: vec4<f32>,
+};
+
+// `CameraUniform` and the other shared types are declared in `src/prelude.wgsl`
+
+@group(0) @binding(0)
+var t_environment: texture_cube<f32>;
+@group(0) @binding(1)
+var s_environment: sampler;
+@group(0) @binding(2)
+var<uniform> material: ReflectiveFactors;
+
+@group(1) @binding(0)
+var<uniform> camera: CameraUniform;
+
+@vertex
+fn vs_main(model: VertexInput, instance: InstanceInput) -> VertexOutput {
+  let model_matrix = mat4x4<f32>(
+    instance.model_matrix_0,
+    instance.model_matrix_1,
+    instance.model_matrix_2,
+    instance.model_matrix_3,
+  );
+  let normal_matrix = mat3x3<f32>(
+    instance.normal_matrix_0,
+    instance.normal_matrix_1,
+    instance.normal_matrix_2,
+  );
+
+  let world_position = model_matrix * vec4<f32>(model.position, 1.0);
+  var out: VertexOutput;
+  out.pos = camera.view_proj * world_position;
+  out.world_position = world_position.xyz;
+  out.world_normal = normalize(normal_matrix * model.normal);
+  out.color = model.color;
+  return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+  let n = normalize(in.world_normal);
+  let v = normalize(camera.view_pos.xyz - in.world_position);
+  let environment = textureSample(t_environment, s_environment, reflect(-v, n)).rgb;
+  let base_color = material.base_color * in.color.rgb;
+  let color = mix(base_color, environment, clamp(material.reflectivity, 0.0, 1.0));
+  return vec4<f32>(color, 1.0);
+}
@@ -0,0 +1,231 @@
+use crate::renderer::Renderer;
+
+/// A texture a `Pass` draws into.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Target {
+    /// The view the frame is drawn into, e.g. the surface texture
+    Output,
+    /// The HDR target the scene is drawn into and tone mapped from
+    Hdr,
+    /// The copy of the HDR target water reflects, see `Renderer::record_water_reflection`
+    WaterReflection,
+    /// The depth buffer of the scene
+    Depth,
+    /// The shadow map cascades
+    ShadowMap,
+}
+
+/// What a `Pass` records with.
+pub struct PassContext<'a> {
+    pub renderer: &'a Renderer,
+    pub encoder: &'a mut wgpu::CommandEncoder,
+    /// The view the frame is drawn into
+    pub output: &'a wgpu::TextureView,
+    /// The scene view a per-view pass draws, see `Renderer::scene_view_count`. 0 for other
+    /// passes.
+    pub view: usize,
+    /// Draw calls recorded so far this frame, which passes add theirs to for
+    /// `Renderer::draw_count`
+    pub draws: u32,
+}
+
+impl PassContext<'_> {
+    /// The view of `target`. `None` for the shadow map, whose cascades are drawn one at a
+    /// time, and for the water reflection while there is no water.
+    pub fn target_view(&self, target: Target) -> Option<&wgpu::TextureView> {
+        match target {
+            Target::Output => Some(self.output),
+            Target::Hdr => Some(self.renderer.hdr_view()),
+            Target::Depth => Some(self.renderer.depth_view()),
+            Target::WaterReflection => self.renderer.water_reflection_view(),
+            Target::ShadowMap => None,
+        }
+    }
+}
+
+/// One step of a `RenderGraph`: the targets it draws into and a closure recording it.
+pub struct Pass {
+    pub name: String,
+    pub color_targets: Vec<Target>,
+    pub depth_target: Option<Target>,
+    /// Records the pass once for every scene view instead of once per frame, see
+    /// `RenderGraph`
+    pub per_view: bool,
+    /// Disabled passes are skipped but keep their place
+    pub enabled: bool,
+    record: Box<dyn Fn(&mut PassContext<'_>)>,
+}
+
+impl Pass {
+    pub fn new(
+        name: impl Into<String>,
+        color_targets: &[Target],
+        depth_target: Option<Target>,
+        record: impl Fn(&mut PassContext<'_>) + 'static,
+    ) -> Self {
+        Self {
+            name: name.into(),
+            color_targets: color_targets.to_vec(),
+            depth_target,
+            per_view: false,
+            enabled: true,
+            record: Box::new(record),
+        }
+    }
+
+    /// Makes the pass record once for every scene view.
+    pub fn per_view(mut self) -> Self {
+        self.per_view = true;
+        self
+    }
+}
+
+/// The passes recording a frame, in the order they are recorded, see
+/// `Renderer::render_graph_to_view`. Passes don't declare what they read, so nothing is
+/// reordered or culled: the order is up to whoever inserts them.
+///
+/// Consecutive per-view passes are recorded together for one scene view after the other,
+/// so each view is finished before the next, which may overlap it, draws over it.
+pub struct RenderGraph {
+    passes: Vec<Pass>,
+}
+
+impl RenderGraph {
+    /// Names of the passes of `new`, in order.
+    pub const DEFAULT_PASSES: [&'static str; 6] = [
+        "shadow",
+        "opaque",
+        "transparent",
+        "water_reflection",
+        "selection",
+        "post",
+    ];
+
+    /// The renderer's own frame: the shadow maps, the opaque then the transparent scene of
+    /// each view, the copy water reflects, the selection outline and tone mapping into the
+    /// output, each calling the `Renderer::record_*` method of the same name.
+    pub fn new() -> Self {
+        let mut graph = Self::empty();
+        graph.passes = vec![
+            Pass::new("shadow", &[], Some(Target::ShadowMap), |ctx| {
+                ctx.draws += ctx.renderer.record_shadows(ctx.encoder);
+            }),
+            Pass::new("opaque", &[Target::Hdr], Some(Target::Depth), |ctx| {
+                ctx.draws += ctx.renderer.record_opaque(ctx.encoder, ctx.view);
+            })
+            .per_view(),
+            Pass::new("transparent", &[Target::Hdr], Some(Target::Depth), |ctx| {
+                ctx.draws += ctx.renderer.record_transparent(ctx.encoder, ctx.view);
+            })
+            .per_view(),
+            Pass::new(
+                "water_reflection",
+                &[Target::WaterReflection],
+                None,
+                |ctx| {
+                    ctx.renderer.record_water_reflection(ctx.encoder);
+                },
+            ),
+            Pass::new("selection", &[Target::Hdr], None, |ctx| {
+                ctx.draws += ctx.renderer.record_selection(ctx.encoder);
+            }),
+            Pass::new("post", &[Target::Output], None, |ctx| {
+                ctx.draws += ctx.renderer.record_post(ctx.encoder, ctx.output);
+            }),
+        ];
+        graph
+    }
+
+    /// A graph without passes, drawing nothing.
+    pub fn empty() -> Self {
+        Self { passes: Vec::new() }
+    }
+
+    pub fn passes(&self) -> &[Pass] {
+        &self.passes
+    }
+
+    pub fn pass_mut(&mut self, name: &str) -> Option<&mut Pass> {
+        self.passes.iter_mut().find(|pass| pass.name == name)
+    }
+
+    /// Appends `pass`, recording it after all others. Fails if a pass of the same name
+    /// exists.
+    pub fn push(&mut self, pass: Pass) -> anyhow::Result<()> {
+        self.insert(self.passes.len(), pass)
+    }
+
+    /// Inserts `pass` right before the pass named `before`.
+    pub fn insert_before(&mut self, before: &str, pass: Pass) -> anyhow::Result<()> {
+        let index = self.position(before)?;
+        self.insert(index, pass)
+    }
+
+    /// Inserts `pass` right after the pass named `after`.
+    pub fn insert_after(&mut self, after: &str, pass: Pass) -> anyhow::Result<()> {
+        let index = self.position(after)?;
+        self.insert(index + 1, pass)
+    }
+
+    /// Takes the pass named `name` out of the graph, e.g. to insert it elsewhere.
+    pub fn remove(&mut self, name: &str) -> Option<Pass> {
+        let index = self.position(name).ok()?;
+        Some(self.passes.remove(index))
+    }
+
+    fn insert(&mut self, index: usize, pass: Pass) -> anyhow::Result<()> {
+        if self.position(&pass.name).is_ok() {
+            anyhow::bail!("render graph already has a pass named {:?}", pass.name);
+        }
+        self.passes.insert(index, pass);
+        Ok(())
+    }
+
+    fn position(&self, name: &str) -> anyhow::Result<usize> {
+        self.passes
+            .iter()
+            .position(|pass| pass.name == name)
+            .ok_or_else(|| anyhow::anyhow!("render graph has no pass named {name:?}"))
+    }
+
+    /// Records the enabled passes into `encoder` and returns the number of draw calls.
+    pub(crate) fn record(
+        &self,
+        renderer: &Renderer,
+        encoder: &mut wgpu::CommandEncoder,
+        output: &wgpu::TextureView,
+    ) -> u32 {
+        let mut ctx = PassContext {
+            renderer,
+            encoder,
+            output,
+            view: 0,
+            draws: 0,
+        };
+        let passes = self
+            .passes
+            .iter()
+            .filter(|pass| pass.enabled)
+            .collect::<Vec<_>>();
+        for group in passes.chunk_by(|a, b| a.per_view == b.per_view) {
+            let views = if group[0].per_view {
+                renderer.scene_view_count()
+            } else {
+                1
+            };
+            for view in 0..views {
+                ctx.view = view;
+                for pass in group {
+                    (pass.record)(&mut ctx);
+                }
+            }
+        }
+        ctx.draws
+    }
+}
+
+impl Default for RenderGraph {
+    fn default() -> Self {
+        Self::new()
+    }
+}
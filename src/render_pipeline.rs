@@ -1,38 +1,400 @@
+use crate::{
+    hot_reload::wgsl_source,
+    model::{ModelVertex, Vertex},
+    transform::Transform,
+};
+
+/// How a pipeline combines its output with what is already in the color target.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub enum Transparency {
+    /// Alpha is ignored
+    #[default]
+    Opaque,
+    /// Alpha blended over the target. Needs drawing back-to-front after opaque geometry,
+    /// without writing depth.
+    AlphaBlend,
+    /// Alpha becomes the share of MSAA samples covered, giving order independent cutouts
+    /// for e.g. foliage. Acts like `Opaque` without MSAA.
+    AlphaToCoverage,
+}
+
+impl Transparency {
+    /// The `PipelineDesc::blend` for this mode.
+    pub fn blend(self) -> Option<wgpu::BlendState> {
+        match self {
+            Transparency::AlphaBlend => Some(wgpu::BlendState::ALPHA_BLENDING),
+            Transparency::Opaque | Transparency::AlphaToCoverage => None,
+        }
+    }
+
+    /// The `PipelineDesc::alpha_to_coverage` for this mode.
+    pub fn alpha_to_coverage(self) -> bool {
+        self == Transparency::AlphaToCoverage
+    }
+}
+
+/// Which triangles a pipeline rasterizes, see `create_render_pipeline`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct PrimitiveConfig {
+    /// The side of triangles skipped, `None` draws both
+    pub cull_mode: Option<wgpu::Face>,
+    /// The winding of front facing triangles on screen. Meshes exported with the opposite
+    /// convention render inside out unless this is `Cw`.
+    pub front_face: wgpu::FrontFace,
+}
+
+impl PrimitiveConfig {
+    /// Draws back faces too, e.g. to tell flipped normals from missing geometry.
+    pub const DOUBLE_SIDED: Self = Self {
+        cull_mode: None,
+        front_face: wgpu::FrontFace::Ccw,
+    };
+}
+
+impl Default for PrimitiveConfig {
+    /// Counter-clockwise front faces with back faces culled.
+    fn default() -> Self {
+        Self {
+            cull_mode: Some(wgpu::Face::Back),
+            front_face: wgpu::FrontFace::Ccw,
+        }
+    }
+}
+
+/// The settings of `create_render_pipeline` and its presets besides the targets, vertex
+/// layouts and shader. Set the ones that differ and take the rest from `Default`, e.g.
+/// `PipelineDesc { sample_count, ..Default::default() }`.
+#[derive(Debug, Copy, Clone)]
+pub struct PipelineDesc<'a> {
+    /// The depth test, `Less` unless the renderer uses reverse-Z
+    pub depth_compare: wgpu::CompareFunction,
+    /// Offsets the depth of every fragment, none by default. See `overlay_depth_bias` for
+    /// geometry lying on other surfaces.
+    pub depth_bias: wgpu::DepthBiasState,
+    /// Which triangles are rasterized
+    pub primitive: PrimitiveConfig,
+    /// Draws triangle edges only, needs `Features::POLYGON_MODE_LINE`
+    pub wireframe: bool,
+    /// MSAA samples of the targets
+    pub sample_count: u32,
+    /// `None` replaces the targets' colors. Blended pipelines test against the depth buffer
+    /// but don't write to it.
+    pub blend: Option<wgpu::BlendState>,
+    /// Only takes effect with MSAA
+    pub alpha_to_coverage: bool,
+    /// The channels written to every color target
+    pub color_writes: wgpu::ColorWrites,
+    pub vertex_entry: &'a str,
+    /// Unused by depth-only pipelines, which have no fragment stage
+    pub fragment_entry: &'a str,
+}
+
+impl Default for PipelineDesc<'_> {
+    /// An opaque pipeline with `vs_main` and `fs_main`, one sample and back faces culled.
+    fn default() -> Self {
+        Self {
+            depth_compare: wgpu::CompareFunction::Less,
+            depth_bias: wgpu::DepthBiasState::default(),
+            primitive: PrimitiveConfig::default(),
+            wireframe: false,
+            sample_count: 1,
+            blend: None,
+            alpha_to_coverage: false,
+            color_writes: wgpu::ColorWrites::ALL,
+            vertex_entry: "vs_main",
+            fragment_entry: "fs_main",
+        }
+    }
+}
+
+/// Writes one color target per format in `color_formats`, none makes a depth-only pipeline.
+/// Everything else comes from `desc`.
 pub fn create_render_pipeline(
     device: &wgpu::Device,
     layout: &wgpu::PipelineLayout,
-    color_format: wgpu::TextureFormat,
+    color_formats: &[wgpu::TextureFormat],
     depth_format: Option<wgpu::TextureFormat>,
     vertex_layouts: &[wgpu::VertexBufferLayout],
     shader: wgpu::ShaderModuleDescriptor,
-    wireframe_mode: bool,
+    desc: PipelineDesc,
+) -> wgpu::RenderPipeline {
+    build_pipeline(
+        device,
+        layout,
+        color_formats,
+        depth_format.map(|format| wgpu::DepthStencilState {
+            bias: desc.depth_bias,
+            ..depth_state(format, desc.blend.is_none(), desc.depth_compare)
+        }),
+        vertex_layouts,
+        shader,
+        desc,
+    )
+}
+
+/// Pulls fragments towards the camera by a couple of depth steps, more on surfaces seen at
+/// a grazing angle, so geometry drawn onto a coplanar surface, e.g. roads or markings on
+/// terrain, wins the depth test instead of flickering against it. The direction follows
+/// `depth_compare`, the test of the pipeline it is for, so it also holds with reverse-Z.
+pub fn overlay_depth_bias(depth_compare: wgpu::CompareFunction) -> wgpu::DepthBiasState {
+    let (constant, slope_scale) = match depth_compare {
+        wgpu::CompareFunction::Greater | wgpu::CompareFunction::GreaterEqual => (2, 2.0),
+        _ => (-2, -2.0),
+    };
+    wgpu::DepthBiasState {
+        constant,
+        slope_scale,
+        // Clamping needs `DownlevelFlags::DEPTH_BIAS_CLAMP`, which GL may lack
+        clamp: 0.0,
+    }
+}
+
+/// `create_render_pipeline` for alpha blended meshes lying on other geometry, e.g. roads or
+/// overlay meshes on terrain, using `overlay_depth_bias` for `desc.depth_compare`. Like
+/// other blended pipelines it tests depth without writing it, so overlays are drawn after
+/// what they lie on.
+pub fn create_overlay_pipeline(
+    device: &wgpu::Device,
+    layout: &wgpu::PipelineLayout,
+    color_format: wgpu::TextureFormat,
+    depth_format: wgpu::TextureFormat,
+    vertex_layouts: &[wgpu::VertexBufferLayout],
+    shader: wgpu::ShaderModuleDescriptor,
+    desc: PipelineDesc,
+) -> wgpu::RenderPipeline {
+    create_render_pipeline(
+        device,
+        layout,
+        &[color_format],
+        Some(depth_format),
+        vertex_layouts,
+        shader,
+        PipelineDesc {
+            depth_bias: overlay_depth_bias(desc.depth_compare),
+            blend: Some(wgpu::BlendState::ALPHA_BLENDING),
+            ..desc
+        },
+    )
+}
+
+/// `create_render_pipeline` testing and updating the stencil buffer as `depth_stencil`
+/// says, which needs a format with a stencil aspect such as
+/// `Texture::DEPTH_STENCIL_FORMAT`. The reference value comes from
+/// `RenderPass::set_stencil_reference`. `depth_stencil` also sets the depth test and write,
+/// e.g. `Always` without writing ignores depth, in place of the depth fields of `desc`. An
+/// empty `desc.color_writes` makes a pipeline that only marks pixels in the stencil buffer.
+pub fn create_stencil_pipeline(
+    device: &wgpu::Device,
+    layout: &wgpu::PipelineLayout,
+    color_format: wgpu::TextureFormat,
+    depth_stencil: wgpu::DepthStencilState,
+    vertex_layouts: &[wgpu::VertexBufferLayout],
+    shader: wgpu::ShaderModuleDescriptor,
+    desc: PipelineDesc,
+) -> wgpu::RenderPipeline {
+    build_pipeline(
+        device,
+        layout,
+        &[color_format],
+        Some(depth_stencil),
+        vertex_layouts,
+        shader,
+        desc,
+    )
+}
+
+/// Depth-only pipeline running just the vertex stage of `shader`, to fill the depth buffer
+/// before drawing the same geometry with `create_depth_equal_pipeline`. Every fragment
+/// hidden behind another is then rejected by the depth test instead of being shaded.
+pub fn create_depth_prepass_pipeline(
+    device: &wgpu::Device,
+    layout: &wgpu::PipelineLayout,
+    depth_format: wgpu::TextureFormat,
+    vertex_layouts: &[wgpu::VertexBufferLayout],
+    shader: wgpu::ShaderModuleDescriptor,
+    desc: PipelineDesc,
+) -> wgpu::RenderPipeline {
+    create_render_pipeline(
+        device,
+        layout,
+        &[],
+        Some(depth_format),
+        vertex_layouts,
+        shader,
+        desc,
+    )
+}
+
+/// Opaque color pipeline for geometry already drawn by `create_depth_prepass_pipeline`.
+/// Only the fragments whose depth matches the prepass are shaded, and depth isn't written
+/// again, whatever `desc.depth_compare` says. `shader` needs an `@invariant` position so
+/// both pipelines compute the same depth.
+pub fn create_depth_equal_pipeline(
+    device: &wgpu::Device,
+    layout: &wgpu::PipelineLayout,
+    color_format: wgpu::TextureFormat,
+    depth_format: wgpu::TextureFormat,
+    vertex_layouts: &[wgpu::VertexBufferLayout],
+    shader: wgpu::ShaderModuleDescriptor,
+    desc: PipelineDesc,
+) -> wgpu::RenderPipeline {
+    build_pipeline(
+        device,
+        layout,
+        &[color_format],
+        Some(depth_state(
+            depth_format,
+            false,
+            wgpu::CompareFunction::Equal,
+        )),
+        vertex_layouts,
+        shader,
+        PipelineDesc {
+            blend: None,
+            ..desc
+        },
+    )
+}
+
+/// Model and normal matrix of one draw, set with `RenderPass::set_push_constants` for a
+/// pipeline from `create_push_constant_pipeline`. Like a WGSL `mat3x3`, each column of the
+/// normal matrix is padded to four floats.
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct DrawTransform {
+    pub model: [[f32; 4]; 4],
+    pub normal: [[f32; 4]; 3],
+}
+
+impl DrawTransform {
+    /// 112 bytes, within the 128 wgpu guarantees wherever push constants are supported.
+    pub const SIZE: u32 = std::mem::size_of::<Self>() as u32;
+
+    /// The push constant range of the pipeline layout, covering the vertex stage.
+    pub const RANGE: wgpu::PushConstantRange = wgpu::PushConstantRange {
+        stages: wgpu::ShaderStages::VERTEX,
+        range: 0..Self::SIZE,
+    };
+
+    /// Whether `device`, created from `adapter`, can take a `DrawTransform` as push
+    /// constants. Besides the `PUSH_CONSTANTS` feature, its `max_push_constant_size` limit
+    /// has to hold `SIZE` bytes. That limit defaults to 0, so it must be requested with the
+    /// device, see `Renderer::request_device`. Otherwise draw through an instance buffer
+    /// instead, e.g. `ModelTransform`.
+    pub fn supported(adapter: &wgpu::Adapter, device: &wgpu::Device) -> bool {
+        // GL only emulates push constants with plain uniforms, and mixes up the types of
+        // their members once a module has several entry points
+        adapter.get_info().backend != wgpu::Backend::Gl
+            && device.features().contains(wgpu::Features::PUSH_CONSTANTS)
+            && device.limits().max_push_constant_size >= Self::SIZE
+    }
+}
+
+impl From<&Transform> for DrawTransform {
+    fn from(transform: &Transform) -> Self {
+        let normal: [[f32; 3]; 3] = transform.normal_matrix().into();
+        Self {
+            model: transform.to_matrix().into(),
+            normal: normal.map(|[x, y, z]| [x, y, z, 0.0]),
+        }
+    }
+}
+
+/// `create_render_pipeline` for opaque single models with shader.wgsl, taking the model's
+/// matrices from a `DrawTransform` pushed before each draw rather than from vertex buffer
+/// slot 1. Needs a device where `DrawTransform::supported`, and `layout` has to include
+/// `DrawTransform::RANGE`. The vertex entry point of `desc` is replaced.
+pub fn create_push_constant_pipeline(
+    device: &wgpu::Device,
+    layout: &wgpu::PipelineLayout,
+    color_format: wgpu::TextureFormat,
+    depth_format: wgpu::TextureFormat,
+    desc: PipelineDesc,
+) -> wgpu::RenderPipeline {
+    // Only this pipeline declares the push constants, which would fail validation on
+    // devices without them
+    let source = [
+        wgsl_source!(
+            "shader.wgsl",
+            "src/shadow_sampling.wgsl",
+            "src/prelude.wgsl"
+        ),
+        wgsl_source!("push_constants.wgsl"),
+    ]
+    .concat();
+    create_render_pipeline(
+        device,
+        layout,
+        &[color_format],
+        Some(depth_format),
+        &[ModelVertex::desc()],
+        wgpu::ShaderModuleDescriptor {
+            label: Some("Push Constant Shader"),
+            source: wgpu::ShaderSource::Wgsl(source.into()),
+        },
+        PipelineDesc {
+            vertex_entry: "vs_push_constant",
+            ..desc
+        },
+    )
+}
+
+fn depth_state(
+    format: wgpu::TextureFormat,
+    depth_write_enabled: bool,
+    depth_compare: wgpu::CompareFunction,
+) -> wgpu::DepthStencilState {
+    wgpu::DepthStencilState {
+        format,
+        depth_write_enabled,
+        depth_compare,
+        stencil: wgpu::StencilState::default(),
+        bias: wgpu::DepthBiasState::default(),
+    }
+}
+
+fn build_pipeline(
+    device: &wgpu::Device,
+    layout: &wgpu::PipelineLayout,
+    color_formats: &[wgpu::TextureFormat],
+    depth_stencil: Option<wgpu::DepthStencilState>,
+    vertex_layouts: &[wgpu::VertexBufferLayout],
+    shader: wgpu::ShaderModuleDescriptor,
+    desc: PipelineDesc,
 ) -> wgpu::RenderPipeline {
     let shader = device.create_shader_module(shader);
+    let color_targets = color_formats
+        .iter()
+        .map(|&format| {
+            Some(wgpu::ColorTargetState {
+                format,
+                blend: Some(desc.blend.unwrap_or(wgpu::BlendState::REPLACE)),
+                write_mask: desc.color_writes,
+            })
+        })
+        .collect::<Vec<_>>();
     device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
         label: Some("Render Pipeline"),
         layout: Some(layout),
         vertex: wgpu::VertexState {
             module: &shader,
-            entry_point: Some("vs_main"),
+            entry_point: Some(desc.vertex_entry),
             buffers: vertex_layouts,
             compilation_options: wgpu::PipelineCompilationOptions::default(),
         },
-        fragment: Some(wgpu::FragmentState {
+        // Depth-only pipelines (e.g. shadow maps) have no color output and no fragment stage
+        fragment: (!color_targets.is_empty()).then(|| wgpu::FragmentState {
             module: &shader,
-            entry_point: Some("fs_main"),
-            targets: &[Some(wgpu::ColorTargetState {
-                format: color_format,
-                blend: Some(wgpu::BlendState::REPLACE),
-                write_mask: wgpu::ColorWrites::ALL,
-            })],
+            entry_point: Some(desc.fragment_entry),
+            targets: &color_targets,
             compilation_options: wgpu::PipelineCompilationOptions::default(),
         }),
         primitive: wgpu::PrimitiveState {
             topology: wgpu::PrimitiveTopology::TriangleList,
             strip_index_format: None,
-            front_face: wgpu::FrontFace::Ccw,
-            cull_mode: Some(wgpu::Face::Back),
-            polygon_mode: if wireframe_mode {
+            front_face: desc.primitive.front_face,
+            cull_mode: desc.primitive.cull_mode,
+            polygon_mode: if desc.wireframe {
                 wgpu::PolygonMode::Line
             } else {
                 wgpu::PolygonMode::Fill
@@ -40,19 +402,84 @@ pub fn create_render_pipeline(
             unclipped_depth: false,
             conservative: false,
         },
-        depth_stencil: depth_format.map(|format| wgpu::DepthStencilState {
-            format,
-            depth_write_enabled: true,
-            depth_compare: wgpu::CompareFunction::LessEqual,
-            stencil: wgpu::StencilState::default(),
-            bias: wgpu::DepthBiasState::default(),
-        }),
+        depth_stencil,
         multisample: wgpu::MultisampleState {
-            count: 1,
+            count: desc.sample_count,
             mask: !0,
-            alpha_to_coverage_enabled: false,
+            alpha_to_coverage_enabled: desc.alpha_to_coverage && desc.sample_count > 1,
         },
         multiview: None,
         cache: None,
     })
 }
+
+/// `create_render_pipeline` with the metallic-roughness shader in `pbr.wgsl`, for drawing
+/// `PbrMaterial`s. The layout holds the material, camera, light array, sun, shadow, fog and
+/// `IblEnvironment` bind groups, in that order.
+pub fn create_pbr_pipeline(
+    device: &wgpu::Device,
+    layout: &wgpu::PipelineLayout,
+    color_format: Option<wgpu::TextureFormat>,
+    depth_format: Option<wgpu::TextureFormat>,
+    vertex_layouts: &[wgpu::VertexBufferLayout],
+    desc: PipelineDesc,
+) -> wgpu::RenderPipeline {
+    create_render_pipeline(
+        device,
+        layout,
+        color_format.as_slice(),
+        depth_format,
+        vertex_layouts,
+        wgpu::ShaderModuleDescriptor {
+            label: Some("PBR Shader"),
+            source: wgpu::ShaderSource::Wgsl(wgsl_source!(
+                "src/pbr.wgsl",
+                "src/shadow_sampling.wgsl",
+                "src/prelude.wgsl"
+            )),
+        },
+        desc,
+    )
+}
+
+/// The layout of `create_reflective_pipeline`: `material_layout` from
+/// `ReflectiveMaterial::create_bind_group_layout`, then the camera's.
+pub fn create_reflective_pipeline_layout(
+    device: &wgpu::Device,
+    material_layout: &wgpu::BindGroupLayout,
+    camera_layout: &wgpu::BindGroupLayout,
+) -> wgpu::PipelineLayout {
+    device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+        label: Some("Reflective Pipeline Layout"),
+        bind_group_layouts: &[material_layout, camera_layout],
+        push_constant_ranges: &[],
+    })
+}
+
+/// `create_render_pipeline` with the environment-mapped mirror shader in
+/// `reflective.wgsl`, for drawing `ReflectiveMaterial`s. The layout holds the material and
+/// camera bind groups, in that order, see `create_reflective_pipeline_layout`.
+pub fn create_reflective_pipeline(
+    device: &wgpu::Device,
+    layout: &wgpu::PipelineLayout,
+    color_format: wgpu::TextureFormat,
+    depth_format: Option<wgpu::TextureFormat>,
+    vertex_layouts: &[wgpu::VertexBufferLayout],
+    desc: PipelineDesc,
+) -> wgpu::RenderPipeline {
+    create_render_pipeline(
+        device,
+        layout,
+        &[color_format],
+        depth_format,
+        vertex_layouts,
+        wgpu::ShaderModuleDescriptor {
+            label: Some("Reflective Shader"),
+            source: wgpu::ShaderSource::Wgsl(wgsl_source!(
+                "src/reflective.wgsl",
+                "src/prelude.wgsl"
+            )),
+        },
+        desc,
+    )
+}
@@ -0,0 +1,4345 @@
+use std::{
+    iter,
+    sync::{
+        Arc,
+        atomic::{AtomicU32, AtomicU64, Ordering},
+    },
+};
+
+use crate::{
+    animation::{AnimatedModel, SkinUniform},
+    atmosphere::AtmosphereSkybox,
+    billboard::{Billboard, ImpostorLod},
+    bloom::Bloom,
+    cache::ResourceCache,
+    camera::{Camera, CameraState, CameraUniform, Projection, ProjectionKind},
+    colormap::Colormap,
+    debug_lines::DebugLines,
+    decal::Decals,
+    deferred::{Deferred, RenderingMode},
+    environment::Environment,
+    exposure::{AutoExposure, AutoExposureSettings},
+    fog::{Fog, FogMode, FogUniform},
+    frustum::Frustum,
+    fxaa::Fxaa,
+    gizmo::Gizmo,
+    grid::GridPlane,
+    hot_reload::wgsl_source,
+    ibl::{IblBaker, IblEnvironment},
+    instance::{Instance, InstanceRaw},
+    light::{
+        Attenuation, DirectionalLight, DirectionalLightUniform, Light, LightAnimator, LightArray,
+        LightUniform, SpotLight,
+    },
+    loader::{LoadHandle, LoadProgress, LoaderPool, ResourceLoader},
+    model::{self, Vertex},
+    outline::{ObjectId, Outline, OutlineMethod},
+    overlay::{LoadingScreen, RenderStats, StatsOverlay},
+    particles::{ParticleSettings, ParticleSystem},
+    picking::DepthProbe,
+    render_graph::RenderGraph,
+    render_pipeline::{
+        DrawTransform, PipelineDesc, PrimitiveConfig, Transparency, create_depth_equal_pipeline,
+        create_depth_prepass_pipeline, create_push_constant_pipeline, create_render_pipeline,
+    },
+    resources,
+    shadow::ShadowMap,
+    skybox::Skybox,
+    splat::{Contours, SplatMaterial, SplatParams},
+    ssao::SsaoSettings,
+    taa::Taa,
+    terrain::{ChunkCoord, HeightField, LodSelector, Terrain, TerrainStreamer},
+    tessellation::{TerrainTessellator, TessellationSettings, TessellationView},
+    timing::{GpuPass, GpuTimer, GpuTimings},
+    tonemap::{TonemapMode, Tonemapper},
+    transform::{ModelTransform, Transform},
+    velocity::MotionVectors,
+    viewport::{Rect, Viewport},
+    water::Water,
+};
+use cgmath::Rotation3;
+use cgmath::prelude::*;
+use wgpu::util::DeviceExt;
+
+use crate::texture;
+
+/// The device, pipelines and scene, drawing frames into any texture of the output format.
+/// `WindowState` wraps one to present into a window, `new_headless` creates one without.
+pub struct Renderer {
+    /// Format and size of the textures frames are drawn into, a copy of the surface
+    /// configuration when presenting to a window
+    config: wgpu::SurfaceConfiguration,
+    /// Background of the scene wherever nothing, not even the skybox, is drawn
+    clear_color: wgpu::Color,
+    /// Value the depth buffer is cleared to each frame
+    depth_clear: f32,
+    /// Near is at depth 1 and far at 0, see `RendererOptions::reverse_z`
+    reverse_z: bool,
+    /// With a stencil aspect if `RendererOptions::stencil` is set
+    depth_format: wgpu::TextureFormat,
+
+    // wgpu resource
+    adapter: wgpu::Adapter,
+    device: wgpu::Device,
+    queue: wgpu::Queue,
+
+    // main pipeline
+    render_pipeline_layout: wgpu::PipelineLayout,
+    render_pipeline: wgpu::RenderPipeline,
+    /// `None` when the device lacks `POLYGON_MODE_LINE`
+    wireframe_pipeline: Option<wgpu::RenderPipeline>,
+    /// Whether the wireframe overlay is drawn over the filled models
+    wireframe: bool,
+    /// Culling and winding of the scene pipelines drawing models and terrain
+    primitive: PrimitiveConfig,
+    /// Fill the depth buffer with the opaque geometry before shading it, so each pixel is
+    /// shaded once no matter how much geometry overlaps
+    depth_prepass: bool,
+    depth_prepass_pipeline: wgpu::RenderPipeline,
+    /// `render_pipeline` testing for equal depth, for the color pass after the prepass
+    depth_equal_pipeline: wgpu::RenderPipeline,
+    /// Layout and pipeline drawing `models` with their `DrawTransform` in push constants,
+    /// `None` unless enabled with `set_push_constants`
+    push_constants: Option<(wgpu::PipelineLayout, wgpu::RenderPipeline)>,
+    projection: Projection,
+    instances: Vec<Instance>,
+    /// The model matrix of each of `instances` as of the previous update, for motion
+    /// vectors
+    previous_instances: Vec<cgmath::Matrix4<f32>>,
+    /// Every instance, drawn into the shadow map
+    instance_buffer: wgpu::Buffer,
+    /// The instances inside the view frustum, refreshed every update
+    visible_instance_buffer: wgpu::Buffer,
+    visible_instances: u32,
+    /// How `transparent_instances` are drawn
+    transparency: Transparency,
+    transparent_pipeline: wgpu::RenderPipeline,
+    /// Instances drawn after all opaque geometry
+    transparent_instances: Vec<Instance>,
+    /// The transparent instances inside the view frustum sorted back-to-front, refreshed
+    /// every update
+    transparent_instance_buffer: wgpu::Buffer,
+    visible_transparent_instances: u32,
+    frustum: Frustum,
+    obj_model: model::Model,
+    /// The startup model while it loads in the background, replacing `obj_model` once
+    /// `update` finds it finished
+    pending_model: Option<LoadHandle<model::Model>>,
+    texture_bind_group_layout: wgpu::BindGroupLayout,
+    /// Textures and materials of loaded models, shared with the loaders handed out
+    resource_cache: Arc<ResourceCache>,
+    /// Worker threads of the startup load and the loaders handed out
+    loader_pool: LoaderPool,
+    terrain_streamer: Option<TerrainStreamer>,
+    /// Replaces the meshes of streamed chunks near the camera with subdivided ones
+    terrain_tessellator: Option<TerrainTessellator>,
+    /// Rain or snow around the camera, drawn with the transparent geometry
+    particles: Option<ParticleSystem>,
+    /// Textures stamped onto the opaque geometry before the transparent pass
+    decals: Decals,
+    #[cfg(feature = "hot-reload")]
+    shader_watcher: crate::hot_reload::ShaderWatcher,
+    /// The WGSL files whose edits the last `update` took in
+    #[cfg(feature = "hot-reload")]
+    reloaded_shaders: Vec<String>,
+    /// Group 6 of the forward pipelines, holding joint matrices
+    skin_bind_group_layout: wgpu::BindGroupLayout,
+    /// Bound for everything that isn't skinned
+    identity_skin: SkinUniform,
+    animated_models: Vec<AnimatedModel>,
+    /// Single models placed by their own transform, see `add_model`
+    models: Vec<(model::Model, ModelTransform)>,
+    /// Outlined by `outline`, see `set_selected`
+    selected: Vec<ObjectId>,
+    /// Created once something is selected or the outline is configured
+    outline: Option<Outline>,
+    /// Billboards standing in for far instances of `obj_model`
+    impostors: Option<ImpostorLod>,
+    water: Option<Water>,
+    /// Copy of the last frame's scene the water reflects, while there is water
+    water_reflection: Option<texture::Texture>,
+    /// A single identity instance, streamed terrain chunks are already in world space
+    terrain_instance_buffer: wgpu::Buffer,
+    /// Group 0 of the splat pipeline, see `SplatMaterial::create_bind_group_layout`
+    splat_bind_group_layout: wgpu::BindGroupLayout,
+    splat_pipeline_layout: wgpu::PipelineLayout,
+    /// Streamed terrain is drawn with this material and its pipelines instead of the
+    /// chunks' own material, while set
+    terrain_splat: Option<(SplatMaterial, SplatPipelines)>,
+    depth_texture: texture::Texture,
+    /// MSAA samples per pixel, 1 when disabled
+    sample_count: u32,
+    /// Multisampled color target resolved into the HDR target, `None` without MSAA
+    msaa_view: Option<wgpu::TextureView>,
+    /// Fraction of the output size the scene is rendered at
+    render_scale: f32,
+    /// The scene is drawn into this HDR target at the render resolution, then tone mapped
+    /// into the output texture
+    hdr_target: texture::Texture,
+    hdr_bind_group: wgpu::BindGroup,
+    tonemapper: Tonemapper,
+    fxaa: Fxaa,
+    /// While FXAA is on, the tone mapper writes into this output sized image, which FXAA
+    /// then smooths into the output texture
+    fxaa_target: Option<(texture::Texture, wgpu::BindGroup)>,
+    /// Glow added onto the HDR target before tone mapping, while on
+    bloom: Option<Bloom>,
+    /// Adapts the tone mapping exposure to the HDR target, while on
+    auto_exposure: Option<AutoExposure>,
+    auto_exposure_settings: AutoExposureSettings,
+    /// Per-pixel screen motion of the opaque scene, while on
+    motion_vectors: Option<MotionVectors>,
+    /// Temporal anti-aliasing of the HDR target, while on. Needs `motion_vectors`.
+    taa: Option<Taa>,
+    taa_blend: f32,
+    depth_probe: DepthProbe,
+    rendering_mode: RenderingMode,
+    /// G-buffer and pipelines of `RenderingMode::Deferred`, created on first use
+    deferred: Option<Deferred>,
+    /// Whether deferred rendering darkens ambient light with screen-space occlusion
+    ssao: bool,
+    ssao_settings: SsaoSettings,
+
+    // camera
+    camera: Camera,
+    camera_uniform: CameraUniform,
+    camera_buffer: wgpu::Buffer,
+    camera_bind_group: wgpu::BindGroup,
+    camera_bind_group_layout: wgpu::BindGroupLayout,
+    /// Cameras drawing into parts of the output in place of `camera`, while there are any
+    viewports: Vec<Viewport>,
+
+    // skybox
+    skybox: Skybox,
+    /// Procedural sky drawn in place of `skybox` while set, lit by `sun`
+    atmosphere: Option<AtmosphereSkybox>,
+    /// Image-based lighting baked from the sky, empty until `set_environment`
+    ibl: IblEnvironment,
+    ibl_bind_group_layout: wgpu::BindGroupLayout,
+    /// Created by the first `set_environment`, after which the lighting follows the sky
+    ibl_baker: Option<IblBaker>,
+
+    // light
+    light: Light,
+    light_animator: LightAnimator,
+    lights: LightArray,
+    sun: DirectionalLight,
+    shadow_map: ShadowMap,
+
+    /// Off by default, see `Fog::set_mode`
+    pub fog: Fog,
+
+    // debug
+    stats_overlay: StatsOverlay,
+    loading_screen: LoadingScreen,
+    /// Lines drawn over the scene while `debug_lines_visible` is set. Queue lines before
+    /// `update`, which uploads them for the next frame.
+    pub debug_lines: DebugLines,
+    /// Draws the debug lines, including the bounds of every visible instance and chunk
+    debug_lines_visible: bool,
+    /// Translation gizmo on the point light, drawn over everything while visible
+    gizmo: Gizmo,
+    gizmo_visible: bool,
+    /// Infinite reference grid on the ground, drawn before opaque geometry while visible
+    grid: GridPlane,
+    grid_visible: bool,
+    /// Draw calls issued by the last frame's shadow, scene and post command buffers, each
+    /// stored by its own encoder so they can be recorded on different threads
+    draw_counts: [AtomicU32; 3],
+    /// Triangles and instances drawn since the last `update`, see `count_instances`
+    triangle_count: AtomicU64,
+    instance_count: AtomicU64,
+    /// Per-pass GPU timing, if the device supports timestamp queries
+    gpu_timer: Option<GpuTimer>,
+}
+
+/// Choices fixed when a `Renderer` is created.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RendererOptions {
+    /// Maps the near plane to depth 1 and the far plane to 0, clearing depth to 0 and
+    /// keeping the fragments with the greater depth. Floats are densest near 0, so this
+    /// spreads precision much more evenly over the view distance and avoids z-fighting far
+    /// away on large terrain.
+    pub reverse_z: bool,
+    /// Gives the depth buffer a stencil aspect (`Texture::DEPTH_STENCIL_FORMAT`), which
+    /// `OutlineMethod::Stencil` needs. Depth may be less precise in exchange.
+    pub stencil: bool,
+    /// Whether frames are drawn in an sRGB or a plain unorm format, see
+    /// `SurfaceFormatPreference`
+    pub surface_format: SurfaceFormatPreference,
+}
+
+/// Which kind of surface format frames are drawn in. Either way the output is sRGB
+/// encoded: sRGB formats encode on write, while for the others the tone mapping pass
+/// encodes in the shader. Check `Renderer::output_format` for the format in use.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SurfaceFormatPreference {
+    /// e.g. `Bgra8UnormSrgb`, blending and filtering the output in linear space
+    #[default]
+    Srgb,
+    /// e.g. `Bgra8Unorm`, holding the encoded values as they are. Some compositors and
+    /// capture tools only accept these.
+    NonSrgb,
+}
+
+impl SurfaceFormatPreference {
+    /// Whether `format` is of the preferred kind.
+    pub fn matches(self, format: wgpu::TextureFormat) -> bool {
+        format.is_srgb() == (self == SurfaceFormatPreference::Srgb)
+    }
+
+    /// The first of the surface's supported `formats` of the preferred kind, or the
+    /// surface's first format with a warning if it supports none.
+    pub fn choose(self, formats: &[wgpu::TextureFormat]) -> wgpu::TextureFormat {
+        formats
+            .iter()
+            .copied()
+            .find(|&format| self.matches(format))
+            .unwrap_or_else(|| {
+                log::warn!(
+                    "No {self:?} surface format in {formats:?}, using {:?}",
+                    formats[0]
+                );
+                formats[0]
+            })
+    }
+}
+
+/// The command buffers recording one frame, see `Renderer::encode_frame`. Each only reads
+/// the renderer, so they can be recorded on different threads, but they must be submitted
+/// together and in field order, e.g. with `queue.submit(commands)`: the scene samples the
+/// shadow maps and post processing reads the scene from the HDR target. Call
+/// `Renderer::after_submit` once they are submitted.
+pub struct FrameCommands {
+    /// The shadow map cascades
+    pub shadow: wgpu::CommandBuffer,
+    /// Every scene view, drawn into the HDR target
+    pub scene: wgpu::CommandBuffer,
+    /// Tone mapping, FXAA and the stats overlay into the output view
+    pub post: wgpu::CommandBuffer,
+}
+
+impl IntoIterator for FrameCommands {
+    type Item = wgpu::CommandBuffer;
+    type IntoIter = std::array::IntoIter<wgpu::CommandBuffer, 3>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        [self.shadow, self.scene, self.post].into_iter()
+    }
+}
+
+// Recording `FrameCommands` on worker threads shares the renderer between them
+#[cfg(not(target_arch = "wasm32"))]
+const _: () = {
+    fn assert_sync<T: Sync>() {}
+    let _ = assert_sync::<Renderer>;
+};
+
+/// What one camera sees of the scene in a frame, the main camera's or a viewport's.
+struct SceneView<'a> {
+    camera_bind_group: &'a wgpu::BindGroup,
+    frustum: &'a Frustum,
+    /// Buffer and count of the opaque instances inside the frustum
+    instances: (&'a wgpu::Buffer, u32),
+    /// Buffer and count of the transparent instances inside the frustum, back-to-front
+    transparent_instances: (&'a wgpu::Buffer, u32),
+    /// Part of the render target drawn into, `None` for all of it
+    rect: Option<Rect>,
+    /// Whether to draw the impostor billboards, which are only placed for the main camera
+    impostors: bool,
+}
+
+impl SceneView<'_> {
+    /// Confines the draws of `render_pass` to this view's part of the render target.
+    fn restrict(&self, render_pass: &mut wgpu::RenderPass<'_>) {
+        if let Some(rect) = self.rect {
+            render_pass.set_viewport(
+                rect.x as f32,
+                rect.y as f32,
+                rect.width as f32,
+                rect.height as f32,
+                0.0,
+                1.0,
+            );
+            render_pass.set_scissor_rect(rect.x, rect.y, rect.width, rect.height);
+        }
+    }
+}
+
+/// Which pass `draw_opaque` draws the opaque geometry for, picking its pipeline.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum OpaquePass {
+    /// Depth only, ahead of `DepthEqual`
+    DepthPrepass,
+    /// Depth and `MotionVectors::velocity`, in place of `DepthPrepass` while motion vectors
+    /// are on
+    Velocity,
+    /// Into the G-buffer of `RenderingMode::Deferred`, and the velocity while motion
+    /// vectors are on
+    GBuffer,
+    /// Shading only the fragments the prepass left visible
+    DepthEqual,
+    /// Shading with the depth test, the only pass drawing `models` with push constants
+    /// once they are enabled
+    Forward,
+}
+
+/// The pipelines drawing streamed terrain with a `SplatMaterial`, all from `terrain.wgsl`
+/// so the prepass and the color pass place the vertices identically.
+struct SplatPipelines {
+    forward: wgpu::RenderPipeline,
+    depth_prepass: wgpu::RenderPipeline,
+    depth_equal: wgpu::RenderPipeline,
+}
+
+const NUM_INSTANCES_PER_ROW: u32 = 10;
+impl Renderer {
+    /// A renderer without a window, drawing `width` x `height` frames in
+    /// `Rgba8UnormSrgb`, or `Rgba8Unorm` with `SurfaceFormatPreference::NonSrgb`, e.g. for
+    /// snapshot tests or thumbnails.
+    pub async fn new_headless(width: u32, height: u32) -> anyhow::Result<Self> {
+        Self::new_headless_with_options(width, height, RendererOptions::default()).await
+    }
+
+    /// `new_headless` with non-default `options`.
+    pub async fn new_headless_with_options(
+        width: u32,
+        height: u32,
+        options: RendererOptions,
+    ) -> anyhow::Result<Self> {
+        let instance = wgpu::Instance::new(&wgpu::InstanceDescriptor {
+            backends: wgpu::Backends::all(),
+            flags: wgpu::InstanceFlags::default(),
+            memory_budget_thresholds: Default::default(),
+            backend_options: Default::default(),
+        });
+        let adapter = instance
+            .request_adapter(&wgpu::RequestAdapterOptions {
+                power_preference: wgpu::PowerPreference::default(),
+                compatible_surface: None,
+                force_fallback_adapter: false,
+            })
+            .await?;
+        let (device, queue) = Self::request_device(&adapter).await?;
+        let config = wgpu::SurfaceConfiguration {
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+            format: options.surface_format.choose(&[
+                wgpu::TextureFormat::Rgba8UnormSrgb,
+                wgpu::TextureFormat::Rgba8Unorm,
+            ]),
+            width: width.max(1),
+            height: height.max(1),
+            present_mode: wgpu::PresentMode::Fifo,
+            alpha_mode: wgpu::CompositeAlphaMode::Auto,
+            view_formats: vec![],
+            desired_maximum_frame_latency: 2,
+        };
+        Ok(Self::with_options(adapter, device, queue, config, options).await)
+    }
+
+    /// Requests a device from `adapter` with the features and limits the renderer needs.
+    pub async fn request_device(
+        adapter: &wgpu::Adapter,
+    ) -> anyhow::Result<(wgpu::Device, wgpu::Queue)> {
+        Ok(adapter
+            .request_device(&wgpu::DeviceDescriptor {
+                label: None,
+                // Adapter specific format features allow 2x and 8x MSAA where supported
+                // Line polygon mode is only needed for the optional wireframe overlay
+                // Timestamp queries are only needed for per-pass GPU timings
+                // Without texture compression KTX2 textures are decoded on the CPU
+                // Push constants are only needed for `Renderer::set_push_constants`
+                required_features: adapter.features()
+                    & (wgpu::Features::POLYGON_MODE_LINE
+                        | wgpu::Features::TEXTURE_ADAPTER_SPECIFIC_FORMAT_FEATURES
+                        | wgpu::Features::TIMESTAMP_QUERY
+                        | wgpu::Features::TEXTURE_COMPRESSION_BC
+                        | wgpu::Features::TEXTURE_COMPRESSION_ASTC
+                        | wgpu::Features::PUSH_CONSTANTS),
+                required_limits: wgpu::Limits {
+                    // The forward pass uses more than the default four bind groups
+                    max_bind_groups: adapter.limits().max_bind_groups,
+                    // 0 without the feature, see `DrawTransform::supported`
+                    max_push_constant_size: adapter.limits().max_push_constant_size,
+                    ..Default::default()
+                },
+                memory_hints: Default::default(),
+                trace: wgpu::Trace::Off,
+            })
+            .await?)
+    }
+
+    /// Sets up the scene on `device`, drawing frames of the format and size in `config`.
+    pub async fn new(
+        adapter: wgpu::Adapter,
+        device: wgpu::Device,
+        queue: wgpu::Queue,
+        config: wgpu::SurfaceConfiguration,
+    ) -> Self {
+        Self::with_options(adapter, device, queue, config, RendererOptions::default()).await
+    }
+
+    /// `new` with non-default `options`.
+    pub async fn with_options(
+        adapter: wgpu::Adapter,
+        device: wgpu::Device,
+        queue: wgpu::Queue,
+        config: wgpu::SurfaceConfiguration,
+        options: RendererOptions,
+    ) -> Self {
+        let info = adapter.get_info();
+        log::info!(
+            "Using {} ({:?}, {:?}) driver {} {}",
+            info.name,
+            info.device_type,
+            info.backend,
+            info.driver,
+            info.driver_info
+        );
+        log::info!("Enabled features {:?}", device.features());
+
+        let sample_count =
+            if Self::supports_sample_count(&adapter, &device, Tonemapper::HDR_FORMAT, 4) {
+                4
+            } else {
+                1
+            };
+        let depth_compare = texture::Texture::depth_compare(options.reverse_z);
+        let depth_format = texture::Texture::depth_format(options.stencil);
+
+        let texture_bind_group_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                entries: &[
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 0,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Texture {
+                            sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                            view_dimension: wgpu::TextureViewDimension::D2,
+                            multisampled: false,
+                        },
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 1,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 2,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Texture {
+                            multisampled: false,
+                            sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                            view_dimension: wgpu::TextureViewDimension::D2,
+                        },
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 3,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                        count: None,
+                    },
+                ],
+                label: Some("texture_bind_group_layout"),
+            });
+
+        let camera = Camera::new((0.0, 5.0, 10.0), cgmath::Deg(-90.0), cgmath::Deg(-20.0));
+        let mut projection =
+            Projection::new(config.width, config.height, cgmath::Deg(45.0), 0.1, 100.0);
+        projection.set_reverse_z(options.reverse_z);
+
+        let mut camera_uniform = CameraUniform::new();
+        camera_uniform.update_view_proj(&camera, &projection);
+        let camera_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Camera Buffer"),
+            contents: bytemuck::cast_slice(&[camera_uniform]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+
+        let camera_bind_group_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                entries: &[wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::VERTEX_FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                }],
+                label: Some("camera_bind_group_layout"),
+            });
+
+        let camera_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &camera_bind_group_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: camera_buffer.as_entire_binding(),
+            }],
+            label: Some("camera_bind_group"),
+        });
+
+        let light = Light::new(
+            &device,
+            LightUniform::new([4.0, 2.0, 4.0], [1.0, 1.0, 1.0]),
+            Tonemapper::HDR_FORMAT,
+            Some(depth_format),
+            depth_compare,
+            &camera_bind_group_layout,
+            sample_count,
+        );
+
+        // The animated point light is mirrored into slot 0 of the light array used for shading
+        let mut lights = LightArray::new(&device, LightArray::DEFAULT_CAPACITY);
+        lights.add_light(light.uniform);
+        lights.upload(&queue);
+
+        let sun = DirectionalLight::new(
+            &device,
+            DirectionalLightUniform::new([-0.5, -1.0, -0.3], [0.4, 0.4, 0.35]),
+        );
+
+        let skin_bind_group_layout = SkinUniform::create_bind_group_layout(&device);
+        let identity_skin = SkinUniform::new(&device, &skin_bind_group_layout, 1);
+
+        let mut shadow_map = ShadowMap::new(
+            &device,
+            2048,
+            &[model::ModelVertex::desc(), InstanceRaw::desc()],
+            &skin_bind_group_layout,
+        );
+        shadow_map.update_cascades(&queue, sun.uniform.direction.into(), &camera, &projection);
+
+        let stats_overlay = StatsOverlay::new(&device, config.width, config.height, config.format);
+        let loading_screen =
+            LoadingScreen::new(&device, config.width, config.height, config.format);
+
+        // Off until a mode is picked, then a light haze from 40 to 100 units unless the
+        // range or density is set too
+        let fog = Fog::new(
+            &device,
+            FogUniform {
+                mode: FogMode::None as u32,
+                density: 0.02,
+                ..FogUniform::linear([0.7, 0.75, 0.8, 1.0], 40.0, 100.0)
+            },
+        );
+
+        let render_config = Self::render_config(&config, 1.0);
+        let depth_texture = texture::Texture::create_depth_texture(
+            &device,
+            &render_config,
+            depth_format,
+            sample_count,
+            "depth_texture",
+        );
+        let msaa_view = Self::create_msaa_view(&device, &render_config, sample_count);
+        let tonemapper = Tonemapper::new(&device, config.format);
+        let (hdr_target, hdr_bind_group) =
+            Self::create_hdr_target(&device, &render_config, &tonemapper);
+        let fxaa = Fxaa::new(&device, config.format);
+        let depth_probe = DepthProbe::new(&device);
+        let splat_bind_group_layout = SplatMaterial::create_bind_group_layout(&device);
+
+        let render_pipeline_layout =
+            device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: Some("Render Pipeline Layout"),
+                bind_group_layouts: &[
+                    &texture_bind_group_layout,
+                    &camera_bind_group_layout,
+                    &lights.bind_group_layout,
+                    &sun.bind_group_layout,
+                    &shadow_map.bind_group_layout,
+                    &fog.bind_group_layout,
+                    &skin_bind_group_layout,
+                ],
+                push_constant_ranges: &[],
+            });
+
+        let splat_pipeline_layout =
+            device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: Some("Splat Pipeline Layout"),
+                bind_group_layouts: &[
+                    &splat_bind_group_layout,
+                    &camera_bind_group_layout,
+                    &lights.bind_group_layout,
+                    &sun.bind_group_layout,
+                    &shadow_map.bind_group_layout,
+                    &fog.bind_group_layout,
+                ],
+                push_constant_ranges: &[],
+            });
+
+        let primitive = PrimitiveConfig::default();
+        let pipeline_desc = PipelineDesc {
+            depth_compare,
+            primitive,
+            sample_count,
+            ..Default::default()
+        };
+        let (render_pipeline, wireframe_pipeline) = Self::create_scene_pipelines(
+            &device,
+            &render_pipeline_layout,
+            Tonemapper::HDR_FORMAT,
+            depth_format,
+            pipeline_desc,
+        );
+        let (depth_prepass_pipeline, depth_equal_pipeline) = Self::create_depth_prepass_pipelines(
+            &device,
+            &render_pipeline_layout,
+            Tonemapper::HDR_FORMAT,
+            depth_format,
+            pipeline_desc,
+        );
+        let transparency = Transparency::AlphaBlend;
+        let transparent_pipeline = Self::create_transparent_pipeline(
+            &device,
+            &render_pipeline_layout,
+            Tonemapper::HDR_FORMAT,
+            depth_format,
+            pipeline_desc,
+            transparency,
+        );
+
+        const SPACE_BETWEEN: f32 = 3.0;
+        let instances = (0..NUM_INSTANCES_PER_ROW)
+            .flat_map(|z| {
+                (0..NUM_INSTANCES_PER_ROW).map(move |x| {
+                    let x = SPACE_BETWEEN * (x as f32 - NUM_INSTANCES_PER_ROW as f32 / 2.0);
+                    let z = SPACE_BETWEEN * (z as f32 - NUM_INSTANCES_PER_ROW as f32 / 2.0);
+
+                    let position = cgmath::Vector3 { x, y: 0.0, z };
+
+                    let rotation = if position.is_zero() {
+                        cgmath::Quaternion::from_axis_angle(
+                            cgmath::Vector3::unit_z(),
+                            cgmath::Deg(0.0),
+                        )
+                    } else {
+                        cgmath::Quaternion::from_axis_angle(position.normalize(), cgmath::Deg(45.0))
+                    };
+
+                    Instance::new(position, rotation)
+                })
+            })
+            .collect::<Vec<_>>();
+
+        let instance_buffer = crate::instance::create_instance_buffer(&device, &instances);
+        let visible_instance_buffer = crate::instance::create_instance_buffer(&device, &instances);
+        // Everything counts as visible until the first update culls against the frustum
+        let visible_instances = instances.len() as u32;
+        let transparent_instance_buffer = crate::instance::create_instance_buffer(&device, &[]);
+        let terrain_instance_buffer = crate::instance::create_instance_buffer(
+            &device,
+            &[Instance::new(
+                cgmath::Vector3::zero(),
+                cgmath::Quaternion::one(),
+            )],
+        );
+        let frustum = Frustum::from_matrix(projection.calc_matrix() * camera.calc_matrix());
+
+        // Sharpens textures seen at grazing angles, e.g. terrain towards the horizon
+        let anisotropy =
+            texture::Texture::supported_anisotropy(&adapter, texture::Texture::MAX_ANISOTROPY);
+        let resource_cache = Arc::new(ResourceCache::new());
+        let loader_pool = LoaderPool::new();
+        // Nothing is drawn at the instances until the cube is loaded
+        let obj_model = model::Model {
+            meshes: Vec::new(),
+            materials: Vec::new(),
+        };
+        let pending_model = ResourceLoader::on_pool(&loader_pool, &device, &queue, anisotropy)
+            .with_cache(resource_cache.clone())
+            .load_model("res/cube.obj", &texture_bind_group_layout);
+
+        let sky_texture = resources::EquirectToCubemapConverter::new(&device)
+            .from_equirectangular_bytes(
+                &device,
+                &queue,
+                include_bytes!("../res/pure-sky.jpg"),
+                1080,
+                "sky texture",
+            )
+            .unwrap();
+
+        let skybox = Skybox::new(
+            &device,
+            sky_texture,
+            &camera_bind_group_layout,
+            Tonemapper::HDR_FORMAT,
+            Some(depth_format),
+            depth_compare,
+            sample_count,
+        );
+        let ibl_bind_group_layout = IblEnvironment::create_bind_group_layout(&device);
+        let ibl = IblEnvironment::empty(&device, &ibl_bind_group_layout);
+
+        let debug_lines = DebugLines::new(
+            &device,
+            &camera_bind_group_layout,
+            Tonemapper::HDR_FORMAT,
+            Some(depth_format),
+            depth_compare,
+            sample_count,
+        );
+        let gizmo = Gizmo::new(
+            &device,
+            &camera_bind_group_layout,
+            Tonemapper::HDR_FORMAT,
+            Some(depth_format),
+            sample_count,
+        );
+        let grid = GridPlane::new(
+            &device,
+            &camera_bind_group_layout,
+            Tonemapper::HDR_FORMAT,
+            depth_format,
+            depth_compare,
+            sample_count,
+        );
+        let decals = Decals::new(
+            &device,
+            &camera_bind_group_layout,
+            Tonemapper::HDR_FORMAT,
+            &depth_texture,
+            depth_compare,
+        );
+
+        let gpu_timer = GpuTimer::new(&device, &queue);
+        if gpu_timer.is_none() {
+            log::info!("Timestamp queries are not supported, GPU pass timings are disabled");
+        }
+
+        Self {
+            adapter,
+            device,
+            queue,
+            config,
+            render_pipeline_layout,
+            render_pipeline,
+            wireframe: wireframe_pipeline.is_some(),
+            wireframe_pipeline,
+            primitive,
+            depth_prepass: false,
+            depth_prepass_pipeline,
+            push_constants: None,
+            depth_equal_pipeline,
+            camera,
+            camera_uniform,
+            camera_bind_group,
+            camera_bind_group_layout,
+            camera_buffer,
+            viewports: Vec::new(),
+            instances,
+            previous_instances: Vec::new(),
+            instance_buffer,
+            visible_instance_buffer,
+            visible_instances,
+            transparency,
+            transparent_pipeline,
+            transparent_instances: Vec::new(),
+            transparent_instance_buffer,
+            visible_transparent_instances: 0,
+            frustum,
+            depth_texture,
+            sample_count,
+            msaa_view,
+            render_scale: 1.0,
+            hdr_target,
+            hdr_bind_group,
+            tonemapper,
+            fxaa,
+            fxaa_target: None,
+            bloom: None,
+            auto_exposure: None,
+            auto_exposure_settings: AutoExposureSettings::default(),
+            motion_vectors: None,
+            taa: None,
+            taa_blend: Taa::DEFAULT_BLEND,
+            depth_probe,
+            rendering_mode: RenderingMode::Forward,
+            deferred: None,
+            ssao: false,
+            ssao_settings: SsaoSettings::default(),
+            light,
+            light_animator: LightAnimator::default(),
+            lights,
+            sun,
+            shadow_map,
+            fog,
+            stats_overlay,
+            loading_screen,
+            debug_lines,
+            debug_lines_visible: false,
+            gizmo,
+            gizmo_visible: false,
+            grid,
+            grid_visible: false,
+            draw_counts: Default::default(),
+            triangle_count: AtomicU64::new(0),
+            instance_count: AtomicU64::new(0),
+            gpu_timer,
+            obj_model,
+            pending_model: Some(pending_model),
+            texture_bind_group_layout,
+            resource_cache,
+            loader_pool,
+            terrain_streamer: None,
+            terrain_tessellator: None,
+            particles: None,
+            decals,
+            #[cfg(feature = "hot-reload")]
+            shader_watcher: crate::hot_reload::ShaderWatcher::new(),
+            #[cfg(feature = "hot-reload")]
+            reloaded_shaders: Vec::new(),
+            skin_bind_group_layout,
+            identity_skin,
+            animated_models: Vec::new(),
+            models: Vec::new(),
+            selected: Vec::new(),
+            outline: None,
+            impostors: None,
+            water: None,
+            water_reflection: None,
+            terrain_instance_buffer,
+            splat_bind_group_layout,
+            splat_pipeline_layout,
+            terrain_splat: None,
+            projection,
+            skybox,
+            atmosphere: None,
+            ibl,
+            ibl_bind_group_layout,
+            ibl_baker: None,
+            clear_color: wgpu::Color {
+                r: 0.1,
+                g: 0.2,
+                b: 0.3,
+                a: 1.0,
+            },
+            depth_clear: texture::Texture::far_depth(options.reverse_z),
+            reverse_z: options.reverse_z,
+            depth_format,
+        }
+    }
+
+    /// Draws the current frame into `view`, which must be in the output format, by
+    /// submitting the `FrameCommands` of `encode_frame` like a window without a customized
+    /// `RenderGraph`.
+    pub fn render_to_view(&self, view: &wgpu::TextureView) {
+        self.queue.submit(self.encode_frame(view));
+        self.after_submit();
+    }
+
+    /// Draws the loading screen laid out by the last `prepare_loading_screen` into `view`
+    /// instead of the scene.
+    pub fn render_loading_screen(&self, view: &wgpu::TextureView) {
+        let mut encoder = self
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some("Loading Screen Encoder"),
+            });
+        {
+            let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("Loading Screen Pass"),
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view,
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                        store: wgpu::StoreOp::Store,
+                    },
+                    depth_slice: None,
+                })],
+                depth_stencil_attachment: None,
+                occlusion_query_set: None,
+                timestamp_writes: None,
+            });
+            self.loading_screen.draw(&mut pass);
+        }
+        self.queue.submit(iter::once(encoder.finish()));
+    }
+
+    /// Lays out `progress` for the next `render_loading_screen`.
+    pub fn prepare_loading_screen(&mut self, progress: &LoadProgress) {
+        self.loading_screen
+            .prepare(&self.device, &self.queue, progress);
+    }
+
+    /// Draws the current frame into a new texture of the output format and size, which can
+    /// be sampled or copied from.
+    pub fn render_to_texture(&self) -> wgpu::Texture {
+        let texture = self.device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("Offscreen Texture"),
+            size: wgpu::Extent3d {
+                width: self.config.width.max(1),
+                height: self.config.height.max(1),
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: self.config.format,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT
+                | wgpu::TextureUsages::TEXTURE_BINDING
+                | wgpu::TextureUsages::COPY_SRC,
+            view_formats: &[],
+        });
+        self.render_to_view(&texture.create_view(&wgpu::TextureViewDescriptor::default()));
+        texture
+    }
+
+    /// Renders the current frame offscreen and reads it back.
+    pub fn capture_frame(&self) -> anyhow::Result<image::RgbaImage> {
+        let format = self.config.format;
+        let swap_red_blue = match format {
+            wgpu::TextureFormat::Rgba8Unorm | wgpu::TextureFormat::Rgba8UnormSrgb => false,
+            wgpu::TextureFormat::Bgra8Unorm | wgpu::TextureFormat::Bgra8UnormSrgb => true,
+            _ => anyhow::bail!("can't capture frames in output format {format:?}"),
+        };
+        let (width, height) = (self.config.width.max(1), self.config.height.max(1));
+        let size = wgpu::Extent3d {
+            width,
+            height,
+            depth_or_array_layers: 1,
+        };
+        let texture = self.render_to_texture();
+
+        // Buffer copies need every row to start at a multiple of 256 bytes
+        let unpadded_bytes_per_row = 4 * width;
+        let padded_bytes_per_row =
+            unpadded_bytes_per_row.next_multiple_of(wgpu::COPY_BYTES_PER_ROW_ALIGNMENT);
+        let buffer = self.device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Capture Buffer"),
+            size: (padded_bytes_per_row * height) as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+            mapped_at_creation: false,
+        });
+
+        let mut encoder = self
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some("Capture Encoder"),
+            });
+        encoder.copy_texture_to_buffer(
+            texture.as_image_copy(),
+            wgpu::TexelCopyBufferInfo {
+                buffer: &buffer,
+                layout: wgpu::TexelCopyBufferLayout {
+                    offset: 0,
+                    bytes_per_row: Some(padded_bytes_per_row),
+                    rows_per_image: Some(height),
+                },
+            },
+            size,
+        );
+        self.queue.submit(iter::once(encoder.finish()));
+
+        let slice = buffer.slice(..);
+        let (sender, receiver) = std::sync::mpsc::channel();
+        slice.map_async(wgpu::MapMode::Read, move |result| {
+            let _ = sender.send(result);
+        });
+        self.device.poll(wgpu::PollType::Wait)?;
+        receiver.recv()??;
+
+        let mut pixels = Vec::with_capacity((unpadded_bytes_per_row * height) as usize);
+        {
+            let data = slice.get_mapped_range();
+            for row in data.chunks_exact(padded_bytes_per_row as usize) {
+                pixels.extend_from_slice(&row[..unpadded_bytes_per_row as usize]);
+            }
+        }
+        buffer.unmap();
+
+        if swap_red_blue {
+            for pixel in pixels.chunks_exact_mut(4) {
+                pixel.swap(0, 2);
+            }
+        }
+        image::RgbaImage::from_raw(width, height, pixels)
+            .ok_or_else(|| anyhow::anyhow!("captured frame has the wrong size"))
+    }
+
+    /// Captures the current frame and writes it to `path`, e.g. a `.png`.
+    pub fn save_screenshot(&self, path: impl AsRef<std::path::Path>) -> anyhow::Result<()> {
+        self.capture_frame()?.save(path)?;
+        Ok(())
+    }
+
+    /// The world position of the surface drawn under `screen` (in physical pixels) in the
+    /// last frame, or `None` if only the sky is there.
+    pub fn world_position_at(
+        &self,
+        screen: winit::dpi::PhysicalPosition<f64>,
+    ) -> Option<cgmath::Point3<f32>> {
+        let (width, height) = (self.config.width.max(1), self.config.height.max(1));
+        if screen.x < 0.0 || screen.y < 0.0 {
+            return None;
+        }
+        let (x, y) = (screen.x as u32, screen.y as u32);
+        if x >= width || y >= height {
+            return None;
+        }
+
+        // The depth buffer is at the render resolution, not the output's
+        let depth_size = self.depth_texture.size;
+        let (x, y) = (
+            (x * depth_size.width / width).min(depth_size.width - 1),
+            (y * depth_size.height / height).min(depth_size.height - 1),
+        );
+        let depth = self
+            .depth_probe
+            .read_depth(&self.device, &self.queue, &self.depth_texture.texture, x, y)
+            .inspect_err(|e| log::error!("Unable to read depth {}", e))
+            .ok()?;
+        // Nothing was drawn here, the depth buffer still holds its clear value
+        if depth == self.depth_clear {
+            return None;
+        }
+
+        // Pixel centers to NDC, where y points up
+        let ndc = cgmath::Point3::new(
+            (x as f32 + 0.5) / depth_size.width as f32 * 2.0 - 1.0,
+            1.0 - (y as f32 + 0.5) / depth_size.height as f32 * 2.0,
+            depth,
+        );
+        Some(self.camera_uniform.unproject(ndc))
+    }
+
+    /// The views the scene is drawn from this frame: the main camera over the whole render
+    /// target, or each viewport over its part of it.
+    fn scene_views(&self) -> Vec<SceneView<'_>> {
+        if self.viewports.is_empty() {
+            return vec![SceneView {
+                camera_bind_group: &self.camera_bind_group,
+                frustum: &self.frustum,
+                instances: (&self.visible_instance_buffer, self.visible_instances),
+                transparent_instances: (
+                    &self.transparent_instance_buffer,
+                    self.visible_transparent_instances,
+                ),
+                rect: None,
+                impostors: true,
+            }];
+        }
+        let output_size = (self.config.width, self.config.height);
+        let target_size = (self.hdr_target.size.width, self.hdr_target.size.height);
+        self.viewports
+            .iter()
+            .filter_map(|viewport| {
+                Some(SceneView {
+                    camera_bind_group: viewport.camera_bind_group(),
+                    frustum: viewport.frustum(),
+                    instances: viewport.visible_instances(),
+                    transparent_instances: viewport.visible_transparent_instances(),
+                    rect: Some(viewport.rect.scaled(output_size, target_size)?),
+                    impostors: false,
+                })
+            })
+            .collect()
+    }
+
+    /// Draws the visible opaque instances and terrain chunks of `view` with the pipeline of
+    /// `pass` and returns the number of draw calls.
+    fn draw_opaque<'a>(
+        &'a self,
+        render_pass: &mut wgpu::RenderPass<'a>,
+        pass: OpaquePass,
+        view: &SceneView<'a>,
+    ) -> u32 {
+        use model::DrawModel;
+
+        let pipeline = match pass {
+            OpaquePass::DepthPrepass => &self.depth_prepass_pipeline,
+            OpaquePass::Velocity => match &self.motion_vectors {
+                Some(motion_vectors) => motion_vectors.pipeline(),
+                None => return 0,
+            },
+            OpaquePass::GBuffer => match &self.deferred {
+                Some(deferred) => deferred.geometry_pipeline(),
+                None => return 0,
+            },
+            OpaquePass::DepthEqual => &self.depth_equal_pipeline,
+            OpaquePass::Forward => &self.render_pipeline,
+        };
+        let mut draws = 0;
+        render_pass.set_pipeline(pipeline);
+        render_pass.set_bind_group(3, &self.sun.bind_group, &[]);
+        render_pass.set_bind_group(4, &self.shadow_map.bind_group, &[]);
+        render_pass.set_bind_group(5, &self.fog.bind_group, &[]);
+        render_pass.set_bind_group(6, &self.identity_skin.bind_group, &[]);
+
+        // Everything may have been culled
+        let (instance_buffer, instance_count) = view.instances;
+        if instance_count > 0 {
+            render_pass.set_vertex_buffer(1, instance_buffer.slice(..));
+            render_pass.draw_model_instanced(
+                &self.obj_model,
+                0..instance_count,
+                view.camera_bind_group,
+                &self.lights.bind_group,
+            );
+            draws += self.obj_model.meshes.len() as u32;
+            self.count_instances(&self.obj_model, instance_count);
+        }
+
+        // Splatted terrain is shaded separately by `draw_splat_terrain`, but moves and
+        // fills the G-buffer like any other
+        if self.terrain_splat.is_none()
+            || matches!(pass, OpaquePass::Velocity | OpaquePass::GBuffer)
+        {
+            draws += self.draw_terrain_chunks(render_pass, view);
+        }
+
+        // Only the forward pipeline has a push constant variant, the prepass and G-buffer
+        // keep reading the transforms' buffers
+        let push_constant_pipeline = self
+            .push_constants
+            .as_ref()
+            .filter(|_| pass == OpaquePass::Forward)
+            .map(|(_, pipeline)| pipeline);
+        if let Some(pipeline) = push_constant_pipeline {
+            render_pass.set_pipeline(pipeline);
+        }
+        for (model, transform) in &self.models {
+            let bounds = model
+                .bounding_box()
+                .transform(&transform.transform.to_matrix());
+            if !view.frustum.intersects_aabb(&bounds) {
+                continue;
+            }
+            if push_constant_pipeline.is_some() {
+                render_pass.set_push_constants(
+                    wgpu::ShaderStages::VERTEX,
+                    0,
+                    bytemuck::bytes_of(&DrawTransform::from(&transform.transform)),
+                );
+                for mesh in &model.meshes {
+                    render_pass.draw_mesh(
+                        mesh,
+                        &model.materials[mesh.material],
+                        view.camera_bind_group,
+                        &self.lights.bind_group,
+                    );
+                }
+            } else {
+                render_pass.draw_model(
+                    model,
+                    transform,
+                    view.camera_bind_group,
+                    &self.lights.bind_group,
+                );
+            }
+            draws += model.meshes.len() as u32;
+            self.count_instances(model, 1);
+        }
+        if push_constant_pipeline.is_some() {
+            render_pass.set_pipeline(pipeline);
+        }
+
+        // Last, as each of them binds its own skin
+        for animated in &self.animated_models {
+            animated.draw(render_pass, view.camera_bind_group, &self.lights.bind_group);
+            draws += animated.model.meshes.len() as u32;
+            self.count_instances(&animated.model, 1);
+        }
+        draws
+    }
+
+    /// Draws the streamed terrain chunks `view` sees with the pipeline and the bind groups
+    /// past 2 already set, e.g. by `draw_opaque`, and returns the number of draw calls.
+    fn draw_terrain_chunks<'a>(
+        &'a self,
+        render_pass: &mut wgpu::RenderPass<'a>,
+        view: &SceneView<'a>,
+    ) -> u32 {
+        use model::DrawModel;
+
+        let Some(streamer) = &self.terrain_streamer else {
+            return 0;
+        };
+        let mut draws = 0;
+        render_pass.set_vertex_buffer(1, self.terrain_instance_buffer.slice(..));
+        for (coord, terrain) in streamer.visible_chunks(view.frustum) {
+            let model = self.terrain_model(coord, terrain);
+            render_pass.draw_model_instanced(
+                model,
+                0..1,
+                view.camera_bind_group,
+                &self.lights.bind_group,
+            );
+            draws += model.meshes.len() as u32;
+            self.count_instances(model, 1);
+        }
+        draws
+    }
+
+    /// The mesh drawn for a streamed terrain chunk, its tessellated one if it has one.
+    fn terrain_model<'a>(&'a self, coord: ChunkCoord, terrain: &'a Terrain) -> &'a model::Model {
+        self.terrain_tessellator
+            .as_ref()
+            .and_then(|tessellator| tessellator.model(coord))
+            .unwrap_or(terrain.model())
+    }
+
+    /// Draws the streamed terrain chunks `view` sees with the splat material, if there is
+    /// one, for `pass` and returns the number of draw calls. `draw_opaque` leaves them out,
+    /// as its pipelines would place the vertices slightly differently than `terrain.wgsl`.
+    fn draw_splat_terrain<'a>(
+        &'a self,
+        render_pass: &mut wgpu::RenderPass<'a>,
+        pass: OpaquePass,
+        view: &SceneView<'a>,
+    ) -> u32 {
+        let (Some(streamer), Some((material, pipelines))) =
+            (&self.terrain_streamer, &self.terrain_splat)
+        else {
+            return 0;
+        };
+        let pipeline = match pass {
+            OpaquePass::DepthPrepass => &pipelines.depth_prepass,
+            OpaquePass::DepthEqual => &pipelines.depth_equal,
+            OpaquePass::Forward => &pipelines.forward,
+            // `draw_opaque` lays down the chunks in these, to be shaded depth-equal on top
+            OpaquePass::Velocity | OpaquePass::GBuffer => return 0,
+        };
+
+        let mut draws = 0;
+        render_pass.set_pipeline(pipeline);
+        render_pass.set_bind_group(0, &material.bind_group, &[]);
+        render_pass.set_bind_group(1, view.camera_bind_group, &[]);
+        render_pass.set_bind_group(2, &self.lights.bind_group, &[]);
+        render_pass.set_bind_group(3, &self.sun.bind_group, &[]);
+        render_pass.set_bind_group(4, &self.shadow_map.bind_group, &[]);
+        render_pass.set_bind_group(5, &self.fog.bind_group, &[]);
+        render_pass.set_vertex_buffer(1, self.terrain_instance_buffer.slice(..));
+        for (coord, terrain) in streamer.visible_chunks(view.frustum) {
+            let model = self.terrain_model(coord, terrain);
+            for mesh in &model.meshes {
+                render_pass.set_vertex_buffer(0, mesh.vertex_buffer.slice(..));
+                render_pass
+                    .set_index_buffer(mesh.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
+                render_pass.draw_indexed(0..mesh.num_elements, 0, 0..1);
+                draws += 1;
+            }
+            self.count_instances(model, 1);
+        }
+        draws
+    }
+
+    /// Records a frame into `view` in three command buffers, one after the other. With
+    /// viewports, the scene passes repeat for each of them. Recording them on
+    /// worker threads instead works the same, see `encode_shadows`, `encode_scene` and
+    /// `encode_post`.
+    pub fn encode_frame(&self, view: &wgpu::TextureView) -> FrameCommands {
+        FrameCommands {
+            shadow: self.encode_shadows(),
+            scene: self.encode_scene(),
+            post: self.encode_post(view),
+        }
+    }
+
+    /// Records the shadow passes of every cascade, `FrameCommands::shadow`.
+    pub fn encode_shadows(&self) -> wgpu::CommandBuffer {
+        let mut encoder = self
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some("Shadow Encoder"),
+            });
+        let draws = self.record_shadows(&mut encoder);
+        self.draw_counts[0].store(draws, Ordering::Relaxed);
+        encoder.finish()
+    }
+
+    /// Records the opaque and transparent passes of every scene view into the HDR target,
+    /// followed by the water reflection copy and the selection outline,
+    /// `FrameCommands::scene`.
+    pub fn encode_scene(&self) -> wgpu::CommandBuffer {
+        let mut encoder = self
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some("Scene Encoder"),
+            });
+        let mut draws = 0;
+        for index in 0..self.scene_view_count() {
+            draws += self.record_opaque(&mut encoder, index);
+            draws += self.record_transparent(&mut encoder, index);
+        }
+        self.record_water_reflection(&mut encoder);
+        draws += self.record_selection(&mut encoder);
+        self.draw_counts[1].store(draws, Ordering::Relaxed);
+        encoder.finish()
+    }
+
+    /// Records the tone mapping pass resolving the HDR target into `view`, FXAA and the
+    /// stats overlay if it is visible, `FrameCommands::post`. Also resolves the GPU
+    /// timestamps of the passes recorded so far, so with timing on it should be recorded
+    /// after the other two for them to be measured.
+    pub fn encode_post(&self, view: &wgpu::TextureView) -> wgpu::CommandBuffer {
+        let mut encoder = self
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some("Post Encoder"),
+            });
+        let draws = self.record_post(&mut encoder, view);
+        self.draw_counts[2].store(draws, Ordering::Relaxed);
+        if let Some(timer) = &self.gpu_timer {
+            timer.resolve(&mut encoder);
+        }
+        encoder.finish()
+    }
+
+    /// Draws the current frame into `view` by recording the passes of `graph` in order, in
+    /// place of the fixed sequence of `encode_frame`.
+    pub fn render_graph_to_view(&self, graph: &RenderGraph, view: &wgpu::TextureView) {
+        let mut encoder = self
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some("Render Graph Encoder"),
+            });
+        let draws = graph.record(self, &mut encoder, view);
+        for (count, draws) in self.draw_counts.iter().zip([draws, 0, 0]) {
+            count.store(draws, Ordering::Relaxed);
+        }
+        if let Some(timer) = &self.gpu_timer {
+            timer.resolve(&mut encoder);
+        }
+        self.queue.submit(iter::once(encoder.finish()));
+        self.after_submit();
+    }
+
+    /// Starts reading back the GPU timestamps `encode_post` resolved, for
+    /// `last_gpu_times`. Call after submitting a frame's `FrameCommands`.
+    pub fn after_submit(&self) {
+        if let Some(timer) = &self.gpu_timer {
+            timer.request_readback();
+        }
+    }
+
+    /// Number of cameras the scene is drawn from each frame, one per viewport or just the
+    /// main camera. Indexes `record_opaque` and `record_transparent`.
+    pub fn scene_view_count(&self) -> usize {
+        self.scene_views().len()
+    }
+
+    /// The HDR target the scene is drawn into and tone mapped from.
+    pub fn hdr_view(&self) -> &wgpu::TextureView {
+        &self.hdr_target.view
+    }
+
+    /// The depth buffer of the scene passes.
+    pub fn depth_view(&self) -> &wgpu::TextureView {
+        &self.depth_texture.view
+    }
+
+    /// The copy of the HDR target water reflects, `None` without water.
+    pub fn water_reflection_view(&self) -> Option<&wgpu::TextureView> {
+        self.water_reflection
+            .as_ref()
+            .map(|reflection| &reflection.view)
+    }
+
+    /// Records the shadow passes of every cascade into `encoder` and returns the number of
+    /// draw calls.
+    pub fn record_shadows(&self, encoder: &mut wgpu::CommandEncoder) -> u32 {
+        use model::DrawDepth;
+
+        // One draw call per mesh of each model drawn
+        let model_draws = self.obj_model.meshes.len() as u32;
+        let mut draws = model_draws;
+        let timestamp_writes = |pass| {
+            self.gpu_timer
+                .as_ref()
+                .map(|timer| timer.timestamp_writes(pass))
+        };
+        // One pass per cascade, timed from the start of the first to the end of the last
+        let cascade_count = self.shadow_map.cascade_count() as usize;
+        let shadow_timestamps = timestamp_writes(GpuPass::Shadow);
+        for cascade in 0..cascade_count {
+            let timestamp_writes = shadow_timestamps
+                .as_ref()
+                .map(|writes| wgpu::RenderPassTimestampWrites {
+                    query_set: writes.query_set,
+                    beginning_of_pass_write_index: writes
+                        .beginning_of_pass_write_index
+                        .filter(|_| cascade == 0),
+                    end_of_pass_write_index: writes
+                        .end_of_pass_write_index
+                        .filter(|_| cascade == cascade_count - 1),
+                })
+                .filter(|writes| {
+                    writes.beginning_of_pass_write_index.is_some()
+                        || writes.end_of_pass_write_index.is_some()
+                });
+            let mut shadow_pass =
+                self.shadow_map
+                    .begin_shadow_pass(encoder, cascade, timestamp_writes);
+            shadow_pass.set_bind_group(1, &self.identity_skin.bind_group, &[]);
+            shadow_pass.set_vertex_buffer(1, self.instance_buffer.slice(..));
+            shadow_pass.draw_model_depth_instanced(&self.obj_model, 0..self.instances.len() as u32);
+            self.count_instances(&self.obj_model, self.instances.len() as u32);
+            if cascade > 0 {
+                draws += model_draws;
+            }
+            for (model, transform) in &self.models {
+                shadow_pass.set_vertex_buffer(1, transform.buffer().slice(..));
+                shadow_pass.draw_model_depth_instanced(model, 0..1);
+                draws += model.meshes.len() as u32;
+                self.count_instances(model, 1);
+            }
+            for animated in &self.animated_models {
+                animated.draw_depth(&mut shadow_pass);
+                draws += animated.model.meshes.len() as u32;
+                self.count_instances(&animated.model, 1);
+            }
+            // Every loaded chunk, as hills outside the view still shade what is in it
+            if let Some(streamer) = &self.terrain_streamer {
+                shadow_pass.set_vertex_buffer(1, self.terrain_instance_buffer.slice(..));
+                for (coord, terrain) in streamer.chunks() {
+                    let model = self.terrain_model(coord, terrain);
+                    shadow_pass.draw_model_depth_instanced(model, 0..1);
+                    draws += model.meshes.len() as u32;
+                    self.count_instances(model, 1);
+                }
+            }
+        }
+        draws
+    }
+
+    /// Records the depth and main passes drawing everything opaque from the scene view
+    /// `index` into the HDR target and returns the number of draw calls: the sky, the
+    /// opaque instances, models and terrain, impostors and the wireframe overlay. Only the
+    /// first view clears the color target and writes GPU timestamps, every view clears the
+    /// depth buffer for itself.
+    pub fn record_opaque(&self, encoder: &mut wgpu::CommandEncoder, index: usize) -> u32 {
+        use model::DrawLight;
+        use model::DrawModel;
+
+        let scene_views = self.scene_views();
+        let Some(scene_view) = scene_views.get(index) else {
+            return 0;
+        };
+        let first = index == 0;
+        let model_draws = self.obj_model.meshes.len() as u32;
+        let mut draws = 0;
+        let timestamp_writes = |pass| {
+            self.gpu_timer
+                .as_ref()
+                .filter(|_| first)
+                .map(|timer| timer.timestamp_writes(pass))
+        };
+
+        // Motion vectors are written along with the depth, by the G-buffer pass or the
+        // prepass. A resolve covers the whole target, so only the last view resolves.
+        let velocity = self.motion_vectors.as_ref().map(|motion_vectors| {
+            motion_vectors.color_attachment(first, index + 1 == scene_views.len())
+        });
+
+        // The G-buffer pass also fills the depth buffer, taking the place of the prepass
+        let deferred = self
+            .deferred
+            .as_ref()
+            .filter(|_| self.rendering_mode == RenderingMode::Deferred);
+        let depth_prepass = deferred.is_none() && (self.depth_prepass || velocity.is_some());
+        if let Some(deferred) = deferred {
+            let mut geometry_pass = deferred.begin_geometry_pass(
+                encoder,
+                &self.depth_texture.view,
+                self.depth_clear,
+                velocity,
+                None,
+            );
+            scene_view.restrict(&mut geometry_pass);
+            draws += self.draw_opaque(&mut geometry_pass, OpaquePass::GBuffer, scene_view);
+            drop(geometry_pass);
+            if self.ssao {
+                deferred
+                    .ssao
+                    .apply(encoder, scene_view.camera_bind_group, scene_view.rect);
+            }
+        } else if depth_prepass {
+            // Depth first, so the color pass below shades every pixel only once
+            let mut prepass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("Depth Prepass"),
+                color_attachments: velocity.map(Some).as_slice(),
+                depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                    view: &self.depth_texture.view,
+                    depth_ops: Some(wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(self.depth_clear),
+                        store: wgpu::StoreOp::Store,
+                    }),
+                    stencil_ops: None,
+                }),
+                occlusion_query_set: None,
+                timestamp_writes: timestamp_writes(GpuPass::DepthPrepass),
+            });
+            scene_view.restrict(&mut prepass);
+            if self.motion_vectors.is_some() {
+                draws += self.draw_opaque(&mut prepass, OpaquePass::Velocity, scene_view);
+            } else {
+                draws += self.draw_opaque(&mut prepass, OpaquePass::DepthPrepass, scene_view);
+                draws +=
+                    self.draw_splat_terrain(&mut prepass, OpaquePass::DepthPrepass, scene_view);
+            }
+        }
+
+        // Keep what the prepass or G-buffer pass wrote
+        let depth_load = if depth_prepass || deferred.is_some() {
+            wgpu::LoadOp::Load
+        } else {
+            wgpu::LoadOp::Clear(self.depth_clear)
+        };
+        // Later viewports draw next to the earlier ones
+        let color_load = if first {
+            wgpu::LoadOp::Clear(self.clear_color)
+        } else {
+            wgpu::LoadOp::Load
+        };
+        let mut render_pass = self.begin_scene_pass(
+            encoder,
+            "Render Pass",
+            color_load,
+            depth_load,
+            // The transparent pass draws on top of the samples. A resolve covers the whole
+            // target whatever the viewport, so with several views only the last transparent
+            // pass resolves, after all of them were drawn.
+            (scene_views.len() == 1).then_some(wgpu::StoreOp::Store),
+            timestamp_writes(GpuPass::Main),
+        );
+        scene_view.restrict(&mut render_pass);
+
+        match &self.atmosphere {
+            Some(atmosphere) => atmosphere.draw(&mut render_pass, scene_view.camera_bind_group),
+            None => self
+                .skybox
+                .draw(&mut render_pass, scene_view.camera_bind_group),
+        }
+        draws += 1;
+
+        // TODO: encapsulate it
+        let light_bounds =
+            self.obj_model
+                .bounding_box()
+                .transform(&cgmath::Matrix4::from_translation(
+                    self.light.uniform.position.into(),
+                ));
+        if scene_view.frustum.intersects_aabb(&light_bounds) {
+            render_pass.set_pipeline(&self.light.render_pipeline);
+            render_pass.draw_light_model(
+                &self.obj_model,
+                scene_view.camera_bind_group,
+                &self.light.bind_group,
+            );
+            draws += model_draws;
+            self.count_instances(&self.obj_model, 1);
+        }
+
+        if let Some(deferred) = deferred {
+            deferred.draw_lighting(
+                &mut render_pass,
+                [
+                    scene_view.camera_bind_group,
+                    &self.lights.bind_group,
+                    &self.sun.bind_group,
+                    &self.shadow_map.bind_group,
+                    &self.fog.bind_group,
+                ],
+            );
+            draws += 1;
+            // Lighting covers all of the G-buffer, so the grid goes over it instead, testing
+            // against the depth the geometry pass left
+            if self.grid_visible {
+                self.grid
+                    .draw(&mut render_pass, scene_view.camera_bind_group);
+                draws += 1;
+            }
+        } else {
+            if self.grid_visible {
+                self.grid
+                    .draw(&mut render_pass, scene_view.camera_bind_group);
+                draws += 1;
+            }
+            let opaque_pass = if depth_prepass {
+                OpaquePass::DepthEqual
+            } else {
+                OpaquePass::Forward
+            };
+            draws += self.draw_opaque(&mut render_pass, opaque_pass, scene_view);
+        }
+        // The G-buffer and velocity passes lay down the splatted terrain's depth as well
+        let splat_pass = if depth_prepass || deferred.is_some() {
+            OpaquePass::DepthEqual
+        } else {
+            OpaquePass::Forward
+        };
+        draws += self.draw_splat_terrain(&mut render_pass, splat_pass, scene_view);
+
+        if let Some(impostors) = self.impostors.as_ref()
+            && scene_view.impostors
+            && impostors.billboard.count() > 0
+        {
+            impostors.billboard.draw(
+                &mut render_pass,
+                scene_view.camera_bind_group,
+                &self.sun.bind_group,
+            );
+            draws += 1;
+        }
+
+        // Draw wireframe overlay
+        let (instance_buffer, instance_count) = scene_view.instances;
+        if let Some(wireframe_pipeline) = self
+            .wireframe_pipeline
+            .as_ref()
+            .filter(|_| self.wireframe && instance_count > 0)
+        {
+            render_pass.set_vertex_buffer(1, instance_buffer.slice(..));
+            render_pass.set_pipeline(wireframe_pipeline);
+            // In deferred mode the opaque draws binding these went into the G-buffer pass,
+            // and the lighting pass left its own groups here
+            render_pass.set_bind_group(3, &self.sun.bind_group, &[]);
+            render_pass.set_bind_group(4, &self.shadow_map.bind_group, &[]);
+            render_pass.set_bind_group(5, &self.fog.bind_group, &[]);
+            render_pass.set_bind_group(6, &self.identity_skin.bind_group, &[]);
+            render_pass.draw_model_instanced(
+                &self.obj_model,
+                0..instance_count,
+                scene_view.camera_bind_group,
+                &self.lights.bind_group,
+            );
+            draws += model_draws;
+            self.count_instances(&self.obj_model, instance_count);
+        }
+        draws
+    }
+
+    /// Draws what a `ReflectionProbe` face sees of the scene from `camera_bind_group`
+    /// within `frustum`: the sky and every opaque instance, model and terrain chunk, with
+    /// the forward pipelines whatever the rendering mode. Returns the number of draw calls.
+    pub(crate) fn draw_environment<'a>(
+        &'a self,
+        render_pass: &mut wgpu::RenderPass<'a>,
+        camera_bind_group: &'a wgpu::BindGroup,
+        frustum: &'a Frustum,
+    ) -> u32 {
+        // Culling the instances for each face isn't worth it at a probe's resolution
+        let view = SceneView {
+            camera_bind_group,
+            frustum,
+            instances: (&self.instance_buffer, self.instances.len() as u32),
+            transparent_instances: (&self.transparent_instance_buffer, 0),
+            rect: None,
+            impostors: false,
+        };
+        match &self.atmosphere {
+            Some(atmosphere) => atmosphere.draw(render_pass, camera_bind_group),
+            None => self.skybox.draw(render_pass, camera_bind_group),
+        }
+        1 + self.draw_opaque(render_pass, OpaquePass::Forward, &view)
+            + self.draw_splat_terrain(render_pass, OpaquePass::Forward, &view)
+    }
+
+    /// Records the pass blending everything transparent from the scene view `index` over
+    /// what `record_opaque` drew and returns the number of draw calls: decals in a pass of
+    /// their own, then water, the transparent instances, then the model outline, debug
+    /// lines and the gizmo on top.
+    pub fn record_transparent(&self, encoder: &mut wgpu::CommandEncoder, index: usize) -> u32 {
+        use model::DrawModel;
+
+        let scene_views = self.scene_views();
+        let Some(scene_view) = scene_views.get(index) else {
+            return 0;
+        };
+        let model_draws = self.obj_model.meshes.len() as u32;
+        let mut draws = 0;
+
+        // Decals read the depth buffer, so it can't be attached while they are drawn
+        if !self.decals.is_empty() {
+            let mut decal_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("Decal Pass"),
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    // The transparent pass below resolves the samples
+                    view: self.msaa_view.as_ref().unwrap_or(&self.hdr_target.view),
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Load,
+                        store: wgpu::StoreOp::Store,
+                    },
+                    depth_slice: None,
+                })],
+                depth_stencil_attachment: None,
+                occlusion_query_set: None,
+                timestamp_writes: None,
+            });
+            scene_view.restrict(&mut decal_pass);
+            draws += self
+                .decals
+                .draw(&mut decal_pass, scene_view.camera_bind_group);
+        }
+
+        let timestamp_writes = self
+            .gpu_timer
+            .as_ref()
+            .filter(|_| index == 0)
+            .map(|timer| timer.timestamp_writes(GpuPass::Transparent));
+        let mut render_pass = self.begin_scene_pass(
+            encoder,
+            "Transparent Pass",
+            wgpu::LoadOp::Load,
+            wgpu::LoadOp::Load,
+            // Resolved after the last view, the samples themselves aren't needed afterwards.
+            // Earlier views keep them for the next view to draw next to.
+            (index + 1 == scene_views.len()).then_some(wgpu::StoreOp::Discard),
+            timestamp_writes,
+        );
+        scene_view.restrict(&mut render_pass);
+
+        // After everything opaque, so blending sees what's behind
+        if let Some(water) = self.water.as_ref().filter(|water| water.is_ready()) {
+            water.draw(
+                &mut render_pass,
+                scene_view.camera_bind_group,
+                &self.sun.bind_group,
+                &self.fog.bind_group,
+            );
+            draws += 1;
+        }
+        let (transparent_buffer, transparent_count) = scene_view.transparent_instances;
+        if transparent_count > 0 {
+            render_pass.set_vertex_buffer(1, transparent_buffer.slice(..));
+            render_pass.set_pipeline(&self.transparent_pipeline);
+            render_pass.set_bind_group(3, &self.sun.bind_group, &[]);
+            render_pass.set_bind_group(4, &self.shadow_map.bind_group, &[]);
+            render_pass.set_bind_group(5, &self.fog.bind_group, &[]);
+            render_pass.set_bind_group(6, &self.identity_skin.bind_group, &[]);
+            render_pass.draw_model_instanced(
+                &self.obj_model,
+                0..transparent_count,
+                scene_view.camera_bind_group,
+                &self.lights.bind_group,
+            );
+            draws += model_draws;
+            self.count_instances(&self.obj_model, transparent_count);
+        }
+        if let Some(particles) = &self.particles {
+            particles.draw(&mut render_pass, scene_view.camera_bind_group);
+            draws += 1;
+        }
+
+        if let Some(outline) = self.outline.as_ref().filter(|_| !self.selected.is_empty()) {
+            let models = self.selected_models();
+            draws += outline.draw_stencil(
+                &mut render_pass,
+                scene_view.camera_bind_group,
+                &self.obj_model,
+                &models,
+            );
+        }
+
+        if self.debug_lines_visible {
+            self.debug_lines
+                .draw(&mut render_pass, scene_view.camera_bind_group);
+            draws += 1;
+        }
+        // Last, as it is drawn over everything
+        if self.gizmo_visible {
+            self.gizmo
+                .draw(&mut render_pass, scene_view.camera_bind_group);
+            draws += 1;
+        }
+        draws
+    }
+
+    /// Begins a pass drawing into the HDR target, or the multisampled target, with the
+    /// depth buffer. With `msaa_resolve` the multisampled target is resolved into the HDR
+    /// target and then stored or discarded as it says, without it the samples are stored
+    /// unresolved.
+    fn begin_scene_pass<'a>(
+        &'a self,
+        encoder: &'a mut wgpu::CommandEncoder,
+        label: &str,
+        color_load: wgpu::LoadOp<wgpu::Color>,
+        depth_load: wgpu::LoadOp<f32>,
+        msaa_resolve: Option<wgpu::StoreOp>,
+        timestamp_writes: Option<wgpu::RenderPassTimestampWrites<'a>>,
+    ) -> wgpu::RenderPass<'a> {
+        let scene_target = &self.hdr_target.view;
+        encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some(label),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                // With MSAA, render into the multisampled target and resolve into the
+                // HDR target
+                view: self.msaa_view.as_ref().unwrap_or(scene_target),
+                resolve_target: self
+                    .msaa_view
+                    .as_ref()
+                    .filter(|_| msaa_resolve.is_some())
+                    .map(|_| scene_target),
+                ops: wgpu::Operations {
+                    load: color_load,
+                    store: if self.msaa_view.is_some() {
+                        msaa_resolve.unwrap_or(wgpu::StoreOp::Store)
+                    } else {
+                        wgpu::StoreOp::Store
+                    },
+                },
+                depth_slice: None,
+            })],
+            depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                view: &self.depth_texture.view,
+                depth_ops: Some(wgpu::Operations {
+                    load: depth_load,
+                    store: wgpu::StoreOp::Store,
+                }),
+                // Only the outline uses the stencil buffer, and only within one pass
+                stencil_ops: self
+                    .depth_format
+                    .has_stencil_aspect()
+                    .then_some(wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(0),
+                        store: wgpu::StoreOp::Discard,
+                    }),
+            }),
+            occlusion_query_set: None,
+            timestamp_writes,
+        })
+    }
+
+    /// Copies the scene every view was drawn into for the next frame's water reflections,
+    /// if there is water reflecting anything. Recorded after the last scene view and before
+    /// the selection outline, which the reflections shouldn't show.
+    pub fn record_water_reflection(&self, encoder: &mut wgpu::CommandEncoder) {
+        if let Some(reflection) = &self.water_reflection
+            && self
+                .water
+                .as_ref()
+                .is_some_and(|water| water.uniform.reflection_strength > 0.0)
+        {
+            encoder.copy_texture_to_texture(
+                self.hdr_target.texture.as_image_copy(),
+                reflection.texture.as_image_copy(),
+                self.hdr_target.size,
+            );
+        }
+    }
+
+    /// Records the outline of the selected objects over every scene view into `encoder`
+    /// and returns the number of draw calls.
+    pub fn record_selection(&self, encoder: &mut wgpu::CommandEncoder) -> u32 {
+        let Some(outline) = self.outline.as_ref().filter(|_| !self.selected.is_empty()) else {
+            return 0;
+        };
+        let views = self
+            .scene_views()
+            .iter()
+            .map(|view| (view.camera_bind_group, view.rect))
+            .collect::<Vec<_>>();
+        outline.apply_edges(
+            encoder,
+            &views,
+            &self.obj_model,
+            &self.selected_models(),
+            self.depth_clear,
+            &self.hdr_target.view,
+        )
+    }
+
+    /// The selected models added with `add_model` and their transforms.
+    fn selected_models(&self) -> Vec<(&model::Model, &ModelTransform)> {
+        self.selected
+            .iter()
+            .filter_map(|id| match id {
+                ObjectId::Model(i) => self.models.get(*i).map(|(model, t)| (model, t)),
+                ObjectId::Instance(_) => None,
+            })
+            .collect()
+    }
+
+    /// Records TAA, bloom, auto exposure, the tone mapping pass resolving the HDR target into
+    /// `view`, FXAA and the stats overlay if it is visible, and returns the number of draw
+    /// calls.
+    pub fn record_post(&self, encoder: &mut wgpu::CommandEncoder, view: &wgpu::TextureView) -> u32 {
+        let mut draws = 0;
+        if let Some(taa) = &self.taa {
+            draws += taa.apply(encoder, &self.hdr_target.texture);
+        }
+        if let Some(bloom) = &self.bloom {
+            draws += bloom.apply(encoder, &self.hdr_target.view);
+        }
+        if let Some(auto_exposure) = &self.auto_exposure {
+            auto_exposure.apply(encoder, &self.tonemapper);
+        }
+        let timestamp_writes = self
+            .gpu_timer
+            .as_ref()
+            .map(|timer| timer.timestamp_writes(GpuPass::Post));
+        let tonemap_target = self
+            .fxaa_target
+            .as_ref()
+            .map_or(view, |(target, _)| &target.view);
+        self.tonemapper.apply(
+            encoder,
+            &self.hdr_bind_group,
+            tonemap_target,
+            timestamp_writes,
+        );
+        draws += 1;
+        if let Some((_, bind_group)) = &self.fxaa_target {
+            self.fxaa.apply(encoder, bind_group, view);
+            draws += 1;
+        }
+
+        // Text goes on top of the resolved image, so it needs neither depth nor MSAA
+        if self.stats_overlay.visible() {
+            let mut overlay_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("Stats Overlay Pass"),
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view,
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Load,
+                        store: wgpu::StoreOp::Store,
+                    },
+                    depth_slice: None,
+                })],
+                depth_stencil_attachment: None,
+                occlusion_query_set: None,
+                timestamp_writes: None,
+            });
+            self.stats_overlay.draw(&mut overlay_pass);
+        }
+        draws
+    }
+
+    pub fn update(&mut self, dt: std::time::Duration) {
+        #[cfg(feature = "hot-reload")]
+        self.reload_changed_shaders();
+        if let Some(pending) = &self.pending_model
+            && let Some(loaded) = pending.take()
+        {
+            self.pending_model = None;
+            // A failed load is logged by the loader, the instances stay empty then
+            if let Ok(model) = loaded {
+                self.obj_model = model;
+            }
+        }
+        self.light_animator.update(&mut self.light, &self.queue, dt);
+        self.lights.update_light(0, self.light.uniform);
+        self.lights.upload(&self.queue);
+        self.fog.upload(&self.queue);
+        if let Some(auto_exposure) = &self.auto_exposure {
+            auto_exposure.update(&self.queue, dt);
+        }
+        if let Some(deferred) = self.deferred.as_mut() {
+            deferred
+                .ssao
+                .update(&self.queue, self.ssao_settings, self.ssao);
+        }
+
+        let jitter = match self.taa.as_mut() {
+            Some(taa) => {
+                let size = self.hdr_target.size;
+                taa.next_frame(&self.queue, size.width, size.height)
+            }
+            None => cgmath::Vector2::new(0.0, 0.0),
+        };
+        self.projection.set_jitter(jitter);
+        self.camera_uniform
+            .update_view_proj(&self.camera, &self.projection);
+        self.shadow_map.update_cascades(
+            &self.queue,
+            self.sun.uniform.direction.into(),
+            &self.camera,
+            &self.projection.without_jitter(),
+        );
+        self.queue.write_buffer(
+            &self.camera_buffer,
+            0,
+            bytemuck::cast_slice(&[self.camera_uniform]),
+        );
+
+        self.frustum =
+            Frustum::from_matrix(self.projection.calc_matrix() * self.camera.calc_matrix());
+        let bounds = self.obj_model.bounding_box();
+        let raws = self.instance_raws();
+        let mut visible = Vec::with_capacity(raws.len());
+        let mut billboards = Vec::new();
+        for (i, &raw) in raws.iter().enumerate() {
+            let world_bounds = bounds.transform(&cgmath::Matrix4::from(raw.model));
+            if !self.frustum.intersects_aabb(&world_bounds) {
+                continue;
+            }
+            if let Some(impostors) = self.impostors.as_mut()
+                && impostors.use_billboard(i, self.camera.position, &world_bounds)
+            {
+                billboards.push(impostors.billboard_instance(&world_bounds));
+            } else {
+                visible.push(raw);
+            }
+        }
+        if let Some(impostors) = self.impostors.as_mut() {
+            impostors
+                .billboard
+                .upload(&self.device, &self.queue, &billboards);
+        }
+        self.queue.write_buffer(
+            &self.visible_instance_buffer,
+            0,
+            bytemuck::cast_slice(&visible),
+        );
+        self.visible_instances = visible.len() as u32;
+
+        let transparent = self.sorted_transparent_instances(&self.frustum, self.camera.position);
+        self.queue.write_buffer(
+            &self.transparent_instance_buffer,
+            0,
+            bytemuck::cast_slice(&transparent),
+        );
+        self.visible_transparent_instances = transparent.len() as u32;
+
+        let mut viewports = std::mem::take(&mut self.viewports);
+        for viewport in &mut viewports {
+            viewport.update_camera(&self.queue, &self.projection);
+            let frustum = *viewport.frustum();
+            let visible = self.culled_instances(&raws, &frustum);
+            let transparent = self.sorted_transparent_instances(&frustum, viewport.camera.position);
+            viewport.upload_instances(&self.queue, &visible, &transparent);
+        }
+        self.viewports = viewports;
+        self.previous_instances = raws.iter().map(|raw| raw.model.into()).collect();
+
+        if let Some(streamer) = self.terrain_streamer.as_mut() {
+            streamer.update(self.camera.position);
+            if let Some(tessellator) = self.terrain_tessellator.as_mut() {
+                let view = TessellationView {
+                    position: self.camera.position,
+                    pixels_per_unit: match self.projection.kind() {
+                        ProjectionKind::Perspective { fovy } => {
+                            self.config.height as f32 / (2.0 * (fovy / 2.0).tan())
+                        }
+                        ProjectionKind::Orthographic { height } => {
+                            self.config.height as f32 / height
+                        }
+                    },
+                    orthographic: matches!(
+                        self.projection.kind(),
+                        ProjectionKind::Orthographic { .. }
+                    ),
+                };
+                tessellator.update(&self.queue, streamer, view);
+            }
+        }
+        if let Some(particles) = self.particles.as_mut() {
+            particles.center = self.camera.position;
+            match &self.terrain_streamer {
+                Some(streamer) => particles.sample_ground(|x, z| streamer.height_at(x, z)),
+                None => particles.clear_ground(),
+            }
+            particles.update(dt);
+        }
+        for (_, transform) in &mut self.models {
+            transform.next_frame(&self.queue);
+        }
+        if let Some(outline) = self.outline.as_mut() {
+            let instances = self
+                .selected
+                .iter()
+                .filter_map(|id| match id {
+                    ObjectId::Instance(i) => self.instances.get(*i).map(Instance::to_raw),
+                    ObjectId::Model(_) => None,
+                })
+                .collect::<Vec<_>>();
+            outline.set_instances(&self.device, &self.queue, &instances);
+            outline.upload(&self.queue);
+        }
+        for animated in &mut self.animated_models {
+            animated.update(&self.queue, dt);
+        }
+        if let Some(water) = self.water.as_mut() {
+            water.update(&self.queue, dt);
+        }
+
+        if self.debug_lines_visible {
+            for raw in &visible {
+                let aabb = bounds.transform(&cgmath::Matrix4::from(raw.model));
+                self.debug_lines
+                    .draw_aabb(aabb.min, aabb.max, [1.0, 1.0, 0.0, 1.0]);
+            }
+            if let Some(streamer) = &self.terrain_streamer {
+                for (_, terrain) in streamer.visible_chunks(&self.frustum) {
+                    let aabb = terrain.model().bounding_box();
+                    self.debug_lines
+                        .draw_aabb(aabb.min, aabb.max, [0.0, 1.0, 0.0, 1.0]);
+                }
+            }
+            self.debug_lines.upload(&self.device, &self.queue);
+        } else {
+            self.debug_lines.clear();
+        }
+
+        if self.grid_visible {
+            self.grid.upload(&self.queue);
+        }
+        self.decals.upload(&self.queue);
+        if let Some(atmosphere) = self.atmosphere.as_mut() {
+            atmosphere.set_sun_direction(-cgmath::Vector3::from(self.sun.uniform.direction));
+            atmosphere.upload(&self.queue);
+        }
+        if self.gizmo_visible {
+            if self.gizmo.dragging().is_none() {
+                self.gizmo.position = self.light.uniform.position.into();
+            }
+            self.gizmo.update(
+                &self.queue,
+                &self.camera_uniform,
+                self.config.width,
+                self.config.height,
+            );
+        }
+
+        if let Some(timer) = self.gpu_timer.as_mut() {
+            timer.collect(&self.device);
+        }
+        self.stats_overlay.stats.record(dt);
+        let render_stats = self.render_stats();
+        self.stats_overlay
+            .prepare(&self.device, &self.queue, &render_stats);
+        // Counted afresh while the next frame is recorded
+        self.triangle_count.store(0, Ordering::Relaxed);
+        self.instance_count.store(0, Ordering::Relaxed);
+    }
+
+    /// The data of every opaque instance, moved from where it was the previous update.
+    fn instance_raws(&self) -> Vec<InstanceRaw> {
+        self.instances
+            .iter()
+            .enumerate()
+            .map(|(i, instance)| match self.previous_instances.get(i) {
+                Some(&previous) => instance.to_raw().moved_from(previous),
+                // The first update has no previous one, so it starts out without motion
+                None => instance.to_raw(),
+            })
+            .collect()
+    }
+
+    /// Those of `instances` inside `frustum`, without swapping far ones for impostors.
+    fn culled_instances(&self, instances: &[InstanceRaw], frustum: &Frustum) -> Vec<InstanceRaw> {
+        let bounds = self.obj_model.bounding_box();
+        instances
+            .iter()
+            .copied()
+            .filter(|raw| {
+                frustum.intersects_aabb(&bounds.transform(&cgmath::Matrix4::from(raw.model)))
+            })
+            .collect()
+    }
+
+    /// The transparent instances inside `frustum`, sorted back-to-front from `eye`.
+    fn sorted_transparent_instances(
+        &self,
+        frustum: &Frustum,
+        eye: cgmath::Point3<f32>,
+    ) -> Vec<InstanceRaw> {
+        // Blending needs the farthest instances drawn first
+        let bounds = self.obj_model.bounding_box();
+        let eye = eye.to_vec();
+        let mut transparent = self
+            .transparent_instances
+            .iter()
+            .map(|instance| (instance.position.distance2(eye), instance.to_raw()))
+            .filter(|(_, raw)| {
+                frustum.intersects_aabb(&bounds.transform(&cgmath::Matrix4::from(raw.model)))
+            })
+            .collect::<Vec<_>>();
+        transparent.sort_by(|(a, _), (b, _)| b.total_cmp(a));
+        transparent.into_iter().map(|(_, raw)| raw).collect()
+    }
+
+    /// The view frustum of the last update, for culling custom draws.
+    pub fn frustum(&self) -> &Frustum {
+        &self.frustum
+    }
+
+    /// Changes the size of the frames drawn, ignoring empty sizes.
+    pub fn resize(&mut self, width: u32, height: u32) {
+        if width > 0 && height > 0 {
+            self.config.width = width;
+            self.config.height = height;
+            self.stats_overlay.resize(&self.queue, width, height);
+            self.loading_screen.resize(&self.queue, width, height);
+        }
+        self.recreate_render_targets();
+    }
+
+    pub fn render_scale(&self) -> f32 {
+        self.render_scale
+    }
+
+    /// Renders the scene at `scale` times the output size, clamped to `0.1..=2.0`, and
+    /// stretches it over the output. Below 1 this trades sharpness for speed, above 1 it
+    /// supersamples.
+    pub fn set_render_scale(&mut self, scale: f32) {
+        let scale = scale.clamp(0.1, 2.0);
+        if scale == self.render_scale {
+            return;
+        }
+        self.render_scale = scale;
+        self.recreate_render_targets();
+    }
+
+    pub fn exposure(&self) -> f32 {
+        self.tonemapper.exposure()
+    }
+
+    /// Scales the scene's HDR colors before tone mapping, 1 leaves them unchanged. While
+    /// auto exposure is on this is only what it falls back to once turned off.
+    pub fn set_exposure(&mut self, exposure: f32) {
+        self.tonemapper.set_exposure(&self.queue, exposure);
+    }
+
+    pub fn auto_exposure(&self) -> bool {
+        self.auto_exposure.is_some()
+    }
+
+    /// Adapts the exposure to how bright the scene is, like eyes do, instead of using the
+    /// one from `set_exposure`, see `AutoExposure`. It starts out from the manual exposure,
+    /// which comes back when this is turned off. Needs compute shader support.
+    pub fn set_auto_exposure(&mut self, enabled: bool) -> anyhow::Result<()> {
+        if !enabled {
+            if self.auto_exposure.take().is_some() {
+                // Overwrites the adapted exposure the tone mapper was left with
+                self.tonemapper
+                    .set_exposure(&self.queue, self.tonemapper.exposure());
+            }
+            return Ok(());
+        }
+        if self.auto_exposure.is_some() {
+            return Ok(());
+        }
+        anyhow::ensure!(
+            self.adapter
+                .get_downlevel_capabilities()
+                .flags
+                .contains(wgpu::DownlevelFlags::COMPUTE_SHADERS),
+            "auto exposure needs compute shaders, which the adapter doesn't support"
+        );
+        let size = self.hdr_target.size;
+        self.auto_exposure = Some(AutoExposure::new(
+            &self.device,
+            &self.hdr_target.view,
+            size.width,
+            size.height,
+            self.auto_exposure_settings,
+            self.tonemapper.exposure(),
+        ));
+        Ok(())
+    }
+
+    pub fn auto_exposure_settings(&self) -> AutoExposureSettings {
+        self.auto_exposure_settings
+    }
+
+    /// The exposure range and adaptation speed of auto exposure, kept while it is off.
+    /// Negative values are raised to 0 and `max_exposure` to `min_exposure`.
+    pub fn set_auto_exposure_settings(&mut self, settings: AutoExposureSettings) {
+        self.auto_exposure_settings = settings.sanitized();
+        if let Some(auto_exposure) = self.auto_exposure.as_mut() {
+            auto_exposure.set_settings(self.auto_exposure_settings);
+        }
+    }
+
+    pub fn tonemap(&self) -> TonemapMode {
+        self.tonemapper.mode()
+    }
+
+    pub fn set_tonemap(&mut self, mode: TonemapMode) {
+        self.tonemapper.set_mode(&self.queue, mode);
+    }
+
+    /// The configuration of the scene's render targets: `config` in the HDR format with its
+    /// size scaled to the render resolution.
+    fn render_config(
+        config: &wgpu::SurfaceConfiguration,
+        render_scale: f32,
+    ) -> wgpu::SurfaceConfiguration {
+        let scale = |size: u32| ((size.max(1) as f32 * render_scale).round() as u32).max(1);
+        wgpu::SurfaceConfiguration {
+            format: Tonemapper::HDR_FORMAT,
+            width: scale(config.width),
+            height: scale(config.height),
+            ..config.clone()
+        }
+    }
+
+    fn create_hdr_target(
+        device: &wgpu::Device,
+        render_config: &wgpu::SurfaceConfiguration,
+        tonemapper: &Tonemapper,
+    ) -> (texture::Texture, wgpu::BindGroup) {
+        let target = texture::Texture::create_texture(
+            device,
+            Some("hdr_texture"),
+            wgpu::Extent3d {
+                width: render_config.width,
+                height: render_config.height,
+                depth_or_array_layers: 1,
+            },
+            render_config.format,
+            wgpu::TextureUsages::RENDER_ATTACHMENT
+                | wgpu::TextureUsages::TEXTURE_BINDING
+                | wgpu::TextureUsages::COPY_SRC
+                | wgpu::TextureUsages::COPY_DST,
+            wgpu::TextureDimension::D2,
+            wgpu::FilterMode::Linear,
+        );
+        let bind_group = tonemapper.create_bind_group(device, &target.view);
+        (target, bind_group)
+    }
+
+    fn create_fxaa_target(
+        device: &wgpu::Device,
+        config: &wgpu::SurfaceConfiguration,
+        fxaa: &Fxaa,
+    ) -> (texture::Texture, wgpu::BindGroup) {
+        let target = texture::Texture::create_texture(
+            device,
+            Some("fxaa_input_texture"),
+            wgpu::Extent3d {
+                width: config.width.max(1),
+                height: config.height.max(1),
+                depth_or_array_layers: 1,
+            },
+            config.format,
+            wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+            wgpu::TextureDimension::D2,
+            wgpu::FilterMode::Linear,
+        );
+        let bind_group = fxaa.create_bind_group(device, &target.view);
+        (target, bind_group)
+    }
+
+    /// The image water reflects, matching the HDR target so it can be copied from it.
+    fn create_water_reflection(
+        device: &wgpu::Device,
+        render_config: &wgpu::SurfaceConfiguration,
+    ) -> texture::Texture {
+        texture::Texture::create_texture(
+            device,
+            Some("water_reflection_texture"),
+            wgpu::Extent3d {
+                width: render_config.width,
+                height: render_config.height,
+                depth_or_array_layers: 1,
+            },
+            render_config.format,
+            wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+            wgpu::TextureDimension::D2,
+            wgpu::FilterMode::Linear,
+        )
+    }
+
+    /// Recreates the depth, MSAA, HDR and G-buffer targets at the current render size and updates the
+    /// projection to match.
+    fn recreate_render_targets(&mut self) {
+        let config = Self::render_config(&self.config, self.render_scale);
+        self.depth_texture = texture::Texture::create_depth_texture(
+            &self.device,
+            &config,
+            self.depth_format,
+            self.sample_count,
+            "depth_texture",
+        );
+        self.msaa_view = Self::create_msaa_view(&self.device, &config, self.sample_count);
+        (self.hdr_target, self.hdr_bind_group) =
+            Self::create_hdr_target(&self.device, &config, &self.tonemapper);
+        if self.fxaa_target.is_some() {
+            self.fxaa_target = Some(Self::create_fxaa_target(
+                &self.device,
+                &self.config,
+                &self.fxaa,
+            ));
+        }
+        if let Some(bloom) = self.bloom.as_mut() {
+            bloom.resize(
+                &self.device,
+                &self.queue,
+                &self.hdr_target.view,
+                config.width,
+                config.height,
+            );
+        }
+        if let Some(auto_exposure) = self.auto_exposure.as_mut() {
+            auto_exposure.resize(
+                &self.device,
+                &self.hdr_target.view,
+                config.width,
+                config.height,
+            );
+        }
+        self.decals.set_depth(&self.depth_texture);
+        if let Some(deferred) = self.deferred.as_mut() {
+            deferred.resize(&self.device, config.width, config.height);
+        }
+        if let Some(motion_vectors) = self.motion_vectors.as_mut() {
+            motion_vectors.resize(&self.device, config.width, config.height, self.sample_count);
+            if let Some(taa) = self.taa.as_mut() {
+                taa.resize(
+                    &self.device,
+                    &self.hdr_target.view,
+                    &motion_vectors.velocity.view,
+                    config.width,
+                    config.height,
+                );
+            }
+        }
+        if let Some(outline) = self.outline.as_mut() {
+            outline.resize(&self.device, config.width, config.height);
+        }
+        if let Some(water) = self.water.as_mut() {
+            let reflection = Self::create_water_reflection(&self.device, &config);
+            water.set_reflection(&self.device, &reflection);
+            self.water_reflection = Some(reflection);
+        }
+        self.projection.resize(config.width, config.height);
+    }
+
+    pub fn wireframe(&self) -> bool {
+        self.wireframe
+    }
+
+    /// Shows or hides the wireframe overlay. Without `POLYGON_MODE_LINE` support the overlay
+    /// stays off and a warning is logged instead.
+    pub fn set_wireframe(&mut self, wireframe: bool) {
+        if wireframe && self.wireframe_pipeline.is_none() {
+            log::warn!("Wireframe rendering is not supported by this adapter");
+            return;
+        }
+        self.wireframe = wireframe;
+    }
+
+    pub fn clear_color(&self) -> wgpu::Color {
+        self.clear_color
+    }
+
+    pub fn set_clear_color(&mut self, color: wgpu::Color) {
+        self.clear_color = color;
+    }
+
+    pub fn depth_clear(&self) -> f32 {
+        self.depth_clear
+    }
+
+    /// Sets the value the depth buffer starts each frame with, clamped to `0.0..=1.0`. It
+    /// defaults to the far plane, 1 or 0 with reverse-Z. Geometry farther than it fails the
+    /// depth test, so moving it towards the near plane clips the scene.
+    pub fn set_depth_clear(&mut self, depth: f32) {
+        self.depth_clear = depth.clamp(0.0, 1.0);
+    }
+
+    pub fn reverse_z(&self) -> bool {
+        self.reverse_z
+    }
+
+    /// The depth test keeping the nearest fragment under the renderer's depth convention.
+    fn depth_compare(&self) -> wgpu::CompareFunction {
+        texture::Texture::depth_compare(self.reverse_z)
+    }
+
+    /// The depth test, culled faces and sample count shared by the scene's pipelines.
+    fn pipeline_desc(&self) -> PipelineDesc<'static> {
+        PipelineDesc {
+            depth_compare: self.depth_compare(),
+            primitive: self.primitive,
+            sample_count: self.sample_count,
+            ..Default::default()
+        }
+    }
+
+    pub fn fxaa(&self) -> bool {
+        self.fxaa_target.is_some()
+    }
+
+    /// Turns FXAA on the tone mapped image on or off. It is a cheap alternative to MSAA,
+    /// which it can also complement, at the cost of an extra output sized texture.
+    pub fn set_fxaa(&mut self, enabled: bool) {
+        if enabled == self.fxaa() {
+            return;
+        }
+        self.fxaa_target =
+            enabled.then(|| Self::create_fxaa_target(&self.device, &self.config, &self.fxaa));
+    }
+
+    /// Intensity and threshold of bloom, `None` while it is off.
+    pub fn bloom(&self) -> Option<(f32, f32)> {
+        self.bloom
+            .as_ref()
+            .map(|bloom| (bloom.intensity(), bloom.threshold()))
+    }
+
+    /// Makes HDR colors brighter than `threshold` glow, adding the blurred glow scaled by
+    /// `intensity` onto the scene before tone mapping. An `intensity` of 0 turns bloom off
+    /// and frees its mip chain.
+    pub fn set_bloom(&mut self, intensity: f32, threshold: f32) {
+        if intensity <= 0.0 {
+            self.bloom = None;
+            return;
+        }
+        match self.bloom.as_mut() {
+            Some(bloom) => bloom.set(&self.queue, intensity, threshold),
+            None => {
+                let size = self.hdr_target.size;
+                self.bloom = Some(Bloom::new(
+                    &self.device,
+                    &self.queue,
+                    &self.hdr_target.view,
+                    size.width,
+                    size.height,
+                    intensity,
+                    threshold,
+                ));
+            }
+        }
+    }
+
+    /// The velocity texture of the last frame and what wrote it, `None` while motion
+    /// vectors are off.
+    pub fn motion_vectors(&self) -> Option<&MotionVectors> {
+        self.motion_vectors.as_ref()
+    }
+
+    /// Fills `MotionVectors::velocity` each frame, for post-processing passes that need to
+    /// know how the image moved. It is written along with the depth, so in forward
+    /// rendering the depth prepass runs while it is on, whatever `set_depth_prepass` says.
+    /// Turning it off frees the targets, which TAA doesn't allow while it is on.
+    pub fn set_motion_vectors(&mut self, enabled: bool) {
+        if !enabled {
+            if self.taa.is_some() {
+                log::warn!("Motion vectors stay on while TAA uses them");
+                return;
+            }
+            self.motion_vectors = None;
+        } else if self.motion_vectors.is_none() {
+            let size = self.hdr_target.size;
+            self.motion_vectors = Some(MotionVectors::new(
+                &self.device,
+                &self.render_pipeline_layout,
+                size.width,
+                size.height,
+                self.depth_format,
+                self.depth_compare(),
+                self.primitive,
+                self.sample_count,
+            ));
+        } else {
+            return;
+        }
+        // The G-buffer pass writes the velocity only with a pipeline made for it
+        let depth_compare = self.depth_compare();
+        if let Some(deferred) = self.deferred.as_mut() {
+            deferred.recreate_geometry_pipeline(
+                &self.device,
+                &self.render_pipeline_layout,
+                self.depth_format,
+                depth_compare,
+                self.primitive,
+                self.motion_vectors.is_some(),
+            );
+        }
+    }
+
+    pub fn taa(&self) -> bool {
+        self.taa.is_some()
+    }
+
+    /// Turns temporal anti-aliasing on or off. While on, the projection is jittered by a
+    /// fraction of a pixel every frame and the frames are accumulated in a history reprojected
+    /// by the motion vectors, which get turned on along with it. Turning it off frees the
+    /// history, but leaves the motion vectors on.
+    pub fn set_taa(&mut self, enabled: bool) {
+        if !enabled {
+            self.taa = None;
+            return;
+        }
+        if self.taa.is_some() {
+            return;
+        }
+        self.set_motion_vectors(true);
+        let Some(motion_vectors) = &self.motion_vectors else {
+            return;
+        };
+        let size = self.hdr_target.size;
+        self.taa = Some(Taa::new(
+            &self.device,
+            &self.hdr_target.view,
+            &motion_vectors.velocity.view,
+            size.width,
+            size.height,
+            self.taa_blend,
+        ));
+    }
+
+    pub fn taa_blend(&self) -> f32 {
+        self.taa_blend
+    }
+
+    /// Sets the weight of each new frame in the TAA history, see `Taa::set_blend`. It is
+    /// kept while TAA is off.
+    pub fn set_taa_blend(&mut self, blend: f32) {
+        self.taa_blend = blend.clamp(0.0, 1.0);
+        if let Some(taa) = self.taa.as_mut() {
+            taa.set_blend(&self.queue, self.taa_blend);
+        }
+    }
+
+    pub fn depth_prepass(&self) -> bool {
+        self.depth_prepass
+    }
+
+    /// Draws the opaque geometry depth-only before shading it. Pays off when fragment
+    /// shading is expensive and geometry overlaps a lot, e.g. dense terrain, otherwise the
+    /// extra vertex work makes it a loss.
+    pub fn set_depth_prepass(&mut self, enabled: bool) {
+        self.depth_prepass = enabled;
+    }
+
+    /// Whether `models` get their matrices from push constants in the forward pass.
+    pub fn push_constants(&self) -> bool {
+        self.push_constants.is_some()
+    }
+
+    /// Pushes each of the `models`' `DrawTransform` with its draw in the forward pass,
+    /// instead of binding the `ModelTransform`'s buffer. The depth prepass, deferred
+    /// G-buffer and shadow passes keep using the buffers. Fails to enable unless
+    /// `DrawTransform::supported` on this device.
+    pub fn set_push_constants(&mut self, enabled: bool) -> anyhow::Result<()> {
+        if !enabled {
+            self.push_constants = None;
+            return Ok(());
+        }
+        if !DrawTransform::supported(&self.adapter, &self.device) {
+            anyhow::bail!(
+                "push constants of {} bytes are not supported on this device",
+                DrawTransform::SIZE
+            );
+        }
+        if self.push_constants.is_none() {
+            let layout = self
+                .device
+                .create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                    label: Some("Push Constant Pipeline Layout"),
+                    bind_group_layouts: &[
+                        &self.texture_bind_group_layout,
+                        &self.camera_bind_group_layout,
+                        &self.lights.bind_group_layout,
+                        &self.sun.bind_group_layout,
+                        &self.shadow_map.bind_group_layout,
+                        &self.fog.bind_group_layout,
+                        &self.skin_bind_group_layout,
+                    ],
+                    push_constant_ranges: &[DrawTransform::RANGE],
+                });
+            let pipeline = create_push_constant_pipeline(
+                &self.device,
+                &layout,
+                Tonemapper::HDR_FORMAT,
+                self.depth_format,
+                self.pipeline_desc(),
+            );
+            self.push_constants = Some((layout, pipeline));
+        }
+        Ok(())
+    }
+
+    pub fn rendering_mode(&self) -> RenderingMode {
+        self.rendering_mode
+    }
+
+    /// Switches between forward and deferred shading of the opaque scene. Deferred
+    /// rendering fails to enable with MSAA on, its G-buffer is single sampled.
+    pub fn set_rendering_mode(&mut self, mode: RenderingMode) -> anyhow::Result<()> {
+        if mode == RenderingMode::Deferred {
+            if self.sample_count > 1 {
+                anyhow::bail!("deferred rendering doesn't support MSAA, disable it first");
+            }
+            if self.deferred.is_none() {
+                let config = Self::render_config(&self.config, self.render_scale);
+                self.deferred = Some(Deferred::new(
+                    &self.device,
+                    config.width,
+                    config.height,
+                    &self.render_pipeline_layout,
+                    [
+                        &self.camera_bind_group_layout,
+                        &self.lights.bind_group_layout,
+                        &self.sun.bind_group_layout,
+                        &self.shadow_map.bind_group_layout,
+                        &self.fog.bind_group_layout,
+                    ],
+                    Tonemapper::HDR_FORMAT,
+                    self.depth_format,
+                    self.depth_compare(),
+                    self.primitive,
+                    self.motion_vectors.is_some(),
+                ));
+            }
+        }
+        self.rendering_mode = mode;
+        Ok(())
+    }
+
+    pub fn shadow_cascades(&self) -> u32 {
+        self.shadow_map.cascade_count()
+    }
+
+    /// Splits the sun's shadow into `count` cascades, up to `ShadowMap::MAX_CASCADES`.
+    /// More cascades keep distant shadows sharper, at a shadow pass each.
+    pub fn set_shadow_cascades(&mut self, count: u32) {
+        self.shadow_map.set_cascade_count(count);
+    }
+
+    pub fn shadow_resolution(&self) -> u32 {
+        self.shadow_map.resolution()
+    }
+
+    /// Renders each shadow cascade at `size` x `size` texels, see
+    /// `ShadowMap::set_resolution`.
+    pub fn set_shadow_resolution(&mut self, size: u32) -> anyhow::Result<()> {
+        self.shadow_map
+            .set_resolution(&self.device, &self.queue, size)
+    }
+
+    pub fn shadow_pcf_samples(&self) -> u32 {
+        self.shadow_map.pcf_samples()
+    }
+
+    /// Filters shadow edges with a kernel of `samples` x `samples` comparisons, see
+    /// `ShadowMap::set_pcf_samples`.
+    pub fn set_shadow_pcf_samples(&mut self, samples: u32) {
+        self.shadow_map.set_pcf_samples(&self.queue, samples);
+    }
+
+    pub fn shadow_split_lambda(&self) -> f32 {
+        self.shadow_map.split_lambda
+    }
+
+    /// Places the cascade splits evenly at 0 and logarithmically at 1, clamped to that
+    /// range. Higher values give the cascades near the camera more detail.
+    pub fn set_shadow_split_lambda(&mut self, lambda: f32) {
+        self.shadow_map.split_lambda = lambda.clamp(0.0, 1.0);
+    }
+
+    pub fn shadow_cascade_debug(&self) -> bool {
+        self.shadow_map.debug_cascades()
+    }
+
+    /// Tints the scene by the shadow cascade covering each fragment, see
+    /// `ShadowMap::set_debug_cascades`.
+    pub fn set_shadow_cascade_debug(&mut self, enabled: bool) {
+        self.shadow_map.set_debug_cascades(&self.queue, enabled);
+    }
+
+    pub fn ssao(&self) -> bool {
+        self.ssao
+    }
+
+    /// Darkens the ambient light in creases with screen-space ambient occlusion. Only
+    /// `RenderingMode::Deferred` has the G-buffer it needs, forward rendering ignores it.
+    pub fn set_ssao(&mut self, enabled: bool) {
+        self.ssao = enabled;
+    }
+
+    pub fn ssao_settings(&self) -> SsaoSettings {
+        self.ssao_settings
+    }
+
+    pub fn set_ssao_settings(&mut self, settings: SsaoSettings) {
+        self.ssao_settings = settings;
+    }
+
+    pub fn toggle_wireframe(&mut self) {
+        self.set_wireframe(!self.wireframe);
+    }
+
+    pub fn primitive_config(&self) -> PrimitiveConfig {
+        self.primitive
+    }
+
+    /// Sets which faces of models and terrain are culled and which winding faces front,
+    /// e.g. `FrontFace::Cw` for meshes that render inside out. Rebuilds the scene
+    /// pipelines.
+    pub fn set_primitive_config(&mut self, primitive: PrimitiveConfig) {
+        if primitive != self.primitive {
+            self.primitive = primitive;
+            self.recreate_pipelines();
+        }
+    }
+
+    pub fn backface_culling(&self) -> bool {
+        self.primitive.cull_mode.is_some()
+    }
+
+    /// Draws both sides of every triangle while disabled, so surfaces whose normals or
+    /// winding are flipped show up instead of vanishing. Enabling culls back faces again.
+    pub fn set_backface_culling(&mut self, enabled: bool) {
+        self.set_primitive_config(PrimitiveConfig {
+            cull_mode: enabled.then_some(wgpu::Face::Back),
+            ..self.primitive
+        });
+    }
+
+    pub fn toggle_backface_culling(&mut self) {
+        self.set_backface_culling(!self.backface_culling());
+    }
+
+    /// Draw calls issued by the last frame.
+    pub fn draw_count(&self) -> u32 {
+        self.draw_counts
+            .iter()
+            .map(|count| count.load(Ordering::Relaxed))
+            .sum()
+    }
+
+    /// Draw calls, triangles and instances of the frame drawn since the last `update`, so
+    /// read after drawing it, and the GPU time of a recent frame. Triangles and instances
+    /// read 0 until the frame is recorded.
+    pub fn render_stats(&self) -> RenderStats {
+        let gpu_time = self
+            .gpu_timer
+            .as_ref()
+            .map(GpuTimer::last_timings)
+            .filter(|timings| *timings != GpuTimings::default())
+            .map(|timings| timings.total());
+        RenderStats {
+            draw_calls: self.draw_count(),
+            triangles: self.triangle_count.load(Ordering::Relaxed),
+            instances: self.instance_count.load(Ordering::Relaxed),
+            gpu_time_ms: gpu_time.map(|time| time.as_secs_f32() * 1000.0),
+        }
+    }
+
+    /// Adds `instances` copies of `model` to the triangles and instances of this frame.
+    fn count_instances(&self, model: &model::Model, instances: u32) {
+        let triangles: u64 = model
+            .meshes
+            .iter()
+            .map(|mesh| mesh.num_elements as u64 / 3)
+            .sum();
+        self.triangle_count
+            .fetch_add(triangles * instances as u64, Ordering::Relaxed);
+        self.instance_count
+            .fetch_add(instances as u64, Ordering::Relaxed);
+    }
+
+    pub fn stats_visible(&self) -> bool {
+        self.stats_overlay.visible()
+    }
+
+    /// Shows or hides the FPS, frame time and draw call overlay.
+    pub fn set_stats_visible(&mut self, visible: bool) {
+        self.stats_overlay.set_visible(visible);
+    }
+
+    /// A loader for models and textures in the background, on this renderer's device and
+    /// with its texture filtering. Every loader runs on the same worker threads, created
+    /// with the renderer, and shares uploads through `resource_cache`.
+    pub fn resource_loader(&self) -> ResourceLoader {
+        let anisotropy =
+            texture::Texture::supported_anisotropy(&self.adapter, texture::Texture::MAX_ANISOTROPY);
+        ResourceLoader::on_pool(&self.loader_pool, &self.device, &self.queue, anisotropy)
+            .with_cache(self.resource_cache.clone())
+    }
+
+    /// Textures and materials uploaded for models so far, e.g. to check `stats` for how
+    /// often loads are shared.
+    pub fn resource_cache(&self) -> &Arc<ResourceCache> {
+        &self.resource_cache
+    }
+
+    /// Layout of the camera uniform at group 0 or 1 of the scene pipelines, e.g. for a
+    /// `ReflectionProbe`'s cameras.
+    pub fn camera_bind_group_layout(&self) -> &wgpu::BindGroupLayout {
+        &self.camera_bind_group_layout
+    }
+
+    /// Layout of the model materials' bind group, for loading models to hand to the
+    /// renderer, e.g. with `ResourceLoader::load_model`.
+    pub fn texture_bind_group_layout(&self) -> &wgpu::BindGroupLayout {
+        &self.texture_bind_group_layout
+    }
+
+    /// Replaces the model drawn at every opaque and transparent instance, also if the
+    /// startup model is still loading.
+    pub fn set_model(&mut self, model: model::Model) {
+        self.pending_model = None;
+        self.obj_model = model;
+    }
+
+    /// Whether the startup model is still loading. Until then the instances are empty.
+    pub fn model_loading(&self) -> bool {
+        self.pending_model.is_some()
+    }
+
+    /// Loads a skinned glTF model and places it at `instance`, returning its index for
+    /// `animated_model_mut`. No clip plays until one is started with `AnimationPlayer::play`.
+    pub async fn load_animated_model(
+        &mut self,
+        path: &str,
+        instance: Instance,
+    ) -> anyhow::Result<usize> {
+        let anisotropy =
+            texture::Texture::supported_anisotropy(&self.adapter, texture::Texture::MAX_ANISOTROPY);
+        let (model, player) = resources::load_gltf_animated(
+            path,
+            anisotropy,
+            &self.device,
+            &self.queue,
+            &self.texture_bind_group_layout,
+        )
+        .await?;
+        Ok(self.add_animated_model(model, player, instance))
+    }
+
+    /// Draws `model` posed by `player` at `instance` from now on, returning its index.
+    pub fn add_animated_model(
+        &mut self,
+        model: model::Model,
+        player: crate::animation::AnimationPlayer,
+        instance: Instance,
+    ) -> usize {
+        self.animated_models.push(AnimatedModel::new(
+            &self.device,
+            &self.skin_bind_group_layout,
+            model,
+            player,
+            instance,
+        ));
+        self.animated_models.len() - 1
+    }
+
+    /// Draws `model` placed by `transform` from now on, returning its index.
+    pub fn add_model(&mut self, model: model::Model, transform: Transform) -> usize {
+        self.models
+            .push((model, ModelTransform::new(&self.device, transform)));
+        self.models.len() - 1
+    }
+
+    /// The placement of the model `add_model` returned `index` for, uploaded on `update`.
+    pub fn model_transform_mut(&mut self, index: usize) -> Option<&mut Transform> {
+        self.models
+            .get_mut(index)
+            .map(|(_, transform)| &mut transform.transform)
+    }
+
+    pub fn selected(&self) -> &[ObjectId] {
+        &self.selected
+    }
+
+    /// Outlines `ids` with `outline_mut`, replacing the previous selection. Ids without an
+    /// instance or model behind them are skipped.
+    pub fn set_selected(&mut self, ids: &[ObjectId]) {
+        if !ids.is_empty() {
+            self.outline_mut();
+        }
+        self.selected = ids.to_vec();
+    }
+
+    /// The outline of the selection, to change its color or width. Uploaded on `update`.
+    pub fn outline_mut(&mut self) -> &mut Outline {
+        self.outline.get_or_insert_with(|| {
+            let config = Self::render_config(&self.config, self.render_scale);
+            Outline::new(
+                &self.device,
+                &self.camera_bind_group_layout,
+                Tonemapper::HDR_FORMAT,
+                texture::Texture::depth_compare(self.reverse_z),
+                config.width,
+                config.height,
+            )
+        })
+    }
+
+    /// Switches how the selection is outlined. Fails for `OutlineMethod::Stencil` if the
+    /// renderer was created without `RendererOptions::stencil`.
+    pub fn set_outline_method(&mut self, method: OutlineMethod) -> anyhow::Result<()> {
+        let (depth_format, sample_count) = (self.depth_format, self.sample_count);
+        let device = self.device.clone();
+        let camera_layout = self.camera_bind_group_layout.clone();
+        self.outline_mut().set_method(
+            &device,
+            &camera_layout,
+            method,
+            Tonemapper::HDR_FORMAT,
+            depth_format,
+            sample_count,
+        )
+    }
+
+    pub fn animated_models(&self) -> &[AnimatedModel] {
+        &self.animated_models
+    }
+
+    pub fn animated_model_mut(&mut self, index: usize) -> Option<&mut AnimatedModel> {
+        self.animated_models.get_mut(index)
+    }
+
+    /// Where the point light is, as moved by the light animator or `set_light_position`.
+    pub fn light_position(&self) -> cgmath::Point3<f32> {
+        self.light.uniform.position.into()
+    }
+
+    /// Moves the point light, which the light animator then orbits from, if enabled.
+    pub fn set_light_position(&mut self, position: cgmath::Point3<f32>) {
+        self.light.uniform.position = position.into();
+        self.light.upload(&self.queue);
+    }
+
+    pub fn gizmo_visible(&self) -> bool {
+        self.gizmo_visible
+    }
+
+    /// Shows the translation gizmo on the point light, see `WindowState` for dragging it.
+    pub fn set_gizmo_visible(&mut self, visible: bool) {
+        self.gizmo_visible = visible;
+        if !visible {
+            self.gizmo.end_drag();
+        }
+    }
+
+    pub fn grid_visible(&self) -> bool {
+        self.grid_visible
+    }
+
+    /// Shows the reference grid, see `GridPlane`.
+    pub fn set_grid_visible(&mut self, visible: bool) {
+        self.grid_visible = visible;
+    }
+
+    /// The grid's spacing, colors and fading, uploaded on `update` while it is visible.
+    pub fn grid_mut(&mut self) -> &mut GridPlane {
+        &mut self.grid
+    }
+
+    /// The gizmo is placed on the point light and uploaded on `update`, except while it is
+    /// being dragged.
+    pub fn gizmo_mut(&mut self) -> &mut Gizmo {
+        &mut self.gizmo
+    }
+
+    /// Direction the sun's light travels in, which also places the sun of the atmosphere.
+    pub fn sun_direction(&self) -> cgmath::Vector3<f32> {
+        self.sun.uniform.direction.into()
+    }
+
+    /// Points the sun's light along `direction`, moving its shadows and, from the next
+    /// `update` on, the sun in the atmosphere.
+    pub fn set_sun_direction(&mut self, direction: cgmath::Vector3<f32>) {
+        if direction.magnitude2() == 0.0 {
+            return;
+        }
+        self.sun.uniform.direction = direction.normalize().into();
+        self.queue.write_buffer(
+            &self.sun.buffer,
+            0,
+            bytemuck::cast_slice(&[self.sun.uniform]),
+        );
+    }
+
+    /// Draws a procedural sky lit by the sun instead of the skybox's cubemap, see
+    /// `AtmosphereSkybox`, or goes back to the cubemap. Once `set_environment` was called
+    /// the image-based lighting is baked again from the new sky.
+    pub fn set_atmosphere(&mut self, enabled: bool) {
+        if enabled == self.atmosphere.is_some() {
+            return;
+        }
+        self.atmosphere = enabled.then(|| {
+            AtmosphereSkybox::new(
+                &self.device,
+                &self.camera_bind_group_layout,
+                Tonemapper::HDR_FORMAT,
+                Some(self.depth_format),
+                self.depth_compare(),
+                self.sample_count,
+            )
+        });
+        self.bake_ibl();
+    }
+
+    /// Switches the sky to `environment` and bakes the image-based lighting in `ibl` from
+    /// it, replacing the skybox's cubemap or turning on the atmosphere. Baking runs compute
+    /// passes over the whole sky, so this is meant for occasional switches, e.g. comparing
+    /// an HDR environment map with the procedural sky, not for every frame. The atmosphere's
+    /// lighting isn't baked again when the sun moves, setting it again does that.
+    pub fn set_environment(&mut self, environment: Environment) {
+        if self.ibl_baker.is_none() {
+            self.ibl_baker = Some(IblBaker::new(&self.device));
+        }
+        match environment {
+            Environment::Cubemap(texture) => {
+                self.atmosphere = None;
+                self.skybox.set_texture(&self.device, texture);
+                self.bake_ibl();
+            }
+            Environment::Atmosphere if self.atmosphere.is_some() => self.bake_ibl(),
+            Environment::Atmosphere => self.set_atmosphere(true),
+        }
+    }
+
+    /// The image-based lighting of the current sky, bound with `IblEnvironment::bind_group`
+    /// as the last group of `create_pbr_pipeline`'s layout. Without IBL until
+    /// `set_environment` is called.
+    pub fn ibl(&self) -> &IblEnvironment {
+        &self.ibl
+    }
+
+    pub fn ibl_bind_group_layout(&self) -> &wgpu::BindGroupLayout {
+        &self.ibl_bind_group_layout
+    }
+
+    /// Bakes `ibl` from the sky drawn now, unless `set_environment` wasn't called yet.
+    fn bake_ibl(&mut self) {
+        /// Texels per side of the cube the atmosphere is baked into first
+        const ATMOSPHERE_CUBE_SIZE: u32 = 256;
+        let Some(baker) = &self.ibl_baker else {
+            return;
+        };
+        let baked_sky;
+        let env = match self.atmosphere.as_mut() {
+            Some(atmosphere) => {
+                atmosphere.set_sun_direction(-cgmath::Vector3::from(self.sun.uniform.direction));
+                atmosphere.upload(&self.queue);
+                baked_sky = atmosphere.bake(&self.device, &self.queue, ATMOSPHERE_CUBE_SIZE);
+                &baked_sky
+            }
+            None => &self.skybox.texture,
+        };
+        self.ibl = IblEnvironment::bake(
+            &self.device,
+            &self.queue,
+            baker,
+            env,
+            &self.ibl_bind_group_layout,
+        );
+    }
+
+    pub fn atmosphere(&self) -> Option<&AtmosphereSkybox> {
+        self.atmosphere.as_ref()
+    }
+
+    /// The atmosphere's turbidity, ground albedo and intensity, uploaded on `update`.
+    pub fn atmosphere_mut(&mut self) -> Option<&mut AtmosphereSkybox> {
+        self.atmosphere.as_mut()
+    }
+
+    /// How the point light orbits the scene.
+    pub fn light_animator(&self) -> LightAnimator {
+        self.light_animator
+    }
+
+    pub fn set_light_animator(&mut self, animator: LightAnimator) {
+        self.light_animator = animator;
+    }
+
+    /// Distance falloff of the animated point light. It lights the scene equally at any
+    /// distance, `Attenuation::NONE`, until set otherwise, e.g. to `Attenuation::from_range`.
+    pub fn light_attenuation(&self) -> Attenuation {
+        self.light.uniform.attenuation()
+    }
+
+    pub fn set_light_attenuation(&mut self, attenuation: Attenuation) {
+        self.light.uniform.set_attenuation(attenuation);
+    }
+
+    /// Lights the scene with `light` from the next `update` on, returning its index, or
+    /// `None` if the light array is full.
+    pub fn add_spot_light(&mut self, light: &SpotLight) -> Option<usize> {
+        self.lights.add_spot_light(light.uniform())
+    }
+
+    /// Replaces the spotlight at `index`, e.g. to move a flashlight with the camera.
+    /// Returns whether there was one.
+    pub fn update_spot_light(&mut self, index: usize, light: &SpotLight) -> bool {
+        self.lights.update_spot_light(index, light.uniform())
+    }
+
+    pub fn spot_light_count(&self) -> usize {
+        self.lights.spot_lights.len()
+    }
+
+    pub fn clear_spot_lights(&mut self) {
+        self.lights.spot_lights.clear();
+    }
+
+    /// Draws instances the `selector` puts past LOD 0 as billboards showing cell
+    /// `atlas_index` of `atlas`, split into `columns` x `rows` cells. Nearer instances keep
+    /// their mesh. Shadows are still cast by the mesh of every instance.
+    pub fn set_impostors(
+        &mut self,
+        atlas: texture::Texture,
+        columns: u32,
+        rows: u32,
+        atlas_index: u32,
+        selector: LodSelector,
+    ) {
+        let depth_compare = self.depth_compare();
+        let billboard = Billboard::new(
+            &self.device,
+            [&self.camera_bind_group_layout, &self.sun.bind_group_layout],
+            atlas,
+            columns,
+            rows,
+            Tonemapper::HDR_FORMAT,
+            Some(self.depth_format),
+            depth_compare,
+            self.sample_count,
+        );
+        self.impostors = Some(ImpostorLod::new(billboard, selector, atlas_index));
+    }
+
+    /// Draws every instance with its mesh again.
+    pub fn clear_impostors(&mut self) {
+        self.impostors = None;
+    }
+
+    /// Draws `water` in the transparency pass from now on, replacing any previous water.
+    pub fn set_water(&mut self, mut water: Water) -> anyhow::Result<()> {
+        let normal_map = Water::create_normal_map(&self.device, &self.queue)?;
+        let config = Self::render_config(&self.config, self.render_scale);
+        let reflection = Self::create_water_reflection(&self.device, &config);
+        water.set_textures(&self.device, normal_map, &reflection);
+        let depth_compare = self.depth_compare();
+        water.recreate_pipeline(
+            &self.device,
+            &self.camera_bind_group_layout,
+            &self.sun.bind_group_layout,
+            &self.fog.bind_group_layout,
+            Tonemapper::HDR_FORMAT,
+            Some(self.depth_format),
+            depth_compare,
+            self.sample_count,
+        );
+        self.water = Some(water);
+        self.water_reflection = Some(reflection);
+        Ok(())
+    }
+
+    pub fn clear_water(&mut self) {
+        self.water = None;
+        self.water_reflection = None;
+    }
+
+    pub fn water(&self) -> Option<&Water> {
+        self.water.as_ref()
+    }
+
+    pub fn water_mut(&mut self) -> Option<&mut Water> {
+        self.water.as_mut()
+    }
+
+    pub fn impostors(&self) -> Option<&ImpostorLod> {
+        self.impostors.as_ref()
+    }
+
+    pub fn impostors_mut(&mut self) -> Option<&mut ImpostorLod> {
+        self.impostors.as_mut()
+    }
+
+    /// GPU time per pass of a recent frame. Every pass is `None` without
+    /// `Features::TIMESTAMP_QUERY`, see `gpu_timing_supported`.
+    pub fn last_gpu_times(&self) -> GpuTimings {
+        self.gpu_timer
+            .as_ref()
+            .map(GpuTimer::last_timings)
+            .unwrap_or_default()
+    }
+
+    pub fn gpu_timing_supported(&self) -> bool {
+        self.gpu_timer.is_some()
+    }
+
+    pub fn debug_lines_visible(&self) -> bool {
+        self.debug_lines_visible
+    }
+
+    /// Shows or hides the debug lines. While visible, the bounding boxes of visible
+    /// instances (yellow) and terrain chunks (green) are drawn along with queued lines.
+    pub fn set_debug_lines_visible(&mut self, visible: bool) {
+        self.debug_lines_visible = visible;
+    }
+
+    /// Starts streaming `field` in chunks around the camera, replacing any previous
+    /// streamer. Chunks are drawn with a plain white material.
+    pub fn stream_terrain(
+        &mut self,
+        field: impl HeightField + 'static,
+        chunk_size: f32,
+        resolution: u32,
+        load_radius: f32,
+    ) -> anyhow::Result<()> {
+        let material = resources::white_material(
+            &self.device,
+            &self.queue,
+            "terrain",
+            &self.texture_bind_group_layout,
+        )?;
+        self.terrain_streamer = Some(TerrainStreamer::new(
+            &self.device,
+            field,
+            material,
+            chunk_size,
+            resolution,
+            load_radius,
+        ));
+        if let Some(tessellator) = self.terrain_tessellator.as_mut() {
+            tessellator.clear();
+        }
+        Ok(())
+    }
+
+    /// Subdivides streamed terrain near the camera on the GPU for smooth close-up detail,
+    /// or stops with `None`, see `TerrainTessellator`. Needs compute shader support.
+    pub fn set_terrain_tessellation(
+        &mut self,
+        settings: Option<TessellationSettings>,
+    ) -> anyhow::Result<()> {
+        let Some(settings) = settings else {
+            self.terrain_tessellator = None;
+            return Ok(());
+        };
+        anyhow::ensure!(
+            self.adapter
+                .get_downlevel_capabilities()
+                .flags
+                .contains(wgpu::DownlevelFlags::COMPUTE_SHADERS),
+            "terrain tessellation needs compute shaders, which the adapter doesn't support"
+        );
+        match self.terrain_tessellator.as_mut() {
+            Some(tessellator) => tessellator.set_settings(settings),
+            None => {
+                self.terrain_tessellator = Some(TerrainTessellator::new(&self.device, settings))
+            }
+        }
+        Ok(())
+    }
+
+    pub fn terrain_tessellation(&self) -> Option<TessellationSettings> {
+        self.terrain_tessellator
+            .as_ref()
+            .map(TerrainTessellator::settings)
+    }
+
+    /// Stamps `texture` onto the terrain, or whatever else is opaque, inside the unit box
+    /// `transform` places, projected along the box's y axis, see `Decals`. Returns the
+    /// decal's index in `decals`.
+    pub fn add_decal(&mut self, transform: Transform, texture: texture::Texture) -> usize {
+        self.decals.add(transform, texture)
+    }
+
+    pub fn decals(&self) -> &Decals {
+        &self.decals
+    }
+
+    /// Decals can be moved or removed here, their transforms are uploaded on `update`.
+    pub fn decals_mut(&mut self) -> &mut Decals {
+        &mut self.decals
+    }
+
+    /// Starts rain, snow or other weather particles around the camera, or stops them with
+    /// `None`, see `ParticleSystem`. Needs compute shader support.
+    pub fn set_weather(&mut self, settings: Option<ParticleSettings>) -> anyhow::Result<()> {
+        let Some(settings) = settings else {
+            self.particles = None;
+            return Ok(());
+        };
+        anyhow::ensure!(
+            self.adapter
+                .get_downlevel_capabilities()
+                .flags
+                .contains(wgpu::DownlevelFlags::COMPUTE_SHADERS),
+            "weather particles need compute shaders, which the adapter doesn't support"
+        );
+        match self.particles.as_mut() {
+            Some(particles) => particles.set_settings(settings),
+            None => {
+                self.particles = Some(ParticleSystem::new(
+                    &self.device,
+                    &self.queue,
+                    &self.camera_bind_group_layout,
+                    Tonemapper::HDR_FORMAT,
+                    self.depth_format,
+                    self.depth_compare(),
+                    self.sample_count,
+                    settings,
+                ))
+            }
+        }
+        Ok(())
+    }
+
+    pub fn particle_system(&self) -> Option<&ParticleSystem> {
+        self.particles.as_ref()
+    }
+
+    pub fn particle_system_mut(&mut self) -> Option<&mut ParticleSystem> {
+        self.particles.as_mut()
+    }
+
+    /// Layout `SplatMaterial`s passed to `set_terrain_splat` must be created with.
+    pub fn splat_bind_group_layout(&self) -> &wgpu::BindGroupLayout {
+        &self.splat_bind_group_layout
+    }
+
+    /// Draws streamed terrain blending the layers of `material` instead of plain white.
+    pub fn set_terrain_splat(&mut self, material: SplatMaterial) {
+        let pipelines = Self::create_splat_pipelines(
+            &self.device,
+            &self.splat_pipeline_layout,
+            Tonemapper::HDR_FORMAT,
+            self.depth_format,
+            self.pipeline_desc(),
+        );
+        self.terrain_splat = Some((material, pipelines));
+    }
+
+    pub fn clear_terrain_splat(&mut self) {
+        self.terrain_splat = None;
+    }
+
+    pub fn terrain_splat(&self) -> Option<&SplatMaterial> {
+        self.terrain_splat.as_ref().map(|(material, _)| material)
+    }
+
+    /// The material every streamed chunk is drawn with, e.g. for `SplatMaterial::set_colormap`
+    /// with a `Colormap` of its own. It belongs to the renderer rather than to a `Terrain`,
+    /// which only holds the heights and mesh of one chunk.
+    pub fn terrain_splat_mut(&mut self) -> Option<&mut SplatMaterial> {
+        self.terrain_splat.as_mut().map(|(material, _)| material)
+    }
+
+    /// Colors streamed terrain by height through the built-in colormap `name`, one of
+    /// `Colormap::NAMES`, over `SplatParams::elevation_range`. Without a splat material, a
+    /// plain white one is set. `None` blends the splat layers again. Like the contours this
+    /// is set here and not on `Terrain`, see `terrain_splat_mut`.
+    pub fn set_terrain_colormap(&mut self, name: Option<&str>) -> anyhow::Result<()> {
+        let colormap = name
+            .map(|name| Colormap::new(&self.device, &self.queue, name))
+            .transpose()?;
+        if colormap.is_none() && self.terrain_splat.is_none() {
+            return Ok(());
+        }
+        self.terrain_splat_or_white()?;
+        if let Some((material, _)) = self.terrain_splat.as_mut() {
+            material.set_colormap(
+                &self.device,
+                &self.queue,
+                &self.splat_bind_group_layout,
+                colormap,
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Draws contour lines over streamed terrain, or stops with `None`, like setting
+    /// `SplatParams::contours`. Without a splat material, a plain white one is set.
+    pub fn set_terrain_contours(&mut self, contours: Option<Contours>) -> anyhow::Result<()> {
+        if contours.is_none() && self.terrain_splat.is_none() {
+            return Ok(());
+        }
+        let material = self.terrain_splat_or_white()?;
+        let params = SplatParams {
+            contours,
+            ..material.params()
+        };
+        self.set_terrain_splat_params(params);
+        Ok(())
+    }
+
+    /// The splat material, after setting a plain white one if there is none.
+    fn terrain_splat_or_white(&mut self) -> anyhow::Result<&SplatMaterial> {
+        if self.terrain_splat.is_none() {
+            let white = image::DynamicImage::ImageRgba8(image::RgbaImage::from_pixel(
+                1,
+                1,
+                image::Rgba([255; 4]),
+            ));
+            // Two layers, as GL takes single layer textures for plain 2D ones
+            let layers = texture::Texture::array_from_images(
+                &self.device,
+                &self.queue,
+                &[white.clone(), white],
+                "terrain_white_layer",
+            )?;
+            let material = SplatMaterial::new(
+                &self.device,
+                &self.queue,
+                &self.splat_bind_group_layout,
+                layers,
+                None,
+                SplatParams::default(),
+            )?;
+            self.set_terrain_splat(material);
+        }
+        Ok(self.terrain_splat().expect("set above"))
+    }
+
+    /// Changes how the splat material picks its layers, if there is one.
+    pub fn set_terrain_splat_params(&mut self, params: SplatParams) {
+        if let Some((material, _)) = self.terrain_splat.as_mut() {
+            material.set_params(&self.queue, params);
+        }
+    }
+
+    pub fn terrain_streamer(&self) -> Option<&TerrainStreamer> {
+        self.terrain_streamer.as_ref()
+    }
+
+    pub fn terrain_streamer_mut(&mut self) -> Option<&mut TerrainStreamer> {
+        self.terrain_streamer.as_mut()
+    }
+
+    /// Drops all streamed terrain chunks and stops the worker.
+    pub fn stop_terrain_streaming(&mut self) {
+        self.terrain_streamer = None;
+    }
+
+    /// Replaces the instances drawn in the transparent pass after all opaque geometry.
+    pub fn set_transparent_instances(&mut self, instances: Vec<Instance>) {
+        self.transparent_instance_buffer =
+            crate::instance::create_instance_buffer(&self.device, &instances);
+        for viewport in &mut self.viewports {
+            viewport.set_transparent_instances(&self.device, &instances);
+        }
+        self.transparent_instances = instances;
+        // Nothing is sorted into the new buffer before the next update
+        self.visible_transparent_instances = 0;
+    }
+
+    pub fn transparency(&self) -> Transparency {
+        self.transparency
+    }
+
+    /// Switches how the transparent instances are drawn. Alpha to coverage only differs
+    /// from opaque with MSAA on.
+    pub fn set_transparency(&mut self, transparency: Transparency) {
+        if transparency == Transparency::AlphaToCoverage && self.sample_count == 1 {
+            log::warn!("Alpha to coverage needs MSAA, transparent instances are drawn opaque");
+        }
+        self.transparency = transparency;
+        self.transparent_pipeline = Self::create_transparent_pipeline(
+            &self.device,
+            &self.render_pipeline_layout,
+            Tonemapper::HDR_FORMAT,
+            self.depth_format,
+            self.pipeline_desc(),
+            transparency,
+        );
+    }
+
+    /// Name, vendor, backend and driver of the GPU in use.
+    pub fn adapter_info(&self) -> wgpu::AdapterInfo {
+        self.adapter.get_info()
+    }
+
+    /// Features the device was created with. Optional ones like `POLYGON_MODE_LINE` are
+    /// only requested when the adapter supports them, see `adapter_features` for the rest.
+    pub fn enabled_features(&self) -> wgpu::Features {
+        self.device.features()
+    }
+
+    /// Everything the adapter could enable, e.g. `FLOAT32_FILTERABLE`.
+    pub fn adapter_features(&self) -> wgpu::Features {
+        self.adapter.features()
+    }
+
+    /// Format of the frames drawn, for pipelines drawing into them. The scene itself
+    /// renders in `Tonemapper::HDR_FORMAT`. Colors written to a format that isn't sRGB
+    /// have to be encoded in the shader, see `SurfaceFormatPreference`.
+    pub fn output_format(&self) -> wgpu::TextureFormat {
+        self.config.format
+    }
+
+    /// Size of the frames drawn, before `render_scale`.
+    pub fn output_size(&self) -> (u32, u32) {
+        (self.config.width, self.config.height)
+    }
+
+    pub fn adapter(&self) -> &wgpu::Adapter {
+        &self.adapter
+    }
+
+    pub fn device(&self) -> &wgpu::Device {
+        &self.device
+    }
+
+    pub fn queue(&self) -> &wgpu::Queue {
+        &self.queue
+    }
+
+    pub fn camera(&self) -> &Camera {
+        &self.camera
+    }
+
+    /// The camera is uploaded and culled against on the next `update`.
+    pub fn camera_mut(&mut self) -> &mut Camera {
+        &mut self.camera
+    }
+
+    pub fn projection(&self) -> &Projection {
+        &self.projection
+    }
+
+    /// The projection is uploaded with the camera on the next `update`.
+    pub fn projection_mut(&mut self) -> &mut Projection {
+        &mut self.projection
+    }
+
+    /// Draws the scene once per camera into its rectangle of the output, e.g. side by side
+    /// for comparing two angles, instead of from `camera` over all of it. The cameras share
+    /// the projection, stretched to each rectangle's aspect ratio. Rectangles may overlap,
+    /// later ones are drawn over earlier ones. An empty slice goes back to `camera`.
+    pub fn set_viewports(&mut self, viewports: &[(Camera, Rect)]) {
+        // Keep the buffers of existing viewports, the cameras may change every frame
+        self.viewports.truncate(viewports.len());
+        for (index, &(camera, rect)) in viewports.iter().enumerate() {
+            match self.viewports.get_mut(index) {
+                Some(viewport) => {
+                    viewport.camera = camera;
+                    viewport.rect = rect;
+                }
+                None => self.viewports.push(Viewport::new(
+                    &self.device,
+                    &self.camera_bind_group_layout,
+                    camera,
+                    rect,
+                    &self.instances,
+                    &self.transparent_instances,
+                )),
+            }
+        }
+    }
+
+    pub fn viewports(&self) -> &[Viewport] {
+        &self.viewports
+    }
+
+    /// A viewport's camera can be moved like `camera_mut`, e.g. by a second controller.
+    pub fn viewport_mut(&mut self, index: usize) -> Option<&mut Viewport> {
+        self.viewports.get_mut(index)
+    }
+
+    /// The camera's placement and lens, e.g. to reproduce a screenshot later.
+    pub fn camera_state(&self) -> CameraState {
+        self.camera.save_state(&self.projection)
+    }
+
+    /// Moves the camera to `state` and switches to its lens on the next `update`.
+    pub fn set_camera_state(&mut self, state: &CameraState) {
+        self.camera.restore_state(state, &mut self.projection);
+    }
+
+    /// Writes `camera_state` to `path` as JSON.
+    pub fn save_camera(&self, path: impl AsRef<std::path::Path>) -> anyhow::Result<()> {
+        std::fs::write(path, self.camera_state().to_json()?)?;
+        Ok(())
+    }
+
+    /// Restores a camera written by `save_camera`. The camera is left untouched if the file
+    /// can't be read or parsed.
+    pub fn load_camera(&mut self, path: impl AsRef<std::path::Path>) -> anyhow::Result<()> {
+        let state = CameraState::from_json(&std::fs::read_to_string(path)?)?;
+        self.set_camera_state(&state);
+        Ok(())
+    }
+
+    pub fn depth_format(&self) -> wgpu::TextureFormat {
+        self.depth_format
+    }
+
+    pub fn sample_count(&self) -> u32 {
+        self.sample_count
+    }
+
+    /// Switches MSAA to `samples` per pixel (1 disables it), recreating the render targets
+    /// and every pipeline that draws into them. Fails if the adapter can't multisample the
+    /// HDR or depth format at that count, or if deferred rendering is on.
+    pub fn set_msaa(&mut self, samples: u32) -> anyhow::Result<()> {
+        if !matches!(samples, 1 | 2 | 4 | 8) {
+            anyhow::bail!("unsupported MSAA sample count {samples}, expected 1, 2, 4 or 8");
+        }
+        let velocity_format = self.motion_vectors.as_ref().map(|_| MotionVectors::FORMAT);
+        for format in [Tonemapper::HDR_FORMAT, self.depth_format]
+            .into_iter()
+            .chain(velocity_format)
+        {
+            if !Self::supports_sample_count(&self.adapter, &self.device, format, samples) {
+                anyhow::bail!("{samples}x MSAA is not supported for {format:?} on this adapter");
+            }
+        }
+        if samples == self.sample_count {
+            return Ok(());
+        }
+        if samples > 1 && self.rendering_mode == RenderingMode::Deferred {
+            anyhow::bail!("deferred rendering doesn't support MSAA, switch to forward first");
+        }
+
+        self.sample_count = samples;
+        self.recreate_render_targets();
+        self.recreate_pipelines();
+        Ok(())
+    }
+
+    /// Rebuilds the scene's pipelines for the current sample count from the current
+    /// shaders, see `hot_reload`.
+    fn recreate_pipelines(&mut self) {
+        self.recreate_pipelines_using(|_| true);
+    }
+
+    /// Rebuilds the scene's pipelines whose shader `uses` returns true for, given the
+    /// files it is concatenated from.
+    fn recreate_pipelines_using(&mut self, uses: impl Fn(&[&str]) -> bool) {
+        const SHADOW: &str = "src/shadow_sampling.wgsl";
+        const PRELUDE: &str = "src/prelude.wgsl";
+        const SCENE: &[&str] = &["shader.wgsl", SHADOW, PRELUDE];
+        let samples = self.sample_count;
+        let depth_compare = self.depth_compare();
+        let desc = self.pipeline_desc();
+
+        if uses(&["shader.wgsl", "wireframe.wgsl", SHADOW, PRELUDE]) {
+            (self.render_pipeline, self.wireframe_pipeline) = Self::create_scene_pipelines(
+                &self.device,
+                &self.render_pipeline_layout,
+                Tonemapper::HDR_FORMAT,
+                self.depth_format,
+                desc,
+            );
+        }
+        if uses(SCENE) {
+            (self.depth_prepass_pipeline, self.depth_equal_pipeline) =
+                Self::create_depth_prepass_pipelines(
+                    &self.device,
+                    &self.render_pipeline_layout,
+                    Tonemapper::HDR_FORMAT,
+                    self.depth_format,
+                    desc,
+                );
+        }
+        if uses(&["shader.wgsl", "push_constants.wgsl", SHADOW, PRELUDE])
+            && let Some((layout, pipeline)) = self.push_constants.as_mut()
+        {
+            *pipeline = create_push_constant_pipeline(
+                &self.device,
+                layout,
+                Tonemapper::HDR_FORMAT,
+                self.depth_format,
+                desc,
+            );
+        }
+        if uses(SCENE) {
+            self.transparent_pipeline = Self::create_transparent_pipeline(
+                &self.device,
+                &self.render_pipeline_layout,
+                Tonemapper::HDR_FORMAT,
+                self.depth_format,
+                desc,
+                self.transparency,
+            );
+        }
+        if uses(&["src/gbuffer.wgsl", PRELUDE])
+            && let Some(deferred) = self.deferred.as_mut()
+        {
+            deferred.recreate_geometry_pipeline(
+                &self.device,
+                &self.render_pipeline_layout,
+                self.depth_format,
+                depth_compare,
+                self.primitive,
+                self.motion_vectors.is_some(),
+            );
+        }
+        if uses(&["src/velocity.wgsl", PRELUDE])
+            && let Some(motion_vectors) = self.motion_vectors.as_mut()
+        {
+            motion_vectors.recreate_pipeline(
+                &self.device,
+                &self.render_pipeline_layout,
+                self.depth_format,
+                depth_compare,
+                self.primitive,
+                samples,
+            );
+        }
+        if uses(&["src/terrain.wgsl", SHADOW, PRELUDE])
+            && let Some((_, pipelines)) = self.terrain_splat.as_mut()
+        {
+            *pipelines = Self::create_splat_pipelines(
+                &self.device,
+                &self.splat_pipeline_layout,
+                Tonemapper::HDR_FORMAT,
+                self.depth_format,
+                desc,
+            );
+        }
+        if uses(&["src/light.wgsl", PRELUDE]) {
+            self.light.recreate_pipeline(
+                &self.device,
+                Tonemapper::HDR_FORMAT,
+                Some(self.depth_format),
+                depth_compare,
+                &self.camera_bind_group_layout,
+                samples,
+            );
+        }
+        if uses(&["sky.wgsl", PRELUDE]) {
+            self.skybox.recreate_pipeline(
+                &self.device,
+                &self.camera_bind_group_layout,
+                Tonemapper::HDR_FORMAT,
+                Some(self.depth_format),
+                depth_compare,
+                samples,
+            );
+        }
+        if uses(&["src/atmosphere.wgsl", PRELUDE])
+            && let Some(atmosphere) = self.atmosphere.as_mut()
+        {
+            atmosphere.recreate_pipeline(
+                &self.device,
+                &self.camera_bind_group_layout,
+                Tonemapper::HDR_FORMAT,
+                Some(self.depth_format),
+                depth_compare,
+                samples,
+            );
+        }
+        if uses(&["src/debug_lines.wgsl", PRELUDE]) {
+            self.debug_lines.recreate_pipeline(
+                &self.device,
+                &self.camera_bind_group_layout,
+                Tonemapper::HDR_FORMAT,
+                Some(self.depth_format),
+                depth_compare,
+                samples,
+            );
+        }
+        if uses(&["src/debug_lines.wgsl", PRELUDE]) {
+            self.gizmo.recreate_pipeline(
+                &self.device,
+                &self.camera_bind_group_layout,
+                Tonemapper::HDR_FORMAT,
+                Some(self.depth_format),
+                samples,
+            );
+        }
+        if uses(&["src/grid.wgsl", PRELUDE]) {
+            self.grid.recreate_pipeline(
+                &self.device,
+                &self.camera_bind_group_layout,
+                Tonemapper::HDR_FORMAT,
+                self.depth_format,
+                depth_compare,
+                samples,
+            );
+        }
+        if uses(&["src/decal.wgsl", PRELUDE]) {
+            self.decals.recreate_pipeline(
+                &self.camera_bind_group_layout,
+                Tonemapper::HDR_FORMAT,
+                depth_compare,
+                samples,
+            );
+        }
+        if uses(&["src/particles.wgsl", PRELUDE])
+            && let Some(particles) = self.particles.as_mut()
+        {
+            particles.recreate_pipeline(
+                &self.camera_bind_group_layout,
+                Tonemapper::HDR_FORMAT,
+                self.depth_format,
+                depth_compare,
+                samples,
+            );
+        }
+        if uses(&["src/outline.wgsl", "src/fullscreen.wgsl", PRELUDE])
+            && let Some(outline) = self.outline.as_mut()
+        {
+            outline.recreate_pipelines(
+                &self.device,
+                &self.camera_bind_group_layout,
+                Tonemapper::HDR_FORMAT,
+                self.depth_format,
+                depth_compare,
+                samples,
+            );
+        }
+        if uses(&["src/water.wgsl", PRELUDE])
+            && let Some(water) = self.water.as_mut()
+        {
+            water.recreate_pipeline(
+                &self.device,
+                &self.camera_bind_group_layout,
+                &self.sun.bind_group_layout,
+                &self.fog.bind_group_layout,
+                Tonemapper::HDR_FORMAT,
+                Some(self.depth_format),
+                depth_compare,
+                samples,
+            );
+        }
+        if uses(&["src/billboard.wgsl", PRELUDE])
+            && let Some(impostors) = self.impostors.as_mut()
+        {
+            impostors.billboard.recreate_pipeline(
+                &self.device,
+                [&self.camera_bind_group_layout, &self.sun.bind_group_layout],
+                Tonemapper::HDR_FORMAT,
+                Some(self.depth_format),
+                depth_compare,
+                samples,
+            );
+        }
+    }
+
+    /// Rebuilds the pipelines outside the scene passes whose shader `uses` returns true for,
+    /// like `recreate_pipelines_using`. None of them depend on the sample count or depth
+    /// test, so only shader edits need this.
+    #[cfg(feature = "hot-reload")]
+    fn recreate_other_pipelines_using(&mut self, uses: impl Fn(&[&str]) -> bool) {
+        const SHADOW: &str = "src/shadow_sampling.wgsl";
+        const PRELUDE: &str = "src/prelude.wgsl";
+        const FULLSCREEN: &str = "src/fullscreen.wgsl";
+
+        if uses(&["src/tonemap.wgsl", FULLSCREEN]) {
+            self.tonemapper
+                .recreate_pipeline(&self.device, self.config.format);
+        }
+        if uses(&["src/fxaa.wgsl", FULLSCREEN]) {
+            self.fxaa
+                .recreate_pipeline(&self.device, self.config.format);
+        }
+        if uses(&["src/bloom.wgsl", FULLSCREEN])
+            && let Some(bloom) = self.bloom.as_mut()
+        {
+            bloom.recreate_pipelines(&self.device);
+        }
+        if uses(&["src/mipmap.wgsl", FULLSCREEN]) {
+            texture::MipmapBlit::recreate(&self.device);
+        }
+        if uses(&["src/taa.wgsl", FULLSCREEN])
+            && let Some(taa) = self.taa.as_mut()
+        {
+            taa.recreate_pipeline(&self.device);
+        }
+        if uses(&["src/exposure.wgsl"])
+            && let Some(auto_exposure) = self.auto_exposure.as_mut()
+        {
+            auto_exposure.recreate_pipelines(&self.device);
+        }
+        if let Some(deferred) = self.deferred.as_mut() {
+            if uses(&["src/deferred.wgsl", FULLSCREEN, SHADOW, PRELUDE]) {
+                deferred.recreate_lighting_pipeline(
+                    &self.device,
+                    [
+                        &self.camera_bind_group_layout,
+                        &self.lights.bind_group_layout,
+                        &self.sun.bind_group_layout,
+                        &self.shadow_map.bind_group_layout,
+                        &self.fog.bind_group_layout,
+                    ],
+                    Tonemapper::HDR_FORMAT,
+                    self.depth_format,
+                );
+            }
+            if uses(&["src/ssao.wgsl", FULLSCREEN, PRELUDE]) {
+                deferred
+                    .ssao
+                    .recreate_pipelines(&self.device, &self.camera_bind_group_layout);
+            }
+        }
+        if uses(&["src/shadow.wgsl"]) {
+            self.shadow_map.recreate_pipeline(
+                &self.device,
+                &[model::ModelVertex::desc(), InstanceRaw::desc()],
+            );
+        }
+        if uses(&["src/picking.wgsl"]) {
+            self.depth_probe.recreate_pipelines(&self.device);
+        }
+        if uses(&["src/tessellation.wgsl"])
+            && let Some(tessellator) = self.terrain_tessellator.as_mut()
+        {
+            tessellator.recreate_pipeline();
+        }
+        // Built again on the next bake
+        if uses(&["src/ibl.wgsl"]) {
+            self.ibl_baker = None;
+        }
+    }
+
+    /// Takes in the edits of WGSL files changed on disk and rebuilds the pipelines using
+    /// them, in an error scope. If any of them fails to build, e.g. as the edit doesn't
+    /// compile, the edits are logged and taken back, so the pipelines stay as they were
+    /// until fixed.
+    #[cfg(feature = "hot-reload")]
+    fn reload_changed_shaders(&mut self) {
+        self.reloaded_shaders.clear();
+        let changed = self.shader_watcher.changed();
+        if changed.is_empty() {
+            return;
+        }
+        let previous = crate::hot_reload::stage(&changed);
+        if previous.is_empty() {
+            return;
+        }
+        let changed = previous
+            .iter()
+            .map(|(path, _)| path.clone())
+            .collect::<Vec<_>>();
+        let rebuild = |renderer: &mut Self| {
+            let uses = |files: &[&str]| {
+                files
+                    .iter()
+                    .any(|file| changed.iter().any(|path| path == file))
+            };
+            renderer.recreate_pipelines_using(uses);
+            renderer.recreate_other_pipelines_using(uses);
+        };
+
+        self.device.push_error_scope(wgpu::ErrorFilter::Validation);
+        rebuild(self);
+        if let Some(error) = pollster::block_on(self.device.pop_error_scope()) {
+            log::error!("Not reloading shaders after {changed:?} changed: {error}");
+            crate::hot_reload::restore(previous);
+            rebuild(self);
+            return;
+        }
+        log::info!("Reloaded shaders after {changed:?} changed");
+        self.reloaded_shaders = changed;
+    }
+
+    /// The WGSL files, relative to the crate root, whose edits the last `update` took in
+    /// and rebuilt the renderer's pipelines for, to rebuild pipelines made outside the
+    /// renderer, e.g. with `create_pbr_pipeline`, from the same files.
+    #[cfg(feature = "hot-reload")]
+    pub fn reloaded_shaders(&self) -> &[String] {
+        &self.reloaded_shaders
+    }
+
+    fn supports_sample_count(
+        adapter: &wgpu::Adapter,
+        device: &wgpu::Device,
+        format: wgpu::TextureFormat,
+        samples: u32,
+    ) -> bool {
+        // Without the adapter specific feature only the WebGPU guarantees (1x and 4x) apply
+        let features = if device
+            .features()
+            .contains(wgpu::Features::TEXTURE_ADAPTER_SPECIFIC_FORMAT_FEATURES)
+        {
+            adapter.get_texture_format_features(format)
+        } else {
+            format.guaranteed_format_features(device.features())
+        };
+        features.flags.sample_count_supported(samples)
+    }
+
+    fn create_msaa_view(
+        device: &wgpu::Device,
+        config: &wgpu::SurfaceConfiguration,
+        sample_count: u32,
+    ) -> Option<wgpu::TextureView> {
+        if sample_count == 1 {
+            return None;
+        }
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("msaa_texture"),
+            size: wgpu::Extent3d {
+                width: config.width.max(1),
+                height: config.height.max(1),
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count,
+            dimension: wgpu::TextureDimension::D2,
+            format: config.format,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+            view_formats: &[],
+        });
+        Some(texture.create_view(&wgpu::TextureViewDescriptor::default()))
+    }
+
+    fn create_transparent_pipeline(
+        device: &wgpu::Device,
+        layout: &wgpu::PipelineLayout,
+        color_format: wgpu::TextureFormat,
+        depth_format: wgpu::TextureFormat,
+        desc: PipelineDesc,
+        transparency: Transparency,
+    ) -> wgpu::RenderPipeline {
+        let shader = wgpu::ShaderModuleDescriptor {
+            source: wgpu::ShaderSource::Wgsl(wgsl_source!(
+                "shader.wgsl",
+                "src/shadow_sampling.wgsl",
+                "src/prelude.wgsl"
+            )),
+            label: Some("Transparent Shader"),
+        };
+        create_render_pipeline(
+            device,
+            layout,
+            &[color_format],
+            Some(depth_format),
+            &[model::ModelVertex::desc(), InstanceRaw::desc()],
+            shader,
+            PipelineDesc {
+                blend: transparency.blend(),
+                alpha_to_coverage: transparency.alpha_to_coverage(),
+                ..desc
+            },
+        )
+    }
+
+    fn create_splat_pipelines(
+        device: &wgpu::Device,
+        layout: &wgpu::PipelineLayout,
+        color_format: wgpu::TextureFormat,
+        depth_format: wgpu::TextureFormat,
+        desc: PipelineDesc,
+    ) -> SplatPipelines {
+        let shader = || wgpu::ShaderModuleDescriptor {
+            source: wgpu::ShaderSource::Wgsl(wgsl_source!(
+                "src/terrain.wgsl",
+                "src/shadow_sampling.wgsl",
+                "src/prelude.wgsl"
+            )),
+            label: Some("Splat Shader"),
+        };
+        let vertex_layouts = [model::ModelVertex::desc(), InstanceRaw::desc()];
+        SplatPipelines {
+            forward: create_render_pipeline(
+                device,
+                layout,
+                &[color_format],
+                Some(depth_format),
+                &vertex_layouts,
+                shader(),
+                desc,
+            ),
+            depth_prepass: create_depth_prepass_pipeline(
+                device,
+                layout,
+                depth_format,
+                &vertex_layouts,
+                shader(),
+                desc,
+            ),
+            depth_equal: create_depth_equal_pipeline(
+                device,
+                layout,
+                color_format,
+                depth_format,
+                &vertex_layouts,
+                shader(),
+                desc,
+            ),
+        }
+    }
+
+    /// The depth-only prepass pipeline and the matching color pipeline shading only the
+    /// fragments left visible by it.
+    fn create_depth_prepass_pipelines(
+        device: &wgpu::Device,
+        layout: &wgpu::PipelineLayout,
+        color_format: wgpu::TextureFormat,
+        depth_format: wgpu::TextureFormat,
+        desc: PipelineDesc,
+    ) -> (wgpu::RenderPipeline, wgpu::RenderPipeline) {
+        let shader = || wgpu::ShaderModuleDescriptor {
+            source: wgpu::ShaderSource::Wgsl(wgsl_source!(
+                "shader.wgsl",
+                "src/shadow_sampling.wgsl",
+                "src/prelude.wgsl"
+            )),
+            label: Some("Depth Prepass Shader"),
+        };
+        let vertex_layouts = [model::ModelVertex::desc(), InstanceRaw::desc()];
+        let prepass = create_depth_prepass_pipeline(
+            device,
+            layout,
+            depth_format,
+            &vertex_layouts,
+            shader(),
+            desc,
+        );
+        let color = create_depth_equal_pipeline(
+            device,
+            layout,
+            color_format,
+            depth_format,
+            &vertex_layouts,
+            shader(),
+            desc,
+        );
+        (prepass, color)
+    }
+
+    /// The filled and wireframe model pipelines, which share a layout. The wireframe
+    /// pipeline is only created if the device supports `POLYGON_MODE_LINE`.
+    fn create_scene_pipelines(
+        device: &wgpu::Device,
+        layout: &wgpu::PipelineLayout,
+        color_format: wgpu::TextureFormat,
+        depth_format: wgpu::TextureFormat,
+        desc: PipelineDesc,
+    ) -> (wgpu::RenderPipeline, Option<wgpu::RenderPipeline>) {
+        let render_pipeline = {
+            let shader = wgpu::ShaderModuleDescriptor {
+                source: wgpu::ShaderSource::Wgsl(wgsl_source!(
+                    "shader.wgsl",
+                    "src/shadow_sampling.wgsl",
+                    "src/prelude.wgsl"
+                )),
+                label: Some("Filled Shader"),
+            };
+            create_render_pipeline(
+                device,
+                layout,
+                &[color_format],
+                Some(depth_format),
+                &[model::ModelVertex::desc(), InstanceRaw::desc()],
+                shader,
+                desc,
+            )
+        };
+
+        let wireframe_supported = device
+            .features()
+            .contains(wgpu::Features::POLYGON_MODE_LINE);
+        let wireframe_pipeline = wireframe_supported.then(|| {
+            let shader = wgpu::ShaderModuleDescriptor {
+                source: wgpu::ShaderSource::Wgsl(wgsl_source!(
+                    "wireframe.wgsl",
+                    "src/prelude.wgsl"
+                )),
+                label: Some("Wireframe Shader"),
+            };
+            create_render_pipeline(
+                device,
+                layout,
+                &[color_format],
+                Some(depth_format),
+                &[model::ModelVertex::desc(), InstanceRaw::desc()],
+                shader,
+                PipelineDesc {
+                    wireframe: true,
+                    ..desc
+                },
+            )
+        });
+
+        (render_pipeline, wireframe_pipeline)
+    }
+}
@@ -1,17 +1,78 @@
+#[cfg(not(target_arch = "wasm32"))]
+use std::time::Instant;
 use std::{
     fs,
     io::{BufReader, Cursor},
+    sync::Arc,
     vec,
 };
+// `std::time::Instant` panics in the browser
+#[cfg(target_arch = "wasm32")]
+use web_time::Instant;
 
-use anyhow::Ok;
-
-use crate::{model, texture};
+use crate::{
+    animation::{
+        AnimationClip, AnimationPlayer, Channel, Interpolation, Joint, JointPose, Keyframes,
+        Skeleton,
+    },
+    cache::{ResourceCache, TextureKey},
+    frustum::Aabb,
+    hot_reload::wgsl,
+    model, texture,
+};
+use cgmath::prelude::*;
 use wgpu::util::DeviceExt;
 
+/// Reads a file as text. In the browser, where there is no file system, `path` is fetched
+/// relative to the page's URL instead.
+pub async fn load_string(path: &str) -> anyhow::Result<String> {
+    Ok(String::from_utf8(load_binary(path).await?)?)
+}
+
+/// Reads a file's bytes. In the browser, where there is no file system, `path` is fetched
+/// relative to the page's URL instead.
+pub async fn load_binary(path: &str) -> anyhow::Result<Vec<u8>> {
+    #[cfg(not(target_arch = "wasm32"))]
+    let bytes = fs::read(path)?;
+    #[cfg(target_arch = "wasm32")]
+    let bytes = fetch(path).await?;
+    Ok(bytes)
+}
+
+#[cfg(target_arch = "wasm32")]
+async fn fetch(path: &str) -> anyhow::Result<Vec<u8>> {
+    use wasm_bindgen::JsCast;
+    use wasm_bindgen_futures::JsFuture;
+
+    let js_error = |e: wasm_bindgen::JsValue| anyhow::anyhow!("fetching {path} failed: {e:?}");
+    let window = web_sys::window().ok_or_else(|| anyhow::anyhow!("no browser window"))?;
+    let response: web_sys::Response = JsFuture::from(window.fetch_with_str(path))
+        .await
+        .map_err(js_error)?
+        .dyn_into()
+        .map_err(js_error)?;
+    if !response.ok() {
+        anyhow::bail!("fetching {path} failed with status {}", response.status());
+    }
+    let buffer = JsFuture::from(response.array_buffer().map_err(js_error)?)
+        .await
+        .map_err(js_error)?;
+    Ok(js_sys::Uint8Array::new(&buffer).to_vec())
+}
+
+/// Reads a Radiance `.hdr` or OpenEXR `.exr` image as linear RGBA floats, row by row, with
+/// its width and height. Feed the result to `EquirectToCubemapConverter::from_equirectangular_hdr`.
+pub fn load_hdr(path: impl AsRef<std::path::Path>) -> anyhow::Result<(Vec<f32>, u32, u32)> {
+    let img = image::open(path)?.into_rgba32f();
+    let (width, height) = img.dimensions();
+    Ok((img.into_raw(), width, height))
+}
+
+/// `anisotropy` is the sampler's anisotropic filtering level, 1 to turn it off.
 pub fn load_texture(
     filename: &str,
     is_normal_map: bool,
+    anisotropy: u16,
     device: &wgpu::Device,
     queue: &wgpu::Queue,
 ) -> anyhow::Result<texture::Texture> {
@@ -19,79 +80,189 @@ pub fn load_texture(
     let bytes = fs::read(filename)?;
 
     // create texture from bytes
-    texture::Texture::from_bytes(device, queue, &bytes, filename, is_normal_map)
+    texture_from_bytes(&bytes, filename, is_normal_map, anisotropy, device, queue)
+}
+
+/// Decodes a texture file's `bytes`. KTX2 files keep their own mip levels and compression,
+/// anything else is decoded with `image` and mipmapped on the GPU.
+fn texture_from_bytes(
+    bytes: &[u8],
+    path: &str,
+    is_normal_map: bool,
+    anisotropy: u16,
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+) -> anyhow::Result<texture::Texture> {
+    let is_ktx2 = std::path::Path::new(path)
+        .extension()
+        .is_some_and(|extension| extension.eq_ignore_ascii_case("ktx2"));
+    if is_ktx2 {
+        texture::Texture::from_ktx2(device, queue, bytes, path, anisotropy)
+    } else {
+        texture::Texture::from_bytes_mipmapped(
+            device,
+            queue,
+            bytes,
+            path,
+            is_normal_map,
+            anisotropy,
+        )
+    }
+}
+
+/// Loads a GPU-compressed texture with its mip chain from a KTX2 file, see
+/// `Texture::from_ktx2`.
+pub fn load_ktx2(
+    path: &str,
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+) -> anyhow::Result<texture::Texture> {
+    let bytes = fs::read(path)?;
+    texture::Texture::from_ktx2(device, queue, &bytes, path, 1)
+}
+
+/// `load_texture` through `cache`, which shares the texture with every other load of the
+/// same file instead of uploading it again.
+pub fn load_texture_cached(
+    filename: &str,
+    is_normal_map: bool,
+    anisotropy: u16,
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    cache: &ResourceCache,
+) -> anyhow::Result<Arc<texture::Texture>> {
+    let key = TextureKey {
+        path: filename.to_string(),
+        is_normal_map,
+        anisotropy,
+    };
+    cache.texture_or_load(key, |key| {
+        load_texture(&key.path, key.is_normal_map, key.anisotropy, device, queue)
+    })
 }
 
+/// Like `load_texture`, but reads the file with `load_binary`, so it also works in the
+/// browser, and takes it from `cache` instead if it was uploaded there before.
+pub async fn load_texture_async(
+    path: &str,
+    is_normal_map: bool,
+    anisotropy: u16,
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    cache: Option<&ResourceCache>,
+) -> anyhow::Result<Arc<texture::Texture>> {
+    let key = TextureKey {
+        path: path.to_string(),
+        is_normal_map,
+        anisotropy,
+    };
+    if let Some(texture) = cache.and_then(|cache| cache.texture(&key)) {
+        return Ok(texture);
+    }
+    let bytes = load_binary(path).await?;
+    let texture = texture_from_bytes(&bytes, path, is_normal_map, anisotropy, device, queue)?;
+    Ok(match cache {
+        Some(cache) => cache.insert_texture(key, texture),
+        None => Arc::new(texture),
+    })
+}
+
+/// Texture samplers use `anisotropy` levels of anisotropic filtering, 1 to turn it off.
 pub async fn load_model(
     filename: &str,
+    anisotropy: u16,
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    layout: &wgpu::BindGroupLayout,
+) -> anyhow::Result<model::Model> {
+    load_obj(filename, anisotropy, device, queue, layout, None).await
+}
+
+/// `load_model` sharing textures and materials through `cache`, so models using the same
+/// texture files don't upload them again. The cached materials are bound with `layout`,
+/// use one cache per material layout.
+pub async fn load_model_cached(
+    filename: &str,
+    anisotropy: u16,
     device: &wgpu::Device,
     queue: &wgpu::Queue,
     layout: &wgpu::BindGroupLayout,
+    cache: &ResourceCache,
+) -> anyhow::Result<model::Model> {
+    load_obj(filename, anisotropy, device, queue, layout, Some(cache)).await
+}
+
+async fn load_obj(
+    filename: &str,
+    anisotropy: u16,
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    layout: &wgpu::BindGroupLayout,
+    cache: Option<&ResourceCache>,
 ) -> anyhow::Result<model::Model> {
     // Load model data from file
-    let obj_text = std::fs::read_to_string(filename)?;
+    let obj_text = load_string(filename).await?;
     let base_path = std::path::Path::new(filename)
         .parent()
         .unwrap_or_else(|| std::path::Path::new("."));
     let obj_cursor = Cursor::new(obj_text);
     let mut obj_reader = BufReader::new(obj_cursor);
-    let (models, obj_materials) = tobj::load_obj_buf(
+    let (models, obj_materials) = tobj::load_obj_buf_async(
         &mut obj_reader,
         &tobj::LoadOptions {
             triangulate: true,
             single_index: true,
             ..Default::default()
         },
-        |p| {
+        |p| async move {
             let path = base_path.join(p);
-            tobj::load_mtl(path)
+            let mtl_text = load_string(&path.to_string_lossy())
+                .await
+                .map_err(|_| tobj::LoadError::OpenFileFailed)?;
+            tobj::load_mtl_buf(&mut BufReader::new(Cursor::new(mtl_text)))
         },
-    )?;
+    )
+    .await?;
 
     let mut materials = Vec::new();
     for m in obj_materials? {
         println!("Material: {:?}", m);
-        let diffuse_texture = load_texture(
-            base_path.join(&m.diffuse_texture).to_str().unwrap(),
-            false,
-            device,
-            queue,
-        )?;
-        let normal_texture = load_texture(
-            base_path.join(&m.normal_texture).to_str().unwrap(),
-            true,
-            device,
-            queue,
-        )?;
-        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-            layout,
-            entries: &[
-                wgpu::BindGroupEntry {
-                    binding: 0,
-                    resource: wgpu::BindingResource::TextureView(&diffuse_texture.view),
-                },
-                wgpu::BindGroupEntry {
-                    binding: 1,
-                    resource: wgpu::BindingResource::Sampler(&diffuse_texture.sampler),
-                },
-                wgpu::BindGroupEntry {
-                    binding: 2,
-                    resource: wgpu::BindingResource::TextureView(&normal_texture.view),
-                },
-                wgpu::BindGroupEntry {
-                    binding: 3,
-                    resource: wgpu::BindingResource::Sampler(&normal_texture.sampler),
-                },
-            ],
-            label: None,
-        });
+        // tobj leaves the map names empty when the .mtl has no map_Kd / map_Bump
+        let map_path = |name: &str| {
+            (!name.is_empty()).then(|| base_path.join(name).to_string_lossy().into_owned())
+        };
+        let diffuse_path = map_path(&m.diffuse_texture);
+        let normal_path = map_path(&m.normal_texture);
+        // Apart from its name a material is nothing but its textures, so materials of the
+        // same name binding the same files are interchangeable
+        let material_key = format!(
+            "{}|{}|{}|{anisotropy}",
+            m.name,
+            diffuse_path.as_deref().unwrap_or(""),
+            normal_path.as_deref().unwrap_or(""),
+        );
+        if let Some(material) = cache.and_then(|cache| cache.material(&material_key)) {
+            materials.push(material);
+            continue;
+        }
 
-        materials.push(model::Material {
-            name: m.name,
-            diffuse_texture,
-            bind_group,
-            normal_texture,
-        })
+        let diffuse_texture = match &diffuse_path {
+            Some(path) => load_texture_async(path, false, anisotropy, device, queue, cache).await?,
+            None => Arc::new(texture::Texture::from_color(
+                device, queue, [255; 4], &m.name, false,
+            )?),
+        };
+        let normal_texture = match &normal_path {
+            Some(path) => load_texture_async(path, true, anisotropy, device, queue, cache).await?,
+            None => Arc::new(flat_normal_texture(device, queue, &m.name)?),
+        };
+
+        let material =
+            model::Material::new(device, &m.name, diffuse_texture, normal_texture, layout);
+        materials.push(match cache {
+            Some(cache) => cache.insert_material(material_key, material),
+            None => Arc::new(material),
+        });
     }
 
     let meshes = models
@@ -104,84 +275,32 @@ pub async fn load_model(
                         m.mesh.positions[i * 3 + 1],
                         m.mesh.positions[i * 3 + 2],
                     ],
-                    tex_coords: [m.mesh.texcoords[i * 2], 1.0 - m.mesh.texcoords[i * 2 + 1]],
-                    normal: [
-                        m.mesh.normals[i * 3],
-                        m.mesh.normals[i * 3 + 1],
-                        m.mesh.normals[i * 3 + 2],
-                    ],
+                    tex_coords: m
+                        .mesh
+                        .texcoords
+                        .get(i * 2..i * 2 + 2)
+                        .map_or([0.0; 2], |uv| [uv[0], 1.0 - uv[1]]),
+                    normal: m
+                        .mesh
+                        .normals
+                        .get(i * 3..i * 3 + 3)
+                        .map_or([0.0; 3], |n| [n[0], n[1], n[2]]),
                     bitangent: [0.0; 3],
+                    joints: [0; 4],
+                    weights: [0.0; 4],
                     tangent: [0.0; 3],
+                    color: m
+                        .mesh
+                        .vertex_color
+                        .get(i * 3..i * 3 + 3)
+                        .map_or([1.0; 4], |c| [c[0], c[1], c[2], 1.0]),
                 })
                 .collect::<Vec<_>>();
 
-            let indices = &m.mesh.indices;
-            let mut triangles_included = vec![0; vertices.len()];
-
-            // Calculate tangents and bitangets. We're going to
-            // use the triangles, so we need to loop through the
-            // indices in chunks of 3
-            for c in indices.chunks(3) {
-                let v0 = vertices[c[0] as usize];
-                let v1 = vertices[c[1] as usize];
-                let v2 = vertices[c[2] as usize];
-
-                let pos0: cgmath::Vector3<_> = v0.position.into();
-                let pos1: cgmath::Vector3<_> = v1.position.into();
-                let pos2: cgmath::Vector3<_> = v2.position.into();
-
-                let uv0: cgmath::Vector2<_> = v0.tex_coords.into();
-                let uv1: cgmath::Vector2<_> = v1.tex_coords.into();
-                let uv2: cgmath::Vector2<_> = v2.tex_coords.into();
-
-                // Calculate the edges of the triangle
-                let delta_pos1 = pos1 - pos0;
-                let delta_pos2 = pos2 - pos0;
-
-                // This will give us a direction to calculate the
-                // tangent and bitangent
-                let delta_uv1 = uv1 - uv0;
-                let delta_uv2 = uv2 - uv0;
-
-                // Solving the following system of equations will
-                // give us the tangent and bitangent.
-                //     delta_pos1 = delta_uv1.x * T + delta_u.y * B
-                //     delta_pos2 = delta_uv2.x * T + delta_uv2.y * B
-                // Luckily, the place I found this equation provided
-                // the solution!
-                let r = 1.0 / (delta_uv1.x * delta_uv2.y - delta_uv1.y * delta_uv2.x);
-                let tangent = (delta_pos1 * delta_uv2.y - delta_pos2 * delta_uv1.y) * r;
-                // We flip the bitangent to enable right-handed normal
-                // maps with wgpu texture coordinate system
-                let bitangent = (delta_pos2 * delta_uv1.x - delta_pos1 * delta_uv2.x) * -r;
-
-                // We'll use the same tangent/bitangent for each vertex in the triangle
-                vertices[c[0] as usize].tangent =
-                    (tangent + cgmath::Vector3::from(vertices[c[0] as usize].tangent)).into();
-                vertices[c[1] as usize].tangent =
-                    (tangent + cgmath::Vector3::from(vertices[c[1] as usize].tangent)).into();
-                vertices[c[2] as usize].tangent =
-                    (tangent + cgmath::Vector3::from(vertices[c[2] as usize].tangent)).into();
-                vertices[c[0] as usize].bitangent =
-                    (bitangent + cgmath::Vector3::from(vertices[c[0] as usize].bitangent)).into();
-                vertices[c[1] as usize].bitangent =
-                    (bitangent + cgmath::Vector3::from(vertices[c[1] as usize].bitangent)).into();
-                vertices[c[2] as usize].bitangent =
-                    (bitangent + cgmath::Vector3::from(vertices[c[2] as usize].bitangent)).into();
-
-                // Used to average the tangents/bitangents
-                triangles_included[c[0] as usize] += 1;
-                triangles_included[c[1] as usize] += 1;
-                triangles_included[c[2] as usize] += 1;
-            }
-
-            // Average the tangents/bitangents
-            for (i, n) in triangles_included.into_iter().enumerate() {
-                let denom = 1.0 / n as f32;
-                let v = &mut vertices[i];
-                v.tangent = (cgmath::Vector3::from(v.tangent) * denom).into();
-                v.bitangent = (cgmath::Vector3::from(v.bitangent) * denom).into();
+            if m.mesh.normals.is_empty() {
+                compute_normals(&mut vertices, &m.mesh.indices);
             }
+            compute_tangents(&mut vertices, &m.mesh.indices);
 
             let vertex_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
                 label: Some(&format!("{:?} Vertex Buffer", filename)),
@@ -200,6 +319,7 @@ pub async fn load_model(
                 index_buffer,
                 num_elements: m.mesh.indices.len() as u32,
                 material: m.mesh.material_id.unwrap_or(0),
+                bounds: Aabb::from_points(vertices.iter().map(|v| v.position.into())),
             }
         })
         .collect::<Vec<_>>();
@@ -207,14 +327,632 @@ pub async fn load_model(
     Ok(model::Model { meshes, materials })
 }
 
+/// Texture samplers use `anisotropy` levels of anisotropic filtering, 1 to turn it off.
+/// Skinned meshes are loaded in their bind pose, see `load_gltf_animated` to animate them.
+pub async fn load_gltf(
+    filename: &str,
+    anisotropy: u16,
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    layout: &wgpu::BindGroupLayout,
+) -> anyhow::Result<model::Model> {
+    let (model, _) = import_gltf(filename, anisotropy, device, queue, layout)?;
+    Ok(model)
+}
+
+/// Like `load_gltf`, but also loads the skeleton of the first skin and every animation
+/// moving its joints. Fails if the file has no skin.
+pub async fn load_gltf_animated(
+    filename: &str,
+    anisotropy: u16,
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    layout: &wgpu::BindGroupLayout,
+) -> anyhow::Result<(model::Model, AnimationPlayer)> {
+    let (model, player) = import_gltf(filename, anisotropy, device, queue, layout)?;
+    let player = player.ok_or_else(|| anyhow::anyhow!("{filename} has no skin to animate"))?;
+    Ok((model, player))
+}
+
+fn import_gltf(
+    filename: &str,
+    anisotropy: u16,
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    layout: &wgpu::BindGroupLayout,
+) -> anyhow::Result<(model::Model, Option<AnimationPlayer>)> {
+    let (document, buffers, images) = gltf::import(filename)?;
+
+    let mut materials = Vec::new();
+    for m in document.materials() {
+        let name = m.name().unwrap_or(filename);
+        let pbr = m.pbr_metallic_roughness();
+        let diffuse_texture = match pbr.base_color_texture() {
+            Some(info) => {
+                let img = gltf_image_to_rgba(&images[info.texture().source().index()])?;
+                texture::Texture::from_image_mipmapped(
+                    device,
+                    queue,
+                    &img,
+                    Some(name),
+                    false,
+                    anisotropy,
+                )?
+            }
+            None => {
+                // Without a texture the base color factor is the whole color
+                let [r, g, b, a] = pbr.base_color_factor().map(|c| (c * 255.0).round() as u8);
+                texture::Texture::from_color(device, queue, [r, g, b, a], name, false)?
+            }
+        };
+        let normal_texture = match m.normal_texture() {
+            Some(info) => {
+                let img = gltf_image_to_rgba(&images[info.texture().source().index()])?;
+                texture::Texture::from_image_mipmapped(
+                    device,
+                    queue,
+                    &img,
+                    Some(name),
+                    true,
+                    anisotropy,
+                )?
+            }
+            None => flat_normal_texture(device, queue, name)?,
+        };
+        materials.push(Arc::new(model::Material::new(
+            device,
+            name,
+            diffuse_texture,
+            normal_texture,
+            layout,
+        )));
+    }
+
+    // Primitives without a material use a plain white one appended at the end
+    let default_material = materials.len();
+    let mut needs_default_material = false;
+
+    let mut meshes = Vec::new();
+    let scene = document
+        .default_scene()
+        .or_else(|| document.scenes().next())
+        .ok_or_else(|| anyhow::anyhow!("{filename} has no scenes"))?;
+    let mut nodes = scene
+        .nodes()
+        .map(|node| (node, cgmath::Matrix4::<f32>::identity()))
+        .collect::<Vec<_>>();
+    // Model space transform and parent of every node, to place the skeleton's roots
+    let mut node_transforms = std::collections::HashMap::new();
+    let mut node_parents = std::collections::HashMap::new();
+    while let Some((node, parent_transform)) = nodes.pop() {
+        let transform = parent_transform * cgmath::Matrix4::from(node.transform().matrix());
+        node_transforms.insert(node.index(), transform);
+        for child in node.children() {
+            node_parents.insert(child.index(), node.index());
+            nodes.push((child, transform));
+        }
+
+        let Some(mesh) = node.mesh() else {
+            continue;
+        };
+        // Skinned meshes are placed by their joints alone, glTF ignores their node's transform
+        let skinned = node.skin().is_some();
+        let transform = if skinned {
+            cgmath::Matrix4::identity()
+        } else {
+            transform
+        };
+        // Model has no transform of its own, so the node hierarchy is baked into the vertices
+        let normal_matrix = cgmath::Matrix3::new(
+            transform.x.x,
+            transform.x.y,
+            transform.x.z,
+            transform.y.x,
+            transform.y.y,
+            transform.y.z,
+            transform.z.x,
+            transform.z.y,
+            transform.z.z,
+        )
+        .invert()
+        .map(|m| m.transpose())
+        .unwrap_or_else(cgmath::Matrix3::identity);
+
+        for primitive in mesh.primitives() {
+            if primitive.mode() != gltf::mesh::Mode::Triangles {
+                log::warn!(
+                    "{filename}: skipping {:?} primitive, only triangles are supported",
+                    primitive.mode()
+                );
+                continue;
+            }
+
+            let reader = primitive.reader(|buffer| Some(&buffers[buffer.index()]));
+            let Some(positions) = reader.read_positions() else {
+                continue;
+            };
+            let mut vertices = positions
+                .map(|position| model::ModelVertex {
+                    position,
+                    tex_coords: [0.0; 2],
+                    normal: [0.0, 1.0, 0.0],
+                    tangent: [0.0; 3],
+                    bitangent: [0.0; 3],
+                    joints: [0; 4],
+                    weights: [0.0; 4],
+                    color: [1.0; 4],
+                })
+                .collect::<Vec<_>>();
+            if let Some(normals) = reader.read_normals() {
+                for (v, normal) in vertices.iter_mut().zip(normals) {
+                    v.normal = normal;
+                }
+            }
+            if let Some(colors) = reader.read_colors(0) {
+                for (v, color) in vertices.iter_mut().zip(colors.into_rgba_f32()) {
+                    v.color = color;
+                }
+            }
+            if let Some(tex_coords) = reader.read_tex_coords(0) {
+                for (v, tex_coords) in vertices.iter_mut().zip(tex_coords.into_f32()) {
+                    v.tex_coords = tex_coords;
+                }
+            }
+            if skinned
+                && let (Some(joints), Some(weights)) =
+                    (reader.read_joints(0), reader.read_weights(0))
+            {
+                for (v, (joints, weights)) in vertices
+                    .iter_mut()
+                    .zip(joints.into_u16().zip(weights.into_f32()))
+                {
+                    v.joints = joints.map(u32::from);
+                    v.weights = weights;
+                }
+            }
+
+            let indices = match reader.read_indices() {
+                Some(indices) => indices.into_u32().collect::<Vec<_>>(),
+                None => (0..vertices.len() as u32).collect(),
+            };
+
+            match reader.read_tangents() {
+                Some(tangents) => {
+                    for (v, [x, y, z, w]) in vertices.iter_mut().zip(tangents) {
+                        let tangent = cgmath::Vector3::new(x, y, z);
+                        v.tangent = tangent.into();
+                        v.bitangent = handed_bitangent(v.normal.into(), tangent, w).into();
+                    }
+                }
+                None => compute_tangents(&mut vertices, &indices),
+            }
+
+            for v in &mut vertices {
+                let position = transform * cgmath::Point3::from(v.position).to_homogeneous();
+                v.position = (position.truncate() / position.w).into();
+                v.normal = (normal_matrix * cgmath::Vector3::from(v.normal))
+                    .normalize()
+                    .into();
+                v.tangent = (transform * cgmath::Vector3::from(v.tangent).extend(0.0))
+                    .truncate()
+                    .into();
+                v.bitangent = (transform * cgmath::Vector3::from(v.bitangent).extend(0.0))
+                    .truncate()
+                    .into();
+            }
+
+            let name = mesh.name().unwrap_or(filename);
+            let vertex_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: Some(&format!("{:?} Vertex Buffer", name)),
+                contents: bytemuck::cast_slice(&vertices),
+                usage: wgpu::BufferUsages::VERTEX,
+            });
+            let index_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: Some(&format!("{:?} Index Buffer", name)),
+                contents: bytemuck::cast_slice(&indices),
+                usage: wgpu::BufferUsages::INDEX,
+            });
+
+            let material = primitive.material().index().unwrap_or_else(|| {
+                needs_default_material = true;
+                default_material
+            });
+            meshes.push(model::Mesh {
+                name: name.to_string(),
+                vertex_buffer,
+                index_buffer,
+                num_elements: indices.len() as u32,
+                material,
+                bounds: Aabb::from_points(vertices.iter().map(|v| v.position.into())),
+            });
+        }
+    }
+
+    if needs_default_material {
+        materials.push(Arc::new(white_material(device, queue, "default", layout)?));
+    }
+
+    if document.skins().len() > 1 {
+        log::warn!(
+            "{filename}: only the first of {} skins is animated",
+            document.skins().len()
+        );
+    }
+    let player = document
+        .skins()
+        .next()
+        .map(|skin| load_gltf_skeleton(&document, &skin, &buffers, &node_transforms, &node_parents))
+        .transpose()?;
+
+    Ok((model::Model { meshes, materials }, player))
+}
+
+/// The skeleton of `skin` and the animations of its joints. Channels animating other nodes
+/// or morph targets are skipped, and cubic spline keyframes are interpolated linearly.
+fn load_gltf_skeleton(
+    document: &gltf::Document,
+    skin: &gltf::Skin,
+    buffers: &[gltf::buffer::Data],
+    node_transforms: &std::collections::HashMap<usize, cgmath::Matrix4<f32>>,
+    node_parents: &std::collections::HashMap<usize, usize>,
+) -> anyhow::Result<AnimationPlayer> {
+    let joint_of_node = skin
+        .joints()
+        .enumerate()
+        .map(|(joint, node)| (node.index(), joint))
+        .collect::<std::collections::HashMap<_, _>>();
+    let inverse_binds = skin
+        .reader(|buffer| Some(&buffers[buffer.index()]))
+        .read_inverse_bind_matrices()
+        .map(|matrices| matrices.map(cgmath::Matrix4::from).collect::<Vec<_>>())
+        .unwrap_or_default();
+
+    let joints = skin
+        .joints()
+        .enumerate()
+        .map(|(i, node)| {
+            let parent_node = node_parents.get(&node.index()).copied();
+            let parent = parent_node.and_then(|p| joint_of_node.get(&p).copied());
+            let parent_transform = match (parent, parent_node) {
+                (None, Some(p)) => node_transforms
+                    .get(&p)
+                    .copied()
+                    .unwrap_or_else(cgmath::Matrix4::identity),
+                _ => cgmath::Matrix4::identity(),
+            };
+            let (translation, [x, y, z, w], scale) = node.transform().decomposed();
+            Joint {
+                name: node.name().unwrap_or_default().to_string(),
+                parent,
+                parent_transform,
+                rest: JointPose {
+                    translation: translation.into(),
+                    rotation: cgmath::Quaternion::new(w, x, y, z),
+                    scale: scale.into(),
+                },
+                inverse_bind: inverse_binds
+                    .get(i)
+                    .copied()
+                    .unwrap_or_else(cgmath::Matrix4::identity),
+            }
+        })
+        .collect();
+    let skeleton = Skeleton::new(joints)?;
+
+    // Cubic spline outputs hold an in-tangent, value and out-tangent per keyframe
+    fn keyframe_values<T>(values: impl Iterator<Item = T>, cubic: bool) -> Vec<T> {
+        if cubic {
+            values.skip(1).step_by(3).collect()
+        } else {
+            values.collect()
+        }
+    }
+
+    let clips = document
+        .animations()
+        .enumerate()
+        .map(|(i, animation)| {
+            let channels = animation
+                .channels()
+                .filter_map(|channel| {
+                    let joint = *joint_of_node.get(&channel.target().node().index())?;
+                    let reader = channel.reader(|buffer| Some(&buffers[buffer.index()]));
+                    let times = reader.read_inputs()?.collect::<Vec<_>>();
+                    let interpolation = channel.sampler().interpolation();
+                    let cubic = interpolation == gltf::animation::Interpolation::CubicSpline;
+                    let keyframes = match reader.read_outputs()? {
+                        gltf::animation::util::ReadOutputs::Translations(values) => {
+                            Keyframes::Translation(keyframe_values(values.map(Into::into), cubic))
+                        }
+                        gltf::animation::util::ReadOutputs::Rotations(values) => {
+                            Keyframes::Rotation(keyframe_values(
+                                values
+                                    .into_f32()
+                                    .map(|[x, y, z, w]| cgmath::Quaternion::new(w, x, y, z)),
+                                cubic,
+                            ))
+                        }
+                        gltf::animation::util::ReadOutputs::Scales(values) => {
+                            Keyframes::Scale(keyframe_values(values.map(Into::into), cubic))
+                        }
+                        gltf::animation::util::ReadOutputs::MorphTargetWeights(_) => return None,
+                    };
+                    if keyframes.len() != times.len() {
+                        log::warn!("skipping animation channel with mismatched keyframes");
+                        return None;
+                    }
+                    Some(Channel {
+                        joint,
+                        times,
+                        keyframes,
+                        interpolation: match interpolation {
+                            gltf::animation::Interpolation::Step => Interpolation::Step,
+                            _ => Interpolation::Linear,
+                        },
+                    })
+                })
+                .collect();
+            let name = animation
+                .name()
+                .map(str::to_string)
+                .unwrap_or_else(|| format!("animation {i}"));
+            AnimationClip::new(name, channels)
+        })
+        .collect();
+
+    Ok(AnimationPlayer::new(skeleton, clips))
+}
+
+/// A plain white material with an unperturbed normal, for meshes without their own.
+pub fn white_material(
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    name: &str,
+    layout: &wgpu::BindGroupLayout,
+) -> anyhow::Result<model::Material> {
+    let diffuse_texture = texture::Texture::from_color(device, queue, [255; 4], name, false)?;
+    let normal_texture = flat_normal_texture(device, queue, name)?;
+    Ok(model::Material::new(
+        device,
+        name,
+        diffuse_texture,
+        normal_texture,
+        layout,
+    ))
+}
+
+fn flat_normal_texture(
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    label: &str,
+) -> anyhow::Result<texture::Texture> {
+    // Tangent space +Z, i.e. the unperturbed surface normal
+    texture::Texture::from_color(device, queue, [128, 128, 255, 255], label, true)
+}
+
+/// Expands any of the pixel formats glTF images decode to into 8-bit RGBA.
+fn gltf_image_to_rgba(data: &gltf::image::Data) -> anyhow::Result<image::DynamicImage> {
+    use gltf::image::Format;
+
+    let (channels, bytes_per_channel) = match data.format {
+        Format::R8 => (1, 1),
+        Format::R8G8 => (2, 1),
+        Format::R8G8B8 => (3, 1),
+        Format::R8G8B8A8 => (4, 1),
+        Format::R16 => (1, 2),
+        Format::R16G16 => (2, 2),
+        Format::R16G16B16 => (3, 2),
+        Format::R16G16B16A16 => (4, 2),
+        Format::R32G32B32FLOAT => (3, 4),
+        Format::R32G32B32A32FLOAT => (4, 4),
+    };
+    let float = matches!(
+        data.format,
+        Format::R32G32B32FLOAT | Format::R32G32B32A32FLOAT
+    );
+    let channel = |texel: &[u8], c: usize| -> u8 {
+        let bytes = &texel[c * bytes_per_channel..(c + 1) * bytes_per_channel];
+        match bytes_per_channel {
+            1 => bytes[0],
+            // 16-bit channels are little endian, keep the most significant byte
+            2 => bytes[1],
+            _ if float => {
+                let value = f32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+                (value.clamp(0.0, 1.0) * 255.0).round() as u8
+            }
+            _ => unreachable!(),
+        }
+    };
+
+    let rgba = data
+        .pixels
+        .chunks_exact(channels * bytes_per_channel)
+        .flat_map(|texel| match channels {
+            1 => {
+                let r = channel(texel, 0);
+                [r, r, r, 255]
+            }
+            2 => [channel(texel, 0), channel(texel, 1), 0, 255],
+            3 => [channel(texel, 0), channel(texel, 1), channel(texel, 2), 255],
+            _ => [
+                channel(texel, 0),
+                channel(texel, 1),
+                channel(texel, 2),
+                channel(texel, 3),
+            ],
+        })
+        .collect::<Vec<_>>();
+
+    let img = image::RgbaImage::from_raw(data.width, data.height, rgba)
+        .ok_or_else(|| anyhow::anyhow!("glTF image data does not match its dimensions"))?;
+    Ok(image::DynamicImage::ImageRgba8(img))
+}
+
+/// Computes per-vertex tangents and bitangents from the triangle UVs, averaging
+/// the contributions of every triangle a vertex belongs to.
+pub(crate) fn compute_tangents(vertices: &mut [model::ModelVertex], indices: &[u32]) {
+    let mut triangles_included = vec![0; vertices.len()];
+
+    // Calculate tangents and bitangets. We're going to
+    // use the triangles, so we need to loop through the
+    // indices in chunks of 3
+    for c in indices.chunks(3) {
+        let v0 = vertices[c[0] as usize];
+        let v1 = vertices[c[1] as usize];
+        let v2 = vertices[c[2] as usize];
+
+        let pos0: cgmath::Vector3<_> = v0.position.into();
+        let pos1: cgmath::Vector3<_> = v1.position.into();
+        let pos2: cgmath::Vector3<_> = v2.position.into();
+
+        let uv0: cgmath::Vector2<_> = v0.tex_coords.into();
+        let uv1: cgmath::Vector2<_> = v1.tex_coords.into();
+        let uv2: cgmath::Vector2<_> = v2.tex_coords.into();
+
+        // Calculate the edges of the triangle
+        let delta_pos1 = pos1 - pos0;
+        let delta_pos2 = pos2 - pos0;
+
+        // This will give us a direction to calculate the
+        // tangent and bitangent
+        let delta_uv1 = uv1 - uv0;
+        let delta_uv2 = uv2 - uv0;
+
+        // Solving the following system of equations will
+        // give us the tangent and bitangent.
+        //     delta_pos1 = delta_uv1.x * T + delta_u.y * B
+        //     delta_pos2 = delta_uv2.x * T + delta_uv2.y * B
+        // Luckily, the place I found this equation provided
+        // the solution!
+        let det = delta_uv1.x * delta_uv2.y - delta_uv1.y * delta_uv2.x;
+        // Triangles without a UV area (e.g. no texture coordinates at all) have no
+        // defined tangent, leave them out of the average
+        if det.abs() < f32::EPSILON {
+            continue;
+        }
+        let r = 1.0 / det;
+        let tangent = (delta_pos1 * delta_uv2.y - delta_pos2 * delta_uv1.y) * r;
+        // We flip the bitangent to enable right-handed normal
+        // maps with wgpu texture coordinate system
+        let bitangent = (delta_pos2 * delta_uv1.x - delta_pos1 * delta_uv2.x) * -r;
+
+        // We'll use the same tangent/bitangent for each vertex in the triangle
+        vertices[c[0] as usize].tangent =
+            (tangent + cgmath::Vector3::from(vertices[c[0] as usize].tangent)).into();
+        vertices[c[1] as usize].tangent =
+            (tangent + cgmath::Vector3::from(vertices[c[1] as usize].tangent)).into();
+        vertices[c[2] as usize].tangent =
+            (tangent + cgmath::Vector3::from(vertices[c[2] as usize].tangent)).into();
+        vertices[c[0] as usize].bitangent =
+            (bitangent + cgmath::Vector3::from(vertices[c[0] as usize].bitangent)).into();
+        vertices[c[1] as usize].bitangent =
+            (bitangent + cgmath::Vector3::from(vertices[c[1] as usize].bitangent)).into();
+        vertices[c[2] as usize].bitangent =
+            (bitangent + cgmath::Vector3::from(vertices[c[2] as usize].bitangent)).into();
+
+        // Used to average the tangents/bitangents
+        triangles_included[c[0] as usize] += 1;
+        triangles_included[c[1] as usize] += 1;
+        triangles_included[c[2] as usize] += 1;
+    }
+
+    // Average the tangents/bitangents
+    for (i, n) in triangles_included.into_iter().enumerate() {
+        let v = &mut vertices[i];
+        if n == 0 {
+            // Any frame around the normal will do when the UVs don't define one, a flat
+            // normal map sample points along the normal either way
+            let normal = cgmath::Vector3::from(v.normal);
+            let helper = if normal.x.abs() < 0.9 {
+                cgmath::Vector3::unit_x()
+            } else {
+                cgmath::Vector3::unit_y()
+            };
+            let tangent = helper.cross(normal).normalize();
+            v.tangent = tangent.into();
+            v.bitangent = normal.cross(tangent).into();
+            continue;
+        }
+        let normal = cgmath::Vector3::from(v.normal);
+        let tangent = cgmath::Vector3::from(v.tangent) / n as f32;
+        // Keep only the handedness of the summed bitangent, the same `w` a glTF tangent
+        // carries, so both loaders build the frame the same way
+        let w = if normal.cross(tangent).dot(v.bitangent.into()) < 0.0 {
+            -1.0
+        } else {
+            1.0
+        };
+        v.tangent = tangent.into();
+        v.bitangent = handed_bitangent(normal, tangent, w).into();
+    }
+}
+
+/// The bitangent of a tangent frame, `w` is the handedness sign (+1 or -1) stored in
+/// a glTF tangent's fourth component.
+fn handed_bitangent(
+    normal: cgmath::Vector3<f32>,
+    tangent: cgmath::Vector3<f32>,
+    w: f32,
+) -> cgmath::Vector3<f32> {
+    normal.cross(tangent) * w.signum()
+}
+
+/// Area weighted vertex normals for meshes that don't come with any.
+fn compute_normals(vertices: &mut [model::ModelVertex], indices: &[u32]) {
+    let mut normals = vec![cgmath::Vector3::new(0.0, 0.0, 0.0); vertices.len()];
+    for c in indices.chunks(3) {
+        let pos0: cgmath::Vector3<f32> = vertices[c[0] as usize].position.into();
+        let pos1: cgmath::Vector3<f32> = vertices[c[1] as usize].position.into();
+        let pos2: cgmath::Vector3<f32> = vertices[c[2] as usize].position.into();
+        // The cross product's length is twice the triangle's area
+        let face_normal = (pos1 - pos0).cross(pos2 - pos0);
+        for &i in c {
+            normals[i as usize] += face_normal;
+        }
+    }
+    for (v, normal) in vertices.iter_mut().zip(normals) {
+        v.normal = if normal.magnitude2() > 0.0 {
+            normal.normalize().into()
+        } else {
+            [0.0, 1.0, 0.0]
+        };
+    }
+}
+
 pub struct EquirectToCubemapConverter {
     texture_format: wgpu::TextureFormat,
     equirect_layout: wgpu::BindGroupLayout,
     equirect_to_cubemap: wgpu::ComputePipeline,
+    workgroup_size: u32,
 }
 
 impl EquirectToCubemapConverter {
+    /// Side of the square workgroups `new` uses, 256 invocations each.
+    pub const DEFAULT_WORKGROUP_SIZE: u32 = 16;
+
     pub fn new(device: &wgpu::Device) -> Self {
+        Self::with_workgroup_size(device, Self::DEFAULT_WORKGROUP_SIZE)
+            .expect("the default workgroup size is within the limits WebGPU guarantees")
+    }
+
+    /// A converter running `workgroup_size` x `workgroup_size` invocations per workgroup,
+    /// passed to the shader as an override constant. Which size is fastest depends on the
+    /// GPU, see `benchmark`. Fails if the device's limits don't allow workgroups that large.
+    pub fn with_workgroup_size(device: &wgpu::Device, workgroup_size: u32) -> anyhow::Result<Self> {
+        let limits = device.limits();
+        if workgroup_size == 0
+            || workgroup_size > limits.max_compute_workgroup_size_x
+            || workgroup_size > limits.max_compute_workgroup_size_y
+            || workgroup_size * workgroup_size > limits.max_compute_invocations_per_workgroup
+        {
+            anyhow::bail!(
+                "Workgroups of {workgroup_size}x{workgroup_size} exceed the device's limits of \
+                 {}x{} and {} invocations",
+                limits.max_compute_workgroup_size_x,
+                limits.max_compute_workgroup_size_y,
+                limits.max_compute_invocations_per_workgroup
+            );
+        }
         let texture_format = wgpu::TextureFormat::Rgba32Float;
 
         let equirect_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
@@ -225,7 +963,8 @@ impl EquirectToCubemapConverter {
                     ty: wgpu::BindingType::Texture {
                         multisampled: false,
                         view_dimension: wgpu::TextureViewDimension::D2,
-                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                        // Only read with textureLoad, which also allows Rgba32Float sources
+                        sample_type: wgpu::TextureSampleType::Float { filterable: false },
                     },
                     count: None,
                 },
@@ -243,10 +982,7 @@ impl EquirectToCubemapConverter {
             label: Some("equirect_to_cubemap_bind_group_layout"),
         });
 
-        let shader_module = device.create_shader_module(wgpu::ShaderModuleDescriptor {
-            label: Some("Equirectangular To Cubemap Shader"),
-            source: wgpu::ShaderSource::Wgsl(include_str!("../equirectangular.wgsl").into()),
-        });
+        let shader_module = device.create_shader_module(wgsl!("equirectangular.wgsl"));
 
         let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
             label: Some("Equirect To Cubemap Pipeline Layout"),
@@ -254,21 +990,65 @@ impl EquirectToCubemapConverter {
             push_constant_ranges: &[],
         });
 
+        let constants = [("workgroup_size", workgroup_size as f64)];
         let equirect_to_cubemap =
             device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
                 label: Some("Equirect To Cubemap Pipeline"),
                 layout: Some(&pipeline_layout),
                 module: &shader_module,
                 entry_point: Some("compute_equirect_to_cubemap"),
-                compilation_options: Default::default(),
+                compilation_options: wgpu::PipelineCompilationOptions {
+                    constants: &constants,
+                    ..Default::default()
+                },
                 cache: None,
             });
 
-        Self {
+        Ok(Self {
             texture_format,
             equirect_layout,
             equirect_to_cubemap,
+            workgroup_size,
+        })
+    }
+
+    pub fn workgroup_size(&self) -> u32 {
+        self.workgroup_size
+    }
+
+    /// Converts `data`, an image as for `from_equirectangular_bytes`, into a cubemap of
+    /// `dst_size` with each of `workgroup_sizes` and returns how long it took, averaged over
+    /// `runs` conversions after a warm-up one. Sizes the device doesn't support are left
+    /// out. The times are measured on the CPU, waiting for the device after every
+    /// conversion, so they include the submission overhead.
+    pub fn benchmark(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        data: &[u8],
+        dst_size: u32,
+        workgroup_sizes: &[u32],
+        runs: u32,
+    ) -> anyhow::Result<Vec<(u32, std::time::Duration)>> {
+        let source = texture::Texture::from_bytes(device, queue, data, "benchmark source", false)?;
+        let mut results = Vec::new();
+        for &workgroup_size in workgroup_sizes {
+            let converter = match Self::with_workgroup_size(device, workgroup_size) {
+                Ok(converter) => converter,
+                Err(e) => {
+                    log::warn!("Skipping workgroup size {workgroup_size}: {e}");
+                    continue;
+                }
+            };
+            converter.convert(device, queue, &source, dst_size, "benchmark cubemap")?;
+            device.poll(wgpu::PollType::Wait)?;
+            let start = Instant::now();
+            for _ in 0..runs.max(1) {
+                converter.convert(device, queue, &source, dst_size, "benchmark cubemap")?;
+                device.poll(wgpu::PollType::Wait)?;
+            }
+            results.push((workgroup_size, start.elapsed() / runs.max(1)));
         }
+        Ok(results)
     }
 
     pub fn from_equirectangular_bytes(
@@ -280,10 +1060,85 @@ impl EquirectToCubemapConverter {
         label: &str,
     ) -> anyhow::Result<texture::CubeTexture> {
         let texture = texture::Texture::from_bytes(device, queue, data, label, false)?;
+        self.convert(device, queue, &texture, dst_size, label)
+    }
+
+    /// Like `from_equirectangular_bytes`, but takes linear RGBA floats such as those from
+    /// `load_hdr`. They are uploaded as they are, so values above 1 survive into the cubemap.
+    #[allow(clippy::too_many_arguments)]
+    pub fn from_equirectangular_hdr(
+        &self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        data: &[f32],
+        width: u32,
+        height: u32,
+        dst_size: u32,
+        label: &str,
+    ) -> anyhow::Result<texture::CubeTexture> {
+        let expected = 4 * width as usize * height as usize;
+        if data.len() != expected {
+            anyhow::bail!(
+                "{label} has {} floats, expected {expected} for {width}x{height} RGBA",
+                data.len()
+            );
+        }
+
+        let size = wgpu::Extent3d {
+            width,
+            height,
+            depth_or_array_layers: 1,
+        };
+        let texture = texture::Texture::create_texture(
+            device,
+            Some(label),
+            size,
+            wgpu::TextureFormat::Rgba32Float,
+            wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+            wgpu::TextureDimension::D2,
+            wgpu::FilterMode::Nearest,
+        );
+        queue.write_texture(
+            wgpu::TexelCopyTextureInfo {
+                aspect: wgpu::TextureAspect::All,
+                texture: &texture.texture,
+                mip_level: 0,
+                origin: wgpu::Origin3d::ZERO,
+            },
+            bytemuck::cast_slice(data),
+            wgpu::TexelCopyBufferLayout {
+                offset: 0,
+                bytes_per_row: Some(16 * width),
+                rows_per_image: Some(height),
+            },
+            size,
+        );
+        self.convert(device, queue, &texture, dst_size, label)
+    }
+
+    fn convert(
+        &self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        texture: &texture::Texture,
+        dst_size: u32,
+        label: &str,
+    ) -> anyhow::Result<texture::CubeTexture> {
+        // Enough workgroups to cover every texel, the shader skips those past the edge
+        let num_workgroups = dst_size.div_ceil(self.workgroup_size);
+        let max_workgroups = device.limits().max_compute_workgroups_per_dimension;
+        if dst_size == 0 || num_workgroups > max_workgroups {
+            anyhow::bail!(
+                "{label}: cubemap faces of {dst_size} texels need {num_workgroups} workgroups of \
+                 {} per side, the device dispatches 1 to {max_workgroups}",
+                self.workgroup_size
+            );
+        }
+        debug_assert!(num_workgroups * self.workgroup_size >= dst_size);
+
         let dst = texture::CubeTexture::create_2d(
             device,
             dst_size,
-            dst_size,
             self.texture_format,
             1,
             wgpu::TextureUsages::STORAGE_BINDING
@@ -320,7 +1175,6 @@ impl EquirectToCubemapConverter {
             timestamp_writes: None,
         });
 
-        let num_workgroups = dst_size.div_ceil(16);
         pass.set_pipeline(&self.equirect_to_cubemap);
         pass.set_bind_group(0, &bind_group, &[]);
         pass.dispatch_workgroups(num_workgroups, num_workgroups, 6);
@@ -0,0 +1,514 @@
+use cgmath::{EuclideanSpace, InnerSpace, Matrix4, Point3, SquareMatrix, Vector3, Vector4};
+use wgpu::util::DeviceExt as _;
+
+use crate::{
+    camera::{Camera, OPENGL_TO_WGPU_MATRIX, Projection},
+    hot_reload::wgsl,
+    render_pipeline::{PipelineDesc, create_render_pipeline},
+    texture,
+};
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct ShadowUniform {
+    /// Light matrix of each cascade, the first `cascade_count` are used
+    pub light_view_proj: [[[f32; 4]; 4]; ShadowMap::MAX_CASCADES],
+    /// View depth each cascade reaches to, nearest first
+    pub splits: [f32; ShadowMap::MAX_CASCADES],
+    /// Constant offset subtracted from the fragment depth before comparing
+    pub depth_bias: f32,
+    /// How far to push the lookup position along the surface normal, in world units
+    pub normal_bias: f32,
+    pub cascade_count: u32,
+    /// Nonzero tints the lit fragments by the cascade they are shadowed from
+    pub debug_cascades: u32,
+    /// Comparisons per side of the square PCF kernel, see `ShadowMap::set_pcf_samples`
+    pub pcf_samples: u32,
+    /// Width of a shadow map texel in UV units, the spacing of the PCF comparisons
+    pub texel_size: f32,
+    _padding: [u32; 2],
+}
+
+impl Default for ShadowUniform {
+    fn default() -> Self {
+        Self {
+            light_view_proj: [Matrix4::identity().into(); ShadowMap::MAX_CASCADES],
+            splits: [0.0; ShadowMap::MAX_CASCADES],
+            depth_bias: 0.002,
+            normal_bias: 0.05,
+            cascade_count: ShadowMap::MAX_CASCADES as u32,
+            debug_cascades: 0,
+            pcf_samples: 1,
+            texel_size: 1.0 / 2048.0,
+            _padding: [0; 2],
+        }
+    }
+}
+
+/// One layer of the cascaded shadow map and the light matrix it is rendered with.
+struct Cascade {
+    view: wgpu::TextureView,
+    buffer: wgpu::Buffer,
+    /// Bound by the shadow pass itself; holds the light matrix for the vertex stage
+    bind_group: wgpu::BindGroup,
+}
+
+/// Cascaded depth maps rendered from the directional light's point of view. The view
+/// frustum is split into `cascade_count` depth ranges, each covered by its own layer of
+/// `texture`, so nearby shadows get as much detail as distant ones across the terrain.
+pub struct ShadowMap {
+    pub uniform: ShadowUniform,
+    pub light_view_proj: [Matrix4<f32>; Self::MAX_CASCADES],
+    /// Blend between evenly spaced splits at 0 and logarithmic ones at 1, which give the
+    /// near cascades more of the resolution
+    pub split_lambda: f32,
+    /// View depth beyond which nothing is shadowed, if the camera's far plane is further
+    pub max_distance: f32,
+    /// Width and height of each cascade's layer in texels, see `set_resolution`
+    size: u32,
+    /// A `D2Array` depth texture with a layer per cascade
+    pub texture: texture::Texture,
+    cascades: Vec<Cascade>,
+    pub buffer: wgpu::Buffer,
+    /// The pass's cascade group, then the skinning group
+    pipeline_layout: wgpu::PipelineLayout,
+    pub render_pipeline: wgpu::RenderPipeline,
+    /// Bound by the main pass; holds the shadow texture, comparison sampler and light matrices
+    pub bind_group: wgpu::BindGroup,
+    pub bind_group_layout: wgpu::BindGroupLayout,
+}
+
+impl ShadowMap {
+    pub const DEPTH_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Depth32Float;
+    pub const MAX_CASCADES: usize = 4;
+    /// Largest PCF kernel, 7x7 comparisons of 2x2 texels each
+    pub const MAX_PCF_SAMPLES: u32 = 7;
+    /// Most bytes the layers of every cascade may take up together, enough for 4096 x 4096
+    /// texels per cascade
+    pub const MEMORY_BUDGET: u64 = 256 << 20;
+    /// How far towards the light each cascade reaches past the part of the view it covers,
+    /// to catch the casters outside the view
+    const CASTER_DISTANCE: f32 = 50.0;
+
+    /// `skin_bind_group_layout` is group 1 of the shadow pipeline, see `SkinUniform`.
+    pub fn new(
+        device: &wgpu::Device,
+        size: u32,
+        vertex_layouts: &[wgpu::VertexBufferLayout],
+        skin_bind_group_layout: &wgpu::BindGroupLayout,
+    ) -> Self {
+        let uniform = ShadowUniform {
+            texel_size: 1.0 / size as f32,
+            ..Default::default()
+        };
+        let buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Shadow Buffer"),
+            contents: bytemuck::cast_slice(&[uniform]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+
+        let texture = Self::create_shadow_texture(device, size);
+
+        let pass_bind_group_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                entries: &[wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::VERTEX,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                }],
+                label: Some("shadow_pass_bind_group_layout"),
+            });
+
+        let cascades = (0..Self::MAX_CASCADES as u32)
+            .map(|layer| {
+                let view = Self::create_cascade_view(&texture, layer);
+                let buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                    label: Some("Shadow Cascade Buffer"),
+                    contents: bytemuck::cast_slice(&uniform.light_view_proj[layer as usize]),
+                    usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+                });
+                let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+                    layout: &pass_bind_group_layout,
+                    entries: &[wgpu::BindGroupEntry {
+                        binding: 0,
+                        resource: buffer.as_entire_binding(),
+                    }],
+                    label: Some("shadow_pass_bind_group"),
+                });
+                Cascade {
+                    view,
+                    buffer,
+                    bind_group,
+                }
+            })
+            .collect();
+
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Depth,
+                        view_dimension: wgpu::TextureViewDimension::D2Array,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Comparison),
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::VERTEX_FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ],
+            label: Some("shadow_bind_group_layout"),
+        });
+
+        let bind_group = Self::create_bind_group(device, &bind_group_layout, &texture, &buffer);
+
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Shadow Pipeline Layout"),
+            bind_group_layouts: &[&pass_bind_group_layout, skin_bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let render_pipeline = Self::create_pipeline(device, &pipeline_layout, vertex_layouts);
+
+        Self {
+            uniform,
+            light_view_proj: [Matrix4::identity(); Self::MAX_CASCADES],
+            split_lambda: 0.75,
+            max_distance: 100.0,
+            size,
+            texture,
+            cascades,
+            buffer,
+            pipeline_layout,
+            render_pipeline,
+            bind_group,
+            bind_group_layout,
+        }
+    }
+
+    fn create_pipeline(
+        device: &wgpu::Device,
+        layout: &wgpu::PipelineLayout,
+        vertex_layouts: &[wgpu::VertexBufferLayout],
+    ) -> wgpu::RenderPipeline {
+        create_render_pipeline(
+            device,
+            layout,
+            &[],
+            Some(Self::DEPTH_FORMAT),
+            vertex_layouts,
+            wgsl!("src/shadow.wgsl"),
+            PipelineDesc {
+                // The light's projection keeps the standard depth range, even with reverse-Z
+                depth_compare: wgpu::CompareFunction::LessEqual,
+                ..Default::default()
+            },
+        )
+    }
+
+    /// Rebuilds the shadow pipeline from the current shader, see `hot_reload` and `new`.
+    pub fn recreate_pipeline(
+        &mut self,
+        device: &wgpu::Device,
+        vertex_layouts: &[wgpu::VertexBufferLayout],
+    ) {
+        self.render_pipeline = Self::create_pipeline(device, &self.pipeline_layout, vertex_layouts);
+    }
+
+    fn create_shadow_texture(device: &wgpu::Device, size: u32) -> texture::Texture {
+        let size = wgpu::Extent3d {
+            width: size,
+            height: size,
+            depth_or_array_layers: Self::MAX_CASCADES as u32,
+        };
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("shadow_texture"),
+            size,
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: Self::DEPTH_FORMAT,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+            view_formats: &[],
+        });
+        let view = texture.create_view(&wgpu::TextureViewDescriptor {
+            dimension: Some(wgpu::TextureViewDimension::D2Array),
+            ..Default::default()
+        });
+        // Linear filtering on a comparison sampler gives us 2x2 PCF for free
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("shadow_sampler"),
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Nearest,
+            compare: Some(wgpu::CompareFunction::LessEqual),
+            ..Default::default()
+        });
+
+        texture::Texture {
+            texture,
+            view,
+            sampler,
+            size,
+        }
+    }
+
+    /// The layer of `texture` the shadow pass of cascade `layer` renders into.
+    fn create_cascade_view(texture: &texture::Texture, layer: u32) -> wgpu::TextureView {
+        texture.texture.create_view(&wgpu::TextureViewDescriptor {
+            label: Some("shadow_cascade_view"),
+            dimension: Some(wgpu::TextureViewDimension::D2),
+            base_array_layer: layer,
+            array_layer_count: Some(1),
+            ..Default::default()
+        })
+    }
+
+    fn create_bind_group(
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        texture: &texture::Texture,
+        buffer: &wgpu::Buffer,
+    ) -> wgpu::BindGroup {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&texture.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(&texture.sampler),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: buffer.as_entire_binding(),
+                },
+            ],
+            label: Some("shadow_bind_group"),
+        })
+    }
+
+    /// Splits the view of `camera` through `projection` into the cascades' depth ranges
+    /// and fits an orthographic light frustum around each, looking along the light's travel
+    /// `direction`.
+    pub fn update_cascades(
+        &mut self,
+        queue: &wgpu::Queue,
+        direction: Vector3<f32>,
+        camera: &Camera,
+        projection: &Projection,
+    ) {
+        let count = self.cascade_count() as usize;
+        let near = projection.znear();
+        let far = projection.zfar().min(self.max_distance).max(near);
+        let view = camera.calc_matrix();
+        let mut cascade_near = near;
+        for cascade in 0..count {
+            let t = (cascade + 1) as f32 / count as f32;
+            let logarithmic = near * (far / near).powf(t);
+            let uniform = near + (far - near) * t;
+            let cascade_far = uniform + (logarithmic - uniform) * self.split_lambda;
+
+            let slice = projection.with_depth_range(cascade_near, cascade_far);
+            let corners = (slice.calc_matrix() * view)
+                .invert()
+                .map(frustum_corners)
+                .unwrap_or_default();
+            self.light_view_proj[cascade] = self.fit_cascade(direction, &corners);
+            self.uniform.light_view_proj[cascade] = self.light_view_proj[cascade].into();
+            self.uniform.splits[cascade] = cascade_far;
+            queue.write_buffer(
+                &self.cascades[cascade].buffer,
+                0,
+                bytemuck::cast_slice(&self.uniform.light_view_proj[cascade]),
+            );
+            cascade_near = cascade_far;
+        }
+        self.upload(queue);
+    }
+
+    /// Light matrix of an orthographic frustum around the bounding sphere of `corners`. The
+    /// sphere keeps the frustum's size as the camera turns, and snapping it to whole
+    /// texels keeps the shadow edges from shimmering as the camera moves.
+    fn fit_cascade(&self, direction: Vector3<f32>, corners: &[Point3<f32>]) -> Matrix4<f32> {
+        let center = Point3::centroid(corners);
+        let radius = corners
+            .iter()
+            .map(|corner| (corner - center).magnitude())
+            .fold(0.01_f32, f32::max);
+        // Rounded up, so small changes don't rescale the cascade
+        let radius = (radius * 16.0).ceil() / 16.0;
+
+        let direction = direction.normalize();
+        // look_to_rh breaks down when looking straight along the up vector
+        let up = if direction.y.abs() > 0.99 {
+            Vector3::unit_z()
+        } else {
+            Vector3::unit_y()
+        };
+        let back = radius + Self::CASTER_DISTANCE;
+        let eye = Point3::from_vec(center.to_vec() - direction * back);
+        let view = Matrix4::look_to_rh(eye, direction, up);
+        let proj = cgmath::ortho(-radius, radius, -radius, radius, 0.0, back + radius);
+        let light_view_proj = OPENGL_TO_WGPU_MATRIX * proj * view;
+
+        let texels = self.size as f32 / 2.0;
+        let origin = light_view_proj * Vector4::new(0.0, 0.0, 0.0, 1.0);
+        let snap = |ndc: f32| ((ndc * texels).round() - ndc * texels) / texels;
+        Matrix4::from_translation(Vector3::new(snap(origin.x), snap(origin.y), 0.0))
+            * light_view_proj
+    }
+
+    /// Width and height of each cascade's layer in texels.
+    pub fn resolution(&self) -> u32 {
+        self.size
+    }
+
+    /// Recreates the cascades' layers with `size` texels per side, independent of the size of
+    /// the view, trading memory and shadow pass fill rate for sharper shadow edges. Fails,
+    /// keeping the current layers, if `size` is 0, larger than the device's
+    /// `max_texture_dimension_2d` or the layers would take up more than `MEMORY_BUDGET`.
+    /// The layers start out empty until the next shadow pass.
+    pub fn set_resolution(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        size: u32,
+    ) -> anyhow::Result<()> {
+        let max_size = device.limits().max_texture_dimension_2d;
+        if size == 0 || size > max_size {
+            anyhow::bail!("Shadow map resolution {size} is outside of 1..={max_size}");
+        }
+        // Every cascade has its layer, whether it is used or not
+        let bytes = u64::from(size).pow(2)
+            * Self::MAX_CASCADES as u64
+            * u64::from(Self::DEPTH_FORMAT.block_copy_size(None).unwrap_or(4));
+        if bytes > Self::MEMORY_BUDGET {
+            anyhow::bail!(
+                "Shadow map resolution {size} needs {} MiB, more than the budget of {} MiB",
+                bytes >> 20,
+                Self::MEMORY_BUDGET >> 20
+            );
+        }
+        self.size = size;
+        self.texture = Self::create_shadow_texture(device, size);
+        for (layer, cascade) in self.cascades.iter_mut().enumerate() {
+            cascade.view = Self::create_cascade_view(&self.texture, layer as u32);
+        }
+        self.bind_group =
+            Self::create_bind_group(device, &self.bind_group_layout, &self.texture, &self.buffer);
+        self.uniform.texel_size = 1.0 / size as f32;
+        self.upload(queue);
+        Ok(())
+    }
+
+    pub fn pcf_samples(&self) -> u32 {
+        self.uniform.pcf_samples
+    }
+
+    /// Softens shadow edges by averaging `samples` x `samples` comparisons a texel apart,
+    /// clamped to `1..=MAX_PCF_SAMPLES`. Each comparison already filters 2x2 texels, which
+    /// is all a single sample does.
+    pub fn set_pcf_samples(&mut self, queue: &wgpu::Queue, samples: u32) {
+        self.uniform.pcf_samples = samples.clamp(1, Self::MAX_PCF_SAMPLES);
+        self.upload(queue);
+    }
+
+    pub fn cascade_count(&self) -> u32 {
+        self.uniform.cascade_count
+    }
+
+    /// Uses `count` cascades, clamped to `1..=MAX_CASCADES`. Takes effect on the next
+    /// `update_cascades`.
+    pub fn set_cascade_count(&mut self, count: u32) {
+        self.uniform.cascade_count = count.clamp(1, Self::MAX_CASCADES as u32);
+    }
+
+    pub fn debug_cascades(&self) -> bool {
+        self.uniform.debug_cascades != 0
+    }
+
+    /// Tints lit fragments red, green, blue and yellow by the cascade their shadow comes
+    /// from, nearest first.
+    pub fn set_debug_cascades(&mut self, queue: &wgpu::Queue, enabled: bool) {
+        self.uniform.debug_cascades = enabled.into();
+        self.upload(queue);
+    }
+
+    pub fn set_bias(&mut self, queue: &wgpu::Queue, depth_bias: f32, normal_bias: f32) {
+        self.uniform.depth_bias = depth_bias;
+        self.uniform.normal_bias = normal_bias;
+        self.upload(queue);
+    }
+
+    fn upload(&self, queue: &wgpu::Queue) {
+        queue.write_buffer(&self.buffer, 0, bytemuck::cast_slice(&[self.uniform]));
+    }
+
+    /// Starts a depth-only pass into the layer of `cascade` with the shadow pipeline
+    /// bound. The caller sets the skin in group 1 and vertex buffers, and issues the draws.
+    pub fn begin_shadow_pass<'a>(
+        &self,
+        encoder: &'a mut wgpu::CommandEncoder,
+        cascade: usize,
+        timestamp_writes: Option<wgpu::RenderPassTimestampWrites<'_>>,
+    ) -> wgpu::RenderPass<'a> {
+        let cascade = &self.cascades[cascade];
+        let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Shadow Pass"),
+            color_attachments: &[],
+            depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                view: &cascade.view,
+                depth_ops: Some(wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(1.0),
+                    store: wgpu::StoreOp::Store,
+                }),
+                stencil_ops: None,
+            }),
+            occlusion_query_set: None,
+            timestamp_writes,
+        });
+        pass.set_pipeline(&self.render_pipeline);
+        pass.set_bind_group(0, &cascade.bind_group, &[]);
+        pass
+    }
+}
+
+/// The eight corners of the clip space volume mapped back by the inverse view projection
+/// `inverse`, in world space.
+fn frustum_corners(inverse: Matrix4<f32>) -> Vec<Point3<f32>> {
+    let mut corners = Vec::with_capacity(8);
+    for x in [-1.0, 1.0] {
+        for y in [-1.0, 1.0] {
+            for z in [0.0, 1.0] {
+                let corner = inverse * Vector4::new(x, y, z, 1.0);
+                corners.push(Point3::from_homogeneous(corner));
+            }
+        }
+    }
+    corners
+}
@@ -0,0 +1,47 @@
+// Light matrix of the cascade being rendered
+@group(0) @binding(0)
+var<uniform> light_view_proj: mat4x4<f32>;
+
+@group(1) @binding(0)
+var<storage, read> joint_matrices: array<mat4x4<f32>>;
+
+struct VertexInput {
+  @location(0) position: vec3<f32>,
+  @location(12) joints: vec4<u32>,
+  @location(13) weights: vec4<f32>,
+}
+
+// Same as the main shader's, so shadows follow the animated pose
+fn skin_matrix(joints: vec4<u32>, weights: vec4<f32>) -> mat4x4<f32> {
+  if dot(weights, vec4<f32>(1.0)) == 0.0 {
+    return mat4x4<f32>(
+      vec4<f32>(1.0, 0.0, 0.0, 0.0),
+      vec4<f32>(0.0, 1.0, 0.0, 0.0),
+      vec4<f32>(0.0, 0.0, 1.0, 0.0),
+      vec4<f32>(0.0, 0.0, 0.0, 1.0),
+    );
+  }
+  return joint_matrices[joints.x] * weights.x
+    + joint_matrices[joints.y] * weights.y
+    + joint_matrices[joints.z] * weights.z
+    + joint_matrices[joints.w] * weights.w;
+}
+
+struct InstanceInput {
+  @location(5) model_matrix_0: vec4<f32>,
+  @location(6) model_matrix_1: vec4<f32>,
+  @location(7) model_matrix_2: vec4<f32>,
+  @location(8) model_matrix_3: vec4<f32>,
+}
+
+@vertex
+fn vs_main(model: VertexInput, instance: InstanceInput) -> @builtin(position) vec4<f32> {
+  let model_matrix = mat4x4<f32>(
+    instance.model_matrix_0,
+    instance.model_matrix_1,
+    instance.model_matrix_2,
+    instance.model_matrix_3,
+  );
+  let skin = skin_matrix(model.joints, model.weights);
+  return light_view_proj * model_matrix * skin * vec4<f32>(model.position, 1.0);
+}
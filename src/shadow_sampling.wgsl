@@ -0,0 +1,87 @@
+// Sampling the cascaded shadow map, shared by the shaders lit by the sun and appended to
+// their source. They declare `camera` (with its `view` matrix), `t_shadow`, `s_shadow`
+// and `shadow` themselves, in whichever groups their pipelines bind them.
+
+// A light matrix per cascade, each used up to the view depth in `splits`
+struct Shadow {
+  light_view_proj: array<mat4x4<f32>, 4>,
+  splits: vec4<f32>,
+  depth_bias: f32,
+  normal_bias: f32,
+  cascade_count: u32,
+  debug_cascades: u32,
+  // Comparisons per side of the PCF kernel
+  pcf_samples: u32,
+  // Size of a shadow map texel in UV units
+  texel_size: f32,
+};
+
+// The cascade whose shadow covers `world_position`, `cascade_count` past the last one
+fn shadow_cascade(world_position: vec3<f32>) -> u32 {
+  let view_depth = -(camera.view * vec4<f32>(world_position, 1.0)).z;
+  var cascade = 0u;
+  while cascade < shadow.cascade_count && view_depth > shadow.splits[cascade] {
+    cascade += 1u;
+  }
+  return cascade;
+}
+
+// Color to multiply by to show the cascades, white unless `debug_cascades` is set
+fn cascade_tint(world_position: vec3<f32>) -> vec3<f32> {
+  if shadow.debug_cascades == 0u {
+    return vec3<f32>(1.0);
+  }
+  switch shadow_cascade(world_position) {
+    case 0u: {
+      return vec3<f32>(1.0, 0.4, 0.4);
+    }
+    case 1u: {
+      return vec3<f32>(0.4, 1.0, 0.4);
+    }
+    case 2u: {
+      return vec3<f32>(0.4, 0.4, 1.0);
+    }
+    case 3u: {
+      return vec3<f32>(1.0, 1.0, 0.4);
+    }
+    default: {
+      return vec3<f32>(1.0);
+    }
+  }
+}
+
+// Averages `pcf_samples` x `pcf_samples` comparisons a texel apart around `uv`, each of
+// which already filters 2x2 texels through the linear comparison sampler
+fn shadow_pcf(uv: vec2<f32>, cascade: u32, depth: f32) -> f32 {
+  let half_width = f32(shadow.pcf_samples - 1u) * 0.5;
+  var sum = 0.0;
+  for (var y = 0u; y < shadow.pcf_samples; y += 1u) {
+    for (var x = 0u; x < shadow.pcf_samples; x += 1u) {
+      let offset = (vec2<f32>(f32(x), f32(y)) - half_width) * shadow.texel_size;
+      sum += textureSampleCompareLevel(t_shadow, s_shadow, uv + offset, cascade, depth);
+    }
+  }
+  return sum / f32(shadow.pcf_samples * shadow.pcf_samples);
+}
+
+// Returns 1.0 when fully lit by the sun and 0.0 when fully in shadow
+fn sun_visibility(world_position: vec3<f32>, normal: vec3<f32>, light_dir: vec3<f32>) -> f32 {
+  let cascade = shadow_cascade(world_position);
+  // Beyond the last cascade nothing is shadowed
+  if cascade >= shadow.cascade_count {
+    return 1.0;
+  }
+  // Offsetting along the normal and scaling the bias with the slope keeps surfaces
+  // from shadowing themselves (shadow acne)
+  let offset_position = world_position + normal * shadow.normal_bias;
+  let light_space = shadow.light_view_proj[cascade] * vec4<f32>(offset_position, 1.0);
+  let ndc = light_space.xyz / light_space.w;
+  let uv = ndc.xy * vec2<f32>(0.5, -0.5) + 0.5;
+  let slope = 1.0 - max(dot(normal, light_dir), 0.0);
+  let bias = shadow.depth_bias * (1.0 + slope);
+  let visibility = shadow_pcf(uv, cascade, ndc.z - bias);
+
+  // Everything outside the shadow map's coverage is considered lit
+  let outside = any(uv < vec2<f32>(0.0)) || any(uv > vec2<f32>(1.0)) || ndc.z > 1.0;
+  return select(visibility, 1.0, outside);
+}
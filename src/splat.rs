@@ -0,0 +1,368 @@
+use cgmath::Vector2;
+use wgpu::util::DeviceExt as _;
+
+use crate::{colormap::Colormap, texture};
+
+/// How a `SplatMaterial` picks its layers, when it has no control map, and projects them.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SplatParams {
+    /// World heights where layers 1, 2 and 3 take over from the layer below, ascending
+    pub layer_heights: [f32; 3],
+    /// Height band over which neighboring layers blend
+    pub blend: f32,
+    /// Layer covering steep ground over the height layers, e.g. rock
+    pub slope_layer: Option<u32>,
+    /// Steepness, `1 - normal.y`, where the slope layer starts to show and where it covers
+    /// the height layers completely
+    pub slope_range: (f32, f32),
+    /// World units covered by one repeat of the layer textures
+    pub tiling: f32,
+    /// Projects the layers along all three world axes, weighted by the surface normal,
+    /// instead of straight down, so cliffs don't stretch them
+    pub triplanar: bool,
+    /// How sharply triplanar mapping switches between axes, higher blends less
+    pub triplanar_sharpness: f32,
+    /// World heights at the low and high end of the colormap, when the material has one
+    pub elevation_range: (f32, f32),
+    /// Isolines drawn over the layers or the colormap
+    pub contours: Option<Contours>,
+}
+
+impl Default for SplatParams {
+    fn default() -> Self {
+        Self {
+            layer_heights: [2.0, 6.0, 12.0],
+            blend: 1.0,
+            slope_layer: None,
+            slope_range: (0.3, 0.5),
+            tiling: 4.0,
+            triplanar: false,
+            triplanar_sharpness: 4.0,
+            elevation_range: (0.0, 16.0),
+            contours: None,
+        }
+    }
+}
+
+/// Contour lines every `interval` of world height, antialiased and the same width in
+/// pixels at any distance.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Contours {
+    /// Height between neighboring lines, the first at height 0
+    pub interval: f32,
+    /// Width of the lines in pixels
+    pub thickness: f32,
+    /// Linear color, alpha is how much the lines cover the terrain
+    pub color: [f32; 4],
+    /// Every `major_every`th line, counting from height 0, is a major line drawn
+    /// `major_thickness` wide instead. 0 draws no major lines.
+    pub major_every: u32,
+    pub major_thickness: f32,
+}
+
+impl Contours {
+    /// Dark lines with every fifth one twice as thick.
+    pub fn new(interval: f32, thickness: f32) -> Self {
+        Self {
+            interval,
+            thickness,
+            color: [0.02, 0.015, 0.01, 0.8],
+            major_every: 5,
+            major_thickness: thickness * 2.0,
+        }
+    }
+}
+
+/// Layer weights painted over a world area: red, green, blue and alpha are the weights
+/// of layers 0 to 3. The texture should be linear, e.g. loaded with `is_normal_map`.
+pub struct SplatControl {
+    pub texture: texture::Texture,
+    /// World `(x, z)` of the texture's top left corner
+    pub origin: Vector2<f32>,
+    /// World size covered by the texture along x and z
+    pub size: Vector2<f32>,
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
+struct SplatUniform {
+    // x is unused, layer 0 covers everything below layer 1
+    layer_heights: [f32; 4],
+    control_origin: [f32; 2],
+    control_size: [f32; 2],
+    tiling: f32,
+    blend: f32,
+    slope_start: f32,
+    slope_end: f32,
+    slope_layer: u32,
+    layer_count: u32,
+    use_control: u32,
+    triplanar: u32,
+    triplanar_sharpness: f32,
+    use_colormap: u32,
+    elevation_min: f32,
+    elevation_max: f32,
+    contour_color: [f32; 4],
+    // 0 without contours
+    contour_interval: f32,
+    contour_thickness: f32,
+    contour_major_every: u32,
+    contour_major_thickness: f32,
+}
+
+/// Terrain material blending up to four layers of a texture array, by height and slope
+/// or by the weights of a `SplatControl` map, or coloring by height through a `Colormap`
+/// instead. Drawn with `terrain.wgsl`.
+pub struct SplatMaterial {
+    /// `D2Array` texture, see `Texture::array_from_bytes`
+    pub layers: texture::Texture,
+    pub control: Option<SplatControl>,
+    colormap: Option<Colormap>,
+    params: SplatParams,
+    buffer: wgpu::Buffer,
+    pub bind_group: wgpu::BindGroup,
+}
+
+impl SplatMaterial {
+    /// Layers past the fourth are ignored.
+    pub const MAX_LAYERS: u32 = 4;
+
+    /// Group 0 of `terrain.wgsl`: the layer array and the control map, each followed by
+    /// its sampler, then the parameters uniform and the colormap and its sampler.
+    pub fn create_bind_group_layout(device: &wgpu::Device) -> wgpu::BindGroupLayout {
+        let texture = |binding, view_dimension| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Texture {
+                multisampled: false,
+                view_dimension,
+                sample_type: wgpu::TextureSampleType::Float { filterable: true },
+            },
+            count: None,
+        };
+        let sampler = |binding| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+            count: None,
+        };
+        device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                texture(0, wgpu::TextureViewDimension::D2Array),
+                sampler(1),
+                texture(2, wgpu::TextureViewDimension::D2),
+                sampler(3),
+                wgpu::BindGroupLayoutEntry {
+                    binding: 4,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                texture(5, wgpu::TextureViewDimension::D2),
+                sampler(6),
+            ],
+            label: Some("splat_material_bind_group_layout"),
+        })
+    }
+
+    /// Without a `control` map, layers are picked by `params`.
+    pub fn new(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        layout: &wgpu::BindGroupLayout,
+        layers: texture::Texture,
+        control: Option<SplatControl>,
+        params: SplatParams,
+    ) -> anyhow::Result<Self> {
+        let buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Splat Material Buffer"),
+            contents: bytemuck::cast_slice(&[Self::uniform(
+                &layers,
+                control.as_ref(),
+                false,
+                &params,
+            )]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        let bind_group = Self::create_bind_group(
+            device,
+            queue,
+            layout,
+            &layers,
+            control.as_ref(),
+            None,
+            &buffer,
+        )?;
+
+        Ok(Self {
+            layers,
+            control,
+            colormap: None,
+            params,
+            buffer,
+            bind_group,
+        })
+    }
+
+    fn create_bind_group(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        layout: &wgpu::BindGroupLayout,
+        layers: &texture::Texture,
+        control: Option<&SplatControl>,
+        colormap: Option<&Colormap>,
+        buffer: &wgpu::Buffer,
+    ) -> anyhow::Result<wgpu::BindGroup> {
+        // The bind group needs some control map and colormap even when they go unused
+        let control_fallback;
+        let control_texture = match control {
+            Some(control) => &control.texture,
+            None => {
+                control_fallback = texture::Texture::from_color(
+                    device,
+                    queue,
+                    [255, 0, 0, 0],
+                    "splat_control_fallback",
+                    true,
+                )?;
+                &control_fallback
+            }
+        };
+        let colormap_fallback;
+        let colormap_texture = match colormap {
+            Some(colormap) => &colormap.texture,
+            None => {
+                colormap_fallback = texture::Texture::from_color(
+                    device,
+                    queue,
+                    [255; 4],
+                    "splat_colormap_fallback",
+                    false,
+                )?;
+                &colormap_fallback
+            }
+        };
+        Ok(device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&layers.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(&layers.sampler),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::TextureView(&control_texture.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: wgpu::BindingResource::Sampler(&control_texture.sampler),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 4,
+                    resource: buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 5,
+                    resource: wgpu::BindingResource::TextureView(&colormap_texture.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 6,
+                    resource: wgpu::BindingResource::Sampler(&colormap_texture.sampler),
+                },
+            ],
+            label: Some("splat_material_bind_group"),
+        }))
+    }
+
+    pub fn colormap(&self) -> Option<&Colormap> {
+        self.colormap.as_ref()
+    }
+
+    /// Colors the terrain by height through `colormap` over `SplatParams::elevation_range`
+    /// instead of blending the layers, or blends them again with `None`. `layout` is the
+    /// one the material was created with.
+    pub fn set_colormap(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        layout: &wgpu::BindGroupLayout,
+        colormap: Option<Colormap>,
+    ) -> anyhow::Result<()> {
+        self.bind_group = Self::create_bind_group(
+            device,
+            queue,
+            layout,
+            &self.layers,
+            self.control.as_ref(),
+            colormap.as_ref(),
+            &self.buffer,
+        )?;
+        self.colormap = colormap;
+        self.set_params(queue, self.params);
+        Ok(())
+    }
+
+    pub fn params(&self) -> SplatParams {
+        self.params
+    }
+
+    pub fn set_params(&mut self, queue: &wgpu::Queue, params: SplatParams) {
+        self.params = params;
+        let uniform = Self::uniform(
+            &self.layers,
+            self.control.as_ref(),
+            self.colormap.is_some(),
+            &params,
+        );
+        queue.write_buffer(&self.buffer, 0, bytemuck::cast_slice(&[uniform]));
+    }
+
+    fn uniform(
+        layers: &texture::Texture,
+        control: Option<&SplatControl>,
+        use_colormap: bool,
+        params: &SplatParams,
+    ) -> SplatUniform {
+        let layer_count = layers.size.depth_or_array_layers.min(Self::MAX_LAYERS);
+        let [h1, h2, h3] = params.layer_heights;
+        SplatUniform {
+            layer_heights: [0.0, h1, h2, h3],
+            control_origin: control.map_or([0.0; 2], |control| control.origin.into()),
+            control_size: control.map_or([1.0; 2], |control| control.size.into()),
+            tiling: params.tiling.max(0.0001),
+            blend: params.blend.max(0.0),
+            slope_start: params.slope_range.0,
+            slope_end: params.slope_range.1,
+            // Past the last layer disables the slope layer
+            slope_layer: params
+                .slope_layer
+                .map_or(Self::MAX_LAYERS, |layer| layer.min(layer_count - 1)),
+            layer_count,
+            use_control: control.is_some().into(),
+            triplanar: params.triplanar.into(),
+            triplanar_sharpness: params.triplanar_sharpness.max(1.0),
+            use_colormap: use_colormap.into(),
+            elevation_min: params.elevation_range.0,
+            elevation_max: params.elevation_range.1,
+            contour_color: params.contours.map_or([0.0; 4], |contours| contours.color),
+            contour_interval: params
+                .contours
+                .map_or(0.0, |contours| contours.interval.max(0.0)),
+            contour_thickness: params
+                .contours
+                .map_or(0.0, |contours| contours.thickness.max(0.0)),
+            contour_major_every: params.contours.map_or(0, |contours| contours.major_every),
+            contour_major_thickness: params
+                .contours
+                .map_or(0.0, |contours| contours.major_thickness.max(0.0)),
+        }
+    }
+}
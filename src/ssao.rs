@@ -0,0 +1,390 @@
+use wgpu::util::DeviceExt as _;
+
+use crate::{hot_reload::wgsl, texture, viewport::Rect};
+
+/// How strongly `Ssao` darkens creases, see `Renderer::set_ssao_settings`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SsaoSettings {
+    /// World space radius of the hemisphere sampled for occluders
+    pub radius: f32,
+    /// Depth difference below which an occluder is ignored, against self-shadowing acne
+    pub bias: f32,
+    /// Scales the occlusion, 0 leaves the ambient light alone
+    pub intensity: f32,
+}
+
+impl Default for SsaoSettings {
+    fn default() -> Self {
+        Self {
+            radius: 0.5,
+            bias: 0.025,
+            intensity: 1.0,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
+struct SsaoUniform {
+    kernel: [[f32; 4]; Ssao::KERNEL_SIZE],
+    noise: [[f32; 4]; 16],
+    radius: f32,
+    bias: f32,
+    intensity: f32,
+    enabled: u32,
+}
+
+/// Screen-space ambient occlusion of the deferred G-buffer. Samples a hemisphere kernel
+/// around each pixel's normal, rotated by a tiled 4x4 noise pattern, counts the samples
+/// behind the surfaces the G-buffer holds, then blurs away the pattern. The deferred
+/// lighting pass darkens ambient light by `occlusion`.
+pub struct Ssao {
+    uniform: SsaoUniform,
+    buffer: wgpu::Buffer,
+    input_layout: wgpu::BindGroupLayout,
+    input_bind_group: wgpu::BindGroup,
+    blur_layout: wgpu::BindGroupLayout,
+    blur_bind_group: wgpu::BindGroup,
+    /// Raw occlusion, noisy
+    raw: texture::Texture,
+    /// Blurred occlusion, 0 unoccluded to 1 fully occluded
+    pub occlusion: texture::Texture,
+    occlusion_pipeline: wgpu::RenderPipeline,
+    blur_pipeline: wgpu::RenderPipeline,
+}
+
+impl Ssao {
+    pub const KERNEL_SIZE: usize = 16;
+    pub const FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::R8Unorm;
+
+    /// Reads the G-buffer's `normal` and camera relative `position` texture of `width` x
+    /// `height`, and the camera in the layout of `camera_layout`.
+    pub fn new(
+        device: &wgpu::Device,
+        camera_layout: &wgpu::BindGroupLayout,
+        normal: &wgpu::TextureView,
+        position: &wgpu::TextureView,
+        width: u32,
+        height: u32,
+    ) -> Self {
+        let uniform = Self::create_uniform(SsaoSettings::default(), false);
+        let buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("SSAO Buffer"),
+            contents: bytemuck::cast_slice(&[uniform]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+
+        let texture = |binding| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Texture {
+                multisampled: false,
+                view_dimension: wgpu::TextureViewDimension::D2,
+                sample_type: wgpu::TextureSampleType::Float { filterable: false },
+            },
+            count: None,
+        };
+        let input_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                texture(0),
+                texture(1),
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ],
+            label: Some("ssao_bind_group_layout"),
+        });
+        let blur_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[texture(0)],
+            label: Some("ssao_blur_bind_group_layout"),
+        });
+
+        let (occlusion_pipeline, blur_pipeline) =
+            Self::create_pipelines(device, &input_layout, &blur_layout, camera_layout);
+
+        let (raw, occlusion, input_bind_group, blur_bind_group) = Self::create_targets(
+            device,
+            &input_layout,
+            &blur_layout,
+            &buffer,
+            normal,
+            position,
+            width,
+            height,
+        );
+        Self {
+            uniform,
+            buffer,
+            input_layout,
+            input_bind_group,
+            blur_layout,
+            blur_bind_group,
+            raw,
+            occlusion,
+            occlusion_pipeline,
+            blur_pipeline,
+        }
+    }
+
+    /// The occlusion and blur pipelines.
+    fn create_pipelines(
+        device: &wgpu::Device,
+        input_layout: &wgpu::BindGroupLayout,
+        blur_layout: &wgpu::BindGroupLayout,
+        camera_layout: &wgpu::BindGroupLayout,
+    ) -> (wgpu::RenderPipeline, wgpu::RenderPipeline) {
+        let shader = device.create_shader_module(wgsl!(
+            "src/ssao.wgsl",
+            "src/fullscreen.wgsl",
+            "src/prelude.wgsl"
+        ));
+        let create_pipeline = |label, layouts: &[&wgpu::BindGroupLayout], entry_point| {
+            let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: Some(label),
+                bind_group_layouts: layouts,
+                push_constant_ranges: &[],
+            });
+            device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                label: Some(label),
+                layout: Some(&layout),
+                vertex: wgpu::VertexState {
+                    module: &shader,
+                    entry_point: Some("vs_main"),
+                    buffers: &[],
+                    compilation_options: Default::default(),
+                },
+                fragment: Some(wgpu::FragmentState {
+                    module: &shader,
+                    entry_point: Some(entry_point),
+                    targets: &[Some(Self::FORMAT.into())],
+                    compilation_options: Default::default(),
+                }),
+                primitive: wgpu::PrimitiveState::default(),
+                depth_stencil: None,
+                multisample: wgpu::MultisampleState::default(),
+                multiview: None,
+                cache: None,
+            })
+        };
+        (
+            create_pipeline(
+                "SSAO Pipeline",
+                &[input_layout, camera_layout],
+                "fs_occlusion",
+            ),
+            create_pipeline("SSAO Blur Pipeline", &[blur_layout], "fs_blur"),
+        )
+    }
+
+    /// Rebuilds the pipelines from the current shader, see `hot_reload` and `new`.
+    pub fn recreate_pipelines(
+        &mut self,
+        device: &wgpu::Device,
+        camera_layout: &wgpu::BindGroupLayout,
+    ) {
+        (self.occlusion_pipeline, self.blur_pipeline) =
+            Self::create_pipelines(device, &self.input_layout, &self.blur_layout, camera_layout);
+    }
+
+    /// A fixed hemisphere kernel and noise pattern, so the occlusion doesn't flicker
+    fn create_uniform(settings: SsaoSettings, enabled: bool) -> SsaoUniform {
+        // xorshift, any reasonably spread sequence will do
+        let mut state = 0x9e37_79b9_u32;
+        let mut random = move || {
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            state as f32 / u32::MAX as f32
+        };
+
+        let mut kernel = [[0.0; 4]; Self::KERNEL_SIZE];
+        for (i, sample) in kernel.iter_mut().enumerate() {
+            let direction = cgmath::Vector3::new(
+                random() * 2.0 - 1.0,
+                random() * 2.0 - 1.0,
+                random().max(0.05),
+            );
+            // Most samples close to the surface, where occluders matter most
+            let t = i as f32 / Self::KERNEL_SIZE as f32;
+            let scale = 0.1 + 0.9 * t * t;
+            let direction = cgmath::InnerSpace::normalize(direction) * random() * scale;
+            *sample = [direction.x, direction.y, direction.z, 0.0];
+        }
+        let mut noise = [[0.0; 4]; 16];
+        for rotation in noise.iter_mut() {
+            *rotation = [random() * 2.0 - 1.0, random() * 2.0 - 1.0, 0.0, 0.0];
+        }
+
+        SsaoUniform {
+            kernel,
+            noise,
+            radius: settings.radius,
+            bias: settings.bias,
+            intensity: settings.intensity,
+            enabled: enabled.into(),
+        }
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn create_targets(
+        device: &wgpu::Device,
+        input_layout: &wgpu::BindGroupLayout,
+        blur_layout: &wgpu::BindGroupLayout,
+        buffer: &wgpu::Buffer,
+        normal: &wgpu::TextureView,
+        position: &wgpu::TextureView,
+        width: u32,
+        height: u32,
+    ) -> (
+        texture::Texture,
+        texture::Texture,
+        wgpu::BindGroup,
+        wgpu::BindGroup,
+    ) {
+        let target = |label| {
+            texture::Texture::create_texture(
+                device,
+                Some(label),
+                wgpu::Extent3d {
+                    width: width.max(1),
+                    height: height.max(1),
+                    depth_or_array_layers: 1,
+                },
+                Self::FORMAT,
+                wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+                wgpu::TextureDimension::D2,
+                wgpu::FilterMode::Nearest,
+            )
+        };
+        let raw = target("ssao_raw_texture");
+        let occlusion = target("ssao_occlusion_texture");
+        let input_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: input_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(normal),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(position),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: buffer.as_entire_binding(),
+                },
+            ],
+            label: Some("ssao_bind_group"),
+        });
+        let blur_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: blur_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: wgpu::BindingResource::TextureView(&raw.view),
+            }],
+            label: Some("ssao_blur_bind_group"),
+        });
+        (raw, occlusion, input_bind_group, blur_bind_group)
+    }
+
+    /// Recreates the occlusion targets for a resized G-buffer.
+    pub fn resize(
+        &mut self,
+        device: &wgpu::Device,
+        normal: &wgpu::TextureView,
+        position: &wgpu::TextureView,
+        width: u32,
+        height: u32,
+    ) {
+        (
+            self.raw,
+            self.occlusion,
+            self.input_bind_group,
+            self.blur_bind_group,
+        ) = Self::create_targets(
+            device,
+            &self.input_layout,
+            &self.blur_layout,
+            &self.buffer,
+            normal,
+            position,
+            width,
+            height,
+        );
+    }
+
+    /// Uploads `settings`. While not `enabled` the lighting pass ignores `occlusion`, and
+    /// `apply` needn't run.
+    pub fn update(&mut self, queue: &wgpu::Queue, settings: SsaoSettings, enabled: bool) {
+        self.uniform.radius = settings.radius;
+        self.uniform.bias = settings.bias;
+        self.uniform.intensity = settings.intensity;
+        self.uniform.enabled = enabled.into();
+        queue.write_buffer(&self.buffer, 0, bytemuck::cast_slice(&[self.uniform]));
+    }
+
+    /// The uniform with the settings, also read by the lighting pass
+    pub fn buffer(&self) -> &wgpu::Buffer {
+        &self.buffer
+    }
+
+    /// Records the occlusion and blur passes for the G-buffer as seen by the camera in
+    /// `camera_bind_group`, which projects into `viewport` of it, `None` for all of it.
+    pub fn apply(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        camera_bind_group: &wgpu::BindGroup,
+        viewport: Option<Rect>,
+    ) {
+        let mut pass = Self::begin_pass(encoder, "SSAO Pass", &self.raw.view);
+        if let Some(rect) = viewport {
+            pass.set_viewport(
+                rect.x as f32,
+                rect.y as f32,
+                rect.width as f32,
+                rect.height as f32,
+                0.0,
+                1.0,
+            );
+        }
+        pass.set_pipeline(&self.occlusion_pipeline);
+        pass.set_bind_group(0, &self.input_bind_group, &[]);
+        pass.set_bind_group(1, camera_bind_group, &[]);
+        pass.draw(0..3, 0..1);
+        drop(pass);
+
+        let mut pass = Self::begin_pass(encoder, "SSAO Blur Pass", &self.occlusion.view);
+        pass.set_pipeline(&self.blur_pipeline);
+        pass.set_bind_group(0, &self.blur_bind_group, &[]);
+        pass.draw(0..3, 0..1);
+    }
+
+    fn begin_pass<'a>(
+        encoder: &'a mut wgpu::CommandEncoder,
+        label: &str,
+        target: &wgpu::TextureView,
+    ) -> wgpu::RenderPass<'a> {
+        encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some(label),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: target,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(wgpu::Color::TRANSPARENT),
+                    store: wgpu::StoreOp::Store,
+                },
+                depth_slice: None,
+            })],
+            depth_stencil_attachment: None,
+            occlusion_query_set: None,
+            timestamp_writes: None,
+        })
+    }
+}
@@ -0,0 +1,96 @@
+// Screen-space ambient occlusion from the deferred G-buffer: how much of the hemisphere
+// above each surface is blocked by nearby geometry, then a blur hiding the noise pattern
+
+struct Ssao {
+  // Hemisphere samples in tangent space along +z, denser near the center
+  kernel: array<vec4<f32>, 16>,
+  // Rotations of the kernel around the normal, tiled over 4x4 pixels
+  noise: array<vec4<f32>, 16>,
+  radius: f32,
+  bias: f32,
+  intensity: f32,
+  enabled: u32,
+};
+
+// `CameraUniform` and the other shared types are declared in `src/prelude.wgsl`
+
+// `vs_main` and `FullscreenOutput` are appended from `src/fullscreen.wgsl`
+
+@group(0) @binding(0)
+var t_normal: texture_2d<f32>;
+@group(0) @binding(1)
+var t_position: texture_2d<f32>;
+@group(0) @binding(2)
+var<uniform> ssao: Ssao;
+
+@group(1) @binding(0)
+var<uniform> camera: CameraUniform;
+
+const KERNEL_SIZE: u32 = 16u;
+
+fn view_depth(world_position: vec3<f32>) -> f32 {
+  return -(camera.view * vec4<f32>(world_position, 1.0)).z;
+}
+
+@fragment
+fn fs_occlusion(in: FullscreenOutput) -> @location(0) vec4<f32> {
+  // Pixels per uv unit, the size of the viewport the camera projects into
+  let viewport_size = 1.0 / abs(vec2<f32>(dpdx(in.uv.x), dpdy(in.uv.y)));
+  let coords = vec2<i32>(in.pos.xy);
+  let position_sample = textureLoad(t_position, coords, 0);
+  if position_sample.w == 0.0 {
+    return vec4<f32>(0.0);
+  }
+  let origin = position_sample.xyz + camera.view_pos.xyz;
+  let origin_depth = view_depth(origin);
+  let normal = normalize(textureLoad(t_normal, coords, 0).xyz);
+
+  // A basis around the normal, rotated per pixel so neighbors sample different directions
+  let random = ssao.noise[u32(coords.y % 4) * 4u + u32(coords.x % 4)].xyz;
+  let tangent = normalize(random - normal * dot(random, normal));
+  let bitangent = cross(normal, tangent);
+
+  let size = vec2<i32>(textureDimensions(t_position));
+  var occlusion = 0.0;
+  for (var i = 0u; i < KERNEL_SIZE; i += 1u) {
+    let k = ssao.kernel[i].xyz;
+    let sample_position = origin + (tangent * k.x + bitangent * k.y + normal * k.z) * ssao.radius;
+    let clip = camera.view_proj * vec4<f32>(sample_position, 1.0);
+    if clip.w <= 0.0 {
+      continue;
+    }
+    let uv = clip.xy / clip.w * vec2<f32>(0.5, -0.5) + 0.5;
+    let sample_coords = vec2<i32>(in.pos.xy + (uv - in.uv) * viewport_size);
+    if any(sample_coords < vec2<i32>(0)) || any(sample_coords >= size) {
+      continue;
+    }
+    let scene = textureLoad(t_position, sample_coords, 0);
+    if scene.w == 0.0 {
+      continue;
+    }
+    let scene_depth = view_depth(scene.xyz + camera.view_pos.xyz);
+    // Geometry far in front of the surface, e.g. a distant edge, doesn't shadow it
+    let range = smoothstep(0.0, 1.0, ssao.radius / max(abs(origin_depth - scene_depth), 0.0001));
+    if scene_depth <= view_depth(sample_position) - ssao.bias {
+      occlusion += range;
+    }
+  }
+  return vec4<f32>(clamp(occlusion / f32(KERNEL_SIZE) * ssao.intensity, 0.0, 1.0));
+}
+
+@group(0) @binding(0)
+var t_occlusion: texture_2d<f32>;
+
+// Averages the 4x4 pixels the noise pattern repeats over
+@fragment
+fn fs_blur(in: FullscreenOutput) -> @location(0) vec4<f32> {
+  let coords = vec2<i32>(in.pos.xy);
+  let last = vec2<i32>(textureDimensions(t_occlusion)) - 1;
+  var total = 0.0;
+  for (var y = -2; y < 2; y += 1) {
+    for (var x = -2; x < 2; x += 1) {
+      total += textureLoad(t_occlusion, clamp(coords + vec2<i32>(x, y), vec2<i32>(0), last), 0).r;
+    }
+  }
+  return vec4<f32>(total / 16.0);
+}
@@ -1,136 +1,80 @@
-use std::iter;
-
 use crate::{
-    camera::{Camera, CameraController, CameraUniform, Projection},
-    light::{Light, LightUniform},
-    model::{self, Vertex},
-    render_pipeline::create_render_pipeline,
-    resources,
+    camera::{Camera, CameraController, FlyCameraController},
+    camera_path::CameraPath,
+    environment::Environment,
+    frustum::Frustum,
+    light::LightAnimator,
+    loader::ResourceLoader,
+    model::Model,
+    outline::ObjectId,
+    overlay::RenderStats,
+    particles::ParticleSettings,
+    render_graph::RenderGraph,
+    renderer::{Renderer, RendererOptions},
+    timing::GpuTimings,
+    tonemap::TonemapMode,
+    viewport::Rect,
 };
-use cgmath::Rotation3;
-use cgmath::prelude::*;
-use wgpu::util::DeviceExt;
 use winit::{
     event::{ElementState, KeyEvent, MouseButton, WindowEvent},
-    keyboard::PhysicalKey,
-    window::Window,
+    keyboard::{KeyCode, PhysicalKey},
+    window::{CursorGrabMode, Fullscreen, Window},
 };
 
-use crate::texture;
-
-struct Instance {
-    position: cgmath::Vector3<f32>,
-    rotation: cgmath::Quaternion<f32>,
-}
-
-impl Instance {
-    fn to_raw(&self) -> InstanceRaw {
-        InstanceRaw {
-            model: (cgmath::Matrix4::from_translation(self.position)
-                * cgmath::Matrix4::from(self.rotation))
-            .into(),
-            normal: cgmath::Matrix3::from(self.rotation).into(),
-        }
-    }
-}
-
-#[repr(C)]
-#[derive(Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
-struct InstanceRaw {
-    model: [[f32; 4]; 4],
-    normal: [[f32; 3]; 3],
+/// Whether `WindowState` draws the scene or the loading screen.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum LoadState {
+    /// Resources started with `WindowState::start_loading` are still loading
+    Loading,
+    Ready,
 }
 
-impl model::Vertex for InstanceRaw {
-    fn desc() -> wgpu::VertexBufferLayout<'static> {
-        use std::mem;
-        wgpu::VertexBufferLayout {
-            array_stride: mem::size_of::<InstanceRaw>() as wgpu::BufferAddress,
-            step_mode: wgpu::VertexStepMode::Instance,
-            attributes: &[
-                wgpu::VertexAttribute {
-                    offset: 0,
-                    // While our vertex shader only uses locations 0, and 1 now, in later tutorials, we'll
-                    // be using 2, 3, and 4, for Vertex. We'll start at slot 5, not conflict with them later
-                    shader_location: 5,
-                    format: wgpu::VertexFormat::Float32x4,
-                },
-                wgpu::VertexAttribute {
-                    offset: mem::size_of::<[f32; 4]>() as wgpu::BufferAddress,
-                    shader_location: 6,
-                    format: wgpu::VertexFormat::Float32x4,
-                },
-                wgpu::VertexAttribute {
-                    offset: mem::size_of::<[f32; 8]>() as wgpu::BufferAddress,
-                    shader_location: 7,
-                    format: wgpu::VertexFormat::Float32x4,
-                },
-                wgpu::VertexAttribute {
-                    offset: mem::size_of::<[f32; 12]>() as wgpu::BufferAddress,
-                    shader_location: 8,
-                    format: wgpu::VertexFormat::Float32x4,
-                },
-                wgpu::VertexAttribute {
-                    offset: mem::size_of::<[f32; 16]>() as wgpu::BufferAddress,
-                    shader_location: 9,
-                    format: wgpu::VertexFormat::Float32x3,
-                },
-                wgpu::VertexAttribute {
-                    offset: mem::size_of::<[f32; 19]>() as wgpu::BufferAddress,
-                    shader_location: 10,
-                    format: wgpu::VertexFormat::Float32x3,
-                },
-                wgpu::VertexAttribute {
-                    offset: mem::size_of::<[f32; 22]>() as wgpu::BufferAddress,
-                    shader_location: 11,
-                    format: wgpu::VertexFormat::Float32x3,
-                },
-            ],
-        }
-    }
+/// Loads in flight and what to do with them once they all finished.
+struct PendingLoad {
+    loader: ResourceLoader,
+    on_ready: Box<dyn FnOnce(&mut Renderer)>,
 }
 
-/// Holds window-specific state such as size, scale factor, and GPU resources.
+/// Holds window-specific state such as size, the surface and input handling, and the
+/// `Renderer` drawing into it.
 pub struct WindowState {
     // window state
     window: std::sync::Arc<Window>,
     config: wgpu::SurfaceConfiguration,
     size: winit::dpi::PhysicalSize<u32>,
-    color: wgpu::Color,
     pub mouse_pressed: bool,
-    pub camera_controller: CameraController,
+    /// Last cursor position in physical pixels, for dragging the gizmo
+    cursor_position: cgmath::Vector2<f32>,
+    /// Set while a gizmo drag holds the light animator, which was running before
+    gizmo_paused_animator: bool,
+    /// How the hidden cursor is held while grabbed, see `set_cursor_grab`
+    cursor_grab: Option<CursorGrabMode>,
+    pub camera_controller: Box<dyn CameraController>,
+    /// Drives the camera instead of the controller while playing, see `play_path`
+    camera_path: Option<CameraPath>,
+    /// Set by `set_clear_color`, while `None` the cursor position tints the clear color
+    clear_color: Option<wgpu::Color>,
+    loading: Option<PendingLoad>,
+    /// The passes `render` records once customized with `render_graph_mut`, until then
+    /// it submits the renderer's `FrameCommands`
+    render_graph: Option<RenderGraph>,
+    /// Frames per second the event loop paces redraws to, see `set_target_fps`
+    target_fps: Option<u32>,
 
     // wgpu resource
     surface: wgpu::Surface<'static>,
-    device: wgpu::Device,
-    queue: wgpu::Queue,
-
-    // main pipeline
-    render_pipeline: wgpu::RenderPipeline,
-    wireframe_pipeline: wgpu::RenderPipeline,
-    projection: Projection,
-    instances: Vec<Instance>,
-    instance_buffer: wgpu::Buffer,
-    obj_model: model::Model,
-    depth_texture: texture::Texture,
-
-    // camera
-    camera: Camera,
-    camera_uniform: CameraUniform,
-    camera_buffer: wgpu::Buffer,
-    camera_bind_group: wgpu::BindGroup,
-
-    // skubox
-    environment_bind_group: wgpu::BindGroup,
-    sky_pipeline: wgpu::RenderPipeline,
-
-    // light
-    light: Light,
+
+    pub renderer: Renderer,
 }
 
-const NUM_INSTANCES_PER_ROW: u32 = 10;
 impl WindowState {
     pub async fn new(window: std::sync::Arc<Window>) -> Self {
+        Self::with_options(window, RendererOptions::default()).await
+    }
+
+    /// `new` with non-default renderer `options`, e.g. to enable reverse-Z or pick a surface
+    /// format that isn't sRGB.
+    pub async fn with_options(window: std::sync::Arc<Window>, options: RendererOptions) -> Self {
         let instance = wgpu::Instance::new(&wgpu::InstanceDescriptor {
             backends: wgpu::Backends::all(),
             flags: wgpu::InstanceFlags::default(),
@@ -147,404 +91,253 @@ impl WindowState {
             })
             .await
             .unwrap();
-        let (device, queue) = adapter
-            .request_device(&wgpu::DeviceDescriptor {
-                label: None,
-                required_features: wgpu::Features::POLYGON_MODE_LINE,
-                required_limits: wgpu::Limits::default(),
-                memory_hints: Default::default(),
-                trace: wgpu::Trace::Off,
-            })
-            .await
-            .unwrap();
+        let (device, queue) = Renderer::request_device(&adapter).await.unwrap();
 
         let size = window.inner_size();
         let surface_caps = surface.get_capabilities(&adapter);
-        let surface_format = surface_caps
-            .formats
-            .iter()
-            .copied()
-            .find(|f| f.is_srgb())
-            .unwrap_or(surface_caps.formats[0]);
+        let surface_format = options.surface_format.choose(&surface_caps.formats);
         let config = wgpu::SurfaceConfiguration {
             usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
             format: surface_format,
-            width: size.width,
-            height: size.height,
+            // A canvas may not be laid out yet, and surfaces can't be empty
+            width: size.width.max(1),
+            height: size.height.max(1),
             present_mode: surface_caps.present_modes[0],
             alpha_mode: surface_caps.alpha_modes[0],
             view_formats: vec![],
             desired_maximum_frame_latency: 2,
         };
         surface.configure(&device, &config);
-
-        let texture_bind_group_layout =
-            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-                entries: &[
-                    wgpu::BindGroupLayoutEntry {
-                        binding: 0,
-                        visibility: wgpu::ShaderStages::FRAGMENT,
-                        ty: wgpu::BindingType::Texture {
-                            sample_type: wgpu::TextureSampleType::Float { filterable: true },
-                            view_dimension: wgpu::TextureViewDimension::D2,
-                            multisampled: false,
-                        },
-                        count: None,
-                    },
-                    wgpu::BindGroupLayoutEntry {
-                        binding: 1,
-                        visibility: wgpu::ShaderStages::FRAGMENT,
-                        ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
-                        count: None,
-                    },
-                    wgpu::BindGroupLayoutEntry {
-                        binding: 2,
-                        visibility: wgpu::ShaderStages::FRAGMENT,
-                        ty: wgpu::BindingType::Texture {
-                            multisampled: false,
-                            sample_type: wgpu::TextureSampleType::Float { filterable: true },
-                            view_dimension: wgpu::TextureViewDimension::D2,
-                        },
-                        count: None,
-                    },
-                    wgpu::BindGroupLayoutEntry {
-                        binding: 3,
-                        visibility: wgpu::ShaderStages::FRAGMENT,
-                        ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
-                        count: None,
-                    },
-                ],
-                label: Some("texture_bind_group_layout"),
-            });
-
-        let camera = Camera::new((0.0, 5.0, 10.0), cgmath::Deg(-90.0), cgmath::Deg(-20.0));
-        let projection =
-            Projection::new(config.width, config.height, cgmath::Deg(45.0), 0.1, 100.0);
-        let camera_controller = CameraController::new(4.0, 0.2);
-
-        let mut camera_uniform = CameraUniform::new();
-        camera_uniform.update_view_proj(&camera, &projection);
-        let camera_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-            label: Some("Camera Buffer"),
-            contents: bytemuck::cast_slice(&[camera_uniform]),
-            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
-        });
-
-        let camera_bind_group_layout =
-            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-                entries: &[wgpu::BindGroupLayoutEntry {
-                    binding: 0,
-                    visibility: wgpu::ShaderStages::VERTEX_FRAGMENT,
-                    ty: wgpu::BindingType::Buffer {
-                        ty: wgpu::BufferBindingType::Uniform,
-                        has_dynamic_offset: false,
-                        min_binding_size: None,
-                    },
-                    count: None,
-                }],
-                label: Some("camera_bind_group_layout"),
-            });
-
-        let camera_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-            layout: &camera_bind_group_layout,
-            entries: &[wgpu::BindGroupEntry {
-                binding: 0,
-                resource: camera_buffer.as_entire_binding(),
-            }],
-            label: Some("camera_bind_group"),
-        });
-
-        let light = Light::new(
-            &device,
-            LightUniform::new([4.0, 2.0, 4.0], [1.0, 1.0, 1.0]),
+        log::info!(
+            "Surface format {:?}, present mode {:?}",
             config.format,
-            Some(texture::Texture::DEPTH_FORMAT),
-            &camera_bind_group_layout,
+            config.present_mode
         );
 
-        let depth_texture =
-            texture::Texture::create_depth_texture(&device, &config, "depth_texture");
-
-        let render_pipeline_layout =
-            device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
-                label: Some("Render Pipeline Layout"),
-                bind_group_layouts: &[
-                    &texture_bind_group_layout,
-                    &camera_bind_group_layout,
-                    &light.bind_group_layout,
-                ],
-                push_constant_ranges: &[],
-            });
-
-        let render_pipeline = {
-            let shader = wgpu::ShaderModuleDescriptor {
-                source: wgpu::ShaderSource::Wgsl(include_str!("../shader.wgsl").into()),
-                label: Some("Filled Shader"),
-            };
-            create_render_pipeline(
-                &device,
-                &render_pipeline_layout,
-                config.format,
-                Some(texture::Texture::DEPTH_FORMAT),
-                &[model::ModelVertex::desc(), InstanceRaw::desc()],
-                shader,
-                false, // Filled mode
-            )
-        };
-
-        let wireframe_pipeline = {
-            let shader = wgpu::ShaderModuleDescriptor {
-                source: wgpu::ShaderSource::Wgsl(include_str!("../wireframe.wgsl").into()),
-                label: Some("Wireframe Shader"),
-            };
-            create_render_pipeline(
-                &device,
-                &render_pipeline_layout,
-                config.format,
-                Some(texture::Texture::DEPTH_FORMAT),
-                &[model::ModelVertex::desc(), InstanceRaw::desc()],
-                shader,
-                true, // Wireframe mode
-            )
-        };
-
-        const SPACE_BETWEEN: f32 = 3.0;
-        let instances = (0..NUM_INSTANCES_PER_ROW)
-            .flat_map(|z| {
-                (0..NUM_INSTANCES_PER_ROW).map(move |x| {
-                    let x = SPACE_BETWEEN * (x as f32 - NUM_INSTANCES_PER_ROW as f32 / 2.0);
-                    let z = SPACE_BETWEEN * (z as f32 - NUM_INSTANCES_PER_ROW as f32 / 2.0);
-
-                    let position = cgmath::Vector3 { x, y: 0.0, z };
-
-                    let rotation = if position.is_zero() {
-                        cgmath::Quaternion::from_axis_angle(
-                            cgmath::Vector3::unit_z(),
-                            cgmath::Deg(0.0),
-                        )
-                    } else {
-                        cgmath::Quaternion::from_axis_angle(position.normalize(), cgmath::Deg(45.0))
-                    };
-
-                    Instance { position, rotation }
-                })
-            })
-            .collect::<Vec<_>>();
-
-        let instance_data = instances.iter().map(Instance::to_raw).collect::<Vec<_>>();
-        let instance_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-            label: Some("instance buffer"),
-            contents: bytemuck::cast_slice(&instance_data),
-            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
-        });
-
-        let obj_model =
-            resources::load_model("res/cube.obj", &device, &queue, &texture_bind_group_layout)
-                .await
-                .unwrap();
-
-        let sky_texture = resources::EquirectToCubemapConverter::new(&device)
-            .from_equirectangular_bytes(
-                &device,
-                &queue,
-                include_bytes!("../res/pure-sky.jpg"),
-                1080,
-                "sky texture",
-            )
-            .unwrap();
-
-        let environment_layout =
-            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-                label: Some("environment_layout"),
-                entries: &[
-                    wgpu::BindGroupLayoutEntry {
-                        binding: 0,
-                        visibility: wgpu::ShaderStages::VERTEX_FRAGMENT,
-                        ty: wgpu::BindingType::Texture {
-                            sample_type: wgpu::TextureSampleType::Float { filterable: false },
-                            view_dimension: wgpu::TextureViewDimension::Cube,
-                            multisampled: false,
-                        },
-                        count: None,
-                    },
-                    wgpu::BindGroupLayoutEntry {
-                        binding: 1,
-                        visibility: wgpu::ShaderStages::FRAGMENT,
-                        ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::NonFiltering),
-                        count: None,
-                    },
-                ],
-            });
-
-        let environment_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-            label: Some("environment_bind_group"),
-            layout: &environment_layout,
-            entries: &[
-                wgpu::BindGroupEntry {
-                    binding: 0,
-                    resource: wgpu::BindingResource::TextureView(sky_texture.view()),
-                },
-                wgpu::BindGroupEntry {
-                    binding: 1,
-                    resource: wgpu::BindingResource::Sampler(sky_texture.sampler()),
-                },
-            ],
-        });
-
-        let sky_pipeline = {
-            let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
-                label: Some("Sky Pipeline Layout"),
-                bind_group_layouts: &[&camera_bind_group_layout, &environment_layout],
-                push_constant_ranges: &[],
-            });
-            let shader = wgpu::include_wgsl!("../sky.wgsl");
-            create_render_pipeline(
-                &device,
-                &layout,
-                config.format,
-                Some(texture::Texture::DEPTH_FORMAT),
-                // None,
-                &[],
-                shader,
-                false, // Sky doesn't need wireframe
-            )
-        };
+        let renderer =
+            Renderer::with_options(adapter, device, queue, config.clone(), options).await;
+        let camera_controller: Box<dyn CameraController> =
+            Box::new(FlyCameraController::new(4.0, 0.2));
 
         Self {
             window,
             surface,
-            device,
-            queue,
             config,
             size,
-            render_pipeline,
-            wireframe_pipeline,
-            camera,
-            camera_uniform,
-            camera_bind_group,
-            camera_buffer,
-            camera_controller,
-            instances,
-            instance_buffer,
-            depth_texture,
-            light,
-            obj_model,
-            projection,
-            sky_pipeline,
-            environment_bind_group,
             mouse_pressed: false,
-            color: wgpu::Color {
-                r: 0.1,
-                g: 0.2,
-                b: 0.3,
-                a: 1.0,
-            },
+            cursor_position: cgmath::Vector2::new(0.0, 0.0),
+            gizmo_paused_animator: false,
+            cursor_grab: None,
+            camera_controller,
+            camera_path: None,
+            clear_color: None,
+            loading: None,
+            render_graph: None,
+            target_fps: None,
+            renderer,
         }
     }
 
-    pub fn render(&self) -> Result<(), wgpu::SurfaceError> {
-        use model::DrawLight;
-        use model::DrawModel;
-        self.window.request_redraw();
+    /// Draws and presents a frame, returning what it drew.
+    pub fn render(&self) -> Result<RenderStats, wgpu::SurfaceError> {
+        // With a frame rate cap the event loop asks for the next frame once it is due
+        if self.target_fps.is_none() {
+            self.window.request_redraw();
+        }
 
         let output = self.surface.get_current_texture()?;
         let view = output
             .texture
             .create_view(&wgpu::TextureViewDescriptor::default());
-
-        let mut encoder = self
-            .device
-            .create_command_encoder(&wgpu::CommandEncoderDescriptor {
-                label: Some("Render Encoder"),
-            });
-
-        {
-            let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
-                label: Some("Render Pass"),
-                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
-                    view: &view,
-                    resolve_target: None,
-                    ops: wgpu::Operations {
-                        load: wgpu::LoadOp::Clear(self.color),
-                        store: wgpu::StoreOp::Store,
-                    },
-                    depth_slice: None,
-                })],
-                depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
-                    view: &self.depth_texture.view,
-                    depth_ops: Some(wgpu::Operations {
-                        load: wgpu::LoadOp::Clear(1.0),
-                        store: wgpu::StoreOp::Store,
-                    }),
-                    stencil_ops: None,
-                }),
-                occlusion_query_set: None,
-                timestamp_writes: None,
-            });
-
-            // TODO: encapsulate it
-            render_pass.set_vertex_buffer(1, self.instance_buffer.slice(..));
-            render_pass.set_pipeline(&self.light.render_pipeline);
-            render_pass.draw_light_model(
-                &self.obj_model,
-                &self.camera_bind_group,
-                &self.light.bind_group,
-            );
-
-            // Draw filled objects first
-            render_pass.set_pipeline(&self.render_pipeline);
-            render_pass.draw_model_instanced(
-                &self.obj_model,
-                0..self.instances.len() as u32,
-                &self.camera_bind_group,
-                &self.light.bind_group,
-            );
-
-            // Draw wireframe overlay
-            render_pass.set_pipeline(&self.wireframe_pipeline);
-            render_pass.draw_model_instanced(
-                &self.obj_model,
-                0..self.instances.len() as u32,
-                &self.camera_bind_group,
-                &self.light.bind_group,
-            );
-
-            render_pass.set_pipeline(&self.sky_pipeline);
-            render_pass.set_bind_group(0, &self.camera_bind_group, &[]);
-            render_pass.set_bind_group(1, &self.environment_bind_group, &[]);
-            render_pass.draw(0..3, 0..1);
+        if self.loading.is_some() {
+            self.renderer.render_loading_screen(&view);
+        } else if let Some(graph) = &self.render_graph {
+            self.renderer.render_graph_to_view(graph, &view);
+        } else {
+            self.renderer.render_to_view(&view);
         }
-        self.queue.submit(iter::once(encoder.finish()));
         output.present();
 
-        Ok(())
+        Ok(self.renderer.render_stats())
     }
 
-    pub fn update(&mut self, dt: std::time::Duration) {
-        let old_position = cgmath::Vector3::from(self.light.uniform.position);
-        self.light.uniform.position = (cgmath::Quaternion::from_axis_angle(
-            (0.0, 1.0, 0.0).into(),
-            cgmath::Deg(60.0 * dt.as_secs_f32()),
-        ) * old_position)
-            .into();
-        self.queue.write_buffer(
-            &self.light.buffer,
-            0,
-            bytemuck::cast_slice(&[self.light.uniform]),
-        );
+    /// The customized passes drawing each frame, `None` while `render` submits the
+    /// renderer's own `FrameCommands`.
+    pub fn render_graph(&self) -> Option<&RenderGraph> {
+        self.render_graph.as_ref()
+    }
 
-        self.camera_controller.update_camera(&mut self.camera, dt);
-        self.camera_uniform
-            .update_view_proj(&self.camera, &self.projection);
-        self.queue.write_buffer(
-            &self.camera_buffer,
-            0,
-            bytemuck::cast_slice(&[self.camera_uniform]),
-        );
+    /// The passes drawing each frame, for reordering them or inserting new ones. Starts
+    /// out as `RenderGraph::new`, the same passes as the renderer's own frame, which
+    /// `render` then records in one encoder instead of submitting `FrameCommands`.
+    pub fn render_graph_mut(&mut self) -> &mut RenderGraph {
+        self.render_graph.get_or_insert_with(RenderGraph::new)
+    }
+
+    /// Asks for a `RedrawRequested` event. Without a frame rate cap `render` requests the
+    /// next one itself, so this only starts the loop.
+    pub fn request_redraw(&self) {
+        self.window.request_redraw();
+    }
+
+    pub fn update(&mut self, dt: std::time::Duration) {
+        if let Some(loading) = self.loading.as_mut() {
+            let progress = loading.loader.poll();
+            if !progress.is_done() {
+                self.renderer.prepare_loading_screen(progress);
+                return;
+            }
+            if let Some(loading) = self.loading.take() {
+                (loading.on_ready)(&mut self.renderer);
+            }
+        }
+        if let Some(path) = self.camera_path.as_mut() {
+            *self.renderer.camera_mut() = path.update(dt);
+            if path.is_finished() {
+                self.stop_path();
+            }
+        } else {
+            self.camera_controller
+                .update_camera(self.renderer.camera_mut(), dt);
+            self.camera_controller
+                .update_projection(self.renderer.projection_mut());
+        }
+        self.renderer.update(dt);
     }
 
     pub fn window_event(&mut self, event: winit::event::WindowEvent) -> bool {
         match event {
+            WindowEvent::KeyboardInput {
+                event:
+                    KeyEvent {
+                        physical_key: PhysicalKey::Code(KeyCode::F1),
+                        state: ElementState::Pressed,
+                        repeat: false,
+                        ..
+                    },
+                ..
+            } => {
+                self.renderer.toggle_wireframe();
+                true
+            }
+            WindowEvent::KeyboardInput {
+                event:
+                    KeyEvent {
+                        physical_key: PhysicalKey::Code(KeyCode::F2),
+                        state: ElementState::Pressed,
+                        repeat: false,
+                        ..
+                    },
+                ..
+            } => {
+                let visible = self.renderer.debug_lines_visible();
+                self.renderer.set_debug_lines_visible(!visible);
+                true
+            }
+            WindowEvent::KeyboardInput {
+                event:
+                    KeyEvent {
+                        physical_key: PhysicalKey::Code(KeyCode::F3),
+                        state: ElementState::Pressed,
+                        repeat: false,
+                        ..
+                    },
+                ..
+            } => {
+                let visible = self.renderer.stats_visible();
+                self.renderer.set_stats_visible(!visible);
+                true
+            }
+            WindowEvent::KeyboardInput {
+                event:
+                    KeyEvent {
+                        physical_key: PhysicalKey::Code(KeyCode::F4),
+                        state: ElementState::Pressed,
+                        repeat: false,
+                        ..
+                    },
+                ..
+            } => {
+                let visible = self.renderer.gizmo_visible();
+                self.renderer.set_gizmo_visible(!visible);
+                true
+            }
+            WindowEvent::KeyboardInput {
+                event:
+                    KeyEvent {
+                        physical_key: PhysicalKey::Code(KeyCode::F6),
+                        state: ElementState::Pressed,
+                        repeat: false,
+                        ..
+                    },
+                ..
+            } => {
+                let visible = self.renderer.grid_visible();
+                self.renderer.set_grid_visible(!visible);
+                true
+            }
+            WindowEvent::KeyboardInput {
+                event:
+                    KeyEvent {
+                        physical_key: PhysicalKey::Code(KeyCode::F7),
+                        state: ElementState::Pressed,
+                        repeat: false,
+                        ..
+                    },
+                ..
+            } => {
+                // Cycles through no weather, rain and snow
+                let next = match self.renderer.particle_system().map(|p| p.settings()) {
+                    None => Some(ParticleSettings::rain()),
+                    Some(settings) if settings == ParticleSettings::rain() => {
+                        Some(ParticleSettings::snow())
+                    }
+                    Some(_) => None,
+                };
+                if let Err(e) = self.renderer.set_weather(next) {
+                    log::warn!("Unable to change the weather: {e}");
+                }
+                true
+            }
+            WindowEvent::KeyboardInput {
+                event:
+                    KeyEvent {
+                        physical_key: PhysicalKey::Code(KeyCode::F8),
+                        state: ElementState::Pressed,
+                        repeat: false,
+                        ..
+                    },
+                ..
+            } => {
+                self.renderer.toggle_backface_culling();
+                true
+            }
+            WindowEvent::KeyboardInput {
+                event:
+                    KeyEvent {
+                        physical_key: PhysicalKey::Code(KeyCode::F5),
+                        state: ElementState::Pressed,
+                        repeat: false,
+                        ..
+                    },
+                ..
+            } => {
+                self.set_cursor_grab(!self.cursor_grabbed());
+                true
+            }
+            WindowEvent::KeyboardInput {
+                event:
+                    KeyEvent {
+                        physical_key: PhysicalKey::Code(KeyCode::F11),
+                        state: ElementState::Pressed,
+                        repeat: false,
+                        ..
+                    },
+                ..
+            } => {
+                self.toggle_fullscreen();
+                true
+            }
             WindowEvent::KeyboardInput {
                 event:
                     KeyEvent {
@@ -559,24 +352,127 @@ impl WindowState {
                 state,
                 ..
             } => {
-                self.mouse_pressed = state == ElementState::Pressed;
+                let pressed = state == ElementState::Pressed;
+                // Grabbing a gizmo arrow moves the light instead of looking around, with
+                // the light animator paused until it is let go
+                let grabbed = pressed
+                    && self.renderer.gizmo_visible()
+                    && self
+                        .renderer
+                        .gizmo_mut()
+                        .begin_drag(self.cursor_position)
+                        .is_some();
+                if grabbed {
+                    let mut animator = self.renderer.light_animator();
+                    self.gizmo_paused_animator = animator.enabled;
+                    animator.enabled = false;
+                    self.renderer.set_light_animator(animator);
+                } else {
+                    self.renderer.gizmo_mut().end_drag();
+                    self.mouse_pressed = pressed;
+                    // It orbits on from wherever the light was dropped
+                    if std::mem::take(&mut self.gizmo_paused_animator) {
+                        let mut animator = self.renderer.light_animator();
+                        animator.enabled = true;
+                        self.renderer.set_light_animator(animator);
+                    }
+                }
                 true
             }
             WindowEvent::MouseWheel { delta, .. } => {
-                self.camera_controller.handle_mouse_scroll(&delta);
+                if self.camera_path.is_none() {
+                    self.camera_controller.handle_mouse_scroll(&delta);
+                }
+                true
+            }
+            // Grabbing doesn't survive switching to another window on every platform
+            WindowEvent::Focused(false) => {
+                self.set_cursor_grab(false);
                 true
             }
             WindowEvent::CursorMoved { position, .. } => {
-                self.color.r = position.x / self.size.width as f64;
-                self.color.g = position.y / self.size.height as f64;
-                self.color.b = 0.5;
-                self.color.a = 1.0;
+                let cursor = cgmath::Vector2::new(position.x as f32, position.y as f32);
+                let delta = cursor - self.cursor_position;
+                self.cursor_position = cursor;
+                // A confined cursor would stop at the window's edge, keep it in the middle
+                if self.cursor_grab == Some(CursorGrabMode::Confined) {
+                    let center = winit::dpi::PhysicalPosition::new(
+                        self.size.width / 2,
+                        self.size.height / 2,
+                    );
+                    if position.cast::<u32>() != center
+                        && self.window.set_cursor_position(center).is_ok()
+                    {
+                        self.cursor_position =
+                            cgmath::Vector2::new(center.x as f32, center.y as f32);
+                    }
+                }
+                if self.renderer.gizmo_mut().dragging().is_some() {
+                    let light_position = self.renderer.gizmo_mut().handle_drag(delta);
+                    self.renderer.set_light_position(light_position);
+                }
+                if self.clear_color.is_none() {
+                    self.renderer.set_clear_color(wgpu::Color {
+                        r: position.x / self.size.width as f64,
+                        g: position.y / self.size.height as f64,
+                        b: 0.5,
+                        a: 1.0,
+                    });
+                }
                 true
             }
             _ => false,
         }
     }
 
+    /// Shows a loading screen with the progress of `loader` in place of the scene, until
+    /// all of its loads finished. `on_ready` then runs once, e.g. to take the results from
+    /// their `LoadHandle`s and hand them to the renderer.
+    pub fn start_loading(
+        &mut self,
+        loader: ResourceLoader,
+        on_ready: impl FnOnce(&mut Renderer) + 'static,
+    ) {
+        self.loading = Some(PendingLoad {
+            loader,
+            on_ready: Box::new(on_ready),
+        });
+    }
+
+    pub fn load_state(&self) -> LoadState {
+        if self.loading.is_some() {
+            LoadState::Loading
+        } else {
+            LoadState::Ready
+        }
+    }
+
+    /// Replaces the active camera controller, e.g. to switch between free-fly and orbit.
+    pub fn set_camera_controller(&mut self, controller: Box<dyn CameraController>) {
+        self.camera_controller = controller;
+    }
+
+    /// Flies the camera along `path` from its current time, with its speed and looping,
+    /// replacing any path already playing. The camera controller's input is ignored until
+    /// a path that doesn't loop ends or `stop_path` is called, after which the controller
+    /// picks up from where the path left the camera.
+    pub fn play_path(&mut self, path: CameraPath) {
+        self.camera_path = Some(path);
+    }
+
+    /// Stops the path playing, if any, and hands the camera back to the controller.
+    pub fn stop_path(&mut self) {
+        if self.camera_path.take().is_some() {
+            self.camera_controller
+                .sync_to_camera(self.renderer.camera());
+        }
+    }
+
+    /// The path playing, e.g. to change its speed or see how far it got.
+    pub fn camera_path_mut(&mut self) -> Option<&mut CameraPath> {
+        self.camera_path.as_mut()
+    }
+
     pub fn resize(&mut self, size: winit::dpi::PhysicalSize<u32>) {
         let width = size.width;
         let height = size.height;
@@ -584,11 +480,274 @@ impl WindowState {
             self.size = size;
             self.config.width = width;
             self.config.height = height;
-            self.surface.configure(&self.device, &self.config);
+            self.surface.configure(self.renderer.device(), &self.config);
+        }
+        self.renderer.resize(width, height);
+    }
+
+    /// Captures the current frame and writes it to `path`, e.g. a `.png`.
+    pub fn save_screenshot(&self, path: impl AsRef<std::path::Path>) -> anyhow::Result<()> {
+        self.renderer.save_screenshot(path)
+    }
+
+    /// Saves the current viewpoint to `path` as JSON, see `Renderer::save_camera`.
+    pub fn save_camera(&self, path: impl AsRef<std::path::Path>) -> anyhow::Result<()> {
+        self.renderer.save_camera(path)
+    }
+
+    /// Restores a viewpoint saved by `save_camera` and syncs the camera controller to it.
+    pub fn load_camera(&mut self, path: impl AsRef<std::path::Path>) -> anyhow::Result<()> {
+        self.renderer.load_camera(path)?;
+        self.camera_controller
+            .sync_to_camera(self.renderer.camera());
+        Ok(())
+    }
+
+    /// Points the camera at `model` and backs it off until the model fills the view, see
+    /// `Camera::frame_sphere`, with the camera controller following. The model is framed in
+    /// its own space, i.e. as it would be drawn without a transform. A model without any
+    /// vertices leaves the camera where it is and logs a warning.
+    pub fn frame_object(&mut self, model: &Model) {
+        if model.bounding_box().is_empty() {
+            log::warn!("Can't frame a model without vertices");
+            return;
+        }
+        let (center, radius) = model.bounding_sphere();
+        let mut projection = *self.renderer.projection();
+        self.renderer
+            .camera_mut()
+            .frame_sphere(&mut projection, center, radius);
+        *self.renderer.projection_mut() = projection;
+        self.camera_controller
+            .sync_to_focus(self.renderer.camera(), center);
+    }
+
+    /// Switches between an environment map and the procedural sky, rebinding the sky and
+    /// the image-based lighting baked from it, see `Renderer::set_environment`.
+    pub fn set_environment(&mut self, environment: Environment) {
+        self.renderer.set_environment(environment);
+    }
+
+    /// Splits the window between several cameras, see `Renderer::set_viewports`. The camera
+    /// controller keeps driving the main camera, not the viewports'.
+    pub fn set_viewports(&mut self, viewports: &[(Camera, Rect)]) {
+        self.renderer.set_viewports(viewports);
+    }
+
+    /// Clears the scene to `color` each frame, which then no longer follows the cursor
+    /// until `reset_clear_color`.
+    pub fn set_clear_color(&mut self, color: wgpu::Color) {
+        self.clear_color = Some(color);
+        self.renderer.set_clear_color(color);
+    }
+
+    /// Lets the cursor position tint the clear color again, undoing `set_clear_color`.
+    /// Takes effect on the next cursor move.
+    pub fn reset_clear_color(&mut self) {
+        self.clear_color = None;
+    }
+
+    /// Sets the depth buffer's clear value, see `Renderer::set_depth_clear`.
+    pub fn set_depth_clear(&mut self, depth: f32) {
+        self.renderer.set_depth_clear(depth);
+    }
+
+    /// Smooths edges with FXAA after tone mapping, see `Renderer::set_fxaa`.
+    pub fn set_fxaa(&mut self, enabled: bool) {
+        self.renderer.set_fxaa(enabled);
+    }
+
+    /// Outlines `ids`, replacing the previous selection, see `Renderer::set_selected`.
+    pub fn set_selected(&mut self, ids: &[ObjectId]) {
+        self.renderer.set_selected(ids);
+    }
+
+    /// Linear HDR color of the selection outline, its alpha the outline's opacity.
+    pub fn set_selection_color(&mut self, color: [f32; 4]) {
+        self.renderer.outline_mut().color = color;
+    }
+
+    /// Turns screen-space ambient occlusion on or off, see `Renderer::set_ssao`.
+    pub fn set_ssao(&mut self, enabled: bool) {
+        self.renderer.set_ssao(enabled);
+    }
+
+    /// Sets how the point light orbits the scene on `update`, e.g. to stop it.
+    pub fn set_light_animator(&mut self, animator: LightAnimator) {
+        self.renderer.set_light_animator(animator);
+    }
+
+    /// GPU time per pass of a recent frame, see `Renderer::last_gpu_times`.
+    pub fn last_gpu_times(&self) -> GpuTimings {
+        self.renderer.last_gpu_times()
+    }
+
+    /// Renders the current frame offscreen and reads it back, see
+    /// `Renderer::capture_frame`.
+    pub fn capture_frame(&self) -> anyhow::Result<image::RgbaImage> {
+        self.renderer.capture_frame()
+    }
+
+    /// The world position of the surface drawn under `screen` (in physical pixels) in the
+    /// last frame, or `None` if only the sky is there.
+    pub fn world_position_at(
+        &self,
+        screen: winit::dpi::PhysicalPosition<f64>,
+    ) -> Option<cgmath::Point3<f32>> {
+        self.renderer.world_position_at(screen)
+    }
+
+    /// The view frustum of the last update, for culling custom draws.
+    pub fn frustum(&self) -> &Frustum {
+        self.renderer.frustum()
+    }
+
+    pub fn sample_count(&self) -> u32 {
+        self.renderer.sample_count()
+    }
+
+    /// Switches MSAA to `samples` per pixel (1 disables it), see `Renderer::set_msaa`.
+    pub fn set_msaa(&mut self, samples: u32) -> anyhow::Result<()> {
+        self.renderer.set_msaa(samples)
+    }
+
+    /// Scales the scene's HDR colors before tone mapping, see `Renderer::set_exposure`.
+    pub fn set_exposure(&mut self, exposure: f32) {
+        self.renderer.set_exposure(exposure);
+    }
+
+    pub fn set_tonemap(&mut self, mode: TonemapMode) {
+        self.renderer.set_tonemap(mode);
+    }
+
+    /// Renders the scene at `scale` times the window size, see
+    /// `Renderer::set_render_scale`.
+    pub fn set_render_scale(&mut self, scale: f32) {
+        self.renderer.set_render_scale(scale);
+    }
+
+    /// Shows or hides the wireframe overlay, see `Renderer::set_wireframe`.
+    pub fn set_wireframe(&mut self, wireframe: bool) {
+        self.renderer.set_wireframe(wireframe);
+    }
+
+    pub fn toggle_wireframe(&mut self) {
+        self.renderer.toggle_wireframe();
+    }
+
+    /// Shows or hides the FPS, frame time and draw call overlay.
+    pub fn set_stats_visible(&mut self, visible: bool) {
+        self.renderer.set_stats_visible(visible);
+    }
+
+    /// Name, vendor, backend and driver of the GPU in use.
+    pub fn adapter_info(&self) -> wgpu::AdapterInfo {
+        self.renderer.adapter_info()
+    }
+
+    /// Features the device was created with, see `Renderer::enabled_features`.
+    pub fn enabled_features(&self) -> wgpu::Features {
+        self.renderer.enabled_features()
+    }
+
+    /// Whether mouse motion turns the camera, while the left button is held or the cursor
+    /// is grabbed.
+    pub fn mouse_look(&self) -> bool {
+        self.mouse_pressed || self.cursor_grab.is_some()
+    }
+
+    pub fn cursor_grabbed(&self) -> bool {
+        self.cursor_grab.is_some()
+    }
+
+    /// Hides the cursor and keeps it in the window, so every mouse motion turns the camera
+    /// like in a first person game, or releases it again. Locks the cursor in place where
+    /// the platform can, otherwise confines it to the window and moves it back to the
+    /// middle after each motion. Logs a warning and leaves the cursor free if neither
+    /// works.
+    pub fn set_cursor_grab(&mut self, grab: bool) {
+        if !grab {
+            if self.cursor_grab.take().is_some() {
+                if let Err(e) = self.window.set_cursor_grab(CursorGrabMode::None) {
+                    log::warn!("Unable to release the cursor {}", e);
+                }
+                self.window.set_cursor_visible(true);
+            }
+            return;
+        }
+        if self.cursor_grab.is_some() {
+            return;
+        }
+        let mode = [CursorGrabMode::Locked, CursorGrabMode::Confined]
+            .into_iter()
+            .find(|&mode| self.window.set_cursor_grab(mode).is_ok());
+        match mode {
+            Some(mode) => {
+                self.window.set_cursor_visible(false);
+                self.cursor_grab = Some(mode);
+            }
+            None => log::warn!("Unable to grab the cursor on this platform"),
         }
+    }
+
+    pub fn is_fullscreen(&self) -> bool {
+        self.window.fullscreen().is_some()
+    }
+
+    /// Switches between windowed and borderless fullscreen on the current monitor. The
+    /// window then receives a `Resized` event, which recreates the render targets.
+    pub fn toggle_fullscreen(&self) {
+        let fullscreen = (!self.is_fullscreen()).then_some(Fullscreen::Borderless(None));
+        self.window.set_fullscreen(fullscreen);
+    }
+
+    /// Format of the swapchain textures, of the kind `RendererOptions::surface_format`
+    /// prefers if the surface supports it. The scene itself renders in
+    /// `Tonemapper::HDR_FORMAT`.
+    pub fn surface_format(&self) -> wgpu::TextureFormat {
+        self.config.format
+    }
+
+    pub fn present_mode(&self) -> wgpu::PresentMode {
+        self.config.present_mode
+    }
+
+    /// Present modes the surface supports on this adapter.
+    pub fn supported_present_modes(&self) -> Vec<wgpu::PresentMode> {
+        self.surface
+            .get_capabilities(self.renderer.adapter())
+            .present_modes
+    }
+
+    /// Reconfigures the surface to present with `mode`. Unsupported modes fall back to
+    /// `Fifo`, which every surface supports, and log a warning.
+    pub fn set_present_mode(&mut self, mode: wgpu::PresentMode) {
+        let mode = if self.supported_present_modes().contains(&mode) {
+            mode
+        } else {
+            log::warn!("Present mode {mode:?} is not supported, falling back to Fifo");
+            wgpu::PresentMode::Fifo
+        };
+        self.config.present_mode = mode;
+        self.surface.configure(self.renderer.device(), &self.config);
+    }
+
+    pub fn target_fps(&self) -> Option<u32> {
+        self.target_fps
+    }
+
+    /// Caps the frame rate at `fps`, or lifts the cap with `None`. While capped `render`
+    /// stops requesting the next frame right away and the event loop waits until it is due,
+    /// see `frame_interval`, instead of spinning the GPU.
+    pub fn set_target_fps(&mut self, fps: Option<u32>) {
+        self.target_fps = fps.filter(|&fps| fps > 0);
+        // Restarts the loop when the cap is lifted
+        self.window.request_redraw();
+    }
 
-        self.depth_texture =
-            texture::Texture::create_depth_texture(&self.device, &self.config, "depth_texture");
-        self.projection.resize(width, height);
+    /// Time between the starts of two frames at the target frame rate, `None` when uncapped.
+    pub fn frame_interval(&self) -> Option<std::time::Duration> {
+        self.target_fps
+            .map(|fps| std::time::Duration::from_secs(1) / fps)
     }
 }
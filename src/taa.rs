@@ -0,0 +1,341 @@
+use cgmath::Vector2;
+use wgpu::util::DeviceExt as _;
+
+use crate::{hot_reload::wgsl, texture, tonemap::Tonemapper};
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
+struct TaaUniform {
+    jitter_delta: [f32; 2],
+    blend: f32,
+    reset: u32,
+}
+
+/// Temporal anti-aliasing: the projection is offset by a different fraction of a pixel every
+/// frame, see `Projection::set_jitter`, and each frame is blended into a history of the
+/// earlier ones, which converges on an image sampled many times per pixel.
+///
+/// The history is looked up where each pixel was in the last frame through
+/// `MotionVectors::velocity`, and clamped to the colors around the pixel in the current
+/// frame so that surfaces that got uncovered don't smear. The resolved frame is written into
+/// the next of two history textures and copied back onto the HDR target before bloom and
+/// tone mapping, so everything after it sees the anti-aliased image. The UI is drawn after
+/// tone mapping without the scene's projection, so it isn't jittered.
+pub struct Taa {
+    uniform: TaaUniform,
+    buffer: wgpu::Buffer,
+    bind_group_layout: wgpu::BindGroupLayout,
+    sampler: wgpu::Sampler,
+    pipeline: wgpu::RenderPipeline,
+    history: [texture::Texture; 2],
+    /// `bind_groups[i]` reads `history[1 - i]` while the pass writes `history[i]`
+    bind_groups: [wgpu::BindGroup; 2],
+    /// Index of the history written this frame
+    write: usize,
+    frame: u32,
+    /// The jitter of this frame in NDC
+    jitter: Vector2<f32>,
+    /// Whether the history holds an earlier frame
+    has_history: bool,
+}
+
+impl Taa {
+    /// Length of the Halton (2, 3) sequence the jitter cycles through
+    pub const SAMPLES: u32 = 8;
+    pub const DEFAULT_BLEND: f32 = 0.1;
+
+    /// TAA for the HDR target bound from `current`, of `width` x `height`, reprojected by
+    /// `velocity`. `blend` is the weight of each new frame, see `set_blend`.
+    pub fn new(
+        device: &wgpu::Device,
+        current: &wgpu::TextureView,
+        velocity: &wgpu::TextureView,
+        width: u32,
+        height: u32,
+        blend: f32,
+    ) -> Self {
+        let uniform = TaaUniform {
+            jitter_delta: [0.0; 2],
+            blend: blend.clamp(0.0, 1.0),
+            reset: 1,
+        };
+        let buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("TAA Buffer"),
+            contents: bytemuck::cast_slice(&[uniform]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+
+        let texture_entry = |binding, filterable| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Texture {
+                multisampled: false,
+                view_dimension: wgpu::TextureViewDimension::D2,
+                sample_type: wgpu::TextureSampleType::Float { filterable },
+            },
+            count: None,
+        };
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                texture_entry(0, true),
+                texture_entry(1, true),
+                texture_entry(2, false),
+                wgpu::BindGroupLayoutEntry {
+                    binding: 3,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 4,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ],
+            label: Some("taa_bind_group_layout"),
+        });
+        let pipeline = Self::create_pipeline(device, &bind_group_layout);
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("TAA Sampler"),
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
+
+        let history = Self::create_history(device, width, height);
+        let bind_groups = Self::create_bind_groups(
+            device,
+            &bind_group_layout,
+            &sampler,
+            &buffer,
+            &history,
+            current,
+            velocity,
+        );
+        Self {
+            uniform,
+            buffer,
+            bind_group_layout,
+            sampler,
+            pipeline,
+            history,
+            bind_groups,
+            write: 0,
+            frame: 0,
+            jitter: Vector2::new(0.0, 0.0),
+            has_history: false,
+        }
+    }
+
+    fn create_pipeline(
+        device: &wgpu::Device,
+        bind_group_layout: &wgpu::BindGroupLayout,
+    ) -> wgpu::RenderPipeline {
+        let shader = device.create_shader_module(wgsl!("src/taa.wgsl", "src/fullscreen.wgsl"));
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("TAA Pipeline Layout"),
+            bind_group_layouts: &[bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("TAA Pipeline"),
+            layout: Some(&pipeline_layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: Some("vs_main"),
+                buffers: &[],
+                compilation_options: Default::default(),
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: Some("fs_main"),
+                targets: &[Some(wgpu::ColorTargetState {
+                    format: Tonemapper::HDR_FORMAT,
+                    blend: None,
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+                compilation_options: Default::default(),
+            }),
+            primitive: wgpu::PrimitiveState::default(),
+            depth_stencil: None,
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+            cache: None,
+        })
+    }
+
+    /// Rebuilds the pipeline from the current shader, see `hot_reload`.
+    pub fn recreate_pipeline(&mut self, device: &wgpu::Device) {
+        self.pipeline = Self::create_pipeline(device, &self.bind_group_layout);
+    }
+
+    fn create_history(device: &wgpu::Device, width: u32, height: u32) -> [texture::Texture; 2] {
+        let size = wgpu::Extent3d {
+            width: width.max(1),
+            height: height.max(1),
+            depth_or_array_layers: 1,
+        };
+        [0, 1].map(|_| {
+            texture::Texture::create_texture(
+                device,
+                Some("taa_history_texture"),
+                size,
+                Tonemapper::HDR_FORMAT,
+                wgpu::TextureUsages::RENDER_ATTACHMENT
+                    | wgpu::TextureUsages::TEXTURE_BINDING
+                    | wgpu::TextureUsages::COPY_SRC,
+                wgpu::TextureDimension::D2,
+                wgpu::FilterMode::Linear,
+            )
+        })
+    }
+
+    fn create_bind_groups(
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        sampler: &wgpu::Sampler,
+        buffer: &wgpu::Buffer,
+        history: &[texture::Texture; 2],
+        current: &wgpu::TextureView,
+        velocity: &wgpu::TextureView,
+    ) -> [wgpu::BindGroup; 2] {
+        [1, 0].map(|read: usize| {
+            device.create_bind_group(&wgpu::BindGroupDescriptor {
+                layout,
+                entries: &[
+                    wgpu::BindGroupEntry {
+                        binding: 0,
+                        resource: wgpu::BindingResource::TextureView(current),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 1,
+                        resource: wgpu::BindingResource::TextureView(&history[read].view),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 2,
+                        resource: wgpu::BindingResource::TextureView(velocity),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 3,
+                        resource: wgpu::BindingResource::Sampler(sampler),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 4,
+                        resource: buffer.as_entire_binding(),
+                    },
+                ],
+                label: Some("taa_bind_group"),
+            })
+        })
+    }
+
+    /// Recreates the history for a new HDR target or velocity texture and starts it over.
+    /// Call it whenever either is recreated.
+    pub fn resize(
+        &mut self,
+        device: &wgpu::Device,
+        current: &wgpu::TextureView,
+        velocity: &wgpu::TextureView,
+        width: u32,
+        height: u32,
+    ) {
+        self.history = Self::create_history(device, width, height);
+        self.bind_groups = Self::create_bind_groups(
+            device,
+            &self.bind_group_layout,
+            &self.sampler,
+            &self.buffer,
+            &self.history,
+            current,
+            velocity,
+        );
+        self.has_history = false;
+    }
+
+    pub fn blend(&self) -> f32 {
+        self.uniform.blend
+    }
+
+    /// Sets how much of each new frame goes into the history, clamped to `0.0..=1.0`. Lower
+    /// values smooth edges over more frames but ghost more behind moving objects, 1 turns
+    /// the accumulation off while keeping the jitter.
+    pub fn set_blend(&mut self, queue: &wgpu::Queue, blend: f32) {
+        self.uniform.blend = blend.clamp(0.0, 1.0);
+        self.upload(queue);
+    }
+
+    /// Moves on to the next frame of a `width` x `height` target and returns its jitter in
+    /// NDC, to pass to `Projection::set_jitter` before the camera is uploaded. Call it once
+    /// per frame before `apply`.
+    pub fn next_frame(&mut self, queue: &wgpu::Queue, width: u32, height: u32) -> Vector2<f32> {
+        self.frame = self.frame.wrapping_add(1);
+        self.write = 1 - self.write;
+        let index = self.frame % Self::SAMPLES + 1;
+        // Within the center pixel, from -0.5 to 0.5 pixels
+        let jitter = Vector2::new(
+            (halton(index, 2) - 0.5) * 2.0 / width.max(1) as f32,
+            (halton(index, 3) - 0.5) * 2.0 / height.max(1) as f32,
+        );
+        // The motion vectors are measured between the jittered projections
+        let delta = jitter - self.jitter;
+        self.uniform.jitter_delta = [delta.x * 0.5, delta.y * -0.5];
+        self.uniform.reset = (!self.has_history) as u32;
+        self.jitter = jitter;
+        self.has_history = true;
+        self.upload(queue);
+        jitter
+    }
+
+    fn upload(&self, queue: &wgpu::Queue) {
+        queue.write_buffer(&self.buffer, 0, bytemuck::cast_slice(&[self.uniform]));
+    }
+
+    /// Resolves this frame into the history and copies it onto `target`, the HDR target
+    /// bound at creation or the last `resize`. Returns the number of draw calls.
+    pub fn apply(&self, encoder: &mut wgpu::CommandEncoder, target: &wgpu::Texture) -> u32 {
+        let history = &self.history[self.write];
+        {
+            let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("TAA Pass"),
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view: &history.view,
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                        store: wgpu::StoreOp::Store,
+                    },
+                    depth_slice: None,
+                })],
+                depth_stencil_attachment: None,
+                occlusion_query_set: None,
+                timestamp_writes: None,
+            });
+            pass.set_pipeline(&self.pipeline);
+            pass.set_bind_group(0, &self.bind_groups[self.write], &[]);
+            pass.draw(0..3, 0..1);
+        }
+        encoder.copy_texture_to_texture(
+            history.texture.as_image_copy(),
+            target.as_image_copy(),
+            history.size,
+        );
+        1
+    }
+}
+
+/// Element `index` of the Halton sequence in `base`, evenly spread over 0..1
+fn halton(mut index: u32, base: u32) -> f32 {
+    let mut result = 0.0;
+    let mut fraction = 1.0;
+    while index > 0 {
+        fraction /= base as f32;
+        result += fraction * (index % base) as f32;
+        index /= base;
+    }
+    result
+}
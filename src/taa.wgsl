@@ -0,0 +1,57 @@
+// Temporal anti-aliasing: blends each pixel of the jittered frame into the history of
+// earlier frames, found where the pixel was last frame through its motion vector. The
+// history is clamped to the colors around the pixel this frame, so what moved in or out of
+// view doesn't leave ghosts behind.
+
+struct Taa {
+  // Change of the jitter since last frame in UV units, which the motion vectors include
+  jitter_delta: vec2<f32>,
+  // Weight of the current frame, the rest is history
+  blend: f32,
+  // Set while there is no history yet, e.g. after a resize
+  reset: u32,
+};
+
+// `vs_main` and `FullscreenOutput` are appended from `src/fullscreen.wgsl`
+
+@group(0) @binding(0)
+var t_current: texture_2d<f32>;
+@group(0) @binding(1)
+var t_history: texture_2d<f32>;
+@group(0) @binding(2)
+var t_velocity: texture_2d<f32>;
+@group(0) @binding(3)
+var s_history: sampler;
+@group(0) @binding(4)
+var<uniform> taa: Taa;
+
+@fragment
+fn fs_main(in: FullscreenOutput) -> @location(0) vec4<f32> {
+  let size = vec2<i32>(textureDimensions(t_current));
+  let texel = vec2<i32>(in.pos.xy);
+  let current = textureLoad(t_current, texel, 0);
+  if taa.reset != 0u {
+    return current;
+  }
+
+  // Bounds of the 3x3 neighborhood
+  var lo = current.rgb;
+  var hi = current.rgb;
+  for (var y = -1; y <= 1; y += 1) {
+    for (var x = -1; x <= 1; x += 1) {
+      let neighbor = textureLoad(t_current, clamp(texel + vec2<i32>(x, y), vec2<i32>(0), size - 1), 0).rgb;
+      lo = min(lo, neighbor);
+      hi = max(hi, neighbor);
+    }
+  }
+
+  // Nothing was drawn where the velocity is exactly zero, e.g. the sky, which stays put
+  let velocity = textureLoad(t_velocity, texel, 0).xy;
+  let motion = select(velocity - taa.jitter_delta, vec2<f32>(0.0), all(velocity == vec2<f32>(0.0)));
+  let history_uv = in.pos.xy / vec2<f32>(size) - motion;
+  if any(history_uv < vec2<f32>(0.0)) || any(history_uv > vec2<f32>(1.0)) {
+    return current;
+  }
+  let history = clamp(textureSampleLevel(t_history, s_history, history_uv, 0.0).rgb, lo, hi);
+  return vec4<f32>(mix(history, current.rgb, taa.blend), current.a);
+}
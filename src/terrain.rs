@@ -0,0 +1,1009 @@
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{Arc, Mutex, PoisonError, mpsc},
+    thread,
+};
+
+use cgmath::{InnerSpace, MetricSpace, Point3, Vector2, Vector3};
+use noise::{Fbm, MultiFractal, NoiseFn, Perlin, Simplex};
+use wgpu::util::DeviceExt as _;
+
+use crate::{
+    frustum::{Aabb, Frustum},
+    model, resources,
+};
+
+/// How `Terrain::from_heights` shades the grid.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TerrainNormals {
+    /// One normal per triangle, so every face is shaded flat. Triangles don't share
+    /// vertices then, which takes about six times as many.
+    Faceted,
+    /// Normals shared by the triangles around each sample, from the slope between its
+    /// neighbors. Before that the heights are box blurred over `smoothing` samples in every
+    /// direction, which hides noise of rough data in the shading without moving any
+    /// vertex. 0 takes the heights as they are.
+    Smooth { smoothing: u32 },
+}
+
+impl Default for TerrainNormals {
+    fn default() -> Self {
+        Self::Smooth { smoothing: 0 }
+    }
+}
+
+/// A regular grid of heights that can be turned into a renderable `Model`. Shading streamed
+/// terrain by a colormap or with contour lines is up to the renderer, whose one
+/// `SplatMaterial` draws every chunk, see `Renderer::set_terrain_colormap`,
+/// `Renderer::set_terrain_contours` and `Renderer::terrain_splat_mut`.
+pub struct Terrain {
+    width: u32,
+    depth: u32,
+    /// World-space (x, z) of the first sample
+    origin: Vector2<f32>,
+    scale: Vector3<f32>,
+    /// Heights in world units, row-major with `width` samples per row
+    heights: Vec<f32>,
+    model: model::Model,
+}
+
+impl Terrain {
+    /// Builds a terrain from a grayscale heightmap. Each pixel becomes a grid vertex
+    /// `scale.x`/`scale.z` apart, with white raised to `scale.y`. 8-bit heights step
+    /// visibly on gentle slopes, which `TerrainNormals::Smooth` can blur out of the shading.
+    ///
+    /// Returns the `Terrain` rather than just its `Model` so the heights stay around for
+    /// `height_at` and `raycast`, take the mesh with `model` or `into_model`.
+    pub fn from_heightmap(
+        device: &wgpu::Device,
+        heightmap: &image::GrayImage,
+        scale: Vector3<f32>,
+        normals: TerrainNormals,
+        material: model::Material,
+    ) -> Self {
+        let (width, depth) = heightmap.dimensions();
+        let heights = heightmap
+            .pixels()
+            .map(|p| p.0[0] as f32 / 255.0 * scale.y)
+            .collect::<Vec<_>>();
+        Self::from_heights(device, width, depth, heights, scale, normals, material)
+    }
+
+    /// Builds a terrain from heights already in world units.
+    pub fn from_heights(
+        device: &wgpu::Device,
+        width: u32,
+        depth: u32,
+        heights: Vec<f32>,
+        scale: Vector3<f32>,
+        normals: TerrainNormals,
+        material: model::Material,
+    ) -> Self {
+        assert!(
+            width >= 2 && depth >= 2,
+            "terrain needs at least 2x2 samples"
+        );
+        assert_eq!(heights.len(), (width * depth) as usize);
+
+        let origin = Vector2::new(0.0, 0.0);
+        // Past the edges there is nothing to sample, so clamp
+        let clamped = |heights: &[f32], x: i64, z: i64| {
+            let x = x.clamp(0, width as i64 - 1) as u32;
+            let z = z.clamp(0, depth as i64 - 1) as u32;
+            heights[(z * width + x) as usize]
+        };
+        let (mut vertices, mut indices) =
+            build_grid_mesh(width, depth, origin, scale, |x, z| clamped(&heights, x, z));
+        match normals {
+            TerrainNormals::Faceted => (vertices, indices) = facet_mesh(&vertices, &indices),
+            TerrainNormals::Smooth { smoothing: 0 } => {}
+            TerrainNormals::Smooth { smoothing } => {
+                let blurred = blur_heights(&heights, width, depth, smoothing);
+                for (i, vertex) in vertices.iter_mut().enumerate() {
+                    let (x, z) = ((i as u32 % width) as i64, (i as u32 / width) as i64);
+                    vertex.normal = grid_normal(|x, z| clamped(&blurred, x, z), x, z, scale).into();
+                }
+            }
+        }
+        let model = upload_mesh(device, "terrain", &vertices, &indices, material);
+        Self {
+            width,
+            depth,
+            origin,
+            scale,
+            heights,
+            model,
+        }
+    }
+
+    /// The grid mesh, ready for the regular model draw path.
+    pub fn model(&self) -> &model::Model {
+        &self.model
+    }
+
+    /// Drops the heights and keeps only the mesh.
+    pub fn into_model(self) -> model::Model {
+        self.model
+    }
+
+    /// Number of samples along the x axis.
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    /// Number of samples along the z axis.
+    pub fn depth(&self) -> u32 {
+        self.depth
+    }
+
+    pub fn origin(&self) -> Vector2<f32> {
+        self.origin
+    }
+
+    pub fn scale(&self) -> Vector3<f32> {
+        self.scale
+    }
+
+    /// Heights in world units, row-major with `width` samples per row.
+    pub fn heights(&self) -> &[f32] {
+        &self.heights
+    }
+
+    /// Bilinearly interpolated height at world position `(x, z)`, clamped to the terrain edges.
+    pub fn height_at(&self, x: f32, z: f32) -> f32 {
+        let gx = ((x - self.origin.x) / self.scale.x).clamp(0.0, (self.width - 1) as f32);
+        let gz = ((z - self.origin.y) / self.scale.z).clamp(0.0, (self.depth - 1) as f32);
+        let x0 = (gx.floor() as u32).min(self.width - 2);
+        let z0 = (gz.floor() as u32).min(self.depth - 2);
+        let tx = gx - x0 as f32;
+        let tz = gz - z0 as f32;
+
+        let h00 = self.sample(x0, z0);
+        let h10 = self.sample(x0 + 1, z0);
+        let h01 = self.sample(x0, z0 + 1);
+        let h11 = self.sample(x0 + 1, z0 + 1);
+        let h0 = h00 + (h10 - h00) * tx;
+        let h1 = h01 + (h11 - h01) * tx;
+        h0 + (h1 - h0) * tz
+    }
+
+    fn sample(&self, x: u32, z: u32) -> f32 {
+        self.heights[(z * self.width + x) as usize]
+    }
+
+    /// World position of grid sample `(x, z)`, a vertex of the mesh.
+    fn vertex(&self, x: u32, z: u32) -> Point3<f32> {
+        Point3::new(
+            self.origin.x + x as f32 * self.scale.x,
+            self.sample(x, z),
+            self.origin.y + z as f32 * self.scale.z,
+        )
+    }
+
+    /// Casts a ray from `origin` along `direction` against the triangles of the terrain's
+    /// mesh and returns the first hit within `max_dist` world units, hitting from above or
+    /// below. Walks the grid cells under the ray in order with a DDA, so the cost grows
+    /// with the length of the ray over the terrain rather than its size. Only reads the
+    /// heights, giving the same result on any machine whatever is rendered.
+    pub fn raycast(
+        &self,
+        origin: Point3<f32>,
+        direction: Vector3<f32>,
+        max_dist: f32,
+    ) -> Option<RayHit> {
+        if direction.magnitude2() == 0.0 || max_dist.is_nan() {
+            return None;
+        }
+        let direction = direction.normalize();
+
+        // Ray in grid units, one per sample on x and z, with t still in world units
+        let start = Vector2::new(
+            (origin.x - self.origin.x) / self.scale.x,
+            (origin.z - self.origin.y) / self.scale.z,
+        );
+        let step = Vector2::new(direction.x / self.scale.x, direction.z / self.scale.z);
+        let cells = Vector2::new((self.width - 1) as f32, (self.depth - 1) as f32);
+
+        // Clip the ray to the grid's footprint
+        let (mut t_min, mut t_max) = (0.0f32, max_dist);
+        for axis in 0..2 {
+            if step[axis] == 0.0 {
+                if start[axis] < 0.0 || start[axis] > cells[axis] {
+                    return None;
+                }
+            } else {
+                let t0 = -start[axis] / step[axis];
+                let t1 = (cells[axis] - start[axis]) / step[axis];
+                t_min = t_min.max(t0.min(t1));
+                t_max = t_max.min(t0.max(t1));
+            }
+        }
+        if t_min > t_max {
+            return None;
+        }
+
+        let entry = start + step * t_min;
+        let mut cell =
+            [0, 1].map(|axis| (entry[axis].floor().max(0.0) as u32).min(cells[axis] as u32 - 1));
+        // Distance along the ray to the next cell boundary on each axis, and between them
+        let mut t_next = [0, 1].map(|axis| match step[axis] {
+            s if s > 0.0 => (cell[axis] as f32 + 1.0 - start[axis]) / s,
+            s if s < 0.0 => (cell[axis] as f32 - start[axis]) / s,
+            _ => f32::INFINITY,
+        });
+        let t_delta = [0, 1].map(|axis| 1.0 / step[axis].abs());
+
+        loop {
+            if let Some(hit) = self.raycast_cell(cell, origin, direction, t_min, t_max) {
+                return Some(hit);
+            }
+            let axis = if t_next[0] < t_next[1] { 0 } else { 1 };
+            if t_next[axis] > t_max {
+                return None;
+            }
+            if step[axis] > 0.0 {
+                cell[axis] += 1;
+                if cell[axis] >= cells[axis] as u32 {
+                    return None;
+                }
+            } else {
+                if cell[axis] == 0 {
+                    return None;
+                }
+                cell[axis] -= 1;
+            }
+            t_next[axis] += t_delta[axis];
+        }
+    }
+
+    /// The nearest hit between `t_min` and `t_max` on the two triangles of grid cell
+    /// `[x, z]`, split like `build_grid_mesh` does.
+    fn raycast_cell(
+        &self,
+        [x, z]: [u32; 2],
+        origin: Point3<f32>,
+        direction: Vector3<f32>,
+        t_min: f32,
+        t_max: f32,
+    ) -> Option<RayHit> {
+        let v00 = self.vertex(x, z);
+        let v10 = self.vertex(x + 1, z);
+        let v01 = self.vertex(x, z + 1);
+        let v11 = self.vertex(x + 1, z + 1);
+        [[v00, v01, v10], [v10, v01, v11]]
+            .into_iter()
+            .filter_map(|[a, b, c]| {
+                let distance = intersect_triangle(origin, direction, a, b, c)?;
+                (t_min..=t_max).contains(&distance).then(|| RayHit {
+                    position: origin + direction * distance,
+                    // Counter-clockwise from above, so this points up
+                    normal: (b - a).cross(c - a).normalize(),
+                    distance,
+                })
+            })
+            .min_by(|a, b| a.distance.total_cmp(&b.distance))
+    }
+}
+
+/// Where a ray cast with `Terrain::raycast` hits the terrain.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RayHit {
+    pub position: Point3<f32>,
+    /// Upward normal of the triangle hit
+    pub normal: Vector3<f32>,
+    /// World units from the ray's origin to `position`
+    pub distance: f32,
+}
+
+/// Distance along the normalized `direction` at which the ray from `origin` crosses the
+/// triangle `a`, `b`, `c` from either side (Möller-Trumbore).
+fn intersect_triangle(
+    origin: Point3<f32>,
+    direction: Vector3<f32>,
+    a: Point3<f32>,
+    b: Point3<f32>,
+    c: Point3<f32>,
+) -> Option<f32> {
+    let edge1 = b - a;
+    let edge2 = c - a;
+    let p = direction.cross(edge2);
+    let det = edge1.dot(p);
+    // Parallel to the triangle's plane
+    if det.abs() < f32::EPSILON {
+        return None;
+    }
+    let inv_det = 1.0 / det;
+    let s = origin - a;
+    let u = s.dot(p) * inv_det;
+    if !(0.0..=1.0).contains(&u) {
+        return None;
+    }
+    let q = s.cross(edge1);
+    let v = direction.dot(q) * inv_det;
+    if v < 0.0 || u + v > 1.0 {
+        return None;
+    }
+    Some(edge2.dot(q) * inv_det)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NoiseKind {
+    Perlin,
+    Simplex,
+}
+
+/// Fractal Brownian motion parameters, see `noise::Fbm`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FbmParams {
+    pub kind: NoiseKind,
+    pub octaves: usize,
+    /// Frequency of the first octave, in cycles per world unit
+    pub frequency: f64,
+    /// Frequency multiplier between octaves
+    pub lacunarity: f64,
+    /// Amplitude multiplier between octaves
+    pub persistence: f64,
+    /// Scales the roughly -1..1 noise output into world units
+    pub amplitude: f32,
+}
+
+impl Default for FbmParams {
+    fn default() -> Self {
+        Self {
+            kind: NoiseKind::Perlin,
+            octaves: 6,
+            frequency: 0.01,
+            lacunarity: 2.0,
+            persistence: 0.5,
+            amplitude: 20.0,
+        }
+    }
+}
+
+enum FbmNoise {
+    Perlin(Fbm<Perlin>),
+    Simplex(Fbm<Simplex>),
+}
+
+/// Procedural, unbounded terrain. Heights are a pure function of the seed, parameters
+/// and world position, so any region can be generated independently and always agrees
+/// with `height_at`.
+pub struct NoiseTerrain {
+    seed: u32,
+    params: FbmParams,
+    noise: FbmNoise,
+}
+
+impl NoiseTerrain {
+    pub fn new(seed: u32, params: FbmParams) -> Self {
+        let noise = match params.kind {
+            NoiseKind::Perlin => FbmNoise::Perlin(Self::configure(Fbm::new(seed), &params)),
+            NoiseKind::Simplex => FbmNoise::Simplex(Self::configure(Fbm::new(seed), &params)),
+        };
+        Self {
+            seed,
+            params,
+            noise,
+        }
+    }
+
+    fn configure<T: MultiFractal>(fbm: T, params: &FbmParams) -> T {
+        fbm.set_octaves(params.octaves)
+            .set_frequency(params.frequency)
+            .set_lacunarity(params.lacunarity)
+            .set_persistence(params.persistence)
+    }
+
+    pub fn seed(&self) -> u32 {
+        self.seed
+    }
+
+    pub fn params(&self) -> FbmParams {
+        self.params
+    }
+
+    /// Height in world units at world position `(x, z)`.
+    pub fn height_at(&self, x: f32, z: f32) -> f32 {
+        let point = [x as f64, z as f64];
+        let value = match &self.noise {
+            FbmNoise::Perlin(fbm) => fbm.get(point),
+            FbmNoise::Simplex(fbm) => fbm.get(point),
+        };
+        value as f32 * self.params.amplitude
+    }
+
+    /// Samples a `width` x `depth` grid starting at world `(origin.x, origin.y)` with
+    /// `spacing` world units between samples and builds a terrain from it.
+    pub fn generate(
+        &self,
+        device: &wgpu::Device,
+        origin: Vector2<f32>,
+        width: u32,
+        depth: u32,
+        spacing: f32,
+        material: model::Material,
+    ) -> Terrain {
+        mesh_region(self, origin, width, depth, spacing, self.params.amplitude).upload(
+            device,
+            "noise terrain",
+            material,
+        )
+    }
+
+    /// Generates a square chunk `size` world units across with one terrain per entry in
+    /// `resolutions`, finest first. Each LOD spans the same area with `resolution` samples
+    /// per side, and `material` is called once per LOD.
+    pub fn generate_chunk(
+        &self,
+        device: &wgpu::Device,
+        origin: Vector2<f32>,
+        size: f32,
+        resolutions: &[u32],
+        mut material: impl FnMut() -> model::Material,
+    ) -> TerrainChunk {
+        let lods = resolutions
+            .iter()
+            .map(|&resolution| {
+                let spacing = size / (resolution.max(2) - 1) as f32;
+                self.generate(device, origin, resolution, resolution, spacing, material())
+            })
+            .collect();
+        TerrainChunk::new(lods)
+    }
+}
+
+/// Picks a level of detail from the distance between the camera and a bounding sphere.
+/// LOD `i` is used up to `thresholds[i]` world units away, anything farther than the last
+/// threshold gets the coarsest LOD, `thresholds.len()`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LodSelector {
+    thresholds: Vec<f32>,
+    /// Distance past a threshold needed before switching away from the current LOD, so
+    /// chunks sitting right at a boundary don't flicker between two LODs
+    pub hysteresis: f32,
+}
+
+impl LodSelector {
+    pub fn new(thresholds: Vec<f32>, hysteresis: f32) -> Self {
+        assert!(
+            thresholds.windows(2).all(|pair| pair[0] <= pair[1]),
+            "LOD thresholds must be ascending"
+        );
+        Self {
+            thresholds,
+            hysteresis: hysteresis.max(0.0),
+        }
+    }
+
+    pub fn thresholds(&self) -> &[f32] {
+        &self.thresholds
+    }
+
+    /// Number of LODs the selector can return.
+    pub fn lod_count(&self) -> usize {
+        self.thresholds.len() + 1
+    }
+
+    /// LOD for a sphere at `center` with `radius` seen from `position`. `current` is the LOD
+    /// picked last frame, if any, and only changes once the distance is `hysteresis` past
+    /// the threshold between the two.
+    pub fn select(
+        &self,
+        position: Point3<f32>,
+        center: Point3<f32>,
+        radius: f32,
+        current: Option<usize>,
+    ) -> usize {
+        let distance = (position.distance(center) - radius).max(0.0);
+        let Some(current) = current else {
+            return self.thresholds.iter().filter(|&&t| distance > t).count();
+        };
+
+        let mut lod = current.min(self.thresholds.len());
+        while lod < self.thresholds.len() && distance > self.thresholds[lod] + self.hysteresis {
+            lod += 1;
+        }
+        while lod > 0 && distance < self.thresholds[lod - 1] - self.hysteresis {
+            lod -= 1;
+        }
+        lod
+    }
+}
+
+/// The same area of terrain prebuilt at several resolutions, finest first. `update_lod`
+/// picks which one `model` returns for drawing.
+pub struct TerrainChunk {
+    pub lods: Vec<Terrain>,
+    bounds: Aabb,
+    radius: f32,
+    lod: Option<usize>,
+}
+
+impl TerrainChunk {
+    pub fn new(lods: Vec<Terrain>) -> Self {
+        assert!(!lods.is_empty(), "terrain chunk needs at least one LOD");
+        let bounds = lods
+            .iter()
+            .flat_map(|terrain| terrain.model().meshes.iter())
+            .fold(Aabb::EMPTY, |bounds, mesh| bounds.union(mesh.bounds));
+        let radius = bounds.center().distance(bounds.max);
+        Self {
+            lods,
+            bounds,
+            radius,
+            lod: None,
+        }
+    }
+
+    pub fn bounds(&self) -> Aabb {
+        self.bounds
+    }
+
+    pub fn center(&self) -> Point3<f32> {
+        self.bounds.center()
+    }
+
+    /// Radius of the sphere around `center` enclosing every LOD.
+    pub fn radius(&self) -> f32 {
+        self.radius
+    }
+
+    /// Index into `lods` currently drawn.
+    pub fn lod(&self) -> usize {
+        self.lod.unwrap_or(0)
+    }
+
+    pub fn is_visible(&self, frustum: &Frustum) -> bool {
+        frustum.intersects_aabb(&self.bounds)
+    }
+
+    /// Re-selects the LOD for a camera at `position` and returns it. Selector LODs past
+    /// the last prebuilt mesh use the coarsest one.
+    pub fn update_lod(&mut self, selector: &LodSelector, position: Point3<f32>) -> usize {
+        let lod = selector
+            .select(position, self.center(), self.radius, self.lod)
+            .min(self.lods.len() - 1);
+        self.lod = Some(lod);
+        lod
+    }
+
+    /// The terrain for the current LOD.
+    pub fn terrain(&self) -> &Terrain {
+        &self.lods[self.lod()]
+    }
+
+    pub fn model(&self) -> &model::Model {
+        self.terrain().model()
+    }
+}
+
+/// A height in world units for any world position `(x, z)`. Implemented by `NoiseTerrain`
+/// and by closures, e.g. one sampling a heightmap, so regions can be meshed on any thread.
+pub trait HeightField: Send + Sync {
+    fn height_at(&self, x: f32, z: f32) -> f32;
+}
+
+impl HeightField for NoiseTerrain {
+    fn height_at(&self, x: f32, z: f32) -> f32 {
+        NoiseTerrain::height_at(self, x, z)
+    }
+}
+
+impl<F: Fn(f32, f32) -> f32 + Send + Sync> HeightField for F {
+    fn height_at(&self, x: f32, z: f32) -> f32 {
+        self(x, z)
+    }
+}
+
+/// The CPU half of a `Terrain`, everything but the GPU buffers.
+pub(crate) struct TerrainMesh {
+    width: u32,
+    depth: u32,
+    origin: Vector2<f32>,
+    scale: Vector3<f32>,
+    heights: Vec<f32>,
+    vertices: Vec<model::ModelVertex>,
+    indices: Vec<u32>,
+}
+
+impl TerrainMesh {
+    pub(crate) fn upload(
+        self,
+        device: &wgpu::Device,
+        name: &str,
+        material: model::Material,
+    ) -> Terrain {
+        let model = upload_mesh(device, name, &self.vertices, &self.indices, material);
+        Terrain {
+            width: self.width,
+            depth: self.depth,
+            origin: self.origin,
+            scale: self.scale,
+            heights: self.heights,
+            model,
+        }
+    }
+}
+
+/// Samples `field` on a `width` x `depth` grid starting at world `(origin.x, origin.y)` with
+/// `spacing` world units between samples. `height_scale` is recorded as the terrain's
+/// `scale.y`.
+pub(crate) fn mesh_region(
+    field: &(impl HeightField + ?Sized),
+    origin: Vector2<f32>,
+    width: u32,
+    depth: u32,
+    spacing: f32,
+    height_scale: f32,
+) -> TerrainMesh {
+    assert!(
+        width >= 2 && depth >= 2,
+        "terrain needs at least 2x2 samples"
+    );
+
+    let scale = Vector3::new(spacing, height_scale, spacing);
+    let world = |x: i64, z: i64| (origin.x + x as f32 * spacing, origin.y + z as f32 * spacing);
+    let heights = (0..depth as i64)
+        .flat_map(|z| (0..width as i64).map(move |x| (x, z)))
+        .map(|(x, z)| {
+            let (wx, wz) = world(x, z);
+            field.height_at(wx, wz)
+        })
+        .collect::<Vec<_>>();
+    // Sampling the field past the edges keeps normals continuous between
+    // neighboring regions
+    let (vertices, indices) = build_grid_mesh(width, depth, origin, scale, |x, z| {
+        let (wx, wz) = world(x, z);
+        field.height_at(wx, wz)
+    });
+
+    TerrainMesh {
+        width,
+        depth,
+        origin,
+        scale,
+        heights,
+        vertices,
+        indices,
+    }
+}
+
+/// Builds a triangle grid of `width` x `depth` samples starting at world `(origin.x, origin.y)`.
+/// `height(x, z)` returns the height in world units of grid sample `(x, z)` and is also
+/// called one sample outside the grid to compute normals by central differences.
+pub(crate) fn build_grid_mesh(
+    width: u32,
+    depth: u32,
+    origin: Vector2<f32>,
+    scale: Vector3<f32>,
+    height: impl Fn(i64, i64) -> f32,
+) -> (Vec<model::ModelVertex>, Vec<u32>) {
+    let mut vertices = Vec::with_capacity((width * depth) as usize);
+    for z in 0..depth as i64 {
+        for x in 0..width as i64 {
+            let normal = grid_normal(&height, x, z, scale);
+            vertices.push(model::ModelVertex {
+                position: [
+                    origin.x + x as f32 * scale.x,
+                    height(x, z),
+                    origin.y + z as f32 * scale.z,
+                ],
+                tex_coords: [x as f32 / (width - 1) as f32, z as f32 / (depth - 1) as f32],
+                normal: normal.into(),
+                tangent: [0.0; 3],
+                bitangent: [0.0; 3],
+                joints: [0; 4],
+                weights: [0.0; 4],
+                color: [1.0; 4],
+            });
+        }
+    }
+
+    let mut indices = Vec::with_capacity(((width - 1) * (depth - 1) * 6) as usize);
+    for z in 0..depth - 1 {
+        for x in 0..width - 1 {
+            let i0 = z * width + x;
+            let i1 = i0 + 1;
+            let i2 = i0 + width;
+            let i3 = i2 + 1;
+            // Counter-clockwise when seen from above
+            indices.extend_from_slice(&[i0, i2, i1, i1, i2, i3]);
+        }
+    }
+
+    resources::compute_tangents(&mut vertices, &indices);
+    (vertices, indices)
+}
+
+/// Normal of grid sample `(x, z)` by central differences of `height`, which is called for
+/// the four neighbors.
+fn grid_normal(
+    height: impl Fn(i64, i64) -> f32,
+    x: i64,
+    z: i64,
+    scale: Vector3<f32>,
+) -> Vector3<f32> {
+    let dh_dx = (height(x + 1, z) - height(x - 1, z)) / (2.0 * scale.x);
+    let dh_dz = (height(x, z + 1) - height(x, z - 1)) / (2.0 * scale.z);
+    Vector3::new(-dh_dx, 1.0, -dh_dz).normalize()
+}
+
+/// `heights` of a `width` x `depth` grid averaged over `radius` samples in every direction,
+/// one axis after the other. Near the edges the window is cut off.
+fn blur_heights(heights: &[f32], width: u32, depth: u32, radius: u32) -> Vec<f32> {
+    let blur = |source: &[f32], len: u32, stride: u32, lines: u32, line_stride: u32| {
+        let mut output = vec![0.0; source.len()];
+        for line in 0..lines {
+            let at = |i: u32| (line * line_stride + i * stride) as usize;
+            for i in 0..len {
+                let (start, end) = (i.saturating_sub(radius), (i + radius).min(len - 1));
+                let sum: f32 = (start..=end).map(|j| source[at(j)]).sum();
+                output[at(i)] = sum / (end - start + 1) as f32;
+            }
+        }
+        output
+    };
+    let rows = blur(heights, width, 1, depth, width);
+    blur(&rows, depth, width, width, 1)
+}
+
+/// Gives every triangle of an indexed mesh its own vertices with the triangle's normal, and
+/// recomputes the tangents for them.
+fn facet_mesh(
+    vertices: &[model::ModelVertex],
+    indices: &[u32],
+) -> (Vec<model::ModelVertex>, Vec<u32>) {
+    let mut faceted = Vec::with_capacity(indices.len());
+    for triangle in indices.chunks(3) {
+        let corners = [0, 1, 2].map(|i| vertices[triangle[i] as usize]);
+        let [a, b, c] = corners.map(|v| Vector3::from(v.position));
+        // Counter-clockwise seen from the front
+        let normal = (b - a).cross(c - a).normalize();
+        faceted.extend(corners.map(|v| model::ModelVertex {
+            normal: normal.into(),
+            tangent: [0.0; 3],
+            bitangent: [0.0; 3],
+            ..v
+        }));
+    }
+    let indices = (0..faceted.len() as u32).collect::<Vec<_>>();
+    resources::compute_tangents(&mut faceted, &indices);
+    (faceted, indices)
+}
+
+pub(crate) fn upload_mesh(
+    device: &wgpu::Device,
+    name: &str,
+    vertices: &[model::ModelVertex],
+    indices: &[u32],
+    material: model::Material,
+) -> model::Model {
+    let vertex_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+        label: Some(&format!("{:?} Vertex Buffer", name)),
+        contents: bytemuck::cast_slice(vertices),
+        usage: wgpu::BufferUsages::VERTEX,
+    });
+    let index_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+        label: Some(&format!("{:?} Index Buffer", name)),
+        contents: bytemuck::cast_slice(indices),
+        usage: wgpu::BufferUsages::INDEX,
+    });
+
+    model::Model {
+        meshes: vec![model::Mesh {
+            name: name.to_string(),
+            vertex_buffer,
+            index_buffer,
+            num_elements: indices.len() as u32,
+            material: 0,
+            bounds: Aabb::from_points(vertices.iter().map(|v| v.position.into())),
+        }],
+        materials: vec![Arc::new(material)],
+    }
+}
+
+/// Integer coordinates of a `TerrainStreamer` chunk, chunk `(x, z)` starts at world
+/// `(x * chunk_size, z * chunk_size)`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ChunkCoord {
+    pub x: i32,
+    pub z: i32,
+}
+
+/// Keeps the chunks of an unbounded height field loaded around the camera. Chunks within
+/// `load_radius` are meshed on a worker thread and uploaded by `update` once ready, chunks
+/// farther than `unload_radius` are dropped.
+pub struct TerrainStreamer {
+    device: wgpu::Device,
+    material: model::Material,
+    chunk_size: f32,
+    resolution: u32,
+    /// Distance on the xz plane from the camera to a chunk's center within which it is loaded
+    pub load_radius: f32,
+    /// Distance past which loaded chunks are dropped, kept above `load_radius` so chunks at
+    /// the edge aren't reloaded every time the camera moves back and forth
+    pub unload_radius: f32,
+    chunks: HashMap<ChunkCoord, Terrain>,
+    /// Requested from the worker but not uploaded yet. Shared with the worker, which skips
+    /// requests `update` took out again as the camera moved away before they were meshed.
+    pending: Arc<Mutex<HashSet<ChunkCoord>>>,
+    requests: Option<mpsc::Sender<ChunkCoord>>,
+    /// Behind a mutex only so the renderer can be shared between threads recording
+    /// commands, `update` takes it through `&mut self` without locking
+    meshes: Mutex<mpsc::Receiver<(ChunkCoord, TerrainMesh)>>,
+}
+
+impl TerrainStreamer {
+    /// Streams `field` in square chunks `chunk_size` world units across with `resolution`
+    /// samples per side. Every chunk is drawn with `material`.
+    pub fn new(
+        device: &wgpu::Device,
+        field: impl HeightField + 'static,
+        material: model::Material,
+        chunk_size: f32,
+        resolution: u32,
+        load_radius: f32,
+    ) -> Self {
+        assert!(chunk_size > 0.0, "chunk size must be positive");
+        assert!(resolution >= 2, "terrain needs at least 2x2 samples");
+
+        let (requests, worker_requests) = mpsc::channel::<ChunkCoord>();
+        let (worker_meshes, meshes) = mpsc::channel();
+        let pending = Arc::new(Mutex::new(HashSet::new()));
+        let worker_pending = pending.clone();
+        let spacing = chunk_size / (resolution - 1) as f32;
+        thread::Builder::new()
+            .name("terrain streamer".to_string())
+            .spawn(move || {
+                // Ends once the streamer is dropped, either the requests run out or the
+                // send fails
+                for coord in worker_requests {
+                    let wanted = worker_pending
+                        .lock()
+                        .unwrap_or_else(PoisonError::into_inner)
+                        .contains(&coord);
+                    if !wanted {
+                        continue;
+                    }
+                    let origin = Vector2::new(coord.x as f32, coord.z as f32) * chunk_size;
+                    let mesh = mesh_region(&field, origin, resolution, resolution, spacing, 1.0);
+                    if worker_meshes.send((coord, mesh)).is_err() {
+                        break;
+                    }
+                }
+            })
+            .expect("failed to spawn terrain streamer thread");
+
+        Self {
+            device: device.clone(),
+            material,
+            chunk_size,
+            resolution,
+            load_radius,
+            unload_radius: load_radius + chunk_size,
+            chunks: HashMap::new(),
+            pending,
+            requests: Some(requests),
+            meshes: Mutex::new(meshes),
+        }
+    }
+
+    pub fn chunk_size(&self) -> f32 {
+        self.chunk_size
+    }
+
+    pub fn resolution(&self) -> u32 {
+        self.resolution
+    }
+
+    /// The chunk containing world position `(x, z)`.
+    pub fn chunk_at(&self, x: f32, z: f32) -> ChunkCoord {
+        ChunkCoord {
+            x: (x / self.chunk_size).floor() as i32,
+            z: (z / self.chunk_size).floor() as i32,
+        }
+    }
+
+    /// World-space (x, z) of a chunk's center.
+    pub fn chunk_center(&self, coord: ChunkCoord) -> Vector2<f32> {
+        (Vector2::new(coord.x as f32, coord.z as f32) + Vector2::new(0.5, 0.5)) * self.chunk_size
+    }
+
+    /// Uploads chunks the worker finished, requests missing chunks around `camera_position`
+    /// nearest first, and drops chunks that fell out of range.
+    pub fn update(&mut self, camera_position: Point3<f32>) {
+        let camera = Vector2::new(camera_position.x, camera_position.z);
+        let distance = |streamer: &Self, coord| (streamer.chunk_center(coord) - camera).magnitude();
+
+        let finished = self
+            .meshes
+            .get_mut()
+            .unwrap_or_else(PoisonError::into_inner)
+            .try_iter()
+            .collect::<Vec<_>>();
+        let mut pending = self.pending.lock().unwrap_or_else(PoisonError::into_inner);
+        for (coord, mesh) in finished {
+            pending.remove(&coord);
+            // The camera may have moved away while the chunk was being meshed
+            if distance(self, coord) <= self.unload_radius {
+                let terrain = mesh.upload(&self.device, "terrain chunk", self.material.clone());
+                self.chunks.insert(coord, terrain);
+            }
+        }
+
+        let unload_radius = self.unload_radius;
+        let far = self
+            .chunks
+            .keys()
+            .copied()
+            .filter(|&coord| distance(self, coord) > unload_radius)
+            .collect::<Vec<_>>();
+        for coord in far {
+            self.chunks.remove(&coord);
+        }
+        pending.retain(|&coord| distance(self, coord) <= unload_radius);
+
+        let Some(requests) = &self.requests else {
+            return;
+        };
+        let reach = (self.load_radius / self.chunk_size).ceil() as i32 + 1;
+        let center = self.chunk_at(camera.x, camera.y);
+        let mut missing = (-reach..=reach)
+            .flat_map(|z| (-reach..=reach).map(move |x| (x, z)))
+            .map(|(x, z)| ChunkCoord {
+                x: center.x + x,
+                z: center.z + z,
+            })
+            .filter(|coord| !self.chunks.contains_key(coord) && !pending.contains(coord))
+            .map(|coord| (distance(self, coord), coord))
+            .filter(|&(d, _)| d <= self.load_radius)
+            .collect::<Vec<_>>();
+        missing.sort_by(|a, b| a.0.total_cmp(&b.0));
+        for (_, coord) in missing {
+            if requests.send(coord).is_err() {
+                log::error!("Terrain streamer worker stopped");
+                self.requests = None;
+                return;
+            }
+            pending.insert(coord);
+        }
+    }
+
+    /// Loaded chunks, in no particular order.
+    pub fn chunks(&self) -> impl Iterator<Item = (ChunkCoord, &Terrain)> {
+        self.chunks.iter().map(|(coord, terrain)| (*coord, terrain))
+    }
+
+    pub fn chunk(&self, coord: ChunkCoord) -> Option<&Terrain> {
+        self.chunks.get(&coord)
+    }
+
+    /// Height of the loaded terrain at world position `(x, z)`, `None` if its chunk isn't
+    /// loaded.
+    pub fn height_at(&self, x: f32, z: f32) -> Option<f32> {
+        self.chunk(self.chunk_at(x, z))
+            .map(|terrain| terrain.height_at(x, z))
+    }
+
+    /// Number of chunks requested but not uploaded yet.
+    pub fn pending(&self) -> usize {
+        self.pending
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .len()
+    }
+
+    /// Loaded chunks overlapping `frustum`, in no particular order.
+    pub fn visible_chunks<'a>(
+        &'a self,
+        frustum: &'a Frustum,
+    ) -> impl Iterator<Item = (ChunkCoord, &'a Terrain)> {
+        self.chunks().filter(|(_, terrain)| {
+            terrain
+                .model
+                .meshes
+                .iter()
+                .any(|mesh| frustum.intersects_aabb(&mesh.bounds))
+        })
+    }
+}
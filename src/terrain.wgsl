@@ -0,0 +1,230 @@
+// Terrain blending the layers of a texture array, picked by height and slope or by a
+// control map, or colored by height through a colormap, and lit like shader.wgsl without
+// normal maps
+
+// `CameraUniform` and the other shared types are declared in `src/prelude.wgsl`
+
+struct Splat {
+  // Height where each layer takes over from the one below, x is unused
+  layer_heights: vec4<f32>,
+  control_origin: vec2<f32>,
+  control_size: vec2<f32>,
+  tiling: f32,
+  blend: f32,
+  slope_start: f32,
+  slope_end: f32,
+  // Past the last layer when there is no slope layer
+  slope_layer: u32,
+  layer_count: u32,
+  use_control: u32,
+  triplanar: u32,
+  triplanar_sharpness: f32,
+  // Replaces the layers with the colormap from elevation_min to elevation_max
+  use_colormap: u32,
+  elevation_min: f32,
+  elevation_max: f32,
+  contour_color: vec4<f32>,
+  // No contour lines when 0
+  contour_interval: f32,
+  // In pixels
+  contour_thickness: f32,
+  // Every nth line is drawn contour_major_thickness wide, none when 0
+  contour_major_every: u32,
+  contour_major_thickness: f32,
+};
+
+struct VertexInput {
+  @location(0) position: vec3<f32>,
+  @location(2) normal: vec3<f32>,
+};
+
+struct InstanceInput {
+  @location(5) model_matrix_0: vec4<f32>,
+  @location(6) model_matrix_1: vec4<f32>,
+  @location(7) model_matrix_2: vec4<f32>,
+  @location(8) model_matrix_3: vec4<f32>,
+  @location(9) normal_matrix_0: vec3<f32>,
+  @location(10) normal_matrix_1: vec3<f32>,
+  @location(11) normal_matrix_2: vec3<f32>,
+};
+
+struct VertexOutput {
+  // Invariant so the depth prepass and the color pass produce identical depths
+  @builtin(position) @invariant pos: vec4<f32>,
+  @location(0) world_position: vec3<f32>,
+  @location(1) world_normal: vec3<f32>,
+};
+
+@group(0) @binding(0)
+var t_layers: texture_2d_array<f32>;
+@group(0) @binding(1)
+var s_layers: sampler;
+@group(0) @binding(2)
+var t_control: texture_2d<f32>;
+@group(0) @binding(3)
+var s_control: sampler;
+@group(0) @binding(4)
+var<uniform> splat: Splat;
+@group(0) @binding(5)
+var t_colormap: texture_2d<f32>;
+@group(0) @binding(6)
+var s_colormap: sampler;
+
+@group(1) @binding(0)
+var<uniform> camera: CameraUniform;
+
+@group(2) @binding(0)
+var<storage, read> lights: LightArray;
+@group(2) @binding(1)
+var<storage, read> spot_lights: SpotLightArray;
+
+@group(3) @binding(0)
+var<uniform> sun: DirectionalLight;
+
+// `Shadow` and the functions sampling the cascades are in `src/shadow_sampling.wgsl`
+@group(4) @binding(0)
+var t_shadow: texture_depth_2d_array;
+@group(4) @binding(1)
+var s_shadow: sampler_comparison;
+@group(4) @binding(2)
+var<uniform> shadow: Shadow;
+
+@group(5) @binding(0)
+var<uniform> fog: Fog;
+
+@vertex
+fn vs_main(model: VertexInput, instance: InstanceInput) -> VertexOutput {
+  let model_matrix = mat4x4<f32>(
+    instance.model_matrix_0,
+    instance.model_matrix_1,
+    instance.model_matrix_2,
+    instance.model_matrix_3,
+  );
+  let normal_matrix = mat3x3<f32>(
+    instance.normal_matrix_0,
+    instance.normal_matrix_1,
+    instance.normal_matrix_2,
+  );
+
+  let world_position = model_matrix * vec4<f32>(model.position, 1.0);
+  var out: VertexOutput;
+  out.pos = camera.view_proj * world_position;
+  out.world_position = world_position.xyz;
+  out.world_normal = normalize(normal_matrix * model.normal);
+  return out;
+}
+
+fn layer_mask(layer: u32) -> vec4<f32> {
+  return vec4<f32>(vec4<u32>(0u, 1u, 2u, 3u) == vec4<u32>(layer));
+}
+
+// Weights of layers 0 to 3, summing to 1
+fn layer_weights(position: vec3<f32>, normal: vec3<f32>) -> vec4<f32> {
+  if splat.use_control != 0u {
+    let uv = (position.xz - splat.control_origin) / splat.control_size;
+    let control = textureSampleLevel(t_control, s_control, uv, 0.0);
+    let total = dot(control, vec4<f32>(1.0));
+    return select(layer_mask(0u), control / total, total > 0.0001);
+  }
+
+  // How far each layer has taken over from the ones below, 1 for layer 0 and 0 for
+  // layers that don't exist. Neighbors' differences then telescope to a sum of 1.
+  let half_blend = max(splat.blend * 0.5, 0.0001);
+  let exists = vec4<f32>(vec4<u32>(0u, 1u, 2u, 3u) < vec4<u32>(splat.layer_count));
+  var cover = smoothstep(
+    splat.layer_heights - half_blend,
+    splat.layer_heights + half_blend,
+    vec4<f32>(position.y),
+  ) * exists;
+  cover.x = 1.0;
+  var weights = cover - vec4<f32>(cover.yzw, 0.0);
+
+  let steepness = 1.0 - normal.y;
+  let has_slope_layer = f32(splat.slope_layer < splat.layer_count);
+  let slope = smoothstep(splat.slope_start, splat.slope_end, steepness) * has_slope_layer;
+  weights = mix(weights, layer_mask(splat.slope_layer), slope);
+  return weights;
+}
+
+// Color of `layer` at `position`, projected straight down or, with triplanar mapping,
+// along each world axis and blended by how much the surface faces it
+fn sample_layer(position: vec3<f32>, normal: vec3<f32>, layer: u32) -> vec3<f32> {
+  let p = position / splat.tiling;
+  if splat.triplanar == 0u {
+    return textureSample(t_layers, s_layers, p.xz, layer).rgb;
+  }
+  var blend = pow(abs(normal), vec3<f32>(splat.triplanar_sharpness));
+  blend /= max(dot(blend, vec3<f32>(1.0)), 0.0001);
+  let x = textureSample(t_layers, s_layers, p.zy, layer).rgb;
+  let y = textureSample(t_layers, s_layers, p.xz, layer).rgb;
+  let z = textureSample(t_layers, s_layers, p.xy, layer).rgb;
+  return x * blend.x + y * blend.y + z * blend.z;
+}
+
+// How much the contour line closest to `height` covers the pixel, fading over one pixel
+// at its edges by measuring the distance in screen space with the height's derivatives
+fn contour_coverage(height: f32) -> f32 {
+  let lines = height / splat.contour_interval;
+  let distance = abs(fract(lines - 0.5) - 0.5) / max(fwidth(lines), 0.0001);
+  let nearest = i32(round(lines));
+  let every = i32(splat.contour_major_every);
+  let major = every > 0 && ((nearest % every) + every) % every == 0;
+  let half_width = select(splat.contour_thickness, splat.contour_major_thickness, major) * 0.5;
+  return 1.0 - smoothstep(half_width - 0.5, half_width + 0.5, distance);
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+  let normal = normalize(in.world_normal);
+  var albedo = vec3<f32>(0.0);
+  if splat.use_colormap != 0u {
+    let range = max(splat.elevation_max - splat.elevation_min, 0.0001);
+    let t = clamp((in.world_position.y - splat.elevation_min) / range, 0.0, 1.0);
+    albedo = textureSample(t_colormap, s_colormap, vec2<f32>(t, 0.5)).rgb;
+  } else {
+    let weights = layer_weights(in.world_position, normal);
+    let last = max(splat.layer_count, 1u) - 1u;
+    for (var i = 0u; i < 4u; i += 1u) {
+      albedo += sample_layer(in.world_position, normal, min(i, last)) * weights[i];
+    }
+  }
+
+  let view_dir = normalize(camera.view_pos.xyz - in.world_position);
+  var color = AMBIENT_STRENGTH * albedo;
+  let count = min(lights.count, arrayLength(&lights.lights));
+  for (var i = 0u; i < count; i += 1u) {
+    let light = lights.lights[i];
+    let light_dir = normalize(light.position - in.world_position);
+    let half_dir = normalize(view_dir + light_dir);
+    let diffuse_color = light.color * max(dot(normal, light_dir), 0.0) * albedo;
+    let specular_color = light.color * pow(max(dot(normal, half_dir), 0.0), 32.0);
+    let attenuation = point_attenuation(light, in.world_position);
+    color += (diffuse_color + specular_color) * attenuation;
+  }
+
+  let spot_count = min(spot_lights.count, arrayLength(&spot_lights.lights));
+  for (var i = 0u; i < spot_count; i += 1u) {
+    let light = spot_lights.lights[i];
+    let light_dir = normalize(light.position - in.world_position);
+    let half_dir = normalize(view_dir + light_dir);
+    let diffuse_color = light.color * max(dot(normal, light_dir), 0.0) * albedo;
+    let specular_color = light.color * pow(max(dot(normal, half_dir), 0.0), 32.0);
+    color += (diffuse_color + specular_color) * spot_attenuation(light, in.world_position);
+  }
+
+  let sun_dir = -sun.direction;
+  let sun_half_dir = normalize(view_dir + sun_dir);
+  let sun_diffuse = sun.color * max(dot(normal, sun_dir), 0.0) * albedo;
+  let sun_specular = sun.color * pow(max(dot(normal, sun_half_dir), 0.0), 32.0);
+  color += (sun_diffuse + sun_specular) * sun_visibility(in.world_position, normal, sun_dir);
+
+  color *= cascade_tint(in.world_position);
+  if splat.contour_interval > 0.0 {
+    let coverage = contour_coverage(in.world_position.y) * splat.contour_color.a;
+    color = mix(color, splat.contour_color.rgb, coverage);
+  }
+  let view_depth = -(camera.view * vec4<f32>(in.world_position, 1.0)).z;
+  color = mix(color, fog.color.rgb, fog_factor(fog, view_depth));
+
+  return vec4<f32>(color, 1.0);
+}
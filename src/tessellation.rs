@@ -0,0 +1,477 @@
+use std::collections::{HashMap, HashSet};
+
+use cgmath::{InnerSpace as _, Point3};
+use wgpu::util::DeviceExt as _;
+
+use crate::{
+    frustum::Aabb,
+    hot_reload::wgsl,
+    model,
+    terrain::{ChunkCoord, Terrain, TerrainStreamer},
+};
+
+/// How `TerrainTessellator` subdivides streamed terrain near the camera.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TessellationSettings {
+    /// How finely cells are split for their size on screen. At 1 the edges of the
+    /// subdivided triangles aim for about 16 pixels, at 2 for 8 and so on.
+    pub factor: f32,
+    /// Each cell of a chunk's height grid splits into at most `2^max_subdivision` quads
+    /// per side, up to `MAX_SUBDIVISION`. Every tessellated chunk allocates room for all
+    /// of them, `(2^max_subdivision + 1)^2` vertices per cell, so for large chunks it is
+    /// lowered until their vertex buffer fits the device's limits, see
+    /// `TerrainTessellator::subdivision`.
+    pub max_subdivision: u32,
+    /// Distance on the xz plane from the camera to a chunk's center within which it is
+    /// tessellated, farther chunks keep their base mesh
+    pub radius: f32,
+}
+
+impl TessellationSettings {
+    pub const MAX_SUBDIVISION: u32 = 5;
+}
+
+impl Default for TessellationSettings {
+    fn default() -> Self {
+        Self {
+            factor: 1.0,
+            max_subdivision: 3,
+            radius: 48.0,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, bytemuck::Pod, bytemuck::Zeroable)]
+struct TessellationUniform {
+    camera_position: [f32; 3],
+    camera_height: f32,
+    origin: [f32; 2],
+    cell_offset: [i32; 2],
+    size: [u32; 2],
+    spacing: f32,
+    pixels_per_unit: f32,
+    orthographic: u32,
+    factor: f32,
+    max_subdivision: u32,
+    tessellated_neighbors: u32,
+}
+
+/// How the camera sees the terrain, for picking subdivision levels from the size of cells
+/// on screen.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TessellationView {
+    pub position: Point3<f32>,
+    /// Pixels a world unit covers one unit in front of a perspective camera, or anywhere
+    /// in front of an orthographic one
+    pub pixels_per_unit: f32,
+    pub orthographic: bool,
+}
+
+/// A chunk's height samples and the subdivided mesh the compute pass writes.
+struct TessellatedChunk {
+    uniform_buffer: wgpu::Buffer,
+    bind_group: wgpu::BindGroup,
+    uniform: TessellationUniform,
+    vertex_count: u32,
+    model: model::Model,
+}
+
+/// The triangles of a tessellated chunk, the same for every chunk of a streamer.
+struct PatchIndices {
+    /// Height samples per side of the chunks
+    resolution: u32,
+    /// `max_subdivision` lowered to fit the chunks' vertex buffers into the limits
+    subdivision: u32,
+    buffer: wgpu::Buffer,
+    count: u32,
+}
+
+/// Emulates hardware tessellation for a `TerrainStreamer`'s chunks near the camera. A
+/// compute pass splits every cell of their height grids into a patch of up to
+/// `2^max_subdivision` quads per side, depending on its size on screen, and writes the
+/// vertices into a buffer drawn like any other terrain mesh. Heights between the samples
+/// follow Catmull-Rom splines, so close-up terrain is smooth rather than faceted.
+///
+/// Patches always have all their vertices, coarser ones collapse groups of them onto the
+/// same position. Vertices on a patch's edge collapse onto the coarser level of the two
+/// patches sharing it, and onto the base resolution towards untessellated chunks, so
+/// neighbors line up without cracks.
+pub struct TerrainTessellator {
+    device: wgpu::Device,
+    settings: TessellationSettings,
+    bind_group_layout: wgpu::BindGroupLayout,
+    pipeline: wgpu::ComputePipeline,
+    /// Shared by every chunk, for the streamer's resolution and `max_subdivision`
+    index_buffer: Option<PatchIndices>,
+    /// Resolution of streamed chunks too large to tessellate at all, warned about once
+    oversized: Option<u32>,
+    chunks: HashMap<ChunkCoord, TessellatedChunk>,
+}
+
+impl TerrainTessellator {
+    /// Workgroup size of the compute shader
+    const WORKGROUP_SIZE: u32 = 64;
+    /// Floats in a `ModelVertex`, as the compute shader writes them
+    const VERTEX_FLOATS: u64 = (std::mem::size_of::<model::ModelVertex>() / 4) as u64;
+
+    pub fn new(device: &wgpu::Device, settings: TessellationSettings) -> Self {
+        let storage = |binding, read_only| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Storage { read_only },
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                storage(1, true),
+                storage(2, false),
+            ],
+            label: Some("tessellation_bind_group_layout"),
+        });
+        let pipeline = Self::create_pipeline(device, &bind_group_layout);
+        Self {
+            device: device.clone(),
+            settings: Self::clamped(settings),
+            bind_group_layout,
+            pipeline,
+            index_buffer: None,
+            oversized: None,
+            chunks: HashMap::new(),
+        }
+    }
+
+    fn create_pipeline(
+        device: &wgpu::Device,
+        bind_group_layout: &wgpu::BindGroupLayout,
+    ) -> wgpu::ComputePipeline {
+        let shader = device.create_shader_module(wgsl!("src/tessellation.wgsl"));
+        let constants = [("VERTEX_FLOATS", Self::VERTEX_FLOATS as f64)];
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Tessellation Pipeline Layout"),
+            bind_group_layouts: &[bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: Some("Tessellation Pipeline"),
+            layout: Some(&pipeline_layout),
+            module: &shader,
+            entry_point: Some("tessellate"),
+            compilation_options: wgpu::PipelineCompilationOptions {
+                constants: &constants,
+                ..Default::default()
+            },
+            cache: None,
+        })
+    }
+
+    /// Rebuilds the pipeline from the current shader, see `hot_reload`. The chunks
+    /// tessellated so far stay as they are until they are tessellated again.
+    pub fn recreate_pipeline(&mut self) {
+        self.pipeline = Self::create_pipeline(&self.device, &self.bind_group_layout);
+    }
+
+    pub fn settings(&self) -> TessellationSettings {
+        self.settings
+    }
+
+    /// Takes effect on the next `update`. Changing `max_subdivision` reallocates every
+    /// tessellated chunk.
+    pub fn set_settings(&mut self, settings: TessellationSettings) {
+        let settings = Self::clamped(settings);
+        if settings.max_subdivision != self.settings.max_subdivision {
+            self.chunks.clear();
+            self.index_buffer = None;
+            self.oversized = None;
+        }
+        self.settings = settings;
+    }
+
+    fn clamped(settings: TessellationSettings) -> TessellationSettings {
+        TessellationSettings {
+            factor: settings.factor.max(0.0),
+            max_subdivision: settings
+                .max_subdivision
+                .min(TessellationSettings::MAX_SUBDIVISION),
+            radius: settings.radius.max(0.0),
+        }
+    }
+
+    /// The highest subdivision chunks are tessellated with, `max_subdivision` unless their
+    /// vertex buffers wouldn't fit the device's buffer limits with it. `None` before the
+    /// first `update` with chunks in range, or if not even unsubdivided patches fit.
+    pub fn subdivision(&self) -> Option<u32> {
+        self.index_buffer
+            .as_ref()
+            .map(|indices| indices.subdivision)
+    }
+
+    /// The highest subdivision up to `max_subdivision` whose vertex and index buffers fit
+    /// the device's limits for chunks of `resolution` samples per side.
+    fn fitting_subdivision(&self, resolution: u32) -> Option<u32> {
+        let limits = self.device.limits();
+        let max_size = limits
+            .max_buffer_size
+            .min(limits.max_storage_buffer_binding_size as u64);
+        let cells = ((resolution - 1) as u64).pow(2);
+        (0..=self.settings.max_subdivision)
+            .rev()
+            .find(|&subdivision| {
+                let n = 1u64 << subdivision;
+                let vertex_bytes = cells * (n + 1) * (n + 1) * Self::VERTEX_FLOATS * 4;
+                let index_bytes = cells * n * n * 6 * 4;
+                vertex_bytes <= max_size && index_bytes <= limits.max_buffer_size
+            })
+    }
+
+    /// The subdivided mesh of the chunk `coord`, `None` if it isn't tessellated.
+    pub fn model(&self, coord: ChunkCoord) -> Option<&model::Model> {
+        self.chunks.get(&coord).map(|chunk| &chunk.model)
+    }
+
+    /// Drops every tessellated chunk, e.g. after the streamed terrain was replaced.
+    pub fn clear(&mut self) {
+        self.chunks.clear();
+    }
+
+    /// Number of chunks currently tessellated.
+    pub fn chunk_count(&self) -> usize {
+        self.chunks.len()
+    }
+
+    /// Picks the loaded chunks of `streamer` within `radius` of the camera and
+    /// re-tessellates those that are new or whose view or neighbors changed since the
+    /// last update.
+    pub fn update(
+        &mut self,
+        queue: &wgpu::Queue,
+        streamer: &TerrainStreamer,
+        view: TessellationView,
+    ) {
+        let camera = cgmath::Vector2::new(view.position.x, view.position.z);
+        let near = streamer
+            .chunks()
+            .filter(|&(coord, _)| {
+                (streamer.chunk_center(coord) - camera).magnitude() <= self.settings.radius
+            })
+            .collect::<HashMap<_, _>>();
+        self.chunks.retain(|coord, _| near.contains_key(coord));
+        if near.is_empty() {
+            return;
+        }
+        let coords = near.keys().copied().collect::<HashSet<_>>();
+        let camera_height = view.position.y - streamer.height_at(camera.x, camera.y).unwrap_or(0.0);
+        let resolution = streamer.resolution();
+        let cells = resolution - 1;
+        if self.oversized == Some(resolution) {
+            return;
+        }
+        if self
+            .index_buffer
+            .as_ref()
+            .is_none_or(|indices| indices.resolution != resolution)
+        {
+            self.chunks.clear();
+            let Some(subdivision) = self.fitting_subdivision(resolution) else {
+                log::warn!(
+                    "terrain chunks of {resolution} samples per side are too large to tessellate"
+                );
+                self.index_buffer = None;
+                self.oversized = Some(resolution);
+                return;
+            };
+            let indices = Self::patch_indices(cells, 1 << subdivision);
+            let buffer = self
+                .device
+                .create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                    label: Some("Tessellated Terrain Index Buffer"),
+                    contents: bytemuck::cast_slice(&indices),
+                    usage: wgpu::BufferUsages::INDEX,
+                });
+            self.index_buffer = Some(PatchIndices {
+                resolution,
+                subdivision,
+                buffer,
+                count: indices.len() as u32,
+            });
+        }
+        let Some(subdivision) = self.subdivision() else {
+            return;
+        };
+
+        let mut encoder = self
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some("Tessellation Encoder"),
+            });
+        let mut dispatched = false;
+        {
+            let mut pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+                label: Some("Terrain Tessellation"),
+                timestamp_writes: None,
+            });
+            pass.set_pipeline(&self.pipeline);
+            for (&coord, terrain) in &near {
+                let neighbors = [(-1, 0), (1, 0), (0, -1), (0, 1)]
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, (x, z))| {
+                        coords.contains(&ChunkCoord {
+                            x: coord.x + x,
+                            z: coord.z + z,
+                        })
+                    })
+                    .fold(0, |bits, (bit, _)| bits | 1 << bit);
+                let uniform = TessellationUniform {
+                    camera_position: view.position.into(),
+                    camera_height,
+                    origin: terrain.origin().into(),
+                    cell_offset: [coord.x * cells as i32, coord.z * cells as i32],
+                    size: [terrain.width(), terrain.depth()],
+                    spacing: terrain.scale().x,
+                    pixels_per_unit: view.pixels_per_unit,
+                    orthographic: view.orthographic.into(),
+                    factor: self.settings.factor,
+                    max_subdivision: subdivision,
+                    tessellated_neighbors: neighbors,
+                };
+                // Chunks keep their vertices until the camera, the settings or their
+                // neighbors change
+                match self.chunks.get_mut(&coord) {
+                    Some(chunk) if chunk.uniform == uniform => continue,
+                    Some(chunk) => {
+                        chunk.uniform = uniform;
+                        queue.write_buffer(
+                            &chunk.uniform_buffer,
+                            0,
+                            bytemuck::cast_slice(&[uniform]),
+                        );
+                    }
+                    None => {
+                        let chunk = self.create_chunk(terrain, uniform);
+                        self.chunks.insert(coord, chunk);
+                    }
+                }
+                let chunk = &self.chunks[&coord];
+                dispatched = true;
+                // Rows of workgroups once there are more than fit in one dimension
+                let groups = chunk.vertex_count.div_ceil(Self::WORKGROUP_SIZE);
+                let max_groups = self.device.limits().max_compute_workgroups_per_dimension;
+                let rows = groups.div_ceil(max_groups);
+                pass.set_bind_group(0, &chunk.bind_group, &[]);
+                pass.dispatch_workgroups(groups.div_ceil(rows), rows, 1);
+            }
+        }
+        if dispatched {
+            queue.submit(std::iter::once(encoder.finish()));
+        }
+    }
+
+    fn create_chunk(&self, terrain: &Terrain, uniform: TessellationUniform) -> TessellatedChunk {
+        let n = 1 << uniform.max_subdivision;
+        let cells = (terrain.width() - 1) * (terrain.depth() - 1);
+        let vertex_count = cells * (n + 1) * (n + 1);
+        let uniform_buffer = self
+            .device
+            .create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: Some("Tessellation Uniform Buffer"),
+                contents: bytemuck::cast_slice(&[uniform]),
+                usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            });
+        let height_buffer = self
+            .device
+            .create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: Some("Tessellation Height Buffer"),
+                contents: bytemuck::cast_slice(terrain.heights()),
+                usage: wgpu::BufferUsages::STORAGE,
+            });
+        let vertex_buffer = self.device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Tessellated Terrain Vertex Buffer"),
+            size: vertex_count as u64 * Self::VERTEX_FLOATS * 4,
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::VERTEX,
+            mapped_at_creation: false,
+        });
+        let bind_group = self.device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &self.bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: uniform_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: height_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: vertex_buffer.as_entire_binding(),
+                },
+            ],
+            label: Some("tessellation_bind_group"),
+        });
+        let indices = self
+            .index_buffer
+            .as_ref()
+            .expect("index buffer is created before chunks");
+        // The splines overshoot the samples by up to an eighth of the steepest step
+        let base = terrain.model().bounding_box();
+        let overshoot = (base.max.y - base.min.y) / 8.0;
+        let bounds = Aabb::new(
+            base.min - cgmath::Vector3::unit_y() * overshoot,
+            base.max + cgmath::Vector3::unit_y() * overshoot,
+        );
+        let model = model::Model {
+            meshes: vec![model::Mesh {
+                name: "tessellated terrain chunk".to_string(),
+                vertex_buffer,
+                index_buffer: indices.buffer.clone(),
+                num_elements: indices.count,
+                material: 0,
+                bounds,
+            }],
+            materials: terrain.model().materials.clone(),
+        };
+        TessellatedChunk {
+            uniform_buffer,
+            bind_group,
+            uniform,
+            vertex_count,
+            model,
+        }
+    }
+
+    /// Triangles of `cells` patches of `n` x `n` quads each, with the vertices of every
+    /// patch following the previous patch's, row by row.
+    fn patch_indices(cells: u32, n: u32) -> Vec<u32> {
+        let row = n + 1;
+        let mut indices = Vec::with_capacity((cells * cells * n * n * 6) as usize);
+        for patch in 0..cells * cells {
+            let first = patch * row * row;
+            for z in 0..n {
+                for x in 0..n {
+                    let i0 = first + z * row + x;
+                    let i1 = i0 + 1;
+                    let i2 = i0 + row;
+                    let i3 = i2 + 1;
+                    // Counter-clockwise when seen from above, like `build_grid_mesh`
+                    indices.extend_from_slice(&[i0, i2, i1, i1, i2, i3]);
+                }
+            }
+        }
+        indices
+    }
+}
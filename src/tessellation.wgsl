@@ -0,0 +1,182 @@
+// Emulates hardware tessellation of a terrain chunk: every cell of its height grid is a
+// patch of (2^max_subdivision + 1)^2 vertices, which collapse onto a coarser grid the
+// smaller the cell is on screen. Heights are interpolated with Catmull-Rom splines, so
+// denser patches are smooth rather than flat.
+
+struct Tessellation {
+  camera_position: vec3<f32>,
+  // Camera height above the terrain under it, the vertical part of every cell's distance
+  camera_height: f32,
+  // World (x, z) of the first height sample
+  origin: vec2<f32>,
+  // Global index of the chunk's first cell, so neighboring chunks pick the same levels
+  cell_offset: vec2<i32>,
+  // Samples per row and per column of `heights`
+  size: vec2<u32>,
+  spacing: f32,
+  // Pixels a world unit covers one unit from the camera, or anywhere when orthographic
+  pixels_per_unit: f32,
+  orthographic: u32,
+  factor: f32,
+  max_subdivision: u32,
+  // Bit per chunk edge, -x, +x, -z and +z, whose neighbor is tessellated too. Edges
+  // towards untessellated chunks stay at their base resolution.
+  tessellated_neighbors: u32,
+};
+
+@group(0) @binding(0)
+var<uniform> params: Tessellation;
+@group(0) @binding(1)
+var<storage, read> heights: array<f32>;
+// `ModelVertex`s, `VERTEX_FLOATS` floats each
+@group(0) @binding(2)
+var<storage, read_write> vertices: array<f32>;
+
+// Floats in a `ModelVertex`, set from its size by `TerrainTessellator`. Its last four are
+// the vertex color.
+override VERTEX_FLOATS: u32;
+// Screen size of a patch edge segment at a factor of 1
+const SEGMENT_PIXELS: f32 = 16.0;
+
+// Subdivision level of the cell `cell` of the chunk, which may lie outside it. Only depends
+// on the cell's global index, so both chunks sharing an edge agree on it.
+fn cell_level(cell: vec2<i32>) -> u32 {
+  let center = (vec2<f32>(params.cell_offset + cell) + 0.5) * params.spacing;
+  let offset = vec3<f32>(center - params.camera_position.xz, params.camera_height);
+  var pixels = params.spacing * params.pixels_per_unit;
+  if params.orthographic == 0u {
+    pixels /= max(length(offset), 0.0001);
+  }
+  let segments = pixels * params.factor / SEGMENT_PIXELS;
+  if segments <= 1.0 {
+    return 0u;
+  }
+  return min(u32(ceil(log2(segments))), params.max_subdivision);
+}
+
+// Level of the edge between `cell` and its neighbor `step` away, the coarser of the two,
+// or 0 where the neighbor is in an untessellated chunk
+fn edge_level(cell: vec2<i32>, level: u32, step: vec2<i32>, edge_bit: u32) -> u32 {
+  let neighbor = cell + step;
+  let cells = vec2<i32>(params.size) - 1;
+  let outside = any(neighbor < vec2<i32>(0)) || any(neighbor >= cells);
+  if outside && (params.tessellated_neighbors & edge_bit) == 0u {
+    return 0u;
+  }
+  return min(level, cell_level(neighbor));
+}
+
+// `i` rounded down to a multiple of `step`
+fn snap(i: u32, step: u32) -> u32 {
+  return i / step * step;
+}
+
+fn sample_height(x: i32, z: i32) -> f32 {
+  let size = vec2<i32>(params.size);
+  let clamped = clamp(vec2<i32>(x, z), vec2<i32>(0), size - 1);
+  return heights[clamped.y * size.x + clamped.x];
+}
+
+// Catmull-Rom weights of the four samples around `t`
+fn weights(t: f32) -> vec4<f32> {
+  let t2 = t * t;
+  let t3 = t2 * t;
+  return 0.5 * vec4<f32>(
+    -t + 2.0 * t2 - t3,
+    2.0 - 5.0 * t2 + 3.0 * t3,
+    t + 4.0 * t2 - 3.0 * t3,
+    -t2 + t3,
+  );
+}
+
+// Derivatives of `weights`
+fn weight_slopes(t: f32) -> vec4<f32> {
+  let t2 = t * t;
+  return 0.5 * vec4<f32>(
+    -1.0 + 4.0 * t - 3.0 * t2,
+    -10.0 * t + 9.0 * t2,
+    1.0 + 8.0 * t - 9.0 * t2,
+    -2.0 * t + 3.0 * t2,
+  );
+}
+
+fn write_vertex(index: u32, position: vec3<f32>, uv: vec2<f32>, normal: vec3<f32>, tangent: vec3<f32>, bitangent: vec3<f32>) {
+  let base = index * VERTEX_FLOATS;
+  let values = array<f32, 14>(
+    position.x, position.y, position.z,
+    uv.x, uv.y,
+    normal.x, normal.y, normal.z,
+    tangent.x, tangent.y, tangent.z,
+    bitangent.x, bitangent.y, bitangent.z,
+  );
+  for (var i = 0u; i < 14u; i++) {
+    vertices[base + i] = values[i];
+  }
+  // No joints or weights, white vertex color
+  for (var i = 14u; i < VERTEX_FLOATS - 4u; i++) {
+    vertices[base + i] = 0.0;
+  }
+  for (var i = VERTEX_FLOATS - 4u; i < VERTEX_FLOATS; i++) {
+    vertices[base + i] = 1.0;
+  }
+}
+
+@compute @workgroup_size(64)
+fn tessellate(
+  @builtin(global_invocation_id) id: vec3<u32>,
+  @builtin(num_workgroups) groups: vec3<u32>,
+) {
+  let n = 1u << params.max_subdivision;
+  let patch_vertices = (n + 1u) * (n + 1u);
+  let cells = params.size - 1u;
+  let index = id.y * groups.x * 64u + id.x;
+  if index >= patch_vertices * cells.x * cells.y {
+    return;
+  }
+  let patch_index = index / patch_vertices;
+  let local = index % patch_vertices;
+  let cell = vec2<i32>(vec2<u32>(patch_index % cells.x, patch_index / cells.x));
+
+  // Collapse onto the cell's level, and vertices on its edges onto the edge's level, which
+  // is never finer. The edges then match the neighbors' exactly.
+  let level = cell_level(cell);
+  var ij = vec2<u32>(local % (n + 1u), local / (n + 1u));
+  ij = vec2<u32>(snap(ij.x, n >> level), snap(ij.y, n >> level));
+  if ij.x == 0u {
+    ij.y = snap(ij.y, n >> edge_level(cell, level, vec2<i32>(-1, 0), 1u));
+  } else if ij.x == n {
+    ij.y = snap(ij.y, n >> edge_level(cell, level, vec2<i32>(1, 0), 2u));
+  }
+  if ij.y == 0u {
+    ij.x = snap(ij.x, n >> edge_level(cell, level, vec2<i32>(0, -1), 4u));
+  } else if ij.y == n {
+    ij.x = snap(ij.x, n >> edge_level(cell, level, vec2<i32>(0, 1), 8u));
+  }
+
+  // Grid coordinates, exact as n is a power of two, so the cells sharing an edge
+  // interpolate the same samples with the same weights
+  let grid = vec2<f32>(cell) + vec2<f32>(ij) / f32(n);
+  let base = clamp(floor(grid), vec2<f32>(0.0), vec2<f32>(cells - 1u));
+  let t = grid - base;
+  let wx = weights(t.x);
+  let wz = weights(t.y);
+  let sx = weight_slopes(t.x);
+  let sz = weight_slopes(t.y);
+  var height = 0.0;
+  var slope = vec2<f32>(0.0);
+  for (var b = 0; b < 4; b++) {
+    for (var a = 0; a < 4; a++) {
+      let h = sample_height(i32(base.x) - 1 + a, i32(base.y) - 1 + b);
+      height += wx[a] * wz[b] * h;
+      slope += vec2<f32>(sx[a] * wz[b], wx[a] * sz[b]) * h;
+    }
+  }
+  slope /= params.spacing;
+
+  let position = vec3<f32>(params.origin.x + grid.x * params.spacing, height, params.origin.y + grid.y * params.spacing);
+  let uv = grid / vec2<f32>(cells);
+  let normal = normalize(vec3<f32>(-slope.x, 1.0, -slope.y));
+  let tangent = normalize(vec3<f32>(1.0, slope.x, 0.0));
+  let bitangent = normalize(vec3<f32>(0.0, slope.y, 1.0));
+  write_vertex(index, position, uv, normal, tangent, bitangent);
+}
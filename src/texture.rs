@@ -1,6 +1,15 @@
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
 use anyhow::*;
 use image::GenericImageView;
 
+use crate::{hot_reload::wgsl, ktx2};
+
+#[derive(Clone)]
 pub struct Texture {
     #[allow(unused)]
     pub texture: wgpu::Texture,
@@ -11,10 +20,40 @@ pub struct Texture {
 
 impl Texture {
     pub const DEPTH_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Depth32Float;
+    /// Depth plus an 8 bit stencil buffer, for effects masking out pixels such as outlines.
+    /// Backends may only give depth 24 bits of precision.
+    pub const DEPTH_STENCIL_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Depth24PlusStencil8;
+
+    /// The depth buffer format, `DEPTH_STENCIL_FORMAT` if a stencil buffer is needed and
+    /// `DEPTH_FORMAT` otherwise.
+    pub fn depth_format(stencil: bool) -> wgpu::TextureFormat {
+        if stencil {
+            Self::DEPTH_STENCIL_FORMAT
+        } else {
+            Self::DEPTH_FORMAT
+        }
+    }
+
+    /// The depth test keeping the nearest fragment: `LessEqual`, or `GreaterEqual` with
+    /// reverse-Z, where near maps to depth 1 and far to 0.
+    pub fn depth_compare(reverse_z: bool) -> wgpu::CompareFunction {
+        if reverse_z {
+            wgpu::CompareFunction::GreaterEqual
+        } else {
+            wgpu::CompareFunction::LessEqual
+        }
+    }
+
+    /// Depth of the far plane, which depth buffers are cleared to.
+    pub fn far_depth(reverse_z: bool) -> f32 {
+        if reverse_z { 0.0 } else { 1.0 }
+    }
 
     pub fn create_depth_texture(
         device: &wgpu::Device,
         config: &wgpu::SurfaceConfiguration,
+        format: wgpu::TextureFormat,
+        sample_count: u32,
         label: &str,
     ) -> Self {
         let size = wgpu::Extent3d {
@@ -26,11 +65,11 @@ impl Texture {
             label: Some(label),
             size,
             mip_level_count: 1,
-            sample_count: 1,
+            sample_count,
             dimension: wgpu::TextureDimension::D2,
-            format: Self::DEPTH_FORMAT,
+            format,
             usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
-            view_formats: &[Self::DEPTH_FORMAT],
+            view_formats: &[format],
         };
         let texture = device.create_texture(&desc);
         let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
@@ -67,12 +106,322 @@ impl Texture {
         Self::from_image(device, queue, &img, Some(label), is_normal_map)
     }
 
+    /// Like `from_bytes`, but allocates the full mip chain and fills it on the GPU.
+    /// `anisotropy` is the sampler's anisotropic filtering level, 1 to turn it off.
+    pub fn from_bytes_mipmapped(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        bytes: &[u8],
+        label: &str,
+        is_normal_map: bool,
+        anisotropy: u16,
+    ) -> Result<Self> {
+        let img = image::load_from_memory(bytes)?;
+        Self::from_image_mipmapped(device, queue, &img, Some(label), is_normal_map, anisotropy)
+    }
+
+    /// A texture from a KTX2 file, with the mip levels stored in it. The levels are uploaded
+    /// still compressed where the device supports their format, e.g. BC or ASTC. Otherwise BC
+    /// levels are decoded to RGBA8 first, and other formats fail to load.
+    pub fn from_ktx2(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        bytes: &[u8],
+        label: &str,
+        anisotropy: u16,
+    ) -> Result<Self> {
+        let image = ktx2::Ktx2Image::parse(bytes)?;
+        let (block_width, block_height) = image.format.block_dimensions();
+        let supported = device.features().contains(image.format.required_features())
+            && image.width % block_width == 0
+            && image.height % block_height == 0;
+
+        let (format, levels) = if supported {
+            let levels = image.levels.iter().map(|level| level.to_vec()).collect();
+            (image.format, levels)
+        } else {
+            log::warn!(
+                "{label}: {:?} isn't supported by the device, decoding it on the CPU",
+                image.format
+            );
+            let format = if image.format.is_srgb() {
+                wgpu::TextureFormat::Rgba8UnormSrgb
+            } else {
+                wgpu::TextureFormat::Rgba8Unorm
+            };
+            let levels = image
+                .levels
+                .iter()
+                .enumerate()
+                .map(|(level, data)| {
+                    let width = (image.width >> level).max(1);
+                    let height = (image.height >> level).max(1);
+                    ktx2::decode_to_rgba8(image.format, width, height, data)
+                })
+                .collect::<Result<Vec<_>>>()?;
+            (format, levels)
+        };
+
+        let size = wgpu::Extent3d {
+            width: image.width,
+            height: image.height,
+            depth_or_array_layers: 1,
+        };
+        let texture = Self::create_texture_with_mips(
+            device,
+            Some(label),
+            size,
+            format,
+            wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+            wgpu::TextureDimension::D2,
+            wgpu::FilterMode::Linear,
+            levels.len() as u32,
+            anisotropy,
+        );
+
+        let (block_width, block_height) = format.block_dimensions();
+        let block_size = format.block_copy_size(None).unwrap_or(4);
+        for (mip_level, data) in levels.iter().enumerate() {
+            // Levels smaller than a block still take up a whole one
+            let level_size = size
+                .mip_level_size(mip_level as u32, wgpu::TextureDimension::D2)
+                .physical_size(format);
+            queue.write_texture(
+                wgpu::TexelCopyTextureInfo {
+                    aspect: wgpu::TextureAspect::All,
+                    texture: &texture.texture,
+                    mip_level: mip_level as u32,
+                    origin: wgpu::Origin3d::ZERO,
+                },
+                data,
+                wgpu::TexelCopyBufferLayout {
+                    offset: 0,
+                    bytes_per_row: Some(level_size.width / block_width * block_size),
+                    rows_per_image: Some(level_size.height / block_height),
+                },
+                level_size,
+            );
+        }
+
+        Ok(texture)
+    }
+
+    /// A mipmapped sRGB `D2Array` texture with one layer per encoded image in `layers`,
+    /// which must all have the same size. The sampler repeats, for tiling the layers over
+    /// large surfaces such as terrain.
+    pub fn array_from_bytes(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        layers: &[&[u8]],
+        label: &str,
+    ) -> Result<Self> {
+        let images = layers
+            .iter()
+            .map(|bytes| Ok(image::load_from_memory(bytes)?))
+            .collect::<Result<Vec<_>>>()?;
+        Self::array_from_images(device, queue, &images, label)
+    }
+
+    /// `array_from_bytes` with layers already decoded, or made up in memory.
+    pub fn array_from_images(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        images: &[image::DynamicImage],
+        label: &str,
+    ) -> Result<Self> {
+        let Some(first) = images.first() else {
+            bail!("texture array {label:?} has no layers");
+        };
+        let (width, height) = first.dimensions();
+        if let Some((i, img)) = images
+            .iter()
+            .enumerate()
+            .find(|(_, img)| img.dimensions() != (width, height))
+        {
+            bail!(
+                "layer {i} of texture array {label:?} is {:?}, expected {:?}",
+                img.dimensions(),
+                (width, height)
+            );
+        }
+
+        let size = wgpu::Extent3d {
+            width,
+            height,
+            depth_or_array_layers: images.len() as u32,
+        };
+        let mip_level_count = Self::mip_level_count(width, height);
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some(label),
+            size,
+            mip_level_count,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: wgpu::TextureFormat::Rgba8UnormSrgb,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+            view_formats: &[],
+        });
+        // Each layer's mip chain is generated in a texture of its own and copied over, as
+        // some backends can't render into single layers of a sampled array
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("Texture Array Encoder"),
+        });
+        for (layer, img) in images.iter().enumerate() {
+            let mipmapped = device.create_texture(&wgpu::TextureDescriptor {
+                label: Some(label),
+                size: wgpu::Extent3d {
+                    depth_or_array_layers: 1,
+                    ..size
+                },
+                mip_level_count,
+                sample_count: 1,
+                dimension: wgpu::TextureDimension::D2,
+                format: wgpu::TextureFormat::Rgba8UnormSrgb,
+                usage: wgpu::TextureUsages::TEXTURE_BINDING
+                    | wgpu::TextureUsages::COPY_DST
+                    | wgpu::TextureUsages::COPY_SRC
+                    | wgpu::TextureUsages::RENDER_ATTACHMENT,
+                view_formats: &[],
+            });
+            queue.write_texture(
+                mipmapped.as_image_copy(),
+                &img.to_rgba8(),
+                wgpu::TexelCopyBufferLayout {
+                    offset: 0,
+                    bytes_per_row: Some(4 * width),
+                    rows_per_image: Some(height),
+                },
+                mipmapped.size(),
+            );
+            Self::generate_mipmaps(device, queue, &mipmapped);
+            for mip_level in 0..mip_level_count {
+                encoder.copy_texture_to_texture(
+                    wgpu::TexelCopyTextureInfo {
+                        aspect: wgpu::TextureAspect::All,
+                        texture: &mipmapped,
+                        mip_level,
+                        origin: wgpu::Origin3d::ZERO,
+                    },
+                    wgpu::TexelCopyTextureInfo {
+                        aspect: wgpu::TextureAspect::All,
+                        texture: &texture,
+                        mip_level,
+                        origin: wgpu::Origin3d {
+                            x: 0,
+                            y: 0,
+                            z: layer as u32,
+                        },
+                    },
+                    wgpu::Extent3d {
+                        width: (width >> mip_level).max(1),
+                        height: (height >> mip_level).max(1),
+                        depth_or_array_layers: 1,
+                    },
+                );
+            }
+        }
+        queue.submit([encoder.finish()]);
+
+        // A single layer would otherwise get a plain D2 view
+        let view = texture.create_view(&wgpu::TextureViewDescriptor {
+            dimension: Some(wgpu::TextureViewDimension::D2Array),
+            ..Default::default()
+        });
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            address_mode_u: wgpu::AddressMode::Repeat,
+            address_mode_v: wgpu::AddressMode::Repeat,
+            address_mode_w: wgpu::AddressMode::Repeat,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
+
+        Ok(Self {
+            texture,
+            view,
+            sampler,
+            size,
+        })
+    }
+
+    /// A 1x1 texture filled with `rgba`, used where a material has no texture of its own.
+    pub fn from_color(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        rgba: [u8; 4],
+        label: &str,
+        is_normal_map: bool,
+    ) -> Result<Self> {
+        let img =
+            image::DynamicImage::ImageRgba8(image::RgbaImage::from_pixel(1, 1, image::Rgba(rgba)));
+        Self::from_image(device, queue, &img, Some(label), is_normal_map)
+    }
+
     pub fn from_image(
         device: &wgpu::Device,
         queue: &wgpu::Queue,
         img: &image::DynamicImage,
         label: Option<&str>,
         is_normal_map: bool,
+    ) -> Result<Self> {
+        Self::upload_image(device, queue, img, label, is_normal_map, 1, 1)
+    }
+
+    /// Like `from_image`, but allocates the full mip chain and fills it on the GPU.
+    /// `anisotropy` is the sampler's anisotropic filtering level, 1 to turn it off.
+    pub fn from_image_mipmapped(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        img: &image::DynamicImage,
+        label: Option<&str>,
+        is_normal_map: bool,
+        anisotropy: u16,
+    ) -> Result<Self> {
+        let mip_level_count = Self::mip_level_count(img.width(), img.height());
+        let texture = Self::upload_image(
+            device,
+            queue,
+            img,
+            label,
+            is_normal_map,
+            mip_level_count,
+            anisotropy,
+        )?;
+        Self::generate_mipmaps(device, queue, &texture.texture);
+        Ok(texture)
+    }
+
+    /// Number of levels in a full mip chain down to 1x1: `floor(log2(max(w, h))) + 1`.
+    pub fn mip_level_count(width: u32, height: u32) -> u32 {
+        width.max(height).max(1).ilog2() + 1
+    }
+
+    /// The highest anisotropic filtering level wgpu accepts.
+    pub const MAX_ANISOTROPY: u16 = 16;
+
+    /// Clamps `requested` to what `adapter` can do: up to `MAX_ANISOTROPY` where anisotropic
+    /// filtering is supported, 1 (off) where it isn't.
+    pub fn supported_anisotropy(adapter: &wgpu::Adapter, requested: u16) -> u16 {
+        let supported = adapter
+            .get_downlevel_capabilities()
+            .flags
+            .contains(wgpu::DownlevelFlags::ANISOTROPIC_FILTERING);
+        if supported {
+            requested.clamp(1, Self::MAX_ANISOTROPY)
+        } else {
+            1
+        }
+    }
+
+    fn upload_image(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        img: &image::DynamicImage,
+        label: Option<&str>,
+        is_normal_map: bool,
+        mip_level_count: u32,
+        anisotropy: u16,
     ) -> Result<Self> {
         let dimensions = img.dimensions();
         let rgba = img.to_rgba8();
@@ -82,20 +431,26 @@ impl Texture {
         } else {
             wgpu::TextureFormat::Rgba8UnormSrgb
         };
-        let usage = wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST;
+        let mut usage = wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST;
+        if mip_level_count > 1 {
+            // The lower levels are rendered from the ones above them
+            usage |= wgpu::TextureUsages::RENDER_ATTACHMENT;
+        }
         let size = wgpu::Extent3d {
             width: img.width(),
             height: img.height(),
             depth_or_array_layers: 1,
         };
-        let texture = Self::create_2d_texture(
+        let texture = Self::create_texture_with_mips(
             device,
-            size.width,
-            size.height,
+            label,
+            size,
             format,
             usage,
+            wgpu::TextureDimension::D2,
             wgpu::FilterMode::Linear,
-            label,
+            mip_level_count,
+            anisotropy,
         );
 
         queue.write_texture(
@@ -117,32 +472,28 @@ impl Texture {
         Ok(texture)
     }
 
-    pub(crate) fn create_2d_texture(
+    pub fn create_texture(
         device: &wgpu::Device,
-        width: u32,
-        height: u32,
+        label: Option<&str>,
+        size: wgpu::Extent3d,
         format: wgpu::TextureFormat,
         usage: wgpu::TextureUsages,
+        dimension: wgpu::TextureDimension,
         mag_filter: wgpu::FilterMode,
-        label: Option<&str>,
     ) -> Self {
-        let size = wgpu::Extent3d {
-            width,
-            height,
-            depth_or_array_layers: 1,
-        };
-        Self::create_texture(
-            device,
-            label,
-            size,
-            format,
-            usage,
-            wgpu::TextureDimension::D2,
-            mag_filter,
+        Self::create_texture_with_mips(
+            device, label, size, format, usage, dimension, mag_filter, 1, 1,
         )
     }
 
-    pub fn create_texture(
+    /// Creates a texture with `mip_level_count` levels. With more than one level the sampler
+    /// filters linearly between them.
+    ///
+    /// `anisotropy` above 1 enables anisotropic filtering, which wgpu only allows when every
+    /// filter is linear; otherwise it is ignored with a warning. Values are clamped to
+    /// `MAX_ANISOTROPY`, use `supported_anisotropy` to respect the adapter's support.
+    #[allow(clippy::too_many_arguments)]
+    pub fn create_texture_with_mips(
         device: &wgpu::Device,
         label: Option<&str>,
         size: wgpu::Extent3d,
@@ -150,11 +501,13 @@ impl Texture {
         usage: wgpu::TextureUsages,
         dimension: wgpu::TextureDimension,
         mag_filter: wgpu::FilterMode,
+        mip_level_count: u32,
+        anisotropy: u16,
     ) -> Self {
         let texture = device.create_texture(&wgpu::TextureDescriptor {
             label,
             size,
-            mip_level_count: 1,
+            mip_level_count,
             sample_count: 1,
             dimension,
             format,
@@ -162,14 +515,28 @@ impl Texture {
             view_formats: &[],
         });
 
+        let (min_filter, mipmap_filter) = if mip_level_count > 1 {
+            (wgpu::FilterMode::Linear, wgpu::FilterMode::Linear)
+        } else {
+            (wgpu::FilterMode::Nearest, wgpu::FilterMode::Nearest)
+        };
+        let all_linear = [mag_filter, min_filter, mipmap_filter] == [wgpu::FilterMode::Linear; 3];
+        let anisotropy = anisotropy.clamp(1, Self::MAX_ANISOTROPY);
+        let anisotropy_clamp = if anisotropy > 1 && !all_linear {
+            log::warn!("Anisotropic filtering needs linear filtering and mipmaps, ignoring it");
+            1
+        } else {
+            anisotropy
+        };
         let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
         let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
             address_mode_u: wgpu::AddressMode::ClampToEdge,
             address_mode_v: wgpu::AddressMode::ClampToEdge,
             address_mode_w: wgpu::AddressMode::ClampToEdge,
             mag_filter,
-            min_filter: wgpu::FilterMode::Nearest,
-            mipmap_filter: wgpu::FilterMode::Nearest,
+            min_filter,
+            mipmap_filter,
+            anisotropy_clamp,
             ..Default::default()
         });
 
@@ -180,6 +547,215 @@ impl Texture {
             size, // NEW!
         }
     }
+
+    /// Fills mip levels `1..` of every array layer of a 2D `texture` by repeatedly
+    /// downsampling the level above. The texture needs `TEXTURE_BINDING` and
+    /// `RENDER_ATTACHMENT` usage and a renderable, filterable format.
+    pub fn generate_mipmaps(device: &wgpu::Device, queue: &wgpu::Queue, texture: &wgpu::Texture) {
+        if texture.mip_level_count() < 2 {
+            return;
+        }
+
+        let blit = MipmapBlit::get(device, texture.format());
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("Mipmap Encoder"),
+        });
+        for layer in 0..texture.depth_or_array_layers() {
+            for level in 1..texture.mip_level_count() {
+                let src = Self::mip_level_view(texture, level - 1, layer);
+                let dst = Self::mip_level_view(texture, level, layer);
+                let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+                    layout: &blit.bind_group_layout,
+                    entries: &[
+                        wgpu::BindGroupEntry {
+                            binding: 0,
+                            resource: wgpu::BindingResource::TextureView(&src),
+                        },
+                        wgpu::BindGroupEntry {
+                            binding: 1,
+                            resource: wgpu::BindingResource::Sampler(&blit.sampler),
+                        },
+                    ],
+                    label: Some("mipmap_bind_group"),
+                });
+                MipmapBlit::draw(
+                    &mut encoder,
+                    "Mipmap Pass",
+                    &blit.pipeline,
+                    &bind_group,
+                    &dst,
+                    wgpu::LoadOp::Clear(wgpu::Color::TRANSPARENT),
+                );
+            }
+        }
+        queue.submit([encoder.finish()]);
+    }
+
+    /// A 2D view of only `level` of one array `layer`, to render into or sample from while
+    /// walking a mip chain.
+    pub fn mip_level_view(texture: &wgpu::Texture, level: u32, layer: u32) -> wgpu::TextureView {
+        texture.create_view(&wgpu::TextureViewDescriptor {
+            label: Some("Mipmap Level"),
+            dimension: Some(wgpu::TextureViewDimension::D2),
+            base_mip_level: level,
+            mip_level_count: Some(1),
+            base_array_layer: layer,
+            array_layer_count: Some(1),
+            ..Default::default()
+        })
+    }
+}
+
+/// Every `MipmapBlit` built on one thread, by device and format
+#[derive(Default)]
+struct MipmapBlits {
+    /// The `MIPMAP_GENERATION` they were built in
+    generation: usize,
+    blits: HashMap<(wgpu::Device, wgpu::TextureFormat), MipmapBlit>,
+}
+
+thread_local! {
+    static MIPMAP_BLITS: RefCell<MipmapBlits> = RefCell::default();
+}
+
+/// Bumped by `MipmapBlit::recreate` so every thread builds its pipelines again
+static MIPMAP_GENERATION: AtomicUsize = AtomicUsize::new(0);
+
+/// The pipeline `Texture::generate_mipmaps` downsamples one level into the next with,
+/// sampling the level above through a linear sampler. Built once per device and format on
+/// each thread that generates mipmaps, e.g. the workers of a `ResourceLoader`.
+#[derive(Clone)]
+pub struct MipmapBlit {
+    pub bind_group_layout: wgpu::BindGroupLayout,
+    pub sampler: wgpu::Sampler,
+    pub pipeline: wgpu::RenderPipeline,
+}
+
+impl MipmapBlit {
+    /// The blit rendering into `format`, built on the first call for `device` and `format`.
+    pub fn get(device: &wgpu::Device, format: wgpu::TextureFormat) -> Self {
+        MIPMAP_BLITS.with_borrow_mut(|built| {
+            let generation = MIPMAP_GENERATION.load(Ordering::Relaxed);
+            if built.generation != generation {
+                built.blits.clear();
+                built.generation = generation;
+            }
+            built
+                .blits
+                .entry((device.clone(), format))
+                .or_insert_with(|| Self::new(device, format))
+                .clone()
+        })
+    }
+
+    fn new(device: &wgpu::Device, format: wgpu::TextureFormat) -> Self {
+        let shader = device.create_shader_module(wgsl!("src/mipmap.wgsl", "src/fullscreen.wgsl"));
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        multisampled: false,
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+            ],
+            label: Some("mipmap_bind_group_layout"),
+        });
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Mipmap Pipeline Layout"),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Mipmap Pipeline"),
+            layout: Some(&pipeline_layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: Some("vs_main"),
+                buffers: &[],
+                compilation_options: Default::default(),
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: Some("fs_main"),
+                targets: &[Some(format.into())],
+                compilation_options: Default::default(),
+            }),
+            primitive: wgpu::PrimitiveState::default(),
+            depth_stencil: None,
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+            cache: None,
+        });
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("Mipmap Sampler"),
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
+        Self {
+            bind_group_layout,
+            sampler,
+            pipeline,
+        }
+    }
+
+    /// Drops the blits of every thread, so they are built again from the current shader,
+    /// see `hot_reload`. The ones this thread built for `device` are rebuilt right away.
+    pub fn recreate(device: &wgpu::Device) {
+        let formats = MIPMAP_BLITS.with_borrow(|built| {
+            built
+                .blits
+                .keys()
+                .filter(|(built_for, _)| built_for == device)
+                .map(|(_, format)| *format)
+                .collect::<Vec<_>>()
+        });
+        MIPMAP_GENERATION.fetch_add(1, Ordering::Relaxed);
+        for format in formats {
+            Self::get(device, format);
+        }
+    }
+
+    /// Records one step along a mip chain: a fullscreen triangle drawn with `pipeline`
+    /// into `dst`, sampling the level bound by `bind_group`.
+    pub fn draw(
+        encoder: &mut wgpu::CommandEncoder,
+        label: &str,
+        pipeline: &wgpu::RenderPipeline,
+        bind_group: &wgpu::BindGroup,
+        dst: &wgpu::TextureView,
+        load: wgpu::LoadOp<wgpu::Color>,
+    ) {
+        let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some(label),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: dst,
+                resolve_target: None,
+                depth_slice: None,
+                ops: wgpu::Operations {
+                    load,
+                    store: wgpu::StoreOp::Store,
+                },
+            })],
+            depth_stencil_attachment: None,
+            timestamp_writes: None,
+            occlusion_query_set: None,
+        });
+        pass.set_pipeline(pipeline);
+        pass.set_bind_group(0, bind_group, &[]);
+        pass.draw(0..3, 0..1);
+    }
 }
 
 pub struct CubeTexture {
@@ -189,10 +765,10 @@ pub struct CubeTexture {
 }
 
 impl CubeTexture {
+    /// A cube of six `size` x `size` faces.
     pub fn create_2d(
         device: &wgpu::Device,
-        width: u32,
-        height: u32,
+        size: u32,
         format: wgpu::TextureFormat,
         mip_level_count: u32,
         usage: wgpu::TextureUsages,
@@ -202,8 +778,8 @@ impl CubeTexture {
         let texture = device.create_texture(&wgpu::TextureDescriptor {
             label,
             size: wgpu::Extent3d {
-                width,
-                height,
+                width: size,
+                height: size,
                 // A cube has 6 sides, so we need 6 layers
                 depth_or_array_layers: 6,
             },
@@ -228,8 +804,13 @@ impl CubeTexture {
             address_mode_v: wgpu::AddressMode::ClampToEdge,
             address_mode_w: wgpu::AddressMode::ClampToEdge,
             mag_filter,
-            min_filter: wgpu::FilterMode::Nearest,
-            mipmap_filter: wgpu::FilterMode::Nearest,
+            min_filter: mag_filter,
+            // Mip levels of a cube are e.g. prefiltered roughness levels, blend between them
+            mipmap_filter: if mip_level_count > 1 {
+                mag_filter
+            } else {
+                wgpu::FilterMode::Nearest
+            },
             ..Default::default()
         });
 
@@ -240,6 +821,67 @@ impl CubeTexture {
         }
     }
 
+    /// Builds a cube from six encoded images, e.g. PNGs, in layer order +X, -X, +Y, -Y, +Z,
+    /// -Z. Every face must be square and all of them the same size. Like cubes converted
+    /// from equirectangular images it samples with `Nearest` filtering, as `Skybox` expects.
+    pub fn from_faces(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        faces: [&[u8]; 6],
+        label: &str,
+    ) -> Result<Self> {
+        let images = faces
+            .iter()
+            .map(|bytes| Ok(image::load_from_memory(bytes)?.to_rgba8()))
+            .collect::<Result<Vec<_>>>()?;
+        let size = images[0].width();
+        for (i, img) in images.iter().enumerate() {
+            let (width, height) = img.dimensions();
+            if width != height {
+                bail!("face {i} of cube {label} is {width}x{height}, faces must be square");
+            }
+            if width != size {
+                bail!("face {i} of cube {label} is {width}x{height}, expected {size}x{size}");
+            }
+        }
+
+        let cube = Self::create_2d(
+            device,
+            size,
+            wgpu::TextureFormat::Rgba8UnormSrgb,
+            1,
+            wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+            wgpu::FilterMode::Nearest,
+            Some(label),
+        );
+        for (layer, img) in images.iter().enumerate() {
+            queue.write_texture(
+                wgpu::TexelCopyTextureInfo {
+                    aspect: wgpu::TextureAspect::All,
+                    texture: &cube.texture,
+                    mip_level: 0,
+                    origin: wgpu::Origin3d {
+                        x: 0,
+                        y: 0,
+                        z: layer as u32,
+                    },
+                },
+                img,
+                wgpu::TexelCopyBufferLayout {
+                    offset: 0,
+                    bytes_per_row: Some(4 * size),
+                    rows_per_image: Some(size),
+                },
+                wgpu::Extent3d {
+                    width: size,
+                    height: size,
+                    depth_or_array_layers: 1,
+                },
+            );
+        }
+        Ok(cube)
+    }
+
     pub fn texture(&self) -> &wgpu::Texture {
         &self.texture
     }
@@ -0,0 +1,242 @@
+use std::{
+    sync::{
+        Arc,
+        atomic::{AtomicBool, AtomicU32, Ordering},
+    },
+    time::Duration,
+};
+
+/// The passes of a frame that get timed on the GPU.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GpuPass {
+    Shadow = 0,
+    DepthPrepass = 1,
+    /// Sky, opaque and wireframe draws
+    Main = 2,
+    /// Tone mapping into the output
+    Post = 3,
+    /// Water, transparent, outline, debug line and gizmo draws
+    Transparent = 4,
+}
+
+impl GpuPass {
+    const COUNT: u32 = 5;
+    const ALL: [GpuPass; Self::COUNT as usize] = [
+        GpuPass::Shadow,
+        GpuPass::DepthPrepass,
+        GpuPass::Main,
+        GpuPass::Post,
+        GpuPass::Transparent,
+    ];
+}
+
+/// GPU time spent in each pass of a frame. A pass is `None` if it didn't run in the
+/// measured frame or timestamp queries aren't supported.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct GpuTimings {
+    pub shadow: Option<Duration>,
+    pub depth_prepass: Option<Duration>,
+    pub main: Option<Duration>,
+    pub transparent: Option<Duration>,
+    pub post: Option<Duration>,
+}
+
+impl GpuTimings {
+    /// Sum of the passes that were measured.
+    pub fn total(&self) -> Duration {
+        [
+            self.shadow,
+            self.depth_prepass,
+            self.main,
+            self.transparent,
+            self.post,
+        ]
+        .into_iter()
+        .flatten()
+        .sum()
+    }
+
+    fn set(&mut self, pass: GpuPass, time: Duration) {
+        let slot = match pass {
+            GpuPass::Shadow => &mut self.shadow,
+            GpuPass::DepthPrepass => &mut self.depth_prepass,
+            GpuPass::Main => &mut self.main,
+            GpuPass::Transparent => &mut self.transparent,
+            GpuPass::Post => &mut self.post,
+        };
+        *slot = Some(time);
+    }
+}
+
+/// Measures passes with timestamp queries written at their start and end. Results are
+/// read back asynchronously, so they trail the rendered frame by a frame or two, and
+/// frames recorded while a readback is still in flight aren't measured.
+///
+/// Per frame: pass `timestamp_writes` to each timed pass, call `resolve` once all of them
+/// are recorded, `request_readback` after submitting and `collect` before the next frame.
+pub struct GpuTimer {
+    query_set: wgpu::QuerySet,
+    resolve_buffer: wgpu::Buffer,
+    readback_buffer: wgpu::Buffer,
+    /// Nanoseconds per timestamp tick
+    period: f32,
+    /// Bit mask of the passes given timestamp writes since the last `resolve`. Atomic, like
+    /// the flags below, so passes can be recorded on several threads.
+    written: AtomicU32,
+    /// Passes whose timestamps were copied into the readback buffer while it's in use, 0
+    /// when it isn't, as frames without timestamps aren't resolved
+    in_flight: AtomicU32,
+    map_requested: AtomicBool,
+    mapped: Arc<AtomicBool>,
+    /// Set instead of `mapped` when mapping failed, so `collect` frees the timer again
+    map_failed: Arc<AtomicBool>,
+    last: GpuTimings,
+}
+
+impl GpuTimer {
+    /// Each pass resolves into its own slot, as resolve offsets must be aligned and passes
+    /// that didn't run can't be resolved on every backend
+    const SLOT_SIZE: wgpu::BufferAddress = wgpu::QUERY_RESOLVE_BUFFER_ALIGNMENT;
+
+    /// `None` unless `device` was created with `Features::TIMESTAMP_QUERY`.
+    pub fn new(device: &wgpu::Device, queue: &wgpu::Queue) -> Option<Self> {
+        if !device.features().contains(wgpu::Features::TIMESTAMP_QUERY) {
+            return None;
+        }
+        let query_count = 2 * GpuPass::COUNT;
+        let size = GpuPass::COUNT as wgpu::BufferAddress * Self::SLOT_SIZE;
+        let query_set = device.create_query_set(&wgpu::QuerySetDescriptor {
+            label: Some("GPU Timer Queries"),
+            ty: wgpu::QueryType::Timestamp,
+            count: query_count,
+        });
+        let resolve_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("GPU Timer Resolve Buffer"),
+            size,
+            usage: wgpu::BufferUsages::QUERY_RESOLVE | wgpu::BufferUsages::COPY_SRC,
+            mapped_at_creation: false,
+        });
+        let readback_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("GPU Timer Readback Buffer"),
+            size,
+            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+            mapped_at_creation: false,
+        });
+        Some(Self {
+            query_set,
+            resolve_buffer,
+            readback_buffer,
+            period: queue.get_timestamp_period(),
+            written: AtomicU32::new(0),
+            in_flight: AtomicU32::new(0),
+            map_requested: AtomicBool::new(false),
+            mapped: Arc::new(AtomicBool::new(false)),
+            map_failed: Arc::new(AtomicBool::new(false)),
+            last: GpuTimings::default(),
+        })
+    }
+
+    /// Timestamp writes for the render pass descriptor of `pass`.
+    pub fn timestamp_writes(&self, pass: GpuPass) -> wgpu::RenderPassTimestampWrites<'_> {
+        let index = pass as u32;
+        self.written.fetch_or(1 << index, Ordering::Relaxed);
+        wgpu::RenderPassTimestampWrites {
+            query_set: &self.query_set,
+            beginning_of_pass_write_index: Some(2 * index),
+            end_of_pass_write_index: Some(2 * index + 1),
+        }
+    }
+
+    /// Resolves the timestamps written this frame and copies them for reading back, unless
+    /// the previous frame's are still being read.
+    pub fn resolve(&self, encoder: &mut wgpu::CommandEncoder) {
+        let written = self.written.swap(0, Ordering::Relaxed);
+        if self.in_flight.load(Ordering::Relaxed) != 0 || written == 0 {
+            return;
+        }
+        for pass in GpuPass::ALL {
+            let index = pass as u32;
+            if written & 1 << index != 0 {
+                encoder.resolve_query_set(
+                    &self.query_set,
+                    2 * index..2 * index + 2,
+                    &self.resolve_buffer,
+                    index as wgpu::BufferAddress * Self::SLOT_SIZE,
+                );
+            }
+        }
+        encoder.copy_buffer_to_buffer(
+            &self.resolve_buffer,
+            0,
+            &self.readback_buffer,
+            0,
+            self.resolve_buffer.size(),
+        );
+        self.in_flight.store(written, Ordering::Relaxed);
+    }
+
+    /// Starts mapping the readback buffer. Call after submitting the frame's commands.
+    pub fn request_readback(&self) {
+        if self.in_flight.load(Ordering::Relaxed) == 0
+            || self.map_requested.swap(true, Ordering::Relaxed)
+        {
+            return;
+        }
+        let mapped = self.mapped.clone();
+        let map_failed = self.map_failed.clone();
+        self.readback_buffer
+            .slice(..)
+            .map_async(wgpu::MapMode::Read, move |result| match result {
+                Ok(()) => mapped.store(true, Ordering::Release),
+                // That frame goes unmeasured, the next ones are again
+                Err(e) => {
+                    log::warn!("Unable to read GPU timestamps: {e}");
+                    map_failed.store(true, Ordering::Release);
+                }
+            });
+    }
+
+    /// Picks up finished readbacks without blocking, updating `last_timings`.
+    pub fn collect(&mut self, device: &wgpu::Device) {
+        let written = *self.in_flight.get_mut();
+        if written == 0 {
+            return;
+        }
+        let _ = device.poll(wgpu::PollType::Poll);
+        if self.map_failed.swap(false, Ordering::Acquire) {
+            *self.in_flight.get_mut() = 0;
+            *self.map_requested.get_mut() = false;
+            return;
+        }
+        if !self.mapped.swap(false, Ordering::Acquire) {
+            return;
+        }
+
+        let mut timings = GpuTimings::default();
+        {
+            let data = self.readback_buffer.slice(..).get_mapped_range();
+            let ticks: &[u64] = bytemuck::cast_slice(&data);
+            let stride = (Self::SLOT_SIZE / wgpu::QUERY_SIZE as wgpu::BufferAddress) as usize;
+            for pass in GpuPass::ALL {
+                let index = pass as usize;
+                if written & 1 << index == 0 {
+                    continue;
+                }
+                let slot = &ticks[index * stride..];
+                // Some drivers reset the counter between passes, so clamp at zero
+                let elapsed = slot[1].saturating_sub(slot[0]);
+                let nanos = elapsed as f64 * self.period as f64;
+                timings.set(pass, Duration::from_nanos(nanos as u64));
+            }
+        }
+        self.readback_buffer.unmap();
+        *self.in_flight.get_mut() = 0;
+        *self.map_requested.get_mut() = false;
+        self.last = timings;
+    }
+
+    /// The most recently read back frame.
+    pub fn last_timings(&self) -> GpuTimings {
+        self.last
+    }
+}
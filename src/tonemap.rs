@@ -0,0 +1,238 @@
+use wgpu::util::DeviceExt as _;
+
+use crate::hot_reload::wgsl;
+
+/// The curve compressing HDR scene colors into the display's 0..1 range.
+#[repr(u32)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TonemapMode {
+    /// Clips everything above 1, the look of drawing straight into the surface
+    #[default]
+    Linear = 0,
+    /// `c / (1 + c)`, never fully white
+    Reinhard = 1,
+    /// Filmic curve with a soft shoulder and slightly crushed blacks
+    Aces = 2,
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
+struct TonemapUniform {
+    exposure: f32,
+    mode: u32,
+    encode_srgb: u32,
+    _padding: u32,
+}
+
+/// Resolves the HDR scene target into the surface texture: applies exposure and the tone
+/// mapping curve, then sRGB encodes. It stretches the target over the whole output, so the
+/// scene may be rendered at a different resolution than the window.
+pub struct Tonemapper {
+    uniform: TonemapUniform,
+    buffer: wgpu::Buffer,
+    bind_group_layout: wgpu::BindGroupLayout,
+    sampler: wgpu::Sampler,
+    pipeline: wgpu::RenderPipeline,
+}
+
+impl Tonemapper {
+    /// Format of the HDR target the scene is drawn into
+    pub const HDR_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba16Float;
+
+    pub fn new(device: &wgpu::Device, output_format: wgpu::TextureFormat) -> Self {
+        let uniform = TonemapUniform {
+            exposure: 1.0,
+            mode: TonemapMode::default() as u32,
+            // sRGB formats encode on write, any other format gets it done in the shader
+            encode_srgb: (!output_format.is_srgb()).into(),
+            _padding: 0,
+        };
+        let buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Tonemap Buffer"),
+            contents: bytemuck::cast_slice(&[uniform]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        multisampled: false,
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ],
+            label: Some("tonemap_bind_group_layout"),
+        });
+        let pipeline = Self::create_pipeline(device, &bind_group_layout, output_format);
+        // Bilinear, for when the scene is rendered below or above window resolution
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("Tonemap Sampler"),
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
+
+        Self {
+            uniform,
+            buffer,
+            bind_group_layout,
+            sampler,
+            pipeline,
+        }
+    }
+
+    fn create_pipeline(
+        device: &wgpu::Device,
+        bind_group_layout: &wgpu::BindGroupLayout,
+        output_format: wgpu::TextureFormat,
+    ) -> wgpu::RenderPipeline {
+        let shader = device.create_shader_module(wgsl!("src/tonemap.wgsl", "src/fullscreen.wgsl"));
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Tonemap Pipeline Layout"),
+            bind_group_layouts: &[bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Tonemap Pipeline"),
+            layout: Some(&pipeline_layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: Some("vs_main"),
+                buffers: &[],
+                compilation_options: Default::default(),
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: Some("fs_main"),
+                targets: &[Some(output_format.into())],
+                compilation_options: Default::default(),
+            }),
+            primitive: wgpu::PrimitiveState::default(),
+            depth_stencil: None,
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+            cache: None,
+        })
+    }
+
+    /// Rebuilds the pipeline from the current shader, see `hot_reload`.
+    pub fn recreate_pipeline(&mut self, device: &wgpu::Device, output_format: wgpu::TextureFormat) {
+        self.pipeline = Self::create_pipeline(device, &self.bind_group_layout, output_format);
+    }
+
+    pub fn exposure(&self) -> f32 {
+        self.uniform.exposure
+    }
+
+    /// Multiplies the scene colors before tone mapping.
+    pub fn set_exposure(&mut self, queue: &wgpu::Queue, exposure: f32) {
+        self.uniform.exposure = exposure.max(0.0);
+        self.upload(queue);
+    }
+
+    pub fn mode(&self) -> TonemapMode {
+        match self.uniform.mode {
+            0 => TonemapMode::Linear,
+            1 => TonemapMode::Reinhard,
+            _ => TonemapMode::Aces,
+        }
+    }
+
+    pub fn set_mode(&mut self, queue: &wgpu::Queue, mode: TonemapMode) {
+        self.uniform.mode = mode as u32;
+        self.upload(queue);
+    }
+
+    /// Replaces the exposure of the tone mapping passes recorded after this with the `f32`
+    /// at the start of `source`, e.g. the one `AutoExposure` adapted. The manual exposure
+    /// comes back with the next upload, i.e. any setter here.
+    pub fn copy_exposure(&self, encoder: &mut wgpu::CommandEncoder, source: &wgpu::Buffer) {
+        // `exposure` leads the uniform
+        encoder.copy_buffer_to_buffer(
+            source,
+            0,
+            &self.buffer,
+            0,
+            Some(std::mem::size_of::<f32>() as u64),
+        );
+    }
+
+    fn upload(&self, queue: &wgpu::Queue) {
+        queue.write_buffer(&self.buffer, 0, bytemuck::cast_slice(&[self.uniform]));
+    }
+
+    /// Binds the HDR target `source` for `apply`. Recreate it whenever the target is.
+    pub fn create_bind_group(
+        &self,
+        device: &wgpu::Device,
+        source: &wgpu::TextureView,
+    ) -> wgpu::BindGroup {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &self.bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(source),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(&self.sampler),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: self.buffer.as_entire_binding(),
+                },
+            ],
+            label: Some("tonemap_bind_group"),
+        })
+    }
+
+    /// Records a pass that tone maps the target bound in `bind_group` over all of `target`.
+    pub fn apply(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        bind_group: &wgpu::BindGroup,
+        target: &wgpu::TextureView,
+        timestamp_writes: Option<wgpu::RenderPassTimestampWrites<'_>>,
+    ) {
+        let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Tonemap Pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: target,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                    store: wgpu::StoreOp::Store,
+                },
+                depth_slice: None,
+            })],
+            depth_stencil_attachment: None,
+            occlusion_query_set: None,
+            timestamp_writes,
+        });
+        pass.set_pipeline(&self.pipeline);
+        pass.set_bind_group(0, bind_group, &[]);
+        pass.draw(0..3, 0..1);
+    }
+}
@@ -0,0 +1,58 @@
+// Maps the HDR scene onto the display range with a single fullscreen triangle
+
+struct Tonemap {
+  exposure: f32,
+  // 0 linear, 1 Reinhard, 2 ACES
+  mode: u32,
+  // Set when the target isn't an sRGB format, so the shader has to encode itself
+  encode_srgb: u32,
+};
+
+// `vs_main` and `FullscreenOutput` are appended from `src/fullscreen.wgsl`
+
+@group(0) @binding(0)
+var t_hdr: texture_2d<f32>;
+@group(0) @binding(1)
+var s_hdr: sampler;
+@group(0) @binding(2)
+var<uniform> tonemap: Tonemap;
+
+// Narkowicz's fit of the ACES filmic curve
+fn aces(x: vec3<f32>) -> vec3<f32> {
+  let a = 2.51;
+  let b = 0.03;
+  let c = 2.43;
+  let d = 0.59;
+  let e = 0.14;
+  return clamp((x * (a * x + b)) / (x * (c * x + d) + e), vec3<f32>(0.0), vec3<f32>(1.0));
+}
+
+fn linear_to_srgb(x: vec3<f32>) -> vec3<f32> {
+  let low = x * 12.92;
+  let high = 1.055 * pow(x, vec3<f32>(1.0 / 2.4)) - 0.055;
+  return select(high, low, x <= vec3<f32>(0.0031308));
+}
+
+@fragment
+fn fs_main(in: FullscreenOutput) -> @location(0) vec4<f32> {
+  let hdr = textureSample(t_hdr, s_hdr, in.uv);
+  let exposed = hdr.rgb * tonemap.exposure;
+
+  var color: vec3<f32>;
+  switch tonemap.mode {
+    case 1u: {
+      color = exposed / (1.0 + exposed);
+    }
+    case 2u: {
+      color = aces(exposed);
+    }
+    default: {
+      color = clamp(exposed, vec3<f32>(0.0), vec3<f32>(1.0));
+    }
+  }
+
+  if tonemap.encode_srgb != 0u {
+    color = linear_to_srgb(color);
+  }
+  return vec4<f32>(color, hdr.a);
+}
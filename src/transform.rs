@@ -0,0 +1,119 @@
+use cgmath::{Matrix3, Matrix4, Quaternion, Vector3};
+use wgpu::util::DeviceExt as _;
+
+use crate::instance::{Instance, InstanceRaw, affine_rows};
+
+/// Placement of a single model: scaled, then rotated, then moved to `position`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Transform {
+    pub position: Vector3<f32>,
+    pub rotation: Quaternion<f32>,
+    pub scale: Vector3<f32>,
+}
+
+impl Transform {
+    pub fn new(position: Vector3<f32>, rotation: Quaternion<f32>) -> Self {
+        Self {
+            position,
+            rotation,
+            scale: Vector3::new(1.0, 1.0, 1.0),
+        }
+    }
+
+    pub fn with_scale(mut self, scale: Vector3<f32>) -> Self {
+        self.scale = scale;
+        self
+    }
+
+    /// The model matrix, taking the model's vertices into world space.
+    pub fn to_matrix(&self) -> Matrix4<f32> {
+        Matrix4::from_translation(self.position)
+            * Matrix4::from(self.rotation)
+            * Matrix4::from_nonuniform_scale(self.scale.x, self.scale.y, self.scale.z)
+    }
+
+    /// The matrix taking the model's normals into world space, which stays perpendicular
+    /// to the surface under non-uniform scale.
+    pub fn normal_matrix(&self) -> Matrix3<f32> {
+        // The inverse transpose of rotation * scale is rotation * inverse scale
+        Matrix3::from(self.rotation)
+            * Matrix3::new(
+                1.0 / self.scale.x,
+                0.0,
+                0.0,
+                0.0,
+                1.0 / self.scale.y,
+                0.0,
+                0.0,
+                0.0,
+                1.0 / self.scale.z,
+            )
+    }
+
+    /// The instance data of this placement, not moving since the previous frame.
+    pub fn to_raw(&self) -> InstanceRaw {
+        let model = self.to_matrix().into();
+        InstanceRaw {
+            model,
+            normal: self.normal_matrix().into(),
+            previous: affine_rows(model),
+        }
+    }
+}
+
+impl Default for Transform {
+    fn default() -> Self {
+        Self::new(
+            Vector3::new(0.0, 0.0, 0.0),
+            Quaternion::new(1.0, 0.0, 0.0, 0.0),
+        )
+    }
+}
+
+impl From<Instance> for Transform {
+    fn from(instance: Instance) -> Self {
+        Self::new(instance.position, instance.rotation).with_scale(instance.scale)
+    }
+}
+
+/// A `Transform` on the GPU, for drawing one model with `DrawModel::draw_model`. The
+/// shaders read the model and normal matrix as a single instance, so it is bound at
+/// vertex buffer slot 1 like an instance buffer.
+pub struct ModelTransform {
+    pub transform: Transform,
+    /// The model matrix as of the previous frame, for motion vectors
+    previous: Matrix4<f32>,
+    buffer: wgpu::Buffer,
+}
+
+impl ModelTransform {
+    pub fn new(device: &wgpu::Device, transform: Transform) -> Self {
+        let buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Model Transform Buffer"),
+            contents: bytemuck::cast_slice(&[transform.to_raw()]),
+            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+        });
+        Self {
+            transform,
+            previous: transform.to_matrix(),
+            buffer,
+        }
+    }
+
+    /// Uploads the model and normal matrix of `transform`, e.g. after moving it.
+    pub fn upload(&self, queue: &wgpu::Queue) {
+        let raw = self.transform.to_raw().moved_from(self.previous);
+        queue.write_buffer(&self.buffer, 0, bytemuck::cast_slice(&[raw]));
+    }
+
+    /// Uploads `transform` and remembers it as where the model was for the next frame.
+    /// `Renderer::update` calls it once a frame for the models it draws.
+    pub fn next_frame(&mut self, queue: &wgpu::Queue) {
+        self.upload(queue);
+        self.previous = self.transform.to_matrix();
+    }
+
+    pub fn buffer(&self) -> &wgpu::Buffer {
+        &self.buffer
+    }
+}
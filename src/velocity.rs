@@ -0,0 +1,179 @@
+use crate::{
+    hot_reload::wgsl,
+    instance::InstanceRaw,
+    model::{self, Vertex as _},
+    render_pipeline::{self, PipelineDesc, PrimitiveConfig},
+    texture,
+};
+
+/// How far every opaque pixel moved on screen since the previous frame, for temporal
+/// effects such as TAA or motion blur.
+///
+/// Written along with the depth by the pass laying down the opaque geometry: the depth
+/// prepass with `pipeline`, which projects each vertex with both this and the previous
+/// frame's view-projection (see `CameraUniform::motion`), or the G-buffer pass of
+/// `RenderingMode::Deferred`. The previous frame's placement comes with each instance, see
+/// `InstanceRaw::moved_from`, and skinned models keep their previous pose, so moving and
+/// animated models have motion of their own.
+pub struct MotionVectors {
+    /// `FORMAT`, the motion of each pixel's surface in UV units (x right, y down): where it
+    /// is now minus where it was. Zero where nothing was drawn.
+    pub velocity: texture::Texture,
+    /// `velocity` with the samples of MSAA, resolved into it after the last scene view
+    multisampled: Option<wgpu::TextureView>,
+    pipeline: wgpu::RenderPipeline,
+}
+
+impl MotionVectors {
+    pub const FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rg16Float;
+
+    /// `scene_layout` is the layout of the forward scene pipelines, whose bind groups the
+    /// prepass shares, and the rest matches the scene's depth buffer.
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        device: &wgpu::Device,
+        scene_layout: &wgpu::PipelineLayout,
+        width: u32,
+        height: u32,
+        depth_format: wgpu::TextureFormat,
+        depth_compare: wgpu::CompareFunction,
+        primitive: PrimitiveConfig,
+        sample_count: u32,
+    ) -> Self {
+        let (velocity, multisampled) = Self::create_targets(device, width, height, sample_count);
+        Self {
+            velocity,
+            multisampled,
+            pipeline: Self::create_pipeline(
+                device,
+                scene_layout,
+                depth_format,
+                depth_compare,
+                primitive,
+                sample_count,
+            ),
+        }
+    }
+
+    fn create_targets(
+        device: &wgpu::Device,
+        width: u32,
+        height: u32,
+        sample_count: u32,
+    ) -> (texture::Texture, Option<wgpu::TextureView>) {
+        let size = wgpu::Extent3d {
+            width: width.max(1),
+            height: height.max(1),
+            depth_or_array_layers: 1,
+        };
+        let velocity = texture::Texture::create_texture(
+            device,
+            Some("velocity_texture"),
+            size,
+            Self::FORMAT,
+            wgpu::TextureUsages::RENDER_ATTACHMENT
+                | wgpu::TextureUsages::TEXTURE_BINDING
+                | wgpu::TextureUsages::COPY_SRC,
+            wgpu::TextureDimension::D2,
+            wgpu::FilterMode::Nearest,
+        );
+        let multisampled = (sample_count > 1).then(|| {
+            device
+                .create_texture(&wgpu::TextureDescriptor {
+                    label: Some("velocity_msaa_texture"),
+                    size,
+                    mip_level_count: 1,
+                    sample_count,
+                    dimension: wgpu::TextureDimension::D2,
+                    format: Self::FORMAT,
+                    usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+                    view_formats: &[],
+                })
+                .create_view(&wgpu::TextureViewDescriptor::default())
+        });
+        (velocity, multisampled)
+    }
+
+    fn create_pipeline(
+        device: &wgpu::Device,
+        scene_layout: &wgpu::PipelineLayout,
+        depth_format: wgpu::TextureFormat,
+        depth_compare: wgpu::CompareFunction,
+        primitive: PrimitiveConfig,
+        sample_count: u32,
+    ) -> wgpu::RenderPipeline {
+        render_pipeline::create_render_pipeline(
+            device,
+            scene_layout,
+            &[Self::FORMAT],
+            Some(depth_format),
+            &[model::ModelVertex::desc(), InstanceRaw::motion_desc()],
+            wgsl!("src/velocity.wgsl", "src/prelude.wgsl"),
+            PipelineDesc {
+                depth_compare,
+                primitive,
+                sample_count,
+                ..Default::default()
+            },
+        )
+    }
+
+    /// Resizes the targets, e.g. along with the render targets of the scene.
+    pub fn resize(&mut self, device: &wgpu::Device, width: u32, height: u32, sample_count: u32) {
+        (self.velocity, self.multisampled) =
+            Self::create_targets(device, width, height, sample_count);
+    }
+
+    /// Rebuilds the pipeline, e.g. once the culled faces or the sample count changed.
+    pub fn recreate_pipeline(
+        &mut self,
+        device: &wgpu::Device,
+        scene_layout: &wgpu::PipelineLayout,
+        depth_format: wgpu::TextureFormat,
+        depth_compare: wgpu::CompareFunction,
+        primitive: PrimitiveConfig,
+        sample_count: u32,
+    ) {
+        self.pipeline = Self::create_pipeline(
+            device,
+            scene_layout,
+            depth_format,
+            depth_compare,
+            primitive,
+            sample_count,
+        );
+    }
+
+    /// The depth prepass pipeline writing the velocity as well, into `color_attachment`.
+    pub fn pipeline(&self) -> &wgpu::RenderPipeline {
+        &self.pipeline
+    }
+
+    /// The velocity as a color attachment of the pass laying down the opaque geometry.
+    /// `clear` clears it first, which later viewports leave out to draw next to the earlier
+    /// ones. With MSAA, `resolve` resolves the samples into `velocity`, which only the last
+    /// viewport should, as it covers the whole target.
+    pub fn color_attachment(
+        &self,
+        clear: bool,
+        resolve: bool,
+    ) -> wgpu::RenderPassColorAttachment<'_> {
+        let (view, resolve_target) = match &self.multisampled {
+            Some(multisampled) => (multisampled, resolve.then_some(&self.velocity.view)),
+            None => (&self.velocity.view, None),
+        };
+        wgpu::RenderPassColorAttachment {
+            view,
+            resolve_target,
+            ops: wgpu::Operations {
+                load: if clear {
+                    wgpu::LoadOp::Clear(wgpu::Color::TRANSPARENT)
+                } else {
+                    wgpu::LoadOp::Load
+                },
+                store: wgpu::StoreOp::Store,
+            },
+            depth_slice: None,
+        }
+    }
+}
@@ -0,0 +1,108 @@
+// Motion vectors: the vertex stage of shader.wgsl, run through this and the previous
+// frame's placement, pose and view-projection to write how far each pixel's surface moved
+// on screen. Drawn as the depth prepass, so groups 0 and 2 to 5 of the shared layout go
+// unused.
+
+struct VertexInput {
+  @location(0) position: vec3<f32>,
+  @location(12) joints: vec4<u32>,
+  @location(13) weights: vec4<f32>,
+};
+
+// `InstanceRaw::motion_desc`
+struct InstanceInput {
+  @location(5) model_matrix_0: vec4<f32>,
+  @location(6) model_matrix_1: vec4<f32>,
+  @location(7) model_matrix_2: vec4<f32>,
+  @location(8) model_matrix_3: vec4<f32>,
+  // Rows of the previous model matrix
+  @location(9) previous_0: vec4<f32>,
+  @location(10) previous_1: vec4<f32>,
+  @location(11) previous_2: vec4<f32>,
+};
+
+struct VertexOutput {
+  // Invariant like shader.wgsl's, so its color pass sees the same depths
+  @builtin(position) @invariant pos: vec4<f32>,
+  // Interpolated as clip positions, divided per pixel
+  @location(0) current: vec4<f32>,
+  @location(1) previous: vec4<f32>,
+};
+
+// `CameraUniform` and the other shared types are declared in `src/prelude.wgsl`
+
+@group(1) @binding(0)
+var<uniform> camera: CameraUniform;
+
+@group(6) @binding(0)
+var<storage, read> joint_matrices: array<mat4x4<f32>>;
+@group(6) @binding(1)
+var<storage, read> previous_joint_matrices: array<mat4x4<f32>>;
+
+const IDENTITY = mat4x4<f32>(
+  vec4<f32>(1.0, 0.0, 0.0, 0.0),
+  vec4<f32>(0.0, 1.0, 0.0, 0.0),
+  vec4<f32>(0.0, 0.0, 1.0, 0.0),
+  vec4<f32>(0.0, 0.0, 0.0, 1.0),
+);
+
+// Linear blend skinning. Vertices without weights aren't skinned.
+fn skin_matrix(joints: vec4<u32>, weights: vec4<f32>) -> mat4x4<f32> {
+  if dot(weights, vec4<f32>(1.0)) == 0.0 {
+    return IDENTITY;
+  }
+  return joint_matrices[joints.x] * weights.x
+    + joint_matrices[joints.y] * weights.y
+    + joint_matrices[joints.z] * weights.z
+    + joint_matrices[joints.w] * weights.w;
+}
+
+// `skin_matrix` in the previous frame's pose
+fn previous_skin_matrix(joints: vec4<u32>, weights: vec4<f32>) -> mat4x4<f32> {
+  if dot(weights, vec4<f32>(1.0)) == 0.0 {
+    return IDENTITY;
+  }
+  return previous_joint_matrices[joints.x] * weights.x
+    + previous_joint_matrices[joints.y] * weights.y
+    + previous_joint_matrices[joints.z] * weights.z
+    + previous_joint_matrices[joints.w] * weights.w;
+}
+
+// How far a point moved on screen in UV units, from its clip positions this frame and
+// the last, see `CameraUniform::motion`
+fn motion(current: vec4<f32>, previous: vec4<f32>) -> vec2<f32> {
+  return (current.xy / current.w - previous.xy / previous.w) * vec2<f32>(0.5, -0.5);
+}
+
+@vertex
+fn vs_main(model: VertexInput, instance: InstanceInput) -> VertexOutput {
+  let model_matrix = mat4x4<f32>(
+    instance.model_matrix_0,
+    instance.model_matrix_1,
+    instance.model_matrix_2,
+    instance.model_matrix_3,
+  );
+  // Affine, so the bottom row is 0, 0, 0, 1
+  let previous_model_matrix = transpose(mat4x4<f32>(
+    instance.previous_0,
+    instance.previous_1,
+    instance.previous_2,
+    vec4<f32>(0.0, 0.0, 0.0, 1.0),
+  ));
+
+  let skin = skin_matrix(model.joints, model.weights);
+  let world_position = model_matrix * skin * vec4<f32>(model.position, 1.0);
+  let previous_world_position = previous_model_matrix
+    * previous_skin_matrix(model.joints, model.weights)
+    * vec4<f32>(model.position, 1.0);
+  var out: VertexOutput;
+  out.pos = camera.view_proj * world_position;
+  out.current = out.pos;
+  out.previous = camera.prev_view_proj * previous_world_position;
+  return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec2<f32> {
+  return motion(in.current, in.previous);
+}
@@ -0,0 +1,171 @@
+use wgpu::util::DeviceExt;
+
+use crate::{
+    camera::{Camera, CameraUniform, Projection},
+    frustum::Frustum,
+    instance::{self, Instance, InstanceRaw},
+};
+
+/// A rectangle of the output in pixels, measured from its top left corner.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rect {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl Rect {
+    pub fn new(x: u32, y: u32, width: u32, height: u32) -> Self {
+        Self {
+            x,
+            y,
+            width,
+            height,
+        }
+    }
+
+    /// `count` side by side columns splitting a `width` x `height` output, e.g. two for
+    /// comparing camera angles.
+    pub fn columns(width: u32, height: u32, count: u32) -> Vec<Self> {
+        let count = count.max(1);
+        (0..count)
+            .map(|i| {
+                let x = width * i / count;
+                Self::new(x, 0, width * (i + 1) / count - x, height)
+            })
+            .collect()
+    }
+
+    /// This rectangle of a `from` sized output mapped onto a `to` sized render target, e.g.
+    /// at a lower render scale, and cut off at its edges. `None` if nothing is left.
+    pub fn scaled(self, from: (u32, u32), to: (u32, u32)) -> Option<Self> {
+        let scale = |value: u32, from: u32, to: u32| {
+            (value as u64 * to as u64 / from.max(1) as u64).min(to as u64) as u32
+        };
+        let x = scale(self.x, from.0, to.0);
+        let y = scale(self.y, from.1, to.1);
+        let right = scale(self.x.saturating_add(self.width), from.0, to.0);
+        let bottom = scale(self.y.saturating_add(self.height), from.1, to.1);
+        (right > x && bottom > y).then(|| Self::new(x, y, right - x, bottom - y))
+    }
+}
+
+/// A camera drawing the scene into a `Rect` of the output, see `Renderer::set_viewports`.
+/// Each has its own camera uniform and culls the instances against its own frustum.
+pub struct Viewport {
+    pub camera: Camera,
+    pub rect: Rect,
+    uniform: CameraUniform,
+    buffer: wgpu::Buffer,
+    bind_group: wgpu::BindGroup,
+    frustum: Frustum,
+    /// The instances inside this viewport's frustum, refreshed every update
+    visible_instance_buffer: wgpu::Buffer,
+    visible_instances: u32,
+    /// The transparent instances inside the frustum sorted back-to-front from this camera
+    transparent_instance_buffer: wgpu::Buffer,
+    visible_transparent_instances: u32,
+}
+
+impl Viewport {
+    /// `instances` and `transparent_instances` size the buffers of the culled instances.
+    pub fn new(
+        device: &wgpu::Device,
+        camera_layout: &wgpu::BindGroupLayout,
+        camera: Camera,
+        rect: Rect,
+        instances: &[Instance],
+        transparent_instances: &[Instance],
+    ) -> Self {
+        let uniform = CameraUniform::new();
+        let buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Viewport Camera Buffer"),
+            contents: bytemuck::cast_slice(&[uniform]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: camera_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: buffer.as_entire_binding(),
+            }],
+            label: Some("viewport_camera_bind_group"),
+        });
+        Self {
+            camera,
+            rect,
+            uniform,
+            buffer,
+            bind_group,
+            frustum: Frustum::from_matrix(cgmath::SquareMatrix::identity()),
+            visible_instance_buffer: instance::create_instance_buffer(device, instances),
+            visible_instances: 0,
+            transparent_instance_buffer: instance::create_instance_buffer(
+                device,
+                transparent_instances,
+            ),
+            visible_transparent_instances: 0,
+        }
+    }
+
+    /// Uploads the camera as seen through `projection`, stretched to the aspect ratio of
+    /// `rect`, and updates the frustum to cull against.
+    pub fn update_camera(&mut self, queue: &wgpu::Queue, projection: &Projection) {
+        let mut projection = *projection;
+        projection.resize(self.rect.width.max(1), self.rect.height.max(1));
+        self.uniform.update_view_proj(&self.camera, &projection);
+        queue.write_buffer(&self.buffer, 0, bytemuck::cast_slice(&[self.uniform]));
+        self.frustum = Frustum::from_matrix(projection.calc_matrix() * self.camera.calc_matrix());
+    }
+
+    /// The view frustum of the last `update_camera`.
+    pub fn frustum(&self) -> &Frustum {
+        &self.frustum
+    }
+
+    pub fn camera_bind_group(&self) -> &wgpu::BindGroup {
+        &self.bind_group
+    }
+
+    /// Uploads the instances this viewport sees, the transparent ones sorted back-to-front.
+    pub fn upload_instances(
+        &mut self,
+        queue: &wgpu::Queue,
+        visible: &[InstanceRaw],
+        transparent: &[InstanceRaw],
+    ) {
+        queue.write_buffer(
+            &self.visible_instance_buffer,
+            0,
+            bytemuck::cast_slice(visible),
+        );
+        self.visible_instances = visible.len() as u32;
+        queue.write_buffer(
+            &self.transparent_instance_buffer,
+            0,
+            bytemuck::cast_slice(transparent),
+        );
+        self.visible_transparent_instances = transparent.len() as u32;
+    }
+
+    /// Resizes the transparent instance buffer for a new set of `instances`.
+    pub fn set_transparent_instances(&mut self, device: &wgpu::Device, instances: &[Instance]) {
+        self.transparent_instance_buffer = instance::create_instance_buffer(device, instances);
+        self.visible_transparent_instances = 0;
+    }
+
+    /// The visible instances' buffer and count, as of the last `upload_instances`.
+    pub fn visible_instances(&self) -> (&wgpu::Buffer, u32) {
+        (&self.visible_instance_buffer, self.visible_instances)
+    }
+
+    /// The visible transparent instances' buffer and count, as of the last
+    /// `upload_instances`.
+    pub fn visible_transparent_instances(&self) -> (&wgpu::Buffer, u32) {
+        (
+            &self.transparent_instance_buffer,
+            self.visible_transparent_instances,
+        )
+    }
+}
@@ -0,0 +1,340 @@
+use std::f32::consts::TAU;
+
+use cgmath::InnerSpace as _;
+use wgpu::util::DeviceExt as _;
+
+use crate::{hot_reload::wgsl, texture};
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct WaterUniform {
+    /// Color looking straight down into the water
+    pub shallow_color: [f32; 3],
+    pub height: f32,
+    /// Color at grazing angles, before the reflection is blended in
+    pub deep_color: [f32; 3],
+    /// Seconds of animation, advanced by `Water::update`
+    pub time: f32,
+    /// Reflected where the scene has nothing to show
+    pub sky_color: [f32; 3],
+    /// Side length of the square surface, centered on the origin
+    pub extent: f32,
+    /// World space velocities of the two ripple layers, `xy` and `zw`
+    pub scroll: [f32; 4],
+    /// World units covered by one tile of the normal map
+    pub ripple_scale: f32,
+    /// How far the ripples tilt the surface normal, 0 for a flat mirror
+    pub ripple_strength: f32,
+    /// Weight of the reprojected scene in reflections, 0 to only reflect `sky_color`
+    pub reflection_strength: f32,
+    /// Distance along the reflected view ray at which the scene is sampled
+    pub reflection_distance: f32,
+}
+
+/// A flat, square water surface drawn in the transparency pass. Ripples come from two
+/// layers of a tiling normal map scrolling across each other, and a fresnel term blends
+/// the water color with reflections.
+///
+/// Reflections are approximated by reprojecting a point along the reflected view ray onto
+/// the previous frame's image, so only what's on screen can be reflected.
+pub struct Water {
+    pub uniform: WaterUniform,
+    buffer: wgpu::Buffer,
+    bind_group_layout: wgpu::BindGroupLayout,
+    normal_sampler: wgpu::Sampler,
+    reflection_sampler: wgpu::Sampler,
+    normal_map: Option<texture::Texture>,
+    /// Created by `set_textures`
+    bind_group: Option<wgpu::BindGroup>,
+    /// Created by `recreate_pipeline`
+    pipeline: Option<wgpu::RenderPipeline>,
+}
+
+impl Water {
+    /// Width and height of the normal map made by `create_normal_map`
+    pub const NORMAL_MAP_SIZE: u32 = 256;
+
+    /// A water surface `extent` world units across at `height`. It is drawn once it has
+    /// textures and a pipeline, which `Renderer::set_water` takes care of.
+    pub fn new(device: &wgpu::Device, height: f32, extent: f32) -> Self {
+        let uniform = WaterUniform {
+            shallow_color: [0.1, 0.35, 0.35],
+            height,
+            deep_color: [0.01, 0.05, 0.1],
+            time: 0.0,
+            sky_color: [0.5, 0.6, 0.7],
+            extent,
+            scroll: [0.3, 0.1, -0.1, 0.25],
+            ripple_scale: 8.0,
+            ripple_strength: 0.3,
+            reflection_strength: 1.0,
+            reflection_distance: 20.0,
+        };
+        let buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Water Buffer"),
+            contents: bytemuck::cast_slice(&[uniform]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+
+        let texture_entry = |binding| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Texture {
+                sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                view_dimension: wgpu::TextureViewDimension::D2,
+                multisampled: false,
+            },
+            count: None,
+        };
+        let sampler_entry = |binding| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+            count: None,
+        };
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::VERTEX_FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                texture_entry(1),
+                sampler_entry(2),
+                texture_entry(3),
+                sampler_entry(4),
+            ],
+            label: Some("water_bind_group_layout"),
+        });
+
+        let normal_sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("Water Normal Sampler"),
+            address_mode_u: wgpu::AddressMode::Repeat,
+            address_mode_v: wgpu::AddressMode::Repeat,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
+        let reflection_sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("Water Reflection Sampler"),
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
+
+        Self {
+            uniform,
+            buffer,
+            bind_group_layout,
+            normal_sampler,
+            reflection_sampler,
+            normal_map: None,
+            bind_group: None,
+            pipeline: None,
+        }
+    }
+
+    pub fn height(&self) -> f32 {
+        self.uniform.height
+    }
+
+    pub fn set_height(&mut self, height: f32) {
+        self.uniform.height = height;
+    }
+
+    pub fn extent(&self) -> f32 {
+        self.uniform.extent
+    }
+
+    /// A seamlessly tiling, mipmapped normal map of overlapping waves. Every wave fits a
+    /// whole number of periods into the tile, so the ripples repeat without seams.
+    pub fn create_normal_map(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+    ) -> anyhow::Result<texture::Texture> {
+        // (cycles across u, cycles across v, amplitude, phase)
+        const WAVES: [(f32, f32, f32, f32); 8] = [
+            (1.0, 2.0, 0.30, 0.0),
+            (-3.0, 1.0, 0.20, 1.3),
+            (2.0, -5.0, 0.12, 2.1),
+            (6.0, 3.0, 0.08, 4.0),
+            (-7.0, -6.0, 0.05, 0.7),
+            (11.0, -4.0, 0.04, 5.2),
+            (-5.0, 13.0, 0.03, 3.3),
+            (17.0, 9.0, 0.02, 1.9),
+        ];
+        let size = Self::NORMAL_MAP_SIZE;
+        let image = image::RgbaImage::from_fn(size, size, |x, y| {
+            let u = x as f32 / size as f32;
+            let v = y as f32 / size as f32;
+            // Slopes of the summed height field, per tile
+            let (mut du, mut dv) = (0.0, 0.0);
+            for (ku, kv, amplitude, phase) in WAVES {
+                let slope = amplitude * (TAU * (ku * u + kv * v) + phase).cos();
+                du += slope * ku;
+                dv += slope * kv;
+            }
+            // Scaled down so the strongest tilts stay well within the encodable range
+            let normal = cgmath::Vector3::new(-du, -dv, 8.0).normalize();
+            let encode = |c: f32| ((c * 0.5 + 0.5) * 255.0).round() as u8;
+            image::Rgba([encode(normal.x), encode(normal.y), encode(normal.z), 255])
+        });
+        texture::Texture::from_image_mipmapped(
+            device,
+            queue,
+            &image::DynamicImage::ImageRgba8(image),
+            Some("water_normal_map"),
+            true,
+            1,
+        )
+    }
+
+    /// Binds the ripple `normal_map` and the `reflection` image, which holds the previous
+    /// frame's scene.
+    pub fn set_textures(
+        &mut self,
+        device: &wgpu::Device,
+        normal_map: texture::Texture,
+        reflection: &texture::Texture,
+    ) {
+        self.normal_map = Some(normal_map);
+        self.set_reflection(device, reflection);
+    }
+
+    /// Binds a new `reflection` image, e.g. after the render targets were resized. Does
+    /// nothing before `set_textures`.
+    pub fn set_reflection(&mut self, device: &wgpu::Device, reflection: &texture::Texture) {
+        let Some(normal_map) = &self.normal_map else {
+            return;
+        };
+        self.bind_group = Some(device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &self.bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: self.buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(&normal_map.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::Sampler(&self.normal_sampler),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: wgpu::BindingResource::TextureView(&reflection.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 4,
+                    resource: wgpu::BindingResource::Sampler(&self.reflection_sampler),
+                },
+            ],
+            label: Some("water_bind_group"),
+        }));
+    }
+
+    /// Builds the pipeline, or rebuilds it e.g. after the MSAA sample count changed.
+    #[allow(clippy::too_many_arguments)]
+    pub fn recreate_pipeline(
+        &mut self,
+        device: &wgpu::Device,
+        camera_bind_group_layout: &wgpu::BindGroupLayout,
+        sun_bind_group_layout: &wgpu::BindGroupLayout,
+        fog_bind_group_layout: &wgpu::BindGroupLayout,
+        color_format: wgpu::TextureFormat,
+        depth_format: Option<wgpu::TextureFormat>,
+        depth_compare: wgpu::CompareFunction,
+        sample_count: u32,
+    ) {
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Water Pipeline Layout"),
+            bind_group_layouts: &[
+                camera_bind_group_layout,
+                &self.bind_group_layout,
+                sun_bind_group_layout,
+                fog_bind_group_layout,
+            ],
+            push_constant_ranges: &[],
+        });
+        let shader = device.create_shader_module(wgsl!("src/water.wgsl", "src/prelude.wgsl"));
+        self.pipeline = Some(
+            device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                label: Some("Water Pipeline"),
+                layout: Some(&layout),
+                vertex: wgpu::VertexState {
+                    module: &shader,
+                    entry_point: Some("vs_main"),
+                    buffers: &[],
+                    compilation_options: Default::default(),
+                },
+                fragment: Some(wgpu::FragmentState {
+                    module: &shader,
+                    entry_point: Some("fs_main"),
+                    targets: &[Some(wgpu::ColorTargetState {
+                        format: color_format,
+                        blend: Some(wgpu::BlendState::ALPHA_BLENDING),
+                        write_mask: wgpu::ColorWrites::ALL,
+                    })],
+                    compilation_options: Default::default(),
+                }),
+                // Visible from above and below
+                primitive: wgpu::PrimitiveState {
+                    topology: wgpu::PrimitiveTopology::TriangleStrip,
+                    ..Default::default()
+                },
+                // Writes depth so transparent instances below the surface are hidden by it
+                depth_stencil: depth_format.map(|format| wgpu::DepthStencilState {
+                    format,
+                    depth_write_enabled: true,
+                    depth_compare,
+                    stencil: wgpu::StencilState::default(),
+                    bias: wgpu::DepthBiasState::default(),
+                }),
+                multisample: wgpu::MultisampleState {
+                    count: sample_count,
+                    ..Default::default()
+                },
+                multiview: None,
+                cache: None,
+            }),
+        );
+    }
+
+    /// Advances the ripple animation by `dt` and uploads the uniform.
+    pub fn update(&mut self, queue: &wgpu::Queue, dt: std::time::Duration) {
+        self.uniform.time += dt.as_secs_f32();
+        queue.write_buffer(&self.buffer, 0, bytemuck::cast_slice(&[self.uniform]));
+    }
+
+    /// Draws the surface once it has textures and a pipeline. Changes bind groups 0 to 3.
+    pub fn draw(
+        &self,
+        pass: &mut wgpu::RenderPass<'_>,
+        camera_bind_group: &wgpu::BindGroup,
+        sun_bind_group: &wgpu::BindGroup,
+        fog_bind_group: &wgpu::BindGroup,
+    ) {
+        let (Some(pipeline), Some(bind_group)) = (&self.pipeline, &self.bind_group) else {
+            return;
+        };
+        pass.set_pipeline(pipeline);
+        pass.set_bind_group(0, camera_bind_group, &[]);
+        pass.set_bind_group(1, bind_group, &[]);
+        pass.set_bind_group(2, sun_bind_group, &[]);
+        pass.set_bind_group(3, fog_bind_group, &[]);
+        pass.draw(0..4, 0..1);
+    }
+
+    /// Whether `draw` has everything it needs.
+    pub fn is_ready(&self) -> bool {
+        self.pipeline.is_some() && self.bind_group.is_some()
+    }
+}
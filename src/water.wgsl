@@ -0,0 +1,102 @@
+// `CameraUniform` and the other shared types are declared in `src/prelude.wgsl`
+
+@group(0) @binding(0)
+var<uniform> camera: CameraUniform;
+
+struct Water {
+    shallow_color: vec3<f32>,
+    height: f32,
+    deep_color: vec3<f32>,
+    time: f32,
+    sky_color: vec3<f32>,
+    extent: f32,
+    // xy and zw are the velocities of the two ripple layers
+    scroll: vec4<f32>,
+    ripple_scale: f32,
+    ripple_strength: f32,
+    reflection_strength: f32,
+    reflection_distance: f32,
+}
+@group(1) @binding(0)
+var<uniform> water: Water;
+@group(1) @binding(1)
+var t_normal: texture_2d<f32>;
+@group(1) @binding(2)
+var s_normal: sampler;
+@group(1) @binding(3)
+var t_reflection: texture_2d<f32>;
+@group(1) @binding(4)
+var s_reflection: sampler;
+
+@group(2) @binding(0)
+var<uniform> sun: DirectionalLight;
+
+@group(3) @binding(0)
+var<uniform> fog: Fog;
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) world_position: vec3<f32>,
+}
+
+@vertex
+fn vs_main(@builtin(vertex_index) vertex_index: u32) -> VertexOutput {
+    // A triangle strip over the unit square, centered on the origin
+    let corner = vec2<f32>(f32(vertex_index & 1u), f32(vertex_index >> 1u)) - 0.5;
+    let world_position = vec3<f32>(corner.x * water.extent, water.height, corner.y * water.extent);
+
+    var out: VertexOutput;
+    out.world_position = world_position;
+    out.clip_position = camera.view_proj * vec4<f32>(world_position, 1.0);
+    return out;
+}
+
+// The ripple normal at `position`, blending two layers of the normal map scrolling in
+// different directions
+fn ripple_normal(position: vec2<f32>) -> vec3<f32> {
+    let uv = position / water.ripple_scale;
+    let offset = water.scroll * water.time / water.ripple_scale;
+    let a = textureSample(t_normal, s_normal, uv + offset.xy).xy * 2.0 - 1.0;
+    let b = textureSample(t_normal, s_normal, uv * 0.7 + offset.zw).xy * 2.0 - 1.0;
+    let tilt = (a + b) * water.ripple_strength;
+    return normalize(vec3<f32>(tilt.x, 1.0, tilt.y));
+}
+
+// What is seen along `reflected` from `position`: the last frame's scene at the point
+// `reflection_distance` along the ray, where that lands on screen, otherwise the sky
+fn reflection(position: vec3<f32>, reflected: vec3<f32>) -> vec3<f32> {
+    let target_clip = camera.view_proj * vec4<f32>(position + reflected * water.reflection_distance, 1.0);
+    let ndc = target_clip.xy / max(target_clip.w, 0.0001);
+    let uv = vec2<f32>(ndc.x * 0.5 + 0.5, 0.5 - ndc.y * 0.5);
+    // Fade out towards the screen edges, where the scene has nothing to reflect
+    let edge = min(min(uv.x, 1.0 - uv.x), min(uv.y, 1.0 - uv.y));
+    let on_screen = select(0.0, clamp(edge * 10.0, 0.0, 1.0), target_clip.w > 0.0);
+    let scene = textureSampleLevel(t_reflection, s_reflection, uv, 0.0).rgb;
+    return mix(water.sky_color, scene, on_screen * water.reflection_strength);
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let normal = ripple_normal(in.world_position.xz);
+    let view_dir = normalize(camera.view_pos.xyz - in.world_position);
+    // Seen from below, the surface faces the other way
+    let facing = select(-1.0, 1.0, view_dir.y >= 0.0);
+    let n = normal * vec3<f32>(1.0, facing, 1.0);
+
+    // Schlick's approximation with water's reflectance at normal incidence
+    let cos_theta = max(dot(n, view_dir), 0.0);
+    let fresnel = 0.02 + 0.98 * pow(1.0 - cos_theta, 5.0);
+
+    let body = mix(water.deep_color, water.shallow_color, cos_theta);
+    let reflected = reflection(in.world_position, reflect(-view_dir, n));
+    var color = mix(body, reflected, fresnel);
+
+    let sun_dir = -sun.direction;
+    let half_dir = normalize(view_dir + sun_dir);
+    color += sun.color * pow(max(dot(n, half_dir), 0.0), 256.0) * fresnel;
+
+    let view_depth = -(camera.view * vec4<f32>(in.world_position, 1.0)).z;
+    color = mix(color, fog.color.rgb, fog_factor(fog, view_depth));
+
+    return vec4<f32>(color, mix(0.7, 1.0, fresnel));
+}
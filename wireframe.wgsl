@@ -20,13 +20,7 @@ struct VertexOutput {
   @builtin(position) pos: vec4<f32>,
 };
 
-struct CameraUniform {
-  view_pos: vec4<f32>,
-  view: mat4x4<f32>,
-  view_proj: mat4x4<f32>,
-  inv_proj: mat4x4<f32>,
-  inv_view: mat4x4<f32>,
-};
+// `CameraUniform` and the other shared types are declared in `src/prelude.wgsl`
 
 // Bind groups (need to match the main shader for compatibility)
 @group(0) @binding(0)